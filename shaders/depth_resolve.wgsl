@@ -0,0 +1,17 @@
+// Resolves multisampled scene depth to a single-sample target for post passes
+
+@group(0) @binding(0)
+var t_depth: texture_multisampled_2d<f32>;
+
+#include "fullscreen"
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    // Keep the nearest sample so edges reflect the foreground surface
+    let coords = vec2<i32>(position.xy);
+    var depth = 1.0;
+    for (var i = 0u; i < textureNumSamples(t_depth); i++) {
+        depth = min(depth, textureLoad(t_depth, coords, i32(i)).x);
+    }
+    return depth;
+}
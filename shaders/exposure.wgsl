@@ -0,0 +1,78 @@
+// Auto-exposure: log-luminance histogram and eye-adapted average
+
+#include "color"
+
+const BIN_COUNT: u32 = 256u;
+
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Fraction of the gap to the target luminance closed this frame
+    adaptation: f32,
+    pixel_count: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> adapted_luminance: array<f32, 1>;
+@group(0) @binding(3)
+var<uniform> params: ExposureParams;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let lum = luminance(color);
+    // Bin 0 is reserved for near-black pixels
+    if (lum < 0.0001) {
+        return 0u;
+    }
+    let t = clamp((log2(lum) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn build_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let dims = textureDimensions(t_source);
+    if (global_id.x < dims.x && global_id.y < dims.y) {
+        let color = textureLoad(t_source, vec2<i32>(global_id.xy), 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn average_luminance(@builtin(local_invocation_index) local_index: u32) {
+    // Weight each bin by its index, then clear it for the next frame
+    let count = atomicExchange(&histogram[local_index], 0u);
+    weighted[local_index] = f32(count) * f32(local_index);
+    workgroupBarrier();
+
+    for (var stride = BIN_COUNT / 2u; stride > 0u; stride = stride / 2u) {
+        if (local_index < stride) {
+            weighted[local_index] += weighted[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        // Black pixels don't contribute to the average
+        let lit_pixels = max(params.pixel_count - f32(count), 1.0);
+        let average_bin = weighted[0] / lit_pixels - 1.0;
+        let log_average = average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance;
+        let target_luminance = exp2(log_average);
+        let previous = adapted_luminance[0];
+        adapted_luminance[0] = previous + (target_luminance - previous) * params.adaptation;
+    }
+}
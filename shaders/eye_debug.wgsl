@@ -0,0 +1,76 @@
+// Per-eye debug panels: scene color, linearised depth and camera motion of one view, drawn
+// into a region of the debug atlas; plus the pass copying the atlas to the mirror window
+
+struct PanelUniform {
+    inv_view_proj: mat4x4<f32>,
+    // The same view's projection last frame, for reprojecting depth into motion
+    prev_view_proj: mat4x4<f32>,
+    // xyz = eye position, w = panel kind (0 = color, 1 = depth, 2 = velocity)
+    eye: vec4<f32>,
+    // x = distance shown as black, y = velocity color per pixel moved
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> panel: PanelUniform;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var s_color: sampler;
+@group(0) @binding(3)
+var t_depth: texture_2d<f32>;
+
+// The composite pass binds only these, in a layout of its own
+@group(0) @binding(4)
+var t_atlas: texture_2d<f32>;
+@group(0) @binding(5)
+var s_atlas: sampler;
+
+#include "fullscreen"
+#include "color"
+
+fn world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = panel.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let kind = u32(panel.eye.w);
+    if kind == 0u {
+        // Scene color before post-processing, Reinhard-mapped so highlights stay readable
+        let color = textureSample(t_color, s_color, in.uv).rgb;
+        return vec4<f32>(tonemap_reinhard(color), 1.0);
+    }
+
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coords = vec2<i32>(min(in.uv * size, size - vec2<f32>(1.0)));
+    let depth = textureLoad(t_depth, coords, 0).x;
+    let world = world_position(in.uv, depth);
+
+    if kind == 1u {
+        // Log scale keeps near detail visible; cleared depth is black
+        if depth >= 1.0 {
+            return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        }
+        let distance = length(world - panel.eye.xyz);
+        let shade = 1.0 - clamp(log2(1.0 + distance) / log2(1.0 + panel.params.x), 0.0, 1.0);
+        return vec4<f32>(vec3<f32>(shade), 1.0);
+    }
+
+    // Screen-space motion since last frame from the view moving; grey is still, red/green
+    // are motion along x/y
+    let previous = panel.prev_view_proj * vec4<f32>(world, 1.0);
+    if previous.w <= 0.0 {
+        return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+    }
+    let previous_uv = vec2<f32>(previous.x / previous.w * 0.5 + 0.5, 0.5 - previous.y / previous.w * 0.5);
+    let motion = (in.uv - previous_uv) * size;
+    return vec4<f32>(clamp(vec2<f32>(0.5) + motion * panel.params.y, vec2<f32>(0.0), vec2<f32>(1.0)), 0.5, 1.0);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_atlas, s_atlas, in.uv);
+}
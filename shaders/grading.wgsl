@@ -0,0 +1,75 @@
+// Final post pass: exposure, tonemapping, contrast/saturation and LUT grading
+
+struct GradingUniform {
+    exposure: f32,
+    contrast: f32,
+    saturation: f32,
+    lut_strength: f32,
+    // 0 = none, 1 = Reinhard, 2 = ACES
+    tonemapper: u32,
+    auto_exposure: u32,
+    key_value: f32,
+    lut_size: f32,
+    // x = adapted scene luminance, written by the auto-exposure pass
+    luminance: vec4<f32>,
+    // 0 = opaque, 1 = premultiplied, 2 = straight alpha
+    output_alpha: u32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> grading: GradingUniform;
+@group(0) @binding(3)
+var t_lut: texture_3d<f32>;
+@group(0) @binding(4)
+var s_lut: sampler;
+
+#include "fullscreen"
+#include "color"
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let source = textureSample(t_source, s_source, in.uv);
+    var color = source.rgb;
+
+    // Exposure
+    var exposure = grading.exposure;
+    if (grading.auto_exposure != 0u) {
+        exposure *= grading.key_value / max(grading.luminance.x, 0.0001);
+    }
+    color *= exposure;
+
+    // Tonemapping
+    if (grading.tonemapper == 1u) {
+        color = tonemap_reinhard(color);
+    } else if (grading.tonemapper == 2u) {
+        color = tonemap_aces(color);
+    }
+
+    // Contrast around middle grey, then saturation around luminance
+    color = max((color - 0.18) * grading.contrast + 0.18, vec3<f32>(0.0));
+    color = max(mix(vec3<f32>(luminance(color)), color, grading.saturation), vec3<f32>(0.0));
+    color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    // LUTs are authored in display (sRGB) space; sample texel centers
+    if (grading.lut_strength > 0.0) {
+        let encoded = linear_to_srgb(color);
+        let scale = (grading.lut_size - 1.0) / grading.lut_size;
+        let offset = 0.5 / grading.lut_size;
+        let graded = textureSampleLevel(t_lut, s_lut, encoded * scale + offset, 0.0).rgb;
+        color = mix(color, srgb_to_linear(graded), grading.lut_strength);
+    }
+
+    // The scene is drawn over transparent black, so color arrives premultiplied
+    let alpha = clamp(source.a, 0.0, 1.0);
+    if (grading.output_alpha == 1u) {
+        color = min(color, vec3<f32>(alpha));
+    } else if (grading.output_alpha == 2u) {
+        color = select(vec3<f32>(0.0), min(color / alpha, vec3<f32>(1.0)), alpha > 0.0);
+    }
+
+    return vec4<f32>(color, source.a);
+}
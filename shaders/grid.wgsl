@@ -0,0 +1,133 @@
+// Infinite ground grid and origin axes, drawn into the scene pass after opaque objects
+
+struct GridUniform {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    camera_pos: vec4<f32>,
+    // x = cell size, y = cells per major line, z = fade distance, w = axis length
+    params: vec4<f32>,
+    // Local position of the absolute world origin (moves with floating-origin rebases)
+    origin: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> grid: GridUniform;
+
+// Sits just below y = 0 so geometry resting on the ground plane wins the depth test
+const GRID_HEIGHT: f32 = -0.001;
+
+struct GridOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // Bound with empty write masks; the grid never feeds reflections or the inspector
+    @location(1) normal: vec4<f32>,
+    @location(2) albedo: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@vertex
+fn vs_grid(@builtin(vertex_index) vertex_index: u32) -> GridOutput {
+    // Single triangle covering the whole screen
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    var out: GridOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = grid.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// Anti-aliased line coverage for a grid with the given spacing
+fn line_coverage(coord: vec2<f32>, spacing: f32) -> f32 {
+    let scaled = coord / spacing;
+    let width = max(fwidth(scaled), vec2<f32>(1e-4));
+    let distance = abs(fract(scaled - 0.5) - 0.5) / width;
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+@fragment
+fn fs_grid(in: GridOutput) -> FragmentOutput {
+    // Intersect the view ray through this pixel with the ground plane
+    let near = unproject(in.ndc, 0.0);
+    let far = unproject(in.ndc, 1.0);
+    let t = (GRID_HEIGHT - near.y) / (far.y - near.y);
+    let world = near + (far - near) * max(t, 0.0);
+
+    let clip = grid.view_proj * vec4<f32>(world, 1.0);
+    let depth = clip.z / clip.w;
+
+    // Derivatives are taken before any discard so they stay in uniform control flow
+    let cell = grid.params.x;
+    let minor = line_coverage(world.xz, cell);
+    let major = line_coverage(world.xz, cell * grid.params.y);
+    let axis_width = max(fwidth(world.xz), vec2<f32>(1e-4));
+    let axis = vec2<f32>(1.0) - min(abs(world.xz - grid.origin.xz) / axis_width, vec2<f32>(1.0));
+
+    var color = vec3<f32>(0.35);
+    var alpha = max(minor * 0.4, major * 0.8);
+    if (major > minor * 0.4) {
+        color = vec3<f32>(0.55);
+    }
+    // The X axis runs along absolute z = 0 (red), the Z axis along absolute x = 0 (blue)
+    if (axis.y > 0.0) {
+        color = mix(color, vec3<f32>(0.9, 0.15, 0.15), axis.y);
+        alpha = max(alpha, axis.y);
+    }
+    if (axis.x > 0.0) {
+        color = mix(color, vec3<f32>(0.15, 0.3, 0.9), axis.x);
+        alpha = max(alpha, axis.x);
+    }
+
+    // Fade out with distance to hide aliasing near the horizon
+    let distance = length(world.xz - grid.camera_pos.xz);
+    alpha *= 1.0 - smoothstep(grid.params.z * 0.5, grid.params.z, distance);
+
+    if (t <= 0.0 || depth < 0.0 || depth > 1.0 || alpha <= 0.001) {
+        discard;
+    }
+
+    var out: FragmentOutput;
+    out.color = vec4<f32>(color, alpha);
+    out.normal = vec4<f32>(0.0);
+    out.albedo = vec4<f32>(0.0);
+    out.depth = depth;
+    return out;
+}
+
+struct AxisOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+@vertex
+fn vs_axis(@builtin(vertex_index) vertex_index: u32) -> AxisOutput {
+    // Three line segments from the origin: X (red), Y (green), Z (blue)
+    let axis = vertex_index / 2u;
+    var direction = vec3<f32>(0.0);
+    direction[axis] = 1.0;
+    // Lifted slightly so the segments don't z-fight with the floor
+    let position = grid.origin.xyz + direction * grid.params.w * f32(vertex_index & 1u) + vec3<f32>(0.0, 0.002, 0.0);
+
+    var out: AxisOutput;
+    out.clip_position = grid.view_proj * vec4<f32>(position, 1.0);
+    out.color = direction;
+    return out;
+}
+
+@fragment
+fn fs_axis(in: AxisOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(in.color, 1.0);
+    out.normal = vec4<f32>(0.0);
+    out.albedo = vec4<f32>(0.0);
+    out.depth = in.clip_position.z;
+    return out;
+}
@@ -0,0 +1,14 @@
+// Lighting terms shared by the scene and VR shaders
+
+// Lambert diffuse plus a Blinn-Phong highlight from a light in direction `light_dir` (unit,
+// towards the light) arriving with `radiance`
+fn blinn_phong(normal: vec3<f32>, light_dir: vec3<f32>, view_dir: vec3<f32>, albedo: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let diffuse = max(dot(normal, light_dir), 0.0);
+    let specular = pow(max(dot(normal, normalize(view_dir + light_dir)), 0.0), 32.0);
+    return radiance * (diffuse * albedo + specular * 0.5);
+}
+
+// Simple ambient occlusion: surfaces facing away from the sky are a little darker
+fn sky_occlusion(normal: vec3<f32>) -> f32 {
+    return max(dot(normal, vec3<f32>(0.0, 1.0, 0.0)), 0.0) * 0.2 + 0.8;
+}
@@ -0,0 +1,277 @@
+// Procedural noise matching `src/noise.rs`: Perlin, simplex and Worley noise in 2D and 3D, and
+// fBm sums of them. Multiply positions by the frequency before calling the fbm_ functions.
+
+// PCG hash (Jarzynski and Olano 2020)
+fn noise_pcg(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn noise_hash2(cell: vec2<i32>, seed: u32) -> u32 {
+    return noise_pcg(bitcast<u32>(cell.y) + noise_pcg(bitcast<u32>(cell.x) + seed));
+}
+
+fn noise_hash3(cell: vec3<i32>, seed: u32) -> u32 {
+    return noise_pcg(bitcast<u32>(cell.z) + noise_hash2(cell.xy, seed));
+}
+
+fn noise_unit(hash: u32) -> f32 {
+    return f32(hash >> 8u) / 16777216.0;
+}
+
+// `offset` dotted with one of the 12 cube edge gradients, picked by `hash` (Perlin 2002)
+fn noise_gradient(hash: u32, offset: vec3<f32>) -> f32 {
+    let h = hash & 15u;
+    let u = select(offset.y, offset.x, h < 8u);
+    var v = offset.z;
+    if (h < 4u) {
+        v = offset.y;
+    } else if (h == 12u || h == 14u) {
+        v = offset.x;
+    }
+    return select(-u, u, (h & 1u) == 0u) + select(-v, v, (h & 2u) == 0u);
+}
+
+fn noise_fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn noise_lerp(a: f32, b: f32, t: f32) -> f32 {
+    return a + (b - a) * t;
+}
+
+fn noise_perlin2_corner(i: vec2<i32>, f: vec2<f32>, corner: vec2<i32>, seed: u32) -> f32 {
+    let offset = f - vec2<f32>(corner);
+    return noise_gradient(noise_hash2(i + corner, seed), vec3<f32>(offset, 0.0));
+}
+
+// Gradient noise, 0 at integer points and roughly in -1..1
+fn perlin2(p: vec2<f32>, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let i = vec2<i32>(cell);
+    let u = noise_fade(f.x);
+    let v = noise_fade(f.y);
+    let bottom = noise_lerp(noise_perlin2_corner(i, f, vec2<i32>(0, 0), seed), noise_perlin2_corner(i, f, vec2<i32>(1, 0), seed), u);
+    let top = noise_lerp(noise_perlin2_corner(i, f, vec2<i32>(0, 1), seed), noise_perlin2_corner(i, f, vec2<i32>(1, 1), seed), u);
+    return noise_lerp(bottom, top, v);
+}
+
+fn noise_perlin3_corner(i: vec3<i32>, f: vec3<f32>, corner: vec3<i32>, seed: u32) -> f32 {
+    return noise_gradient(noise_hash3(i + corner, seed), f - vec3<f32>(corner));
+}
+
+// Gradient noise, 0 at integer points and roughly in -1..1
+fn perlin3(p: vec3<f32>, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let i = vec3<i32>(cell);
+    let u = noise_fade(f.x);
+    let v = noise_fade(f.y);
+    let w = noise_fade(f.z);
+    let near = noise_lerp(
+        noise_lerp(noise_perlin3_corner(i, f, vec3<i32>(0, 0, 0), seed), noise_perlin3_corner(i, f, vec3<i32>(1, 0, 0), seed), u),
+        noise_lerp(noise_perlin3_corner(i, f, vec3<i32>(0, 1, 0), seed), noise_perlin3_corner(i, f, vec3<i32>(1, 1, 0), seed), u),
+        v,
+    );
+    let far = noise_lerp(
+        noise_lerp(noise_perlin3_corner(i, f, vec3<i32>(0, 0, 1), seed), noise_perlin3_corner(i, f, vec3<i32>(1, 0, 1), seed), u),
+        noise_lerp(noise_perlin3_corner(i, f, vec3<i32>(0, 1, 1), seed), noise_perlin3_corner(i, f, vec3<i32>(1, 1, 1), seed), u),
+        v,
+    );
+    return noise_lerp(near, far, w);
+}
+
+fn noise_simplex2_corner(i: vec2<i32>, offset: vec2<f32>, corner: vec2<i32>, seed: u32) -> f32 {
+    let t = 0.5 - dot(offset, offset);
+    if (t < 0.0) {
+        return 0.0;
+    }
+    return t * t * t * t * noise_gradient(noise_hash2(i + corner, seed), vec3<f32>(offset, 0.0));
+}
+
+// Simplex noise (Gustavson's formulation), roughly in -1..1
+fn simplex2(p: vec2<f32>, seed: u32) -> f32 {
+    let F2 = 0.36602542;
+    let G2 = 0.21132487;
+    let cell = floor(p + vec2<f32>((p.x + p.y) * F2));
+    let i = vec2<i32>(cell);
+    let x0 = p - (cell - vec2<f32>((cell.x + cell.y) * G2));
+    var i1 = vec2<i32>(0, 1);
+    if (x0.x > x0.y) {
+        i1 = vec2<i32>(1, 0);
+    }
+    let x1 = x0 - vec2<f32>(i1) + vec2<f32>(G2);
+    let x2 = x0 - vec2<f32>(1.0) + vec2<f32>(2.0 * G2);
+    return 70.0 * (noise_simplex2_corner(i, x0, vec2<i32>(0), seed)
+        + noise_simplex2_corner(i, x1, i1, seed)
+        + noise_simplex2_corner(i, x2, vec2<i32>(1), seed));
+}
+
+fn noise_simplex3_corner(i: vec3<i32>, offset: vec3<f32>, corner: vec3<i32>, seed: u32) -> f32 {
+    let t = 0.6 - dot(offset, offset);
+    if (t < 0.0) {
+        return 0.0;
+    }
+    return t * t * t * t * noise_gradient(noise_hash3(i + corner, seed), offset);
+}
+
+// Simplex noise (Gustavson's formulation), roughly in -1..1
+fn simplex3(p: vec3<f32>, seed: u32) -> f32 {
+    let F3 = 1.0 / 3.0;
+    let G3 = 1.0 / 6.0;
+    let cell = floor(p + vec3<f32>((p.x + p.y + p.z) * F3));
+    let i = vec3<i32>(cell);
+    let x0 = p - (cell - vec3<f32>((cell.x + cell.y + cell.z) * G3));
+    // The two middle corners of the tetrahedron x0 lies in, from the order of its components
+    var i1 = vec3<i32>(0, 1, 0);
+    var i2 = vec3<i32>(1, 1, 0);
+    if (x0.x >= x0.y) {
+        if (x0.y >= x0.z) {
+            i1 = vec3<i32>(1, 0, 0);
+            i2 = vec3<i32>(1, 1, 0);
+        } else if (x0.x >= x0.z) {
+            i1 = vec3<i32>(1, 0, 0);
+            i2 = vec3<i32>(1, 0, 1);
+        } else {
+            i1 = vec3<i32>(0, 0, 1);
+            i2 = vec3<i32>(1, 0, 1);
+        }
+    } else if (x0.y < x0.z) {
+        i1 = vec3<i32>(0, 0, 1);
+        i2 = vec3<i32>(0, 1, 1);
+    } else if (x0.x < x0.z) {
+        i1 = vec3<i32>(0, 1, 0);
+        i2 = vec3<i32>(0, 1, 1);
+    }
+    let x1 = x0 - vec3<f32>(i1) + vec3<f32>(G3);
+    let x2 = x0 - vec3<f32>(i2) + vec3<f32>(2.0 * G3);
+    let x3 = x0 - vec3<f32>(1.0) + vec3<f32>(3.0 * G3);
+    return 32.0 * (noise_simplex3_corner(i, x0, vec3<i32>(0), seed)
+        + noise_simplex3_corner(i, x1, i1, seed)
+        + noise_simplex3_corner(i, x2, i2, seed)
+        + noise_simplex3_corner(i, x3, vec3<i32>(1), seed));
+}
+
+// Distance to the nearest of one random point per unit cell
+fn worley2(p: vec2<f32>, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let i = vec2<i32>(cell);
+    var nearest = 3.40282347e38;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let hash = noise_hash2(i + vec2<i32>(x, y), seed);
+            let point = vec2<f32>(f32(x) + noise_unit(hash), f32(y) + noise_unit(noise_pcg(hash)));
+            let offset = point - f;
+            nearest = min(nearest, dot(offset, offset));
+        }
+    }
+    return sqrt(nearest);
+}
+
+// Distance to the nearest of one random point per unit cell
+fn worley3(p: vec3<f32>, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let i = vec3<i32>(cell);
+    var nearest = 3.40282347e38;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let hash = noise_hash3(i + vec3<i32>(x, y, z), seed);
+                let jitter = vec3<f32>(noise_unit(hash), noise_unit(noise_pcg(hash)), noise_unit(noise_pcg(noise_pcg(hash))));
+                let offset = vec3<f32>(f32(x), f32(y), f32(z)) + jitter - f;
+                nearest = min(nearest, dot(offset, offset));
+            }
+        }
+    }
+    return sqrt(nearest);
+}
+
+// fBm: octave n at frequency lacunarity^n, weight gain^n and seed seed + n, normalized by the
+// total weight
+fn fbm_perlin2(p: vec2<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * perlin2(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
+
+fn fbm_perlin3(p: vec3<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * perlin3(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
+
+fn fbm_simplex2(p: vec2<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * simplex2(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
+
+fn fbm_simplex3(p: vec3<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * simplex3(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
+
+fn fbm_worley2(p: vec2<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * worley2(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
+
+fn fbm_worley3(p: vec3<f32>, seed: u32, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var index = 0u; index < octaves; index++) {
+        sum += amplitude * worley3(p * frequency, seed + index);
+        total += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    return select(0.0, sum / total, total > 0.0);
+}
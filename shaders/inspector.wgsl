@@ -0,0 +1,19 @@
+// Loads one depth texel into an integer target the pixel inspector copies out, since not every
+// backend can copy depth textures to buffers; its bits are kept, as float targets may not render
+
+struct InspectUniform {
+    // xy = texel to read
+    texel: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> inspect: InspectUniform;
+@group(0) @binding(1)
+var t_depth: texture_2d<f32>;
+
+#include "fullscreen"
+
+@fragment
+fn fs_depth(in: FullscreenOutput) -> @location(0) u32 {
+    return bitcast<u32>(textureLoad(t_depth, vec2<i32>(inspect.texel.xy), 0).x);
+}
@@ -0,0 +1,43 @@
+// Headset lens preview for the desktop mirror: the barrel distortion a headset's panels show,
+// with the per-channel scale of lateral chromatic aberration, and black beyond the image
+
+struct LensUniform {
+    // x = k1, y = k2, z = chromatic aberration, w = scale
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> lens: LensUniform;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var s_color: sampler;
+
+#include "fullscreen"
+
+// Where the lens maps `uv` from for a channel magnified by `channel_scale`
+fn distort(uv: vec2<f32>, channel_scale: f32) -> vec2<f32> {
+    let size = vec2<f32>(textureDimensions(t_color));
+    // Radius in half screen heights, so the distortion is round whatever the aspect ratio
+    let aspect = vec2<f32>(size.x / size.y, 1.0);
+    let offset = (uv * 2.0 - vec2<f32>(1.0)) * aspect;
+    let r2 = dot(offset, offset);
+    let factor = (1.0 + lens.params.x * r2 + lens.params.y * r2 * r2) * lens.params.w * channel_scale;
+    return (offset * factor / aspect) * 0.5 + vec2<f32>(0.5);
+}
+
+fn inside(uv: vec2<f32>) -> f32 {
+    return select(0.0, 1.0, all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0)));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // Red is magnified least and blue most, fringing edges towards the rim
+    let red_uv = distort(in.uv, 1.0 - lens.params.z);
+    let green_uv = distort(in.uv, 1.0);
+    let blue_uv = distort(in.uv, 1.0 + lens.params.z);
+    let red = textureSampleLevel(t_color, s_color, red_uv, 0.0).r * inside(red_uv);
+    let green = textureSampleLevel(t_color, s_color, green_uv, 0.0);
+    let blue = textureSampleLevel(t_color, s_color, blue_uv, 0.0).b * inside(blue_uv);
+    return vec4<f32>(red, green.g * inside(green_uv), blue, green.a * inside(green_uv));
+}
@@ -0,0 +1,48 @@
+// Motion blur post pass: each pixel is averaged along its motion since the last frame
+
+struct MotionBlurUniform {
+    // x = fraction of the frame the shutter is open, y = camera motion scale,
+    // z = object motion scale, w = longest blur in UV units
+    params: vec4<f32>,
+    // x = taps along the motion
+    samples: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> blur: MotionBlurUniform;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(3)
+var s_color: sampler;
+
+#include "fullscreen"
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_velocity));
+    let coord = vec2<i32>(clamp(in.uv * size, vec2<f32>(0.0), size - vec2<f32>(1.0)));
+    // xy is the total motion and zw the camera's share of it
+    let velocity = textureLoad(t_velocity, coord, 0);
+    let object_motion = velocity.xy - velocity.zw;
+    var motion = (velocity.zw * blur.params.y + object_motion * blur.params.z) * blur.params.x;
+    let distance = length(motion);
+    if (distance > blur.params.w) {
+        motion *= blur.params.w / distance;
+    }
+
+    let center = textureSampleLevel(t_color, s_color, in.uv, 0.0);
+    // Less than half a pixel of motion leaves nothing to blur
+    if (length(motion * size) < 0.5) {
+        return center;
+    }
+    let taps = max(blur.samples.x, 2u);
+    var sum = vec4<f32>(0.0);
+    for (var i = 0u; i < taps; i++) {
+        // Centered on the pixel, as if the shutter were open around the frame's time
+        let t = f32(i) / f32(taps - 1u) - 0.5;
+        sum += textureSampleLevel(t_color, s_color, in.uv - motion * t, 0.0);
+    }
+    return sum / f32(taps);
+}
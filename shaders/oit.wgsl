@@ -0,0 +1,21 @@
+// Composites weighted blended transparency over the resolved HDR scene
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_reveal: texture_2d<f32>;
+
+#include "fullscreen"
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+    // Revealage is how much of the opaque scene still shows through every layer
+    let reveal = textureLoad(t_reveal, coords, 0).r;
+    if (reveal >= 0.999) {
+        discard;
+    }
+    let accum = textureLoad(t_accum, coords, 0);
+    let color = accum.rgb / max(accum.a, 1e-5);
+    return vec4<f32>(color, 1.0 - reveal);
+}
@@ -0,0 +1,49 @@
+// Object ID pass for picking: each pixel holds the ID of the nearest object, 0 for none
+
+struct PickCamera {
+    view_proj: mat4x4<f32>,
+    // Kept where dot(plane.xyz, p) + plane.w >= 0
+    clip_planes: array<vec4<f32>, 4>,
+    // x = number of clip planes
+    clip: vec4<f32>,
+};
+
+struct ModelUniform {
+    model_matrix: mat4x4<f32>,
+    // x = object ID + 1
+    object_id: vec4<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: PickCamera;
+
+@group(1) @binding(0)
+var<uniform> model: ModelUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+    @location(1) @interpolate(flat) object_id: u32,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    let world_pos = model.model_matrix * vec4<f32>(position, 1.0);
+    out.clip_position = camera.view_proj * world_pos;
+    out.world_pos = world_pos.xyz;
+    out.object_id = model.object_id.x;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    // Cut-away geometry can't be picked
+    for (var i = 0u; i < u32(camera.clip.x); i++) {
+        let plane = camera.clip_planes[i];
+        if (dot(plane.xyz, in.world_pos) + plane.w < 0.0) {
+            discard;
+        }
+    }
+    return in.object_id;
+}
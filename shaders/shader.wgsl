@@ -1,18 +1,84 @@
 // Vertex shader
+//
+// Optional features are compiled per variant from the `#ifdef` blocks below: HAS_NORMAL_MAP,
+// HAS_VERTEX_COLOR, SKINNED, ALPHA_MASK, WIND and CHROMA_KEY (see `ShaderFeatures`).
+
+#include "lighting"
 
 struct CameraUniform {
     view_proj: mat4x4<f32>,
     camera_pos: vec4<f32>,
+    // Kept where dot(plane.xyz, p) + plane.w >= 0
+    clip_planes: array<vec4<f32>, 4>,
+    // x = number of clip planes
+    clip: vec4<f32>,
+    cap_color: vec4<f32>,
+    // The view's view_proj last frame, for motion vectors
+    prev_view_proj: mat4x4<f32>,
+    // x = scene time in seconds, y = last frame's
+    time: vec4<f32>,
 };
 
 struct LightUniform {
     direction: vec4<f32>,
     color: vec4<f32>,
     ambient: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    // x = enabled, y = texel size, z = PCF radius, w = depth bias
+    shadow: vec4<f32>,
+};
+
+struct PointLight {
+    // w = range
+    position_range: vec4<f32>,
+    // w = intensity
+    color_intensity: vec4<f32>,
+    // x = first shadow layer (-1 without a shadow), y = fraction of each face in use, z = texel size
+    shadow: vec4<f32>,
+};
+
+struct PointLightUniform {
+    // x = number of lights
+    count: vec4<u32>,
+    lights: array<PointLight, 16>,
+    // Six faces per shadow slot: +X, -X, +Y, -Y, +Z, -Z
+    face_view_proj: array<mat4x4<f32>, 24>,
 };
 
 struct ModelUniform {
     model_matrix: mat4x4<f32>,
+    // x = object ID + 1, for the picking pass
+    object_id: vec4<u32>,
+    // L1 spherical harmonics from the light probes: constant, x, y, z terms; w of the
+    // first is 1 when set, else the flat ambient light is used
+    irradiance: array<vec4<f32>, 4>,
+    // Last frame's model matrix, for motion vectors
+    prev_model_matrix: mat4x4<f32>,
+};
+
+#ifdef SKINNED
+struct JointUniform {
+    matrices: array<mat4x4<f32>, 64>,
+};
+#endif
+
+struct MaterialUniform {
+    reflectivity: f32,
+    roughness: f32,
+    // 1 when back faces are drawn
+    double_sided: f32,
+    // Fragments with lower alpha are discarded by ALPHA_MASK variants
+    alpha_cutoff: f32,
+    // Linear RGBA tint of the diffuse texture
+    base_color: vec4<f32>,
+    // Direction (x, z), strength and gust frequency of the WIND sway
+    wind: vec4<f32>,
+    // x = 0 to weight by vertex color, 1 by height; y = height of full sway
+    wind_weight: vec4<f32>,
+    // Key color as stored in the texture, and the chroma distance cut out by CHROMA_KEY
+    chroma_key: vec4<f32>,
+    // x = edge smoothness, y = spill removal
+    chroma_params: vec4<f32>,
 };
 
 @group(0) @binding(0)
@@ -20,24 +86,53 @@ var<uniform> camera: CameraUniform;
 
 @group(1) @binding(0)
 var<uniform> light: LightUniform;
+@group(1) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(1) @binding(2)
+var s_shadow: sampler_comparison;
+@group(1) @binding(3)
+var<uniform> point_lights: PointLightUniform;
+@group(1) @binding(4)
+var t_point_shadow: texture_depth_2d_array;
 
 @group(2) @binding(0)
 var<uniform> model: ModelUniform;
+#ifdef SKINNED
+@group(2) @binding(1)
+var<uniform> joints: JointUniform;
+#endif
 
 @group(3) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(3) @binding(1)
 var s_diffuse: sampler;
+#ifdef HAS_NORMAL_MAP
 @group(3) @binding(2)
 var t_normal: texture_2d<f32>;
 @group(3) @binding(3)
 var s_normal: sampler;
+#endif
+@group(3) @binding(4)
+var<uniform> material: MaterialUniform;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+#ifdef HAS_NORMAL_MAP
     @location(3) tangent: vec4<f32>,
+#endif
+#ifdef HAS_VERTEX_COLOR
+    @location(4) color: vec4<f32>,
+#else
+#ifdef WIND
+    @location(4) color: vec4<f32>,
+#endif
+#endif
+#ifdef SKINNED
+    @location(5) joints: vec4<u32>,
+    @location(6) weights: vec4<f32>,
+#endif
 };
 
 struct VertexOutput {
@@ -45,34 +140,116 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) world_pos: vec3<f32>,
+#ifdef HAS_NORMAL_MAP
     @location(3) tangent: vec3<f32>,
     @location(4) bitangent: vec3<f32>,
+#endif
+#ifdef HAS_VERTEX_COLOR
+    @location(5) color: vec4<f32>,
+#endif
+    // Where the point was last frame, skinned with this frame's pose
+    @location(6) prev_world_pos: vec3<f32>,
 };
 
+#ifdef WIND
+// How much of the sway a vertex takes, from 0 where the plant is anchored
+fn wind_weight(model_in: VertexInput) -> f32 {
+    if material.wind_weight.x > 0.5 {
+        let height = clamp(model_in.position.y / material.wind_weight.y, 0.0, 1.0);
+        // Bend rather than shear: the top moves most
+        return height * height;
+    }
+    return model_in.color.r;
+}
+
+// Sway of a fully weighted vertex at `world_pos` of the object at `origin`, `time` seconds in
+fn wind_offset(origin: vec3<f32>, world_pos: vec3<f32>, time: f32) -> vec3<f32> {
+    let direction = vec3<f32>(material.wind.x, 0.0, material.wind.y);
+    let across = vec3<f32>(-direction.z, 0.0, direction.x);
+    // Gusts reach objects further downwind later; the whole object leans together
+    let phase = 6.2831853 * material.wind.w * time - dot(origin, direction) * 0.2;
+    let gust = sin(phase) * 0.5 + 0.5;
+    // Faster flutter, out of step between neighbouring vertices
+    let flutter = sin(phase * 3.7 + dot(world_pos, vec3<f32>(1.3, 0.7, 1.9)));
+    return (direction * (0.25 + 0.75 * gust) + across * flutter * 0.15) * material.wind.z;
+}
+#endif
+
 @vertex
 fn vs_main(
     model_in: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
-    let world_pos = model.model_matrix * vec4<f32>(model_in.position, 1.0);
+#ifdef SKINNED
+    // Blend the joint matrices by the vertex weights
+    let skin = joints.matrices[model_in.joints.x] * model_in.weights.x
+        + joints.matrices[model_in.joints.y] * model_in.weights.y
+        + joints.matrices[model_in.joints.z] * model_in.weights.z
+        + joints.matrices[model_in.joints.w] * model_in.weights.w;
+    let model_matrix = model.model_matrix * skin;
+    let prev_model_matrix = model.prev_model_matrix * skin;
+#else
+    let model_matrix = model.model_matrix;
+    let prev_model_matrix = model.prev_model_matrix;
+#endif
+    var world_pos = model_matrix * vec4<f32>(model_in.position, 1.0);
+    var prev_world_pos = prev_model_matrix * vec4<f32>(model_in.position, 1.0);
+#ifdef WIND
+    let weight = wind_weight(model_in);
+    world_pos += vec4<f32>(wind_offset(model_matrix[3].xyz, world_pos.xyz, camera.time.x) * weight, 0.0);
+    prev_world_pos += vec4<f32>(wind_offset(prev_model_matrix[3].xyz, prev_world_pos.xyz, camera.time.y) * weight, 0.0);
+#endif
     out.clip_position = camera.view_proj * world_pos;
     out.tex_coords = model_in.tex_coords;
     
     // Transform normal and tangent to world space
-    let normal = normalize((model.model_matrix * vec4<f32>(model_in.normal, 0.0)).xyz);
-    let tangent = normalize((model.model_matrix * vec4<f32>(model_in.tangent.xyz, 0.0)).xyz);
+    let normal = normalize((model_matrix * vec4<f32>(model_in.normal, 0.0)).xyz);
+#ifdef HAS_NORMAL_MAP
+    let tangent = normalize((model_matrix * vec4<f32>(model_in.tangent.xyz, 0.0)).xyz);
     let bitangent = cross(normal, tangent) * model_in.tangent.w;
-    
-    out.normal = normal;
     out.tangent = tangent;
     out.bitangent = bitangent;
+#endif
+    
+    out.normal = normal;
     out.world_pos = world_pos.xyz;
+    out.prev_world_pos = prev_world_pos.xyz;
+#ifdef HAS_VERTEX_COLOR
+    out.color = model_in.color;
+#endif
     return out;
 }
 
 // Fragment shader
 
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    // World-space normal and reflectivity, consumed by the SSR pass
+    @location(1) normal_reflectivity: vec4<f32>,
+    // Unlit base color, read back by the pixel inspector
+    @location(2) albedo: vec4<f32>,
+    // Screen motion since last frame in UV units: xy in total, zw from the camera alone, read
+    // by the motion blur pass
+    @location(3) velocity: vec4<f32>,
+};
+
+fn clip_to_uv(clip: vec4<f32>) -> vec2<f32> {
+    let ndc = clip.xy / max(clip.w, 1e-5);
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+fn screen_motion(in: VertexOutput) -> vec4<f32> {
+    let current = clip_to_uv(camera.view_proj * vec4<f32>(in.world_pos, 1.0));
+    let previous = clip_to_uv(camera.prev_view_proj * vec4<f32>(in.prev_world_pos, 1.0));
+    // Where the point would have been had only the camera moved
+    let camera_previous = clip_to_uv(camera.prev_view_proj * vec4<f32>(in.world_pos, 1.0));
+    return vec4<f32>(current - previous, current - camera_previous);
+}
+
 fn calculate_normal(in: VertexOutput) -> vec3<f32> {
+#ifndef HAS_NORMAL_MAP
+    return normalize(in.normal);
+#else
     // Sample normal map and transform from [0,1] to [-1,1] range
     let normal_sample = textureSample(t_normal, s_normal, in.tex_coords);
     let normal_map = normal_sample.xyz * 2.0 - 1.0;
@@ -85,32 +262,287 @@ fn calculate_normal(in: VertexOutput) -> vec3<f32> {
     
     // Transform normal from tangent space to world space
     return normalize(TBN * normal_map);
+#endif
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn calculate_shadow(world_pos: vec3<f32>) -> f32 {
+    if (light.shadow.x == 0.0) {
+        return 1.0;
+    }
+
+    let light_clip = light.view_proj * vec4<f32>(world_pos, 1.0);
+    let ndc = light_clip.xyz / light_clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    // Percentage-closer filtering over a (2r+1)^2 kernel
+    let radius = i32(light.shadow.z);
+    let depth = ndc.z - light.shadow.w;
+    var lit = 0.0;
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * light.shadow.y;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, depth);
+        }
+    }
+    let taps = f32((2 * radius + 1) * (2 * radius + 1));
+    return lit / taps;
+}
+
+// Cube face whose frustum contains `direction`
+fn cube_face(direction: vec3<f32>) -> u32 {
+    let a = abs(direction);
+    if (a.x >= a.y && a.x >= a.z) {
+        return select(1u, 0u, direction.x > 0.0);
+    }
+    if (a.y >= a.z) {
+        return select(3u, 2u, direction.y > 0.0);
+    }
+    return select(5u, 4u, direction.z > 0.0);
+}
+
+fn point_shadow(light: PointLight, world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if (light.shadow.x < 0.0) {
+        return 1.0;
+    }
+
+    // Push the lookup about one shadow texel along the normal to avoid acne
+    let scale = light.shadow.y;
+    let texel = light.shadow.z;
+    let texel_world = 2.0 * distance(world_pos, light.position_range.xyz) * texel / scale;
+    let biased = world_pos + normal * texel_world;
+
+    let layer = u32(light.shadow.x) + cube_face(biased - light.position_range.xyz);
+    let clip = point_lights.face_view_proj[layer] * vec4<f32>(biased, 1.0);
+    let ndc = clip.xyz / clip.w;
+    // Lower LODs only fill the top-left corner of the face; keep the filter inside it
+    let half_texel = 0.5 * texel / scale;
+    let uv = clamp(ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5), vec2<f32>(half_texel), vec2<f32>(1.0 - half_texel));
+    return textureSampleCompareLevel(t_point_shadow, s_shadow, uv * scale, layer, ndc.z);
+}
+
+fn point_lighting(world_pos: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < point_lights.count.x; i++) {
+        let light = point_lights.lights[i];
+        let to_light = light.position_range.xyz - world_pos;
+        let distance = length(to_light);
+        let range = light.position_range.w;
+        if (distance >= range) {
+            continue;
+        }
+
+        // Inverse-square falloff, windowed to reach zero at the light's range
+        let window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (distance * distance + 1.0);
+        let radiance = light.color_intensity.rgb * light.color_intensity.w * attenuation
+            * point_shadow(light, world_pos, normal);
+
+        total += blinn_phong(normal, to_light / distance, view_dir, albedo, radiance);
+    }
+    return total;
+}
+
+fn shade(world_pos: vec3<f32>, normal: vec3<f32>, albedo: vec4<f32>, reflectivity: f32) -> FragmentOutput {
+    let view_dir = normalize(camera.camera_pos.xyz - world_pos);
+
+    // Ambient, from the light probes around the object when there are any
+    var ambient_light = light.ambient.rgb;
+    if (model.irradiance[0].w > 0.0) {
+        let sh = model.irradiance;
+        ambient_light = max(sh[0].rgb + sh[1].rgb * normal.x + sh[2].rgb * normal.y + sh[3].rgb * normal.z, vec3<f32>(0.0));
+    }
+    let ambient = ambient_light * albedo.rgb;
+
+    // Directional light, shadowed; its direction points away from the light
+    let shadow = calculate_shadow(world_pos);
+    let directional = blinn_phong(normal, -normalize(light.direction.xyz), view_dir, albedo.rgb, light.color.rgb * shadow);
+
+    let point = point_lighting(world_pos, normal, view_dir, albedo.rgb);
+
+    let final_color = (ambient + directional + point) * sky_occlusion(normal);
+
+    var out: FragmentOutput;
+    out.color = vec4<f32>(final_color, albedo.a);
+    out.normal_reflectivity = vec4<f32>(normal, reflectivity);
+    out.albedo = albedo;
+    return out;
+}
+
+fn is_clipped(world_pos: vec3<f32>) -> bool {
+    for (var i = 0u; i < u32(camera.clip.x); i++) {
+        let plane = camera.clip_planes[i];
+        if (dot(plane.xyz, world_pos) + plane.w < 0.0) {
+            return true;
+        }
+    }
+    return false;
+}
+
+struct SectionCap {
+    // False when the camera is on the kept side of every plane and sees no cut
+    visible: bool,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+};
+
+// Where the view ray through `world_pos` crosses into the kept region, and the cut it crosses
+fn section_cap(world_pos: vec3<f32>) -> SectionCap {
+    let eye = camera.camera_pos.xyz;
+    let ray = world_pos - eye;
+    var cap: SectionCap;
+    cap.visible = false;
+    var entry = 0.0;
+    for (var i = 0u; i < u32(camera.clip.x); i++) {
+        let plane = camera.clip_planes[i];
+        let eye_distance = dot(plane.xyz, eye) + plane.w;
+        let approach = dot(plane.xyz, ray);
+        if (eye_distance < 0.0 && approach > 0.0) {
+            let t = -eye_distance / approach;
+            if (t > entry) {
+                entry = t;
+                cap.visible = true;
+                cap.normal = -plane.xyz;
+            }
+        }
+    }
+    cap.position = eye + ray * entry;
+    return cap;
+}
+
+// Back faces only reach the scene pipelines for double-sided materials
+fn facing_normal(in: VertexOutput, front_facing: bool) -> vec3<f32> {
     let normal = calculate_normal(in);
-    let light_dir = normalize(light.direction.xyz);
-    let view_dir = normalize(camera.camera_pos.xyz - in.world_pos);
-    let half_dir = normalize(view_dir - light_dir);
+    return select(-normal, normal, front_facing);
+}
+
+#ifdef CHROMA_KEY
+// The Cb and Cr of YCbCr (BT.601): hue and saturation without brightness
+fn chroma(rgb: vec3<f32>) -> vec2<f32> {
+    let luma = dot(rgb, vec3<f32>(0.299, 0.587, 0.114));
+    return vec2<f32>((rgb.b - luma) * 0.564, (rgb.r - luma) * 0.713);
+}
 
-    // Sample texture
-    let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+// Fade out texels near the key color by chroma, so the backdrop's shadows go with it, then
+// shift what's left away from the key's tint to take out the spill on edges
+fn chroma_key(color: vec4<f32>) -> vec4<f32> {
+    let key = chroma(material.chroma_key.rgb);
+    let texel = chroma(color.rgb);
+    let threshold = material.chroma_key.w;
+    let alpha = smoothstep(threshold, threshold + material.chroma_params.x, distance(texel, key));
+    if (length(key) < 1e-4) {
+        return vec4<f32>(color.rgb, color.a * alpha);
+    }
+    let direction = normalize(key);
+    let shifted = texel - direction * max(dot(texel, direction), 0.0) * material.chroma_params.y;
+    let luma = dot(color.rgb, vec3<f32>(0.299, 0.587, 0.114));
+    let r = luma + shifted.y / 0.713;
+    let b = luma + shifted.x / 0.564;
+    let g = (luma - 0.299 * r - 0.114 * b) / 0.587;
+    return vec4<f32>(clamp(vec3<f32>(r, g, b), vec3<f32>(0.0), vec3<f32>(1.0)), color.a * alpha);
+}
+#endif
 
-    // Ambient
-    let ambient = light.ambient.rgb * tex_color.rgb;
+fn base_color(in: VertexOutput) -> vec4<f32> {
+#ifdef CHROMA_KEY
+    let tex_color = chroma_key(textureSample(t_diffuse, s_diffuse, in.tex_coords)) * material.base_color;
+#else
+    let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.base_color;
+#endif
+#ifdef HAS_VERTEX_COLOR
+    return tex_color * in.color;
+#else
+    return tex_color;
+#endif
+}
+
+// Whether ALPHA_MASK variants cut the fragment out; with alpha-to-coverage the written alpha
+// decides the covered samples instead
+fn is_masked(color: vec4<f32>) -> bool {
+#ifdef ALPHA_TO_COVERAGE
+    return false;
+#else
+#ifdef ALPHA_MASK
+    return color.a < material.alpha_cutoff;
+#else
+    return false;
+#endif
+#endif
+}
+
+// Alpha for alpha-to-coverage: the cutoff edge sharpened to about a pixel wide, so masked
+// textures keep crisp edges up close and soften only where texels shrink below a pixel
+fn coverage(alpha: f32) -> f32 {
+    return clamp((alpha - material.alpha_cutoff) / max(fwidth(alpha), 1e-4) + 0.5, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> FragmentOutput {
+    // Sample before any discard so derivatives stay in uniform control flow
+    let tex_color = base_color(in);
+    var out = shade(in.world_pos, facing_normal(in, front_facing), tex_color, material.reflectivity);
+#ifdef ALPHA_TO_COVERAGE
+    out.color.a = coverage(tex_color.a);
+#endif
+    if (is_clipped(in.world_pos) || is_masked(tex_color)) {
+        discard;
+    }
+    out.velocity = screen_motion(in);
+    return out;
+}
 
-    // Diffuse
-    let diff = max(dot(normal, -light_dir), 0.0);
-    let diffuse = light.color.rgb * diff * tex_color.rgb;
+// Section view, drawn without culling: back faces of single-sided materials seen through a cut
+// are the inside of a closed mesh and are filled flat with the cap color, lit as if they lay on
+// the cut plane
+@fragment
+fn fs_section(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> FragmentOutput {
+    let tex_color = base_color(in);
+    let normal = facing_normal(in, front_facing);
+    let cap = section_cap(in.world_pos);
+    let surface = front_facing || material.double_sided > 0.5;
+#ifdef ALPHA_TO_COVERAGE
+    // Derivatives are taken before branching on the facing
+    let alpha = coverage(tex_color.a);
+#endif
 
-    // Specular
-    let spec = pow(max(dot(normal, half_dir), 0.0), 32.0);
-    let specular = light.color.rgb * spec * 0.5;
+    var out: FragmentOutput;
+    if (surface) {
+        out = shade(in.world_pos, normal, tex_color, material.reflectivity);
+#ifdef ALPHA_TO_COVERAGE
+        out.color.a = alpha;
+#endif
+    } else {
+        out = shade(cap.position, cap.normal, vec4<f32>(camera.cap_color.rgb, 1.0), 0.0);
+    }
+    if (is_clipped(in.world_pos) || is_masked(tex_color) || (!surface && !cap.visible)) {
+        discard;
+    }
+    out.velocity = screen_motion(in);
+    return out;
+}
+// Weighted blended order-independent transparency (McGuire and Bavoil 2013): every layer adds
+// its premultiplied color to `accum`, weighted to favour what's near the camera, and multiplies
+// `reveal` by (1 - alpha). `oit.wgsl` divides the two out over the opaque scene.
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) reveal: f32,
+};
 
-    // Ambient occlusion (simple)
-    let ao = max(dot(normal, vec3<f32>(0.0, 1.0, 0.0)), 0.0) * 0.2 + 0.8;
+@fragment
+fn fs_oit(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> OitOutput {
+    let tex_color = base_color(in);
+    let shaded = shade(in.world_pos, facing_normal(in, front_facing), tex_color, material.reflectivity);
+    if (is_clipped(in.world_pos) || is_masked(tex_color)) {
+        discard;
+    }
+    let alpha = clamp(shaded.color.a, 0.0, 1.0);
+    let d = distance(camera.camera_pos.xyz, in.world_pos);
+    let weight = alpha * clamp(10.0 / (1e-5 + pow(d / 5.0, 2.0) + pow(d / 200.0, 6.0)), 1e-2, 3e3);
 
-    let final_color = (ambient + diffuse + specular) * ao;
-    return vec4<f32>(final_color, tex_color.a);
-} 
\ No newline at end of file
+    var out: OitOutput;
+    out.accum = vec4<f32>(shaded.color.rgb * alpha, alpha) * weight;
+    out.reveal = alpha;
+    return out;
+}
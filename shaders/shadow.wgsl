@@ -0,0 +1,20 @@
+// Depth-only pass rendering the scene from the directional light
+
+struct ShadowUniform {
+    light_view_proj: mat4x4<f32>,
+};
+
+struct ModelUniform {
+    model_matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> shadow: ShadowUniform;
+
+@group(1) @binding(0)
+var<uniform> model: ModelUniform;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return shadow.light_view_proj * model.model_matrix * vec4<f32>(position, 1.0);
+}
@@ -0,0 +1,126 @@
+// Screen-space reflections post pass
+
+struct SsrUniform {
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+    // x = max steps, y = stride (view-space units), z = thickness, w = max distance
+    params: vec4<f32>,
+    // x = binary refinement steps
+    refine: vec4<f32>,
+    env_zenith: vec4<f32>,
+    env_horizon: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ssr: SsrUniform;
+@group(0) @binding(1)
+var t_color: texture_2d<f32>;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(4)
+var s_color: sampler;
+
+#include "fullscreen"
+
+fn depth_at(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coord = vec2<i32>(clamp(uv * size, vec2<f32>(0.0), size - vec2<f32>(1.0)));
+    return textureLoad(t_depth, coord, 0).x;
+}
+
+fn view_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view = ssr.inv_proj * ndc;
+    return view.xyz / view.w;
+}
+
+fn project(view_pos: vec3<f32>) -> vec2<f32> {
+    let clip = ssr.proj * vec4<f32>(view_pos, 1.0);
+    let ndc = clip.xy / clip.w;
+    return vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+}
+
+fn environment(view_dir: vec3<f32>) -> vec3<f32> {
+    // Fallback probe: a simple sky gradient sampled with the world-space direction
+    let world_dir = normalize((ssr.inv_view * vec4<f32>(view_dir, 0.0)).xyz);
+    let t = clamp(world_dir.y * 0.5 + 0.5, 0.0, 1.0);
+    return mix(ssr.env_horizon.rgb, ssr.env_zenith.rgb, t);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let base = textureSampleLevel(t_color, s_color, in.uv, 0.0);
+    let normal_reflectivity = textureSampleLevel(t_normal, s_color, in.uv, 0.0);
+    let reflectivity = normal_reflectivity.w;
+    let depth = depth_at(in.uv);
+
+    if (reflectivity <= 0.0 || depth >= 1.0) {
+        return base;
+    }
+
+    let origin = view_position(in.uv, depth);
+    let normal = normalize((ssr.view * vec4<f32>(normal_reflectivity.xyz, 0.0)).xyz);
+    let incident = normalize(origin);
+    let direction = normalize(reflect(incident, normal));
+
+    let max_steps = u32(ssr.params.x);
+    let stride = ssr.params.y;
+    let thickness = ssr.params.z;
+    let max_distance = ssr.params.w;
+
+    var reflection = environment(direction);
+    var previous = origin;
+    var hit = false;
+    var hit_uv = in.uv;
+
+    for (var i = 1u; i <= max_steps; i = i + 1u) {
+        let travelled = stride * f32(i);
+        if (travelled > max_distance) {
+            break;
+        }
+        let sample_pos = origin + direction * travelled;
+        let uv = project(sample_pos);
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            break;
+        }
+
+        let scene_pos = view_position(uv, depth_at(uv));
+        let delta = scene_pos.z - sample_pos.z;
+        if (delta > 0.0 && delta < thickness) {
+            // Binary search between the last two samples to tighten the hit
+            var low = previous;
+            var high = sample_pos;
+            for (var j = 0u; j < u32(ssr.refine.x); j = j + 1u) {
+                let mid = (low + high) * 0.5;
+                let mid_uv = project(mid);
+                let mid_scene = view_position(mid_uv, depth_at(mid_uv));
+                if (mid_scene.z - mid.z > 0.0) {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            hit_uv = project(high);
+            hit = true;
+            break;
+        }
+        previous = sample_pos;
+    }
+
+    if (hit) {
+        // Fade towards the probe near screen edges where the ray runs out of data
+        let edge = min(min(hit_uv.x, 1.0 - hit_uv.x), min(hit_uv.y, 1.0 - hit_uv.y));
+        let fade = clamp(edge * 10.0, 0.0, 1.0);
+        let hit_color = textureSampleLevel(t_color, s_color, hit_uv, 0.0).rgb;
+        reflection = mix(reflection, hit_color, fade);
+    }
+
+    // Schlick-style fresnel so reflections strengthen at grazing angles
+    let cos_theta = clamp(dot(-incident, normal), 0.0, 1.0);
+    let fresnel = 0.25 + 0.75 * pow(1.0 - cos_theta, 5.0);
+    return vec4<f32>(mix(base.rgb, reflection, reflectivity * fresnel), base.a);
+}
@@ -0,0 +1,26 @@
+// Fills the pixels passing a stencil overlay's stencil test with its color; the normal and
+// albedo targets are masked off by the pipeline
+
+struct OverlayUniform {
+    color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> overlay: OverlayUniform;
+
+#include "fullscreen"
+
+struct OverlayOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) albedo: vec4<f32>,
+};
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> OverlayOutput {
+    var out: OverlayOutput;
+    out.color = overlay.color;
+    out.normal = vec4<f32>(0.0);
+    out.albedo = vec4<f32>(0.0);
+    return out;
+}
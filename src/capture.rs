@@ -0,0 +1,134 @@
+//! Programmatic RenderDoc frame captures. Built with the `renderdoc` feature and launched from
+//! RenderDoc (or with it injected), `trigger_capture` records the next frame to a capture file;
+//! otherwise every call is a no-op.
+
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V141};
+
+/// Captures whole frames between `begin_frame` and `end_frame`.
+///
+/// Frames are bracketed explicitly rather than by surface presents, so a VR frame, which is
+/// handed to the OpenXR compositor instead of presented, is captured with both eyes when its
+/// `VRSystem::begin_frame` .. `VRSystem::end_frame` is wrapped the same way.
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    api: Option<RenderDoc<V141>>,
+    requested: bool,
+    capturing: bool,
+}
+
+impl FrameCapture {
+    /// Attach to RenderDoc if it's loaded into the process
+    pub fn new() -> Self {
+        #[cfg(feature = "renderdoc")]
+        let api = match RenderDoc::new() {
+            Ok(api) => {
+                log::info!("RenderDoc attached; F11 captures the next frame");
+                Some(api)
+            }
+            Err(e) => {
+                log::debug!("RenderDoc not available: {}", e);
+                None
+            }
+        };
+        Self {
+            #[cfg(feature = "renderdoc")]
+            api,
+            requested: false,
+            capturing: false,
+        }
+    }
+
+    /// Whether captures can be taken: built with `renderdoc` and running under RenderDoc
+    pub fn is_available(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        let available = self.api.is_some();
+        #[cfg(not(feature = "renderdoc"))]
+        let available = false;
+        available
+    }
+
+    /// Capture the next frame
+    pub fn trigger_capture(&mut self) {
+        if !self.is_available() {
+            if cfg!(feature = "renderdoc") {
+                log::warn!("Frame capture requested, but RenderDoc isn't attached; launch the viewer from RenderDoc");
+            } else {
+                log::warn!("Frame capture requested, but the viewer was built without the `renderdoc` feature");
+            }
+            return;
+        }
+        self.requested = true;
+    }
+
+    /// Whether the frame in progress is being captured
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    /// Start capturing if a capture was requested. Call before any of the frame's GPU work.
+    pub fn begin_frame(&mut self) {
+        if std::mem::take(&mut self.requested) {
+            self.capturing = self.start_capture();
+        }
+    }
+
+    /// Finish a capture started by `begin_frame`. Call once the frame is submitted.
+    pub fn end_frame(&mut self) {
+        if std::mem::take(&mut self.capturing) {
+            self.finish_capture();
+        }
+    }
+
+    #[cfg(feature = "renderdoc")]
+    fn start_capture(&mut self) -> bool {
+        let Some(api) = &mut self.api else {
+            return false;
+        };
+        // Null device and window match the only device, whichever window (if any) it draws to
+        api.start_frame_capture(std::ptr::null(), std::ptr::null());
+        true
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    fn start_capture(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "renderdoc")]
+    fn finish_capture(&mut self) {
+        let Some(api) = &mut self.api else {
+            return;
+        };
+        api.end_frame_capture(std::ptr::null(), std::ptr::null());
+        match api.get_num_captures().checked_sub(1).and_then(|index| api.get_capture(index)) {
+            Some((path, _)) => log::info!("Saved frame capture to {}", path.display()),
+            None => log::warn!("RenderDoc didn't record a capture for this frame"),
+        }
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    fn finish_capture(&mut self) {}
+}
+
+impl Default for FrameCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_without_renderdoc_is_noop() {
+        // Tests never run under RenderDoc, so requests are dropped rather than left pending
+        let mut capture = FrameCapture::new();
+        assert!(!capture.is_available());
+        capture.trigger_capture();
+        capture.begin_frame();
+        assert!(!capture.is_capturing());
+        capture.end_frame();
+    }
+}
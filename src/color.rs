@@ -0,0 +1,334 @@
+use std::fmt;
+use std::ops::Mul;
+use std::str::FromStr;
+use anyhow::{anyhow, bail, Result};
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An RGBA color, stored in linear space with straight alpha.
+///
+/// Every sRGB conversion in the engine goes through this module: colors picked in an editor,
+/// written as hex or taken from 8-bit files are decoded once on construction, and everything
+/// downstream (uniforms, clear colors, lights) reads linear values. Shaders output linear color
+/// and leave the encoding to the sRGB surface or swapchain format.
+///
+/// In settings and prefab files a color is either a `"#rrggbb"` / `"#rrggbbaa"` hex string
+/// (sRGB, as color pickers show it) or a `[r, g, b]` / `[r, g, b, a]` array of linear values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Self::WHITE
+    }
+}
+
+impl Color {
+    pub const WHITE: Self = Self::linear(1.0, 1.0, 1.0);
+    pub const BLACK: Self = Self::linear(0.0, 0.0, 0.0);
+    pub const TRANSPARENT: Self = Self::linear_rgba(0.0, 0.0, 0.0, 0.0);
+
+    /// Opaque color from linear values; above 1 is fine for light colors
+    pub const fn linear(r: f32, g: f32, b: f32) -> Self {
+        Self::linear_rgba(r, g, b, 1.0)
+    }
+
+    pub const fn linear_rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Opaque color from sRGB-encoded values in 0..1
+    pub fn srgb(r: f32, g: f32, b: f32) -> Self {
+        Self::srgba(r, g, b, 1.0)
+    }
+
+    /// sRGB-encoded color with linear alpha, as alpha is never gamma encoded
+    pub fn srgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self::linear_rgba(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    pub fn srgb8(r: u8, g: u8, b: u8) -> Self {
+        Self::srgba8(r, g, b, 255)
+    }
+
+    pub fn srgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        let unit = |value: u8| f32::from(value) / 255.0;
+        Self::srgba(unit(r), unit(g), unit(b), unit(a))
+    }
+
+    /// Parse `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` (the `#` is optional) as sRGB
+    pub fn hex(hex: &str) -> Result<Self> {
+        let digits = hex.trim().trim_start_matches('#');
+        if !digits.is_ascii() {
+            bail!("Invalid hex color '{}'", hex);
+        }
+        let parse = |range: std::ops::Range<usize>| {
+            u8::from_str_radix(&digits[range], 16).map_err(|_| anyhow!("Invalid hex color '{}'", hex))
+        };
+        let (r, g, b, a) = match digits.len() {
+            // Short forms repeat each digit: "f80" is "ff8800"
+            3 | 4 => {
+                let short = |index: usize| parse(index..index + 1).map(|value| value * 17);
+                let a = if digits.len() == 4 { short(3)? } else { 255 };
+                (short(0)?, short(1)?, short(2)?, a)
+            }
+            6 | 8 => {
+                let a = if digits.len() == 8 { parse(6..8)? } else { 255 };
+                (parse(0..2)?, parse(2..4)?, parse(4..6)?, a)
+            }
+            _ => bail!("Invalid hex color '{}': expected 3, 4, 6 or 8 digits", hex),
+        };
+        Ok(Self::srgba8(r, g, b, a))
+    }
+
+    /// Opaque color from hue in degrees, saturation and value in 0..1. Like color pickers, the
+    /// components are taken as sRGB, so equal steps in value look evenly spaced.
+    pub fn hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let (saturation, value) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let min = value - chroma;
+        Self::srgb(r + min, g + min, b + min)
+    }
+
+    /// Hue in degrees, saturation and value of the sRGB-encoded color, the inverse of `hsv`
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let [r, g, b, _] = self.to_srgb();
+        let max = r.max(g).max(b);
+        let chroma = max - r.min(g).min(b);
+        let hue = if chroma <= 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / chroma).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / chroma + 2.0)
+        } else {
+            60.0 * ((r - g) / chroma + 4.0)
+        };
+        let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+        (hue, saturation, max)
+    }
+
+    /// sRGB-encoded components, clamped to 0..1, with alpha unchanged
+    pub fn to_srgb(self) -> [f32; 4] {
+        let encode = |value: f32| linear_to_srgb(value.clamp(0.0, 1.0));
+        [encode(self.r), encode(self.g), encode(self.b), self.a]
+    }
+
+    pub fn to_srgba8(self) -> [u8; 4] {
+        let [r, g, b, a] = self.to_srgb();
+        [r, g, b, a.clamp(0.0, 1.0)].map(|value| (value * 255.0).round() as u8)
+    }
+
+    /// `#rrggbb`, or `#rrggbbaa` when not opaque
+    pub fn to_hex(self) -> String {
+        let [r, g, b, a] = self.to_srgba8();
+        if a == 255 {
+            format!("#{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+        }
+    }
+
+    /// Linear components, for uniforms and vertex data
+    pub fn to_array(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn rgb(self) -> Vec3 {
+        Vec3::new(self.r, self.g, self.b)
+    }
+
+    pub fn to_vec4(self) -> Vec4 {
+        Vec4::new(self.r, self.g, self.b, self.a)
+    }
+
+    pub fn with_alpha(self, a: f32) -> Self {
+        Self { a, ..self }
+    }
+
+    /// Scale the color, not the alpha, e.g. by a light intensity
+    pub fn scaled(self, factor: f32) -> Self {
+        Self::linear_rgba(self.r * factor, self.g * factor, self.b * factor, self.a)
+    }
+
+    /// Interpolate towards `other` in linear space
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self::from(self.to_vec4().lerp(other.to_vec4(), t))
+    }
+}
+
+/// Decode one sRGB component in 0..1 to linear
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode one linear component in 0..1 as sRGB
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Componentwise product, e.g. tinting a light
+impl Mul for Color {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::from(self.to_vec4() * other.to_vec4())
+    }
+}
+
+/// Linear RGB, opaque
+impl From<Vec3> for Color {
+    fn from(rgb: Vec3) -> Self {
+        Self::linear(rgb.x, rgb.y, rgb.z)
+    }
+}
+
+/// Linear RGBA
+impl From<Vec4> for Color {
+    fn from(rgba: Vec4) -> Self {
+        Self::linear_rgba(rgba.x, rgba.y, rgba.z, rgba.w)
+    }
+}
+
+/// wgpu takes clear colors in linear space and encodes them for sRGB targets itself
+impl From<Color> for wgpu::Color {
+    fn from(color: Color) -> Self {
+        Self {
+            r: f64::from(color.r),
+            g: f64::from(color.g),
+            b: f64::from(color.b),
+            a: f64::from(color.a),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        Self::hex(hex)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Arrays keep HDR light colors above 1, which hex can't
+        if self.a == 1.0 {
+            [self.r, self.g, self.b].serialize(serializer)
+        } else {
+            self.to_array().serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Hex(String),
+            Linear(Vec<f32>),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Hex(hex) => Color::hex(&hex).map_err(serde::de::Error::custom),
+            Repr::Linear(values) => match values[..] {
+                [r, g, b] => Ok(Color::linear(r, g, b)),
+                [r, g, b, a] => Ok(Color::linear_rgba(r, g, b, a)),
+                _ => Err(serde::de::Error::invalid_length(values.len(), &"3 or 4 components")),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_round_trip() {
+        // Mid grey in sRGB is about a fifth in linear
+        let grey = Color::srgb8(128, 128, 128);
+        assert!((grey.r - 0.2158).abs() < 1e-3, "{grey:?}");
+        assert_eq!(grey.to_srgba8(), [128, 128, 128, 255]);
+        for value in 0..=255 {
+            assert_eq!(Color::srgb8(value, 0, 0).to_srgba8()[0], value);
+        }
+        // Out-of-range linear light colors encode clamped
+        assert_eq!(Color::linear(4.0, -1.0, 0.0).to_srgba8(), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Color::hex("#ff8000").unwrap().to_srgba8(), [255, 128, 0, 255]);
+        assert_eq!(Color::hex("f80").unwrap(), Color::hex("#ff8800").unwrap());
+        assert_eq!(Color::hex("#ffffff80").unwrap().a, 128.0 / 255.0);
+        assert_eq!("#000".parse::<Color>().unwrap(), Color::BLACK);
+        assert!(Color::hex("#12345").is_err());
+        assert!(Color::hex("#gg0000").is_err());
+        assert!(Color::hex("#ffé0").is_err());
+
+        assert_eq!(Color::hex("#1a2b3c").unwrap().to_hex(), "#1a2b3c");
+        assert_eq!(Color::srgba8(255, 0, 0, 64).to_string(), "#ff000040");
+    }
+
+    #[test]
+    fn test_hsv() {
+        assert_eq!(Color::hsv(0.0, 1.0, 1.0).to_srgba8(), [255, 0, 0, 255]);
+        assert_eq!(Color::hsv(120.0, 1.0, 1.0).to_srgba8(), [0, 255, 0, 255]);
+        assert_eq!(Color::hsv(-120.0, 1.0, 1.0).to_srgba8(), [0, 0, 255, 255]);
+        // Value is sRGB, so half value is sRGB mid grey
+        assert_eq!(Color::hsv(0.0, 0.0, 0.5), Color::srgb(0.5, 0.5, 0.5));
+
+        let (hue, saturation, value) = Color::hex("#3366cc").unwrap().to_hsv();
+        assert!((hue - 220.0).abs() < 0.5 && (saturation - 0.75).abs() < 1e-3 && (value - 0.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Light {
+            color: Color,
+        }
+
+        let hex: Light = toml::from_str("color = \"#ff0000\"").unwrap();
+        assert_eq!(hex.color, Color::linear(1.0, 0.0, 0.0));
+        // Arrays are linear and may be brighter than white
+        let linear: Light = toml::from_str("color = [2.0, 0.5, 0.0]").unwrap();
+        assert_eq!(linear.color, Color::linear(2.0, 0.5, 0.0));
+        assert!(toml::from_str::<Light>("color = [1.0, 0.0]").is_err());
+
+        let translucent = Light { color: Color::linear_rgba(0.25, 0.5, 1.0, 0.5) };
+        assert_eq!(toml::from_str::<Light>(&toml::to_string(&translucent).unwrap()).unwrap(), translucent);
+        assert_eq!(toml::to_string(&linear).unwrap().trim(), "color = [2.0, 0.5, 0.0]");
+    }
+}
@@ -0,0 +1,426 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Records kept for in-engine inspection
+pub const LOG_CAPACITY: usize = 256;
+/// Pass and draw labels kept for crash reports
+pub const BREADCRUMB_CAPACITY: usize = 64;
+/// Validation messages and GPU errors kept for crash reports
+pub const GPU_MESSAGE_CAPACITY: usize = 32;
+/// Breadcrumbs attached to each GPU message, most recent last
+pub const MESSAGE_BREADCRUMBS: usize = 8;
+/// Where crash reports are written on device loss, relative to the working directory
+pub const CRASH_REPORT_DIR: &str = "crash-reports";
+// Vulkan validation layer messages are logged by wgpu-hal under this target
+const VULKAN_TARGET: &str = "wgpu_hal::vulkan";
+// Identical messages within this window are folded into one entry instead of printed again
+const REPEAT_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub time: Instant,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// Further occurrences folded into this entry by rate limiting
+    pub repeats: u32,
+}
+
+/// Fixed-size buffer of recent log records with repeat folding
+pub struct LogRing {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Record a message; returns false when it repeated a recent entry and was folded into it
+    pub fn push(&mut self, level: Level, target: &str, message: String, now: Instant) -> bool {
+        let repeat = self.entries.iter_mut().rev()
+            .take_while(|entry| now.duration_since(entry.time) < REPEAT_WINDOW)
+            .find(|entry| entry.level == level && entry.target == target && entry.message == message);
+        if let Some(entry) = repeat {
+            entry.repeats += 1;
+            return false;
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry {
+            time: now,
+            level,
+            target: target.to_string(),
+            message,
+            repeats: 0,
+        });
+        true
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    /// Entries at `level` or more severe recorded after `since`
+    pub fn count_since(&self, level: Level, since: Instant) -> usize {
+        self.entries.iter()
+            .filter(|entry| entry.level <= level && entry.time >= since)
+            .count()
+    }
+}
+
+/// A label recorded as work is encoded, e.g. a pass name or the object a draw belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breadcrumb {
+    pub frame: u64,
+    pub label: Cow<'static, str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuMessageSource {
+    /// Reported by the Vulkan validation layers
+    Validation,
+    /// A wgpu error no error scope caught
+    Uncaptured,
+    /// A wgpu error caught by `scoped`
+    Scoped,
+}
+
+/// A validation message or GPU error with what was being encoded when it arrived
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpuMessage {
+    pub time: Instant,
+    pub frame: u64,
+    pub source: GpuMessageSource,
+    pub level: Level,
+    pub message: String,
+    pub breadcrumbs: Vec<Breadcrumb>,
+}
+
+/// Recent breadcrumbs and GPU messages, the material for a post-mortem crash report
+pub struct GpuTrail {
+    frame: u64,
+    breadcrumbs: VecDeque<Breadcrumb>,
+    messages: VecDeque<GpuMessage>,
+}
+
+impl GpuTrail {
+    pub fn new() -> Self {
+        Self {
+            frame: 0,
+            breadcrumbs: VecDeque::with_capacity(BREADCRUMB_CAPACITY),
+            messages: VecDeque::with_capacity(GPU_MESSAGE_CAPACITY),
+        }
+    }
+
+    pub fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    pub fn breadcrumb(&mut self, label: Cow<'static, str>) {
+        if self.breadcrumbs.len() == BREADCRUMB_CAPACITY {
+            self.breadcrumbs.pop_front();
+        }
+        self.breadcrumbs.push_back(Breadcrumb { frame: self.frame, label });
+    }
+
+    pub fn breadcrumbs(&self) -> impl DoubleEndedIterator<Item = &Breadcrumb> {
+        self.breadcrumbs.iter()
+    }
+
+    pub fn message(&mut self, source: GpuMessageSource, level: Level, message: String, now: Instant) {
+        if self.messages.len() == GPU_MESSAGE_CAPACITY {
+            self.messages.pop_front();
+        }
+        let skip = self.breadcrumbs.len().saturating_sub(MESSAGE_BREADCRUMBS);
+        self.messages.push_back(GpuMessage {
+            time: now,
+            frame: self.frame,
+            source,
+            level,
+            message,
+            breadcrumbs: self.breadcrumbs.iter().skip(skip).cloned().collect(),
+        });
+    }
+
+    /// Add a continuation line (the objects or command buffers involved) to the last validation message
+    fn continue_validation(&mut self, line: &str) {
+        if let Some(last) = self.messages.back_mut().filter(|last| last.source == GpuMessageSource::Validation) {
+            last.message.push('\n');
+            last.message.push_str(line);
+        }
+    }
+
+    pub fn messages(&self) -> impl DoubleEndedIterator<Item = &GpuMessage> {
+        self.messages.iter()
+    }
+
+    /// Human-readable report of the trail, newest GPU messages last
+    pub fn report(&self, reason: &str, log: &[LogEntry]) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "GPU crash report: {}", reason);
+        let _ = writeln!(report, "Frame: {}", self.frame);
+
+        let _ = writeln!(report, "\nGPU messages ({}):", self.messages.len());
+        for message in &self.messages {
+            let _ = writeln!(report, "[frame {}] {:?} {}: {}", message.frame, message.source, message.level, message.message);
+            let labels: Vec<&str> = message.breadcrumbs.iter().map(|crumb| crumb.label.as_ref()).collect();
+            let _ = writeln!(report, "    after: {}", labels.join(" > "));
+        }
+
+        let _ = writeln!(report, "\nBreadcrumbs, oldest first ({}):", self.breadcrumbs.len());
+        for crumb in &self.breadcrumbs {
+            let _ = writeln!(report, "[frame {}] {}", crumb.frame, crumb.label);
+        }
+
+        let _ = writeln!(report, "\nRecent log ({}):", log.len());
+        for entry in log {
+            let _ = writeln!(report, "{} {}: {}", entry.level, entry.target, entry.message);
+        }
+        report
+    }
+}
+
+impl Default for GpuTrail {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn trail() -> &'static Mutex<GpuTrail> {
+    static TRAIL: OnceLock<Mutex<GpuTrail>> = OnceLock::new();
+    TRAIL.get_or_init(|| Mutex::new(GpuTrail::new()))
+}
+
+fn record_gpu_message(source: GpuMessageSource, level: Level, message: String) {
+    if let Ok(mut trail) = trail().lock() {
+        trail.message(source, level, message, Instant::now());
+    }
+}
+
+fn ring() -> &'static Mutex<LogRing> {
+    static RING: OnceLock<Mutex<LogRing>> = OnceLock::new();
+    RING.get_or_init(|| Mutex::new(LogRing::new(LOG_CAPACITY)))
+}
+
+/// Forwards to env_logger (filtered by `RUST_LOG`) after recording into the ring buffer.
+/// Warnings and errors are always recorded, even when not printed.
+struct DiagnosticsLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for DiagnosticsLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= Level::Warn && record.target().starts_with(VULKAN_TARGET) {
+            let message = record.args().to_string();
+            if let Ok(mut trail) = trail().lock() {
+                // wgpu-hal logs the objects involved as separate tab-indented lines
+                match message.strip_prefix('\t') {
+                    Some(line) => trail.continue_validation(line),
+                    None => trail.message(GpuMessageSource::Validation, record.level(), message, Instant::now()),
+                }
+            }
+        }
+        // A poisoned ring only loses history; still print
+        let first = match ring().lock() {
+            Ok(mut ring) => ring.push(record.level(), record.target(), record.args().to_string(), Instant::now()),
+            Err(_) => true,
+        };
+        if first && self.inner.matches(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the engine logger; call once at startup in place of `env_logger::init`
+pub fn init() {
+    let inner = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).build();
+    let max_level = inner.filter().max(LevelFilter::Warn);
+    if log::set_boxed_logger(Box::new(DiagnosticsLogger { inner })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Most recent log entries, oldest first
+pub fn recent(count: usize) -> Vec<LogEntry> {
+    let Ok(ring) = ring().lock() else { return Vec::new() };
+    let mut entries: Vec<LogEntry> = ring.entries().rev().take(count).cloned().collect();
+    entries.reverse();
+    entries
+}
+
+/// Mark the start of frame `frame` in the breadcrumb trail
+pub fn breadcrumb_frame(frame: u64) {
+    if let Ok(mut trail) = trail().lock() {
+        trail.set_frame(frame);
+    }
+}
+
+/// Record a label for the work about to be encoded, e.g. a pass or a draw's object
+pub fn breadcrumb(label: impl Into<Cow<'static, str>>) {
+    if let Ok(mut trail) = trail().lock() {
+        trail.breadcrumb(label.into());
+    }
+}
+
+/// Most recent breadcrumbs, oldest first
+pub fn breadcrumbs() -> Vec<Breadcrumb> {
+    let Ok(trail) = trail().lock() else { return Vec::new() };
+    trail.breadcrumbs().cloned().collect()
+}
+
+/// Recent validation messages and GPU errors, oldest first
+pub fn gpu_messages() -> Vec<GpuMessage> {
+    let Ok(trail) = trail().lock() else { return Vec::new() };
+    trail.messages().cloned().collect()
+}
+
+/// Write the breadcrumbs, GPU messages and recent log to a timestamped file in `dir`
+pub fn write_crash_report(dir: &Path, reason: &str) -> std::io::Result<PathBuf> {
+    let report = match trail().lock() {
+        Ok(trail) => trail.report(reason, &recent(LOG_CAPACITY)),
+        Err(poisoned) => poisoned.into_inner().report(reason, &recent(LOG_CAPACITY)),
+    };
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("gpu-crash-{}.txt", stamp));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Route wgpu errors that escape any error scope into the log instead of panicking, and
+/// write a crash report to `CRASH_REPORT_DIR` if the device is lost
+pub fn install_device_handler(device: &wgpu::Device) {
+    device.on_uncaptured_error(Box::new(|error| {
+        record_gpu_message(GpuMessageSource::Uncaptured, Level::Error, error.to_string());
+        log::error!(target: "wgpu", "Uncaptured: {}", error);
+    }));
+    device.set_device_lost_callback(|reason, message| {
+        // Destroying or dropping the device also reports it lost
+        if reason != wgpu::DeviceLostReason::Unknown {
+            return;
+        }
+        log::error!(target: "wgpu", "Device lost: {}", message);
+        match write_crash_report(Path::new(CRASH_REPORT_DIR), &format!("device lost: {}", message)) {
+            Ok(path) => log::error!("Wrote GPU crash report to {}", path.display()),
+            Err(e) => log::error!("Failed to write GPU crash report: {}", e),
+        }
+    });
+}
+
+/// Run `f` inside a validation error scope, logging any error together with `context`
+/// (e.g. which pass or material was being created)
+pub fn scoped<T>(device: &wgpu::Device, context: &str, f: impl FnOnce() -> T) -> T {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = f();
+    // Native backends resolve the scope immediately, so this never actually waits
+    if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+        let message = format!("{}: {}", context, error);
+        record_gpu_message(GpuMessageSource::Scoped, Level::Error, message.clone());
+        log::error!(target: "wgpu", "{}", message);
+    }
+    result
+}
+
+/// Like `scoped`, but a validation error is returned to the caller instead of logged
+pub fn checked<T>(device: &wgpu::Device, context: &str, f: impl FnOnce() -> T) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = f();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(anyhow::anyhow!("{}: {}", context, error)),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_are_folded() {
+        let mut ring = LogRing::new(8);
+        let start = Instant::now();
+        assert!(ring.push(Level::Error, "wgpu", "bad bind group".into(), start));
+        assert!(!ring.push(Level::Error, "wgpu", "bad bind group".into(), start + Duration::from_millis(10)));
+        assert!(ring.push(Level::Warn, "wgpu", "bad bind group".into(), start));
+
+        let entries: Vec<_> = ring.entries().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].repeats, 1);
+
+        // Outside the window the message is recorded (and printed) again
+        assert!(ring.push(Level::Error, "wgpu", "bad bind group".into(), start + REPEAT_WINDOW * 2));
+    }
+
+    #[test]
+    fn test_ring_capacity_and_counts() {
+        let mut ring = LogRing::new(3);
+        let start = Instant::now();
+        for i in 0..5 {
+            let level = if i % 2 == 0 { Level::Error } else { Level::Info };
+            ring.push(level, "engine", format!("message {}", i), start + Duration::from_secs(i));
+        }
+
+        let messages: Vec<_> = ring.entries().map(|entry| entry.message.as_str()).collect();
+        assert_eq!(messages, ["message 2", "message 3", "message 4"]);
+        assert_eq!(ring.count_since(Level::Error, start), 2);
+        assert_eq!(ring.count_since(Level::Warn, start + Duration::from_secs(3)), 1);
+        assert_eq!(ring.count_since(Level::Trace, start), 3);
+    }
+
+    #[test]
+    fn test_gpu_trail_messages_carry_breadcrumbs() {
+        let mut trail = GpuTrail::new();
+        let start = Instant::now();
+        for frame in 1..=3 {
+            trail.set_frame(frame);
+            trail.breadcrumb("Shadows".into());
+            trail.breadcrumb(format!("Scene: object {}", frame).into());
+        }
+        for i in 0..BREADCRUMB_CAPACITY {
+            trail.breadcrumb(format!("filler {}", i).into());
+        }
+        assert_eq!(trail.breadcrumbs().count(), BREADCRUMB_CAPACITY);
+        assert_eq!(trail.breadcrumbs().next().unwrap().label, "filler 0");
+
+        trail.breadcrumb("Post".into());
+        trail.message(GpuMessageSource::Validation, Level::Error, "VUID-vkCmdDraw".into(), start);
+        trail.continue_validation("objects: (type: BUFFER)");
+        let message = trail.messages().next().unwrap();
+        assert_eq!(message.frame, 3);
+        assert_eq!(message.message, "VUID-vkCmdDraw\nobjects: (type: BUFFER)");
+        assert_eq!(message.breadcrumbs.len(), MESSAGE_BREADCRUMBS);
+        assert_eq!(message.breadcrumbs.last().unwrap().label, "Post");
+
+        // Continuations only extend validation messages
+        trail.message(GpuMessageSource::Uncaptured, Level::Error, "lost".into(), start);
+        trail.continue_validation("stray");
+        assert_eq!(trail.messages().last().unwrap().message, "lost");
+
+        let report = trail.report("device lost", &[]);
+        assert!(report.contains("Validation ERROR: VUID-vkCmdDraw"));
+        assert!(report.contains("filler 62 > filler 63 > Post"));
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use glam::{Quat, Vec2, Vec3};
+use crate::model::{MeshData, ModelData, ModelVertex};
+use super::spline::Spline;
+
+/// A 2D cross-section swept along a spline: `x` runs to the right of the direction of travel
+/// and `y` up. Faces point to the right of the direction the points run, which is outward
+/// for a counter-clockwise outline.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    points: Vec<Vec2>,
+    closed: bool,
+}
+
+impl Profile {
+    pub fn new(points: Vec<Vec2>, closed: bool) -> Result<Self> {
+        let needed = if closed { 3 } else { 2 };
+        if points.len() < needed {
+            anyhow::bail!("A {} profile needs at least {} points, got {}",
+                if closed { "closed" } else { "open" }, needed, points.len());
+        }
+        Ok(Self { points, closed })
+    }
+
+    /// A tube cross-section, e.g. for pipes
+    pub fn circle(radius: f32, segments: usize) -> Self {
+        let segments = segments.max(3);
+        let points = (0..segments)
+            .map(|i| {
+                let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+                Vec2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect();
+        Self { points, closed: true }
+    }
+
+    /// A box cross-section centered on the spline, e.g. for rails or beams
+    pub fn rectangle(width: f32, height: f32) -> Self {
+        let (x, y) = (width * 0.5, height * 0.5);
+        let points = vec![Vec2::new(x, -y), Vec2::new(x, y), Vec2::new(-x, y), Vec2::new(-x, -y)];
+        Self { points, closed: true }
+    }
+
+    /// A flat upward-facing strip, e.g. a road surface
+    pub fn strip(width: f32) -> Self {
+        Self { points: vec![Vec2::new(width * 0.5, 0.0), Vec2::new(-width * 0.5, 0.0)], closed: false }
+    }
+
+    pub fn points(&self) -> &[Vec2] {
+        &self.points
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Point `i`, wrapping on closed profiles
+    fn point(&self, i: usize) -> Vec2 {
+        self.points[i % self.points.len()]
+    }
+
+    /// Averaged normals of the edges either side of each point
+    fn normals(&self) -> Vec<Vec2> {
+        let n = self.points.len();
+        let edge_normal = |a: Vec2, b: Vec2| {
+            let d = b - a;
+            Vec2::new(d.y, -d.x).normalize_or_zero()
+        };
+        (0..n)
+            .map(|i| {
+                let before = (self.closed || i > 0).then(|| edge_normal(self.point(i + n - 1), self.points[i]));
+                let after = (self.closed || i + 1 < n).then(|| edge_normal(self.points[i], self.point(i + 1)));
+                (before.unwrap_or(Vec2::ZERO) + after.unwrap_or(Vec2::ZERO)).normalize_or_zero()
+            })
+            .collect()
+    }
+}
+
+/// How `extrude` samples the spline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extrusion {
+    /// Distance between cross-sections, in meters; tighter curves need less
+    pub spacing: f32,
+    /// Keep the profile's `y` on this axis, so roads and rails stay level through turns.
+    /// `None` carries the profile along with the least twist instead, for pipes that climb
+    /// and loop.
+    pub up: Option<Vec3>,
+    /// Meters along the spline per texture repeat
+    pub texture_length: f32,
+}
+
+impl Default for Extrusion {
+    fn default() -> Self {
+        Self {
+            spacing: 0.5,
+            up: Some(Vec3::Y),
+            texture_length: 1.0,
+        }
+    }
+}
+
+/// Sweep `profile` along `spline` into a mesh. Texture `u` runs across the profile and `v`
+/// along the spline. The ends are left open.
+pub fn extrude(spline: &Spline, profile: &Profile, extrusion: &Extrusion) -> MeshData {
+    let length = spline.length();
+    let rings = ((length / extrusion.spacing.max(0.01)).ceil() as usize).max(1) + 1;
+
+    // A closed profile repeats its first point so the texture seam gets its own `u`
+    let profile_count = profile.points.len() + profile.closed as usize;
+    let normals = profile.normals();
+    let mut across = vec![0.0];
+    for j in 1..profile_count {
+        across.push(across[j - 1] + profile.point(j).distance(profile.point(j - 1)));
+    }
+    let profile_length = across[profile_count - 1].max(f32::EPSILON);
+
+    let mut vertices = Vec::with_capacity(rings * profile_count);
+    let mut previous: Option<(Vec3, Vec3)> = None;
+    for ring in 0..rings {
+        let distance = length * ring as f32 / (rings - 1) as f32;
+        let t = spline.param_at_distance(distance);
+        let (center, forward) = (spline.point(t), spline.tangent(t));
+        let up = ring_up(forward, extrusion.up, previous);
+        previous = Some((forward, up));
+        let right = forward.cross(up).normalize_or_zero();
+
+        for j in 0..profile_count {
+            let point = profile.point(j);
+            let normal = normals[j % normals.len()];
+            let tangent = (right * -normal.y + up * normal.x).normalize_or_zero();
+            vertices.push(ModelVertex {
+                position: (center + right * point.x + up * point.y).to_array(),
+                tex_coords: [across[j] / profile_length, distance / extrusion.texture_length.max(f32::EPSILON)],
+                normal: (right * normal.x + up * normal.y).to_array(),
+                tangent: [tangent.x, tangent.y, tangent.z, 1.0],
+                color: ModelVertex::WHITE,
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((rings - 1) * (profile_count - 1) * 6);
+    for ring in 0..rings as u32 - 1 {
+        for j in 0..profile_count as u32 - 1 {
+            let a = ring * profile_count as u32 + j;
+            let (b, c) = (a + 1, a + profile_count as u32);
+            let d = c + 1;
+            indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+
+    MeshData {
+        name: "extrusion".to_string(),
+        vertices,
+        indices,
+        material_index: 0,
+        skin: None,
+    }
+}
+
+/// `extrude` as a model with the default material, ready for `Model::from_data`
+pub fn extrude_model(spline: &Spline, profile: &Profile, extrusion: &Extrusion) -> ModelData {
+    ModelData::from_mesh(extrude(spline, profile, extrusion))
+}
+
+/// The profile's up axis at a ring facing `forward`
+fn ring_up(forward: Vec3, fixed_up: Option<Vec3>, previous: Option<(Vec3, Vec3)>) -> Vec3 {
+    if let Some(up) = fixed_up {
+        // Level with `up` unless the spline runs straight along it
+        let right = forward.cross(up);
+        if right.length_squared() > 1e-6 {
+            return right.cross(forward).normalize();
+        }
+    }
+    match previous {
+        // Turn the last ring's up by the same rotation as the direction of travel
+        Some((previous_forward, previous_up)) => {
+            let up = Quat::from_rotation_arc(previous_forward, forward) * previous_up;
+            (up - forward * up.dot(forward)).normalize_or_zero()
+        }
+        None => {
+            let reference = if forward.y.abs() < 0.99 { Vec3::Y } else { Vec3::X };
+            forward.cross(reference).cross(forward).normalize()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extrude_road() {
+        let spline = Spline::catmull_rom(vec![Vec3::ZERO, Vec3::new(0.0, 0.0, -10.0)], false).unwrap();
+        let mesh = extrude(&spline, &Profile::strip(4.0), &Extrusion { spacing: 2.5, ..Extrusion::default() });
+
+        // Five rings of two vertices, level and facing up
+        assert_eq!(mesh.vertices.len(), 10);
+        assert_eq!(mesh.indices.len(), 4 * 6);
+        assert_eq!(mesh.vertices[0].position, [2.0, 0.0, 0.0]);
+        assert_eq!(mesh.vertices[9].position, [-2.0, 0.0, -10.0]);
+        assert!(mesh.vertices.iter().all(|vertex| vertex.normal == [0.0, 1.0, 0.0]));
+        assert_eq!(mesh.vertices[9].tex_coords[0], 1.0);
+        assert!((mesh.vertices[9].tex_coords[1] - 10.0).abs() < 1e-3);
+
+        // Triangles wind counter-clockwise seen from above
+        let position = |i: u32| Vec3::from(mesh.vertices[i as usize].position);
+        let (a, b, c) = (position(mesh.indices[0]), position(mesh.indices[1]), position(mesh.indices[2]));
+        assert!((b - a).cross(c - a).y > 0.0);
+    }
+
+    #[test]
+    fn test_extrude_pipe() {
+        let spline = Spline::bezier(vec![Vec3::ZERO, Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 4.0, -2.0), Vec3::new(0.0, 4.0, -4.0)]).unwrap();
+        let extrusion = Extrusion { up: None, spacing: 0.25, ..Extrusion::default() };
+        let model = extrude_model(&spline, &Profile::circle(0.5, 8), &extrusion);
+        let mesh = &model.meshes[0];
+
+        // Nine vertices per ring with the seam; every one half a meter off the curve, facing out
+        assert_eq!(mesh.vertices.len() % 9, 0);
+        for (i, vertex) in mesh.vertices.iter().enumerate() {
+            let ring = i / 9;
+            let rings = mesh.vertices.len() / 9;
+            let center = spline.point_at_distance(spline.length() * ring as f32 / (rings - 1) as f32);
+            let offset = Vec3::from(vertex.position) - center;
+            assert!((offset.length() - 0.5).abs() < 1e-3);
+            assert!(offset.normalize().dot(Vec3::from(vertex.normal)) > 0.999);
+        }
+        assert!(model.bounds_max[1] > 4.4 && model.bounds_min[2] < -3.9);
+    }
+}
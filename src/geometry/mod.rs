@@ -0,0 +1,8 @@
+//! Procedural geometry: splines and meshes built along them, such as roads, pipes and rails.
+//! Meshes come out as `ModelData`, so they upload like loaded models with `Model::from_data`.
+
+mod extrude;
+mod spline;
+
+pub use extrude::{extrude, extrude_model, Extrusion, Profile};
+pub use spline::{Spline, SplineKind};
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use glam::Vec3;
+
+/// Samples per segment of the arc-length table
+const ARC_SAMPLES_PER_SEGMENT: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplineKind {
+    /// Cubic Bezier segments sharing end points (`p0 c0 c1 p1 c2 c3 p2 ...`); the curve passes
+    /// through every third point and is pulled toward the others
+    Bezier,
+    /// Uniform Catmull-Rom, passing through every point
+    CatmullRom,
+}
+
+/// A piecewise cubic curve. Positions are addressed either by `t`, 0 - 1 over the whole curve
+/// with each segment getting an equal share, or by distance along it through an arc-length table
+/// built when the spline is created.
+#[derive(Debug, Clone)]
+pub struct Spline {
+    kind: SplineKind,
+    points: Vec<Vec3>,
+    closed: bool,
+    /// Distance along the curve at each of `ARC_SAMPLES_PER_SEGMENT` evenly spaced `t` per segment
+    arc_lengths: Vec<f32>,
+}
+
+impl Spline {
+    /// Bezier segments from `3n + 1` points
+    pub fn bezier(points: Vec<Vec3>) -> Result<Self> {
+        if points.len() < 4 || !(points.len() - 1).is_multiple_of(3) {
+            anyhow::bail!("A Bezier spline needs 3n + 1 points (4, 7, 10, ...), got {}", points.len());
+        }
+        Ok(Self::with_arc_lengths(SplineKind::Bezier, points, false))
+    }
+
+    /// A curve through `points`; a closed one also joins the last point back to the first
+    pub fn catmull_rom(points: Vec<Vec3>, closed: bool) -> Result<Self> {
+        let needed = if closed { 3 } else { 2 };
+        if points.len() < needed {
+            anyhow::bail!("A {} Catmull-Rom spline needs at least {} points, got {}",
+                if closed { "closed" } else { "open" }, needed, points.len());
+        }
+        Ok(Self::with_arc_lengths(SplineKind::CatmullRom, points, closed))
+    }
+
+    fn with_arc_lengths(kind: SplineKind, points: Vec<Vec3>, closed: bool) -> Self {
+        let mut spline = Self { kind, points, closed, arc_lengths: Vec::new() };
+        let samples = spline.segment_count() * ARC_SAMPLES_PER_SEGMENT;
+        let mut length = 0.0;
+        let mut previous = spline.point(0.0);
+        spline.arc_lengths.push(0.0);
+        for i in 1..=samples {
+            let point = spline.point(i as f32 / samples as f32);
+            length += point.distance(previous);
+            spline.arc_lengths.push(length);
+            previous = point;
+        }
+        spline
+    }
+
+    pub fn kind(&self) -> SplineKind {
+        self.kind
+    }
+
+    pub fn points(&self) -> &[Vec3] {
+        &self.points
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn segment_count(&self) -> usize {
+        match self.kind {
+            SplineKind::Bezier => (self.points.len() - 1) / 3,
+            SplineKind::CatmullRom if self.closed => self.points.len(),
+            SplineKind::CatmullRom => self.points.len() - 1,
+        }
+    }
+
+    /// Position at `t`, clamped to 0 - 1
+    pub fn point(&self, t: f32) -> Vec3 {
+        let (segment, u) = self.segment(t);
+        let [p0, p1, p2, p3] = self.controls(segment);
+        match self.kind {
+            SplineKind::Bezier => {
+                let v = 1.0 - u;
+                p0 * (v * v * v) + p1 * (3.0 * v * v * u) + p2 * (3.0 * v * u * u) + p3 * (u * u * u)
+            }
+            SplineKind::CatmullRom => {
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * u
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * (u * u)
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * (u * u * u))
+            }
+        }
+    }
+
+    /// Unit direction of travel at `t`
+    pub fn tangent(&self, t: f32) -> Vec3 {
+        let (segment, u) = self.segment(t);
+        let [p0, p1, p2, p3] = self.controls(segment);
+        let derivative = match self.kind {
+            SplineKind::Bezier => {
+                let v = 1.0 - u;
+                (p1 - p0) * (3.0 * v * v) + (p2 - p1) * (6.0 * v * u) + (p3 - p2) * (3.0 * u * u)
+            }
+            SplineKind::CatmullRom => {
+                0.5 * ((p2 - p0)
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * (2.0 * u)
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * (3.0 * u * u))
+            }
+        };
+        // A control point on top of its end point stalls the curve there; look at its neighbours
+        derivative.try_normalize().unwrap_or_else(|| {
+            const STEP: f32 = 1e-3;
+            (self.point(t + STEP) - self.point(t - STEP)).normalize_or_zero()
+        })
+    }
+
+    /// Total arc length
+    pub fn length(&self) -> f32 {
+        self.arc_lengths.last().copied().unwrap_or(0.0)
+    }
+
+    /// The `t` that lies `distance` along the curve, clamped to its ends
+    pub fn param_at_distance(&self, distance: f32) -> f32 {
+        let samples = self.arc_lengths.len() - 1;
+        if distance <= 0.0 || self.length() <= 0.0 {
+            return 0.0;
+        }
+        let i = self.arc_lengths.partition_point(|&length| length < distance);
+        if i > samples {
+            return 1.0;
+        }
+        // Linear between the samples either side
+        let (before, after) = (self.arc_lengths[i - 1], self.arc_lengths[i]);
+        let fraction = if after > before { (distance - before) / (after - before) } else { 0.0 };
+        (i as f32 - 1.0 + fraction) / samples as f32
+    }
+
+    /// Position `distance` along the curve
+    pub fn point_at_distance(&self, distance: f32) -> Vec3 {
+        self.point(self.param_at_distance(distance))
+    }
+
+    /// Segment index and position within it
+    fn segment(&self, t: f32) -> (usize, f32) {
+        let count = self.segment_count();
+        let scaled = t.clamp(0.0, 1.0) * count as f32;
+        let segment = (scaled as usize).min(count - 1);
+        (segment, scaled - segment as f32)
+    }
+
+    /// The four points weighting a segment
+    fn controls(&self, segment: usize) -> [Vec3; 4] {
+        match self.kind {
+            SplineKind::Bezier => {
+                let i = segment * 3;
+                [self.points[i], self.points[i + 1], self.points[i + 2], self.points[i + 3]]
+            }
+            SplineKind::CatmullRom => {
+                let i = segment as isize;
+                [self.neighbour(i - 1), self.neighbour(i), self.neighbour(i + 1), self.neighbour(i + 2)]
+            }
+        }
+    }
+
+    /// Catmull-Rom point `i`, wrapped on closed curves and extrapolated past open ends
+    fn neighbour(&self, i: isize) -> Vec3 {
+        let n = self.points.len() as isize;
+        if self.closed {
+            return self.points[i.rem_euclid(n) as usize];
+        }
+        match i {
+            i if i < 0 => 2.0 * self.points[0] - self.points[1],
+            i if i >= n => 2.0 * self.points[n as usize - 1] - self.points[n as usize - 2],
+            i => self.points[i as usize],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bezier_and_catmull_rom() {
+        let bezier = Spline::bezier(vec![Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0), Vec3::new(2.0, 1.0, 0.0), Vec3::new(3.0, 0.0, 0.0)]).unwrap();
+        assert_eq!(bezier.point(0.0), Vec3::ZERO);
+        assert_eq!(bezier.point(1.0), Vec3::new(3.0, 0.0, 0.0));
+        assert!((bezier.point(0.5) - Vec3::new(1.5, 0.75, 0.0)).length() < 1e-5);
+        assert!((bezier.tangent(0.5) - Vec3::X).length() < 1e-5);
+        assert!(Spline::bezier(vec![Vec3::ZERO; 5]).is_err());
+
+        // Through every point, including back to the start when closed
+        let square = vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 0.0, 1.0), Vec3::Z];
+        let closed = Spline::catmull_rom(square.clone(), true).unwrap();
+        assert_eq!(closed.segment_count(), 4);
+        for (i, point) in square.iter().enumerate() {
+            assert!((closed.point(i as f32 / 4.0) - *point).length() < 1e-5);
+        }
+        assert!((closed.point(1.0) - square[0]).length() < 1e-5);
+        assert!(Spline::catmull_rom(vec![Vec3::ZERO, Vec3::X], true).is_err());
+    }
+
+    #[test]
+    fn test_arc_length() {
+        // Uneven control points make `t` uneven, but distances stay true
+        let line = Spline::catmull_rom(vec![Vec3::ZERO, Vec3::new(4.0, 0.0, 0.0), Vec3::new(10.0, 0.0, 0.0)], false).unwrap();
+        assert!((line.length() - 10.0).abs() < 1e-3);
+        assert!((line.point_at_distance(5.0) - Vec3::new(5.0, 0.0, 0.0)).length() < 0.01);
+        assert_eq!(line.param_at_distance(-1.0), 0.0);
+        assert_eq!(line.param_at_distance(20.0), 1.0);
+
+        // A quarter circle is close to pi / 2 long
+        let k = 0.5523;
+        let arc = Spline::bezier(vec![Vec3::X, Vec3::new(1.0, k, 0.0), Vec3::new(k, 1.0, 0.0), Vec3::Y]).unwrap();
+        assert!((arc.length() - std::f32::consts::FRAC_PI_2).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,293 @@
+//! Engine-level input. Frontends translate their native events (winit, a VR runtime, a test)
+//! into [`InputEvent`]s; once a frame they are folded into an [`InputState`] snapshot that the
+//! camera and scripts read, so game logic never sees windowing types.
+
+use std::collections::{HashMap, HashSet};
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+macro_rules! keys {
+    ($($key:ident),* $(,)?) => {
+        /// A physical key, named as in winit's `KeyCode` so names are stable across frontends
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        pub enum Key {
+            $($key,)*
+        }
+
+        impl Key {
+            pub const ALL: &'static [Key] = &[$(Key::$key,)*];
+
+            /// The key's name, e.g. `"KeyW"` or `"Space"`
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Key::$key => stringify!($key),)*
+                }
+            }
+
+            /// Look up a key by name
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(stringify!($key) => Some(Key::$key),)*
+                    _ => None,
+                }
+            }
+
+            /// The engine key for a winit key code; `None` for keys the engine doesn't name
+            pub fn from_winit(code: winit::keyboard::KeyCode) -> Option<Self> {
+                match code {
+                    $(winit::keyboard::KeyCode::$key => Some(Key::$key),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM,
+    KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    ArrowUp, ArrowDown, ArrowLeft, ArrowRight,
+    Space, Enter, Escape, Tab, Backspace, Delete,
+    ShiftLeft, ShiftRight, ControlLeft, ControlRight, AltLeft, AltRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+impl MouseButton {
+    pub fn from_winit(button: winit::event::MouseButton) -> Self {
+        match button {
+            winit::event::MouseButton::Left => Self::Left,
+            winit::event::MouseButton::Right => Self::Right,
+            winit::event::MouseButton::Middle => Self::Middle,
+            winit::event::MouseButton::Back => Self::Other(3),
+            winit::event::MouseButton::Forward => Self::Other(4),
+            winit::event::MouseButton::Other(index) => Self::Other(index),
+        }
+    }
+}
+
+/// Buttons of a standard gamepad, by position rather than label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftStick,
+    RightStick,
+    Start,
+    Select,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Sticks are -1 - 1 with +Y up; triggers are 0 - 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key { key: Key, pressed: bool },
+    MouseButton { button: MouseButton, pressed: bool },
+    /// Relative motion, e.g. from a captured mouse
+    MouseMotion { dx: f32, dy: f32 },
+    /// Cursor position in surface pixels
+    CursorMoved { x: f32, y: f32 },
+    /// Wheel movement in lines, positive away from the user
+    Scroll { delta: f32 },
+    GamepadButton { button: GamepadButton, pressed: bool },
+    GamepadAxis { axis: GamepadAxis, value: f32 },
+    /// A named VR action, such as a trigger or grip; buttons are 0 or 1
+    VrAction { name: String, value: f32 },
+}
+
+/// Input for one frame: what's held, what changed since the previous frame, and accumulated
+/// motion. Built by [`InputState::begin_frame`] from the frame's events.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    keys: HashSet<Key>,
+    pressed_keys: Vec<Key>,
+    released_keys: Vec<Key>,
+    buttons: HashSet<MouseButton>,
+    pressed_buttons: Vec<MouseButton>,
+    mouse_delta: Vec2,
+    cursor: Option<Vec2>,
+    scroll: f32,
+    gamepad_buttons: HashSet<GamepadButton>,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    vr_actions: HashMap<String, f32>,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new frame: clear the previous frame's changes and motion, then apply `events`
+    /// in order. Held keys, buttons and axes carry over.
+    pub fn begin_frame(&mut self, events: impl IntoIterator<Item = InputEvent>) {
+        self.pressed_keys.clear();
+        self.released_keys.clear();
+        self.pressed_buttons.clear();
+        self.mouse_delta = Vec2::ZERO;
+        self.scroll = 0.0;
+        for event in events {
+            self.apply(event);
+        }
+    }
+
+    fn apply(&mut self, event: InputEvent) {
+        match event {
+            // Key repeat reports presses of held keys; only the first counts
+            InputEvent::Key { key, pressed: true } => {
+                if self.keys.insert(key) {
+                    self.pressed_keys.push(key);
+                }
+            }
+            InputEvent::Key { key, pressed: false } => {
+                if self.keys.remove(&key) {
+                    self.released_keys.push(key);
+                }
+            }
+            InputEvent::MouseButton { button, pressed: true } => {
+                if self.buttons.insert(button) {
+                    self.pressed_buttons.push(button);
+                }
+            }
+            InputEvent::MouseButton { button, pressed: false } => {
+                self.buttons.remove(&button);
+            }
+            InputEvent::MouseMotion { dx, dy } => self.mouse_delta += Vec2::new(dx, dy),
+            InputEvent::CursorMoved { x, y } => self.cursor = Some(Vec2::new(x, y)),
+            InputEvent::Scroll { delta } => self.scroll += delta,
+            InputEvent::GamepadButton { button, pressed } => {
+                if pressed {
+                    self.gamepad_buttons.insert(button);
+                } else {
+                    self.gamepad_buttons.remove(&button);
+                }
+            }
+            InputEvent::GamepadAxis { axis, value } => {
+                self.gamepad_axes.insert(axis, value);
+            }
+            InputEvent::VrAction { name, value } => {
+                self.vr_actions.insert(name, value);
+            }
+        }
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Whether `key` went down this frame
+    pub fn key_pressed(&self, key: Key) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    /// Whether `key` went up this frame
+    pub fn key_released(&self, key: Key) -> bool {
+        self.released_keys.contains(&key)
+    }
+
+    /// Keys that went down this frame, in order
+    pub fn pressed_keys(&self) -> &[Key] {
+        &self.pressed_keys
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// Whether `button` went down this frame
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    /// Relative mouse motion this frame
+    pub fn mouse_delta(&self) -> Vec2 {
+        self.mouse_delta
+    }
+
+    /// Last known cursor position in surface pixels
+    pub fn cursor(&self) -> Option<Vec2> {
+        self.cursor
+    }
+
+    /// Wheel lines scrolled this frame
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    pub fn is_gamepad_button_down(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.contains(&button)
+    }
+
+    /// 0 for axes no event has reported
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepad_axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    /// 0 for actions no event has reported
+    pub fn vr_action(&self, name: &str) -> f32 {
+        self.vr_actions.get(name).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_state_frames() {
+        let mut input = InputState::new();
+        input.begin_frame([
+            InputEvent::Key { key: Key::KeyW, pressed: true },
+            // Key repeat
+            InputEvent::Key { key: Key::KeyW, pressed: true },
+            InputEvent::MouseMotion { dx: 2.0, dy: 1.0 },
+            InputEvent::MouseMotion { dx: 3.0, dy: -1.0 },
+            InputEvent::VrAction { name: "trigger".into(), value: 1.0 },
+        ]);
+        assert!(input.is_key_down(Key::KeyW));
+        assert_eq!(input.pressed_keys(), &[Key::KeyW]);
+        assert_eq!(input.mouse_delta(), Vec2::new(5.0, 0.0));
+        assert_eq!(input.vr_action("trigger"), 1.0);
+
+        // Held state carries over; per-frame changes and motion don't
+        input.begin_frame([InputEvent::Key { key: Key::KeyW, pressed: false }]);
+        assert!(!input.is_key_down(Key::KeyW));
+        assert!(!input.key_pressed(Key::KeyW));
+        assert!(input.key_released(Key::KeyW));
+        assert_eq!(input.mouse_delta(), Vec2::ZERO);
+        assert_eq!(input.vr_action("trigger"), 1.0);
+    }
+
+    #[test]
+    fn test_key_names() {
+        for &key in Key::ALL {
+            assert_eq!(Key::from_name(key.name()), Some(key));
+        }
+        assert_eq!(Key::from_winit(winit::keyboard::KeyCode::KeyE), Some(Key::KeyE));
+        assert_eq!(Key::from_name("NotAKey"), None);
+    }
+}
@@ -1,84 +1,220 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use anyhow::{anyhow, Context};
 use winit::window::Window;
 use glam::Vec3;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+pub mod capture;
+pub mod color;
+pub mod diagnostics;
+pub mod geometry;
+pub mod input;
 pub mod model;
+#[cfg(feature = "networking")]
+pub mod network;
+pub mod noise;
+pub mod prelude;
+pub mod readback;
 pub mod scene;
+pub mod session;
+pub mod settings;
+pub mod shaders;
+pub mod simulation;
+// OpenXR interop needs wgpu's Vulkan backend, which Apple and web targets don't build
+#[cfg(not(any(target_vendor = "apple", target_arch = "wasm32")))]
 pub mod vr;
 
-use scene::{Scene, Renderer, camera::Camera, Transform};
-use model::{Model, ModelVertex};
+use scene::{Scene, Renderer, camera::Camera, capabilities::OPTIONAL_FEATURES, Capabilities, ClipPlane, Level, LoadHandle, LoadState, ObjectId, OutputAlpha, Placement, Ray, Prefab, PrefabInstance, PrefabOverrides, Transform, VolumeId, VolumeLoad};
+use model::{ChromaKey, ImportOptions, MaterialHandle, Model, ModelData, ModelHandle, ModelVertex, VideoSource, VideoTexture};
+use settings::{RendererSettings, SettingsChanges};
+use color::Color;
+use capture::FrameCapture;
+use input::{InputEvent, InputState};
+use simulation::Simulation;
+use session::{Autosave, Session, SessionObject};
+
+// The floor is always the first scene object and survives scene replacement
+const FLOOR_OBJECTS: usize = 1;
+
+/// What to do with the current scene when a loaded model arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadMode {
+    Replace,
+    Add,
+}
+
+/// What a background load was requested for
+enum PendingLoad {
+    Model(LoadMode),
+    /// An object already placed with `place_model`, showing the placeholder meanwhile
+    Placed,
+    /// Objects of a restored session using the model
+    Restore(Vec<SessionObject>),
+    /// A model of a streaming volume, for the load of the volume with this generation
+    Volume(VolumeId, u32),
+}
+
+/// What the device is created to render to; decides which graphics backends may be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// A window on any primary backend: Vulkan, Metal, DX12 or WebGPU
+    Desktop,
+    /// An OpenXR headset, whose session is created from the device's Vulkan handles
+    Vr,
+}
+
+impl RenderMode {
+    /// Backends to create the instance with. `WGPU_BACKEND` (e.g. `gl` or `dx12`) overrides the
+    /// desktop choice; VR always needs Vulkan.
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Self::Desktop => wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY),
+            Self::Vr => wgpu::Backends::VULKAN,
+        }
+    }
+}
+
+/// A step of `State::init`, reported before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitStage {
+    Instance,
+    Adapter,
+    Device,
+    Surface,
+    Renderer,
+    /// Floor and demo models
+    Scene,
+    Ready,
+}
+
+impl InitStage {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Instance => "Creating graphics instance",
+            Self::Adapter => "Finding a GPU",
+            Self::Device => "Creating device",
+            Self::Surface => "Configuring window surface",
+            Self::Renderer => "Building renderer",
+            Self::Scene => "Loading scene",
+            Self::Ready => "Ready",
+        }
+    }
+}
+
+/// Startup progress for a splash or loading screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitProgress {
+    pub stage: InitStage,
+    /// Rough share of startup done, 0 - 100
+    pub percent: u8,
+}
 
 pub struct State {
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    /// Composite alpha modes the surface supports
+    alpha_modes: Vec<wgpu::CompositeAlphaMode>,
     window: Arc<Window>,
     pub scene: Scene,
     renderer: Renderer,
+    /// Used for model files loaded through `Scene::loading`
+    import_options: ImportOptions,
+    /// Loads requested through `Scene::loading`, with their files
+    pending_loads: HashMap<LoadHandle, (PathBuf, PendingLoad)>,
+    /// Live video materials, given their newest frame each `update`
+    videos: Vec<VideoTexture>,
+    /// Models used by prefabs, kept registered so later instances share them
+    prefab_models: HashMap<PathBuf, ModelHandle>,
+    /// The file each model was loaded from, for session saves
+    model_paths: HashMap<ModelHandle, PathBuf>,
+    autosave: Option<Autosave>,
+    /// A session left by a run that crashed, until it is restored or discarded
+    recovery: Option<Session>,
+    /// The object picked last, `None` after picking empty space
+    selected: Option<ObjectId>,
+    /// Snapping used by `place_selected`
+    pub placement: Placement,
+    capture: FrameCapture,
+    /// Events queued since the last `update`
+    input_events: Vec<InputEvent>,
+    input: InputState,
+    simulation: Simulation,
+    #[cfg(feature = "scripting")]
+    scripts: scene::ScriptHost,
+    #[cfg(feature = "networking")]
+    network: Option<network::NetworkSession>,
 }
 
 impl State {
+    /// Initialize on the calling thread, panicking on failure; see `init` for progress reporting
     pub fn new(window: Window) -> Self {
+        pollster::block_on(Self::init(window, |_| {})).expect("Failed to initialize")
+    }
+
+    /// Create the device, renderer and demo scene, awaiting the adapter and device requests.
+    /// `progress` is called as each stage starts, so the application can update a splash screen.
+    pub async fn init(window: Window, mut progress: impl FnMut(InitProgress)) -> anyhow::Result<Self> {
+        let mut report = |stage, percent| {
+            log::debug!("{}...", InitStage::label(stage));
+            progress(InitProgress { stage, percent });
+        };
         let window = Arc::new(window);
         let size = window.inner_size();
 
-        println!("Creating WGPU instance...");
+        report(InitStage::Instance, 0);
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: if cfg!(target_os = "macos") {
-                wgpu::Backends::METAL
-            } else {
-                wgpu::Backends::VULKAN
-            },
+            backends: RenderMode::Desktop.backends(),
             dx12_shader_compiler: Default::default(),
             flags: wgpu::InstanceFlags::DEBUG | wgpu::InstanceFlags::VALIDATION,
             gles_minor_version: wgpu::Gles3MinorVersion::default(),
         });
 
-        println!("Creating surface...");
-        println!("Window info - width: {}, height: {}", size.width, size.height);
+        log::debug!("Creating surface...");
+        log::debug!("Window info - width: {}, height: {}", size.width, size.height);
         let surface = instance.create_surface(window.clone())
-            .expect("Failed to create surface");
+            .context("Failed to create surface")?;
 
-        println!("Requesting adapter...");
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        report(InitStage::Adapter, 10);
+        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
             compatible_surface: Some(&surface),
             force_fallback_adapter: false,
-        }))
-        .expect("Failed to find appropriate adapter");
+        })
+        .await
+        .ok_or_else(|| anyhow!("Failed to find an adapter for backends {:?}", RenderMode::Desktop.backends()))?;
 
         let info = adapter.get_info();
-        println!("Using adapter: {:?}", info);
-        println!("Adapter backend: {:?}", info.backend);
-        println!("Adapter device: {}", info.device);
-        println!("Adapter driver: {}", info.driver);
-        println!("Adapter driver info: {}", info.driver_info);
+        log::info!("Using adapter: {} ({:?})", info.name, info.backend);
+        log::debug!("Adapter device: {}", info.device);
+        log::info!("Adapter driver: {} {}", info.driver, info.driver_info);
 
+        let metal = info.backend == wgpu::Backend::Metal;
         let mut limits = wgpu::Limits::default();
-        if cfg!(target_os = "macos") {
+        if metal {
             // Ensure we don't exceed Metal's limits
             limits.max_texture_dimension_2d = 16384;
             limits.max_bind_groups = 4;
         }
 
-        let (device, queue) = pollster::block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: Some("Primary Device"),
-                required_features: wgpu::Features::empty(),
-                required_limits: limits,
-                memory_hints: Default::default(),
-            },
-            None,
-        ))
-        .unwrap();
+        report(InitStage::Device, 25);
+        // Optional features are dropped, and limits lowered, until the adapter accepts the request
+        let optional = OPTIONAL_FEATURES.iter().fold(wgpu::Features::empty(), |all, (feature, _)| all | *feature);
+        let (device, queue, downgrade) = scene::capabilities::request_device(&adapter, "Primary Device", optional, limits)
+            .await
+            .context("Failed to create device")?;
+        diagnostics::install_device_handler(&device);
+        let mut capabilities = Capabilities::new(&adapter, &device);
+        capabilities.downgrade = downgrade;
+        log::info!("Device capabilities:\n{}", capabilities);
 
+        report(InitStage::Surface, 40);
         let surface_caps = surface.get_capabilities(&adapter);
-        println!("Surface capabilities: {:?}", surface_caps);
+        log::debug!("Surface capabilities: {:?}", surface_caps);
         
-        let surface_format = if cfg!(target_os = "macos") {
+        let surface_format = if metal {
             // Prefer BGRA8UnormSrgb for Metal
             surface_caps.formats.iter()
                 .copied()
@@ -91,9 +227,9 @@ impl State {
                 .unwrap_or(surface_caps.formats[0])
         };
 
-        println!("Selected surface format: {:?}", surface_format);
+        log::info!("Selected surface format: {:?}", surface_format);
 
-        let present_mode = if cfg!(target_os = "macos") {
+        let present_mode = if metal {
             // Prefer immediate mode on Metal for lower latency
             surface_caps.present_modes.iter()
                 .copied()
@@ -103,7 +239,13 @@ impl State {
             surface_caps.present_modes[0]
         };
 
-        println!("Selected present mode: {:?}", present_mode);
+        log::info!("Selected present mode: {:?}", present_mode);
+
+        // Window transparency decides the surface's alpha mode, so settings are read up front;
+        // without a settings file the defaults follow the device's quality tier
+        let mut settings = RendererSettings::load_or(Path::new(settings::SETTINGS_PATH), RendererSettings::for_tier(capabilities.tier));
+        let alpha_mode = select_alpha_mode(&surface_caps.alpha_modes, &mut settings);
+        window.set_transparent(settings.window_alpha.is_transparent());
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -111,20 +253,27 @@ impl State {
             width: size.width,
             height: size.height,
             present_mode,
-            alpha_mode: surface_caps.alpha_modes[0],
+            alpha_mode,
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
 
         surface.configure(&device, &config);
 
+        report(InitStage::Renderer, 45);
         let camera = Camera::new(
             Vec3::new(0.0, 8.0, 16.0),
             size.width as f32 / size.height as f32,
         );
         let mut scene = Scene::new(camera);
-        let renderer = Renderer::new(&device, &queue, &config);
+        let mut renderer = Renderer::with_capabilities(&device, &queue, &config, capabilities);
+        if let Err(e) = renderer.apply_settings(&device, &queue, &config, &settings) {
+            log::warn!("{:#}", e);
+        }
+        let viewport = renderer.viewport();
+        scene.resize(viewport.width, viewport.height);
 
+        report(InitStage::Scene, 60);
         // Add floor plane (20x20 meters)
         let floor_vertices = vec![
             ModelVertex { 
@@ -132,24 +281,28 @@ impl State {
                 normal: [0.0, 1.0, 0.0], 
                 tex_coords: [0.0, 0.0],
                 tangent: [1.0, 0.0, 0.0, 1.0],
+                color: ModelVertex::WHITE,
             },
             ModelVertex { 
                 position: [10.0, 0.0, -10.0], 
                 normal: [0.0, 1.0, 0.0], 
                 tex_coords: [1.0, 0.0],  // One full texture repeat across 20 meters
                 tangent: [1.0, 0.0, 0.0, 1.0],
+                color: ModelVertex::WHITE,
             },
             ModelVertex { 
                 position: [10.0, 0.0, 10.0], 
                 normal: [0.0, 1.0, 0.0], 
                 tex_coords: [1.0, 1.0],
                 tangent: [1.0, 0.0, 0.0, 1.0],
+                color: ModelVertex::WHITE,
             },
             ModelVertex { 
                 position: [-10.0, 0.0, 10.0], 
                 normal: [0.0, 1.0, 0.0], 
                 tex_coords: [0.0, 1.0],
                 tangent: [1.0, 0.0, 0.0, 1.0],
+                color: ModelVertex::WHITE,
             },
         ];
 
@@ -192,40 +345,57 @@ impl State {
         let floor_texture_view = floor_texture.create_view(&wgpu::TextureViewDescriptor::default());
         
         // Create floor model
-        let floor_model = Model::from_vertices(
+        let mut floor_model = Model::from_vertices(
             &device,
             &queue,
             &floor_vertices,
             &floor_indices,
             floor_texture_view,
-            &renderer.material_bind_group_layout,
+            renderer.material_bind_group_layout(),
         );
 
+        // Polished floor so screen-space reflections have something to show
+        floor_model.materials[0].roughness = 0.6;
+        floor_model.materials[0].set_reflective(&queue, true);
+
         // Add floor to scene with identity transform
         let floor_transform = Transform::new();
+        let floor_model = scene.assets.add_model(floor_model);
         scene.add_object(floor_model, floor_transform);
+        scene.assets.unload(floor_model);
+
+        // Shown by objects placed with `place_model` until their model has loaded
+        let placeholder = Model::from_data(&device, &queue, &ModelData::from_mesh(scene::placeholder_mesh()), renderer.material_bind_group_layout());
+        let placeholder = scene.assets.add_model(placeholder);
+        scene.loading.set_placeholder(Some(placeholder));
 
         // Load test models
+        let model1_path = PathBuf::from("assets/2c0f9e16-66c8-4891-bfb6-d79394ee56b8.glb");
+        let model2_path = PathBuf::from("assets/f411cb1d-8c7f-4863-926a-40b8242bd166.glb");
         let model1 = Model::load(
             &device,
             &queue,
-            Path::new("assets/2c0f9e16-66c8-4891-bfb6-d79394ee56b8.glb"),
-            &renderer.material_bind_group_layout,
-        ).expect("Failed to load model 1");
+            &model1_path,
+            renderer.material_bind_group_layout(),
+        ).context("Failed to load model 1")?;
+        report(InitStage::Scene, 80);
 
         let model2 = Model::load(
             &device,
             &queue,
-            Path::new("assets/f411cb1d-8c7f-4863-926a-40b8242bd166.glb"),
-            &renderer.material_bind_group_layout,
-        ).expect("Failed to load model 2");
+            &model2_path,
+            renderer.material_bind_group_layout(),
+        ).context("Failed to load model 2")?;
 
         // Calculate Y offsets to place models on floor
         // We need to offset by the negative of the minimum Y coordinate to place the bottom at y=0
         let model1_y_offset = -model1.bounds_min[1];
         let model2_y_offset = -model2.bounds_min[1];
+        let model1 = scene.assets.add_model(model1);
+        let model2 = scene.assets.add_model(model2);
+        let model_paths = HashMap::from([(model1, model1_path), (model2, model2_path)]);
 
-        // Add multiple instances of each model with different transforms
+        // Add multiple instances of each model with different transforms, sharing the GPU resources
         let positions = [
             Vec3::new(-3.0, model1_y_offset, -3.0),
             Vec3::new(3.0, model1_y_offset, -3.0),
@@ -246,7 +416,7 @@ impl State {
             transform.position = positions[i];
             transform.rotation = rotations[i];
             transform.scale = Vec3::splat(1.0);
-            scene.add_object(model1.clone_with_device(&device, &queue, &renderer.material_bind_group_layout), transform);
+            scene.add_object(model1, transform);
         }
 
         // Add instances of model2
@@ -255,50 +425,659 @@ impl State {
             transform.position = positions[i];
             transform.rotation = rotations[i];
             transform.scale = Vec3::splat(1.0);
-            scene.add_object(model2.clone_with_device(&device, &queue, &renderer.material_bind_group_layout), transform);
+            scene.add_object(model2, transform);
         }
 
+        // The instances now own the models; replacing the scene frees them
+        scene.assets.unload(model1);
+        scene.assets.unload(model2);
+
         // Set up more dramatic lighting
         scene.set_ambient_light(0.3); // Increase ambient light
         scene.set_directional_light(
-            Vec3::new(1.0, 1.0, 1.0), // White light
+            Color::WHITE,
             Vec3::new(-0.5, -1.0, -0.5).normalize(), // Light coming from above and slightly to the side
         );
 
-        Self {
+        let simulation = Simulation::live();
+        #[cfg(feature = "scripting")]
+        let mut scripts = scene::ScriptHost::new();
+        #[cfg(feature = "scripting")]
+        scripts.seed_random(simulation.seed());
+
+        // Models show up as soon as their geometry is ready, textures following as they decode
+        let import_options = ImportOptions { stream_textures: true, ..ImportOptions::default() };
+
+        report(InitStage::Ready, 100);
+        Ok(Self {
             surface,
             device,
             queue,
             config,
+            alpha_modes: surface_caps.alpha_modes,
             window,
             scene,
             renderer,
-        }
+            import_options,
+            pending_loads: HashMap::new(),
+            videos: Vec::new(),
+            prefab_models: HashMap::new(),
+            model_paths,
+            autosave: None,
+            recovery: None,
+            selected: None,
+            placement: Placement { grid: Some(0.25), ..Placement::default() },
+            capture: FrameCapture::new(),
+            input_events: Vec::new(),
+            input: InputState::new(),
+            simulation,
+            #[cfg(feature = "scripting")]
+            scripts,
+            #[cfg(feature = "networking")]
+            network: None,
+        })
     }
 
     pub fn window(&self) -> &Window {
         &self.window
     }
 
+    pub fn settings(&self) -> &RendererSettings {
+        self.renderer.settings()
+    }
+
+    /// Objects drawn and culled in the last frame, for debugging culling
+    pub fn culling_stats(&self) -> scene::CullingStats {
+        self.renderer.culling_stats()
+    }
+
+    /// What the adapter and device support, and the quality tier picked from it
+    pub fn capabilities(&self) -> &scene::Capabilities {
+        self.renderer.capabilities()
+    }
+
+    /// Apply new renderer settings; only resources affected by the change are rebuilt. The
+    /// VR sections are for whoever owns the `VRSystem` to pass on with
+    /// `VRSystem::apply_settings` when the returned changes include them.
+    pub fn apply_settings(&mut self, settings: &RendererSettings) -> anyhow::Result<SettingsChanges> {
+        let mut settings = settings.clone();
+        if settings.window_alpha != self.settings().window_alpha {
+            self.config.alpha_mode = select_alpha_mode(&self.alpha_modes, &mut settings);
+            self.window.set_transparent(settings.window_alpha.is_transparent());
+            self.surface.configure(&self.device, &self.config);
+        }
+        let changes = self.renderer.apply_settings(&self.device, &self.queue, &self.config, &settings)?;
+        // A new viewport mode changes the camera's aspect ratio
+        let viewport = self.renderer.viewport();
+        self.scene.resize(viewport.width, viewport.height);
+        Ok(changes)
+    }
+
+    /// Scripts attached to scene objects
+    #[cfg(feature = "scripting")]
+    pub fn scripts_mut(&mut self) -> &mut scene::ScriptHost {
+        &mut self.scripts
+    }
+
+    /// Forward a named event to object scripts' `on_event`
+    #[cfg(feature = "scripting")]
+    pub fn script_event(&mut self, event: &str) {
+        self.scripts.dispatch_event(&mut self.scene, event);
+    }
+
+    /// Share the scene with other viewers through `session`, replacing any earlier one
+    #[cfg(feature = "networking")]
+    pub fn set_network(&mut self, session: Option<network::NetworkSession>) {
+        self.network = session;
+    }
+
+    /// The multi-user session, e.g. to claim objects before moving them
+    #[cfg(feature = "networking")]
+    pub fn network_mut(&mut self) -> Option<&mut network::NetworkSession> {
+        self.network.as_mut()
+    }
+
+    /// Show or hide the ground grid and origin axes
+    pub fn toggle_grid(&mut self) {
+        let mut settings = self.settings().clone();
+        settings.show_grid = !settings.show_grid;
+        if let Err(e) = self.apply_settings(&settings) {
+            log::warn!("{:#}", e);
+        }
+    }
+
+    /// Cut the loaded models in half with a vertical plane facing away from the camera,
+    /// or remove the cut if there is one
+    pub fn toggle_section_cut(&mut self) {
+        if self.scene.clipping.is_active() {
+            self.scene.clipping.planes.clear();
+            return;
+        }
+        let bounds = self.scene.objects.iter()
+            .skip(FLOOR_OBJECTS)
+            .filter_map(|object| object.world_bounds(&self.scene.assets))
+            .reduce(|(lo, hi), (min, max)| (lo.min(min), hi.max(max)));
+        let center = match bounds.or_else(|| self.scene.bounds()) {
+            Some((min, max)) => (min + max) * 0.5,
+            None => return,
+        };
+        self.scene.clipping.planes.push(ClipPlane::new(center, self.scene.camera.get_forward()));
+    }
+
+    /// Freeze or resume scene updates; rendering and the camera carry on
+    pub fn toggle_pause(&mut self) {
+        let paused = !self.scene.is_paused();
+        self.scene.set_paused(paused);
+        log::info!("{}", if paused { "Paused" } else { "Resumed" });
+    }
+
+    /// Advance one update while paused
+    pub fn step_frame(&mut self) {
+        if !self.scene.is_paused() {
+            log::info!("Not paused; pause first to step");
+            return;
+        }
+        self.scene.step_once();
+        log::info!("Stepped one frame");
+    }
+
+    /// Show or hide the profiler HUD
+    pub fn toggle_profiler_hud(&mut self) {
+        let enabled = !self.renderer.overlay_enabled();
+        self.renderer.set_overlay_enabled(enabled);
+        if enabled {
+            log::info!("{}", self.renderer.profiler().report());
+        }
+    }
+
+    /// Switch to the next per-eye debug view: scene color, depth, velocity, all three, or off
+    pub fn cycle_eye_debug(&mut self) {
+        let mode = self.renderer.eye_debug().mode().next();
+        self.renderer.set_eye_debug_mode(mode);
+        log::info!("Eye debug view: {}", mode.name());
+    }
+
+    /// Select the object under window pixel `(x, y)`; the selection changes a frame or two later
+    pub fn pick(&mut self, x: f32, y: f32) {
+        self.renderer.pick(x, y);
+    }
+
+    /// Log what the scene drew at window pixel `(x, y)`: depth, object, albedo, normal and
+    /// color, a frame or two later
+    pub fn inspect_pixel(&mut self, x: f32, y: f32) {
+        if self.renderer.inspect(x, y).is_none() {
+            log::info!("Pixel ({}, {}) is outside the viewport", x, y);
+        }
+    }
+
+    /// The world-space ray through window pixel `(x, y)`, for placing things under the cursor
+    pub fn screen_to_ray(&self, x: f32, y: f32) -> Ray {
+        self.scene.camera.screen_to_ray(x, y, &self.renderer.viewport())
+    }
+
+    pub fn selected(&self) -> Option<ObjectId> {
+        self.selected
+    }
+
+    /// Move the selected object onto the surface under window pixel `(x, y)`, snapped as
+    /// `placement` says; returns false if nothing is selected or nothing is under the pixel
+    pub fn place_selected(&mut self, x: f32, y: f32) -> bool {
+        let Some(id) = self.selected else {
+            return false;
+        };
+        let ray = self.screen_to_ray(x, y);
+        let Some(transform) = self.placement.place(&self.scene, id, &ray) else {
+            return false;
+        };
+        if let Some(current) = self.scene.transform_mut(id) {
+            *current = transform;
+        }
+        true
+    }
+
+    /// Record the next frame with RenderDoc (needs the `renderdoc` feature and RenderDoc attached)
+    pub fn trigger_capture(&mut self) {
+        self.capture.trigger_capture();
+    }
+
+    /// The frame capture the desktop loop uses; a VR loop brackets its frames with it too,
+    /// so F11 captures whichever frame comes next
+    pub fn frame_capture_mut(&mut self) -> &mut FrameCapture {
+        &mut self.capture
+    }
+
+    /// Persist the current settings so they are picked up at the next startup
+    pub fn save_settings(&self) -> anyhow::Result<()> {
+        self.renderer.settings().save(Path::new(settings::SETTINGS_PATH))
+    }
+
+    /// Start loading a model file in the background; it joins the scene in a later `update`
+    pub fn load_model(&mut self, path: impl Into<PathBuf>, mode: LoadMode) -> anyhow::Result<()> {
+        self.load_model_with(path, mode, self.import_options)
+    }
+
+    /// `load_model` with this model's own units or import scale
+    pub fn load_model_with(&mut self, path: impl Into<PathBuf>, mode: LoadMode, options: ImportOptions) -> anyhow::Result<()> {
+        let path = path.into();
+        if !ModelData::is_supported(&path) {
+            anyhow::bail!("Unsupported model file: {}", path.display());
+        }
+        self.request_load(path, options, PendingLoad::Model(mode));
+        Ok(())
+    }
+
+    /// Place the model at `path` at `transform` at once: the object shows a placeholder box
+    /// while the file loads in the background through `Scene::loading`, then its model. Unlike
+    /// `load_model` it isn't stood on the floor, as its bounds aren't known yet.
+    pub fn place_model(&mut self, path: impl Into<PathBuf>, transform: &Transform) -> anyhow::Result<ObjectId> {
+        let path = path.into();
+        if !ModelData::is_supported(&path) {
+            anyhow::bail!("Unsupported model file: {}", path.display());
+        }
+        let load = self.scene.loading.request(path.clone(), self.import_options);
+        let id = self.scene.add_object_loading(load, *transform)
+            .context("The load was dropped before its object was placed")?;
+        self.pending_loads.insert(load, (path, PendingLoad::Placed));
+        Ok(id)
+    }
+
+    fn request_load(&mut self, path: PathBuf, options: ImportOptions, pending: PendingLoad) {
+        let load = self.scene.loading.request(path.clone(), options);
+        self.pending_loads.insert(load, (path, pending));
+    }
+
+    /// Add the streaming volumes of the level file at `path`; their objects are loaded in the
+    /// background as the camera comes near, and left out of saved sessions
+    pub fn load_level(&mut self, path: &Path) -> anyhow::Result<Vec<VolumeId>> {
+        let level = Level::load(path)?;
+        log::info!("Streaming {} volumes of {}", level.volumes.len(), path.display());
+        Ok(self.scene.streaming.add_level(level))
+    }
+
+    /// Remove the objects of volumes the camera left and request the models of those it neared
+    fn update_streaming(&mut self) {
+        if self.scene.streaming.is_empty() {
+            return;
+        }
+        let changes = self.scene.streaming.update(self.scene.camera.position);
+        for id in changes.unload {
+            self.scene.remove_object(id);
+        }
+        for VolumeLoad { volume, generation, models } in changes.load {
+            for path in models {
+                self.request_load(path, self.import_options, PendingLoad::Volume(volume, generation));
+            }
+        }
+    }
+
+    /// Save the session to `path` every `interval` from now on, and delete the file in
+    /// `finish_session`. A file already there was left by a crash and is offered through
+    /// `recovery` instead; autosave waits until it is restored or discarded.
+    pub fn enable_autosave(&mut self, path: impl Into<PathBuf>, interval: std::time::Duration) {
+        let autosave = Autosave::new(path, interval);
+        self.recovery = autosave.recover();
+        if self.recovery.is_some() {
+            log::warn!("{} holds a scene from a session that didn't exit cleanly", autosave.path().display());
+        }
+        self.autosave = Some(autosave);
+    }
+
+    /// The crashed session on offer, if any
+    pub fn recovery(&self) -> Option<&Session> {
+        self.recovery.as_ref()
+    }
+
+    /// Restore the crashed session on offer; returns false if there isn't one
+    pub fn restore_recovery(&mut self) -> bool {
+        let Some(session) = self.recovery.take() else {
+            log::info!("No crashed session to restore");
+            return false;
+        };
+        self.restore_session(&session);
+        true
+    }
+
+    /// Drop the crashed session on offer; the next autosave overwrites it
+    pub fn discard_recovery(&mut self) {
+        self.recovery = None;
+    }
+
+    /// The camera pose and every object loaded from a file
+    pub fn session(&self) -> Session {
+        let mut session = Session::new(&self.scene.camera);
+        for object in self.scene.objects.iter().skip(FLOOR_OBJECTS) {
+            if let Some(path) = self.model_paths.get(&object.model()) {
+                session.objects.push(SessionObject::new(path, &object.transform, object.visible));
+            }
+        }
+        session
+    }
+
+    /// Replace the scene with a saved session. The camera moves at once; objects appear as
+    /// their model files finish loading in the background.
+    pub fn restore_session(&mut self, session: &Session) {
+        self.clear_objects();
+        session.camera.apply(&mut self.scene.camera);
+        for path in session.model_paths() {
+            let objects: Vec<_> = session.objects.iter().filter(|object| object.model == path).cloned().collect();
+            self.request_load(path, self.import_options, PendingLoad::Restore(objects));
+        }
+        log::info!("Restoring {} objects", session.objects.len());
+    }
+
+    /// Call on a clean exit: removes the recovery file so the next launch doesn't offer it
+    pub fn finish_session(&mut self) {
+        if let Some(autosave) = &mut self.autosave {
+            if let Err(e) = autosave.finish() {
+                log::warn!("{:#}", e);
+            }
+        }
+    }
+
+    fn autosave(&mut self) {
+        if self.recovery.is_some() || !self.autosave.as_ref().is_some_and(Autosave::is_due) {
+            return;
+        }
+        // A session still being restored would be saved without the objects yet to load,
+        // overwriting the only full copy should this one crash too
+        if self.pending_loads.values().any(|(_, pending)| matches!(pending, PendingLoad::Restore(_))) {
+            return;
+        }
+        // Forget models no object uses any more
+        let assets = &self.scene.assets;
+        self.model_paths.retain(|model, _| assets.model(*model).is_some());
+        let session = self.session();
+        if let Some(autosave) = &mut self.autosave {
+            match autosave.save(&session) {
+                Ok(true) => log::debug!("Saved session to {}", autosave.path().display()),
+                Ok(false) => {}
+                Err(e) => log::warn!("Autosave failed: {:#}", e),
+            }
+        }
+    }
+
+    fn clear_objects(&mut self) {
+        let replaced: Vec<_> = self.scene.objects.iter().skip(FLOOR_OBJECTS).map(|object| object.id).collect();
+        for id in replaced {
+            self.scene.remove_object(id);
+        }
+    }
+
+    /// Queue an input event for the next `update`
+    pub fn push_input(&mut self, event: InputEvent) {
+        self.input_events.push(event);
+    }
+
+    /// Input as of the last `update`
+    pub fn input(&self) -> &InputState {
+        &self.input
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    /// Time, randomness and input source for updates, e.g. to draw random numbers that replay
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.simulation
+    }
+
+    /// Switch how updates advance, e.g. to deterministic mode or a replay. A replay only
+    /// reproduces the recorded session when started from the same scene.
+    pub fn set_simulation(&mut self, simulation: Simulation) {
+        #[cfg(feature = "scripting")]
+        self.scripts.seed_random(simulation.seed());
+        self.simulation = simulation;
+    }
+
+    /// Per-frame update: applies queued input (or recorded input when replaying), adds finished
+    /// background loads to the scene, then advances it by the wall clock or the fixed timestep
+    pub fn update(&mut self) {
+        let events = self.simulation.next_frame(self.input_events.drain(..));
+        self.input.begin_frame(events);
+        self.scene.process_input(&self.input);
+        #[cfg(feature = "scripting")]
+        if self.scene.advancing() {
+            self.scripts.set_input(&self.input);
+            for key in self.input.pressed_keys() {
+                self.scripts.dispatch_event(&mut self.scene, key.name());
+            }
+        }
+
+        self.update_streaming();
+        self.scene.upload_loads(&self.device, &self.queue, self.renderer.material_bind_group_layout());
+        self.finish_loads();
+        self.update_videos();
+        for result in self.renderer.poll_picks(&self.device) {
+            if result.object != self.selected {
+                match result.object.and_then(|id| self.scene.object(id)) {
+                    Some(object) => log::info!("Selected object {} at {:?}", object.id.0, object.transform.position),
+                    None => log::info!("Selection cleared"),
+                }
+            }
+            self.selected = result.object;
+        }
+        for info in self.renderer.poll_inspections(&self.device) {
+            log::info!("{}", info);
+        }
+        let shift = match self.simulation.timestep() {
+            Some(dt) => self.scene.step(dt),
+            None => self.scene.update(),
+        };
+        if let Some(shift) = shift {
+            log::debug!("Rebased floating origin by {:?}, now at {:?}", shift, self.scene.origin.offset());
+        }
+        #[cfg(feature = "scripting")]
+        if self.scene.advanced() {
+            let dt = self.scene.frame_time();
+            self.scripts.update(&mut self.scene, dt);
+        }
+        #[cfg(feature = "networking")]
+        if let Some(network) = &mut self.network {
+            network.update(&mut self.scene);
+        }
+        self.autosave();
+    }
+
+    /// Place an instance of the prefab file at `path`; its model paths are relative to the file.
+    /// Models are loaded on the calling thread the first time any prefab uses them.
+    pub fn spawn_prefab(&mut self, path: &Path, transform: &Transform, overrides: &PrefabOverrides) -> anyhow::Result<PrefabInstance> {
+        let prefab = Prefab::load(path)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for model_path in prefab.model_paths(overrides) {
+            let model_path = directory.join(model_path);
+            if self.prefab_models.contains_key(&model_path) {
+                continue;
+            }
+            let data = ModelData::load(&model_path)?;
+            let model = Model::from_data(&self.device, &self.queue, &data, self.renderer.material_bind_group_layout());
+            let model = self.scene.assets.add_model(model);
+            self.model_paths.insert(model, model_path.clone());
+            self.prefab_models.insert(model_path, model);
+        }
+
+        let instance = prefab.instantiate(&mut self.scene, transform, overrides, |model_path| {
+            self.prefab_models.get(&directory.join(model_path)).copied()
+        });
+        log::info!("Spawned prefab '{}' with {} objects", instance.prefab, instance.objects.len());
+        Ok(instance)
+    }
+
+    /// Place the model at `path` at `transform` as one object per node of its hierarchy, parented
+    /// as in the file, instead of a single object; loads on the calling thread. Nodes instancing
+    /// the same meshes share a model. Returns each node's object, `None` for nodes without meshes.
+    /// Node objects are left out of saved sessions, which only know whole model files.
+    pub fn load_hierarchy(&mut self, path: &Path, transform: &Transform) -> anyhow::Result<Vec<Option<ObjectId>>> {
+        let data = ModelData::load_hierarchy(path)?;
+        let (parts, node_parts) = data.split_nodes();
+        let handles: Vec<ModelHandle> = parts.iter()
+            .map(|part| {
+                let model = Model::from_data(&self.device, &self.queue, part, self.renderer.material_bind_group_layout());
+                self.scene.assets.add_model(model)
+            })
+            .collect();
+        if data.nodes.is_empty() {
+            self.model_paths.insert(handles[0], path.to_path_buf());
+            return Ok(vec![Some(self.scene.add_object(handles[0], *transform))]);
+        }
+        let models: Vec<Option<ModelHandle>> = node_parts.iter().map(|part| part.map(|part| handles[part])).collect();
+        let objects = self.scene.add_node_hierarchy(&data.nodes, &models, transform);
+        log::info!("Loaded {} with {} nodes", path.display(), data.nodes.len());
+        Ok(objects)
+    }
+
+    /// A material showing `source`'s video, keyed with `chroma_key` if given, for any object;
+    /// see `set_object_material`. Frames are uploaded in `update` until the material is unloaded.
+    pub fn add_video(&mut self, name: &str, source: impl VideoSource + 'static, chroma_key: Option<ChromaKey>) -> MaterialHandle {
+        let video = VideoTexture::new(&self.device, self.renderer.material_bind_group_layout(), &mut self.scene.assets, name, source, chroma_key);
+        let material = video.material();
+        self.videos.push(video);
+        material
+    }
+
+    /// A panel `aspect` (width / height) wide and a meter tall showing `source`'s video, e.g. a
+    /// webcam feed in the world, or with `chroma_key` a green-screened person composited into it
+    pub fn add_video_panel(&mut self, source: impl VideoSource + 'static, aspect: f32, transform: &Transform, chroma_key: Option<ChromaKey>) -> ObjectId {
+        let material = self.add_video("video", source, chroma_key);
+        let data = ModelData::from_mesh(model::video_panel(aspect));
+        let model = Model::from_data(&self.device, &self.queue, &data, self.renderer.material_bind_group_layout());
+        let model = self.scene.assets.add_model(model);
+        let id = self.scene.add_object(model, *transform);
+        self.scene.set_object_material(id, Some(material));
+        // The object holds its own references now
+        self.scene.assets.release_model(model);
+        self.scene.assets.release_material(material);
+        id
+    }
+
+    /// Upload the newest frame of each live video, dropping those whose material was unloaded
+    fn update_videos(&mut self) {
+        let assets = &mut self.scene.assets;
+        self.videos.retain(|video| assets.material(video.material()).is_some());
+        for video in &mut self.videos {
+            if let Err(error) = video.update(&self.device, &self.queue, self.renderer.material_bind_group_layout(), assets) {
+                log::warn!("Failed to update video: {:#}", error);
+            }
+        }
+    }
+
+    /// Add the objects of loads `Scene::upload_loads` has finished
+    fn finish_loads(&mut self) {
+        let loading = &self.scene.loading;
+        let finished: Vec<LoadHandle> = self.pending_loads.keys()
+            .filter(|load| loading.state(**load) != Some(&LoadState::Loading))
+            .copied()
+            .collect();
+        for load in finished {
+            let (path, pending) = self.pending_loads.remove(&load).expect("load is pending");
+            match self.scene.loading.state(load).cloned() {
+                Some(LoadState::Ready(model)) => self.add_loaded_model(model, path, pending),
+                // Failures are logged by `upload_loads`
+                _ => {
+                    if let PendingLoad::Volume(volume, generation) = pending {
+                        self.scene.streaming.loaded(volume, generation, Vec::new(), 0);
+                    }
+                }
+            }
+            self.scene.loading.forget(load);
+        }
+    }
+
+    fn add_loaded_model(&mut self, model: ModelHandle, path: PathBuf, pending: PendingLoad) {
+        let (bounds_min, memory) = self.scene.assets.model(model)
+            .map_or((0.0, 0), |loaded| (loaded.bounds_min[1], loaded.memory_size()));
+        // Stand the model on the floor at the origin
+        let mut transform = Transform::new();
+        transform.position.y = -bounds_min;
+        if !matches!(pending, PendingLoad::Volume(..)) {
+            self.model_paths.insert(model, path.clone());
+        }
+
+        match pending {
+            PendingLoad::Model(mode) => {
+                if mode == LoadMode::Replace {
+                    self.clear_objects();
+                }
+                let id = self.scene.add_object(model, transform);
+                self.scene.focus_object(id);
+            }
+            // Its object holds the model already
+            PendingLoad::Placed => {}
+            PendingLoad::Restore(objects) => {
+                for object in objects {
+                    let id = self.scene.add_object(model, object.transform());
+                    self.scene.set_visible(id, object.visible);
+                }
+            }
+            PendingLoad::Volume(volume, generation) => {
+                let streaming = &self.scene.streaming;
+                let placements: Vec<(Transform, bool)> = streaming.volume(volume)
+                    .into_iter()
+                    .flat_map(|volume| &volume.objects)
+                    .filter(|object| object.model == path)
+                    .map(|object| (streaming.placement(object), object.visible))
+                    .collect();
+                let objects: Vec<ObjectId> = placements.into_iter()
+                    .map(|(transform, visible)| {
+                        let id = self.scene.add_object(model, transform);
+                        self.scene.set_visible(id, visible);
+                        id
+                    })
+                    .collect();
+                // The camera moved on while the model loaded
+                if !self.scene.streaming.loaded(volume, generation, objects.clone(), memory) {
+                    for id in objects {
+                        self.scene.remove_object(id);
+                    }
+                }
+            }
+        }
+        self.scene.assets.unload(model);
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
             self.surface.configure(&self.device, &self.config);
-            self.scene.resize(width, height);
             self.renderer.resize(&self.device, &self.config);
+            // Letterboxing leaves the camera the viewport, not the whole window
+            let viewport = self.renderer.viewport();
+            self.scene.resize(viewport.width, viewport.height);
         }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let frame = self.surface.get_current_texture()?;
         let view = frame.texture.create_view(&Default::default());
-        self.renderer.render(&self.device, &self.queue, &view, &self.scene)?;
+        self.capture.begin_frame();
+        let result = self.renderer.render(&self.device, &self.queue, &view, &self.scene);
+        if result.is_ok() {
+            self.renderer.render_eye_debug(&self.device, &self.queue, &view, self.config.width, self.config.height);
+        }
         frame.present();
+        self.capture.end_frame();
+        result?;
+        self.scene.assets.end_frame();
         Ok(())
     }
 }
 
+/// The surface alpha mode for `settings.window_alpha`, switching the setting back to opaque
+/// when the surface can't composite that way
+fn select_alpha_mode(supported: &[wgpu::CompositeAlphaMode], settings: &mut RendererSettings) -> wgpu::CompositeAlphaMode {
+    if let Some(mode) = settings.window_alpha.composite_mode(supported) {
+        return mode;
+    }
+    log::warn!(
+        "Surface doesn't support {:?} window alpha (supports {:?}); using an opaque window",
+        settings.window_alpha,
+        supported,
+    );
+    settings.window_alpha = OutputAlpha::Opaque;
+    OutputAlpha::Opaque.composite_mode(supported).unwrap_or(wgpu::CompositeAlphaMode::Auto)
+}
+
 fn create_checkerboard_texture(size: u32) -> Vec<u8> {
     let mut data = Vec::with_capacity((size * size * 4) as usize);
     let squares_per_side = 20; // We want 20x20 squares for our 20x20 meter floor
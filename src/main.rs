@@ -3,20 +3,79 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
     window::WindowBuilder,
 };
-use wgpu_3d_viewer::State;
+use wgpu_3d_viewer::diagnostics;
+use std::path::PathBuf;
+use wgpu_3d_viewer::input;
+use wgpu_3d_viewer::prelude::*;
+use wgpu_3d_viewer::session::{DEFAULT_AUTOSAVE_INTERVAL, RECOVERY_FILE};
+use wgpu_3d_viewer::simulation::DEFAULT_TIMESTEP;
 
 fn main() {
+    diagnostics::init();
+
+    // `--record <file>` runs deterministically and saves the session's input on exit;
+    // `--replay <file>` plays such a session back; `--vr-diagnose` reports on the OpenXR setup;
+    // `--host <port>` and `--join <address>` start a multi-user session
+    let mut record_path = None;
+    let mut replay = None;
+    #[cfg(feature = "networking")]
+    let mut network = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--record", Some(path)) => record_path = Some(PathBuf::from(path)),
+            ("--replay", Some(path)) => match InputRecording::load(path.as_ref()) {
+                Ok(recording) => replay = Some(recording),
+                Err(e) => log::error!("{:#}", e),
+            },
+            #[cfg(feature = "networking")]
+            ("--host", Some(port)) => network = Some((port, None)),
+            #[cfg(feature = "networking")]
+            ("--join", Some(address)) => network = Some(("0".to_string(), Some(address))),
+            #[cfg(not(any(target_vendor = "apple", target_arch = "wasm32")))]
+            ("--vr-diagnose", _) => {
+                print!("{}", wgpu_3d_viewer::vr::VRSystem::diagnose());
+                return;
+            }
+            (arg, _) => log::warn!("Ignoring unknown argument {}", arg),
+        }
+    }
+
     let event_loop = winit::event_loop::EventLoop::new()
         .expect("Failed to create event loop");
     
+    // Some platforms only give a window an alpha channel when it's created with one
+    let settings = RendererSettings::load_or_default(std::path::Path::new(wgpu_3d_viewer::settings::SETTINGS_PATH));
     let window = WindowBuilder::new()
         .with_title("3D Engine")
         .with_visible(true)
+        .with_transparent(settings.window_alpha.is_transparent())
         .build(&event_loop)
         .unwrap();
 
-    let mut state = State::new(window);
+    let mut state = pollster::block_on(State::init(window, |progress| {
+        log::info!("[{:>3}%] {}", progress.percent, progress.stage.label());
+    }))
+    .expect("Failed to initialize");
+    if let Some(recording) = replay {
+        log::info!("Replaying {} updates", recording.frame_count);
+        state.set_simulation(Simulation::replay(recording));
+    } else if record_path.is_some() {
+        state.set_simulation(Simulation::recording(0, DEFAULT_TIMESTEP));
+    }
+    state.enable_autosave(RECOVERY_FILE, DEFAULT_AUTOSAVE_INTERVAL);
+    #[cfg(feature = "networking")]
+    if let Some((port, join)) = network {
+        match start_network(&port, join.as_deref()) {
+            Ok(session) => state.set_network(Some(session)),
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+    if let Some(session) = state.recovery() {
+        log::warn!("Press F9 to restore the {} objects from the last session, or Shift+F9 to discard them", session.objects.len());
+    }
     let mut mouse_captured = false;
+    let mut modifiers = winit::keyboard::ModifiersState::empty();
 
     event_loop.run(move |event, window_target| {
         match event {
@@ -31,30 +90,108 @@ fn main() {
                         ..
                     } => {
                         let pressed = key_state == ElementState::Pressed;
+                        if let Some(key) = Key::from_winit(key_code) {
+                            state.push_input(InputEvent::Key { key, pressed });
+                        }
+                        if !pressed {
+                            return;
+                        }
                         match key_code {
                             KeyCode::Escape => {
-                                if pressed {
-                                    mouse_captured = false;
-                                    state.window().set_cursor_grab(winit::window::CursorGrabMode::None)
-                                        .unwrap();
-                                    state.window().set_cursor_visible(true);
-                                }
+                                mouse_captured = false;
+                                state.window().set_cursor_grab(winit::window::CursorGrabMode::None)
+                                    .unwrap();
+                                state.window().set_cursor_visible(true);
                             }
-                            _ => state.scene.process_keyboard(key_code, pressed),
+                            KeyCode::F3 => state.toggle_profiler_hud(),
+                            KeyCode::F4 => state.cycle_eye_debug(),
+                            KeyCode::F5 => state.toggle_pause(),
+                            KeyCode::F6 => state.step_frame(),
+                            KeyCode::F9 if modifiers.shift_key() => state.discard_recovery(),
+                            KeyCode::F9 => {
+                                state.restore_recovery();
+                            }
+                            KeyCode::F11 => state.trigger_capture(),
+                            KeyCode::KeyC => state.toggle_section_cut(),
+                            KeyCode::KeyG => state.toggle_grid(),
+                            // Inspect the pixel under the crosshair, or the cursor when it's free
+                            KeyCode::KeyI => {
+                                let size = state.window().inner_size();
+                                let center = glam::Vec2::new(size.width as f32 * 0.5, size.height as f32 * 0.5);
+                                let cursor = state.input().cursor().filter(|_| !mouse_captured).unwrap_or(center);
+                                state.inspect_pixel(cursor.x, cursor.y);
+                            }
+                            // Move the selection onto whatever is under the crosshair
+                            KeyCode::KeyP if mouse_captured => {
+                                let size = state.window().inner_size();
+                                state.place_selected(size.width as f32 * 0.5, size.height as f32 * 0.5);
+                            }
+                            _ => {}
                         }
                     }
-                    WindowEvent::MouseInput {
-                        state: ElementState::Pressed,
-                        button: MouseButton::Left,
-                        ..
-                    } => {
+                    WindowEvent::MouseInput { state: button_state, button, .. } => {
+                        let pressed = button_state == ElementState::Pressed;
+                        state.push_input(InputEvent::MouseButton { button: input::MouseButton::from_winit(button), pressed });
+                        if !pressed || button != MouseButton::Left {
+                            return;
+                        }
+
+                        // While looking around, clicks select what's under the crosshair
+                        if mouse_captured {
+                            let size = state.window().inner_size();
+                            state.pick(size.width as f32 * 0.5, size.height as f32 * 0.5);
+                            return;
+                        }
                         mouse_captured = true;
                         state.window().set_cursor_grab(winit::window::CursorGrabMode::Confined)
                             .or_else(|_e| state.window().set_cursor_grab(winit::window::CursorGrabMode::Locked))
                             .unwrap();
                         state.window().set_cursor_visible(false);
                     }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        state.push_input(InputEvent::CursorMoved { x: position.x as f32, y: position.y as f32 });
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let delta = match delta {
+                            MouseScrollDelta::LineDelta(_, lines) => lines,
+                            // Roughly one line per 20 pixels of touchpad scrolling
+                            MouseScrollDelta::PixelDelta(pixels) => pixels.y as f32 / 20.0,
+                        };
+                        state.push_input(InputEvent::Scroll { delta });
+                    }
+                    WindowEvent::ModifiersChanged(new_modifiers) => {
+                        modifiers = new_modifiers.state();
+                    }
+                    WindowEvent::DroppedFile(path) if modifiers.alt_key() => {
+                        // Alt places it on the floor in front of the camera straight away, a
+                        // placeholder box standing in while it loads
+                        let camera = &state.scene.camera;
+                        let forward = (camera.get_forward() * glam::Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+                        let mut position = camera.position + forward * 3.0;
+                        position.y = 0.0;
+                        if let Err(e) = state.place_model(path, &Transform { position, ..Transform::new() }) {
+                            log::warn!("{:#}", e);
+                        }
+                    }
+                    WindowEvent::DroppedFile(path) => {
+                        // Ctrl (Cmd on macOS) adds to the scene, a plain drop replaces it
+                        let mode = if modifiers.control_key() || modifiers.super_key() {
+                            LoadMode::Add
+                        } else {
+                            LoadMode::Replace
+                        };
+                        if let Err(e) = state.load_model(path, mode) {
+                            log::warn!("{:#}", e);
+                        }
+                    }
                     WindowEvent::CloseRequested => {
+                        if let (Some(path), Some(recording)) = (&record_path, state.simulation_mut().take_recording()) {
+                            match recording.save(path) {
+                                Ok(()) => log::info!("Saved {} updates of input to {}", recording.frame_count, path.display()),
+                                Err(e) => log::error!("{:#}", e),
+                            }
+                        }
+                        state.finish_session();
                         window_target.exit();
                     }
                     WindowEvent::Resized(new_size) => {
@@ -72,13 +209,26 @@ fn main() {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
             } if mouse_captured => {
-                state.scene.process_mouse(delta.0 as f32, delta.1 as f32);
+                state.push_input(InputEvent::MouseMotion { dx: delta.0 as f32, dy: delta.1 as f32 });
             }
             Event::AboutToWait => {
-                state.scene.update();
+                state.update();
                 state.window().request_redraw();
             }
             _ => {}
         }
     }).unwrap();
-} 
\ No newline at end of file
+} 
+
+/// Listen on `port` (0 for any) and greet `join` if given
+#[cfg(feature = "networking")]
+fn start_network(port: &str, join: Option<&str>) -> anyhow::Result<wgpu_3d_viewer::network::NetworkSession> {
+    use wgpu_3d_viewer::network::{NetworkSession, PeerId};
+    let port: u16 = port.parse().map_err(|_| anyhow::anyhow!("Invalid port {}", port))?;
+    let mut session = NetworkSession::bind(("0.0.0.0", port), PeerId::generate())?;
+    if let Some(address) = join {
+        session.connect(address)?;
+    }
+    log::info!("Multi-user session on {} as peer {}", session.local_address()?, session.peer_id().0);
+    Ok(session)
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use super::{ImageData, MaterialData, ModelData};
+
+/// UVs this far outside 0..1 still count as inside, allowing for exporters' rounding
+const UV_EPSILON: f32 = 1e-3;
+
+/// How `ModelData::pack_atlases` merges small textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasOptions {
+    /// Textures larger than this on either side keep their own
+    pub max_texture_size: u32,
+    /// Largest width and height of an atlas
+    pub atlas_size: u32,
+    /// Texels around each texture filled with its edge, so filtering doesn't bleed in the
+    /// neighbours; more keeps the smaller mip levels clean too
+    pub padding: u32,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> Self {
+        Self { max_texture_size: 256, atlas_size: 2048, padding: 2 }
+    }
+}
+
+/// What `ModelData::pack_atlases` merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AtlasReport {
+    pub atlases: usize,
+    /// Materials folded into the atlases' materials
+    pub packed_materials: usize,
+}
+
+impl fmt::Display for AtlasReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} materials packed into {} atlases", self.packed_materials, self.atlases)
+    }
+}
+
+/// Materials that can share an atlas also share every other parameter, so each atlas becomes
+/// a single material: base color, roughness, double sided, alpha cutoff, alpha-to-coverage,
+/// transparency and whether there's a normal map
+type MaterialKey = ([u32; 4], u32, bool, Option<u32>, bool, bool, bool);
+
+impl ModelData {
+    /// Merge materials with small textures into shared atlases, rewriting their meshes' UVs, so
+    /// scenes of many low-res props bind a few textures instead of one per material. Only
+    /// materials whose UVs stay within 0..1 are packed, since tiling can't repeat inside an
+    /// atlas, and only those with identical parameters share one. Material indices change.
+    pub fn pack_atlases(&mut self, options: &AtlasOptions) -> AtlasReport {
+        // Materials whose meshes tile or wrap their textures keep them
+        let mut in_range = vec![true; self.materials.len()];
+        for mesh in &self.meshes {
+            let inside = |uv: f32| (-UV_EPSILON..=1.0 + UV_EPSILON).contains(&uv);
+            if !mesh.vertices.iter().all(|vertex| vertex.tex_coords.iter().all(|&uv| inside(uv))) {
+                if let Some(in_range) = in_range.get_mut(mesh.material_index) {
+                    *in_range = false;
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<MaterialKey, Vec<usize>> = BTreeMap::new();
+        for (index, material) in self.materials.iter().enumerate() {
+            let Some(diffuse) = &material.diffuse else {
+                continue;
+            };
+            let fits = diffuse.width > 0 && diffuse.height > 0
+                && diffuse.width.max(diffuse.height) <= options.max_texture_size
+                && diffuse.width.max(diffuse.height) + 2 * options.padding <= options.atlas_size
+                && diffuse.pixels.len() == (diffuse.width * diffuse.height * 4) as usize;
+            // Normal maps share the diffuse texture's place, so they must be the same size
+            let normal_fits = material.normal.as_ref()
+                .is_none_or(|normal| (normal.width, normal.height) == (diffuse.width, diffuse.height)
+                    && normal.pixels.len() == diffuse.pixels.len());
+            if fits && normal_fits && in_range[index] {
+                let key = (
+                    material.base_color.to_array().map(f32::to_bits),
+                    material.roughness.to_bits(),
+                    material.double_sided,
+                    material.alpha_cutoff.map(f32::to_bits),
+                    material.alpha_to_coverage,
+                    material.transparent,
+                    material.normal.is_some(),
+                );
+                groups.entry(key).or_default().push(index);
+            }
+        }
+
+        // Where each packed material went: its atlas, and the scale and offset for its UVs
+        let mut placements: Vec<Option<(usize, [f32; 4])>> = vec![None; self.materials.len()];
+        let mut atlases = Vec::new();
+        let mut report = AtlasReport::default();
+        for members in groups.values() {
+            let sizes: Vec<_> = members.iter()
+                .map(|&index| self.materials[index].diffuse.as_ref().map_or((0, 0), |image| (image.width, image.height)))
+                .collect();
+            // An atlas of one texture saves nothing
+            for page in shelf_pack(&sizes, options.atlas_size, options.padding).into_iter().filter(|page| page.len() > 1) {
+                let width = page.iter().map(|&(item, (x, _))| x + sizes[item].0 + options.padding).max().unwrap_or(0);
+                let height = page.iter().map(|&(item, (_, y))| y + sizes[item].1 + options.padding).max().unwrap_or(0);
+                let blank = || ImageData { width, height, pixels: vec![0; (width * height * 4) as usize] };
+                let first = &self.materials[members[page[0].0]];
+                let mut diffuse = blank();
+                let mut normal = first.normal.as_ref().map(|_| blank());
+                for &(item, (x, y)) in &page {
+                    let material = &self.materials[members[item]];
+                    if let Some(image) = &material.diffuse {
+                        blit(&mut diffuse, image, (x, y), options.padding);
+                    }
+                    if let (Some(atlas), Some(image)) = (&mut normal, &material.normal) {
+                        blit(atlas, image, (x, y), options.padding);
+                    }
+                    let (w, h) = sizes[item];
+                    let transform = [
+                        w as f32 / width as f32,
+                        h as f32 / height as f32,
+                        x as f32 / width as f32,
+                        y as f32 / height as f32,
+                    ];
+                    placements[members[item]] = Some((atlases.len(), transform));
+                }
+                atlases.push(MaterialData {
+                    name: format!("atlas_{}", atlases.len()),
+                    diffuse: Some(diffuse),
+                    normal,
+                    base_color: first.base_color,
+                    roughness: first.roughness,
+                    double_sided: first.double_sided,
+                    alpha_cutoff: first.alpha_cutoff,
+                    alpha_to_coverage: first.alpha_to_coverage,
+                    transparent: first.transparent,
+                });
+                report.atlases += 1;
+                report.packed_materials += page.len();
+            }
+        }
+        if atlases.is_empty() {
+            return report;
+        }
+
+        for mesh in &mut self.meshes {
+            if let Some(&Some((_, [scale_u, scale_v, offset_u, offset_v]))) = placements.get(mesh.material_index) {
+                for vertex in &mut mesh.vertices {
+                    let [u, v] = vertex.tex_coords;
+                    vertex.tex_coords = [offset_u + u * scale_u, offset_v + v * scale_v];
+                }
+            }
+        }
+
+        // Each atlas takes the place of its lowest-numbered material; the rest keep their order
+        let mut atlases: Vec<_> = atlases.into_iter().map(Some).collect();
+        let mut atlas_indices = vec![usize::MAX; atlases.len()];
+        let mut remap = Vec::with_capacity(self.materials.len());
+        let mut materials = Vec::new();
+        for (material, placement) in std::mem::take(&mut self.materials).into_iter().zip(&placements) {
+            match placement {
+                Some((atlas, _)) => {
+                    if let Some(atlas_material) = atlases[*atlas].take() {
+                        atlas_indices[*atlas] = materials.len();
+                        materials.push(atlas_material);
+                    }
+                    remap.push(atlas_indices[*atlas]);
+                }
+                None => {
+                    remap.push(materials.len());
+                    materials.push(material);
+                }
+            }
+        }
+        self.materials = materials;
+        for mesh in &mut self.meshes {
+            if let Some(&index) = remap.get(mesh.material_index) {
+                mesh.material_index = index;
+            }
+        }
+        report
+    }
+}
+
+/// Place rectangles of `sizes`, plus `padding` all round, on pages at most `atlas_size`
+/// square, in shelves of decreasing height. Returns each page's items with the top-left
+/// corner of their texels.
+fn shelf_pack(sizes: &[(u32, u32)], atlas_size: u32, padding: u32) -> Vec<Vec<(usize, (u32, u32))>> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&item| (std::cmp::Reverse(sizes[item].1), std::cmp::Reverse(sizes[item].0)));
+
+    let mut pages = Vec::new();
+    let mut page = Vec::new();
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for item in order {
+        let (width, height) = (sizes[item].0 + 2 * padding, sizes[item].1 + 2 * padding);
+        if x + width > atlas_size {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        if y + height > atlas_size {
+            pages.push(std::mem::take(&mut page));
+            (x, y, shelf_height) = (0, 0, 0);
+        }
+        page.push((item, (x + padding, y + padding)));
+        x += width;
+        shelf_height = shelf_height.max(height);
+    }
+    if !page.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+/// Copy `image` into `atlas` with its top-left texel at `(x, y)`, extending its edges into the
+/// padding around it
+fn blit(atlas: &mut ImageData, image: &ImageData, (x, y): (u32, u32), padding: u32) {
+    for row in 0..image.height + 2 * padding {
+        let source_row = row.saturating_sub(padding).min(image.height - 1);
+        let target_row = y - padding + row;
+        for column in 0..image.width + 2 * padding {
+            let source_column = column.saturating_sub(padding).min(image.width - 1);
+            let source = ((source_row * image.width + source_column) * 4) as usize;
+            let target = ((target_row * atlas.width + x - padding + column) * 4) as usize;
+            atlas.pixels[target..target + 4].copy_from_slice(&image.pixels[source..source + 4]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::model::{MeshData, ModelVertex};
+
+    fn image(size: u32, color: u8) -> ImageData {
+        ImageData { width: size, height: size, pixels: vec![color; (size * size * 4) as usize] }
+    }
+
+    fn material(name: &str, diffuse: ImageData) -> MaterialData {
+        MaterialData {
+            name: name.to_string(),
+            diffuse: Some(diffuse),
+            normal: None,
+            base_color: Color::WHITE,
+            roughness: 0.5,
+            double_sided: false,
+            alpha_cutoff: None,
+            alpha_to_coverage: false,
+            transparent: false,
+        }
+    }
+
+    fn quad(material_index: usize, uv_max: f32) -> MeshData {
+        let vertex = |x: f32, u: f32| ModelVertex {
+            position: [x, 0.0, 0.0],
+            tex_coords: [u, u],
+            normal: [0.0, 1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+        MeshData {
+            name: format!("mesh_{}", material_index),
+            vertices: vec![vertex(0.0, 0.0), vertex(1.0, uv_max)],
+            indices: Vec::new(),
+            material_index,
+            skin: None,
+        }
+    }
+
+    #[test]
+    fn test_pack_atlases() {
+        // Shelves fill left to right, tallest first, and overflow onto a new page
+        let pages = shelf_pack(&[(16, 16), (32, 32), (16, 8), (40, 40)], 64, 1);
+        assert_eq!(pages, vec![vec![(3, (1, 1))], vec![(1, (1, 1)), (0, (35, 1)), (2, (1, 35))]]);
+
+        // Two small props and a large texture; one prop tiles, another has different roughness
+        let mut data = ModelData::from_mesh(quad(0, 1.0));
+        data.materials = vec![
+            material("red", image(4, 200)),
+            material("big", image(512, 10)),
+            material("green", image(8, 100)),
+            material("tiled", image(4, 50)),
+            MaterialData { roughness: 0.9, ..material("shiny", image(4, 30)) },
+            material("blue", image(4, 20)),
+        ];
+        data.meshes = (0..6).map(|index| quad(index, if index == 3 { 4.0 } else { 1.0 })).collect();
+
+        let report = data.pack_atlases(&AtlasOptions::default());
+        assert_eq!(report, AtlasReport { atlases: 1, packed_materials: 3 });
+        let names: Vec<_> = data.materials.iter().map(|material| material.name.as_str()).collect();
+        assert_eq!(names, ["atlas_0", "big", "tiled", "shiny"]);
+        let indices: Vec<_> = data.meshes.iter().map(|mesh| mesh.material_index).collect();
+        assert_eq!(indices, [0, 1, 0, 2, 3, 0]);
+
+        // The 8px texture sits first on the shelf; each UV range now covers only its own texels
+        let atlas = data.materials[0].diffuse.as_ref().unwrap();
+        assert_eq!((atlas.width, atlas.height), (28, 12));
+        let texel = |u: f32, v: f32| {
+            let (x, y) = ((u * atlas.width as f32) as u32, (v * atlas.height as f32) as u32);
+            atlas.pixels[((y * atlas.width + x) * 4) as usize]
+        };
+        for (mesh, color) in [(0, 200), (2, 100), (5, 20)] {
+            let [start, end] = [0, 1].map(|vertex| data.meshes[mesh].vertices[vertex].tex_coords);
+            assert_eq!(texel(start[0], start[1]), color);
+            assert_eq!(texel(end[0] - 1e-3, end[1] - 1e-3), color);
+        }
+        // Untouched meshes keep their UVs
+        assert_eq!(data.meshes[3].vertices[1].tex_coords, [4.0, 4.0]);
+        assert_eq!(data.meshes[1].vertices[1].tex_coords, [1.0, 1.0]);
+        // Padding repeats the edge texels
+        assert_eq!(atlas.pixels[0], 100);
+    }
+}
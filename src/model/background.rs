@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use anyhow::Result;
+
+use super::{ImportOptions, LengthUnit, ModelData, StreamedTexture, ValidationReport};
+
+/// A finished background load, matched to its request by `id`
+pub struct LoadedModel {
+    pub id: u64,
+    pub path: PathBuf,
+    pub data: Result<ModelData>,
+    /// What validation found in the loaded data, and fixed if the loader repairs
+    pub validation: Option<ValidationReport>,
+    /// The unit the model was more likely authored in, when its size after import looks wrong
+    pub suspected_units: Option<LengthUnit>,
+    /// The units it was converted into, `ImportOptions::world_units`
+    pub world_units: LengthUnit,
+    /// With `ImportOptions::stream_textures`, how many textures will follow through
+    /// `BackgroundLoader::poll_textures`
+    pub streamed_textures: usize,
+    /// Materials whose diffuse texture is among them, to show a placeholder meanwhile
+    pub waiting_materials: Vec<usize>,
+}
+
+/// Parses, scales and validates model files on a worker thread so the render loop never
+/// blocks on file I/O. Results still need `Model::from_data` on the thread that owns the device.
+pub struct BackgroundLoader {
+    requests: Sender<(u64, PathBuf, ImportOptions)>,
+    results: Receiver<LoadedModel>,
+    textures: Receiver<(u64, StreamedTexture)>,
+    next_id: u64,
+    /// Used by `request`
+    pub options: ImportOptions,
+}
+
+impl BackgroundLoader {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = mpsc::channel::<(u64, PathBuf, ImportOptions)>();
+        let (result_sender, results) = mpsc::channel();
+        let (texture_sender, textures) = mpsc::channel();
+
+        // The worker exits once the loader (and with it the request sender) is dropped
+        thread::Builder::new()
+            .name("model-loader".to_string())
+            .spawn(move || {
+                for (id, path, options) in request_receiver {
+                    let (mut data, mut images) = match ModelData::load_streamed(&path) {
+                        Ok((data, images)) => (Ok(data), images),
+                        Err(e) => (Err(e), Default::default()),
+                    };
+                    // Atlases are packed from every texture, so those have to be decoded first
+                    if !options.stream_textures || options.atlas.is_some() {
+                        let decoded = std::mem::take(&mut images).decode_all();
+                        data = data.and_then(|mut data| {
+                            decoded?.into_iter().for_each(|texture| data.apply_texture(texture));
+                            Ok(data)
+                        });
+                    }
+                    let suspected_units = data.as_mut().ok().and_then(|data| data.convert_units(&options));
+                    let validation = data.as_mut().ok().map(|data| {
+                        if options.repair { data.repair() } else { data.validate() }
+                    });
+                    if let (Ok(data), Some(atlas)) = (data.as_mut(), options.atlas) {
+                        let report = data.pack_atlases(&atlas);
+                        log::debug!("{}: {}", path.display(), report);
+                    }
+                    if let (Ok(data), Some(merge)) = (data.as_mut(), options.merge_static) {
+                        let report = data.merge_static(&merge);
+                        log::debug!("{}: {}", path.display(), report);
+                    }
+                    if let (Ok(data), Some(compression)) = (data.as_mut(), options.compress_animation) {
+                        for report in data.compress_animation(&compression) {
+                            log::debug!("{}: {}", path.display(), report);
+                        }
+                    }
+                    let streamed_textures = images.len();
+                    let waiting_materials = images.waiting_materials();
+                    let world_units = options.world_units;
+                    let loaded = LoadedModel { id, path, data, validation, suspected_units, world_units, streamed_textures, waiting_materials };
+                    if result_sender.send(loaded).is_err() {
+                        break;
+                    }
+                    // Sent after the model, so each texture arrives once its model has
+                    images.decode(|texture| {
+                        let _ = texture_sender.send((id, texture));
+                    });
+                }
+            })
+            .expect("failed to spawn model loader thread");
+
+        Self {
+            requests,
+            results,
+            textures,
+            next_id: 0,
+            options: ImportOptions::default(),
+        }
+    }
+
+    /// Queue `path` for loading and return the id its result will carry
+    pub fn request(&mut self, path: impl Into<PathBuf>) -> u64 {
+        self.request_with(path, self.options)
+    }
+
+    /// `request` with options for this model only, e.g. its units or an import scale
+    pub fn request_with(&mut self, path: impl Into<PathBuf>, options: ImportOptions) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        // The worker only stops when we do, so the send cannot fail
+        let _ = self.requests.send((id, path.into(), options));
+        id
+    }
+
+    /// Collect all loads finished since the last call, without blocking
+    pub fn poll(&self) -> Vec<LoadedModel> {
+        self.results.try_iter().collect()
+    }
+
+    /// Collect textures decoded since the last call, with the id of their model's load. Poll
+    /// these after `poll`, which has already returned every model they belong to.
+    pub fn poll_textures(&self) -> Vec<(u64, StreamedTexture)> {
+        self.textures.try_iter().collect()
+    }
+}
+
+impl Default for BackgroundLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for(loader: &BackgroundLoader, count: usize) -> Vec<LoadedModel> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut loaded = Vec::new();
+        while loaded.len() < count && Instant::now() < deadline {
+            loaded.extend(loader.poll());
+            thread::sleep(Duration::from_millis(5));
+        }
+        loaded
+    }
+
+    #[test]
+    fn test_background_load() {
+        let models = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("models");
+        let mut loader = BackgroundLoader::new();
+        let cube = loader.request(models.join("cube.obj"));
+        let missing = loader.request(models.join("missing.glb"));
+        let options = ImportOptions { units: Some(LengthUnit::Centimeters), ..ImportOptions::default() };
+        let small_cube = loader.request_with(models.join("cube.obj"), options);
+
+        let loaded = wait_for(&loader, 3);
+        assert_eq!(loaded.len(), 3);
+
+        let cube = loaded.iter().find(|l| l.id == cube).unwrap();
+        let data = cube.data.as_ref().unwrap();
+        assert_eq!(data.meshes[0].indices.len(), 36);
+        assert_eq!(data.bounds_min, [-1.0; 3]);
+        assert!(cube.validation.as_ref().unwrap().repaired);
+        assert_eq!(cube.suspected_units, None);
+
+        let small_cube = loaded.iter().find(|l| l.id == small_cube).unwrap().data.as_ref().unwrap();
+        assert_eq!(small_cube.bounds_min, [-0.01; 3]);
+        assert_eq!(small_cube.import_scale, 0.01);
+
+        assert!(loaded.iter().find(|l| l.id == missing).unwrap().data.is_err());
+
+        // Streamed models arrive untextured, their textures right behind
+        let streamed = loader.request_with(models.join("cube.glb"), ImportOptions { stream_textures: true, ..ImportOptions::default() });
+        let loaded = wait_for(&loader, 1);
+        assert_eq!((loaded[0].id, loaded[0].streamed_textures), (streamed, 1));
+        assert!(loaded[0].data.as_ref().unwrap().materials[0].diffuse.is_none());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut textures = Vec::new();
+        while textures.is_empty() && Instant::now() < deadline {
+            textures.extend(loader.poll_textures());
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(textures.len(), 1);
+        assert_eq!(textures[0].0, streamed);
+        assert!(textures[0].1.image.is_some());
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use glam::Mat4;
+use super::{world_matrices, MeshData, ModelData, Node};
+
+/// How `ModelData::merge_static` combines meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeOptions {
+    /// Most vertices in one merged mesh; larger batches are split
+    pub max_vertices: u32,
+    /// Meshes, and nodes with everything under them, whose name contains this keep their own
+    /// draws, for parts the application moves on their own (doors, props to pick up)
+    pub keep_tag: &'static str,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self { max_vertices: 1 << 20, keep_tag: "[dynamic]" }
+    }
+}
+
+/// What `ModelData::merge_static` combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Meshes folded into merged ones
+    pub merged_meshes: usize,
+    /// Meshes they became, one or more per material
+    pub batches: usize,
+    /// Meshes left alone: tagged, or skinned
+    pub kept: usize,
+}
+
+impl fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} static meshes merged into {} batches, {} kept separate", self.merged_meshes, self.batches, self.kept)
+    }
+}
+
+impl ModelData {
+    /// Combine the static meshes that share a material into one vertex and index buffer each,
+    /// with their node transforms baked in, so scenes of many small props draw in a few calls.
+    /// Skinned meshes and those tagged with `options.keep_tag` are left as they are. Models with
+    /// nodes keep them all, with the merged meshes moved to a new root node; the tagged
+    /// subtrees can still be placed as their own objects (`ModelData::split_nodes`), while the
+    /// merged root culls as one.
+    pub fn merge_static(&mut self, options: &MergeOptions) -> MergeReport {
+        let tagged = |name: &str| !options.keep_tag.is_empty() && name.contains(options.keep_tag);
+        let movable = |mesh: &MeshData| mesh.skin.is_some() || tagged(&mesh.name);
+
+        // Meshes to merge, already in model space
+        let mut statics: Vec<MeshData> = Vec::new();
+        let mut kept: Vec<MeshData> = Vec::new();
+        if self.nodes.is_empty() {
+            for mesh in self.meshes.drain(..) {
+                if movable(&mesh) { kept.push(mesh) } else { statics.push(mesh) }
+            }
+        } else {
+            // Nodes under a tagged one move with it
+            let mut dynamic = vec![false; self.nodes.len()];
+            for (index, node) in self.nodes.iter().enumerate() {
+                dynamic[index] = tagged(&node.name) || node.parent.is_some_and(|parent| dynamic[parent]);
+            }
+            let matrices = world_matrices(&self.nodes);
+            let mut remap: Vec<Option<usize>> = vec![None; self.meshes.len()];
+            for (index, node) in self.nodes.iter_mut().enumerate() {
+                let (stay, merge): (Vec<usize>, Vec<usize>) = node.meshes.iter()
+                    .filter(|&&mesh| mesh < self.meshes.len())
+                    .partition(|&&mesh| dynamic[index] || movable(&self.meshes[mesh]));
+                statics.extend(merge.into_iter().map(|mesh| self.meshes[mesh].transformed(matrices[index])));
+                // Meshes staying in nodes are kept once, however many nodes instance them
+                node.meshes = stay.into_iter()
+                    .map(|mesh| *remap[mesh].get_or_insert_with(|| {
+                        kept.push(self.meshes[mesh].clone());
+                        kept.len() - 1
+                    }))
+                    .collect();
+            }
+        }
+
+        let mut report = MergeReport { merged_meshes: statics.len(), batches: 0, kept: kept.len() };
+        let mut by_material: BTreeMap<usize, Vec<MeshData>> = BTreeMap::new();
+        for mesh in statics {
+            by_material.entry(mesh.material_index).or_default().push(mesh);
+        }
+        let first_batch = kept.len();
+        for (material, meshes) in by_material {
+            let mut batch: Option<MeshData> = None;
+            for mesh in meshes {
+                let full = batch.as_ref()
+                    .is_some_and(|batch| batch.vertices.len() + mesh.vertices.len() > options.max_vertices as usize);
+                if full {
+                    kept.extend(batch.take());
+                }
+                let batch = batch.get_or_insert_with(|| MeshData {
+                    name: format!("static {}", material),
+                    vertices: Vec::new(),
+                    indices: Vec::new(),
+                    material_index: material,
+                    skin: None,
+                });
+                let base = batch.vertices.len() as u32;
+                batch.vertices.extend_from_slice(&mesh.vertices);
+                batch.indices.extend(mesh.indices.iter().map(|index| base + index));
+            }
+            kept.extend(batch);
+        }
+        report.batches = kept.len() - first_batch;
+
+        if !self.nodes.is_empty() && report.batches > 0 {
+            self.nodes.push(Node {
+                name: "static".to_string(),
+                parent: None,
+                transform: Mat4::IDENTITY,
+                meshes: (first_batch..kept.len()).collect(),
+            });
+        }
+        self.meshes = kept;
+        self.update_bounds();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+    use crate::model::ModelVertex;
+
+    fn triangle(name: &str, material_index: usize) -> MeshData {
+        let vertex = |x: f32, y: f32| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+        MeshData { name: name.to_string(), vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)], indices: vec![0, 1, 2], material_index, skin: None }
+    }
+
+    #[test]
+    fn test_merge_static_meshes() {
+        let mut data = ModelData::from_mesh(triangle("rock", 0));
+        data.meshes.push(triangle("bush", 1));
+        data.meshes.push(triangle("pebble", 0));
+        data.meshes.push(triangle("door [dynamic]", 0));
+        data.meshes.push(MeshData { skin: Some(vec![bytemuck::Zeroable::zeroed(); 3]), ..triangle("arm", 0) });
+
+        let report = data.merge_static(&MergeOptions::default());
+        assert_eq!(report, MergeReport { merged_meshes: 3, batches: 2, kept: 2 });
+        let names: Vec<_> = data.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+        assert_eq!(names, ["door [dynamic]", "arm", "static 0", "static 1"]);
+        assert_eq!(data.meshes[2].indices, vec![0, 1, 2, 3, 4, 5]);
+
+        // Batches split at the vertex limit
+        let mut data = ModelData::from_mesh(triangle("a", 0));
+        data.meshes.extend([triangle("b", 0), triangle("c", 0)]);
+        let report = data.merge_static(&MergeOptions { max_vertices: 6, ..MergeOptions::default() });
+        assert_eq!(report.batches, 2);
+        assert_eq!(data.meshes[1].indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_merge_static_nodes() {
+        let mut data = ModelData::from_mesh(triangle("crate", 0));
+        data.meshes.push(triangle("lid", 0));
+        let node = |name: &str, parent, offset: Vec3, meshes| Node { name: name.to_string(), parent, transform: Mat4::from_translation(offset), meshes };
+        data.nodes = vec![
+            node("crate", None, Vec3::X, vec![0]),
+            node("crate", None, Vec3::Y, vec![0]),
+            node("hinge [dynamic]", None, Vec3::Z, vec![]),
+            node("lid", Some(2), Vec3::Z, vec![1]),
+        ];
+
+        let report = data.merge_static(&MergeOptions::default());
+        assert_eq!(report, MergeReport { merged_meshes: 2, batches: 1, kept: 1 });
+        // The lid moves with its tagged parent; the crates are baked where their nodes put them
+        assert_eq!(data.nodes[3].meshes, vec![0]);
+        assert!(data.nodes[0].meshes.is_empty());
+        let merged = &data.nodes[4];
+        assert_eq!((merged.name.as_str(), merged.meshes.as_slice()), ("static", &[1][..]));
+        assert_eq!(data.meshes[1].vertices[0].position, [1.0, 0.0, 0.0]);
+        assert_eq!(data.meshes[1].vertices[3].position, [0.0, 1.0, 0.0]);
+        assert_eq!((data.bounds_min, data.bounds_max), ([0.0, 0.0, 0.0], [2.0, 2.0, 2.0]));
+    }
+}
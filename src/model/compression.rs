@@ -0,0 +1,352 @@
+use std::fmt;
+use glam::{Quat, Vec3};
+use super::{AnimationClip, Channel, Interpolation, Keyframes, ModelData, QuantizedVec3s};
+use super::skin::quantize_rotation;
+
+/// Times the tolerances are doubled to bring clips under `CompressionOptions::budget`
+const BUDGET_ROUNDS: u32 = 6;
+/// Most a rotation turns when its components are rounded to `i16`: each is off by up to half
+/// a step, which moves the quaternion by twice that and turns it by twice that again
+const ROTATION_QUANTIZATION_ERROR: f32 = 2.0 / i16::MAX as f32;
+
+/// How `AnimationClip::compress` shrinks keyframe data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionOptions {
+    /// Furthest a compressed translation may stray from the original, in the model's units
+    pub translation_tolerance: f32,
+    /// Largest angle in radians a compressed rotation may differ by
+    pub rotation_tolerance: f32,
+    /// Largest difference in a scale factor
+    pub scale_tolerance: f32,
+    /// Store the remaining keys at 16 bits a component where that stays within tolerance
+    pub quantize: bool,
+    /// Bytes all of a model's clips may take. Tolerances are doubled, up to 64 times, until
+    /// they fit; clips still over are kept as the loosest attempt left them.
+    pub budget: Option<usize>,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            translation_tolerance: 1e-4,
+            rotation_tolerance: 1e-3,
+            scale_tolerance: 1e-4,
+            quantize: true,
+            budget: None,
+        }
+    }
+}
+
+impl CompressionOptions {
+    fn loosened(&self, factor: f32) -> Self {
+        Self {
+            translation_tolerance: self.translation_tolerance * factor,
+            rotation_tolerance: self.rotation_tolerance * factor,
+            scale_tolerance: self.scale_tolerance * factor,
+            ..*self
+        }
+    }
+}
+
+/// Keys and bytes of a clip's keyframe data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClipMemory {
+    /// Key times across all channels
+    pub keys: usize,
+    /// Times, values and channel headers
+    pub bytes: usize,
+}
+
+impl fmt::Display for ClipMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} keys in {:.1} KiB", self.keys, self.bytes as f32 / 1024.0)
+    }
+}
+
+/// What `AnimationClip::compress` saved on one clip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipReport {
+    pub name: String,
+    pub before: ClipMemory,
+    pub after: ClipMemory,
+}
+
+impl fmt::Display for ClipReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = if self.before.bytes > 0 { self.after.bytes * 100 / self.before.bytes } else { 100 };
+        write!(f, "clip '{}': {} -> {} ({}%)", self.name, self.before, self.after, percent)
+    }
+}
+
+impl Channel {
+    fn memory(&self) -> ClipMemory {
+        let values = match &self.keyframes {
+            Keyframes::Translation(values) | Keyframes::Scale(values) => values.len() * std::mem::size_of::<Vec3>(),
+            Keyframes::Rotation(values) => values.len() * std::mem::size_of::<Quat>(),
+            Keyframes::QuantizedTranslation(values) | Keyframes::QuantizedScale(values) => {
+                values.values.len() * std::mem::size_of::<[u16; 3]>()
+            }
+            Keyframes::QuantizedRotation(values) => values.len() * std::mem::size_of::<[i16; 4]>(),
+        };
+        ClipMemory {
+            keys: self.times.len(),
+            bytes: std::mem::size_of::<Channel>() + self.times.len() * std::mem::size_of::<f32>() + values,
+        }
+    }
+
+    /// Drop the keys the rest reproduce within `options`' tolerance, then quantize. The two
+    /// errors add up, so when quantizing, vectors leave half their tolerance to each and
+    /// rotations leave quantization its fixed error. Cubic spline channels keep their keys,
+    /// as their tangents would need refitting, and their floats, as tangents can reach past
+    /// the values' range.
+    fn compress(&mut self, options: &CompressionOptions) {
+        let quantize = options.quantize && self.interpolation != Interpolation::CubicSpline;
+        let share = if quantize { 0.5 } else { 1.0 };
+        let (translation_tolerance, scale_tolerance) = (options.translation_tolerance * share, options.scale_tolerance * share);
+        let quantize_rotation = quantize && options.rotation_tolerance >= 2.0 * ROTATION_QUANTIZATION_ERROR;
+        let rotation_tolerance = if quantize_rotation {
+            options.rotation_tolerance - ROTATION_QUANTIZATION_ERROR
+        } else {
+            options.rotation_tolerance
+        };
+
+        let distance = |a: Vec3, b: Vec3| a.distance(b);
+        let kept = match &self.keyframes {
+            Keyframes::Translation(values) => reduce(self.interpolation, &self.times, values, translation_tolerance, Vec3::lerp, distance),
+            Keyframes::Scale(values) => reduce(self.interpolation, &self.times, values, scale_tolerance, Vec3::lerp, distance),
+            Keyframes::Rotation(values) => reduce(self.interpolation, &self.times, values, rotation_tolerance, Quat::slerp, rotation_angle),
+            // Already compressed
+            _ => return,
+        };
+        if let Some(kept) = kept {
+            self.times = kept.iter().map(|&key| self.times[key]).collect();
+            match &mut self.keyframes {
+                Keyframes::Translation(values) | Keyframes::Scale(values) => *values = kept.iter().map(|&key| values[key]).collect(),
+                Keyframes::Rotation(values) => *values = kept.iter().map(|&key| values[key]).collect(),
+                _ => {}
+            }
+        }
+
+        if !quantize {
+            return;
+        }
+        let quantized = match &self.keyframes {
+            Keyframes::Translation(values) => QuantizedVec3s::new(values, translation_tolerance).map(Keyframes::QuantizedTranslation),
+            Keyframes::Scale(values) => QuantizedVec3s::new(values, scale_tolerance).map(Keyframes::QuantizedScale),
+            Keyframes::Rotation(values) if quantize_rotation => {
+                Some(Keyframes::QuantizedRotation(values.iter().copied().map(quantize_rotation).collect()))
+            }
+            _ => None,
+        };
+        if let Some(quantized) = quantized {
+            self.keyframes = quantized;
+        }
+    }
+}
+
+/// The angle between two rotations. `Quat::angle_between` goes through `acos`, which loses
+/// most of its precision at the fractions of a degree tolerances are set in.
+fn rotation_angle(a: Quat, b: Quat) -> f32 {
+    let difference = a.conjugate() * b;
+    2.0 * difference.xyz().length().atan2(difference.w.abs())
+}
+
+/// The keys of a linear or step channel to keep so the others are reproduced within
+/// `tolerance`, `None` to keep them all. A channel that holds one value keeps only its first.
+fn reduce<T: Copy>(
+    interpolation: Interpolation,
+    times: &[f32],
+    values: &[T],
+    tolerance: f32,
+    lerp: impl Fn(T, T, f32) -> T,
+    distance: impl Fn(T, T) -> f32,
+) -> Option<Vec<usize>> {
+    if interpolation == Interpolation::CubicSpline || values.len() != times.len() || values.len() < 2 {
+        return None;
+    }
+    if values.iter().all(|&value| distance(value, values[0]) <= tolerance) {
+        return Some(vec![0]);
+    }
+    let mut kept = vec![0];
+    match interpolation {
+        Interpolation::Step => {
+            for (key, &value) in values.iter().enumerate().skip(1) {
+                if distance(value, values[*kept.last().unwrap()]) > tolerance {
+                    kept.push(key);
+                }
+            }
+        }
+        _ => {
+            // Stretch each line from the last kept key as far as it stays close to every key it skips
+            let mut anchor = 0;
+            for end in 2..values.len() {
+                let length = times[end] - times[anchor];
+                let fits = (anchor + 1..end).all(|key| {
+                    let t = if length > 0.0 { (times[key] - times[anchor]) / length } else { 0.0 };
+                    distance(lerp(values[anchor], values[end], t), values[key]) <= tolerance
+                });
+                if !fits {
+                    anchor = end - 1;
+                    kept.push(anchor);
+                }
+            }
+            kept.push(values.len() - 1);
+        }
+    }
+    (kept.len() < values.len()).then_some(kept)
+}
+
+impl AnimationClip {
+    /// Keys and bytes the clip's channels take
+    pub fn memory(&self) -> ClipMemory {
+        self.channels.iter().map(Channel::memory).fold(ClipMemory::default(), |total, channel| ClipMemory {
+            keys: total.keys + channel.keys,
+            bytes: total.bytes + channel.bytes,
+        })
+    }
+
+    /// Drop keys the others reproduce within tolerance and quantize the rest, for mocap
+    /// clips that key every joint every frame. The duration is kept, even when channels
+    /// that hold still lose their last key.
+    pub fn compress(&mut self, options: &CompressionOptions) -> ClipReport {
+        let before = self.memory();
+        for channel in &mut self.channels {
+            channel.compress(options);
+        }
+        ClipReport { name: self.name.clone(), before, after: self.memory() }
+    }
+}
+
+impl ModelData {
+    /// Compress the skeleton's clips, loosening the tolerances while they're over
+    /// `options.budget`. Translation tolerances are in world units, so they're applied after
+    /// `convert_units` as the equivalent in the file's own.
+    pub fn compress_animation(&mut self, options: &CompressionOptions) -> Vec<ClipReport> {
+        let scale = self.import_scale.abs().max(f32::EPSILON);
+        let Some(skeleton) = &mut self.skeleton else {
+            return Vec::new();
+        };
+        let options = CompressionOptions { translation_tolerance: options.translation_tolerance / scale, ..*options };
+        let original = skeleton.clips.clone();
+        let mut round = 0;
+        loop {
+            skeleton.clips = original.clone();
+            let options = options.loosened((1u32 << round) as f32);
+            let reports: Vec<ClipReport> = skeleton.clips.iter_mut().map(|clip| clip.compress(&options)).collect();
+            let total: usize = reports.iter().map(|report| report.after.bytes).sum();
+            match options.budget {
+                Some(budget) if total > budget && round < BUDGET_ROUNDS => round += 1,
+                Some(budget) if total > budget => {
+                    log::warn!("Animation clips take {} bytes, over their budget of {} even at {}x the tolerances", total, budget, 1 << round);
+                    return reports;
+                }
+                _ => return reports,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Mat4;
+    use crate::model::{NodePose, Skeleton};
+
+    /// A node sliding along x for a second then holding, keyed at 100 frames a second the way
+    /// mocap exports are, with a constant rotation channel
+    fn mocap_clip() -> AnimationClip {
+        let times: Vec<f32> = (0..=200).map(|frame| frame as f32 / 100.0).collect();
+        let positions = times.iter().map(|&time| Vec3::new(time.min(1.0), 0.0, 0.0)).collect();
+        AnimationClip::new("slide", vec![
+            Channel { node: 0, interpolation: Interpolation::Linear, times: times.clone(), keyframes: Keyframes::Translation(positions) },
+            Channel { node: 0, interpolation: Interpolation::Linear, times, keyframes: Keyframes::Rotation(vec![Quat::from_rotation_y(0.5); 201]) },
+        ])
+    }
+
+    fn pose_at(clip: &AnimationClip, time: f32) -> NodePose {
+        let mut pose = [NodePose::from_matrix(Mat4::IDENTITY)];
+        clip.sample(time, &mut pose);
+        pose[0]
+    }
+
+    #[test]
+    fn test_compress_clip() {
+        let original = mocap_clip();
+        let mut clip = original.clone();
+        let report = clip.compress(&CompressionOptions::default());
+        assert_eq!(report.before.keys, 402);
+        // The slide needs its ends and the corner; the rotation just one key
+        assert_eq!(clip.channels[0].times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(clip.channels[1].times.len(), 1);
+        assert!(matches!(clip.channels[0].keyframes, Keyframes::QuantizedTranslation(_)));
+        assert!(matches!(clip.channels[1].keyframes, Keyframes::QuantizedRotation(_)));
+        assert!(report.after.bytes * 10 < report.before.bytes, "{}", report);
+        assert_eq!(clip.duration, 2.0);
+
+        for time in [0.0, 0.333, 0.5, 1.0, 1.7, 2.5] {
+            let (expected, got) = (pose_at(&original, time), pose_at(&clip, time));
+            assert!(expected.translation.distance(got.translation) < 1e-4, "{} at {}", got.translation, time);
+            assert!(expected.rotation.angle_between(got.rotation) < 1e-3);
+        }
+
+        // Step channels keep only the keys where the value changes
+        let mut step = Channel {
+            node: 0,
+            interpolation: Interpolation::Step,
+            times: vec![0.0, 1.0, 2.0, 3.0],
+            keyframes: Keyframes::Scale(vec![Vec3::ONE, Vec3::ONE, Vec3::splat(2.0), Vec3::splat(2.0)]),
+        };
+        step.compress(&CompressionOptions { quantize: false, ..CompressionOptions::default() });
+        assert_eq!(step.times, vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn test_compress_curve_within_tolerance() {
+        // Curves keep every key's error near the tolerance, unlike a slide that's exactly linear
+        let times: Vec<f32> = (0..=200).map(|frame| frame as f32 / 100.0).collect();
+        let positions = times.iter().map(|&time| Vec3::new((time * 3.0).sin() * 2.0, time * time, 0.0)).collect();
+        let rotations = times.iter().map(|&time| Quat::from_rotation_y((time * 2.0).sin() * 2.0)).collect();
+        let original = AnimationClip::new("sway", vec![
+            Channel { node: 0, interpolation: Interpolation::Linear, times: times.clone(), keyframes: Keyframes::Translation(positions) },
+            Channel { node: 0, interpolation: Interpolation::Linear, times: times.clone(), keyframes: Keyframes::Rotation(rotations) },
+        ]);
+        let options = CompressionOptions::default();
+        let mut clip = original.clone();
+        let report = clip.compress(&options);
+        assert!(matches!(clip.channels[0].keyframes, Keyframes::QuantizedTranslation(_)));
+        assert!(matches!(clip.channels[1].keyframes, Keyframes::QuantizedRotation(_)));
+        assert!(report.after.keys < report.before.keys, "{}", report);
+
+        // Both curves are linear between the original keys, so the error peaks at one of them
+        for &time in &times {
+            let (expected, got) = (pose_at(&original, time), pose_at(&clip, time));
+            let error = expected.translation.distance(got.translation);
+            assert!(error <= options.translation_tolerance * 1.01, "{} off at {}", error, time);
+            let error = rotation_angle(expected.rotation, got.rotation);
+            assert!(error <= options.rotation_tolerance * 1.01, "{} rad off at {}", error, time);
+        }
+    }
+
+    #[test]
+    fn test_compress_to_budget() {
+        let times: Vec<f32> = (0..100).map(|frame| frame as f32 / 30.0).collect();
+        let wobble = times.iter().map(|&time| Vec3::new((time * 7.0).sin() * 0.01, 0.0, 0.0)).collect();
+        let clip = AnimationClip::new("wobble", vec![
+            Channel { node: 0, interpolation: Interpolation::Linear, times, keyframes: Keyframes::Translation(wobble) },
+        ]);
+        let mut data = ModelData::from_mesh(crate::model::MeshData {
+            name: String::new(),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            material_index: 0,
+            skin: None,
+        });
+        let unlimited = clip.clone().compress(&CompressionOptions::default());
+        data.skeleton = Some(Skeleton { nodes: Vec::new(), joints: Vec::new(), inverse_bind_matrices: Vec::new(), clips: vec![clip], root: Mat4::IDENTITY });
+
+        let budget = unlimited.after.bytes / 2;
+        let reports = data.compress_animation(&CompressionOptions { budget: Some(budget), ..CompressionOptions::default() });
+        assert!(reports[0].after.bytes <= budget, "{}", reports[0]);
+        assert_eq!(reports[0].before, unlimited.before);
+    }
+}
@@ -0,0 +1,164 @@
+use super::{Mesh, ModelVertex};
+
+/// A mesh whose geometry is rewritten from the CPU, e.g. every frame for waves, soft bodies or
+/// CSG previews. Lives in `Model::dynamic_meshes` and is drawn like any other mesh.
+///
+/// Vertices and indices are each double-buffered: an update writes the buffer that isn't being
+/// drawn and then swaps it in, so the previous frame can still read the old contents. Buffers
+/// grow when an update doesn't fit and are never shrunk.
+pub struct DynamicMesh {
+    /// Front buffers, the ones drawn
+    mesh: Mesh,
+    back_vertex_buffer: wgpu::Buffer,
+    back_index_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    bounds_min: [f32; 3],
+    bounds_max: [f32; 3],
+}
+
+impl DynamicMesh {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material_index: usize,
+    ) -> Self {
+        let vertex_size = vertex_bytes(vertices.len());
+        let index_size = index_bytes(indices.len());
+        let mut result = Self {
+            mesh: Mesh {
+                name: name.to_string(),
+                vertex_buffer: create_vertex_buffer(device, name, vertex_size),
+                index_buffer: create_index_buffer(device, name, index_size),
+                num_elements: 0,
+                material_index,
+                vertex_colors: false,
+                skin_buffer: None,
+            },
+            back_vertex_buffer: create_vertex_buffer(device, name, vertex_size),
+            back_index_buffer: create_index_buffer(device, name, index_size),
+            vertex_count: 0,
+            bounds_min: [0.0; 3],
+            bounds_max: [0.0; 3],
+        };
+        result.update_vertices(device, queue, vertices);
+        result.update_indices(device, queue, indices);
+        result
+    }
+
+    pub(super) fn set_material_index(&mut self, material_index: usize) {
+        self.mesh.material_index = material_index;
+    }
+
+    /// The buffers currently drawn
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.mesh.num_elements
+    }
+
+    /// Bounds of the vertices last uploaded
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        (self.bounds_min, self.bounds_max)
+    }
+
+    /// Replace the vertices. Indices are kept, so they must stay valid for the new vertex count.
+    pub fn update_vertices(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[ModelVertex]) {
+        let size = vertex_bytes(vertices.len());
+        if self.back_vertex_buffer.size() < size {
+            // The old buffer is freed once the frames still reading it are done
+            let size = grow(self.back_vertex_buffer.size(), size);
+            self.back_vertex_buffer = create_vertex_buffer(device, &self.mesh.name, size);
+        }
+        queue.write_buffer(&self.back_vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        std::mem::swap(&mut self.mesh.vertex_buffer, &mut self.back_vertex_buffer);
+
+        self.vertex_count = vertices.len() as u32;
+        self.mesh.vertex_colors = vertices.iter().any(|vertex| vertex.color != ModelVertex::WHITE);
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for vertex in vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(vertex.position[i]);
+                max[i] = max[i].max(vertex.position[i]);
+            }
+        }
+        (self.bounds_min, self.bounds_max) = if vertices.is_empty() { ([0.0; 3], [0.0; 3]) } else { (min, max) };
+    }
+
+    /// Replace the triangle list indices
+    pub fn update_indices(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, indices: &[u32]) {
+        let size = index_bytes(indices.len());
+        if self.back_index_buffer.size() < size {
+            let size = grow(self.back_index_buffer.size(), size);
+            self.back_index_buffer = create_index_buffer(device, &self.mesh.name, size);
+        }
+        queue.write_buffer(&self.back_index_buffer, 0, bytemuck::cast_slice(indices));
+        std::mem::swap(&mut self.mesh.index_buffer, &mut self.back_index_buffer);
+        self.mesh.num_elements = indices.len() as u32;
+    }
+
+    /// Bytes of GPU memory the front and back buffers take
+    pub fn memory_size(&self) -> u64 {
+        self.mesh.memory_size() + self.back_vertex_buffer.size() + self.back_index_buffer.size()
+    }
+
+    /// Free the GPU buffers now; the mesh must not be drawn afterwards
+    pub(crate) fn destroy(&self) {
+        self.mesh.destroy();
+        self.back_vertex_buffer.destroy();
+        self.back_index_buffer.destroy();
+    }
+
+    pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let name = &self.mesh.name;
+        Self {
+            mesh: self.mesh.clone_with_device(device, queue),
+            back_vertex_buffer: create_vertex_buffer(device, name, self.back_vertex_buffer.size()),
+            back_index_buffer: create_index_buffer(device, name, self.back_index_buffer.size()),
+            vertex_count: self.vertex_count,
+            bounds_min: self.bounds_min,
+            bounds_max: self.bounds_max,
+        }
+    }
+}
+
+// Never empty, so the buffers can always be bound
+fn vertex_bytes(count: usize) -> u64 {
+    (count.max(1) * std::mem::size_of::<ModelVertex>()) as u64
+}
+
+fn index_bytes(count: usize) -> u64 {
+    (count.max(1) * std::mem::size_of::<u32>()) as u64
+}
+
+/// Room for `needed` bytes with headroom, so meshes that grow a little each frame don't reallocate every time
+fn grow(current: u64, needed: u64) -> u64 {
+    needed.max(current * 2)
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, name: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} Dynamic Vertex Buffer", name)),
+        size,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_index_buffer(device: &wgpu::Device, name: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} Dynamic Index Buffer", name)),
+        size,
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
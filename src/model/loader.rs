@@ -1,102 +1,149 @@
+use std::ops::Range;
 use std::path::Path;
-use std::io::{BufReader, BufRead};
+use std::io::BufReader;
 use std::fs::File;
 use anyhow::Result;
+use glam::{Mat4, Quat, Vec3};
 use wgpu::util::DeviceExt;
+use crate::color::Color;
+use crate::diagnostics;
+
+use super::{AnimationClip, Channel, DeferredImages, DynamicMesh, Interpolation, Keyframes, LengthUnit, Mesh, Material, ModelVertex, Node, Skeleton, SkinVertex, StreamedTexture, TextureSlot, Texture, MAX_JOINTS};
+use super::{mapped, obj, ply};
+use super::mapped::{BufferBytes, GltfSource};
+
+/// Decoded RGBA8 image waiting for GPU upload
+#[derive(Clone)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
 
-use super::{Mesh, Material, ModelVertex, Texture};
+#[derive(Clone)]
+pub struct MaterialData {
+    pub name: String,
+    pub diffuse: Option<ImageData>,
+    pub normal: Option<ImageData>,
+    pub base_color: Color,
+    pub roughness: f32,
+    pub double_sided: bool,
+    /// Set for alpha-masked materials
+    pub alpha_cutoff: Option<f32>,
+    /// Soften the mask with alpha-to-coverage under MSAA
+    pub alpha_to_coverage: bool,
+    /// glTF `alphaMode: BLEND`
+    pub transparent: bool,
+}
 
-#[derive(Debug)]
-struct ObjData {
-    positions: Vec<[f32; 3]>,
-    tex_coords: Vec<[f32; 2]>,
-    normals: Vec<[f32; 3]>,
-    indices: Vec<u32>,
-    vertices: Vec<ModelVertex>,
+#[derive(Clone)]
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub material_index: usize,
+    /// Joints and weights per vertex for meshes bound to the model's skeleton; their vertices
+    /// are in bind space
+    pub skin: Option<Vec<SkinVertex>>,
 }
 
-impl ObjData {
-    fn new() -> Self {
+/// CPU-side model contents. Parsing needs no GPU access, so it can run on a worker thread
+/// and be uploaded later with `Model::from_data`.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    /// The file's node hierarchy, with meshes in their nodes' local space; empty when the
+    /// meshes are already placed, as `load` leaves them. See `load_hierarchy`.
+    pub nodes: Vec<Node>,
+    /// Joints and animation clips of the skinned meshes, if any
+    pub skeleton: Option<Skeleton>,
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    /// Units the file declares its lengths in, if any
+    pub units: Option<LengthUnit>,
+    /// Scale applied since loading, e.g. by `convert_units`
+    pub import_scale: f32,
+}
+
+impl ModelData {
+    /// Whether `path` has an extension `load` understands
+    pub fn is_supported(path: &Path) -> bool {
+        matches!(
+            path.extension()
+                .and_then(std::ffi::OsStr::to_str)
+                .map(|ext| ext.to_lowercase())
+                .as_deref(),
+            Some("glb" | "gltf" | "obj" | "ply")
+        )
+    }
+
+    /// A single-mesh model with the default material, e.g. generated geometry
+    pub fn from_mesh(mut mesh: MeshData) -> Self {
+        mesh.material_index = 0;
+        let (bounds_min, bounds_max) = Self::calculate_bounds(&mesh.vertices);
         Self {
-            positions: Vec::new(),
-            tex_coords: Vec::new(),
-            normals: Vec::new(),
-            indices: Vec::new(),
-            vertices: Vec::new(),
+            meshes: vec![mesh],
+            materials: vec![MaterialData::default_material()],
+            nodes: Vec::new(),
+            skeleton: None,
+            bounds_min,
+            bounds_max,
+            units: None,
+            import_scale: 1.0,
         }
     }
 
-    fn process_face(&mut self, face_tokens: &[&str]) -> Result<()> {
-        let mut vertex_indices = Vec::new();
-
-        // Process each vertex in the face
-        for vertex_str in face_tokens {
-            let indices: Vec<&str> = vertex_str.split('/').collect();
-            
-            // OBJ indices are 1-based
-            let position_idx = indices.get(0)
-                .and_then(|s| s.parse::<i32>().ok())
-                .map(|i| if i < 0 { self.positions.len() as i32 + i } else { i - 1 })
-                .ok_or_else(|| anyhow::anyhow!("Invalid position index"))?;
-
-            let tex_coord_idx = indices.get(1)
-                .and_then(|s| if s.is_empty() { None } else { s.parse::<i32>().ok() })
-                .map(|i| if i < 0 { self.tex_coords.len() as i32 + i } else { i - 1 })
-                .unwrap_or(0);
-
-            let normal_idx = indices.get(2)
-                .and_then(|s| s.parse::<i32>().ok())
-                .map(|i| if i < 0 { self.normals.len() as i32 + i } else { i - 1 })
-                .unwrap_or(0);
-
-            // Create vertex with default tangent
-            let vertex = ModelVertex {
-                position: self.positions[position_idx as usize],
-                tex_coords: if tex_coord_idx >= 0 { self.tex_coords[tex_coord_idx as usize] } else { [0.0, 0.0] },
-                normal: if normal_idx >= 0 { self.normals[normal_idx as usize] } else { [0.0, 1.0, 0.0] },
-                tangent: [1.0, 0.0, 0.0, 1.0], // Default tangent along X axis
-            };
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (mut data, images) = Self::load_streamed(path)?;
+        for texture in images.decode_all()? {
+            data.apply_texture(texture);
+        }
+        Ok(data)
+    }
 
-            // Check if we've seen this vertex before
-            let vertex_idx = self.vertices.iter().position(|v| {
-                v.position == vertex.position && 
-                v.tex_coords == vertex.tex_coords && 
-                v.normal == vertex.normal
-            });
+    /// `load` without decoding textures: materials come back untextured, with their images left
+    /// to decode from `DeferredImages`, so the geometry can be shown while they stream in
+    pub fn load_streamed<P: AsRef<Path>>(path: P) -> Result<(Self, DeferredImages)> {
+        let path = path.as_ref();
+        let extension = path.extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or("");
 
-            let vertex_idx = match vertex_idx {
-                Some(idx) => idx as u32,
-                None => {
-                    let idx = self.vertices.len() as u32;
-                    self.vertices.push(vertex);
-                    idx
+        match extension.to_lowercase().as_str() {
+            "glb" | "gltf" => {
+                let (mut data, images) = Self::load_gltf(path)?;
+                data.flatten_nodes();
+                if data.meshes.is_empty() {
+                    return Err(anyhow::anyhow!("No meshes in the GLTF scene"));
                 }
-            };
-
-            vertex_indices.push(vertex_idx);
+                Ok((data, images))
+            }
+            "obj" => Ok((Self::load_obj(path)?, DeferredImages::default())),
+            "ply" => Ok((Self::load_ply(path)?, DeferredImages::default())),
+            _ => Err(anyhow::anyhow!("Unsupported model format: {}", extension))
         }
+    }
 
-        // Triangulate the face (assuming it's convex)
-        for i in 1..(vertex_indices.len() - 1) {
-            self.indices.push(vertex_indices[0]);
-            self.indices.push(vertex_indices[i]);
-            self.indices.push(vertex_indices[i + 1]);
+    /// `load` keeping the node hierarchy of glTF files in `nodes`, with each mesh stored once
+    /// however many nodes instance it, e.g. for `Scene::add_node_hierarchy`. Other formats have
+    /// no hierarchy and load as with `load`.
+    pub fn load_hierarchy<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let is_gltf = path.extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "glb" | "gltf"));
+        if !is_gltf {
+            return Self::load(path);
         }
-
-        Ok(())
+        let (mut data, images) = Self::load_gltf(path)?;
+        for texture in images.decode_all()? {
+            data.apply_texture(texture);
+        }
+        Ok(data)
     }
-}
 
-pub struct Model {
-    pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
-    pub bounds_min: [f32; 3],
-    pub bounds_max: [f32; 3],
-}
-
-impl Model {
     // Calculate the bounding box for a set of vertices
-    fn calculate_bounds(vertices: &[ModelVertex]) -> ([f32; 3], [f32; 3]) {
+    pub(crate) fn calculate_bounds(vertices: &[ModelVertex]) -> ([f32; 3], [f32; 3]) {
         let mut min = [f32::INFINITY; 3];
         let mut max = [f32::NEG_INFINITY; 3];
 
@@ -110,185 +157,99 @@ impl Model {
         (min, max)
     }
 
-    pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue, material_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
-        Self {
-            meshes: self.meshes.iter().map(|mesh| mesh.clone_with_device(device, queue)).collect(),
-            materials: self.materials.iter().map(|material| material.clone_with_device(device, queue, material_bind_group_layout)).collect(),
-            bounds_min: self.bounds_min,
-            bounds_max: self.bounds_max,
-        }
-    }
-
-    pub fn load<P: AsRef<Path>>(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        path: P,
-        material_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self> {
-        let path = path.as_ref();
-        let extension = path.extension()
-            .and_then(std::ffi::OsStr::to_str)
-            .unwrap_or("");
-
-        match extension.to_lowercase().as_str() {
-            "glb" | "gltf" => Self::load_gltf(device, queue, path, material_bind_group_layout),
-            "obj" => Self::load_obj(device, queue, path, material_bind_group_layout),
-            _ => Err(anyhow::anyhow!("Unsupported model format: {}", extension))
-        }
-    }
-
-    fn load_gltf(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        path: &Path,
-        material_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self> {
-        let (document, buffers, images) = gltf::import(path)?;
+    /// Vertices and indices are read out of the memory-mapped file into their final arrays in
+    /// one pass, and images are left in it until they're decoded, so importing a large GLB
+    /// takes little more memory than the meshes it holds
+    fn load_gltf(path: &Path) -> Result<(Self, DeferredImages)> {
+        let GltfSource { document, buffers } = GltfSource::open(path)?;
+        let base = path.parent();
 
         let mut meshes = Vec::new();
         let mut materials = Vec::new();
-        // Track overall bounds of the model
-        let mut overall_min = [f32::INFINITY; 3];
-        let mut overall_max = [f32::NEG_INFINITY; 3];
+        // Images are decoded afterwards, in parallel, since decoding dominates the load time
+        let mut textures = Vec::new();
 
         // Load materials first
         for material in document.materials() {
             let pbr = material.pbr_metallic_roughness();
-            
-            // Try to load the base color texture
-            let mut diffuse_texture = None;
             if let Some(info) = pbr.base_color_texture() {
-                let texture = info.texture();
-                let source = texture.source().index();
-                if let Ok(texture) = Texture::from_gltf_image(
-                    device,
-                    queue,
-                    &images[source],
-                    Some(&format!("texture_{}", source))
-                ) {
-                    diffuse_texture = Some(texture);
-                }
+                textures.push((materials.len(), TextureSlot::Diffuse, info.texture().source().index()));
             }
-
-            // Try to load the normal map
-            let mut normal_texture = None;
             if let Some(normal) = material.normal_texture() {
-                let texture = normal.texture();
-                let source = texture.source().index();
-                if let Ok(texture) = Texture::from_gltf_image(
-                    device,
-                    queue,
-                    &images[source],
-                    Some(&format!("normal_{}", source))
-                ) {
-                    normal_texture = Some(texture);
-                }
+                textures.push((materials.len(), TextureSlot::Normal, normal.texture().source().index()));
             }
 
-            let mut material = Material {
+            materials.push(MaterialData {
                 name: material.name().unwrap_or("").to_string(),
-                diffuse_texture,
-                normal_texture,
-                bind_group: None,
-            };
-
-            // Create bind group if we have textures
-            material.create_bind_group(device, material_bind_group_layout);
-            materials.push(material);
+                diffuse: None,
+                normal: None,
+                // glTF factors are linear already
+                base_color: Color::from(glam::Vec4::from(pbr.base_color_factor())),
+                roughness: pbr.roughness_factor(),
+                double_sided: material.double_sided(),
+                alpha_cutoff: (material.alpha_mode() == gltf::material::AlphaMode::Mask)
+                    .then(|| material.alpha_cutoff().unwrap_or(0.5)),
+                alpha_to_coverage: false,
+                transparent: material.alpha_mode() == gltf::material::AlphaMode::Blend,
+            });
         }
 
         // Ensure we have at least one material
         if materials.is_empty() {
-            materials.push(Material {
-                name: "default".to_string(),
-                diffuse_texture: None,
-                normal_texture: None,
-                bind_group: None,
-            });
+            materials.push(MaterialData::default_material());
         }
 
-        // Process meshes
+        // Process meshes, remembering which primitives each glTF mesh became for its nodes
+        let mut mesh_ranges = Vec::new();
         for mesh in document.meshes() {
+            let first = meshes.len();
             for primitive in mesh.primitives() {
                 let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                
-                // Get vertex positions
-                let positions: Vec<[f32; 3]> = reader
+
+                // Attributes other than positions are optional, with defaults for the missing
+                let positions = reader
                     .read_positions()
-                    .ok_or_else(|| anyhow::anyhow!("No position data"))?
-                    .collect();
-
-                // Get vertex normals (or generate default)
-                let normals: Vec<[f32; 3]> = reader
-                    .read_normals()
-                    .map(|iter| iter.collect())
-                    .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
-
-                // Get texture coordinates (or generate default)
-                let tex_coords: Vec<[f32; 2]> = reader
-                    .read_tex_coords(0)
-                    .map(|iter| iter.into_f32().collect())
-                    .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
-
-                // Get tangents (or generate default)
-                let tangents: Vec<[f32; 4]> = reader
-                    .read_tangents()
-                    .map(|iter| iter.collect())
-                    .unwrap_or_else(|| {
-                        // Generate default tangents (this is a simplified version)
-                        positions.iter().map(|_| [1.0, 0.0, 0.0, 1.0]).collect()
+                    .ok_or_else(|| anyhow::anyhow!("No position data"))?;
+                let mut tex_coords = reader.read_tex_coords(0).map(|iter| iter.into_f32());
+                let mut normals = reader.read_normals();
+                let mut tangents = reader.read_tangents();
+                let mut colors = reader.read_colors(0).map(|iter| iter.into_rgba_f32());
+                let mut vertices = Vec::with_capacity(positions.len());
+                for position in positions {
+                    vertices.push(ModelVertex {
+                        position,
+                        tex_coords: tex_coords.as_mut().and_then(Iterator::next).unwrap_or([0.0, 0.0]),
+                        normal: normals.as_mut().and_then(Iterator::next).unwrap_or([0.0, 1.0, 0.0]),
+                        tangent: tangents.as_mut().and_then(Iterator::next).unwrap_or([1.0, 0.0, 0.0, 1.0]),
+                        color: colors.as_mut().and_then(Iterator::next).unwrap_or(ModelVertex::WHITE),
                     });
-
-                // Get indices
-                let indices: Vec<u32> = reader
-                    .read_indices()
-                    .map(|iter| iter.into_u32().collect())
-                    .ok_or_else(|| anyhow::anyhow!("No index data"))?;
-
-                // Create vertices
-                let vertices: Vec<ModelVertex> = positions
-                    .iter()
-                    .zip(tex_coords.iter())
-                    .zip(normals.iter())
-                    .zip(tangents.iter())
-                    .map(|(((pos, tex), norm), tan)| ModelVertex {
-                        position: *pos,
-                        tex_coords: *tex,
-                        normal: *norm,
-                        tangent: *tan,
-                    })
-                    .collect();
-
-                // Update the model's bounding box
-                let (mesh_min, mesh_max) = Self::calculate_bounds(&vertices);
-                for i in 0..3 {
-                    overall_min[i] = overall_min[i].min(mesh_min[i]);
-                    overall_max[i] = overall_max[i].max(mesh_max[i]);
                 }
 
-                // Create vertex buffer
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Mesh Vertex Buffer"),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
-                });
+                // Joints and weights of skinned primitives, bound once the nodes are known
+                let skin: Option<Vec<SkinVertex>> = reader.read_joints(0)
+                    .zip(reader.read_weights(0))
+                    .map(|(joints, weights)| {
+                        joints.into_u16().zip(weights.into_f32())
+                            .map(|(joints, weights)| SkinVertex { joints: joints.map(u32::from), weights })
+                            .collect()
+                    })
+                    .filter(|skin: &Vec<SkinVertex>| skin.len() == vertices.len());
 
-                // Create index buffer
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Mesh Index Buffer"),
-                    contents: bytemuck::cast_slice(&indices),
-                    usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
-                });
+                // Tightly packed u32 indices are copied from the file as they are
+                let indices: Vec<u32> = primitive.indices()
+                    .and_then(|accessor| mapped::packed_indices(&accessor, &buffers))
+                    .or_else(|| reader.read_indices().map(|iter| iter.into_u32().collect()))
+                    .ok_or_else(|| anyhow::anyhow!("No index data"))?;
 
-                // Create mesh
-                meshes.push(Mesh {
+                meshes.push(MeshData {
                     name: mesh.name().unwrap_or("").to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: indices.len() as u32,
+                    vertices,
+                    indices,
                     material_index: primitive.material().index().unwrap_or(0),
+                    skin,
                 });
             }
+            mesh_ranges.push(first..meshes.len());
         }
 
         // If no meshes were found, return an error
@@ -296,112 +257,416 @@ impl Model {
             return Err(anyhow::anyhow!("No meshes found in GLTF file"));
         }
 
-        Ok(Self {
+        // Files without a scene have nothing placing their meshes, so they stay as they are
+        let (nodes, node_indices) = document.default_scene()
+            .or_else(|| document.scenes().next())
+            .map(|scene| gltf_nodes(scene, &mesh_ranges, document.nodes().len()))
+            .unwrap_or_default();
+        let skeleton = gltf_skeleton(&document, &buffers, &nodes, &node_indices, &mut meshes, &mesh_ranges);
+
+        // glTF lengths are meters
+        let mut data = Self {
             meshes,
             materials,
-            bounds_min: overall_min,
-            bounds_max: overall_max,
-        })
+            nodes,
+            skeleton,
+            bounds_min: [0.0; 3],
+            bounds_max: [0.0; 3],
+            units: Some(LengthUnit::Meters),
+            import_scale: 1.0,
+        };
+        data.update_bounds();
+        let base = base.map(Path::to_path_buf);
+        Ok((data, DeferredImages::gltf(document, buffers, base, textures)))
     }
 
-    fn load_obj(
-        device: &wgpu::Device,
-        _queue: &wgpu::Queue,
-        path: &Path,
-        material_bind_group_layout: &wgpu::BindGroupLayout,
-    ) -> Result<Self> {
-        let mut obj_data = ObjData::new();
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-
-        // Parse OBJ file
-        for line in reader.lines() {
-            let line = line?;
-            let tokens: Vec<&str> = line.split_whitespace().collect();
-            if tokens.is_empty() {
-                continue;
-            }
-
-            match tokens[0] {
-                "v" => {
-                    if tokens.len() < 4 {
-                        continue;
-                    }
-                    let x = tokens[1].parse::<f32>()?;
-                    let y = tokens[2].parse::<f32>()?;
-                    let z = tokens[3].parse::<f32>()?;
-                    obj_data.positions.push([x, y, z]);
-                }
-                "vt" => {
-                    if tokens.len() < 3 {
-                        continue;
-                    }
-                    let u = tokens[1].parse::<f32>()?;
-                    let v = tokens[2].parse::<f32>()?;
-                    obj_data.tex_coords.push([u, v]);
-                }
-                "vn" => {
-                    if tokens.len() < 4 {
-                        continue;
-                    }
-                    let x = tokens[1].parse::<f32>()?;
-                    let y = tokens[2].parse::<f32>()?;
-                    let z = tokens[3].parse::<f32>()?;
-                    obj_data.normals.push([x, y, z]);
-                }
-                "f" => {
-                    if tokens.len() < 4 {
-                        continue;
-                    }
-                    obj_data.process_face(&tokens[1..])?;
-                }
-                _ => {}
-            }
-        }
+    fn load_obj(path: &Path) -> Result<Self> {
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let obj = obj::parse(BufReader::new(File::open(path)?), name)?;
 
         // Calculate model bounds
-        let (overall_min, overall_max) = Self::calculate_bounds(&obj_data.vertices);
+        let vertices: Vec<_> = obj.meshes.iter().flat_map(|mesh| mesh.vertices.iter().copied()).collect();
+        let (overall_min, overall_max) = Self::calculate_bounds(&vertices);
 
-        // Create vertex buffer
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Mesh Vertex Buffer"),
-            contents: bytemuck::cast_slice(&obj_data.vertices),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
-        });
+        Ok(Self {
+            meshes: obj.meshes,
+            materials: vec![MaterialData::default_material()],
+            nodes: Vec::new(),
+            skeleton: None,
+            bounds_min: overall_min,
+            bounds_max: overall_max,
+            units: obj.units,
+            import_scale: 1.0,
+        })
+    }
 
-        // Create index buffer
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Mesh Index Buffer"),
-            contents: bytemuck::cast_slice(&obj_data.indices),
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
-        });
+    fn load_ply(path: &Path) -> Result<Self> {
+        let mesh = ply::parse(BufReader::new(File::open(path)?))?;
+        if mesh.indices.is_empty() {
+            return Err(anyhow::anyhow!("No faces found in PLY file"));
+        }
 
-        // Create mesh
-        let mesh = Mesh {
+        // Scans rarely carry normals, so smooth ones are generated from the faces
+        let normals = mesh.normals.unwrap_or_else(|| smooth_normals(&mesh.positions, &mesh.indices));
+        let vertices: Vec<ModelVertex> = mesh.positions.iter().enumerate()
+            .map(|(i, position)| ModelVertex {
+                position: *position,
+                tex_coords: mesh.tex_coords.as_ref().map_or([0.0, 0.0], |tex_coords| tex_coords[i]),
+                normal: normals[i],
+                tangent: [1.0, 0.0, 0.0, 1.0],
+                color: mesh.colors.as_ref().map_or(ModelVertex::WHITE, |colors| colors[i]),
+            })
+            .collect();
+
+        let (overall_min, overall_max) = Self::calculate_bounds(&vertices);
+        let mesh = MeshData {
             name: path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_string(),
-            vertex_buffer,
-            index_buffer,
-            num_elements: obj_data.indices.len() as u32,
+            vertices,
+            indices: mesh.indices,
             material_index: 0,
+            skin: None,
         };
 
-        // Create default material
-        let mut material = Material {
-            name: "default".to_string(),
-            diffuse_texture: None,
-            normal_texture: None,
-            bind_group: None,
-        };
-
-        // Create bind group
-        material.create_bind_group(device, material_bind_group_layout);
-
         Ok(Self {
             meshes: vec![mesh],
-            materials: vec![material],
+            materials: vec![MaterialData::default_material()],
+            nodes: Vec::new(),
+            skeleton: None,
             bounds_min: overall_min,
             bounds_max: overall_max,
+            units: None,
+            import_scale: 1.0,
+        })
+    }
+}
+
+/// The nodes of `scene`, parents before their children, with glTF mesh indices resolved
+/// through `mesh_ranges` to the primitives they were loaded as; also returns the position of
+/// each of the document's `node_count` nodes in that list, `None` for nodes outside the scene
+fn gltf_nodes(scene: gltf::Scene, mesh_ranges: &[Range<usize>], node_count: usize) -> (Vec<Node>, Vec<Option<usize>>) {
+    let mut nodes = Vec::new();
+    let mut indices = vec![None; node_count];
+    let mut stack: Vec<(gltf::Node, Option<usize>)> = scene.nodes().map(|node| (node, None)).collect();
+    stack.reverse();
+    while let Some((node, parent)) = stack.pop() {
+        let index = nodes.len();
+        if let Some(slot) = indices.get_mut(node.index()) {
+            *slot = Some(index);
+        }
+        nodes.push(Node {
+            name: node.name().unwrap_or("").to_string(),
+            parent,
+            transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+            meshes: node.mesh()
+                .and_then(|mesh| mesh_ranges.get(mesh.index()))
+                .map_or_else(Vec::new, |range| range.clone().collect()),
+        });
+        stack.extend(node.children().collect::<Vec<_>>().into_iter().rev().map(|child| (child, Some(index))));
+    }
+    (nodes, indices)
+}
+
+/// The skeleton of the first skin a node of the scene uses, with the document's animations.
+/// Only meshes bound to that skin keep their joints and weights; the others draw rigidly.
+fn gltf_skeleton(
+    document: &gltf::Document,
+    buffers: &[BufferBytes],
+    nodes: &[Node],
+    node_indices: &[Option<usize>],
+    meshes: &mut [MeshData],
+    mesh_ranges: &[Range<usize>],
+) -> Option<Skeleton> {
+    // (skin, glTF mesh) of every skinned node in the scene
+    let skinned: Vec<(usize, usize)> = document.nodes()
+        .filter(|node| node_indices.get(node.index()).is_some_and(Option::is_some))
+        .filter_map(|node| Some((node.skin()?.index(), node.mesh()?.index())))
+        .collect();
+    let first = skinned.first().map(|&(skin, _)| skin);
+    if skinned.iter().any(|&(skin, _)| Some(skin) != first) {
+        log::warn!("Only the first skin of a glTF scene is animated; meshes bound to others draw rigidly");
+    }
+
+    let skeleton = first.and_then(|skin| document.skins().nth(skin)).and_then(|skin| {
+        let joints: Vec<usize> = skin.joints()
+            .filter_map(|joint| node_indices.get(joint.index()).copied().flatten())
+            .collect();
+        if joints.len() != skin.joints().count() || joints.len() > MAX_JOINTS {
+            log::warn!("Skin '{}' has joints outside the scene or more than {} joints; drawing it rigidly", skin.name().unwrap_or(""), MAX_JOINTS);
+            return None;
+        }
+        let inverse_bind_matrices = skin.reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(|matrix| Mat4::from_cols_array_2d(&matrix)).collect())
+            .unwrap_or_else(|| vec![Mat4::IDENTITY; joints.len()]);
+        let clips = document.animations()
+            .map(|animation| gltf_clip(animation, buffers, node_indices))
+            .collect();
+        Some(Skeleton { nodes: nodes.to_vec(), joints, inverse_bind_matrices, clips, root: Mat4::IDENTITY })
+    });
+
+    let bound: Vec<usize> = match &skeleton {
+        Some(_) => skinned.iter()
+            .filter(|&&(skin, _)| Some(skin) == first)
+            .flat_map(|&(_, mesh)| mesh_ranges.get(mesh).cloned().unwrap_or_default())
+            .collect(),
+        None => Vec::new(),
+    };
+    for (index, mesh) in meshes.iter_mut().enumerate() {
+        if !bound.contains(&index) {
+            mesh.skin = None;
+        }
+    }
+    skeleton
+}
+
+/// A glTF animation's channels targeting nodes of the scene. Morph target weights are skipped.
+fn gltf_clip(animation: gltf::Animation, buffers: &[BufferBytes], node_indices: &[Option<usize>]) -> AnimationClip {
+    use gltf::animation::util::ReadOutputs;
+    let channels = animation.channels()
+        .filter_map(|channel| {
+            let node = node_indices.get(channel.target().node().index()).copied().flatten()?;
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader.read_inputs()?.collect();
+            let keyframes = match reader.read_outputs()? {
+                ReadOutputs::Translations(values) => Keyframes::Translation(values.map(Vec3::from).collect()),
+                ReadOutputs::Rotations(values) => Keyframes::Rotation(values.into_f32().map(Quat::from_array).collect()),
+                ReadOutputs::Scales(values) => Keyframes::Scale(values.map(Vec3::from).collect()),
+                ReadOutputs::MorphTargetWeights(_) => return None,
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+            Some(Channel { node, interpolation, times, keyframes })
         })
+        .collect();
+    AnimationClip::new(animation.name().unwrap_or(""), channels)
+}
+
+/// Area-weighted vertex normals of an indexed triangle list
+fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![glam::Vec3::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| glam::Vec3::from(positions[index as usize]));
+        // The cross product's length is twice the triangle's area
+        let normal = (b - a).cross(c - a);
+        for index in triangle {
+            normals[*index as usize] += normal;
+        }
+    }
+    normals.into_iter()
+        .map(|normal| normal.try_normalize().unwrap_or(glam::Vec3::Y).to_array())
+        .collect()
+}
+
+impl MaterialData {
+    fn default_material() -> Self {
+        Self {
+            name: "default".to_string(),
+            diffuse: None,
+            normal: None,
+            base_color: Color::WHITE,
+            roughness: 1.0,
+            double_sided: false,
+            alpha_cutoff: None,
+            alpha_to_coverage: false,
+            transparent: false,
+        }
+    }
+}
+
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    /// Meshes whose geometry the application rewrites, drawn after `meshes`
+    pub dynamic_meshes: Vec<DynamicMesh>,
+    pub materials: Vec<Material>,
+    /// Joints and clips moving the skinned meshes; see `Scene::play_animation`
+    pub skeleton: Option<Skeleton>,
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+}
+
+impl Model {
+    /// Free every mesh and material resource now; the model must not be drawn afterwards
+    pub(crate) fn destroy(&self) {
+        for mesh in &self.meshes {
+            mesh.destroy();
+        }
+        for mesh in &self.dynamic_meshes {
+            mesh.destroy();
+        }
+        for material in &self.materials {
+            material.destroy();
+        }
+    }
+
+    /// Bytes of GPU memory the meshes and materials take, e.g. for streaming budgets
+    pub fn memory_size(&self) -> u64 {
+        self.meshes.iter().map(Mesh::memory_size).sum::<u64>()
+            + self.dynamic_meshes.iter().map(DynamicMesh::memory_size).sum::<u64>()
+            + self.materials.iter().map(Material::memory_size).sum::<u64>()
+    }
+
+    pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue, material_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            meshes: self.meshes.iter().map(|mesh| mesh.clone_with_device(device, queue)).collect(),
+            dynamic_meshes: self.dynamic_meshes.iter().map(|mesh| mesh.clone_with_device(device, queue)).collect(),
+            materials: self.materials.iter().map(|material| material.clone_with_device(device, queue, material_bind_group_layout)).collect(),
+            skeleton: self.skeleton.clone(),
+            bounds_min: self.bounds_min,
+            bounds_max: self.bounds_max,
+        }
+    }
+
+    /// A model drawing a single dynamic mesh with `material`
+    pub fn from_dynamic(mut mesh: DynamicMesh, material: Material) -> Self {
+        mesh.set_material_index(0);
+        let (bounds_min, bounds_max) = mesh.bounds();
+        Self {
+            meshes: Vec::new(),
+            dynamic_meshes: vec![mesh],
+            materials: vec![material],
+            skeleton: None,
+            bounds_min,
+            bounds_max,
+        }
+    }
+
+    /// Every mesh to draw, static meshes first
+    pub fn all_meshes(&self) -> impl Iterator<Item = &Mesh> {
+        self.meshes.iter().chain(self.dynamic_meshes.iter().map(DynamicMesh::mesh))
+    }
+
+    /// Grow the bounds to cover the dynamic meshes' current geometry. Bounds never shrink, so
+    /// culling stays conservative for meshes that move back and forth.
+    pub fn update_bounds(&mut self) {
+        for mesh in &self.dynamic_meshes {
+            let (min, max) = mesh.bounds();
+            for i in 0..3 {
+                self.bounds_min[i] = self.bounds_min[i].min(min[i]);
+                self.bounds_max[i] = self.bounds_max[i].max(max[i]);
+            }
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: P,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self> {
+        let data = ModelData::load(path)?;
+        Ok(Self::from_data(device, queue, &data, material_bind_group_layout))
+    }
+
+    /// Upload parsed model data to the GPU
+    pub fn from_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &ModelData,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::from_data_streamed(device, queue, data, material_bind_group_layout, &[])
+    }
+
+    /// `from_data` for a model whose textures are still streaming in: the `waiting` materials
+    /// show a placeholder until `apply_streamed_texture` swaps the real texture in
+    pub fn from_data_streamed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &ModelData,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        waiting: &[usize],
+    ) -> Self {
+        let materials = data.materials.iter().enumerate().map(|(i, material)| {
+            let upload = |image: &ImageData, label: String| {
+                Texture::from_rgba(device, queue, image.width, image.height, &image.pixels, Some(&label))
+            };
+            // Untextured materials sample a white texel so they still get a bind group
+            let diffuse_texture = match &material.diffuse {
+                Some(image) => upload(image, format!("texture_{}", i)),
+                None if waiting.contains(&i) => Texture::placeholder(device, queue),
+                None => Texture::white(device, queue),
+            };
+            let normal_texture = material.normal.as_ref().map(|image| upload(image, format!("normal_{}", i)));
+
+            let mut result = Material::new(&material.name, Some(diffuse_texture), normal_texture);
+            result.base_color = material.base_color;
+            result.roughness = material.roughness;
+            result.double_sided = material.double_sided;
+            result.alpha_cutoff = material.alpha_cutoff;
+            result.alpha_to_coverage = material.alpha_to_coverage;
+            result.transparent = material.transparent;
+            diagnostics::scoped(device, &format!("material '{}'", material.name), || {
+                result.create_bind_group(device, material_bind_group_layout)
+            });
+            result
+        }).collect();
+
+        let meshes = data.meshes.iter().map(|mesh| {
+            // Create vertex buffer
+            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Mesh Vertex Buffer"),
+                contents: bytemuck::cast_slice(&mesh.vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+            });
+
+            // Create index buffer
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Mesh Index Buffer"),
+                contents: bytemuck::cast_slice(&mesh.indices),
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
+            });
+
+            // Joints and weights go in a second stream, so rigid meshes don't carry them
+            let skin_buffer = mesh.skin.as_ref().filter(|_| data.skeleton.is_some()).map(|skin| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Mesh Skin Buffer"),
+                    contents: bytemuck::cast_slice(skin),
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+                })
+            });
+
+            Mesh {
+                name: mesh.name.clone(),
+                vertex_buffer,
+                index_buffer,
+                num_elements: mesh.indices.len() as u32,
+                material_index: mesh.material_index,
+                vertex_colors: mesh.vertices.iter().any(|vertex| vertex.color != ModelVertex::WHITE),
+                skin_buffer,
+            }
+        }).collect();
+
+        Self {
+            meshes,
+            dynamic_meshes: Vec::new(),
+            materials,
+            skeleton: data.skeleton.clone(),
+            bounds_min: data.bounds_min,
+            bounds_max: data.bounds_max,
+        }
+    }
+
+    /// Swap a texture that finished decoding into its material, rebuilding the bind group
+    pub fn apply_streamed_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
+        texture: &StreamedTexture,
+    ) {
+        let Some(material) = self.materials.get_mut(texture.material) else {
+            return;
+        };
+        let label = match texture.slot {
+            TextureSlot::Diffuse => format!("texture_{}", texture.material),
+            TextureSlot::Normal => format!("normal_{}", texture.material),
+        };
+        let uploaded = texture.image.as_ref()
+            .map(|image| Texture::from_rgba(device, queue, image.width, image.height, &image.pixels, Some(&label)));
+        material.set_texture(device, queue, material_bind_group_layout, texture.slot, uploaded);
     }
 
     pub fn extract_glb_textures(
@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use base64::Engine;
+use memmap2::Mmap;
+
+/// A file's contents, mapped where the platform allows it
+enum Backing {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for Backing {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// The bytes of a glTF buffer. A GLB's binary chunk and external `.bin` files stay in their
+/// memory-mapped file and are paged in as accessors and images read them, so a scan of a few
+/// hundred MB isn't copied to the heap before its vertices are built. Cheap to clone.
+#[derive(Clone)]
+pub(crate) struct BufferBytes {
+    backing: Arc<Backing>,
+    range: Range<usize>,
+}
+
+impl BufferBytes {
+    /// Map a whole file, reading it instead where mapping isn't supported
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: the mapping is only read. Should another process truncate the file while it's
+        // mapped, reads past the new end fault, the caveat of every memory-mapped loader.
+        let backing = match unsafe { Mmap::map(&file) } {
+            Ok(map) => Backing::Mapped(map),
+            Err(e) => {
+                log::debug!("Reading {} instead of mapping it: {}", path.display(), e);
+                Backing::Owned(std::fs::read(path)?)
+            }
+        };
+        Ok(Self::from_backing(backing))
+    }
+
+    pub fn owned(bytes: Vec<u8>) -> Self {
+        Self::from_backing(Backing::Owned(bytes))
+    }
+
+    fn from_backing(backing: Backing) -> Self {
+        let range = 0..backing.len();
+        Self { backing: Arc::new(backing), range }
+    }
+
+    /// The part of these bytes that `part` borrows, sharing the mapping; a copy of `part` if
+    /// it borrows something else
+    fn within(&self, part: &[u8]) -> Self {
+        let start = (part.as_ptr() as usize).wrapping_sub(self.as_ptr() as usize);
+        match start.checked_add(part.len()) {
+            Some(end) if end <= self.len() => Self {
+                backing: self.backing.clone(),
+                range: self.range.start + start..self.range.start + end,
+            },
+            _ => Self::owned(part.to_vec()),
+        }
+    }
+}
+
+impl Deref for BufferBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.backing[self.range.clone()]
+    }
+}
+
+/// A glTF document with the bytes of each of its buffers, read without copying the file
+pub(crate) struct GltfSource {
+    pub document: gltf::Document,
+    pub buffers: Vec<BufferBytes>,
+}
+
+impl GltfSource {
+    /// Parse a `.gltf` or `.glb` file, mapping it and the external buffers it names. Only the
+    /// JSON is parsed up front; buffer bytes are touched as they're read.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = BufferBytes::open(path)?;
+        let (root, bin) = if file.starts_with(b"glTF") {
+            let glb = gltf::Glb::from_slice(&file)?;
+            let root = gltf::json::Root::from_slice(&glb.json)?;
+            (root, glb.bin.map(|bin| file.within(&bin)))
+        } else {
+            (gltf::json::Root::from_slice(&file)?, None)
+        };
+        let document = gltf::Document::from_json(root)?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut buffers = Vec::new();
+        for buffer in document.buffers() {
+            let bytes = match buffer.source() {
+                gltf::buffer::Source::Bin => bin.clone().context("The GLB has no binary chunk")?,
+                gltf::buffer::Source::Uri(uri) => match uri.strip_prefix("data:") {
+                    Some(data) => BufferBytes::owned(decode_data_uri(data)?),
+                    None => BufferBytes::open(&base.join(uri_path(uri)?))?,
+                },
+            };
+            anyhow::ensure!(
+                bytes.len() >= buffer.length(),
+                "Buffer {} has {} bytes, fewer than the {} it declares", buffer.index(), bytes.len(), buffer.length(),
+            );
+            buffers.push(bytes);
+        }
+        Ok(Self { document, buffers })
+    }
+
+    /// The bytes of a buffer view
+    pub fn view<'a>(buffers: &'a [BufferBytes], view: &gltf::buffer::View) -> Result<&'a [u8]> {
+        let buffer = buffers.get(view.buffer().index())
+            .with_context(|| format!("No buffer {}", view.buffer().index()))?;
+        view.offset().checked_add(view.length())
+            .and_then(|end| buffer.get(view.offset()..end))
+            .with_context(|| format!("Buffer view {} runs past its buffer", view.index()))
+    }
+}
+
+/// The u32 indices of `accessor`, copied as one slice when they're tightly packed and 4-byte
+/// aligned in the file; `None` when they aren't, to go through the accessor reader instead.
+/// All wgpu targets are little-endian, like glTF.
+pub(crate) fn packed_indices(accessor: &gltf::Accessor, buffers: &[BufferBytes]) -> Option<Vec<u32>> {
+    let view = accessor.view()?;
+    let packed = accessor.data_type() == gltf::accessor::DataType::U32
+        && accessor.sparse().is_none()
+        && view.stride().map_or(true, |stride| stride == 4);
+    if !packed {
+        return None;
+    }
+    // Offsets and counts come straight from the file, so a malformed one mustn't overflow
+    let start = view.offset().checked_add(accessor.offset())?;
+    let end = accessor.count().checked_mul(4)?.checked_add(start)?;
+    let bytes = buffers.get(view.buffer().index())?.get(start..end)?;
+    bytemuck::try_cast_slice::<u8, u32>(bytes).ok().map(<[u32]>::to_vec)
+}
+
+/// The bytes of a `data:` URI, without its scheme
+fn decode_data_uri(data: &str) -> Result<Vec<u8>> {
+    let (_, encoded) = data.split_once(";base64,").context("Only base64 data URIs are supported")?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+}
+
+/// The relative path a buffer URI names, percent-decoded
+fn uri_path(uri: &str) -> Result<String> {
+    let uri = uri.strip_prefix("file://").or_else(|| uri.strip_prefix("file:")).unwrap_or(uri);
+    anyhow::ensure!(!uri.contains(':'), "Unsupported buffer URI scheme: {}", uri);
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut rest = uri.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_bytes_within() {
+        let bytes = BufferBytes::owned((0..16).collect());
+        let part = bytes.within(&bytes[4..8]);
+        assert_eq!(&*part, &[4, 5, 6, 7]);
+        assert!(Arc::ptr_eq(&part.backing, &bytes.backing));
+        // Bytes from elsewhere are copied
+        let other = [1u8, 2];
+        assert_eq!(&*bytes.within(&other), &[1, 2]);
+    }
+
+    #[test]
+    fn test_buffer_bytes_mapped() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"glTF").unwrap();
+        let bytes = BufferBytes::open(file.path()).unwrap();
+        assert!(matches!(*bytes.backing, Backing::Mapped(_)));
+        assert_eq!(&*bytes, b"glTF");
+    }
+
+    #[test]
+    fn test_buffer_uris() {
+        assert_eq!(uri_path("textures/brick%20wall.bin").unwrap(), "textures/brick wall.bin");
+        assert_eq!(uri_path("file:scan.bin").unwrap(), "scan.bin");
+        assert_eq!(uri_path("100%.bin").unwrap(), "100%.bin");
+        assert!(uri_path("https://example.com/scan.bin").is_err());
+        assert_eq!(decode_data_uri("application/octet-stream;base64,AAEC").unwrap(), vec![0, 1, 2]);
+    }
+}
@@ -1,13 +1,249 @@
+use glam::Vec2;
+use wgpu::util::DeviceExt;
+use crate::color::Color;
 use super::texture::Texture;
+use super::TextureSlot;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub reflectivity: f32,
+    pub roughness: f32,
+    /// 1 when back faces are drawn and shaded with flipped normals
+    pub double_sided: f32,
+    /// Alpha below which fragments are discarded by the `ALPHA_MASK` variant
+    pub alpha_cutoff: f32,
+    /// Linear RGBA multiplied into the diffuse texture
+    pub base_color: [f32; 4],
+    /// Direction (x, z), strength and gust frequency of the `WIND` variant's sway
+    pub wind: [f32; 4],
+    /// x = 0 to weight the sway by vertex color, 1 by height; y = height of full sway
+    pub wind_weight: [f32; 4],
+    /// Key color (as stored in the texture) and threshold of the `CHROMA_KEY` variant
+    pub chroma_key: [f32; 4],
+    /// x = edge smoothness, y = spill removal
+    pub chroma_params: [f32; 4],
+}
+
+/// Which vertices of a swaying material move, and how far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindWeight {
+    /// The red vertex color, painted from 0 where the plant is anchored to 1 at leaf tips.
+    /// Meshes without vertex colors sway everywhere.
+    VertexColor,
+    /// Height above the model's origin, swaying fully at this many meters (in model space) and
+    /// bending more towards the top
+    Height(f32),
+}
+
+/// Vertex displacement that keeps vegetation from looking frozen. Whole plants lean and spring
+/// back with the gusts, which roll across the scene along the wind, while their tips flutter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wind {
+    /// Direction the wind blows towards on the ground plane (x, z)
+    pub direction: Vec2,
+    /// Farthest a fully weighted vertex moves, in meters
+    pub strength: f32,
+    /// Gusts per second
+    pub gust_frequency: f32,
+    pub weight: WindWeight,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec2::X,
+            strength: 0.1,
+            gust_frequency: 0.3,
+            weight: WindWeight::Height(1.0),
+        }
+    }
+}
+
+/// Cuts a backdrop color out of the diffuse texture, for green-screen video in mixed-reality
+/// composites. Texels are compared by chroma alone, so shadows and creases on the backdrop
+/// are cut out along with the rest of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChromaKey {
+    /// The backdrop's color
+    pub color: Color,
+    /// Chroma distance from the key within which texels are cut out entirely; pure colors are
+    /// around 0.4 from grey
+    pub threshold: f32,
+    /// Distance beyond the threshold over which texels fade back in, softening the edges
+    pub smoothness: f32,
+    /// Share of the backdrop's tint taken out of what's left, 0 - 1; edges and hair pick it up
+    /// from light bouncing off the screen
+    pub spill: f32,
+}
+
+impl ChromaKey {
+    /// A key for `color` with the default tolerances
+    pub fn new(color: Color) -> Self {
+        Self { color, ..Self::default() }
+    }
+}
+
+impl Default for ChromaKey {
+    /// A studio green screen
+    fn default() -> Self {
+        Self {
+            color: Color::srgb8(0, 177, 64),
+            threshold: 0.15,
+            smoothness: 0.1,
+            spill: 0.5,
+        }
+    }
+}
 
 pub struct Material {
     pub name: String,
     pub diffuse_texture: Option<Texture>,
     pub normal_texture: Option<Texture>,
-    pub bind_group: Option<wgpu::BindGroup>,
+    /// Tints the diffuse texture, e.g. glTF's base color factor
+    pub base_color: Color,
+    pub(crate) bind_group: Option<wgpu::BindGroup>,
+    // Picked up by the screen-space reflection pass
+    pub reflective: bool,
+    pub roughness: f32,
+    /// Drawn without back-face culling, for foliage and other thin geometry
+    pub double_sided: bool,
+    /// Cut out fragments whose alpha falls below this, for foliage and fences
+    pub alpha_cutoff: Option<f32>,
+    /// With MSAA, turn the cutoff into sample coverage for soft edges on distant foliage
+    /// instead of discarding whole fragments; ignored without `alpha_cutoff` or MSAA
+    pub alpha_to_coverage: bool,
+    /// Alpha blended rather than opaque, drawn after the opaque objects with the scene's
+    /// `Transparency` mode
+    pub transparent: bool,
+    /// Sway in the wind, for vegetation; selects the `WIND` shader variant
+    pub wind: Option<Wind>,
+    /// Cut a backdrop color out of the diffuse texture; selects the `CHROMA_KEY` variant. Opaque
+    /// materials cut keyed texels out at half alpha; transparent ones fade them out smoothly.
+    pub chroma_key: Option<ChromaKey>,
+    /// Whether the bind group holds a real normal map rather than the diffuse texture
+    pub(crate) normal_mapped: bool,
+    pub(crate) params_buffer: Option<wgpu::Buffer>,
 }
 
 impl Material {
+    pub fn new(name: &str, diffuse_texture: Option<Texture>, normal_texture: Option<Texture>) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse_texture,
+            normal_texture,
+            base_color: Color::WHITE,
+            bind_group: None,
+            reflective: false,
+            roughness: 1.0,
+            double_sided: false,
+            alpha_cutoff: None,
+            alpha_to_coverage: false,
+            transparent: false,
+            wind: None,
+            chroma_key: None,
+            normal_mapped: false,
+            params_buffer: None,
+        }
+    }
+
+    /// Free the textures and parameter buffer now; the material must not be bound afterwards
+    pub(crate) fn destroy(&self) {
+        for texture in [&self.diffuse_texture, &self.normal_texture].into_iter().flatten() {
+            texture.destroy();
+        }
+        if let Some(buffer) = &self.params_buffer {
+            buffer.destroy();
+        }
+    }
+
+    /// Bytes of GPU memory the textures and parameters take
+    pub fn memory_size(&self) -> u64 {
+        let textures: u64 = [&self.diffuse_texture, &self.normal_texture].into_iter().flatten().map(Texture::memory_size).sum();
+        textures + self.params_buffer.as_ref().map_or(0, |buffer| buffer.size())
+    }
+
+    pub fn uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            reflectivity: if self.reflective { 1.0 - self.roughness.clamp(0.0, 1.0) } else { 0.0 },
+            roughness: self.roughness,
+            double_sided: if self.double_sided { 1.0 } else { 0.0 },
+            alpha_cutoff: self.mask_cutoff().unwrap_or(0.0),
+            base_color: self.base_color.to_array(),
+            wind: self.wind.map_or([0.0; 4], |wind| {
+                let direction = wind.direction.normalize_or_zero();
+                [direction.x, direction.y, wind.strength, wind.gust_frequency]
+            }),
+            wind_weight: match self.wind.map(|wind| wind.weight) {
+                Some(WindWeight::Height(height)) => [1.0, height.max(1e-3), 0.0, 0.0],
+                _ => [0.0; 4],
+            },
+            chroma_key: self.chroma_key.map_or([0.0; 4], |key| {
+                // Textures hold sRGB-encoded texels, sampled without decoding
+                let [r, g, b, _] = key.color.to_srgb();
+                [r, g, b, key.threshold]
+            }),
+            chroma_params: self.chroma_key.map_or([0.0; 4], |key| [key.smoothness.max(1e-4), key.spill.clamp(0.0, 1.0), 0.0, 0.0]),
+        }
+    }
+
+    /// Alpha below which the `ALPHA_MASK` variant cuts fragments out: the material's own
+    /// cutoff, or half for opaque chroma-keyed materials so their backdrop disappears
+    pub(crate) fn mask_cutoff(&self) -> Option<f32> {
+        self.alpha_cutoff.or_else(|| (self.chroma_key.is_some() && !self.transparent).then_some(0.5))
+    }
+
+    /// Create the uniform buffer holding this material's shading parameters
+    pub fn create_params_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{}_params", self.name)),
+            contents: bytemuck::cast_slice(&[self.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    /// Toggle screen-space reflections for this material
+    pub fn set_reflective(&mut self, queue: &wgpu::Queue, reflective: bool) {
+        self.reflective = reflective;
+        self.write_params(queue);
+    }
+
+    pub fn set_base_color(&mut self, queue: &wgpu::Queue, base_color: Color) {
+        self.base_color = base_color;
+        self.write_params(queue);
+    }
+
+    /// Toggle back-face culling for this material
+    pub fn set_double_sided(&mut self, queue: &wgpu::Queue, double_sided: bool) {
+        self.double_sided = double_sided;
+        self.write_params(queue);
+    }
+
+    /// Start, change or stop this material's sway. Starting or stopping it switches shader
+    /// variant, which the renderer picks up on the next frame.
+    pub fn set_wind(&mut self, queue: &wgpu::Queue, wind: Option<Wind>) {
+        self.wind = wind;
+        self.write_params(queue);
+    }
+
+    /// Start, change or stop keying out a backdrop color; starting or stopping switches shader
+    /// variant on the next frame
+    pub fn set_chroma_key(&mut self, queue: &wgpu::Queue, chroma_key: Option<ChromaKey>) {
+        self.chroma_key = chroma_key;
+        self.write_params(queue);
+    }
+
+    /// Whether the material is bound with a normal map, selecting the normal-mapped shader variant
+    pub fn has_normal_map(&self) -> bool {
+        self.normal_mapped
+    }
+
+    fn write_params(&self, queue: &wgpu::Queue) {
+        if let Some(buffer) = &self.params_buffer {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[self.uniform()]));
+        }
+    }
+
     pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> Self {
         let diffuse_texture = self.diffuse_texture.as_ref().map(|texture| {
             texture.clone_with_device(device, queue)
@@ -17,10 +253,16 @@ impl Material {
         });
 
         let mut material = Self {
-            name: self.name.clone(),
-            diffuse_texture,
-            normal_texture,
-            bind_group: None,
+            base_color: self.base_color,
+            reflective: self.reflective,
+            roughness: self.roughness,
+            double_sided: self.double_sided,
+            alpha_cutoff: self.alpha_cutoff,
+            alpha_to_coverage: self.alpha_to_coverage,
+            transparent: self.transparent,
+            wind: self.wind,
+            chroma_key: self.chroma_key,
+            ..Self::new(&self.name, diffuse_texture, normal_texture)
         };
 
         material.create_bind_group(device, layout);
@@ -35,6 +277,40 @@ impl Material {
         // Use a default normal map (flat surface) if none is provided
         let normal_texture = self.normal_texture.as_ref().unwrap_or(diffuse_texture);
 
+        let (bind_group, params_buffer) = self.bind_textures(device, layout, diffuse_texture, normal_texture);
+        self.bind_group = Some(bind_group);
+        self.params_buffer = Some(params_buffer);
+        self.normal_mapped = self.normal_texture.is_some();
+    }
+
+    /// Replace one of the material's textures and rebuild its bind group, e.g. when a streamed
+    /// texture arrives. A missing diffuse texture becomes white; a missing normal map, none.
+    /// The old texture is dropped rather than destroyed, since frames in flight may still use it.
+    pub fn set_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        slot: TextureSlot,
+        texture: Option<Texture>,
+    ) {
+        match slot {
+            TextureSlot::Diffuse => self.diffuse_texture = Some(texture.unwrap_or_else(|| Texture::white(device, queue))),
+            TextureSlot::Normal => self.normal_texture = texture,
+        }
+        self.create_bind_group(device, layout);
+    }
+
+    /// Bind group over textures owned elsewhere (e.g. by the asset registry), plus a fresh params buffer
+    pub(super) fn bind_textures(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        diffuse_texture: &Texture,
+        normal_texture: &Texture,
+    ) -> (wgpu::BindGroup, wgpu::Buffer) {
+        let params_buffer = self.create_params_buffer(device);
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(&format!("{}_bind_group", self.name)),
             layout,
@@ -55,9 +331,13 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
             ],
         });
 
-        self.bind_group = Some(bind_group);
+        (bind_group, params_buffer)
     }
-} 
\ No newline at end of file
+}
@@ -1,12 +1,35 @@
 pub struct Mesh {
     pub name: String,
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
+    pub(crate) vertex_buffer: wgpu::Buffer,
+    pub(crate) index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material_index: usize,
+    /// Whether any vertex color differs from white, selecting the vertex color shader variant
+    pub vertex_colors: bool,
+    /// `SkinVertex` stream of meshes bound to their model's skeleton
+    pub(crate) skin_buffer: Option<wgpu::Buffer>,
 }
 
 impl Mesh {
+    /// Free the GPU buffers now; the mesh must not be drawn afterwards
+    pub(crate) fn destroy(&self) {
+        self.vertex_buffer.destroy();
+        self.index_buffer.destroy();
+        if let Some(buffer) = &self.skin_buffer {
+            buffer.destroy();
+        }
+    }
+
+    /// Bytes of GPU memory the buffers take
+    pub fn memory_size(&self) -> u64 {
+        self.vertex_buffer.size() + self.index_buffer.size() + self.skin_buffer.as_ref().map_or(0, |buffer| buffer.size())
+    }
+
+    /// Whether the mesh has joints and weights to draw skinned with
+    pub fn is_skinned(&self) -> bool {
+        self.skin_buffer.is_some()
+    }
+
     pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
         // Create new vertex buffer
         let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -46,6 +69,17 @@ impl Mesh {
             self.index_buffer.size(),
         );
 
+        let skin_buffer = self.skin_buffer.as_ref().map(|source| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{} Skin Buffer", self.name)),
+                size: source.size(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, source.size());
+            buffer
+        });
+
         // Submit copy commands
         queue.submit(std::iter::once(encoder.finish()));
 
@@ -55,6 +89,8 @@ impl Mesh {
             index_buffer,
             num_elements: self.num_elements,
             material_index: self.material_index,
+            vertex_colors: self.vertex_colors,
+            skin_buffer,
         }
     }
 } 
\ No newline at end of file
@@ -3,14 +3,41 @@ use wgpu::util::DeviceExt;
 mod texture;
 mod material;
 mod mesh;
+mod dynamic;
 mod vertex;
 mod loader;
+mod node;
+mod skin;
+mod ply;
+mod obj;
+mod background;
+mod registry;
+mod units;
+mod validate;
+mod atlas;
+mod batch;
+mod compression;
+mod streaming;
+mod mapped;
+mod video;
 
 pub use texture::Texture;
-pub use material::Material;
+pub use material::{ChromaKey, Material, MaterialUniform, Wind, WindWeight};
 pub use mesh::Mesh;
-pub use vertex::ModelVertex;
-pub use loader::Model;
+pub use dynamic::DynamicMesh;
+pub use vertex::{ModelVertex, SkinVertex, MAX_JOINTS};
+pub use loader::{Model, ModelData, MeshData, MaterialData, ImageData};
+pub use node::{Node, world_matrices};
+pub use skin::{AnimationClip, Channel, Interpolation, Keyframes, NodePose, QuantizedVec3s, Skeleton};
+pub use background::{BackgroundLoader, LoadedModel};
+pub use registry::{AssetRegistry, ModelHandle, MaterialHandle, TextureHandle, FRAMES_IN_FLIGHT};
+pub use units::{ImportOptions, LengthUnit};
+pub use validate::{MeshReport, ValidationReport};
+pub use atlas::{AtlasOptions, AtlasReport};
+pub use batch::{MergeOptions, MergeReport};
+pub use compression::{ClipMemory, ClipReport, CompressionOptions};
+pub use streaming::{DeferredImages, StreamedTexture, TextureSlot};
+pub use video::{video_panel, SharedMemoryFrames, VideoChannel, VideoSender, VideoSource, VideoTexture};
 
 #[cfg(test)]
 mod tests; 
@@ -87,36 +114,40 @@ impl Model {
             index_buffer,
             num_elements: indices.len() as u32,
             material_index: 0,
+            vertex_colors: false,
+            skin_buffer: None,
         };
 
         // Create a single material
-        let material = Material {
-            name: "floor_material".to_string(),
-            diffuse_texture: None,
-            normal_texture: None,
-            bind_group: Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: material_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::TextureView(&normal_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
-                    },
-                ],
-                label: Some("Floor Material Bind Group"),
-            })),
-        };
+        let mut material = Material::new("floor_material", None, None);
+        let params_buffer = material.create_params_buffer(device);
+        material.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: material_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Floor Material Bind Group"),
+        }));
+        material.params_buffer = Some(params_buffer);
 
         // Calculate bounds
         let mut min = [f32::INFINITY; 3];
@@ -130,7 +161,9 @@ impl Model {
 
         Self {
             meshes: vec![mesh],
+            dynamic_meshes: Vec::new(),
             materials: vec![material],
+            skeleton: None,
             bounds_min: min,
             bounds_max: max,
         }
@@ -0,0 +1,213 @@
+use glam::{Mat3, Mat4, Vec3, Vec4};
+use super::{MeshData, ModelData, ModelVertex};
+
+/// One node of a model's scene graph: a transform relative to its parent, and the meshes drawn
+/// with it. Several nodes listing the same meshes are instances of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: String,
+    /// Index of the parent in the model's node list, which always comes before its children;
+    /// `None` for roots
+    pub parent: Option<usize>,
+    /// Local to parent
+    pub transform: Mat4,
+    /// Indices into the model's meshes, which are in this node's local space
+    pub meshes: Vec<usize>,
+}
+
+/// Local-to-world matrix of every node; parents must come before their children
+pub fn world_matrices(nodes: &[Node]) -> Vec<Mat4> {
+    let mut matrices: Vec<Mat4> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let parent = node.parent.and_then(|parent| matrices.get(parent).copied()).unwrap_or(Mat4::IDENTITY);
+        matrices.push(parent * node.transform);
+    }
+    matrices
+}
+
+impl MeshData {
+    /// A copy with `matrix` applied to its vertices. Mirroring matrices reverse the winding,
+    /// so triangles still face out.
+    pub fn transformed(&self, matrix: Mat4) -> Self {
+        let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+        let mirrored = matrix.determinant() < 0.0;
+        let vertices = self.vertices.iter()
+            .map(|vertex| {
+                let mut vertex = *vertex;
+                vertex.position = matrix.transform_point3(Vec3::from(vertex.position)).to_array();
+                vertex.normal = (normal_matrix * Vec3::from(vertex.normal)).normalize_or_zero().to_array();
+                let tangent = Vec4::from(vertex.tangent);
+                let direction = matrix.transform_vector3(tangent.truncate()).normalize_or_zero();
+                vertex.tangent = direction.extend(if mirrored { -tangent.w } else { tangent.w }).to_array();
+                vertex
+            })
+            .collect();
+        let mut indices = self.indices.clone();
+        if mirrored {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        Self { name: self.name.clone(), vertices, indices, material_index: self.material_index, skin: self.skin.clone() }
+    }
+}
+
+impl ModelData {
+    /// Bake every node's world matrix into its meshes, so the model draws correctly as one
+    /// object: instanced meshes are copied once per node, and meshes no node uses are dropped.
+    /// Skinned meshes stay in bind space for the skeleton to place. Models without nodes are
+    /// left as they are.
+    pub fn flatten_nodes(&mut self) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let matrices = world_matrices(&self.nodes);
+        self.meshes = self.nodes.iter().zip(matrices)
+            .flat_map(|(node, matrix)| node.meshes.iter().map(move |&mesh| (mesh, matrix)))
+            .filter_map(|(mesh, matrix)| {
+                let mesh = self.meshes.get(mesh)?;
+                // Skinned meshes are placed by their joints, not their node
+                Some(if mesh.skin.is_some() { mesh.clone() } else { mesh.transformed(matrix) })
+            })
+            .collect();
+        self.nodes.clear();
+        self.update_bounds();
+    }
+
+    /// Split into a model per distinct mesh list, for placing the nodes as separate scene
+    /// objects; returns the parts and the part each node draws, `None` for nodes without
+    /// meshes. Nodes instancing the same meshes share a part. A model without nodes is one
+    /// part. Parts carry copies of the materials they use, but not the skeleton, so skinned
+    /// meshes draw in their bind pose.
+    pub fn split_nodes(&self) -> (Vec<ModelData>, Vec<Option<usize>>) {
+        if self.nodes.is_empty() {
+            let part = self.part(&(0..self.meshes.len()).collect::<Vec<_>>());
+            return (vec![part], Vec::new());
+        }
+        let mut lists: Vec<&[usize]> = Vec::new();
+        let node_parts: Vec<Option<usize>> = self.nodes.iter()
+            .map(|node| {
+                if node.meshes.is_empty() {
+                    return None;
+                }
+                Some(lists.iter().position(|list| *list == node.meshes.as_slice()).unwrap_or_else(|| {
+                    lists.push(&node.meshes);
+                    lists.len() - 1
+                }))
+            })
+            .collect();
+        (lists.into_iter().map(|meshes| self.part(meshes)).collect(), node_parts)
+    }
+
+    /// The given meshes, with the materials they use renumbered
+    fn part(&self, meshes: &[usize]) -> ModelData {
+        let mut materials: Vec<usize> = Vec::new();
+        let meshes: Vec<MeshData> = meshes.iter()
+            .filter_map(|&index| self.meshes.get(index))
+            .map(|mesh| {
+                let material = materials.iter().position(|&used| used == mesh.material_index).unwrap_or_else(|| {
+                    materials.push(mesh.material_index);
+                    materials.len() - 1
+                });
+                MeshData { material_index: material, ..mesh.clone() }
+            })
+            .collect();
+        let materials = materials.iter()
+            .filter_map(|&index| self.materials.get(index).cloned())
+            .collect();
+        let mut part = ModelData {
+            meshes,
+            materials,
+            nodes: Vec::new(),
+            skeleton: None,
+            bounds_min: [0.0; 3],
+            bounds_max: [0.0; 3],
+            units: self.units,
+            import_scale: self.import_scale,
+        };
+        part.update_bounds();
+        part
+    }
+
+    /// Recompute the bounds from the meshes, where the nodes place them if there are any
+    pub(crate) fn update_bounds(&mut self) {
+        let vertices: Vec<_> = if self.nodes.is_empty() {
+            self.meshes.iter().flat_map(|mesh| mesh.vertices.iter().copied()).collect()
+        } else {
+            self.nodes.iter().zip(world_matrices(&self.nodes))
+                .flat_map(|(node, matrix)| node.meshes.iter().map(move |&mesh| (mesh, matrix)))
+                .filter_map(|(mesh, matrix)| self.meshes.get(mesh).map(|mesh| (mesh, matrix)))
+                .flat_map(|(mesh, matrix)| mesh.vertices.iter().map(move |vertex| ModelVertex {
+                    position: matrix.transform_point3(Vec3::from(vertex.position)).to_array(),
+                    ..*vertex
+                }))
+                .collect()
+        };
+        (self.bounds_min, self.bounds_max) = Self::calculate_bounds(&vertices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(material_index: usize) -> MeshData {
+        let vertex = |x: f32, y: f32| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+        MeshData { name: "triangle".to_string(), vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)], indices: vec![0, 1, 2], material_index, skin: None }
+    }
+
+    fn node(parent: Option<usize>, transform: Mat4, meshes: Vec<usize>) -> Node {
+        Node { name: String::new(), parent, transform, meshes }
+    }
+
+    #[test]
+    fn test_world_matrices() {
+        let nodes = [
+            node(None, Mat4::from_translation(Vec3::X), vec![]),
+            node(Some(0), Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2), vec![]),
+            node(Some(1), Mat4::from_translation(Vec3::Z), vec![]),
+        ];
+        let matrices = world_matrices(&nodes);
+        // The grandchild's +Z offset is turned onto +X by its parent, then moved with the root
+        let origin = matrices[2].transform_point3(Vec3::ZERO);
+        assert!((origin - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-5, "{}", origin);
+    }
+
+    #[test]
+    fn test_flatten_and_split_nodes() {
+        let mut data = ModelData::from_mesh(triangle(0));
+        data.meshes.push(triangle(0));
+        // Two instances of the first mesh, one mirrored, and a node of the second under the first
+        data.nodes = vec![
+            node(None, Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0)), vec![0]),
+            node(None, Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)), vec![0]),
+            node(Some(0), Mat4::from_translation(Vec3::Y), vec![1]),
+            node(None, Mat4::IDENTITY, vec![]),
+        ];
+
+        let (parts, node_parts) = data.split_nodes();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(node_parts, vec![Some(0), Some(0), Some(1), None]);
+        assert_eq!(parts[1].meshes.len(), 1);
+        assert_eq!(parts[1].materials.len(), 1);
+
+        data.flatten_nodes();
+        assert!(data.nodes.is_empty());
+        assert_eq!(data.meshes.len(), 3);
+        assert_eq!(data.meshes[0].vertices[1].position, [6.0, 0.0, 0.0]);
+        assert_eq!(data.meshes[2].vertices[0].position, [5.0, 1.0, 0.0]);
+        // The mirrored copy keeps facing +Z by reversing its winding
+        let mirrored = &data.meshes[1];
+        assert_eq!(mirrored.vertices[1].position, [-1.0, 0.0, 0.0]);
+        assert_eq!(mirrored.indices, vec![0, 2, 1]);
+        assert_eq!(mirrored.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(mirrored.vertices[0].tangent, [-1.0, 0.0, 0.0, -1.0]);
+        assert_eq!((data.bounds_min, data.bounds_max), ([-1.0, 0.0, 0.0], [6.0, 2.0, 0.0]));
+    }
+}
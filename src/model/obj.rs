@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use anyhow::{bail, Result};
+use glam::Vec3;
+use super::{LengthUnit, MeshData, ModelVertex};
+
+/// Meshes read from a Wavefront OBJ file, one per object or group (`o`/`g`)
+#[derive(Default)]
+pub struct ObjModel {
+    pub meshes: Vec<MeshData>,
+    /// Units noted in a comment, e.g. `# File units = centimeters`
+    pub units: Option<LengthUnit>,
+}
+
+/// How a corner without a normal in the file gets one: shared with the other faces of its
+/// smoothing group (`s 1`), or its face's own (`s off`, the default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Smoothing {
+    Group(u32),
+    Flat(usize),
+}
+
+/// Position, UV and normal indices of a face corner, plus the smoothing of corners without a
+/// normal, which decides whether they can share a vertex
+type CornerKey = (usize, Option<usize>, Option<usize>, Option<Smoothing>);
+
+/// A mesh being read
+struct ObjMesh {
+    name: String,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    /// Vertices whose normal comes from their faces once the file is read
+    missing_normals: Vec<bool>,
+    /// Vertex already made for each corner
+    vertex_cache: HashMap<CornerKey, u32>,
+}
+
+impl ObjMesh {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            missing_normals: Vec::new(),
+            vertex_cache: HashMap::new(),
+        }
+    }
+
+    /// The finished mesh, with area-weighted face normals summed into the vertices that lack one
+    fn finish(mut self) -> MeshData {
+        let mut sums = vec![Vec3::ZERO; self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.vertices[triangle[i] as usize].position));
+            let normal = (b - a).cross(c - a);
+            for &index in triangle {
+                sums[index as usize] += normal;
+            }
+        }
+        for ((vertex, missing), sum) in self.vertices.iter_mut().zip(&self.missing_normals).zip(sums) {
+            if *missing {
+                vertex.normal = sum.try_normalize().unwrap_or(Vec3::Y).to_array();
+            }
+        }
+        MeshData { name: self.name, vertices: self.vertices, indices: self.indices, material_index: 0, skin: None }
+    }
+}
+
+struct ObjParser {
+    positions: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    normals: Vec<[f32; 3]>,
+    meshes: Vec<ObjMesh>,
+    /// The mesh faces go into, selected by the last `o` or `g`
+    current: usize,
+    /// The current smoothing group; 0 is off
+    smoothing: u32,
+    faces: usize,
+}
+
+impl ObjParser {
+    /// Send the following faces to the mesh called `name`, which a group named again continues
+    fn select_mesh(&mut self, name: &str) {
+        self.current = match self.meshes.iter().position(|mesh| mesh.name == name) {
+            Some(index) => index,
+            None => {
+                self.meshes.push(ObjMesh::new(name));
+                self.meshes.len() - 1
+            }
+        };
+    }
+
+    fn process_face(&mut self, corners: &[&str]) -> Result<()> {
+        let smoothing = match self.smoothing {
+            0 => Smoothing::Flat(self.faces),
+            group => Smoothing::Group(group),
+        };
+        self.faces += 1;
+
+        let mut vertex_indices = Vec::with_capacity(corners.len());
+        let mut points = Vec::with_capacity(corners.len());
+        for corner in corners {
+            let mut indices = corner.split('/');
+
+            // OBJ indices are 1-based, or negative to count back from the latest element
+            let resolve = |index: Option<&str>, count: usize| {
+                index.filter(|s| !s.is_empty())
+                    .and_then(|s| s.parse::<i64>().ok())
+                    .map(|i| if i < 0 { count as i64 + i } else { i - 1 })
+                    .and_then(|i| usize::try_from(i).ok())
+                    .filter(|&i| i < count)
+            };
+            let Some(position_idx) = resolve(indices.next(), self.positions.len()) else {
+                bail!("Invalid position index in face vertex {:?}", corner);
+            };
+            let tex_coord_idx = resolve(indices.next(), self.tex_coords.len());
+            let normal_idx = resolve(indices.next(), self.normals.len());
+
+            // Reuse the vertex made for the same corner, if any
+            let key = (position_idx, tex_coord_idx, normal_idx, normal_idx.is_none().then_some(smoothing));
+            let mesh = &mut self.meshes[self.current];
+            let vertex_idx = match mesh.vertex_cache.get(&key) {
+                Some(&idx) => idx,
+                None => {
+                    let idx = mesh.vertices.len() as u32;
+                    mesh.vertices.push(ModelVertex {
+                        position: self.positions[position_idx],
+                        tex_coords: tex_coord_idx.map_or([0.0, 0.0], |i| self.tex_coords[i]),
+                        normal: normal_idx.map_or([0.0, 1.0, 0.0], |i| self.normals[i]),
+                        tangent: [1.0, 0.0, 0.0, 1.0], // Default tangent along X axis
+                        color: ModelVertex::WHITE,
+                    });
+                    mesh.missing_normals.push(normal_idx.is_none());
+                    mesh.vertex_cache.insert(key, idx);
+                    idx
+                }
+            };
+
+            vertex_indices.push(vertex_idx);
+            points.push(Vec3::from(self.positions[position_idx]));
+        }
+
+        let mesh = &mut self.meshes[self.current];
+        for triangle in triangulate(&points) {
+            mesh.indices.extend(triangle.map(|corner| vertex_indices[corner]));
+        }
+        Ok(())
+    }
+}
+
+/// Read an OBJ file; faces before any `o` or `g` go into a mesh called `default_name`
+pub fn parse(reader: impl BufRead, default_name: &str) -> Result<ObjModel> {
+    let mut parser = ObjParser {
+        positions: Vec::new(),
+        tex_coords: Vec::new(),
+        normals: Vec::new(),
+        meshes: vec![ObjMesh::new(default_name)],
+        current: 0,
+        smoothing: 0,
+        faces: 0,
+    };
+    let mut units = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "v" => {
+                if tokens.len() < 4 {
+                    continue;
+                }
+                let x = tokens[1].parse::<f32>()?;
+                let y = tokens[2].parse::<f32>()?;
+                let z = tokens[3].parse::<f32>()?;
+                parser.positions.push([x, y, z]);
+            }
+            "vt" => {
+                if tokens.len() < 3 {
+                    continue;
+                }
+                let u = tokens[1].parse::<f32>()?;
+                let v = tokens[2].parse::<f32>()?;
+                parser.tex_coords.push([u, v]);
+            }
+            "vn" => {
+                if tokens.len() < 4 {
+                    continue;
+                }
+                let x = tokens[1].parse::<f32>()?;
+                let y = tokens[2].parse::<f32>()?;
+                let z = tokens[3].parse::<f32>()?;
+                parser.normals.push([x, y, z]);
+            }
+            "f" => {
+                if tokens.len() < 4 {
+                    continue;
+                }
+                parser.process_face(&tokens[1..])?;
+            }
+            "o" | "g" => {
+                let name = tokens[1..].join(" ");
+                parser.select_mesh(if name.is_empty() { default_name } else { &name });
+            }
+            "s" => {
+                parser.smoothing = match tokens.get(1).copied() {
+                    None | Some("off") => 0,
+                    // Some exporters write `s on`
+                    Some(group) => group.parse().unwrap_or(1),
+                };
+            }
+            // Some exporters note their units in a comment, e.g. `# File units = centimeters`
+            comment if comment.starts_with('#') && units.is_none() && line.to_lowercase().contains("unit") => {
+                units = line.rsplit([':', '=']).next().and_then(LengthUnit::from_name);
+            }
+            _ => {}
+        }
+    }
+
+    let meshes: Vec<_> = parser.meshes.into_iter()
+        .filter(|mesh| !mesh.indices.is_empty())
+        .map(ObjMesh::finish)
+        .collect();
+    if meshes.is_empty() {
+        bail!("No faces found in OBJ file");
+    }
+    Ok(ObjModel { meshes, units })
+}
+
+/// Triangles covering a polygon, as indices into `points`. Convex polygons become a fan;
+/// concave ones are ear clipped in the plane of their Newell normal, and what's left of a
+/// self-intersecting one once no ear can be cut is fanned.
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    let count = points.len();
+    let fan = |corners: &[usize]| -> Vec<[usize; 3]> {
+        (1..corners.len() - 1).map(|i| [corners[0], corners[i], corners[i + 1]]).collect()
+    };
+    let all: Vec<usize> = (0..count).collect();
+    if count < 4 {
+        return fan(&all);
+    }
+
+    let normal = (0..count).fold(Vec3::ZERO, |sum, i| {
+        let (a, b) = (points[i], points[(i + 1) % count]);
+        sum + Vec3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y))
+    });
+    let Some(normal) = normal.try_normalize() else {
+        return fan(&all);
+    };
+    let turns_left = |a: usize, b: usize, c: usize| (points[b] - points[a]).cross(points[c] - points[b]).dot(normal) > 0.0;
+    let corner = |corners: &[usize], i: usize| {
+        let n = corners.len();
+        (corners[(i + n - 1) % n], corners[i], corners[(i + 1) % n])
+    };
+    if (0..count).all(|i| {
+        let (a, b, c) = corner(&all, i);
+        turns_left(a, b, c)
+    }) {
+        return fan(&all);
+    }
+
+    let inside = |p: usize, (a, b, c): (usize, usize, usize)| {
+        [(a, b), (b, c), (c, a)].iter()
+            .all(|&(from, to)| (points[to] - points[from]).cross(points[p] - points[from]).dot(normal) > 0.0)
+    };
+    let mut remaining = all;
+    let mut triangles = Vec::with_capacity(count - 2);
+    while remaining.len() > 3 {
+        let ear = (0..remaining.len()).find(|&i| {
+            let (a, b, c) = corner(&remaining, i);
+            turns_left(a, b, c) && !remaining.iter().any(|&p| p != a && p != b && p != c && inside(p, (a, b, c)))
+        });
+        let Some(ear) = ear else {
+            triangles.extend(fan(&remaining));
+            return triangles;
+        };
+        let (a, b, c) = corner(&remaining, ear);
+        triangles.push([a, b, c]);
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_groups() {
+        let file = "\
+            v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+            vt 0 0\nvt 1 0\nvt 1 1\n\
+            o Table\n\
+            s 1\n\
+            f 1/1 2/2 3/3\nf 1/1 3/3 4\n\
+            g Chair\n\
+            s off\n\
+            f -4 -3 -2\n\
+            o Table\n\
+            f 1 2 4\n";
+        let model = parse(file.as_bytes(), "room").unwrap();
+        let names: Vec<_> = model.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+        assert_eq!(names, ["Table", "Chair"]);
+
+        // Smoothed corners share vertices; the flat face added back to Table gets its own
+        let table = &model.meshes[0];
+        assert_eq!(table.indices.len(), 9);
+        assert_eq!(table.vertices.len(), 4 + 3);
+        assert!(table.vertices.iter().all(|vertex| (Vec3::from(vertex.normal) - Vec3::Z).length() < 1e-5));
+        // Negative indices count back from the latest position
+        let chair = &model.meshes[1];
+        assert_eq!(chair.vertices.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), [[0.0; 3], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]);
+
+        assert!(parse("v 0 0 0\n".as_bytes(), "empty").is_err());
+    }
+
+    #[test]
+    fn test_triangulate() {
+        // Convex polygons fan from the first corner
+        let square = [Vec3::ZERO, Vec3::X, Vec3::new(1.0, 1.0, 0.0), Vec3::Y];
+        assert_eq!(triangulate(&square), vec![[0, 1, 2], [0, 2, 3]]);
+
+        // A fan from the reflex corner of this arrowhead would cover the notch
+        let arrow = [
+            Vec3::ZERO,
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.5, 1.0, 0.0),
+        ];
+        let triangles = triangulate(&arrow);
+        assert_eq!(triangles.len(), 2);
+        let area = |triangles: &[[usize; 3]], points: &[Vec3]| -> f32 {
+            triangles.iter()
+                .map(|&[a, b, c]| (points[b] - points[a]).cross(points[c] - points[a]).z / 2.0)
+                .sum()
+        };
+        // Every triangle keeps the polygon's winding and together they cover exactly its area
+        assert!(triangles.iter().all(|&[a, b, c]| (arrow[b] - arrow[a]).cross(arrow[c] - arrow[a]).z > 0.0));
+        assert!((area(&triangles, &arrow) - 1.5).abs() < 1e-5);
+    }
+}
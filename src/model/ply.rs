@@ -0,0 +1,368 @@
+use std::io::BufRead;
+use anyhow::{bail, Context, Result};
+use crate::color::srgb_to_linear;
+
+/// Triangle mesh read from a Stanford PLY file; attributes the file lacks are `None`
+#[derive(Debug, Default)]
+pub struct PlyMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub tex_coords: Option<Vec<[f32; 2]>>,
+    /// Linear RGBA; file colors are taken to be sRGB
+    pub colors: Option<Vec<[f32; 4]>>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => bail!("Unknown PLY property type '{}'", name),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Divisor mapping integer colors to 0..1
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::I8 => i8::MAX as f64,
+            Self::U8 => u8::MAX as f64,
+            Self::I16 => i16::MAX as f64,
+            Self::U16 => u16::MAX as f64,
+            Self::I32 => i32::MAX as f64,
+            Self::U32 => u32::MAX as f64,
+            Self::F32 | Self::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+}
+
+/// Element data after the header
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Scalar) -> Result<f64> {
+        match self {
+            Self::Ascii(tokens) => {
+                let token = tokens.next().context("Unexpected end of PLY data")?;
+                token.parse::<f64>().with_context(|| format!("Invalid PLY value '{}'", token))
+            }
+            Self::Binary { data, big_endian } => {
+                let size = ty.size();
+                if data.len() < size {
+                    bail!("Unexpected end of PLY data");
+                }
+                let (bytes, rest) = data.split_at(size);
+                *data = rest;
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                Ok(match ty {
+                    Scalar::I8 => buffer[0] as i8 as f64,
+                    Scalar::U8 => buffer[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+
+    /// One element instance: each property as a list of values, scalars as single-item lists
+    fn read_element(&mut self, element: &Element) -> Result<Vec<Vec<f64>>> {
+        element.properties.iter()
+            .map(|property| match property.ty {
+                PropertyType::Scalar(ty) => Ok(vec![self.read(ty)?]),
+                PropertyType::List { count, item } => {
+                    let count = self.read(count)? as usize;
+                    (0..count).map(|_| self.read(item)).collect()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse a PLY file's `vertex` and `face` elements; faces are triangulated as fans and
+/// every other element is skipped
+pub fn parse(mut reader: impl BufRead) -> Result<PlyMesh> {
+    // Header
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "ply" {
+        bail!("Not a PLY file");
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("PLY header has no end_header");
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["end_header"] => break,
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => bail!("Unknown PLY format '{}'", name),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().with_context(|| format!("Invalid PLY element count '{}'", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().context("PLY property before any element")?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty: PropertyType::List { count: Scalar::parse(count)?, item: Scalar::parse(item)? },
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().context("PLY property before any element")?;
+                element.properties.push(Property { name: name.to_string(), ty: PropertyType::Scalar(Scalar::parse(ty)?) });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => bail!("Invalid PLY header line '{}'", line.trim()),
+        }
+    }
+    let format = format.context("PLY header has no format")?;
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let text;
+    let mut body = match format {
+        Format::Ascii => {
+            text = String::from_utf8(data).context("ASCII PLY data is not valid UTF-8")?;
+            Body::Ascii(text.split_ascii_whitespace())
+        }
+        Format::BinaryLittleEndian => Body::Binary { data: &data, big_endian: false },
+        Format::BinaryBigEndian => Body::Binary { data: &data, big_endian: true },
+    };
+
+    let mut mesh = PlyMesh::default();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => {
+                let indices = element.property(&["vertex_indices", "vertex_index"])
+                    .context("PLY faces have no vertex_indices")?;
+                for _ in 0..element.count {
+                    let face = body.read_element(element)?;
+                    let face = &face[indices];
+                    for i in 1..face.len().saturating_sub(1) {
+                        mesh.indices.extend([face[0], face[i], face[i + 1]].map(|index| index as u32));
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_element(element)?;
+                }
+            }
+        }
+    }
+
+    if let Some(index) = mesh.indices.iter().find(|index| **index as usize >= mesh.positions.len()) {
+        bail!("PLY face references vertex {} of {}", index, mesh.positions.len());
+    }
+    Ok(mesh)
+}
+
+fn read_vertices(body: &mut Body, element: &Element, mesh: &mut PlyMesh) -> Result<()> {
+    let position = [&["x"], &["y"], &["z"]].map(|names| element.property(names));
+    let [Some(x), Some(y), Some(z)] = position else {
+        bail!("PLY vertices have no x/y/z");
+    };
+    let normal = match [&["nx"], &["ny"], &["nz"]].map(|names| element.property(names)) {
+        [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+        _ => None,
+    };
+    let tex_coord = match [&["u", "s", "texture_u", "texture_s"][..], &["v", "t", "texture_v", "texture_t"][..]]
+        .map(|names| element.property(names))
+    {
+        [Some(u), Some(v)] => Some([u, v]),
+        _ => None,
+    };
+    let color = match [&["red", "r"], &["green", "g"], &["blue", "b"]].map(|names| element.property(names)) {
+        [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+        _ => None,
+    };
+    let alpha = element.property(&["alpha", "a"]);
+    let scale = |index: usize| match element.properties[index].ty {
+        PropertyType::Scalar(ty) => ty.color_scale(),
+        PropertyType::List { .. } => 1.0,
+    };
+
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut colors = Vec::new();
+    for _ in 0..element.count {
+        let values = body.read_element(element)?;
+        let value = |index: usize| values[index].first().copied().unwrap_or(0.0) as f32;
+        mesh.positions.push([value(x), value(y), value(z)]);
+        if let Some(normal) = normal {
+            normals.push(normal.map(value));
+        }
+        if let Some(tex_coord) = tex_coord {
+            tex_coords.push(tex_coord.map(value));
+        }
+        if let Some(color) = color {
+            let [r, g, b] = color.map(|index| srgb_to_linear(value(index) / scale(index) as f32));
+            let a = alpha.map_or(1.0, |index| value(index) / scale(index) as f32);
+            colors.push([r, g, b, a]);
+        }
+    }
+    mesh.normals = normal.map(|_| normals);
+    mesh.tex_coords = tex_coord.map(|_| tex_coords);
+    mesh.colors = color.map(|_| colors);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ascii() {
+        let file = "ply\n\
+            format ascii 1.0\n\
+            comment two triangles from a quad\n\
+            element vertex 4\n\
+            property float x\n\
+            property float y\n\
+            property float z\n\
+            property uchar red\n\
+            property uchar green\n\
+            property uchar blue\n\
+            element face 1\n\
+            property list uchar int vertex_indices\n\
+            end_header\n\
+            0 0 0 255 0 0\n\
+            1 0 0 0 255 0\n\
+            1 1 0 0 0 255\n\
+            0 1 0 255 255 255\n\
+            4 0 1 2 3\n";
+        let mesh = parse(file.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert!(mesh.normals.is_none());
+        let colors = mesh.colors.unwrap();
+        assert_eq!(colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(colors[3], [1.0; 4]);
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let mut file = b"ply\n\
+            format binary_big_endian 1.0\n\
+            element vertex 3\n\
+            property double x\n\
+            property double y\n\
+            property double z\n\
+            property float nz\n\
+            element material 1\n\
+            property list uchar uchar name\n\
+            element face 1\n\
+            property list uchar uint vertex_index\n\
+            end_header\n".to_vec();
+        for position in [[0.0f64, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for value in position {
+                file.extend(value.to_be_bytes());
+            }
+            file.extend(1.0f32.to_be_bytes());
+        }
+        // The unrelated element is skipped
+        file.extend([2, b'a', b'b']);
+        file.push(3);
+        for index in [0u32, 1, 2] {
+            file.extend(index.to_be_bytes());
+        }
+
+        let mesh = parse(file.as_slice()).unwrap();
+        assert_eq!(mesh.positions[1], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        // A partial normal isn't used
+        assert!(mesh.normals.is_none());
+        assert!(mesh.colors.is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("solid cube\n".as_bytes()).is_err());
+        let truncated = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n";
+        assert!(parse(truncated.as_bytes()).is_err());
+        let out_of_range = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n";
+        assert!(parse(out_of_range.as_bytes()).is_err());
+    }
+}
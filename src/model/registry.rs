@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use super::{Material, Model, Texture};
+
+/// Frames a released resource is kept alive so command buffers already submitted can finish with it.
+/// Matches the surface's `desired_maximum_frame_latency` plus the frame being recorded.
+pub const FRAMES_IN_FLIGHT: u64 = 3;
+
+/// Copyable reference to a model owned by an [`AssetRegistry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModelHandle(SlotId);
+
+/// Copyable reference to a standalone material owned by an [`AssetRegistry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MaterialHandle(SlotId);
+
+/// Copyable reference to a texture owned by an [`AssetRegistry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TextureHandle(SlotId);
+
+/// Slot index plus the generation it was issued for, so handles to freed slots never alias new assets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct SlotId {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    refs: u32,
+    asset: Option<T>,
+}
+
+/// Reference-counted storage with slot reuse
+struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self { slots: Vec::new(), free: Vec::new() }
+    }
+}
+
+impl<T> Pool<T> {
+    /// Store an asset holding a single reference
+    fn insert(&mut self, asset: T) -> SlotId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.refs = 1;
+                slot.asset = Some(asset);
+                SlotId { index, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, refs: 1, asset: Some(asset) });
+                SlotId { index: self.slots.len() as u32 - 1, generation: 0 }
+            }
+        }
+    }
+
+    fn slot(&self, id: SlotId) -> Option<&Slot<T>> {
+        self.slots.get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation && slot.asset.is_some())
+    }
+
+    fn slot_mut(&mut self, id: SlotId) -> Option<&mut Slot<T>> {
+        self.slots.get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation && slot.asset.is_some())
+    }
+
+    fn get(&self, id: SlotId) -> Option<&T> {
+        self.slot(id).and_then(|slot| slot.asset.as_ref())
+    }
+
+    fn get_mut(&mut self, id: SlotId) -> Option<&mut T> {
+        self.slot_mut(id).and_then(|slot| slot.asset.as_mut())
+    }
+
+    fn retain(&mut self, id: SlotId) -> bool {
+        self.slot_mut(id).map(|slot| slot.refs += 1).is_some()
+    }
+
+    /// Drop one reference; hands the asset back once none remain and retires the slot's handles
+    fn release(&mut self, id: SlotId) -> Option<T> {
+        let slot = self.slot_mut(id)?;
+        slot.refs -= 1;
+        if slot.refs > 0 {
+            return None;
+        }
+        slot.generation = slot.generation.wrapping_add(1);
+        let asset = slot.asset.take();
+        self.free.push(id.index);
+        asset
+    }
+
+    fn refs(&self, id: SlotId) -> u32 {
+        self.slot(id).map_or(0, |slot| slot.refs)
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+}
+
+/// A material plus the registry textures its bind group samples
+struct MaterialEntry {
+    material: Material,
+    textures: Vec<TextureHandle>,
+}
+
+enum Garbage {
+    Model(Model),
+    Material(Box<Material>),
+    Texture(Texture),
+}
+
+impl Garbage {
+    fn destroy(&self) {
+        match self {
+            Garbage::Model(model) => model.destroy(),
+            Garbage::Material(material) => material.destroy(),
+            Garbage::Texture(texture) => texture.destroy(),
+        }
+    }
+}
+
+/// Owns GPU resources once so any number of scene objects can share them through handles.
+///
+/// Every asset is reference counted. Adding an asset takes the registry's own reference, which
+/// [`unload`](Self::unload) gives up; scene objects and materials take further references.
+/// When the last one goes the asset's buffers and textures are destroyed, `FRAMES_IN_FLIGHT`
+/// calls to [`end_frame`](Self::end_frame) later.
+#[derive(Default)]
+pub struct AssetRegistry {
+    models: Pool<Model>,
+    materials: Pool<MaterialEntry>,
+    textures: Pool<Texture>,
+    frame: u64,
+    deletion_queue: VecDeque<(u64, Garbage)>,
+}
+
+impl AssetRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_model(&mut self, model: Model) -> ModelHandle {
+        ModelHandle(self.models.insert(model))
+    }
+
+    pub fn model(&self, handle: ModelHandle) -> Option<&Model> {
+        self.models.get(handle.0)
+    }
+
+    pub fn model_mut(&mut self, handle: ModelHandle) -> Option<&mut Model> {
+        self.models.get_mut(handle.0)
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialHandle {
+        MaterialHandle(self.materials.insert(MaterialEntry { material, textures: Vec::new() }))
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle.0).map(|entry| &entry.material)
+    }
+
+    pub fn material_mut(&mut self, handle: MaterialHandle) -> Option<&mut Material> {
+        self.materials.get_mut(handle.0).map(|entry| &mut entry.material)
+    }
+
+    pub fn add_texture(&mut self, texture: Texture) -> TextureHandle {
+        TextureHandle(self.textures.insert(texture))
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<&Texture> {
+        self.textures.get(handle.0)
+    }
+
+    /// Create a material sampling registered textures, so several materials can share one upload.
+    /// Without a normal map the diffuse texture is bound in its place, as in `Material::create_bind_group`.
+    /// The material keeps its textures alive. Returns `None` if a texture handle is unknown.
+    pub fn create_material(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        diffuse: TextureHandle,
+        normal: Option<TextureHandle>,
+    ) -> Option<MaterialHandle> {
+        let normal_mapped = normal.is_some();
+        let normal = normal.unwrap_or(diffuse);
+        let diffuse_texture = self.texture(diffuse)?;
+        let normal_texture = self.texture(normal)?;
+
+        let mut material = Material::new(name, None, None);
+        let (bind_group, params_buffer) = material.bind_textures(device, layout, diffuse_texture, normal_texture);
+        material.bind_group = Some(bind_group);
+        material.params_buffer = Some(params_buffer);
+        material.normal_mapped = normal_mapped;
+
+        self.textures.retain(diffuse.0);
+        self.textures.retain(normal.0);
+        let textures = vec![diffuse, normal];
+        Some(MaterialHandle(self.materials.insert(MaterialEntry { material, textures })))
+    }
+
+    /// Take a reference for a new user of the model; false for stale handles
+    pub fn retain_model(&mut self, handle: ModelHandle) -> bool {
+        self.models.retain(handle.0)
+    }
+
+    pub fn release_model(&mut self, handle: ModelHandle) {
+        if let Some(model) = self.models.release(handle.0) {
+            self.defer(Garbage::Model(model));
+        }
+    }
+
+    pub fn retain_material(&mut self, handle: MaterialHandle) -> bool {
+        self.materials.retain(handle.0)
+    }
+
+    pub fn release_material(&mut self, handle: MaterialHandle) {
+        if let Some(entry) = self.materials.release(handle.0) {
+            for texture in entry.textures {
+                self.release_texture(texture);
+            }
+            self.defer(Garbage::Material(Box::new(entry.material)));
+        }
+    }
+
+    pub fn retain_texture(&mut self, handle: TextureHandle) -> bool {
+        self.textures.retain(handle.0)
+    }
+
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        if let Some(texture) = self.textures.release(handle.0) {
+            self.defer(Garbage::Texture(texture));
+        }
+    }
+
+    /// Give up the registry's own reference to a model; it is freed once no object uses it
+    pub fn unload(&mut self, handle: ModelHandle) {
+        self.release_model(handle);
+    }
+
+    /// References currently held on a model, including the registry's own until unloaded
+    pub fn model_refs(&self, handle: ModelHandle) -> u32 {
+        self.models.refs(handle.0)
+    }
+
+    fn defer(&mut self, garbage: Garbage) {
+        self.deletion_queue.push_back((self.frame, garbage));
+    }
+
+    /// Advance the frame counter after submitting a frame and destroy resources released
+    /// long enough ago that no in-flight command buffer can still reference them
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+        while let Some((released, _)) = self.deletion_queue.front() {
+            if self.frame - released < FRAMES_IN_FLIGHT {
+                break;
+            }
+            if let Some((_, garbage)) = self.deletion_queue.pop_front() {
+                garbage.destroy();
+            }
+        }
+    }
+
+    /// Released resources still waiting for in-flight frames
+    pub fn pending_deletions(&self) -> usize {
+        self.deletion_queue.len()
+    }
+
+    pub fn model_count(&self) -> usize {
+        self.models.len()
+    }
+
+    pub fn material_count(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_refcounts_and_reuse() {
+        let mut pool = Pool::default();
+        let first = pool.insert("first");
+        assert!(pool.retain(first));
+        assert_eq!(pool.refs(first), 2);
+
+        assert_eq!(pool.release(first), None);
+        assert_eq!(pool.release(first), Some("first"));
+        assert_eq!(pool.len(), 0);
+        assert!(pool.get(first).is_none());
+        assert!(!pool.retain(first));
+
+        // The slot is reused, but the stale handle doesn't reach the new asset
+        let second = pool.insert("second");
+        assert_eq!(second.index, first.index);
+        assert!(pool.get(first).is_none());
+        assert_eq!(pool.release(first), None);
+        assert_eq!(pool.get(second), Some(&"second"));
+    }
+}
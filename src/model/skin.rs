@@ -0,0 +1,355 @@
+use std::ops::{Add, Mul};
+use glam::{Mat4, Quat, Vec3};
+use super::{Node, MAX_JOINTS};
+
+/// Translation, rotation and scale of one node relative to its parent, the form animation
+/// channels write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodePose {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl NodePose {
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self { translation, rotation, scale }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+/// How a channel moves between keyframes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Hold each keyframe until the next
+    Step,
+    /// Straight lines, and spherical ones for rotations
+    Linear,
+    /// Hermite curves; every keyframe stores an in-tangent, its value and an out-tangent
+    CubicSpline,
+}
+
+/// Keyframe values of a channel, one per key time, or three for cubic splines
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+    /// Translations at 16 bits a component, from `AnimationClip::compress`
+    QuantizedTranslation(QuantizedVec3s),
+    /// Rotations with each component scaled to an `i16`
+    QuantizedRotation(Vec<[i16; 4]>),
+    QuantizedScale(QuantizedVec3s),
+}
+
+/// Vectors stored as 16-bit steps from the smallest of them, a quarter of their `f32` size
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizedVec3s {
+    pub min: Vec3,
+    /// Size of one step on each axis
+    pub step: Vec3,
+    pub values: Vec<[u16; 3]>,
+}
+
+impl QuantizedVec3s {
+    /// Quantize `values`, or `None` if rounding to half a step on every axis could move one
+    /// further than `tolerance`
+    pub fn new(values: &[Vec3], tolerance: f32) -> Option<Self> {
+        let min = values.iter().copied().reduce(Vec3::min)?;
+        let max = values.iter().copied().reduce(Vec3::max)?;
+        let step = (max - min) / u16::MAX as f32;
+        if (step * 0.5).length() > tolerance {
+            return None;
+        }
+        let values = values.iter()
+            .map(|value| {
+                let steps = ((*value - min) / step.max(Vec3::splat(f32::MIN_POSITIVE))).round();
+                steps.to_array().map(|steps| steps as u16)
+            })
+            .collect();
+        Some(Self { min, step, values })
+    }
+
+    pub fn get(&self, index: usize) -> Option<Vec3> {
+        let steps = self.values.get(index)?;
+        Some(self.min + self.step * Vec3::from_array(steps.map(f32::from)))
+    }
+}
+
+/// A unit quaternion with each component scaled to an `i16`
+pub(crate) fn quantize_rotation(rotation: Quat) -> [i16; 4] {
+    rotation.normalize().to_array().map(|component| (component * i16::MAX as f32).round() as i16)
+}
+
+fn dequantize_rotation(rotation: [i16; 4]) -> Quat {
+    Quat::from_array(rotation.map(|component| component as f32 / i16::MAX as f32)).normalize()
+}
+
+/// One animated property of one node
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// Index into the skeleton's nodes
+    pub node: usize,
+    pub interpolation: Interpolation,
+    /// Key times in seconds, ascending
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+/// The keyframes around a time, and how far between them it is
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    from: usize,
+    to: usize,
+    /// 0 at `from`, 1 at `to`
+    t: f32,
+    /// Seconds between the keys, which scales cubic tangents
+    length: f32,
+}
+
+impl Channel {
+    fn span(&self, time: f32) -> Option<Span> {
+        let last = self.times.len().checked_sub(1)?;
+        let next = self.times.partition_point(|&key| key <= time);
+        Some(if next == 0 {
+            Span { from: 0, to: 0, t: 0.0, length: 0.0 }
+        } else if next > last {
+            Span { from: last, to: last, t: 0.0, length: 0.0 }
+        } else {
+            let length = self.times[next] - self.times[next - 1];
+            let t = if length > 0.0 { (time - self.times[next - 1]) / length } else { 0.0 };
+            Span { from: next - 1, to: next, t, length }
+        })
+    }
+
+    /// Write the channel's value at `time` into `pose`; times outside the keys hold the first
+    /// or last value
+    pub fn apply(&self, time: f32, pose: &mut NodePose) {
+        let Some(span) = self.span(time) else {
+            return;
+        };
+        let interpolation = self.interpolation;
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                if let Some(value) = sample(interpolation, span, |i| values.get(i).copied(), Vec3::lerp) {
+                    pose.translation = value;
+                }
+            }
+            Keyframes::Scale(values) => {
+                if let Some(value) = sample(interpolation, span, |i| values.get(i).copied(), Vec3::lerp) {
+                    pose.scale = value;
+                }
+            }
+            Keyframes::Rotation(values) => {
+                if let Some(value) = sample(interpolation, span, |i| values.get(i).copied(), Quat::slerp) {
+                    pose.rotation = value.normalize();
+                }
+            }
+            Keyframes::QuantizedTranslation(values) => {
+                if let Some(value) = sample(interpolation, span, |i| values.get(i), Vec3::lerp) {
+                    pose.translation = value;
+                }
+            }
+            Keyframes::QuantizedScale(values) => {
+                if let Some(value) = sample(interpolation, span, |i| values.get(i), Vec3::lerp) {
+                    pose.scale = value;
+                }
+            }
+            Keyframes::QuantizedRotation(values) => {
+                let value = sample(interpolation, span, |i| values.get(i).copied().map(dequantize_rotation), Quat::slerp);
+                if let Some(value) = value {
+                    pose.rotation = value.normalize();
+                }
+            }
+        }
+    }
+}
+
+/// The value `span` falls on, reading keyframe values through `value`; `None` when the
+/// channel has too few values for its keys
+fn sample<T>(interpolation: Interpolation, span: Span, value: impl Fn(usize) -> Option<T>, lerp: impl Fn(T, T, f32) -> T) -> Option<T>
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    match interpolation {
+        Interpolation::Step => value(span.from),
+        Interpolation::Linear => Some(lerp(value(span.from)?, value(span.to)?, span.t)),
+        Interpolation::CubicSpline => {
+            let from = value(span.from * 3 + 1)?;
+            if span.from == span.to {
+                return Some(from);
+            }
+            let out_tangent = value(span.from * 3 + 2)?;
+            let in_tangent = value(span.to * 3)?;
+            let to = value(span.to * 3 + 1)?;
+            let (t, t2, t3) = (span.t, span.t * span.t, span.t * span.t * span.t);
+            Some(
+                from * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * ((t3 - 2.0 * t2 + t) * span.length)
+                    + to * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * ((t3 - t2) * span.length),
+            )
+        }
+    }
+}
+
+/// A named set of channels played together, e.g. a walk cycle
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// Seconds until the last key
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        let duration = channels.iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self { name: name.to_string(), channels, duration }
+    }
+
+    /// Pose the nodes the clip animates as they are `time` seconds in, leaving the rest
+    pub fn sample(&self, time: f32, pose: &mut [NodePose]) {
+        for channel in &self.channels {
+            if let Some(node) = pose.get_mut(channel.node) {
+                channel.apply(time, node);
+            }
+        }
+    }
+}
+
+/// The joints skinned meshes are bound to, and the clips that move them. Skinned vertices are
+/// in bind space: joint matrices, not their node's transform, place them in the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    /// The file's node hierarchy, parents first; joints and channels refer to it by index
+    pub nodes: Vec<Node>,
+    /// Node of each joint, in the order `SkinVertex::joints` counts them
+    pub joints: Vec<usize>,
+    /// Per joint, from bind space to the joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+    pub clips: Vec<AnimationClip>,
+    /// Transform baked into the skinned vertices since loading, e.g. a unit conversion
+    pub root: Mat4,
+}
+
+impl Skeleton {
+    /// Index of the first clip called `name`
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    /// Every node's pose as the file leaves it
+    pub fn rest_pose(&self) -> Vec<NodePose> {
+        self.nodes.iter().map(|node| NodePose::from_matrix(node.transform)).collect()
+    }
+
+    /// Joint matrices for the nodes posed as `pose`, taking skinned vertices from bind space
+    /// to model space; at most `MAX_JOINTS`, the palette the shader holds
+    pub fn joint_matrices(&self, pose: &[NodePose]) -> Vec<Mat4> {
+        let mut world: Vec<Mat4> = Vec::with_capacity(self.nodes.len());
+        for (node, local) in self.nodes.iter().zip(pose) {
+            let parent = node.parent.and_then(|parent| world.get(parent).copied()).unwrap_or(Mat4::IDENTITY);
+            world.push(parent * local.to_matrix());
+        }
+        let root_inverse = self.root.inverse();
+        self.joints.iter().zip(&self.inverse_bind_matrices)
+            .take(MAX_JOINTS)
+            .map(|(&joint, inverse_bind)| {
+                let joint = world.get(joint).copied().unwrap_or(Mat4::IDENTITY);
+                self.root * joint * *inverse_bind * root_inverse
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(interpolation: Interpolation, times: Vec<f32>, values: Vec<Vec3>) -> Channel {
+        Channel { node: 0, interpolation, times, keyframes: Keyframes::Translation(values) }
+    }
+
+    fn translation_at(channel: &Channel, time: f32) -> Vec3 {
+        let mut pose = NodePose::from_matrix(Mat4::IDENTITY);
+        channel.apply(time, &mut pose);
+        pose.translation
+    }
+
+    #[test]
+    fn test_channel_interpolation() {
+        let values = vec![Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)];
+        let linear = channel(Interpolation::Linear, vec![1.0, 2.0], values.clone());
+        assert_eq!(translation_at(&linear, 0.0), Vec3::ZERO);
+        assert_eq!(translation_at(&linear, 1.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(translation_at(&linear, 3.0), Vec3::new(2.0, 0.0, 0.0));
+
+        let step = channel(Interpolation::Step, vec![1.0, 2.0], values);
+        assert_eq!(translation_at(&step, 1.9), Vec3::ZERO);
+        assert_eq!(translation_at(&step, 2.0), Vec3::new(2.0, 0.0, 0.0));
+
+        // Flat tangents ease in and out, passing the midpoint halfway
+        let cubic = channel(Interpolation::CubicSpline, vec![0.0, 1.0], vec![
+            Vec3::ZERO, Vec3::ZERO, Vec3::ZERO,
+            Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0), Vec3::ZERO,
+        ]);
+        assert!((translation_at(&cubic, 0.5) - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-5);
+        assert!(translation_at(&cubic, 0.1).x < 0.2);
+
+        let rotation = Channel {
+            node: 0,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 1.0],
+            keyframes: Keyframes::Rotation(vec![Quat::IDENTITY, Quat::from_rotation_y(std::f32::consts::PI)]),
+        };
+        let mut pose = NodePose::from_matrix(Mat4::IDENTITY);
+        rotation.apply(0.5, &mut pose);
+        assert!(pose.rotation.angle_between(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)) < 1e-4);
+    }
+
+    #[test]
+    fn test_joint_matrices() {
+        let node = |parent: Option<usize>, transform: Mat4| Node { name: String::new(), parent, transform, meshes: Vec::new() };
+        let mut skeleton = Skeleton {
+            nodes: vec![
+                node(None, Mat4::IDENTITY),
+                node(Some(0), Mat4::from_translation(Vec3::Y)),
+            ],
+            joints: vec![0, 1],
+            inverse_bind_matrices: vec![Mat4::IDENTITY, Mat4::from_translation(-Vec3::Y)],
+            clips: vec![AnimationClip::new("lift", vec![Channel {
+                node: 1,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, 1.0],
+                keyframes: Keyframes::Translation(vec![Vec3::Y, Vec3::new(0.0, 2.0, 0.0)]),
+            }])],
+            root: Mat4::IDENTITY,
+        };
+        assert_eq!(skeleton.clip("lift"), Some(0));
+        assert_eq!(skeleton.clips[0].duration, 1.0);
+
+        // The bind pose leaves vertices where they are
+        let rest = skeleton.rest_pose();
+        for matrix in skeleton.joint_matrices(&rest) {
+            assert!(matrix.abs_diff_eq(Mat4::IDENTITY, 1e-5));
+        }
+
+        // Halfway through, the second joint has risen half a unit
+        let mut pose = rest;
+        skeleton.clips[0].sample(0.5, &mut pose);
+        let matrices = skeleton.joint_matrices(&pose);
+        assert!((matrices[1].transform_point3(Vec3::Y) - Vec3::new(0.0, 1.5, 0.0)).length() < 1e-5);
+
+        // A scale baked into the vertices scales the motion with them
+        skeleton.root = Mat4::from_scale(Vec3::splat(2.0));
+        let matrices = skeleton.joint_matrices(&pose);
+        assert!((matrices[1].transform_point3(Vec3::new(0.0, 2.0, 0.0)) - Vec3::new(0.0, 3.0, 0.0)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::Result;
+use rayon::prelude::*;
+use super::{ImageData, ModelData, Texture};
+use super::mapped::{BufferBytes, GltfSource};
+
+/// Which of a material's textures an image is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
+}
+
+/// A texture decoded after its model was loaded, for material `material` of that model
+#[derive(Clone)]
+pub struct StreamedTexture {
+    pub material: usize,
+    pub slot: TextureSlot,
+    /// `None` when the image couldn't be decoded; the material then drops the texture
+    pub image: Option<ImageData>,
+}
+
+/// A glTF file's encoded images and what's needed to decode them
+struct GltfImages {
+    document: gltf::Document,
+    buffers: Vec<BufferBytes>,
+    base: Option<PathBuf>,
+}
+
+impl GltfImages {
+    /// Decode one image. Images inside the file are decoded straight from their buffer view,
+    /// so only the pages of the image being decoded are read from a mapped file.
+    fn decode(&self, index: usize) -> Result<ImageData> {
+        let image = self.document.images().nth(index)
+            .ok_or_else(|| anyhow::anyhow!("No image {} in the file", index))?;
+        match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let encoded = GltfSource::view(&self.buffers, &view)?;
+                let rgba = image::load_from_memory(encoded)?.to_rgba8();
+                Ok(ImageData { width: rgba.width(), height: rgba.height(), pixels: rgba.into_raw() })
+            }
+            // URIs never index the buffers
+            source => {
+                let image = gltf::image::Data::from_source(source, self.base.as_deref(), &[])?;
+                Ok(ImageData { width: image.width, height: image.height, pixels: Texture::gltf_into_rgba(image) })
+            }
+        }
+    }
+}
+
+/// The images of a model loaded with `ModelData::load_streamed`, still to be decoded
+#[derive(Default)]
+pub struct DeferredImages {
+    source: Option<GltfImages>,
+    /// Material, slot and image index of each texture
+    requests: Vec<(usize, TextureSlot, usize)>,
+}
+
+impl DeferredImages {
+    pub(super) fn gltf(document: gltf::Document, buffers: Vec<BufferBytes>, base: Option<PathBuf>, requests: Vec<(usize, TextureSlot, usize)>) -> Self {
+        Self { source: Some(GltfImages { document, buffers, base }), requests }
+    }
+
+    /// Textures still to come
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Materials waiting for their diffuse texture, which should show a placeholder meanwhile
+    pub fn waiting_materials(&self) -> Vec<usize> {
+        let mut materials: Vec<_> = self.requests.iter()
+            .filter(|(_, slot, _)| *slot == TextureSlot::Diffuse)
+            .map(|(material, _, _)| *material)
+            .collect();
+        materials.dedup();
+        materials
+    }
+
+    /// Requests grouped by image, so an image used by several materials is decoded once
+    fn by_image(&self) -> Vec<(usize, Vec<(usize, TextureSlot)>)> {
+        let mut images: BTreeMap<usize, Vec<(usize, TextureSlot)>> = BTreeMap::new();
+        for &(material, slot, image) in &self.requests {
+            images.entry(image).or_default().push((material, slot));
+        }
+        images.into_iter().collect()
+    }
+
+    /// Decode the images in parallel, handing every texture to `sink` as soon as its image is
+    /// ready. Images that fail to decode are logged and handed over as `None`.
+    pub fn decode(self, sink: impl Fn(StreamedTexture) + Sync) {
+        let Some(source) = &self.source else {
+            return;
+        };
+        self.by_image().into_par_iter().for_each(|(index, requests)| {
+            let image = source.decode(index)
+                .map_err(|e| log::warn!("Failed to decode image {}: {:#}", index, e))
+                .ok();
+            for (material, slot) in requests {
+                sink(StreamedTexture { material, slot, image: image.clone() });
+            }
+        });
+    }
+
+    /// Decode every image in parallel, failing if any can't be
+    pub fn decode_all(self) -> Result<Vec<StreamedTexture>> {
+        let Some(source) = &self.source else {
+            return Ok(Vec::new());
+        };
+        let decoded = self.by_image().into_par_iter()
+            .map(|(index, requests)| -> Result<Vec<StreamedTexture>> {
+                let image = source.decode(index)?;
+                Ok(requests.into_iter()
+                    .map(|(material, slot)| StreamedTexture { material, slot, image: Some(image.clone()) })
+                    .collect())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(decoded.into_iter().flatten().collect())
+    }
+}
+
+impl ModelData {
+    /// Put a decoded texture into its material
+    pub fn apply_texture(&mut self, texture: StreamedTexture) {
+        let Some(material) = self.materials.get_mut(texture.material) else {
+            return;
+        };
+        match texture.slot {
+            TextureSlot::Diffuse => material.diffuse = texture.image,
+            TextureSlot::Normal => material.normal = texture.image,
+        }
+    }
+}
@@ -5,6 +5,8 @@ use pollster::FutureExt;
 use wgpu::Instance;
 use assert_fs::prelude::*;
 use image::GenericImageView;
+use crate::color::Color;
+use crate::readback::read_buffer_range;
 
 fn create_test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
     let instance = Instance::default();
@@ -68,6 +70,16 @@ fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
                 ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                 count: None,
             },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     })
 }
@@ -83,8 +95,8 @@ fn test_models_path() -> PathBuf {
 fn test_model_vertex_size() {
     assert_eq!(
         std::mem::size_of::<ModelVertex>(),
-        48,  // 3 * 4 (position) + 2 * 4 (tex_coords) + 3 * 4 (normal) + 4 * 4 (tangent) = 48 bytes
-        "ModelVertex size should be 48 bytes"
+        64,  // 3 * 4 (position) + 2 * 4 (tex_coords) + 3 * 4 (normal) + 4 * 4 (tangent) + 4 * 4 (color) = 64 bytes
+        "ModelVertex size should be 64 bytes"
     );
 }
 
@@ -166,12 +178,7 @@ fn test_load_obj() {
         };
         
         // Create a material with the default texture
-        let mut material = Material {
-            name: "default".to_string(),
-            diffuse_texture: Some(default_texture),
-            normal_texture: None,
-            bind_group: None,
-        };
+        let mut material = Material::new("default", Some(default_texture), None);
         material.create_bind_group(&device, &bind_group_layout);
         
         // Load the model
@@ -195,6 +202,68 @@ fn test_load_obj() {
     }
 }
 
+/// An OBJ of a grid of quads, each split into two triangles, whose corners share vertices
+fn grid_obj(columns: usize, rows: usize) -> String {
+    let mut obj = String::new();
+    for y in 0..=rows {
+        for x in 0..=columns {
+            obj += &format!("v {} {} 0\nvt {} {}\n", x, y, x as f32 / columns as f32, y as f32 / rows as f32);
+        }
+    }
+    obj += "vn 0 0 1\n";
+    let corner = |x: usize, y: usize| {
+        let index = y * (columns + 1) + x + 1;
+        format!("{}/{}/1", index, index)
+    };
+    for y in 0..rows {
+        for x in 0..columns {
+            obj += &format!("f {} {} {}\n", corner(x, y), corner(x + 1, y), corner(x + 1, y + 1));
+            obj += &format!("f {} {} {}\n", corner(x, y), corner(x + 1, y + 1), corner(x, y + 1));
+        }
+    }
+    obj
+}
+
+#[test]
+fn test_load_large_obj() {
+    // 100k faces sharing 50k vertices; scanning every earlier vertex for each face corner
+    // took minutes at this size
+    let (columns, rows) = (250, 200);
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("grid.obj");
+    file.write_str(&grid_obj(columns, rows)).unwrap();
+
+    let data = ModelData::load(file.path()).unwrap();
+    assert_eq!(data.meshes[0].vertices.len(), (columns + 1) * (rows + 1));
+    assert_eq!(data.meshes[0].indices.len(), columns * rows * 6);
+}
+
+/// Timing for OBJ loading at scale: `cargo test --release -- --ignored bench_load_large_obj --nocapture`
+#[test]
+#[ignore]
+fn bench_load_large_obj() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("grid.obj");
+    file.write_str(&grid_obj(1000, 500)).unwrap();
+
+    let start = std::time::Instant::now();
+    let data = ModelData::load(file.path()).unwrap();
+    println!("Loaded {} OBJ faces in {:?}", data.meshes[0].indices.len() / 3, start.elapsed());
+}
+
+#[test]
+fn test_load_obj_defaults() {
+    // Corners without normals get their face's, and bad indices are errors rather than panics
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plain = temp.child("plain.obj");
+    plain.write_str("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -1\n").unwrap();
+    let data = ModelData::load(plain.path()).unwrap();
+    assert_eq!(data.meshes[0].vertices[2].normal, [0.0, 0.0, 1.0]);
+    let broken = temp.child("broken.obj");
+    broken.write_str("v 0 0 0\nf 1 2 3\n").unwrap();
+    assert!(ModelData::load(broken.path()).is_err());
+}
+
 #[test]
 fn test_load_gltf() {
     if let Some((device, queue)) = create_test_device() {
@@ -229,6 +298,336 @@ fn test_load_glb() {
     }
 }
 
+#[test]
+fn test_streamed_textures() {
+    // The geometry comes back with its texture still encoded
+    let path = test_models_path().join("cube.glb");
+    let (data, images) = ModelData::load_streamed(&path).unwrap();
+    assert!(data.materials[0].diffuse.is_none());
+    assert_eq!(images.len(), 1);
+    assert_eq!(images.waiting_materials(), vec![0]);
+
+    let textures = std::sync::Mutex::new(Vec::new());
+    images.decode(|texture| textures.lock().unwrap().push(texture));
+    let textures = textures.into_inner().unwrap();
+    assert_eq!(textures.len(), 1);
+    let loaded = ModelData::load(&path).unwrap();
+    let expected = loaded.materials[0].diffuse.as_ref().unwrap();
+    assert_eq!(textures[0].image.as_ref().unwrap().pixels, expected.pixels);
+
+    if let Some((device, queue)) = create_test_device() {
+        let bind_group_layout = create_bind_group_layout(&device);
+        // Waiting materials show the placeholder, then swap in the real texture
+        let mut model = Model::from_data_streamed(&device, &queue, &data, &bind_group_layout, &[0]);
+        let size = |model: &Model| model.materials[0].diffuse_texture.as_ref().unwrap().texture.size();
+        assert_eq!((size(&model).width, size(&model).height), (8, 8));
+        model.apply_streamed_texture(&device, &queue, &bind_group_layout, &textures[0]);
+        assert_eq!((size(&model).width, size(&model).height), (expected.width, expected.height));
+        assert!(model.materials[0].bind_group.is_some());
+
+        // An image that failed to decode leaves the material white rather than checkered
+        let failed = StreamedTexture { image: None, ..textures[0].clone() };
+        model.apply_streamed_texture(&device, &queue, &bind_group_layout, &failed);
+        assert_eq!(size(&model).width, 1);
+    } else {
+        println!("Skipping test 'test_streamed_textures' - no suitable GPU adapter available");
+    }
+}
+
+#[test]
+fn test_mapped_glb_matches_import() {
+    // Reading out of the mapped file gives what the gltf crate's own importer reads
+    let path = test_models_path().join("cube.glb");
+    let data = ModelData::load(&path).unwrap();
+    let (document, buffers, images) = gltf::import(&path).unwrap();
+    let primitive = document.meshes().next().unwrap().primitives().next().unwrap();
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+    let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+
+    let mesh = &data.meshes[0];
+    assert_eq!(mesh.vertices.iter().map(|vertex| vertex.position).collect::<Vec<_>>(), positions);
+    assert_eq!(mesh.indices, indices);
+    let expected = Texture::gltf_into_rgba(images.into_iter().next().unwrap());
+    assert_eq!(data.materials[0].diffuse.as_ref().unwrap().pixels, expected);
+}
+
+#[test]
+fn test_load_gltf_vertex_colors() {
+    // Files without COLOR_0 are white
+    let data = ModelData::load(test_models_path().join("cube.gltf")).unwrap();
+    assert!(data.meshes[0].vertices.iter().all(|vertex| vertex.color == ModelVertex::WHITE));
+
+    // One triangle with float positions and colors in an external buffer
+    let positions: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let colors: [[f32; 4]; 3] = [[1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 0.5], [0.0, 0.0, 1.0, 1.0]];
+    let indices: [u32; 3] = [0, 1, 2];
+    let mut buffer = bytemuck::cast_slice::<_, u8>(&positions).to_vec();
+    buffer.extend_from_slice(bytemuck::cast_slice(&colors));
+    buffer.extend_from_slice(bytemuck::cast_slice(&indices));
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("triangle.bin").write_binary(&buffer).unwrap();
+    let gltf = temp.child("triangle.gltf");
+    gltf.write_str(r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "uri": "triangle.bin", "byteLength": 96 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 48 },
+            { "buffer": 0, "byteOffset": 84, "byteLength": 12 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC4" },
+            { "bufferView": 2, "componentType": 5125, "count": 3, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "COLOR_0": 1 }, "indices": 2 }] }],
+        "nodes": [{ "mesh": 0 }],
+        "scenes": [{ "nodes": [0] }]
+    }"#).unwrap();
+
+    let data = ModelData::load(gltf.path()).unwrap();
+    let loaded: Vec<[f32; 4]> = data.meshes[0].vertices.iter().map(|vertex| vertex.color).collect();
+    assert_eq!(loaded, colors.to_vec());
+}
+
+#[test]
+fn test_load_gltf_hierarchy() {
+    // One triangle drawn by a translated root and, instanced, by its child
+    let positions: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let indices: [u32; 3] = [0, 1, 2];
+    let mut buffer = bytemuck::cast_slice::<_, u8>(&positions).to_vec();
+    buffer.extend_from_slice(bytemuck::cast_slice(&indices));
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("nodes.bin").write_binary(&buffer).unwrap();
+    let gltf = temp.child("nodes.gltf");
+    gltf.write_str(r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "uri": "nodes.bin", "byteLength": 48 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 12 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+        "nodes": [
+            { "name": "root", "mesh": 0, "translation": [2, 0, 0], "children": [1] },
+            { "name": "child", "mesh": 0, "translation": [0, 3, 0] }
+        ],
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }]
+    }"#).unwrap();
+
+    // Flattened, the instance is copied into place under its parent
+    let data = ModelData::load(gltf.path()).unwrap();
+    assert!(data.nodes.is_empty());
+    assert_eq!(data.meshes.len(), 2);
+    assert_eq!(data.meshes[1].vertices[0].position, [2.0, 3.0, 0.0]);
+    assert_eq!((data.bounds_min, data.bounds_max), ([2.0, 0.0, 0.0], [3.0, 4.0, 0.0]));
+
+    // Kept as a hierarchy, the mesh is stored once and both nodes share one part
+    let data = ModelData::load_hierarchy(gltf.path()).unwrap();
+    assert_eq!(data.meshes.len(), 1);
+    let names: Vec<_> = data.nodes.iter().map(|node| (node.name.as_str(), node.parent)).collect();
+    assert_eq!(names, vec![("root", None), ("child", Some(0))]);
+    let matrices = world_matrices(&data.nodes);
+    assert_eq!(matrices[1].w_axis.truncate(), glam::Vec3::new(2.0, 3.0, 0.0));
+    assert_eq!((data.bounds_min, data.bounds_max), ([2.0, 0.0, 0.0], [3.0, 4.0, 0.0]));
+    let (parts, node_parts) = data.split_nodes();
+    assert_eq!(parts.len(), 1);
+    assert_eq!(node_parts, vec![Some(0), Some(0)]);
+
+    // Unit conversion scales the roots rather than the local meshes
+    let mut data = data;
+    data.scale(0.5);
+    assert_eq!(data.meshes[0].vertices[1].position, [1.0, 0.0, 0.0]);
+    assert_eq!(world_matrices(&data.nodes)[1].w_axis.truncate(), glam::Vec3::new(1.0, 1.5, 0.0));
+}
+
+#[test]
+fn test_load_gltf_skin() {
+    use glam::{Mat4, Vec3};
+    // A triangle whose top vertex hangs off a bone, which a clip raises by one unit
+    let positions: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    let indices: [u32; 3] = [0, 1, 2];
+    let joints: [[u16; 4]; 3] = [[0, 0, 0, 0], [0, 0, 0, 0], [1, 0, 0, 0]];
+    let weights: [[f32; 4]; 3] = [[1.0, 0.0, 0.0, 0.0]; 3];
+    let inverse_binds = [Mat4::IDENTITY, Mat4::from_translation(-Vec3::Y)].map(|matrix| matrix.to_cols_array());
+    let times: [f32; 2] = [0.0, 1.0];
+    let translations: [[f32; 3]; 2] = [[0.0, 1.0, 0.0], [0.0, 2.0, 0.0]];
+    let mut buffer = bytemuck::cast_slice::<_, u8>(&positions).to_vec();
+    buffer.extend_from_slice(bytemuck::cast_slice(&indices));
+    buffer.extend_from_slice(bytemuck::cast_slice(&joints));
+    buffer.extend_from_slice(bytemuck::cast_slice(&weights));
+    buffer.extend_from_slice(bytemuck::cast_slice(&inverse_binds));
+    buffer.extend_from_slice(bytemuck::cast_slice(&times));
+    buffer.extend_from_slice(bytemuck::cast_slice(&translations));
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.child("skin.bin").write_binary(&buffer).unwrap();
+    let gltf = temp.child("skin.gltf");
+    gltf.write_str(r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "uri": "skin.bin", "byteLength": 280 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+            { "buffer": 0, "byteOffset": 48, "byteLength": 24 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 48 },
+            { "buffer": 0, "byteOffset": 120, "byteLength": 128 },
+            { "buffer": 0, "byteOffset": 248, "byteLength": 8 },
+            { "buffer": 0, "byteOffset": 256, "byteLength": 24 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" },
+            { "bufferView": 2, "componentType": 5123, "count": 3, "type": "VEC4" },
+            { "bufferView": 3, "componentType": 5126, "count": 3, "type": "VEC4" },
+            { "bufferView": 4, "componentType": 5126, "count": 2, "type": "MAT4" },
+            { "bufferView": 5, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [1] },
+            { "bufferView": 6, "componentType": 5126, "count": 2, "type": "VEC3" }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "JOINTS_0": 2, "WEIGHTS_0": 3 }, "indices": 1 }] }],
+        "skins": [{ "joints": [1, 2], "inverseBindMatrices": 4 }],
+        "animations": [{
+            "name": "raise",
+            "samplers": [{ "input": 5, "output": 6, "interpolation": "LINEAR" }],
+            "channels": [{ "sampler": 0, "target": { "node": 2, "path": "translation" } }]
+        }],
+        "nodes": [
+            { "name": "body", "mesh": 0, "skin": 0, "translation": [5, 0, 0] },
+            { "name": "root", "children": [2] },
+            { "name": "bone", "translation": [0, 1, 0] }
+        ],
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 1] }]
+    }"#).unwrap();
+
+    // The skinned mesh stays in bind space, ignoring its node's translation
+    let mut data = ModelData::load(gltf.path()).unwrap();
+    assert_eq!(data.meshes.len(), 1);
+    assert_eq!(data.meshes[0].vertices[1].position, [1.0, 0.0, 0.0]);
+    let skin = data.meshes[0].skin.as_ref().expect("joints and weights");
+    assert_eq!(skin[2].joints, [1, 0, 0, 0]);
+
+    let skeleton = data.skeleton.as_ref().expect("skeleton");
+    assert_eq!(skeleton.joints, vec![1, 2]);
+    let clip = skeleton.clip("raise").unwrap();
+    assert_eq!(skeleton.clips[clip].duration, 1.0);
+    let mut pose = skeleton.rest_pose();
+    skeleton.clips[clip].sample(0.5, &mut pose);
+    let matrices = skeleton.joint_matrices(&pose);
+    assert!((matrices[1].transform_point3(Vec3::Y) - Vec3::new(0.0, 1.5, 0.0)).length() < 1e-5);
+
+    // Unit conversion scales the motion along with the vertices
+    data.scale(2.0);
+    let skeleton = data.skeleton.as_ref().unwrap();
+    let matrices = skeleton.joint_matrices(&pose);
+    assert_eq!(data.meshes[0].vertices[2].position, [0.0, 2.0, 0.0]);
+    assert!((matrices[1].transform_point3(Vec3::new(0.0, 2.0, 0.0)) - Vec3::new(0.0, 3.0, 0.0)).length() < 1e-5);
+}
+
+#[test]
+fn test_load_ply() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("quad.PLY");
+    file.write_str("ply\n\
+        format ascii 1.0\n\
+        element vertex 4\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property uchar red\n\
+        property uchar green\n\
+        property uchar blue\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n\
+        0 0 0 255 0 0\n\
+        1 0 0 0 255 0\n\
+        1 0 -1 0 0 255\n\
+        0 0 -1 0 0 0\n\
+        4 0 1 2 3\n").unwrap();
+    assert!(ModelData::is_supported(file.path()));
+
+    let data = ModelData::load(file.path()).unwrap();
+    let mesh = &data.meshes[0];
+    assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    assert_eq!(data.bounds_min, [0.0, 0.0, -1.0]);
+    assert_eq!(mesh.vertices[1].color, [0.0, 1.0, 0.0, 1.0]);
+    assert_eq!(mesh.vertices[3].color, [0.0, 0.0, 0.0, 1.0]);
+    // The file has no normals; the counter-clockwise quad faces up
+    assert!(mesh.vertices.iter().all(|vertex| vertex.normal == [0.0, 1.0, 0.0]));
+
+    // Point clouds can't be drawn
+    let points = temp.child("points.ply");
+    points.write_str("ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n").unwrap();
+    assert!(ModelData::load(points.path()).is_err());
+}
+
+/// Fixtures in `tests/models/corpus`, each checked against the `.snap` file beside it. There's
+/// no Draco fixture: the glTF loader has no Draco decoder.
+const LOADER_CORPUS: [&str; 5] = ["quad.obj", "multi_material.obj", "negative_index.obj", "interleaved.gltf", "missing_normals.gltf"];
+
+/// What a loader produced, in the text form the corpus snapshots are kept in
+fn loader_snapshot(data: &ModelData) -> String {
+    // Adding zero turns -0.0 into 0.0, which would otherwise print with a sign
+    let vector = |values: &[f32]| {
+        let values: Vec<String> = values.iter().map(|value| format!("{:.3}", value + 0.0)).collect();
+        format!("[{}]", values.join(", "))
+    };
+    let mut snapshot = String::new();
+    let units = data.units.map_or("none".to_string(), |unit| format!("{:?}", unit).to_lowercase());
+    snapshot += &format!("units: {}\n", units);
+    snapshot += &format!("bounds: {} .. {}\n", vector(&data.bounds_min), vector(&data.bounds_max));
+    snapshot += &format!("meshes: {}\n", data.meshes.len());
+    for mesh in &data.meshes {
+        let normal = mesh.vertices.iter().fold(glam::Vec3::ZERO, |sum, vertex| sum + glam::Vec3::from(vertex.normal))
+            / mesh.vertices.len().max(1) as f32;
+        snapshot += &format!(
+            "  {:?}: {} vertices, {} indices, material {}, mean normal {}\n",
+            mesh.name, mesh.vertices.len(), mesh.indices.len(), mesh.material_index, vector(&normal.to_array()),
+        );
+    }
+    snapshot += &format!("materials: {}\n", data.materials.len());
+    for material in &data.materials {
+        snapshot += &format!(
+            "  {:?}: base color {}, diffuse {}, normal map {}\n",
+            material.name, vector(&material.base_color.to_array()), material.diffuse.is_some(), material.normal.is_some(),
+        );
+    }
+    snapshot
+}
+
+#[test]
+fn test_loader_snapshots() {
+    // Set UPDATE_SNAPSHOTS to rewrite the snapshots after an intended change to the loaders
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let corpus = test_models_path().join("corpus");
+    let mut changed = Vec::new();
+    for name in LOADER_CORPUS {
+        let actual = match ModelData::load(corpus.join(name)) {
+            Ok(data) => loader_snapshot(&data),
+            Err(e) => format!("error: {:#}\n", e),
+        };
+        let snapshot_path = corpus.join(format!("{}.snap", name));
+        if update {
+            fs::write(&snapshot_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&snapshot_path).unwrap_or_default();
+        if actual != expected {
+            changed.push(format!("{}\n--- expected\n{}--- actual\n{}", name, expected, actual));
+        }
+    }
+    assert!(changed.is_empty(), "Loader output changed (UPDATE_SNAPSHOTS=1 accepts it):\n{}", changed.join("\n"));
+}
+
 #[test]
 fn test_texture_loading() {
     if let Some((device, queue)) = create_test_device() {
@@ -243,18 +642,173 @@ fn test_texture_loading() {
     }
 }
 
+#[test]
+fn test_texture_mips() {
+    // glTF images in other formats become RGBA8
+    let grey = gltf::image::Data { pixels: vec![10, 20], format: gltf::image::Format::R8, width: 2, height: 1 };
+    assert_eq!(Texture::gltf_into_rgba(grey), vec![10, 10, 10, 255, 20, 20, 20, 255]);
+    let rgb = gltf::image::Data { pixels: vec![1, 2, 3, 4, 5, 6], format: gltf::image::Format::R8G8B8, width: 2, height: 1 };
+    assert_eq!(Texture::gltf_into_rgba(rgb), vec![1, 2, 3, 255, 4, 5, 6, 255]);
+
+    if let Some((device, queue)) = create_test_device() {
+        // A width whose rows aren't a multiple of 256 bytes, red on the left and blue on the right
+        let (width, height) = (300, 7);
+        let pixels: Vec<u8> = (0..height)
+            .flat_map(|_| (0..width).flat_map(|x| if x < width / 2 { [255, 0, 0, 255] } else { [0, 0, 255, 255] }))
+            .collect();
+        let texture = Texture::from_rgba(&device, &queue, width, height, &pixels, Some("mipped"));
+        assert_eq!(texture.texture.mip_level_count(), 9);
+
+        let read = |mip_level: u32| {
+            let size = texture.texture.size().mip_level_size(mip_level, wgpu::TextureDimension::D2);
+            let region = crate::readback::TextureRegion { width: size.width, height: size.height, mip_level, ..crate::readback::TextureRegion::full(&texture.texture) };
+            crate::readback::read_texture_region(&device, &queue, &texture.texture, region).unwrap()
+        };
+        let base = read(0);
+        assert_eq!(base.texel(0, 6), [255, 0, 0, 255]);
+        assert_eq!(base.texel(299, 0), [0, 0, 255, 255]);
+        // Each level averages the one above; the last is a single texel between the two
+        let half = read(1);
+        assert_eq!((half.width, half.height), (150, 3));
+        assert_eq!(half.texel(0, 0), [255, 0, 0, 255]);
+        let last = read(8);
+        assert_eq!((last.width, last.height), (1, 1));
+        assert!(last.texel(0, 0)[0] > 0 && last.texel(0, 0)[2] > 0);
+    } else {
+        println!("Skipping test 'test_texture_mips' - no suitable GPU adapter available");
+    }
+}
+
 #[test]
 fn test_vertex_buffer_layout() {
     let layout = ModelVertex::desc();
-    assert_eq!(layout.array_stride, 48);
+    assert_eq!(layout.array_stride, 64);
     assert_eq!(layout.step_mode, wgpu::VertexStepMode::Vertex);
-    assert_eq!(layout.attributes.len(), 4);
+    assert_eq!(layout.attributes.len(), 5);
     
     // Verify attribute formats
     assert_eq!(layout.attributes[0].format, wgpu::VertexFormat::Float32x3);  // position
     assert_eq!(layout.attributes[1].format, wgpu::VertexFormat::Float32x2);  // tex_coords
     assert_eq!(layout.attributes[2].format, wgpu::VertexFormat::Float32x3);  // normal
     assert_eq!(layout.attributes[3].format, wgpu::VertexFormat::Float32x4);  // tangent
+    assert_eq!(layout.attributes[4].format, wgpu::VertexFormat::Float32x4);  // color
+}
+
+#[test]
+fn test_material_reflectivity() {
+    let mut material = Material::new("shiny", None, None);
+    assert_eq!(material.uniform().reflectivity, 0.0, "Materials are not reflective by default");
+
+    material.reflective = true;
+    material.roughness = 0.25;
+    assert!((material.uniform().reflectivity - 0.75).abs() < f32::EPSILON);
+    assert_eq!(std::mem::size_of::<MaterialUniform>() % 16, 0, "MaterialUniform must be 16-byte aligned");
+}
+
+#[test]
+fn test_material_double_sided() {
+    let mut material = Material::new("leaf", None, None);
+    assert_eq!(material.uniform().double_sided, 0.0, "Materials are single-sided by default");
+    material.double_sided = true;
+    assert_eq!(material.uniform().double_sided, 1.0);
+
+    // glTF materials keep their doubleSided flag
+    let data = ModelData::load(test_models_path().join("cube.gltf")).unwrap();
+    assert!(!data.materials[0].double_sided);
+    let temp = assert_fs::TempDir::new().unwrap();
+    let gltf = temp.child("leaf.gltf");
+    let source = fs::read_to_string(test_models_path().join("cube.gltf")).unwrap()
+        .replace("\"name\": \"Material\",", "\"name\": \"Material\", \"doubleSided\": true,")
+        .replace("\"uri\": \"cube", &format!("\"uri\": \"{}/cube", test_models_path().display()));
+    gltf.write_str(&source).unwrap();
+    let data = ModelData::load(gltf.path()).unwrap();
+    assert!(data.materials[0].double_sided);
+}
+
+#[test]
+fn test_material_wind() {
+    use crate::model::{Wind, WindWeight};
+
+    let mut material = Material::new("grass", None, None);
+    assert_eq!(material.uniform().wind, [0.0; 4], "Materials don't sway by default");
+    material.wind = Some(Wind {
+        direction: glam::Vec2::new(0.0, -2.0),
+        strength: 0.2,
+        gust_frequency: 0.5,
+        weight: WindWeight::VertexColor,
+    });
+    let uniform = material.uniform();
+    assert_eq!(uniform.wind, [0.0, -1.0, 0.2, 0.5], "The direction is normalized");
+    assert_eq!(uniform.wind_weight[0], 0.0);
+    material.wind = Some(Wind { weight: WindWeight::Height(3.0), ..Wind::default() });
+    assert_eq!(&material.uniform().wind_weight[..2], &[1.0, 3.0]);
+}
+
+#[test]
+fn test_material_chroma_key() {
+    use crate::model::ChromaKey;
+
+    let mut material = Material::new("video", None, None);
+    assert_eq!(material.uniform().chroma_key, [0.0; 4]);
+    material.chroma_key = Some(ChromaKey { color: Color::srgb8(0, 255, 0), threshold: 0.2, smoothness: 0.0, spill: 2.0 });
+    let uniform = material.uniform();
+    let expected = [0.0, 1.0, 0.0, 0.2];
+    assert!(uniform.chroma_key.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-5), "The key is compared with sRGB texels");
+    assert!(uniform.chroma_params[0] > 0.0, "Hard edges still fade over a small distance");
+    assert_eq!(uniform.chroma_params[1], 1.0);
+    // Opaque keyed materials cut the backdrop out; transparent ones blend it away
+    assert_eq!(uniform.alpha_cutoff, 0.5);
+    material.transparent = true;
+    assert_eq!(material.uniform().alpha_cutoff, 0.0);
+}
+
+#[test]
+fn test_material_alpha_mask() {
+    let data = ModelData::load(test_models_path().join("cube.gltf")).unwrap();
+    assert_eq!(data.materials[0].alpha_cutoff, None, "Opaque materials have no cutoff");
+
+    // MASK without an explicit cutoff uses the glTF default of 0.5
+    let temp = assert_fs::TempDir::new().unwrap();
+    let source = fs::read_to_string(test_models_path().join("cube.gltf")).unwrap()
+        .replace("\"uri\": \"cube", &format!("\"uri\": \"{}/cube", test_models_path().display()));
+    for (mode, expected) in [("\"alphaMode\": \"MASK\",", 0.5), ("\"alphaMode\": \"MASK\", \"alphaCutoff\": 0.3,", 0.3)] {
+        let gltf = temp.child("fence.gltf");
+        gltf.write_str(&source.replace("\"name\": \"Material\",", &format!("\"name\": \"Material\", {}", mode))).unwrap();
+        let data = ModelData::load(gltf.path()).unwrap();
+        assert_eq!(data.materials[0].alpha_cutoff, Some(expected));
+        assert!(!data.materials[0].transparent);
+    }
+
+    // BLEND is transparent rather than masked
+    let gltf = temp.child("glass.gltf");
+    gltf.write_str(&source.replace("\"name\": \"Material\",", "\"name\": \"Material\", \"alphaMode\": \"BLEND\",")).unwrap();
+    let data = ModelData::load(gltf.path()).unwrap();
+    assert!(data.materials[0].transparent);
+    assert_eq!(data.materials[0].alpha_cutoff, None);
+
+    let mut material = Material::new("fence", None, None);
+    assert_eq!(material.uniform().alpha_cutoff, 0.0);
+    material.alpha_cutoff = Some(0.3);
+    assert_eq!(material.uniform().alpha_cutoff, 0.3);
+}
+
+#[test]
+fn test_material_base_color() {
+    let data = ModelData::load(test_models_path().join("cube.gltf")).unwrap();
+    assert_eq!(data.materials[0].base_color, Color::WHITE);
+
+    // glTF's base color factor is linear and taken as is
+    let temp = assert_fs::TempDir::new().unwrap();
+    let gltf = temp.child("tinted.gltf");
+    let source = fs::read_to_string(test_models_path().join("cube.gltf")).unwrap()
+        .replace("\"metallicFactor\"", "\"baseColorFactor\": [0.5, 0.25, 1.0, 0.8], \"metallicFactor\"")
+        .replace("\"uri\": \"cube", &format!("\"uri\": \"{}/cube", test_models_path().display()));
+    gltf.write_str(&source).unwrap();
+    let data = ModelData::load(gltf.path()).unwrap();
+    assert_eq!(data.materials[0].base_color, Color::linear_rgba(0.5, 0.25, 1.0, 0.8));
+
+    let material = Material { base_color: Color::hex("#ff000080").unwrap(), ..Material::new("tinted", None, None) };
+    assert_eq!(material.uniform().base_color, [1.0, 0.0, 0.0, 128.0 / 255.0]);
 }
 
 #[test]
@@ -265,20 +819,79 @@ fn test_material_bind_group() {
         let diffuse_texture = Texture::from_path(&device, &queue, &path, Some("diffuse_texture")).unwrap();
         let normal_texture = Texture::from_path(&device, &queue, &path, Some("normal_texture")).unwrap();
         
-        let mut material = Material {
-            name: "test_material".to_string(),
-            diffuse_texture: Some(diffuse_texture),
-            normal_texture: Some(normal_texture),
-            bind_group: None,
-        };
+        let mut material = Material::new("test_material", Some(diffuse_texture), Some(normal_texture));
 
         material.create_bind_group(&device, &bind_group_layout);
         assert!(material.bind_group.is_some());
+        assert!(material.params_buffer.is_some());
     } else {
         println!("Skipping test 'test_material_bind_group' - no suitable GPU adapter available");
     }
 }
 
+#[test]
+fn test_dynamic_mesh_updates() {
+    if let Some((device, queue)) = create_test_device() {
+        let vertex = |x: f32, y: f32| ModelVertex {
+            position: [x, y, 0.0],
+            tex_coords: [0.0; 2],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+
+        let triangle = [vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.0)];
+        let mut mesh = DynamicMesh::new(&device, &queue, "wave", &triangle, &[0, 1, 2], 0);
+        assert_eq!((mesh.vertex_count(), mesh.index_count()), (3, 3));
+
+        // Each update swaps buffers; the drawn one holds the new data, growing when needed
+        let quad = [vertex(0.0, 0.0), vertex(2.0, 0.0), vertex(2.0, 3.0), vertex(0.0, 3.0)];
+        mesh.update_vertices(&device, &queue, &quad);
+        mesh.update_indices(&device, &queue, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.index_count(), 6);
+        assert_eq!(mesh.bounds(), ([0.0; 3], [2.0, 3.0, 0.0]));
+        let vertices: Vec<ModelVertex> = read_buffer_range(&device, &queue, &mesh.mesh().vertex_buffer, 0, 4).unwrap();
+        assert_eq!(bytemuck::cast_slice::<_, u8>(&vertices), bytemuck::cast_slice::<_, u8>(&quad));
+        let indices: Vec<u32> = read_buffer_range(&device, &queue, &mesh.mesh().index_buffer, 0, 6).unwrap();
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+
+        // Models draw dynamic meshes after static ones and grow their bounds on request
+        let mut model = Model::from_dynamic(mesh, Material::new("water", None, None));
+        assert_eq!(model.all_meshes().count(), 1);
+        model.dynamic_meshes[0].update_vertices(&device, &queue, &[vertex(-1.0, 5.0), vertex(0.0, 0.0)]);
+        model.update_bounds();
+        assert_eq!((model.bounds_min, model.bounds_max), ([-1.0, 0.0, 0.0], [2.0, 5.0, 0.0]));
+    } else {
+        println!("Skipping test 'test_dynamic_mesh_updates' - no suitable GPU adapter available");
+    }
+}
+
+#[test]
+fn test_registry_shared_textures() {
+    if let Some((device, queue)) = create_test_device() {
+        let bind_group_layout = create_bind_group_layout(&device);
+        let path = test_models_path().join("cube_texture.png");
+        let mut assets = AssetRegistry::new();
+        let texture = assets.add_texture(Texture::from_path(&device, &queue, &path, Some("shared")).unwrap());
+
+        // Two materials over one uploaded texture
+        let first = assets.create_material(&device, &bind_group_layout, "first", texture, None).unwrap();
+        let second = assets.create_material(&device, &bind_group_layout, "second", texture, Some(texture)).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(assets.texture_count(), 1);
+        assert_eq!(assets.material_count(), 2);
+        assert!(assets.material(first).unwrap().bind_group.is_some());
+        assert_eq!(assets.material(second).unwrap().name, "second");
+
+        // Unknown handles are rejected rather than panicking
+        let mut other = AssetRegistry::new();
+        assert!(other.create_material(&device, &bind_group_layout, "missing", texture, None).is_none());
+        assert!(other.material(first).is_none());
+    } else {
+        println!("Skipping test 'test_registry_shared_textures' - no suitable GPU adapter available");
+    }
+}
+
 #[test]
 fn test_extract_glb_textures() {
     // Create output directory if it doesn't exist
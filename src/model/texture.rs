@@ -1,5 +1,5 @@
 use std::path::Path;
-use image::GenericImageView;
+use wgpu::util::DeviceExt;
 use anyhow::Result;
 
 pub struct Texture {
@@ -9,50 +9,153 @@ pub struct Texture {
 }
 
 impl Texture {
+    /// Free the GPU texture now; it must not be sampled afterwards
+    pub(crate) fn destroy(&self) {
+        self.texture.destroy();
+    }
+
+    /// Bytes of GPU memory the texture takes, with every mip level
+    pub fn memory_size(&self) -> u64 {
+        let format = self.texture.format();
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(4) as u64;
+        let size = self.texture.size();
+        (0..self.texture.mip_level_count())
+            .map(|level| {
+                let blocks_x = (size.width >> level).max(1).div_ceil(block_width) as u64;
+                let blocks_y = (size.height >> level).max(1).div_ceil(block_height) as u64;
+                blocks_x * blocks_y * block_size * size.depth_or_array_layers as u64
+            })
+            .sum()
+    }
+
     pub fn from_path(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         path: &Path,
         label: Option<&str>,
     ) -> Result<Self> {
-        let img = image::open(path)?;
-        let dimensions = img.dimensions();
+        let rgba = image::open(path)?.to_rgba8();
+        Ok(Self::from_rgba(device, queue, rgba.width(), rgba.height(), &rgba, label))
+    }
+
+    pub fn from_gltf_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &gltf::image::Data,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let pixels = Self::gltf_pixels_to_rgba(image);
+        Ok(Self::from_rgba(device, queue, image.width, image.height, &pixels, label))
+    }
+
+    /// Expand glTF image data to tightly packed RGBA8
+    pub fn gltf_pixels_to_rgba(image: &gltf::image::Data) -> Vec<u8> {
+        Self::gltf_into_rgba(image.clone())
+    }
+
+    /// `gltf_pixels_to_rgba` reusing the decoded buffer: RGBA8 images are returned as they are
+    /// and RGB8 ones expanded in place. Grey images are spread over RGB, and 16-bit and float
+    /// channels narrowed.
+    pub fn gltf_into_rgba(image: gltf::image::Data) -> Vec<u8> {
+        use gltf::image::Format;
+        let (channels, channel_bytes) = match image.format {
+            Format::R8G8B8A8 => return image.pixels,
+            Format::R8G8B8 => {
+                let mut pixels = image.pixels;
+                let texels = pixels.len() / 3;
+                pixels.resize(texels * 4, 255);
+                // Back to front, so no texel is overwritten before it has moved
+                for texel in (0..texels).rev() {
+                    pixels.copy_within(texel * 3..texel * 3 + 3, texel * 4);
+                    pixels[texel * 4 + 3] = 255;
+                }
+                return pixels;
+            }
+            Format::R8 => (1, 1),
+            Format::R8G8 => (2, 1),
+            Format::R16 => (1, 2),
+            Format::R16G16 => (2, 2),
+            Format::R16G16B16 => (3, 2),
+            Format::R16G16B16A16 => (4, 2),
+            Format::R32G32B32FLOAT => (3, 4),
+            Format::R32G32B32A32FLOAT => (4, 4),
+        };
+        let channel = |texel: &[u8], index: usize| -> u8 {
+            let bytes = &texel[index * channel_bytes..(index + 1) * channel_bytes];
+            match channel_bytes {
+                1 => bytes[0],
+                2 => (u16::from_ne_bytes([bytes[0], bytes[1]]) >> 8) as u8,
+                _ => (f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).clamp(0.0, 1.0) * 255.0).round() as u8,
+            }
+        };
+        image.pixels.chunks_exact(channels * channel_bytes)
+            .flat_map(|texel| match channels {
+                1 => [channel(texel, 0); 3].into_iter().chain([255]),
+                2 => [channel(texel, 0); 3].into_iter().chain([channel(texel, 1)]),
+                3 => [channel(texel, 0), channel(texel, 1), channel(texel, 2)].into_iter().chain([255]),
+                _ => [channel(texel, 0), channel(texel, 1), channel(texel, 2)].into_iter().chain([channel(texel, 3)]),
+            })
+            .collect()
+    }
 
+    /// Upload tightly packed RGBA8 pixels with a full mip chain, built on the CPU and copied
+    /// in with the base level from one staging buffer
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        label: Option<&str>,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
-        let format = wgpu::TextureFormat::Rgba8Unorm;
-        let rgba = img.to_rgba8();
-
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: mip_level_count(width, height),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format,
+            format: wgpu::TextureFormat::Rgba8Unorm,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        let (data, offsets) = mip_chain(width, height, pixels);
+        let staging = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Texture Staging Buffer"),
+            contents: &data,
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Upload Encoder"),
+        });
+        for (level, &offset) in offsets.iter().enumerate() {
+            let level_size = size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            encoder.copy_buffer_to_texture(
+                wgpu::ImageCopyBuffer {
+                    buffer: &staging,
+                    layout: wgpu::ImageDataLayout {
+                        offset,
+                        bytes_per_row: Some(padded_row_bytes(level_size.width)),
+                        rows_per_image: Some(level_size.height),
+                    },
+                },
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                level_size,
+            );
+        }
+        queue.submit(Some(encoder.finish()));
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -65,42 +168,19 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
     }
 
-    pub fn from_gltf_image(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        image: &gltf::image::Data,
-        label: Option<&str>,
-    ) -> Result<Self> {
-        let dimensions = (image.width, image.height);
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-
-        // Convert RGB to RGBA if needed
-        let (pixels, bytes_per_row) = if image.pixels.len() == (dimensions.0 * dimensions.1 * 3) as usize {
-            println!("Converting RGB to RGBA");
-            let mut rgba = Vec::with_capacity((dimensions.0 * dimensions.1 * 4) as usize);
-            for chunk in image.pixels.chunks(3) {
-                rgba.extend_from_slice(chunk);
-                rgba.push(255); // Alpha channel
-            }
-            (rgba, 4 * dimensions.0)
-        } else {
-            (image.pixels.to_vec(), 4 * dimensions.0)
-        };
-
+    /// A black texture with a single mip level, for content rewritten every frame such as video;
+    /// fill it with `write_rgba`
+    pub fn streaming(device: &wgpu::Device, width: u32, height: u32, label: Option<&str>) -> Self {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
-            size,
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
@@ -108,23 +188,6 @@ impl Texture {
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
-
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &pixels,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(bytes_per_row),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -132,15 +195,44 @@ impl Texture {
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
+        Self { texture, view, sampler }
+    }
 
-        Ok(Self {
-            texture,
-            view,
-            sampler,
-        })
+    /// Overwrite the base level with tightly packed RGBA8 pixels of the texture's size; other
+    /// mip levels are left as they were
+    pub fn write_rgba(&self, queue: &wgpu::Queue, pixels: &[u8]) {
+        let size = self.texture.size();
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * 4),
+                rows_per_image: Some(size.height),
+            },
+            wgpu::Extent3d { depth_or_array_layers: 1, ..size },
+        );
+    }
+
+    /// A single white texel, sampled by untextured materials
+    pub fn white(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::from_rgba(device, queue, 1, 1, &[255; 4], Some("white_texture"))
+    }
+
+    /// A neutral grey checker shown while a material's texture is still loading, so surfaces
+    /// are never black or missing and read as unfinished rather than as untextured
+    pub fn placeholder(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        const SIZE: u32 = 8;
+        let pixels: Vec<u8> = (0..SIZE * SIZE)
+            .flat_map(|i| {
+                let grey = if (i % SIZE + i / SIZE).is_multiple_of(2) { 150 } else { 190 };
+                [grey, grey, grey, 255]
+            })
+            .collect();
+        Self::from_rgba(device, queue, SIZE, SIZE, &pixels, Some("placeholder_texture"))
     }
 
     pub fn clone_with_device(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
@@ -159,21 +251,23 @@ impl Texture {
             label: Some("Texture Copy Encoder"),
         });
 
-        encoder.copy_texture_to_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            self.texture.size(),
-        );
+        for level in 0..self.texture.mip_level_count() {
+            encoder.copy_texture_to_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &self.texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                self.texture.size().mip_level_size(level, self.texture.dimension()),
+            );
+        }
 
         queue.submit(Some(encoder.finish()));
 
@@ -194,4 +288,63 @@ impl Texture {
             sampler,
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Mip levels from `width` x `height` down to 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Bytes per row of an RGBA8 level in a staging buffer; buffer copies need rows padded to
+/// COPY_BYTES_PER_ROW_ALIGNMENT whatever the width
+fn padded_row_bytes(width: u32) -> u32 {
+    (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+/// Every mip level of an RGBA8 image laid out for buffer copies, with the offset of each level.
+/// Levels are 2x2 box filtered from the one above, repeating the last row or column of odd
+/// sizes. Short pixel data is padded with transparent black rather than read out of bounds.
+fn mip_chain(width: u32, height: u32, pixels: &[u8]) -> (Vec<u8>, Vec<u64>) {
+    let expected = (width * height * 4) as usize;
+    if pixels.len() != expected {
+        log::warn!("{}x{} texture has {} bytes of pixels, expected {}", width, height, pixels.len(), expected);
+    }
+    let levels = mip_level_count(width, height);
+    let mut data = Vec::new();
+    let mut offsets = Vec::with_capacity(levels as usize);
+    // Two scratch levels, swapped as the chain goes down
+    let mut level = pixels.to_vec();
+    level.resize(expected, 0);
+    let mut next = Vec::with_capacity(expected / 4);
+    let (mut level_width, mut level_height) = (width.max(1), height.max(1));
+    for index in 0..levels {
+        offsets.push(data.len() as u64);
+        let padding = (padded_row_bytes(level_width) - level_width * 4) as usize;
+        for row in level.chunks_exact((level_width * 4) as usize) {
+            data.extend_from_slice(row);
+            data.resize(data.len() + padding, 0);
+        }
+        if index + 1 == levels {
+            break;
+        }
+
+        let (next_width, next_height) = ((level_width / 2).max(1), (level_height / 2).max(1));
+        next.clear();
+        for y in 0..next_height {
+            let rows = [(2 * y).min(level_height - 1), (2 * y + 1).min(level_height - 1)];
+            for x in 0..next_width {
+                let columns = [(2 * x).min(level_width - 1), (2 * x + 1).min(level_width - 1)];
+                for channel in 0..4 {
+                    let sum: u32 = rows.iter()
+                        .flat_map(|&row| columns.map(|column| ((row * level_width + column) * 4 + channel) as usize))
+                        .map(|i| level[i] as u32)
+                        .sum();
+                    next.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+        std::mem::swap(&mut level, &mut next);
+        (level_width, level_height) = (next_width, next_height);
+    }
+    (data, offsets)
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use super::{AtlasOptions, CompressionOptions, MergeOptions, ModelData};
+
+/// Meters-sized models larger than this across are probably in a smaller unit
+const SUSPICIOUS_SIZE: f32 = 100.0;
+/// ...and larger than this, probably millimeters rather than centimeters
+const SUSPICIOUS_MILLIMETER_SIZE: f32 = 2000.0;
+
+/// A length unit models may be authored in. The engine's world units are meters unless
+/// [`ImportOptions::world_units`] says otherwise; VR tracking is always in meters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    Millimeters,
+    Centimeters,
+    Meters,
+    Inches,
+    Feet,
+}
+
+impl LengthUnit {
+    pub fn in_meters(self) -> f32 {
+        match self {
+            Self::Millimeters => 0.001,
+            Self::Centimeters => 0.01,
+            Self::Meters => 1.0,
+            Self::Inches => 0.0254,
+            Self::Feet => 0.3048,
+        }
+    }
+
+    /// Factor converting lengths in this unit to `target`
+    pub fn scale_to(self, target: LengthUnit) -> f32 {
+        self.in_meters() / target.in_meters()
+    }
+
+    /// Parse a unit name or abbreviation as exporters write them, e.g. `"cm"` or `"Inches"`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "mm" | "millimeter" | "millimeters" | "millimetre" | "millimetres" => Some(Self::Millimeters),
+            "cm" | "centimeter" | "centimeters" | "centimetre" | "centimetres" => Some(Self::Centimeters),
+            "m" | "meter" | "meters" | "metre" | "metres" => Some(Self::Meters),
+            "in" | "inch" | "inches" => Some(Self::Inches),
+            "ft" | "foot" | "feet" => Some(Self::Feet),
+            _ => None,
+        }
+    }
+
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Self::Millimeters => "mm",
+            Self::Centimeters => "cm",
+            Self::Meters => "m",
+            Self::Inches => "in",
+            Self::Feet => "ft",
+        }
+    }
+}
+
+/// How a loaded model is sized for the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportOptions {
+    /// Units of the scene; models are converted into them
+    pub world_units: LengthUnit,
+    /// Units to assume for the file, overriding any it declares. Files that declare none are
+    /// taken to be in world units.
+    pub units: Option<LengthUnit>,
+    /// Extra scale on top of the unit conversion
+    pub scale: f32,
+    /// Repair meshes rather than only reporting their problems
+    pub repair: bool,
+    /// Pack small textures into shared atlases, for models made of many low-res props
+    pub atlas: Option<AtlasOptions>,
+    /// Hand the model over before its textures are decoded, and the textures through
+    /// `BackgroundLoader::poll_textures` as each is ready. Ignored when packing atlases.
+    pub stream_textures: bool,
+    /// Merge static meshes sharing a material into one draw each, after any atlas packing
+    pub merge_static: Option<MergeOptions>,
+    /// Reduce and quantize animation clips, for mocap files keyed every frame
+    pub compress_animation: Option<CompressionOptions>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            world_units: LengthUnit::Meters,
+            units: None,
+            scale: 1.0,
+            repair: true,
+            atlas: None,
+            stream_textures: false,
+            merge_static: None,
+            compress_animation: None,
+        }
+    }
+}
+
+impl ModelData {
+    /// Largest extent of the bounds
+    pub fn size(&self) -> f32 {
+        (0..3).map(|i| self.bounds_max[i] - self.bounds_min[i]).fold(0.0, f32::max)
+    }
+
+    /// Scale every vertex and the bounds about the model's origin, and the skeleton's motion
+    /// with them. Models with nodes scale their root nodes instead, as the meshes are in the
+    /// nodes' space.
+    pub fn scale(&mut self, factor: f32) {
+        if self.nodes.is_empty() {
+            for vertex in self.meshes.iter_mut().flat_map(|mesh| mesh.vertices.iter_mut()) {
+                vertex.position = vertex.position.map(|v| v * factor);
+            }
+            if let Some(skeleton) = &mut self.skeleton {
+                skeleton.root = glam::Mat4::from_scale(glam::Vec3::splat(factor)) * skeleton.root;
+            }
+        } else {
+            for node in self.nodes.iter_mut().filter(|node| node.parent.is_none()) {
+                node.transform = glam::Mat4::from_scale(glam::Vec3::splat(factor)) * node.transform;
+            }
+        }
+        let (min, max) = (self.bounds_min.map(|v| v * factor), self.bounds_max.map(|v| v * factor));
+        // A negative factor swaps which corner is which
+        self.bounds_min = std::array::from_fn(|i| min[i].min(max[i]));
+        self.bounds_max = std::array::from_fn(|i| min[i].max(max[i]));
+        self.import_scale *= factor;
+    }
+
+    /// Convert the model from its file's units into `options.world_units`, then apply
+    /// `options.scale`. Returns the unit the model was more likely authored in when, unless
+    /// told its units, the result is implausibly large.
+    pub fn convert_units(&mut self, options: &ImportOptions) -> Option<LengthUnit> {
+        let units = options.units.or(self.units).unwrap_or(options.world_units);
+        self.scale(units.scale_to(options.world_units) * options.scale);
+        if options.units.is_some() {
+            return None;
+        }
+        let meters = self.size() * options.world_units.in_meters();
+        if meters > SUSPICIOUS_MILLIMETER_SIZE {
+            Some(LengthUnit::Millimeters)
+        } else if meters > SUSPICIOUS_SIZE {
+            Some(LengthUnit::Centimeters)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{MeshData, ModelVertex};
+
+    fn model(size: f32, units: Option<LengthUnit>) -> ModelData {
+        let vertex = |x: f32| ModelVertex {
+            position: [x, 0.0, 0.0],
+            tex_coords: [0.0; 2],
+            normal: [0.0, 1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+        let mut data = ModelData::from_mesh(MeshData {
+            name: "box".to_string(),
+            vertices: vec![vertex(0.0), vertex(size)],
+            indices: Vec::new(),
+            material_index: 0,
+            skin: None,
+        });
+        data.units = units;
+        data
+    }
+
+    #[test]
+    fn test_convert_units() {
+        // A 1.8 m figure exported in centimeters
+        let mut figure = model(180.0, Some(LengthUnit::Centimeters));
+        assert_eq!(figure.convert_units(&ImportOptions::default()), None);
+        assert!((figure.size() - 1.8).abs() < 1e-5);
+        assert!((figure.import_scale - 0.01).abs() < 1e-7);
+
+        // The same figure without a hint is flagged, and an override fixes it quietly
+        assert_eq!(model(180.0, None).convert_units(&ImportOptions::default()), Some(LengthUnit::Centimeters));
+        assert_eq!(model(5000.0, None).convert_units(&ImportOptions::default()), Some(LengthUnit::Millimeters));
+        assert_eq!(model(50.0, None).convert_units(&ImportOptions::default()), None);
+        assert_eq!(model(50.0, None).convert_units(&ImportOptions { scale: 10.0, ..ImportOptions::default() }), Some(LengthUnit::Centimeters));
+        let mut inches = model(70.0, None);
+        let options = ImportOptions { units: Some(LengthUnit::Inches), scale: 2.0, ..ImportOptions::default() };
+        assert_eq!(inches.convert_units(&options), None);
+        assert!((inches.bounds_max[0] - 3.556).abs() < 1e-4);
+
+        // World units other than meters
+        let mut feet = model(2.0, Some(LengthUnit::Meters));
+        feet.convert_units(&ImportOptions { world_units: LengthUnit::Feet, ..ImportOptions::default() });
+        assert!((feet.size() - 6.5617).abs() < 1e-3);
+
+        assert_eq!(LengthUnit::from_name(" Centimetres"), Some(LengthUnit::Centimeters));
+        assert_eq!(LengthUnit::from_name("parsecs"), None);
+    }
+}
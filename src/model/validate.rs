@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use glam::Vec3;
+use super::{MeshData, ModelData, ModelVertex};
+
+/// Triangles with less area than this fraction of their longest edge squared are degenerate
+const DEGENERATE_EPSILON: f32 = 1e-6;
+
+/// Problems found in one mesh; with `ModelData::repair` each count is also what was fixed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    pub mesh: String,
+    /// Indices left over after the last whole triangle
+    pub trailing_indices: usize,
+    /// Triangles referencing a vertex that doesn't exist
+    pub out_of_range_triangles: usize,
+    /// Vertices with a NaN or infinite position
+    pub non_finite_vertices: usize,
+    /// Triangles with a repeated vertex or no area
+    pub degenerate_triangles: usize,
+    /// Vertices identical in every attribute to an earlier one
+    pub duplicate_vertices: usize,
+    /// Triangles facing the opposite way to the connected surface around them
+    pub flipped_triangles: usize,
+}
+
+impl MeshReport {
+    pub fn is_clean(&self) -> bool {
+        *self == Self { mesh: self.mesh.clone(), ..Self::default() }
+    }
+
+    /// Whether the mesh would render wrongly or risk the GPU, rather than just waste memory
+    pub fn has_errors(&self) -> bool {
+        self.trailing_indices > 0 || self.out_of_range_triangles > 0 || self.non_finite_vertices > 0
+    }
+}
+
+impl fmt::Display for MeshReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let problems = [
+            (self.trailing_indices, "trailing indices"),
+            (self.out_of_range_triangles, "triangles with out-of-range indices"),
+            (self.non_finite_vertices, "NaN/infinite positions"),
+            (self.degenerate_triangles, "degenerate triangles"),
+            (self.duplicate_vertices, "duplicate vertices"),
+            (self.flipped_triangles, "inconsistently wound triangles"),
+        ];
+        let found: Vec<_> = problems.iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, problem)| format!("{} {}", count, problem))
+            .collect();
+        if found.is_empty() {
+            write!(f, "{}: ok", self.mesh)
+        } else {
+            write!(f, "{}: {}", self.mesh, found.join(", "))
+        }
+    }
+}
+
+/// Outcome of validating a model, with a report per mesh
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub meshes: Vec<MeshReport>,
+    /// Whether the problems were fixed rather than only found
+    pub repaired: bool,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.meshes.iter().all(MeshReport::is_clean)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.meshes.iter().any(MeshReport::has_errors)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    /// One line listing the meshes with problems
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "{} meshes ok", self.meshes.len());
+        }
+        let problems: Vec<_> = self.meshes.iter()
+            .filter(|mesh| !mesh.is_clean())
+            .map(ToString::to_string)
+            .collect();
+        write!(f, "{}{}", problems.join("; "), if self.repaired { " (repaired)" } else { "" })
+    }
+}
+
+impl ModelData {
+    /// Check every mesh without changing anything
+    pub fn validate(&self) -> ValidationReport {
+        let meshes = self.meshes.iter()
+            .map(|mesh| inspect(mesh).0)
+            .collect();
+        ValidationReport { meshes, repaired: false }
+    }
+
+    /// Fix what `validate` finds: bad triangles and those touching NaN positions are dropped,
+    /// duplicate vertices welded, unused vertices removed and windings made consistent with each
+    /// connected surface's first triangle. Bounds are recomputed.
+    pub fn repair(&mut self) -> ValidationReport {
+        let meshes: Vec<_> = self.meshes.iter_mut().map(repair_mesh).collect();
+        let vertices: Vec<_> = self.meshes.iter().flat_map(|mesh| mesh.vertices.iter().copied()).collect();
+        if !vertices.is_empty() {
+            (self.bounds_min, self.bounds_max) = Self::calculate_bounds(&vertices);
+        }
+        ValidationReport { meshes, repaired: true }
+    }
+}
+
+/// Report the mesh's problems and the triangles that survive them, wound consistently
+fn inspect(mesh: &MeshData) -> (MeshReport, Vec<[u32; 3]>) {
+    let vertices = &mesh.vertices;
+    let mut report = MeshReport {
+        mesh: mesh.name.clone(),
+        trailing_indices: mesh.indices.len() % 3,
+        ..MeshReport::default()
+    };
+
+    let finite: Vec<bool> = vertices.iter().map(|vertex| vertex.position.iter().all(|v| v.is_finite())).collect();
+    report.non_finite_vertices = finite.iter().filter(|finite| !**finite).count();
+
+    let mut triangles = Vec::with_capacity(mesh.indices.len() / 3);
+    for triangle in mesh.indices.chunks_exact(3) {
+        let triangle = [triangle[0], triangle[1], triangle[2]];
+        if triangle.iter().any(|&i| i as usize >= vertices.len()) {
+            report.out_of_range_triangles += 1;
+        } else if triangle.iter().all(|&i| finite[i as usize]) {
+            if is_degenerate(vertices, triangle) {
+                report.degenerate_triangles += 1;
+            } else {
+                triangles.push(triangle);
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    report.duplicate_vertices = vertices.iter().filter(|vertex| !seen.insert(bytemuck::bytes_of(*vertex))).count();
+
+    report.flipped_triangles = make_winding_consistent(vertices, &mut triangles);
+    (report, triangles)
+}
+
+fn repair_mesh(mesh: &mut MeshData) -> MeshReport {
+    let (report, triangles) = inspect(mesh);
+
+    // Weld identical vertices and drop the ones no triangle uses, keeping first-use order.
+    // Skinned vertices only weld when their joints and weights match too.
+    let mut welded: HashMap<Vec<u8>, u32> = HashMap::new();
+    let mut remap = vec![u32::MAX; mesh.vertices.len()];
+    let mut vertices = Vec::new();
+    let mut skin = mesh.skin.as_ref().map(|_| Vec::new());
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+    for index in triangles.iter().flatten() {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            let mut key = bytemuck::bytes_of(&mesh.vertices[old]).to_vec();
+            if let Some(weights) = &mesh.skin {
+                key.extend_from_slice(bytemuck::bytes_of(&weights[old]));
+            }
+            remap[old] = *welded.entry(key).or_insert_with(|| {
+                vertices.push(mesh.vertices[old]);
+                if let (Some(skin), Some(weights)) = (&mut skin, &mesh.skin) {
+                    skin.push(weights[old]);
+                }
+                vertices.len() as u32 - 1
+            });
+        }
+        indices.push(remap[old]);
+    }
+
+    mesh.vertices = vertices;
+    mesh.skin = skin;
+    mesh.indices = indices;
+    report
+}
+
+fn is_degenerate(vertices: &[ModelVertex], [a, b, c]: [u32; 3]) -> bool {
+    if a == b || b == c || a == c {
+        return true;
+    }
+    let position = |i: u32| Vec3::from(vertices[i as usize].position);
+    let (a, b, c) = (position(a), position(b), position(c));
+    let longest = (b - a).length_squared().max((c - b).length_squared()).max((a - c).length_squared());
+    (b - a).cross(c - a).length() <= DEGENERATE_EPSILON * longest
+}
+
+/// Flip triangles so each pair sharing an edge traverses it in opposite directions, spreading
+/// out from the first triangle of each connected surface; returns how many were flipped.
+/// Vertices are matched by position, so seams split for UVs or normals still connect.
+fn make_winding_consistent(vertices: &[ModelVertex], triangles: &mut [[u32; 3]]) -> usize {
+    let mut ids: HashMap<[u32; 3], u32> = HashMap::new();
+    let point: Vec<u32> = vertices.iter()
+        .map(|vertex| {
+            let key = vertex.position.map(f32::to_bits);
+            let next = ids.len() as u32;
+            *ids.entry(key).or_insert(next)
+        })
+        .collect();
+    let edges = |triangle: &[u32; 3]| {
+        let [a, b, c] = triangle.map(|i| point[i as usize]);
+        [(a, b), (b, c), (c, a)]
+    };
+
+    // Triangles on each undirected edge
+    let mut neighbours: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        for (a, b) in edges(triangle) {
+            neighbours.entry((a.min(b), a.max(b))).or_default().push(t);
+        }
+    }
+
+    let mut visited = vec![false; triangles.len()];
+    let mut flipped = 0;
+    let mut queue = VecDeque::new();
+    for seed in 0..triangles.len() {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+        queue.push_back(seed);
+        while let Some(t) = queue.pop_front() {
+            for (a, b) in edges(&triangles[t]) {
+                let shared = &neighbours[&(a.min(b), a.max(b))];
+                // Only manifold edges say which way a neighbour should face
+                if shared.len() != 2 {
+                    continue;
+                }
+                let other = if shared[0] == t { shared[1] } else { shared[0] };
+                if visited[other] {
+                    continue;
+                }
+                visited[other] = true;
+                // A consistent neighbour runs the shared edge from b to a
+                if edges(&triangles[other]).contains(&(a, b)) {
+                    triangles[other].swap(1, 2);
+                    flipped += 1;
+                }
+                queue.push_back(other);
+            }
+        }
+    }
+    flipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> ModelVertex {
+        ModelVertex {
+            position: [x, y, z],
+            tex_coords: [0.0; 2],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        }
+    }
+
+    fn model(vertices: Vec<ModelVertex>, indices: Vec<u32>) -> ModelData {
+        ModelData::from_mesh(MeshData { name: "broken".to_string(), vertices, indices, material_index: 0, skin: None })
+    }
+
+    #[test]
+    fn test_validate_and_repair() {
+        let vertices = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(1.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0),
+            // Duplicate of 0, and a NaN
+            vertex(0.0, 0.0, 0.0),
+            vertex(f32::NAN, 0.0, 0.0),
+            // Collinear with 0 and 1
+            vertex(2.0, 0.0, 0.0),
+        ];
+        let indices = vec![
+            0, 1, 2,
+            // Wound the other way round the shared edge 0-2
+            4, 3, 2,
+            0, 1, 9,
+            0, 5, 1,
+            0, 1, 6,
+            1, 1, 2,
+            0, 1,
+        ];
+        let mut data = model(vertices, indices);
+
+        let report = data.validate();
+        assert_eq!(report.meshes[0], MeshReport {
+            mesh: "broken".to_string(),
+            trailing_indices: 2,
+            out_of_range_triangles: 1,
+            non_finite_vertices: 1,
+            degenerate_triangles: 2,
+            duplicate_vertices: 1,
+            flipped_triangles: 1,
+        });
+        assert!(report.has_errors());
+        assert_eq!(data.meshes[0].indices.len(), 20);
+
+        let repaired = data.repair();
+        assert!(repaired.repaired);
+        assert_eq!(repaired.meshes, report.meshes);
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!((data.bounds_min, data.bounds_max), ([0.0; 3], [1.0, 1.0, 0.0]));
+
+        // Repaired data has nothing left to find
+        assert!(data.validate().is_clean());
+        assert!(data.repair().to_string().ends_with("meshes ok"));
+    }
+}
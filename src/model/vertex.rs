@@ -5,14 +5,19 @@ pub struct ModelVertex {
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
     pub tangent: [f32; 4],  // xyz = tangent direction, w = handedness for bitangent
+    pub color: [f32; 4],  // linear RGBA, multiplied into the base color
 }
 
 impl ModelVertex {
-    pub const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+    /// Color of vertices without one
+    pub const WHITE: [f32; 4] = [1.0; 4];
+
+    pub const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
         0 => Float32x3,  // position
         1 => Float32x2,  // tex_coords
         2 => Float32x3,  // normal
         3 => Float32x4,  // tangent
+        4 => Float32x4,  // color
     ];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -22,4 +27,30 @@ impl ModelVertex {
             attributes: &Self::ATTRIBUTES,
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Joint matrices a skinned mesh can address, matching `JointUniform` in `shader.wgsl`
+pub const MAX_JOINTS: usize = 64;
+
+/// Second vertex stream of skinned meshes, read by the `SKINNED` shader variant
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl SkinVertex {
+    pub const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        5 => Uint32x4,  // joints
+        6 => Float32x4,  // weights
+    ];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SkinVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
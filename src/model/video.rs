@@ -0,0 +1,315 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use anyhow::{bail, Context, Result};
+use super::{AssetRegistry, ChromaKey, ImageData, Material, MaterialHandle, MeshData, ModelVertex, Texture, TextureSlot};
+
+/// Larger frames are taken for a corrupt header rather than allocated
+const MAX_FRAME_SIZE: u32 = 8192;
+
+/// Where a `VideoTexture` gets its frames from
+pub trait VideoSource {
+    /// The newest frame since the last call, if there is one; frames in between are skipped
+    fn latest_frame(&mut self) -> Result<Option<ImageData>>;
+}
+
+/// Frames handed over from another thread, e.g. a webcam capture loop using whichever
+/// capture crate suits the platform
+pub struct VideoChannel {
+    receiver: Receiver<ImageData>,
+}
+
+/// The capturing side of a `VideoChannel`
+#[derive(Clone)]
+pub struct VideoSender {
+    sender: SyncSender<ImageData>,
+}
+
+impl VideoChannel {
+    /// Frames waiting beyond this many are dropped rather than queued
+    const CAPACITY: usize = 2;
+
+    pub fn new() -> (VideoSender, Self) {
+        let (sender, receiver) = mpsc::sync_channel(Self::CAPACITY);
+        (VideoSender { sender }, Self { receiver })
+    }
+}
+
+impl VideoSource for VideoChannel {
+    fn latest_frame(&mut self) -> Result<Option<ImageData>> {
+        Ok(self.receiver.try_iter().last())
+    }
+}
+
+impl VideoSender {
+    /// Hand over a frame without waiting; it's dropped if the viewer is behind. Returns false
+    /// once the channel is gone, so the capture loop can stop.
+    pub fn send(&self, frame: ImageData) -> bool {
+        match self.sender.try_send(frame) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Frames another process writes into a file, normally on a RAM-backed filesystem such as
+/// `/dev/shm`, for capture tools that can't be linked into the viewer. The file starts with
+/// a 16-byte little-endian header: the magic `VFRM`, a frame counter, the width and the
+/// height, followed by the RGBA8 pixels, rows top to bottom. Writers set the counter to 0
+/// while they write the pixels, then to the frame's number; see `write`.
+pub struct SharedMemoryFrames {
+    path: PathBuf,
+    file: Option<File>,
+    last_frame: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHeader {
+    counter: u32,
+    width: u32,
+    height: u32,
+}
+
+impl SharedMemoryFrames {
+    pub const MAGIC: [u8; 4] = *b"VFRM";
+    pub const HEADER_SIZE: u64 = 16;
+
+    /// Read frames from `path`, which needn't exist until the writer starts
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), file: None, last_frame: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Publish `frame` at `path` as frame number `counter` (not 0), the way readers expect;
+    /// for writers in Rust and for tests
+    pub fn write(path: &Path, counter: u32, frame: &ImageData) -> Result<()> {
+        if counter == 0 {
+            bail!("Shared memory frame counters start at 1");
+        }
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)
+            .with_context(|| format!("Failed to open shared memory frames {}", path.display()))?;
+        let header = |counter: u32| {
+            let mut bytes = Vec::with_capacity(Self::HEADER_SIZE as usize);
+            bytes.extend_from_slice(&Self::MAGIC);
+            for value in [counter, frame.width, frame.height] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            bytes
+        };
+        file.write_all(&header(0))?;
+        file.write_all(&frame.pixels)?;
+        file.set_len(Self::HEADER_SIZE + frame.pixels.len() as u64)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header(counter))?;
+        Ok(())
+    }
+
+    /// The header, `None` while the file is too short to have one
+    fn read_header(file: &mut File) -> Result<Option<FrameHeader>> {
+        let mut bytes = [0; Self::HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(0))?;
+        match file.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+        if bytes[..4] != Self::MAGIC {
+            bail!("Not a shared memory frame file: bad magic");
+        }
+        let value = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        Ok(Some(FrameHeader { counter: value(4), width: value(8), height: value(12) }))
+    }
+}
+
+impl VideoSource for SharedMemoryFrames {
+    fn latest_frame(&mut self) -> Result<Option<ImageData>> {
+        if self.file.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.file = Some(file),
+                Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(error) => return Err(error).with_context(|| format!("Failed to open shared memory frames {}", self.path.display())),
+            }
+        }
+        let Some(file) = &mut self.file else {
+            return Ok(None);
+        };
+        let Some(header) = Self::read_header(file)? else {
+            return Ok(None);
+        };
+        // 0 is a frame being written
+        if header.counter == 0 || Some(header.counter) == self.last_frame {
+            return Ok(None);
+        }
+        if header.width == 0 || header.height == 0 || header.width > MAX_FRAME_SIZE || header.height > MAX_FRAME_SIZE {
+            bail!("Implausible shared memory frame size {}x{}", header.width, header.height);
+        }
+        let mut pixels = vec![0; header.width as usize * header.height as usize * 4];
+        match file.read_exact(&mut pixels) {
+            Ok(()) => {}
+            // Resized by the writer under us
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+        // A frame written while we read is torn; the next poll gets it whole
+        if Self::read_header(file)? != Some(header) {
+            return Ok(None);
+        }
+        self.last_frame = Some(header.counter);
+        Ok(Some(ImageData { width: header.width, height: header.height, pixels }))
+    }
+}
+
+/// A material showing live video: a webcam on an in-world panel, or with a `ChromaKey`, a
+/// person filmed against a green screen composited into the scene for mixed reality. Show
+/// it on any mesh (see `video_panel`) and call `update` every frame.
+pub struct VideoTexture {
+    source: Box<dyn VideoSource>,
+    material: MaterialHandle,
+    size: Option<(u32, u32)>,
+    frames: u64,
+}
+
+impl VideoTexture {
+    /// Register the material, blank until the first frame arrives
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        assets: &mut AssetRegistry,
+        name: &str,
+        source: impl VideoSource + 'static,
+        chroma_key: Option<ChromaKey>,
+    ) -> Self {
+        let mut material = Material::new(name, Some(Texture::streaming(device, 1, 1, Some(name))), None);
+        material.chroma_key = chroma_key;
+        material.create_bind_group(device, layout);
+        let material = assets.add_material(material);
+        Self { source: Box::new(source), material, size: None, frames: 0 }
+    }
+
+    pub fn material(&self) -> MaterialHandle {
+        self.material
+    }
+
+    /// Width and height of the current frame, `None` before the first
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+
+    /// Frames uploaded so far
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// Upload the source's newest frame if it has a new one; returns whether it did. The
+    /// texture is replaced when the frame size changes, and nothing is uploaded once the
+    /// material has been unloaded.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, assets: &mut AssetRegistry) -> Result<bool> {
+        let Some(frame) = self.source.latest_frame()? else {
+            return Ok(false);
+        };
+        if frame.pixels.len() != frame.width as usize * frame.height as usize * 4 {
+            bail!("Video frame of {}x{} has {} bytes of pixels", frame.width, frame.height, frame.pixels.len());
+        }
+        let Some(material) = assets.material_mut(self.material) else {
+            return Ok(false);
+        };
+        if self.size != Some((frame.width, frame.height)) {
+            let texture = Texture::streaming(device, frame.width, frame.height, Some(&material.name));
+            material.set_texture(device, queue, layout, TextureSlot::Diffuse, Some(texture));
+            self.size = Some((frame.width, frame.height));
+        }
+        if let Some(texture) = &material.diffuse_texture {
+            texture.write_rgba(queue, &frame.pixels);
+        }
+        self.frames += 1;
+        Ok(true)
+    }
+}
+
+/// A one-meter-tall rectangle `aspect` (width / height) wide, centered on its origin and
+/// facing +Z, mapping a whole video frame the right way up
+pub fn video_panel(aspect: f32) -> MeshData {
+    let half_width = aspect * 0.5;
+    let vertex = |x: f32, y: f32, u: f32, v: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [u, v],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: ModelVertex::WHITE,
+    };
+    MeshData {
+        name: "video_panel".to_string(),
+        vertices: vec![
+            vertex(-half_width, -0.5, 0.0, 1.0),
+            vertex(half_width, -0.5, 1.0, 1.0),
+            vertex(half_width, 0.5, 1.0, 0.0),
+            vertex(-half_width, 0.5, 0.0, 0.0),
+        ],
+        indices: vec![0, 1, 2, 0, 2, 3],
+        material_index: 0,
+        skin: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ModelData;
+
+    fn frame(width: u32, height: u32, value: u8) -> ImageData {
+        ImageData { width, height, pixels: vec![value; (width * height * 4) as usize] }
+    }
+
+    #[test]
+    fn test_video_channel() {
+        let (sender, mut channel) = VideoChannel::new();
+        assert!(channel.latest_frame().unwrap().is_none());
+        // Frames beyond the capacity are dropped, and the newest queued one wins
+        for value in 1..=4 {
+            assert!(sender.send(frame(2, 2, value)));
+        }
+        assert_eq!(channel.latest_frame().unwrap().unwrap().pixels[0], 2);
+        assert!(channel.latest_frame().unwrap().is_none());
+        drop(channel);
+        assert!(!sender.send(frame(2, 2, 5)));
+    }
+
+    #[test]
+    fn test_shared_memory_frames() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("camera");
+        let mut frames = SharedMemoryFrames::new(&path);
+        // Nothing until the writer starts
+        assert!(frames.latest_frame().unwrap().is_none());
+
+        SharedMemoryFrames::write(&path, 1, &frame(4, 2, 7)).unwrap();
+        let read = frames.latest_frame().unwrap().unwrap();
+        assert_eq!((read.width, read.height, read.pixels.len()), (4, 2, 32));
+        assert!(read.pixels.iter().all(|&value| value == 7));
+        // The same frame isn't read twice
+        assert!(frames.latest_frame().unwrap().is_none());
+
+        // Smaller frames shrink the file
+        SharedMemoryFrames::write(&path, 2, &frame(1, 1, 9)).unwrap();
+        assert_eq!(frames.latest_frame().unwrap().unwrap().pixels, vec![9; 4]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), SharedMemoryFrames::HEADER_SIZE + 4);
+
+        assert!(SharedMemoryFrames::write(&path, 0, &frame(1, 1, 0)).is_err());
+        std::fs::write(&path, b"not a frame file").unwrap();
+        assert!(frames.latest_frame().is_err());
+    }
+
+    #[test]
+    fn test_video_panel() {
+        let panel = video_panel(16.0 / 9.0);
+        let (min, max) = ModelData::calculate_bounds(&panel.vertices);
+        assert!((max[0] - min[0] - 16.0 / 9.0).abs() < 1e-6);
+        assert_eq!((min[1], max[1]), (-0.5, 0.5));
+        // The top of the frame is at the top of the panel
+        assert_eq!(panel.vertices[3].tex_coords, [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,751 @@
+//! Multi-user sessions: viewers with the same scene loaded (a desktop and a headset, say)
+//! replicate object transforms, head and controller poses and named events over UDP, so a
+//! model can be reviewed together. Objects are matched by `ObjectId`, which agrees between
+//! viewers that built their scenes the same way.
+//!
+//! Each object is moved by at most one peer, its owner: a peer `claim`s an object before
+//! moving it and its transforms are ignored otherwise. Everything else is shown as received,
+//! interpolated a little behind the newest update so it moves smoothly through lost and
+//! late packets.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use crate::scene::{AnchorPoses, ObjectId, Scene, Transform};
+
+pub const DEFAULT_PORT: u16 = 47800;
+/// Seconds remote movement is shown behind the newest update received
+pub const DEFAULT_INTERPOLATION_DELAY: f64 = 0.1;
+/// Seconds between pose and transform updates
+pub const DEFAULT_SEND_INTERVAL: f64 = 1.0 / 30.0;
+/// Peers not heard from for this many seconds are dropped, releasing their objects
+pub const PEER_TIMEOUT: f64 = 5.0;
+const HEARTBEAT_INTERVAL: f64 = 1.0;
+/// Heartbeats a release is repeated on, so peers still hear it through lost datagrams
+const RELEASE_HEARTBEATS: u32 = 3;
+
+const MAGIC: [u8; 2] = *b"WV";
+const VERSION: u8 = 1;
+/// Kept under the common path MTU so datagrams aren't fragmented
+const MAX_PACKET: usize = 1200;
+const HEADER_SIZE: usize = 2 + 1 + 4 + 8 + 1;
+/// Object id, position, rotation quaternion and scale
+const TRANSFORM_SIZE: usize = 4 + 10 * 4;
+const SNAPSHOTS: usize = 16;
+
+/// Identifies a viewer in a session; ties in ownership go to the lower id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub u32);
+
+impl PeerId {
+    /// An id unlikely to collide with another viewer's, from the clock and process id
+    pub fn generate() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos());
+        Self(nanos.rotate_left(16) ^ std::process::id().wrapping_mul(0x9e37_79b9))
+    }
+}
+
+/// A tracked position and orientation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+impl Pose {
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (_, rotation, position) = matrix.to_scale_rotation_translation();
+        Self { position, rotation }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.rotation, self.position)
+    }
+}
+
+/// A peer's head and controllers, in world space; `None` for untracked ones
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PeerPoses {
+    pub head: Option<Pose>,
+    pub left_controller: Option<Pose>,
+    pub right_controller: Option<Pose>,
+}
+
+impl PeerPoses {
+    /// For anchoring objects to, or posing an `Avatar` from, a remote peer
+    pub fn to_anchor_poses(&self) -> AnchorPoses {
+        AnchorPoses {
+            head: self.head.map(|pose| pose.to_matrix()),
+            left_controller: self.left_controller.map(|pose| pose.to_matrix()),
+            right_controller: self.right_controller.map(|pose| pose.to_matrix()),
+        }
+    }
+
+    fn from_anchor_poses(poses: &AnchorPoses) -> Self {
+        Self {
+            head: poses.head.map(Pose::from_matrix),
+            left_controller: poses.left_controller.map(Pose::from_matrix),
+            right_controller: poses.right_controller.map(Pose::from_matrix),
+        }
+    }
+}
+
+/// A named event from a peer, e.g. a button press to mirror. Events travel in single
+/// datagrams and may be lost, so use them for cues rather than state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkEvent {
+    pub peer: PeerId,
+    pub name: String,
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Message {
+    /// Announces the sender; doubles as the heartbeat
+    Hello,
+    Poses(PeerPoses),
+    Transforms(Vec<(ObjectId, Transform)>),
+    Claim(ObjectId),
+    Release(ObjectId),
+    Event(String, f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    peer: PeerId,
+    /// The sender's session clock, in seconds
+    time: f64,
+    message: Message,
+}
+
+fn write_f32s(bytes: &mut Vec<u8>, values: &[f32]) {
+    for value in values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn write_pose(bytes: &mut Vec<u8>, pose: &Option<Pose>) {
+    match pose {
+        Some(pose) => {
+            bytes.push(1);
+            write_f32s(bytes, &pose.position.to_array());
+            write_f32s(bytes, &pose.rotation.to_array());
+        }
+        None => bytes.push(0),
+    }
+}
+
+fn encode(packet: &Packet) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAX_PACKET);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&packet.peer.0.to_le_bytes());
+    bytes.extend_from_slice(&packet.time.to_le_bytes());
+    match &packet.message {
+        Message::Hello => bytes.push(0),
+        Message::Poses(poses) => {
+            bytes.push(1);
+            for pose in [&poses.head, &poses.left_controller, &poses.right_controller] {
+                write_pose(&mut bytes, pose);
+            }
+        }
+        Message::Transforms(transforms) => {
+            bytes.push(2);
+            bytes.extend_from_slice(&(transforms.len() as u16).to_le_bytes());
+            for (object, transform) in transforms {
+                let r = transform.rotation;
+                bytes.extend_from_slice(&object.0.to_le_bytes());
+                write_f32s(&mut bytes, &transform.position.to_array());
+                write_f32s(&mut bytes, &Quat::from_euler(EulerRot::XYZ, r.x, r.y, r.z).to_array());
+                write_f32s(&mut bytes, &transform.scale.to_array());
+            }
+        }
+        Message::Claim(object) => {
+            bytes.push(3);
+            bytes.extend_from_slice(&object.0.to_le_bytes());
+        }
+        Message::Release(object) => {
+            bytes.push(4);
+            bytes.extend_from_slice(&object.0.to_le_bytes());
+        }
+        Message::Event(name, value) => {
+            bytes.push(5);
+            let name = &name.as_bytes()[..name.len().min(255)];
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Reads a datagram front to back; every read is `None` past the end
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*head)
+    }
+
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.0.split_at_checked(length)?;
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    fn vec3(&mut self) -> Option<Vec3> {
+        Some(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn quat(&mut self) -> Option<Quat> {
+        Some(Quat::from_xyzw(self.f32()?, self.f32()?, self.f32()?, self.f32()?).normalize())
+    }
+
+    fn pose(&mut self) -> Option<Option<Pose>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => Some(Some(Pose { position: self.vec3()?, rotation: self.quat()? })),
+        }
+    }
+}
+
+/// `None` for datagrams that aren't ours, from another protocol version or cut short
+fn decode(bytes: &[u8]) -> Option<Packet> {
+    let mut reader = Reader(bytes);
+    if reader.take::<2>()? != MAGIC || reader.u8()? != VERSION {
+        return None;
+    }
+    let peer = PeerId(reader.u32()?);
+    let time = f64::from_le_bytes(reader.take()?);
+    let message = match reader.u8()? {
+        0 => Message::Hello,
+        1 => Message::Poses(PeerPoses {
+            head: reader.pose()?,
+            left_controller: reader.pose()?,
+            right_controller: reader.pose()?,
+        }),
+        2 => {
+            let count = u16::from_le_bytes(reader.take()?);
+            let transforms = (0..count)
+                .map(|_| {
+                    let object = ObjectId(reader.u32()?);
+                    let position = reader.vec3()?;
+                    let (x, y, z) = reader.quat()?.to_euler(EulerRot::XYZ);
+                    let scale = reader.vec3()?;
+                    Some((object, Transform { position, rotation: Vec3::new(x, y, z), scale }))
+                })
+                .collect::<Option<_>>()?;
+            Message::Transforms(transforms)
+        }
+        3 => Message::Claim(ObjectId(reader.u32()?)),
+        4 => Message::Release(ObjectId(reader.u32()?)),
+        5 => {
+            let length = reader.u8()? as usize;
+            let name = reader.bytes(length)?;
+            Message::Event(String::from_utf8_lossy(name).into_owned(), reader.f32()?)
+        }
+        _ => return None,
+    };
+    Some(Packet { peer, time, message })
+}
+
+/// Values that can be blended between two snapshots
+trait Interpolate: Copy {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for Pose {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Self { position: self.position.lerp(other.position, t), rotation: self.rotation.slerp(other.rotation, t) }
+    }
+}
+
+impl Interpolate for PeerPoses {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        // A pose that was lost or found between snapshots switches with the newer one
+        let blend = |a: Option<Pose>, b: Option<Pose>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.interpolate(&b, t)),
+            _ => b,
+        };
+        Self {
+            head: blend(self.head, other.head),
+            left_controller: blend(self.left_controller, other.left_controller),
+            right_controller: blend(self.right_controller, other.right_controller),
+        }
+    }
+}
+
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        let to_quat = |rotation: Vec3| Quat::from_euler(EulerRot::XYZ, rotation.x, rotation.y, rotation.z);
+        let (x, y, z) = to_quat(self.rotation).slerp(to_quat(other.rotation), t).to_euler(EulerRot::XYZ);
+        Self {
+            position: self.position.lerp(other.position, t),
+            rotation: Vec3::new(x, y, z),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+/// The latest values received, by sender time
+#[derive(Debug)]
+struct Snapshots<T> {
+    snapshots: VecDeque<(f64, T)>,
+}
+
+impl<T> Default for Snapshots<T> {
+    fn default() -> Self {
+        Self { snapshots: VecDeque::new() }
+    }
+}
+
+impl<T: Interpolate> Snapshots<T> {
+    /// Add a snapshot; ones older than the newest arrived out of order and are dropped
+    fn push(&mut self, time: f64, value: T) {
+        if self.snapshots.back().is_some_and(|&(newest, _)| time <= newest) {
+            return;
+        }
+        if self.snapshots.len() == SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((time, value));
+    }
+
+    /// The value at `time`, blended between the snapshots around it; held at the first or
+    /// last snapshot outside them rather than extrapolated
+    fn sample(&self, time: f64) -> Option<T> {
+        let next = self.snapshots.iter().position(|&(snapshot, _)| snapshot > time);
+        match next {
+            Some(0) => self.snapshots.front().map(|&(_, value)| value),
+            Some(index) => {
+                let ((t0, a), (t1, b)) = (self.snapshots[index - 1], self.snapshots[index]);
+                Some(a.interpolate(&b, ((time - t0) / (t1 - t0)) as f32))
+            }
+            None => self.snapshots.back().map(|&(_, value)| value),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    address: SocketAddr,
+    last_heard: f64,
+    /// How far the peer's clock is ahead of ours, less the quickest delivery seen
+    clock_offset: f64,
+    poses: Snapshots<PeerPoses>,
+}
+
+/// This viewer's end of a multi-user session; call `update` once per frame after
+/// `Scene::update`
+pub struct NetworkSession {
+    socket: UdpSocket,
+    peer: PeerId,
+    peers: BTreeMap<PeerId, Peer>,
+    /// Addresses given to `connect` that haven't answered yet
+    greeting: Vec<SocketAddr>,
+    owners: HashMap<ObjectId, PeerId>,
+    /// Objects we released, with the heartbeats left to repeat the release on
+    releasing: HashMap<ObjectId, u32>,
+    remote_objects: HashMap<ObjectId, Snapshots<Transform>>,
+    events: Vec<NetworkEvent>,
+    /// Seconds remote movement trails the newest update; more rides out worse connections
+    pub interpolation_delay: f64,
+    pub send_interval: f64,
+    started: Instant,
+    last_send: f64,
+    last_heartbeat: f64,
+}
+
+impl NetworkSession {
+    /// Listen on `address`, e.g. `("0.0.0.0", DEFAULT_PORT)`; port 0 picks a free one
+    pub fn bind(address: impl ToSocketAddrs, peer: PeerId) -> Result<Self> {
+        let socket = UdpSocket::bind(address).context("Failed to bind the session socket")?;
+        socket.set_nonblocking(true).context("Failed to make the session socket non-blocking")?;
+        Ok(Self {
+            socket,
+            peer,
+            peers: BTreeMap::new(),
+            greeting: Vec::new(),
+            owners: HashMap::new(),
+            releasing: HashMap::new(),
+            remote_objects: HashMap::new(),
+            events: Vec::new(),
+            interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
+            send_interval: DEFAULT_SEND_INTERVAL,
+            started: Instant::now(),
+            last_send: f64::NEG_INFINITY,
+            last_heartbeat: f64::NEG_INFINITY,
+        })
+    }
+
+    /// Greet another viewer until it answers; either side may connect to the other
+    pub fn connect(&mut self, address: impl ToSocketAddrs) -> Result<()> {
+        let address = address.to_socket_addrs()?.next().context("No address to connect to")?;
+        self.greeting.push(address);
+        self.last_heartbeat = f64::NEG_INFINITY;
+        Ok(())
+    }
+
+    pub fn local_address(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer
+    }
+
+    /// Peers heard from within `PEER_TIMEOUT`
+    pub fn peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.peers.keys().copied()
+    }
+
+    /// A peer's head and controllers as of the interpolation delay, e.g. to draw their avatar
+    pub fn peer_poses(&self, peer: PeerId) -> Option<PeerPoses> {
+        let remote = self.peers.get(&peer)?;
+        remote.poses.sample(self.remote_time(remote))
+    }
+
+    /// The peer moving `object`, if any
+    pub fn owner(&self, object: ObjectId) -> Option<PeerId> {
+        self.owners.get(&object).copied()
+    }
+
+    /// Take ownership of `object` to move it; false while another peer owns it. When two
+    /// peers claim an object at once, the lower id keeps it.
+    pub fn claim(&mut self, object: ObjectId) -> bool {
+        match self.owners.get(&object) {
+            Some(&owner) if owner != self.peer => false,
+            _ => {
+                self.owners.insert(object, self.peer);
+                self.releasing.remove(&object);
+                self.remote_objects.remove(&object);
+                self.broadcast(Message::Claim(object));
+                true
+            }
+        }
+    }
+
+    /// Let other peers move `object`
+    pub fn release(&mut self, object: ObjectId) {
+        if self.owners.get(&object) == Some(&self.peer) {
+            self.owners.remove(&object);
+            self.releasing.insert(object, RELEASE_HEARTBEATS);
+            self.broadcast(Message::Release(object));
+        }
+    }
+
+    /// Send a named event to every peer
+    pub fn send_event(&mut self, name: &str, value: f32) {
+        self.broadcast(Message::Event(name.to_string(), value));
+    }
+
+    /// Events received since the last call
+    pub fn drain_events(&mut self) -> Vec<NetworkEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Receive from peers and move the objects they own, then send our own poses (the
+    /// scene's anchor poses, with the camera as the head when none is set) and the
+    /// transforms of the objects we own
+    pub fn update(&mut self, scene: &mut Scene) {
+        let now = self.now();
+        self.receive(now);
+
+        let expired: Vec<_> = self.peers.iter()
+            .filter(|(_, peer)| now - peer.last_heard > PEER_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            log::info!("Peer {} timed out", id.0);
+            self.peers.remove(&id);
+            self.owners.retain(|_, owner| *owner != id);
+            self.remote_objects.retain(|object, _| self.owners.contains_key(object));
+        }
+
+        for (&object, snapshots) in &self.remote_objects {
+            let Some(peer) = self.owners.get(&object).and_then(|owner| self.peers.get(owner)) else {
+                continue;
+            };
+            if let (Some(transform), Some(target)) = (snapshots.sample(self.remote_time(peer)), scene.transform_mut(object)) {
+                *target = transform;
+            }
+        }
+
+        if now - self.last_heartbeat >= HEARTBEAT_INTERVAL {
+            self.last_heartbeat = now;
+            for address in self.greeting.clone() {
+                self.send_to(address, &Message::Hello);
+            }
+            self.broadcast(Message::Hello);
+            // Claims ride along with the heartbeat, so a lost one is only delayed
+            let owned: Vec<_> = self.owned().collect();
+            for object in owned {
+                self.broadcast(Message::Claim(object));
+            }
+            // As do releases for a few beats; a peer that missed them all would keep
+            // ignoring everyone else's moves of the object
+            let releasing: Vec<_> = self.releasing.keys().copied().collect();
+            for object in releasing {
+                self.broadcast(Message::Release(object));
+            }
+            self.releasing.retain(|_, beats| {
+                *beats -= 1;
+                *beats > 0
+            });
+        }
+        if now - self.last_send >= self.send_interval {
+            self.last_send = now;
+            let mut poses = *scene.anchors.poses();
+            poses.head = poses.head.or_else(|| Some(scene.camera.build_view_matrix().inverse()));
+            self.broadcast(Message::Poses(PeerPoses::from_anchor_poses(&poses)));
+
+            let transforms: Vec<_> = self.owned()
+                .filter_map(|object| Some((object, scene.object(object)?.transform)))
+                .collect();
+            for chunk in transforms.chunks((MAX_PACKET - HEADER_SIZE - 2) / TRANSFORM_SIZE) {
+                self.broadcast(Message::Transforms(chunk.to_vec()));
+            }
+        }
+    }
+
+    fn now(&self) -> f64 {
+        self.started.elapsed().as_secs_f64()
+    }
+
+    /// Where in a peer's clock to show their movement
+    fn remote_time(&self, peer: &Peer) -> f64 {
+        self.now() + peer.clock_offset - self.interpolation_delay
+    }
+
+    fn owned(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.owners.iter().filter(|(_, &owner)| owner == self.peer).map(|(&object, _)| object)
+    }
+
+    fn receive(&mut self, now: f64) {
+        let mut buffer = [0u8; MAX_PACKET];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, address)) => match decode(&buffer[..length]) {
+                    Some(packet) if packet.peer != self.peer => self.handle(packet, address, now),
+                    Some(_) => {}
+                    None => log::debug!("Ignoring a malformed datagram from {}", address),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Some platforms report an earlier send to a closed port here; nothing to do
+                Err(e) => log::debug!("Session receive failed: {}", e),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: Packet, address: SocketAddr, now: f64) {
+        let Packet { peer: id, time, message } = packet;
+        let offset = time - now;
+        if !self.peers.contains_key(&id) {
+            log::info!("Peer {} joined from {}", id.0, address);
+            self.greeting.retain(|greeted| *greeted != address);
+            self.peers.insert(id, Peer { address, last_heard: now, clock_offset: offset, poses: Snapshots::default() });
+            // Answer right away so the newcomer learns about us without waiting a heartbeat
+            self.send_to(address, &Message::Hello);
+        }
+        let peer = self.peers.get_mut(&id).expect("peer was just added");
+        peer.address = address;
+        peer.last_heard = now;
+        peer.clock_offset = peer.clock_offset.max(offset);
+
+        match message {
+            Message::Hello => {}
+            Message::Poses(poses) => peer.poses.push(time, poses),
+            Message::Transforms(transforms) => {
+                for (object, transform) in transforms {
+                    if self.owners.get(&object) == Some(&id) {
+                        self.remote_objects.entry(object).or_default().push(time, transform);
+                    }
+                }
+            }
+            Message::Claim(object) => match self.owners.get(&object) {
+                Some(&owner) if owner == id => {}
+                Some(&owner) if owner < id => {}
+                _ => {
+                    if self.owners.insert(object, id) == Some(self.peer) {
+                        log::info!("Peer {} took object {} over", id.0, object.0);
+                    }
+                }
+            },
+            Message::Release(object) => {
+                if self.owners.get(&object) == Some(&id) {
+                    self.owners.remove(&object);
+                    self.remote_objects.remove(&object);
+                }
+            }
+            Message::Event(name, value) => self.events.push(NetworkEvent { peer: id, name, value }),
+        }
+    }
+
+    fn broadcast(&self, message: Message) {
+        let bytes = encode(&Packet { peer: self.peer, time: self.now(), message });
+        for peer in self.peers.values() {
+            if let Err(e) = self.socket.send_to(&bytes, peer.address) {
+                log::warn!("Failed to send to {}: {}", peer.address, e);
+            }
+        }
+    }
+
+    fn send_to(&self, address: SocketAddr, message: &Message) {
+        let bytes = encode(&Packet { peer: self.peer, time: self.now(), message: message.clone() });
+        if let Err(e) = self.socket.send_to(&bytes, address) {
+            log::warn!("Failed to send to {}: {}", address, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::scene::camera::Camera;
+
+    fn pose(x: f32) -> Pose {
+        Pose { position: Vec3::new(x, 1.6, 0.0), rotation: Quat::from_rotation_y(x) }
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let transform = Transform { position: Vec3::new(1.0, 2.0, 3.0), rotation: Vec3::new(0.1, 0.2, 0.3), scale: Vec3::splat(2.0) };
+        let messages = [
+            Message::Hello,
+            Message::Poses(PeerPoses { head: Some(pose(0.5)), left_controller: None, right_controller: Some(pose(-0.2)) }),
+            Message::Transforms(vec![(ObjectId(7), transform), (ObjectId(9), Transform::new())]),
+            Message::Claim(ObjectId(3)),
+            Message::Release(ObjectId(3)),
+            Message::Event("grab".to_string(), 0.5),
+        ];
+        for message in messages {
+            let packet = Packet { peer: PeerId(42), time: 12.5, message };
+            let bytes = encode(&packet);
+            let decoded = decode(&bytes).expect("round trip");
+            assert_eq!((decoded.peer, decoded.time), (packet.peer, packet.time));
+            match (&decoded.message, &packet.message) {
+                // Rotations travel as quaternions
+                (Message::Transforms(a), Message::Transforms(b)) => {
+                    for ((id_a, a), (id_b, b)) in a.iter().zip(b) {
+                        assert_eq!(id_a, id_b);
+                        assert!((a.position - b.position).length() < 1e-6 && (a.rotation - b.rotation).length() < 1e-5);
+                    }
+                }
+                (Message::Poses(a), Message::Poses(b)) => {
+                    assert!(a.head.unwrap().rotation.abs_diff_eq(b.head.unwrap().rotation, 1e-6));
+                    assert_eq!(a.left_controller, None);
+                }
+                (a, b) => assert_eq!(a, b),
+            }
+            // Truncated and foreign datagrams are ignored
+            assert!(decode(&bytes[..bytes.len() - 1]).is_none());
+        }
+        assert!(decode(b"GET / HTTP/1.1").is_none());
+    }
+
+    #[test]
+    fn test_snapshot_interpolation() {
+        let mut snapshots = Snapshots::default();
+        assert_eq!(snapshots.sample(0.0), None);
+        snapshots.push(1.0, pose(0.0));
+        snapshots.push(2.0, pose(1.0));
+        // Out of order arrivals are dropped
+        snapshots.push(1.5, pose(10.0));
+
+        assert_eq!(snapshots.sample(0.0), Some(pose(0.0)));
+        let halfway = snapshots.sample(1.5).unwrap();
+        assert!((halfway.position.x - 0.5).abs() < 1e-6);
+        assert!(halfway.rotation.abs_diff_eq(Quat::from_rotation_y(0.5), 1e-5));
+        // No extrapolation past the newest
+        assert_eq!(snapshots.sample(5.0), Some(pose(1.0)));
+
+        for i in 0..SNAPSHOTS * 2 {
+            snapshots.push(3.0 + i as f64, pose(0.0));
+        }
+        assert_eq!(snapshots.snapshots.len(), SNAPSHOTS);
+    }
+
+    /// Update both sessions until `done` or a second passes
+    fn exchange(a: &mut NetworkSession, b: &mut NetworkSession, scene: &mut Scene, mut done: impl FnMut(&NetworkSession, &NetworkSession) -> bool) -> bool {
+        for _ in 0..100 {
+            a.update(scene);
+            b.update(scene);
+            if done(a, b) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_loopback_session() {
+        let (Ok(mut desktop), Ok(mut headset)) = (
+            NetworkSession::bind("127.0.0.1:0", PeerId(1)),
+            NetworkSession::bind("127.0.0.1:0", PeerId(2)),
+        ) else {
+            println!("Skipping test 'test_loopback_session' - no loopback networking");
+            return;
+        };
+        let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.6, 3.0), 1.0));
+        headset.connect(desktop.local_address().unwrap()).unwrap();
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |a, b| a.peers().count() == 1 && b.peers().count() == 1));
+
+        // Poses fall back to the camera for the head
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |a, _| a.peer_poses(PeerId(2)).is_some()));
+        let head = desktop.peer_poses(PeerId(2)).unwrap().head.unwrap();
+        assert!((head.position - Vec3::new(0.0, 1.6, 3.0)).length() < 1e-5);
+
+        // Both claim an object at once; the lower id keeps it on both sides
+        let object = ObjectId(5);
+        assert!(desktop.claim(object) && headset.claim(object));
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |a, b| a.owner(object) == Some(PeerId(1)) && b.owner(object) == Some(PeerId(1))));
+        assert!(!headset.claim(object));
+        desktop.release(object);
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |_, b| b.owner(object).is_none()));
+        assert!(headset.claim(object));
+
+        // A lost release is repeated on the following heartbeats
+        let object = ObjectId(6);
+        assert!(desktop.claim(object));
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |_, b| b.owner(object) == Some(PeerId(1))));
+        desktop.release(object);
+        std::thread::sleep(Duration::from_millis(10));
+        // Drop everything in flight to the headset, the release included
+        let mut buffer = [0u8; MAX_PACKET];
+        while headset.socket.recv_from(&mut buffer).is_ok() {}
+        assert_eq!(headset.owner(object), Some(PeerId(1)));
+        desktop.last_heartbeat = f64::NEG_INFINITY;
+        assert!(exchange(&mut desktop, &mut headset, &mut scene, |_, b| b.owner(object).is_none()));
+
+        headset.send_event("teleport", 1.0);
+        let mut events = Vec::new();
+        for _ in 0..100 {
+            desktop.update(&mut scene);
+            events.extend(desktop.drain_events());
+            if !events.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(events, vec![NetworkEvent { peer: PeerId(2), name: "teleport".to_string(), value: 1.0 }]);
+    }
+}
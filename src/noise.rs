@@ -0,0 +1,314 @@
+//! Procedural noise: Perlin, simplex and Worley noise and fractal sums of them (fBm), for
+//! terrain, scattering and other procedural content.
+//!
+//! Shaders get the same functions from the `noise` snippet (`#include "noise"`), built on the
+//! same integer hash, so a field sampled on the CPU (say, to place objects on a terrain) matches
+//! the one a shader draws to within float rounding. Keep `shaders/include/noise.wgsl` in step
+//! with this module.
+
+use glam::{IVec2, IVec3, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// PCG hash (Jarzynski and Olano 2020)
+fn pcg(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn hash2(cell: IVec2, seed: u32) -> u32 {
+    pcg((cell.y as u32).wrapping_add(pcg((cell.x as u32).wrapping_add(seed))))
+}
+
+fn hash3(cell: IVec3, seed: u32) -> u32 {
+    pcg((cell.z as u32).wrapping_add(hash2(cell.truncate(), seed)))
+}
+
+/// The top 24 bits of `hash` as a float in 0..1, exact in f32
+fn unit(hash: u32) -> f32 {
+    (hash >> 8) as f32 / 16777216.0
+}
+
+/// `offset` dotted with one of the 12 cube edge gradients, picked by `hash` (Perlin 2002)
+fn gradient(hash: u32, offset: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { offset.x } else { offset.y };
+    let v = if h < 4 {
+        offset.y
+    } else if h == 12 || h == 14 {
+        offset.x
+    } else {
+        offset.z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Gradient noise, 0 at integer points and roughly in -1..1
+pub fn perlin2(p: Vec2, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let i = cell.as_ivec2();
+    let corner = |x: i32, y: i32| {
+        let offset = f - Vec2::new(x as f32, y as f32);
+        gradient(hash2(i + IVec2::new(x, y), seed), offset.extend(0.0))
+    };
+    let (u, v) = (fade(f.x), fade(f.y));
+    lerp(lerp(corner(0, 0), corner(1, 0), u), lerp(corner(0, 1), corner(1, 1), u), v)
+}
+
+/// Gradient noise, 0 at integer points and roughly in -1..1
+pub fn perlin3(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let i = cell.as_ivec3();
+    let corner = |x: i32, y: i32, z: i32| {
+        let offset = f - Vec3::new(x as f32, y as f32, z as f32);
+        gradient(hash3(i + IVec3::new(x, y, z), seed), offset)
+    };
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let near = lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), u), lerp(corner(0, 1, 0), corner(1, 1, 0), u), v);
+    let far = lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), u), lerp(corner(0, 1, 1), corner(1, 1, 1), u), v);
+    lerp(near, far, w)
+}
+
+/// Simplex noise (Gustavson's formulation), roughly in -1..1 with fewer axis-aligned artifacts
+/// than Perlin noise
+pub fn simplex2(p: Vec2, seed: u32) -> f32 {
+    const F2: f32 = 0.366_025_42;
+    const G2: f32 = 0.211_324_87;
+    // Skew into the lattice of triangles, then back to find the offsets from each corner
+    let cell = (p + Vec2::splat((p.x + p.y) * F2)).floor();
+    let i = cell.as_ivec2();
+    let x0 = p - (cell - Vec2::splat((cell.x + cell.y) * G2));
+    let i1 = if x0.x > x0.y { IVec2::new(1, 0) } else { IVec2::new(0, 1) };
+    let x1 = x0 - i1.as_vec2() + Vec2::splat(G2);
+    let x2 = x0 - Vec2::ONE + Vec2::splat(2.0 * G2);
+    let corner = |offset: Vec2, corner: IVec2| {
+        let t = 0.5 - offset.length_squared();
+        if t < 0.0 {
+            0.0
+        } else {
+            t * t * t * t * gradient(hash2(i + corner, seed), offset.extend(0.0))
+        }
+    };
+    70.0 * (corner(x0, IVec2::ZERO) + corner(x1, i1) + corner(x2, IVec2::ONE))
+}
+
+/// Simplex noise (Gustavson's formulation), roughly in -1..1
+pub fn simplex3(p: Vec3, seed: u32) -> f32 {
+    const F3: f32 = 1.0 / 3.0;
+    const G3: f32 = 1.0 / 6.0;
+    let cell = (p + Vec3::splat((p.x + p.y + p.z) * F3)).floor();
+    let i = cell.as_ivec3();
+    let x0 = p - (cell - Vec3::splat((cell.x + cell.y + cell.z) * G3));
+    // The two middle corners of the tetrahedron x0 lies in, from the order of its components
+    let (i1, i2) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            (IVec3::X, IVec3::new(1, 1, 0))
+        } else if x0.x >= x0.z {
+            (IVec3::X, IVec3::new(1, 0, 1))
+        } else {
+            (IVec3::Z, IVec3::new(1, 0, 1))
+        }
+    } else if x0.y < x0.z {
+        (IVec3::Z, IVec3::new(0, 1, 1))
+    } else if x0.x < x0.z {
+        (IVec3::Y, IVec3::new(0, 1, 1))
+    } else {
+        (IVec3::Y, IVec3::new(1, 1, 0))
+    };
+    let x1 = x0 - i1.as_vec3() + Vec3::splat(G3);
+    let x2 = x0 - i2.as_vec3() + Vec3::splat(2.0 * G3);
+    let x3 = x0 - Vec3::ONE + Vec3::splat(3.0 * G3);
+    let corner = |offset: Vec3, corner: IVec3| {
+        let t = 0.6 - offset.length_squared();
+        if t < 0.0 {
+            0.0
+        } else {
+            t * t * t * t * gradient(hash3(i + corner, seed), offset)
+        }
+    };
+    32.0 * (corner(x0, IVec3::ZERO) + corner(x1, i1) + corner(x2, i2) + corner(x3, IVec3::ONE))
+}
+
+/// Cellular noise: the distance to the nearest of one random point per unit cell, 0 at the
+/// points and rarely above 1
+pub fn worley2(p: Vec2, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let i = cell.as_ivec2();
+    let mut nearest = f32::MAX;
+    for y in -1..=1 {
+        for x in -1..=1 {
+            let hash = hash2(i + IVec2::new(x, y), seed);
+            let point = Vec2::new(x as f32 + unit(hash), y as f32 + unit(pcg(hash)));
+            nearest = nearest.min((point - f).length_squared());
+        }
+    }
+    nearest.sqrt()
+}
+
+/// Cellular noise: the distance to the nearest of one random point per unit cell
+pub fn worley3(p: Vec3, seed: u32) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let i = cell.as_ivec3();
+    let mut nearest = f32::MAX;
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let hash = hash3(i + IVec3::new(x, y, z), seed);
+                let jitter = Vec3::new(unit(hash), unit(pcg(hash)), unit(pcg(pcg(hash))));
+                let point = Vec3::new(x as f32, y as f32, z as f32) + jitter;
+                nearest = nearest.min((point - f).length_squared());
+            }
+        }
+    }
+    nearest.sqrt()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    #[default]
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+/// Fractal Brownian motion: octaves of a noise summed at rising frequency and falling weight,
+/// as the `fbm_*` functions of `noise.wgsl` compute it. Octave n is sampled at
+/// `frequency * lacunarity^n` with weight `gain^n` and seed `seed + n`; the sum is divided by
+/// the total weight, so it keeps the range of the noise it's built from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fbm {
+    pub kind: NoiseKind,
+    pub seed: u32,
+    pub frequency: f32,
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Default for Fbm {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            seed: 0,
+            frequency: 1.0,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fbm {
+    pub fn sample2(&self, p: Vec2) -> f32 {
+        let noise = match self.kind {
+            NoiseKind::Perlin => perlin2,
+            NoiseKind::Simplex => simplex2,
+            NoiseKind::Worley => worley2,
+        };
+        let p = p * self.frequency;
+        self.sum(|frequency, seed| noise(p * frequency, seed))
+    }
+
+    pub fn sample3(&self, p: Vec3) -> f32 {
+        let noise = match self.kind {
+            NoiseKind::Perlin => perlin3,
+            NoiseKind::Simplex => simplex3,
+            NoiseKind::Worley => worley3,
+        };
+        let p = p * self.frequency;
+        self.sum(|frequency, seed| noise(p * frequency, seed))
+    }
+
+    fn sum(&self, octave: impl Fn(f32, u32) -> f32) -> f32 {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut amplitude, mut frequency) = (1.0, 1.0);
+        for index in 0..self.octaves {
+            sum += amplitude * octave(frequency, self.seed.wrapping_add(index));
+            total += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if total > 0.0 { sum / total } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points spread over a few cells, including negative coordinates
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..500).map(|index| {
+            let t = index as f32;
+            Vec3::new((t * 0.731).sin() * 7.3, (t * 0.377).cos() * 5.1 - 0.4, t * 0.0213 - 4.9)
+        })
+    }
+
+    #[test]
+    fn test_noise_ranges() {
+        for p in points() {
+            for value in [perlin2(p.truncate(), 3), perlin3(p, 3), simplex2(p.truncate(), 3), simplex3(p, 3)] {
+                assert!(value.abs() <= 1.1, "{value} at {p}");
+            }
+            for value in [worley2(p.truncate(), 3), worley3(p, 3)] {
+                assert!((0.0..=1.5).contains(&value), "{value} at {p}");
+            }
+        }
+        // Perlin noise vanishes at lattice points
+        assert_eq!(perlin2(Vec2::new(3.0, -2.0), 7), 0.0);
+        assert_eq!(perlin3(Vec3::new(-1.0, 4.0, 0.0), 7), 0.0);
+    }
+
+    #[test]
+    fn test_noise_is_deterministic_and_seeded() {
+        let samples = |seed| -> Vec<f32> { points().map(|p| simplex3(p, seed) + worley2(p.truncate(), seed)).collect() };
+        assert_eq!(samples(1), samples(1));
+        assert_ne!(samples(1), samples(2));
+        // Not flat: the samples actually vary
+        let values: Vec<f32> = points().map(|p| perlin3(p, 0)).collect();
+        let spread = values.iter().fold(f32::MIN, |a, &b| a.max(b)) - values.iter().fold(f32::MAX, |a, &b| a.min(b));
+        assert!(spread > 0.5, "{spread}");
+    }
+
+    #[test]
+    fn test_noise_is_continuous() {
+        let step = Vec3::splat(1e-3);
+        for p in points() {
+            assert!((perlin3(p, 0) - perlin3(p + step, 0)).abs() < 0.02, "perlin at {p}");
+            assert!((simplex3(p, 0) - simplex3(p + step, 0)).abs() < 0.05, "simplex at {p}");
+            assert!((simplex2(p.truncate(), 0) - simplex2(p.truncate() + step.truncate(), 0)).abs() < 0.05);
+            assert!((worley3(p, 0) - worley3(p + step, 0)).abs() < 0.01, "worley at {p}");
+        }
+    }
+
+    #[test]
+    fn test_fbm() {
+        let p = Vec3::new(1.3, -0.7, 2.2);
+        // One octave is the noise itself at the scaled position
+        let single = Fbm { kind: NoiseKind::Simplex, seed: 4, frequency: 2.0, octaves: 1, ..Fbm::default() };
+        assert_eq!(single.sample3(p), simplex3(p * 2.0, 4));
+
+        let fbm = Fbm { octaves: 6, ..Fbm::default() };
+        for p in points() {
+            assert!(fbm.sample2(p.truncate()).abs() <= 1.1);
+        }
+        assert_eq!(Fbm { octaves: 0, ..fbm }.sample3(p), 0.0);
+
+        let parsed: Fbm = toml::from_str("kind = \"worley\"\noctaves = 3").unwrap();
+        assert_eq!(parsed, Fbm { kind: NoiseKind::Worley, octaves: 3, ..Fbm::default() });
+    }
+}
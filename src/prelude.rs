@@ -0,0 +1,21 @@
+//! The supported public surface: `use wgpu_3d_viewer::prelude::*;`
+//!
+//! Types reachable only through their modules (individual post passes, GPU upload helpers,
+//! OpenXR plumbing) are implementation details and may change between releases.
+
+pub use crate::{InitProgress, InitStage, LoadMode, RenderMode, State};
+pub use crate::capture::FrameCapture;
+pub use crate::color::Color;
+pub use crate::geometry::{Extrusion, Profile, Spline, SplineKind};
+pub use crate::input::{GamepadAxis, GamepadButton, InputEvent, InputState, Key};
+pub use crate::noise::{Fbm, NoiseKind};
+pub use crate::model::{AnimationClip, AssetRegistry, AtlasOptions, AtlasReport, BackgroundLoader, ChromaKey, CompressionOptions, DynamicMesh, ImportOptions, LengthUnit, LoadedModel, Material, MergeOptions, MaterialHandle, MeshReport, Model, ModelData, ModelHandle, Node, Skeleton, SharedMemoryFrames, TextureHandle, ValidationReport, VideoChannel, VideoSender, VideoSource, VideoTexture, Wind, WindWeight};
+pub use crate::scene::{Anchor, AnchorPoses, AnchorTarget, Anchors, Animation, Avatar, AvatarCalibration, AvatarParts, Capabilities, CellId, ClipPlane, Clipping, ComputeBinding, ComputeStage, ComputeTask, ComputeTaskId, CullingStats, Density, DensityMap, Easing, EyeDebugMode, FloatingOrigin, Frustum, Hierarchy, Level, LevelStreaming, LightId, MeshSurface, MotionBlur, ObjectId, Occluder, Occluders, OutputAlpha, PickId, PickResult, Placement, Playback, PointLight, PortalGraph, Prefab, PrefabInstance, PrefabOverrides, ProbeBakeSettings, ProbeGrid, QualityTier, Ray, RayHit, RenderView, Renderer, Scatter, ScatterSurface, Scene, Sequence, Sequencer, SceneObject, SceneObjects, Skinning, Spectator, SpectatorMode, SpectatorSettings, Stencil, StreamingVolume, StencilOverlay, Transform, Transparency, TweenId, Tweens, ViewCulling, ViewInfo, Viewport, Visibility};
+pub use crate::scene::camera::{Camera, Projection, ViewAngle};
+#[cfg(feature = "scripting")]
+pub use crate::scene::ScriptHost;
+pub use crate::session::{Autosave, Session, SessionObject};
+pub use crate::settings::{DepthFormat, RendererSettings, ShadowQuality, ViewportMode};
+pub use crate::simulation::{InputRecording, Rng, Simulation};
+#[cfg(not(any(target_vendor = "apple", target_arch = "wasm32")))]
+pub use crate::vr::{HudAnchor, HudStats, VRSystem, VrHud};
@@ -0,0 +1,221 @@
+//! Copying GPU buffers and textures back to the CPU, for screenshots, picking and compute
+//! results. Each call stages the copy, submits it and blocks until the data is mapped.
+
+use std::sync::mpsc;
+use anyhow::{anyhow, bail, Result};
+
+/// Every element of `buffer`, which needs `COPY_SRC` usage
+pub fn read_buffer<T: bytemuck::Pod>(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Result<Vec<T>> {
+    let count = buffer.size() as usize / std::mem::size_of::<T>().max(1);
+    read_buffer_range(device, queue, buffer, 0, count)
+}
+
+/// `count` elements of `buffer` starting `offset` bytes in. Offsets and sizes needn't meet
+/// wgpu's copy alignment; the copy is widened and trimmed.
+pub fn read_buffer_range<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: u64,
+    count: usize,
+) -> Result<Vec<T>> {
+    if !buffer.usage().contains(wgpu::BufferUsages::COPY_SRC) {
+        bail!("buffer can't be read back without COPY_SRC usage");
+    }
+    let size = (count * std::mem::size_of::<T>()) as u64;
+    if offset + size > buffer.size() {
+        bail!("reading {} bytes at {} overruns a buffer of {} bytes", size, offset, buffer.size());
+    }
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+
+    let start = offset - offset % wgpu::COPY_BUFFER_ALIGNMENT;
+    let end = (offset + size).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT).min(buffer.size());
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size: end - start,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, start, &staging, 0, end - start);
+    let submission = queue.submit(Some(encoder.finish()));
+
+    let bytes = map_staging(device, &staging, submission)?;
+    let skip = (offset - start) as usize;
+    // Copied out rather than cast in place: the mapping needn't be aligned for `T`
+    let mut values = vec![T::zeroed(); count];
+    bytemuck::cast_slice_mut(&mut values).copy_from_slice(&bytes[skip..skip + size as usize]);
+    Ok(values)
+}
+
+/// Part of one mip level and array layer of a texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mip_level: u32,
+    pub layer: u32,
+}
+
+impl TextureRegion {
+    /// All of mip level 0, layer 0
+    pub fn full(texture: &wgpu::Texture) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: texture.width(),
+            height: texture.height(),
+            mip_level: 0,
+            layer: 0,
+        }
+    }
+}
+
+/// Texels read back from a texture, rows tightly packed
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub bytes_per_texel: u32,
+    pub data: Vec<u8>,
+}
+
+impl TextureData {
+    /// Bytes of the texel at `(x, y)` within the region
+    pub fn texel(&self, x: u32, y: u32) -> &[u8] {
+        let start = ((y * self.width + x) * self.bytes_per_texel) as usize;
+        &self.data[start..start + self.bytes_per_texel as usize]
+    }
+
+    /// The texels as RGBA8, for 8-bit RGBA and BGRA formats (e.g. a screenshot of the surface)
+    pub fn to_rgba8(&self) -> Option<Vec<u8>> {
+        use wgpu::TextureFormat::*;
+        match self.format {
+            Rgba8Unorm | Rgba8UnormSrgb => Some(self.data.clone()),
+            Bgra8Unorm | Bgra8UnormSrgb => Some(
+                self.data.chunks_exact(4)
+                    .flat_map(|texel| [texel[2], texel[1], texel[0], texel[3]])
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Read `region` of `texture`, which needs `COPY_SRC` usage, a single sample and an
+/// uncompressed format. Depth textures read their depth aspect where the format allows copies.
+pub fn read_texture_region(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    region: TextureRegion,
+) -> Result<TextureData> {
+    if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+        bail!("texture can't be read back without COPY_SRC usage");
+    }
+    if texture.sample_count() > 1 {
+        bail!("multisampled textures can't be copied; resolve them first");
+    }
+    let format = texture.format();
+    if format.block_dimensions() != (1, 1) {
+        bail!("reading back compressed format {:?} isn't supported", format);
+    }
+    let aspect = if format.has_depth_aspect() { wgpu::TextureAspect::DepthOnly } else { wgpu::TextureAspect::All };
+    let bytes_per_texel = format.block_copy_size(Some(aspect))
+        .ok_or_else(|| anyhow!("format {:?} can't be copied to a buffer", format))?;
+
+    let mip_size = texture.size().mip_level_size(region.mip_level, texture.dimension());
+    if region.mip_level >= texture.mip_level_count()
+        || region.layer >= texture.depth_or_array_layers()
+        || region.x + region.width > mip_size.width
+        || region.y + region.height > mip_size.height
+    {
+        bail!("{:?} lies outside the texture", region);
+    }
+    let row_bytes = region.width * bytes_per_texel;
+    if row_bytes == 0 || region.height == 0 {
+        return Ok(TextureData { width: region.width, height: region.height, format, bytes_per_texel, data: Vec::new() });
+    }
+
+    // Buffer rows must be padded to COPY_BYTES_PER_ROW_ALIGNMENT
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size: (padded_row_bytes * region.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: region.mip_level,
+            origin: wgpu::Origin3d { x: region.x, y: region.y, z: region.layer },
+            aspect,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d { width: region.width, height: region.height, depth_or_array_layers: 1 },
+    );
+    let submission = queue.submit(Some(encoder.finish()));
+
+    let bytes = map_staging(device, &staging, submission)?;
+    let data = bytes.chunks_exact(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect();
+    Ok(TextureData { width: region.width, height: region.height, format, bytes_per_texel, data })
+}
+
+/// Map a staging buffer once `submission` has finished and copy its contents out
+fn map_staging(device: &wgpu::Device, staging: &wgpu::Buffer, submission: wgpu::SubmissionIndex) -> Result<Vec<u8>> {
+    let slice = staging.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    // Waiting on the submission also runs the map callback
+    device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+    receiver.recv()
+        .map_err(|_| anyhow!("device lost before the readback finished"))?
+        .map_err(|e| anyhow!("mapping the readback buffer failed: {}", e))?;
+
+    let bytes = slice.get_mapped_range().to_vec();
+    staging.unmap();
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba8_swizzles_bgra() {
+        let data = TextureData {
+            width: 2,
+            height: 1,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            bytes_per_texel: 4,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        assert_eq!(data.texel(1, 0), &[5, 6, 7, 8]);
+        assert_eq!(data.to_rgba8().unwrap(), vec![3, 2, 1, 4, 7, 6, 5, 8]);
+        let float = TextureData { format: wgpu::TextureFormat::R32Float, ..data };
+        assert!(float.to_rgba8().is_none());
+    }
+}
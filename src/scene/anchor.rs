@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use glam::{Mat4, Quat, Vec3};
+use super::{ObjectId, SceneObjects, Transform};
+
+/// What an anchored object is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnchorTarget {
+    /// Follows the viewer's head: the headset in VR, the camera on the desktop
+    #[default]
+    Head,
+    LeftController,
+    RightController,
+    /// Stays where it is in the world, e.g. a panel pinned in place after following the head
+    World,
+}
+
+/// How an object follows its target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anchor {
+    pub target: AnchorTarget,
+    /// The object's transform in the target's space; -Z is ahead of the head or controller
+    pub offset: Transform,
+    /// Seconds the object takes to catch up most (63%) of the way to its target; 0 is rigid.
+    /// A little lag on head-locked panels keeps them from swimming with every small head
+    /// movement, which is a common cause of discomfort.
+    pub lag: f32,
+    /// Follow only the target's heading and keep the object upright, so a head-locked panel
+    /// doesn't roll and pitch with the head
+    pub level: bool,
+}
+
+impl Anchor {
+    /// A panel `distance` meters ahead of the eyes, trailing head turns by `lag` seconds
+    pub fn head(distance: f32, lag: f32) -> Self {
+        let mut offset = Transform::new();
+        offset.position = Vec3::new(0.0, 0.0, -distance);
+        Self { target: AnchorTarget::Head, offset, lag, level: true }
+    }
+
+    /// Rigidly attached to a controller at `offset`
+    pub fn controller(target: AnchorTarget, offset: Transform) -> Self {
+        Self { target, offset, lag: 0.0, level: false }
+    }
+
+    /// Left where it is
+    pub fn world() -> Self {
+        Self { target: AnchorTarget::World, offset: Transform::new(), lag: 0.0, level: false }
+    }
+}
+
+/// World transforms of what anchors can follow this frame; `None` for untracked ones, whose
+/// objects stay where they were
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnchorPoses {
+    /// Defaults to the camera when not set, so head-locked objects work on the desktop too
+    pub head: Option<Mat4>,
+    pub left_controller: Option<Mat4>,
+    pub right_controller: Option<Mat4>,
+}
+
+impl AnchorPoses {
+    fn get(&self, target: AnchorTarget) -> Option<Mat4> {
+        match target {
+            AnchorTarget::Head => self.head,
+            AnchorTarget::LeftController => self.left_controller,
+            AnchorTarget::RightController => self.right_controller,
+            AnchorTarget::World => None,
+        }
+    }
+}
+
+/// Objects attached to the viewer's head or controllers, updated by `Scene::update` from the
+/// poses given with `set_poses`
+#[derive(Debug, Default)]
+pub struct Anchors {
+    anchors: BTreeMap<ObjectId, Anchor>,
+    poses: AnchorPoses,
+}
+
+impl Anchors {
+    /// Attach `object`, replacing any anchor it had
+    pub fn attach(&mut self, object: ObjectId, anchor: Anchor) {
+        self.anchors.insert(object, anchor);
+    }
+
+    /// Stop moving `object`; it stays where it is
+    pub fn detach(&mut self, object: ObjectId) -> Option<Anchor> {
+        self.anchors.remove(&object)
+    }
+
+    pub fn get(&self, object: ObjectId) -> Option<&Anchor> {
+        self.anchors.get(&object)
+    }
+
+    pub fn get_mut(&mut self, object: ObjectId) -> Option<&mut Anchor> {
+        self.anchors.get_mut(&object)
+    }
+
+    /// Poses for the next update; the frontend sets these each frame, e.g. from
+    /// `VRSystem::head_pose` and `VRSystem::pose_to_world`
+    pub fn set_poses(&mut self, poses: AnchorPoses) {
+        self.poses = poses;
+    }
+
+    pub fn poses(&self) -> &AnchorPoses {
+        &self.poses
+    }
+
+    /// Move every anchored object towards its target, with `camera` standing in for a head
+    /// pose that wasn't set. Anchors of removed objects are dropped.
+    pub(crate) fn update(&mut self, dt: f32, camera: Mat4, objects: &mut SceneObjects) {
+        let poses = AnchorPoses { head: self.poses.head.or(Some(camera)), ..self.poses };
+        self.anchors.retain(|&object, anchor| {
+            let Some(index) = objects.index_of(object) else {
+                return false;
+            };
+            if let Some(pose) = poses.get(anchor.target) {
+                let transform = &mut objects.transforms_mut()[index];
+                *transform = follow(transform, &target_transform(anchor, pose), anchor.lag, dt);
+            }
+            true
+        });
+    }
+
+    /// Follow a floating-origin rebase; poses set afterwards are expected in the new origin
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        for pose in [&mut self.poses.head, &mut self.poses.left_controller, &mut self.poses.right_controller].into_iter().flatten() {
+            *pose = Mat4::from_translation(offset) * *pose;
+        }
+    }
+}
+
+/// Where `anchor` puts its object for a target at `pose`
+fn target_transform(anchor: &Anchor, pose: Mat4) -> Transform {
+    let (_, rotation, position) = pose.to_scale_rotation_translation();
+    let rotation = if anchor.level { heading(rotation) } else { rotation };
+    Transform::from_matrix(Mat4::from_rotation_translation(rotation, position) * anchor.offset.to_matrix())
+}
+
+/// Only the heading of `rotation`: the direction it faces, flattened onto the ground
+pub(crate) fn heading(rotation: Quat) -> Quat {
+    let forward = rotation * Vec3::NEG_Z;
+    if forward.x.abs() + forward.z.abs() > 1e-6 {
+        Quat::from_rotation_y(f32::atan2(-forward.x, -forward.z))
+    } else {
+        // Looking straight up or down; the up vector still knows the heading
+        let up = rotation * Vec3::Y * forward.y.signum();
+        Quat::from_rotation_y(f32::atan2(-up.x, -up.z))
+    }
+}
+
+/// `current` moved towards `target` by an exponential lag, which behaves the same at any
+/// frame rate
+fn follow(current: &Transform, target: &Transform, lag: f32, dt: f32) -> Transform {
+    if lag <= 0.0 {
+        return *target;
+    }
+    let t = 1.0 - (-dt / lag).exp();
+    let to_quat = |rotation: Vec3| Quat::from_euler(glam::EulerRot::XYZ, rotation.x, rotation.y, rotation.z);
+    let rotation = to_quat(current.rotation).slerp(to_quat(target.rotation), t);
+    let (x, y, z) = rotation.to_euler(glam::EulerRot::XYZ);
+    Transform {
+        position: current.position.lerp(target.position, t),
+        rotation: Vec3::new(x, y, z),
+        scale: current.scale.lerp(target.scale, t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchor_targets() {
+        // A head at eye height, turned to face +X and looking down a little
+        let head = Mat4::from_translation(Vec3::new(0.0, 1.6, 0.0))
+            * Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2)
+            * Mat4::from_rotation_x(-0.3);
+
+        // Level panels stay at eye height, ahead along the heading
+        let level = target_transform(&Anchor::head(2.0, 0.0), head);
+        assert!((level.position - Vec3::new(2.0, 1.6, 0.0)).length() < 1e-5, "{}", level.position);
+        assert!(level.rotation.x.abs() < 1e-5 && level.rotation.z.abs() < 1e-5);
+
+        // Unlevelled ones follow the head's pitch too
+        let tilted = target_transform(&Anchor { level: false, ..Anchor::head(2.0, 0.0) }, head);
+        assert!(tilted.position.y < 1.6 - 0.5);
+
+        // Lag closes the same share of the gap at any frame rate
+        let start = Transform::new();
+        let target = Transform { position: Vec3::new(10.0, 0.0, 0.0), ..Transform::new() };
+        let once = follow(&start, &target, 0.2, 0.2);
+        let mut twice = follow(&start, &target, 0.2, 0.1);
+        twice = follow(&twice, &target, 0.2, 0.1);
+        assert!((once.position.x - 10.0 * (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+        assert!((once.position.x - twice.position.x).abs() < 1e-4);
+        assert_eq!(follow(&start, &target, 0.0, 0.016), target);
+
+        // World anchors and untracked controllers leave objects alone
+        let poses = AnchorPoses { head: Some(head), ..AnchorPoses::default() };
+        assert_eq!(poses.get(AnchorTarget::World), None);
+        assert_eq!(poses.get(AnchorTarget::LeftController), None);
+    }
+}
@@ -0,0 +1,284 @@
+use glam::{Mat3, Mat4, Quat, Vec3};
+use super::anchor::heading;
+use super::{AnchorPoses, ObjectId, SceneObjects, Transform};
+
+/// Body measurements the arms are solved with, in meters. The defaults fit an adult of about
+/// 1.75m; `from_t_pose` measures the user instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AvatarCalibration {
+    /// From the eyes down to the base of the neck, level with the shoulders
+    pub neck_drop: f32,
+    /// How far the base of the neck sits behind the eyes
+    pub neck_back: f32,
+    /// Between the two shoulder joints
+    pub shoulder_width: f32,
+    /// Shoulder to elbow
+    pub upper_arm: f32,
+    /// Elbow to where the controller is held
+    pub forearm: f32,
+}
+
+impl Default for AvatarCalibration {
+    fn default() -> Self {
+        Self { neck_drop: 0.22, neck_back: 0.08, shoulder_width: 0.38, upper_arm: 0.28, forearm: 0.305 }
+    }
+}
+
+impl AvatarCalibration {
+    /// Measure the user standing with their arms held straight out to the sides, from the
+    /// head and controller world transforms: the controllers give the arm span and, level
+    /// with the shoulders, the neck drop. Proportions the pose can't show keep the defaults'.
+    pub fn from_t_pose(head: Mat4, left: Mat4, right: Mat4) -> Self {
+        let defaults = Self::default();
+        let (left, right) = (left.w_axis.truncate(), right.w_axis.truncate());
+        let scale = left.distance(right) / defaults.span();
+        let shoulders = (left.y + right.y) * 0.5;
+        Self {
+            neck_drop: (head.w_axis.y - shoulders).max(0.0),
+            neck_back: defaults.neck_back * scale,
+            shoulder_width: defaults.shoulder_width * scale,
+            upper_arm: defaults.upper_arm * scale,
+            forearm: defaults.forearm * scale,
+        }
+    }
+
+    /// Controller to controller with the arms held straight out
+    pub fn span(&self) -> f32 {
+        self.shoulder_width + 2.0 * self.reach()
+    }
+
+    /// Shoulder to controller with the arm straight
+    pub fn reach(&self) -> f32 {
+        self.upper_arm + self.forearm
+    }
+}
+
+/// One arm's solved joints, in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArmPose {
+    pub shoulder: Vec3,
+    pub elbow: Vec3,
+    /// The controller's position, or as near it as the arm reaches
+    pub hand: Vec3,
+    pub hand_rotation: Quat,
+    /// Unit direction the elbow points, away from the line between shoulder and hand
+    pub bend: Vec3,
+}
+
+/// The upper body `solve` fits between the head and controllers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AvatarPose {
+    pub head: Mat4,
+    /// Base of the neck, between the shoulders
+    pub neck: Vec3,
+    /// The way the torso faces: the head's heading, kept upright
+    pub heading: Quat,
+    pub left: ArmPose,
+    pub right: ArmPose,
+}
+
+/// Three-point IK: place the neck and shoulders under `head` and bend each arm to reach its
+/// controller. Untracked controllers leave their arm hanging at the side; controllers out of
+/// reach get a straight arm pointing at them.
+pub fn solve(calibration: &AvatarCalibration, head: Mat4, left: Option<Mat4>, right: Option<Mat4>) -> AvatarPose {
+    let (_, head_rotation, eyes) = head.to_scale_rotation_translation();
+    let heading = heading(head_rotation);
+    let neck = eyes + heading * Vec3::new(0.0, -calibration.neck_drop, calibration.neck_back);
+    let shoulder = |side: f32| neck + heading * Vec3::new(side * calibration.shoulder_width * 0.5, 0.0, 0.0);
+    AvatarPose {
+        head,
+        neck,
+        heading,
+        left: solve_arm(calibration, heading, -1.0, shoulder(-1.0), left),
+        right: solve_arm(calibration, heading, 1.0, shoulder(1.0), right),
+    }
+}
+
+/// Two-bone IK for the arm on `side` (-1 left, 1 right)
+fn solve_arm(calibration: &AvatarCalibration, heading: Quat, side: f32, shoulder: Vec3, controller: Option<Mat4>) -> ArmPose {
+    let (upper, forearm) = (calibration.upper_arm, calibration.forearm);
+    let (target, hand_rotation) = match controller {
+        Some(controller) => {
+            let (_, rotation, position) = controller.to_scale_rotation_translation();
+            (position, rotation)
+        }
+        None => (shoulder + heading * Vec3::new(side * 0.05, -calibration.reach(), 0.0), heading),
+    };
+
+    let to_target = target - shoulder;
+    let direction = to_target.try_normalize().unwrap_or(heading * Vec3::NEG_Y);
+    // Keep the elbow from locking straight or folding flat, where its direction is undefined
+    let distance = to_target.length().min(upper + forearm - 1e-4).max((upper - forearm).abs() + 1e-4);
+
+    // Elbows drop down and out to the side, a little behind the body
+    let hint = heading * Vec3::new(side * 0.5, -1.0, 0.3);
+    let bend = (hint - direction * hint.dot(direction)).try_normalize().unwrap_or_else(|| direction.any_orthonormal_vector());
+    // Law of cosines for the shoulder's angle between the upper arm and the hand
+    let cos = ((upper * upper + distance * distance - forearm * forearm) / (2.0 * upper * distance)).clamp(-1.0, 1.0);
+    let elbow = shoulder + (direction * cos + bend * (1.0 - cos * cos).sqrt()) * upper;
+
+    ArmPose { shoulder, elbow, hand: shoulder + direction * distance, hand_rotation, bend }
+}
+
+/// Scene objects standing in for body parts, moved by `Avatar::update`; any left `None` are
+/// not drawn. Arm segments are modelled along their local +Y from the joint nearer the body,
+/// with -Z pointing where the elbow does; the torso hangs down its -Y from the neck.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AvatarParts {
+    /// Usually left out in first person, where it would fill the view
+    pub head: Option<ObjectId>,
+    pub torso: Option<ObjectId>,
+    pub left_upper_arm: Option<ObjectId>,
+    pub left_forearm: Option<ObjectId>,
+    pub left_hand: Option<ObjectId>,
+    pub right_upper_arm: Option<ObjectId>,
+    pub right_forearm: Option<ObjectId>,
+    pub right_hand: Option<ObjectId>,
+}
+
+impl AvatarParts {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// An upper body driven by the viewer's head and controllers, so embodied demos show arms
+/// rather than floating controllers. `Scene::update` solves it from the anchor poses.
+#[derive(Debug, Default)]
+pub struct Avatar {
+    pub calibration: AvatarCalibration,
+    pub parts: AvatarParts,
+    pose: Option<AvatarPose>,
+}
+
+impl Avatar {
+    /// Fit the arms to the user from a T-pose; see `AvatarCalibration::from_t_pose`
+    pub fn calibrate(&mut self, head: Mat4, left: Mat4, right: Mat4) {
+        self.calibration = AvatarCalibration::from_t_pose(head, left, right);
+    }
+
+    /// The pose of the last update, `None` before the first or without any parts
+    pub fn pose(&self) -> Option<&AvatarPose> {
+        self.pose.as_ref()
+    }
+
+    /// Solve the body from `poses`, with `camera` standing in for a head pose that wasn't set,
+    /// and move the parts onto it. Parts keep their scale.
+    pub(crate) fn update(&mut self, poses: &AnchorPoses, camera: Mat4, objects: &mut SceneObjects) {
+        if self.parts.is_empty() {
+            self.pose = None;
+            return;
+        }
+        let pose = solve(&self.calibration, poses.head.unwrap_or(camera), poses.left_controller, poses.right_controller);
+        let parts = &self.parts;
+
+        let (_, head_rotation, eyes) = pose.head.to_scale_rotation_translation();
+        place(objects, parts.head, head_rotation, eyes);
+        place(objects, parts.torso, pose.heading, pose.neck);
+        for (arm, upper, forearm, hand) in [
+            (&pose.left, parts.left_upper_arm, parts.left_forearm, parts.left_hand),
+            (&pose.right, parts.right_upper_arm, parts.right_forearm, parts.right_hand),
+        ] {
+            place(objects, upper, segment_rotation(arm.elbow - arm.shoulder, arm.bend), arm.shoulder);
+            place(objects, forearm, segment_rotation(arm.hand - arm.elbow, arm.bend), arm.elbow);
+            place(objects, hand, arm.hand_rotation, arm.hand);
+        }
+        self.pose = Some(pose);
+    }
+}
+
+/// Turn +Y along `along` and -Z towards `bend`
+fn segment_rotation(along: Vec3, bend: Vec3) -> Quat {
+    let y = along.normalize_or_zero();
+    let Some(z) = (bend - y * bend.dot(y)).try_normalize().map(|bend| -bend) else {
+        return Quat::from_rotation_arc(Vec3::Y, y);
+    };
+    Quat::from_mat3(&Mat3::from_cols(y.cross(z), y, z))
+}
+
+fn place(objects: &mut SceneObjects, part: Option<ObjectId>, rotation: Quat, position: Vec3) {
+    let Some(index) = part.and_then(|id| objects.index_of(id)) else {
+        return;
+    };
+    let transform = &mut objects.transforms_mut()[index];
+    *transform = Transform { scale: transform.scale, ..Transform::from_matrix(Mat4::from_rotation_translation(rotation, position)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32, y: f32, z: f32) -> Mat4 {
+        Mat4::from_translation(Vec3::new(x, y, z))
+    }
+
+    #[test]
+    fn test_arm_lengths() {
+        let calibration = AvatarCalibration::default();
+        let pose = solve(&calibration, at(0.0, 1.6, 0.0), Some(at(-0.3, 1.1, -0.35)), Some(at(0.2, 1.3, -0.4)));
+        for (arm, target) in [(&pose.left, Vec3::new(-0.3, 1.1, -0.35)), (&pose.right, Vec3::new(0.2, 1.3, -0.4))] {
+            // Within reach the hand meets the controller, and the bones keep their lengths
+            assert!((arm.hand - target).length() < 1e-4, "{} != {}", arm.hand, target);
+            assert!((arm.shoulder.distance(arm.elbow) - calibration.upper_arm).abs() < 1e-4);
+            assert!((arm.elbow.distance(arm.hand) - calibration.forearm).abs() < 1e-4);
+            // Elbows drop below the line from shoulder to hand
+            assert!(arm.elbow.y < (arm.shoulder.y + arm.hand.y) * 0.5);
+        }
+        // ...and out to their own side
+        assert!(pose.left.elbow.x < pose.left.shoulder.x.min(pose.left.hand.x) + 0.05);
+        assert!(pose.right.elbow.x > pose.right.shoulder.x - 0.05);
+
+        // Shoulders sit level under the eyes, a little behind them
+        assert!((pose.neck - Vec3::new(0.0, 1.6 - calibration.neck_drop, calibration.neck_back)).length() < 1e-5);
+        assert!((pose.left.shoulder.distance(pose.right.shoulder) - calibration.shoulder_width).abs() < 1e-5);
+        assert!(pose.left.shoulder.x < pose.right.shoulder.x);
+    }
+
+    #[test]
+    fn test_out_of_reach() {
+        let calibration = AvatarCalibration::default();
+        // Turned to face +X, with the right controller far ahead
+        let head = at(0.0, 1.6, 0.0) * Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2);
+        let pose = solve(&calibration, head, None, Some(at(3.0, 1.4, 0.5)));
+
+        // The arm points straight at the controller, as far as it goes
+        let arm = pose.right;
+        let direction = (Vec3::new(3.0, 1.4, 0.5) - arm.shoulder).normalize();
+        assert!(((arm.hand - arm.shoulder).normalize() - direction).length() < 1e-3);
+        assert!((arm.shoulder.distance(arm.hand) - calibration.reach()).abs() < 1e-3);
+        // Turned right, the right shoulder is towards +Z
+        assert!(arm.shoulder.z > pose.neck.z);
+
+        // The untracked left arm hangs down at its side
+        let left = pose.left;
+        assert!(left.hand.y < left.elbow.y && left.elbow.y < left.shoulder.y);
+        assert!(Vec3::new(left.hand.x - left.shoulder.x, 0.0, left.hand.z - left.shoulder.z).length() < 0.1);
+        assert!((left.shoulder.distance(left.elbow) - calibration.upper_arm).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_calibration() {
+        // A taller user: arms out at 1.5m, eyes at 1.8m
+        let head = at(0.0, 1.8, 0.0);
+        let calibration = AvatarCalibration::from_t_pose(head, at(-0.9, 1.5, 0.0), at(0.9, 1.5, 0.0));
+        assert!((calibration.span() - 1.8).abs() < 1e-5);
+        assert!((calibration.neck_drop - 0.3).abs() < 1e-5);
+        let defaults = AvatarCalibration::default();
+        assert!((calibration.upper_arm / calibration.forearm - defaults.upper_arm / defaults.forearm).abs() < 1e-5);
+
+        // Arms calibrated in a T-pose reach straight back out to it
+        let pose = solve(&calibration, head, Some(at(-0.9, 1.5, 0.0)), Some(at(0.9, 1.5, 0.0)));
+        assert!((pose.left.hand - Vec3::new(-0.9, 1.5, 0.0)).length() < 1e-2);
+        assert!((pose.right.elbow.y - 1.5).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_segment_rotation() {
+        let rotation = segment_rotation(Vec3::new(0.0, 0.0, -2.0), Vec3::NEG_Y);
+        assert!((rotation * Vec3::Y - Vec3::NEG_Z).length() < 1e-5);
+        assert!((rotation * Vec3::NEG_Z - Vec3::NEG_Y).length() < 1e-5);
+        // Bending along the segment leaves the twist free
+        let rotation = segment_rotation(Vec3::X, Vec3::X);
+        assert!((rotation * Vec3::Y - Vec3::X).length() < 1e-5);
+    }
+}
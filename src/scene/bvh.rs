@@ -0,0 +1,320 @@
+use glam::Vec3;
+use super::ObjectId;
+use super::ray::Ray;
+
+/// Most objects in a leaf
+const LEAF_SIZE: usize = 4;
+
+/// An object in a `Bvh`: its world bounds and where it sits in `SceneObjects`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BvhItem {
+    pub id: ObjectId,
+    /// Component array index in `SceneObjects`
+    pub index: usize,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    min: Vec3,
+    max: Vec3,
+    /// First item of a leaf, or the left child of an inner node (the right one follows it)
+    first: usize,
+    /// Items in a leaf; 0 for inner nodes
+    count: usize,
+}
+
+/// A bounding volume hierarchy over object bounds, so raycasts and culling visit the few
+/// objects near the ray or view instead of all of them. Built top down, splitting each node at
+/// the median of its objects along the longest axis; `refit` updates it in place for moved
+/// objects without changing which objects share a node.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<BvhItem>,
+}
+
+impl Bvh {
+    pub fn build(mut items: Vec<BvhItem>) -> Self {
+        let mut nodes = Vec::with_capacity((2 * items.len() / LEAF_SIZE).max(1));
+        if !items.is_empty() {
+            nodes.push(Node { min: Vec3::ZERO, max: Vec3::ZERO, first: 0, count: items.len() });
+            split(&mut nodes, &mut items, 0);
+        }
+        Self { nodes, items }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Bounds of every item, or `None` when empty
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.nodes.first().map(|root| (root.min, root.max))
+    }
+
+    /// Update item bounds from `bounds(item)`, then every node's bounds bottom up. The tree
+    /// keeps its shape, so queries stay correct but slow down as objects wander far from
+    /// where they were built; rebuild after large rearrangements.
+    pub fn refit(&mut self, mut bounds: impl FnMut(&BvhItem) -> (Vec3, Vec3)) {
+        for item in &mut self.items {
+            (item.min, item.max) = bounds(item);
+        }
+        // Children always come after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let (min, max) = if node.count > 0 {
+                enclose(&self.items[node.first..node.first + node.count])
+            } else {
+                let (left, right) = (self.nodes[node.first], self.nodes[node.first + 1]);
+                (left.min.min(right.min), left.max.max(right.max))
+            };
+            self.nodes[index].min = min;
+            self.nodes[index].max = max;
+        }
+    }
+
+    /// Visit every item whose bounds pass `overlaps`, skipping whole subtrees whose bounds fail
+    /// it. `overlaps` must be conservative: true for any box containing a box it passes.
+    pub fn query(&self, mut overlaps: impl FnMut(Vec3, Vec3) -> bool, mut visit: impl FnMut(&BvhItem)) {
+        let mut stack = Vec::with_capacity(32);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !overlaps(node.min, node.max) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.extend([node.first + 1, node.first]);
+                continue;
+            }
+            for item in &self.items[node.first..node.first + node.count] {
+                if overlaps(item.min, item.max) {
+                    visit(item);
+                }
+            }
+        }
+    }
+
+    /// The nearest hit along `ray`: `hit` is called for items whose bounds the ray passes
+    /// through, nearest nodes first, and returns a distance (and anything else) for a hit.
+    /// Subtrees further than the best hit so far are skipped.
+    pub fn raycast<T>(&self, ray: &Ray, mut hit: impl FnMut(&BvhItem) -> Option<(f32, T)>) -> Option<(f32, T)> {
+        let inverse = ray.direction.recip();
+        let mut best: Option<(f32, T)> = None;
+        let mut stack = Vec::with_capacity(32);
+        if let Some(root) = self.nodes.first() {
+            if let Some(distance) = slab(ray.origin, inverse, root.min, root.max) {
+                stack.push((0, distance));
+            }
+        }
+        while let Some((index, distance)) = stack.pop() {
+            if best.as_ref().is_some_and(|(nearest, _)| *nearest < distance) {
+                continue;
+            }
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for item in &self.items[node.first..node.first + node.count] {
+                    if slab(ray.origin, inverse, item.min, item.max).is_none() {
+                        continue;
+                    }
+                    if let Some((distance, value)) = hit(item) {
+                        if best.as_ref().is_none_or(|(nearest, _)| distance < *nearest) {
+                            best = Some((distance, value));
+                        }
+                    }
+                }
+                continue;
+            }
+            // Push the further child first so the nearer one is visited next
+            let mut children: Vec<_> = [node.first, node.first + 1].into_iter()
+                .filter_map(|child| {
+                    let child_node = &self.nodes[child];
+                    slab(ray.origin, inverse, child_node.min, child_node.max).map(|distance| (child, distance))
+                })
+                .collect();
+            children.sort_by(|a, b| b.1.total_cmp(&a.1));
+            stack.extend(children);
+        }
+        best
+    }
+
+    /// The item nearest `point` that `accept` takes, and its distance: from `point` to the
+    /// item's bounds, 0 inside them. Subtrees further than the best so far are skipped.
+    pub fn nearest(&self, point: Vec3, mut accept: impl FnMut(&BvhItem) -> bool) -> Option<(&BvhItem, f32)> {
+        let mut best: Option<(&BvhItem, f32)> = None;
+        let mut stack = Vec::with_capacity(32);
+        if let Some(root) = self.nodes.first() {
+            stack.push((0, distance_to_aabb(point, root.min, root.max)));
+        }
+        while let Some((index, distance)) = stack.pop() {
+            if best.is_some_and(|(_, nearest)| nearest <= distance) {
+                continue;
+            }
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for item in &self.items[node.first..node.first + node.count] {
+                    let distance = distance_to_aabb(point, item.min, item.max);
+                    if best.is_none_or(|(_, nearest)| distance < nearest) && accept(item) {
+                        best = Some((item, distance));
+                    }
+                }
+                continue;
+            }
+            let mut children = [node.first, node.first + 1]
+                .map(|child| (child, distance_to_aabb(point, self.nodes[child].min, self.nodes[child].max)));
+            children.sort_by(|a, b| b.1.total_cmp(&a.1));
+            stack.extend(children);
+        }
+        best
+    }
+}
+
+/// Distance from `point` to the nearest point of the box `min..max`, 0 inside it
+pub fn distance_to_aabb(point: Vec3, min: Vec3, max: Vec3) -> f32 {
+    point.distance(point.clamp(min, max))
+}
+
+/// Split node `index`, whose items are `items[first..first + count]`, until leaves are small
+fn split(nodes: &mut Vec<Node>, items: &mut [BvhItem], index: usize) {
+    let Node { first, count, .. } = nodes[index];
+    let range = &mut items[first..first + count];
+    let (min, max) = enclose(range);
+    nodes[index].min = min;
+    nodes[index].max = max;
+    if count <= LEAF_SIZE {
+        return;
+    }
+
+    // Median split along the axis the centers spread out on most
+    let (center_min, center_max) = range.iter()
+        .map(|item| (item.min + item.max) * 0.5)
+        .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(lo, hi), center| (lo.min(center), hi.max(center)));
+    let extent = center_max - center_min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+    let half = count / 2;
+    range.select_nth_unstable_by(half, |a, b| (a.min[axis] + a.max[axis]).total_cmp(&(b.min[axis] + b.max[axis])));
+
+    let left = nodes.len();
+    nodes.push(Node { min, max, first, count: half });
+    nodes.push(Node { min, max, first: first + half, count: count - half });
+    nodes[index].first = left;
+    nodes[index].count = 0;
+    split(nodes, items, left);
+    split(nodes, items, left + 1);
+}
+
+fn enclose(items: &[BvhItem]) -> (Vec3, Vec3) {
+    items.iter().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(lo, hi), item| (lo.min(item.min), hi.max(item.max)))
+}
+
+/// Distance along a ray to where it enters the box, 0 if it starts inside; `None` on a miss
+fn slab(origin: Vec3, inverse: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let t0 = (min - origin) * inverse;
+    let t1 = (max - origin) * inverse;
+    let entry = t0.min(t1).max_element().max(0.0);
+    let exit = t0.max(t1).min_element();
+    (entry <= exit).then_some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit box at each of `centers`
+    fn items(centers: &[Vec3]) -> Vec<BvhItem> {
+        centers.iter().enumerate()
+            .map(|(index, center)| BvhItem {
+                id: ObjectId(index as u32),
+                index,
+                min: *center - Vec3::splat(0.5),
+                max: *center + Vec3::splat(0.5),
+            })
+            .collect()
+    }
+
+    fn grid() -> Vec<Vec3> {
+        (0..10).flat_map(|x| (0..10).map(move |z| Vec3::new(x as f32 * 3.0, 0.0, z as f32 * 3.0))).collect()
+    }
+
+    fn overlapping(bvh: &Bvh, min: Vec3, max: Vec3) -> Vec<ObjectId> {
+        let mut found = Vec::new();
+        bvh.query(|lo, hi| lo.cmple(max).all() && hi.cmpge(min).all(), |item| found.push(item.id));
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_build_and_query() {
+        let bvh = Bvh::build(items(&grid()));
+        assert_eq!(bvh.len(), 100);
+        assert_eq!(bvh.bounds(), Some((Vec3::new(-0.5, -0.5, -0.5), Vec3::new(27.5, 0.5, 27.5))));
+        assert!(Bvh::build(Vec::new()).bounds().is_none());
+
+        // Same answer as testing every box
+        let (min, max) = (Vec3::new(2.0, -1.0, 2.0), Vec3::new(7.0, 1.0, 7.0));
+        let expected: Vec<_> = items(&grid()).into_iter()
+            .filter(|item| item.min.cmple(max).all() && item.max.cmpge(min).all())
+            .map(|item| item.id)
+            .collect();
+        assert_eq!(expected.len(), 4);
+        assert_eq!(overlapping(&bvh, min, max), expected);
+    }
+
+    #[test]
+    fn test_raycast_nearest() {
+        let bvh = Bvh::build(items(&grid()));
+        let mut tested = 0;
+        // Down the row at z = 6, from outside
+        let ray = Ray::new(Vec3::new(-10.0, 0.0, 6.0), Vec3::X);
+        let hit = bvh.raycast(&ray, |item| {
+            tested += 1;
+            ray.intersect_aabb(item.min, item.max).map(|(distance, _)| (distance, item.id))
+        });
+        assert_eq!(hit, Some((9.5, ObjectId(2))));
+        // Only the boxes the ray reaches before the first hit, give or take a leaf
+        assert!(tested <= LEAF_SIZE * 2, "tested {}", tested);
+
+        // Starting inside the hierarchy's bounds, between objects
+        let ray = Ray::new(Vec3::new(10.5, 0.0, 6.0), Vec3::NEG_X);
+        let hit = bvh.raycast(&ray, |item| ray.intersect_aabb(item.min, item.max).map(|(distance, _)| (distance, item.id)));
+        assert_eq!(hit, Some((1.0, ObjectId(32))));
+        let up = Ray::new(Vec3::new(1.5, 0.0, 0.0), Vec3::Y);
+        assert_eq!(bvh.raycast(&up, |item| up.intersect_aabb(item.min, item.max).map(|(distance, _)| (distance, ()))), None);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let bvh = Bvh::build(items(&grid()));
+        let (item, distance) = bvh.nearest(Vec3::new(6.2, 3.0, 8.9), |_| true).unwrap();
+        assert_eq!((item.id, distance), (ObjectId(23), 2.5));
+        // Inside a box
+        assert_eq!(bvh.nearest(Vec3::new(27.2, 0.0, 27.0), |_| true).unwrap().1, 0.0);
+        // Filtered out, the next nearest
+        let (item, _) = bvh.nearest(Vec3::new(6.2, 0.0, 8.9), |item| item.id != ObjectId(23)).unwrap();
+        assert_eq!(item.id, ObjectId(33));
+        assert!(bvh.nearest(Vec3::ZERO, |_| false).is_none());
+        assert!(Bvh::build(Vec::new()).nearest(Vec3::ZERO, |_| true).is_none());
+    }
+
+    #[test]
+    fn test_refit() {
+        let mut bvh = Bvh::build(items(&grid()));
+        // Everything moves up; object 0 moves far away
+        bvh.refit(|item| {
+            let offset = if item.id == ObjectId(0) { Vec3::new(100.0, 0.0, 0.0) } else { Vec3::Y * 10.0 };
+            (item.min + offset, item.max + offset)
+        });
+        assert_eq!(bvh.bounds(), Some((Vec3::splat(-0.5), Vec3::new(100.5, 10.5, 27.5))));
+        assert!(overlapping(&bvh, Vec3::splat(-0.1), Vec3::splat(0.1)).is_empty());
+        assert_eq!(overlapping(&bvh, Vec3::new(99.9, -0.1, -0.1), Vec3::new(100.1, 0.1, 0.1)), vec![ObjectId(0)]);
+        assert_eq!(overlapping(&bvh, Vec3::new(2.9, 9.9, -0.1), Vec3::new(3.1, 10.1, 0.1)), vec![ObjectId(10)]);
+    }
+}
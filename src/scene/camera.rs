@@ -1,5 +1,58 @@
-use glam::{Mat4, Vec3};
-use winit::keyboard::KeyCode;
+use glam::{Mat4, Vec3, Vec4};
+use crate::input::{GamepadAxis, InputState, Key};
+use super::ray::Ray;
+use super::viewport::Viewport;
+
+// Extra distance when framing bounds so the object doesn't touch the screen edges
+const FRAME_MARGIN: f32 = 1.2;
+/// Smallest orthographic half-height `zoom` goes down to
+pub const MIN_HALF_HEIGHT: f32 = 0.01;
+// Half-height change per scrolled wheel line in orthographic mode
+const SCROLL_ZOOM: f32 = 0.9;
+
+/// How the camera projects the scene onto the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Vanishing-point perspective over `Camera::fov`
+    Perspective,
+    /// Parallel projection showing `half_height` world units above and below the view center,
+    /// for CAD-like viewers and 2.5D tools. Depth maps `near..far` linearly onto wgpu's 0..1.
+    Orthographic { half_height: f32 },
+}
+
+/// Preset view angles for tools; `Camera::set_view_angle` turns the camera to one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewAngle {
+    /// True isometric: all three axes equally foreshortened
+    Isometric,
+    /// Pixel-art isometric, 2:1 lines on screen
+    Isometric2To1,
+    /// Ground axes equally foreshortened, looking down 30 degrees
+    Dimetric,
+    /// Straight down at the XZ plane, +X to the right
+    Top,
+    /// Along -Z
+    Front,
+    /// Along -X
+    Right,
+}
+
+impl ViewAngle {
+    /// `(yaw, pitch)` in degrees, in `Camera`'s convention
+    pub fn yaw_pitch(self) -> (f32, f32) {
+        match self {
+            // Down the cube diagonal from (1, 1, 1): atan(1 / sqrt(2))
+            Self::Isometric => (-135.0, -35.264_39),
+            // atan(1 / 2), so a horizontal step of two pixels rises one
+            Self::Isometric2To1 => (-135.0, -26.565_05),
+            Self::Dimetric => (-135.0, -30.0),
+            // Mouse look's pitch limit; straight down has no defined up
+            Self::Top => (-90.0, -89.0),
+            Self::Front => (-90.0, 0.0),
+            Self::Right => (180.0, 0.0),
+        }
+    }
+}
 
 pub struct Camera {
     pub position: Vec3,
@@ -9,6 +62,7 @@ pub struct Camera {
     pub aspect: f32,
     pub near: f32,
     pub far: f32,
+    pub projection: Projection,
     // Movement state
     pub moving_forward: bool,
     pub moving_backward: bool,
@@ -28,6 +82,7 @@ impl Camera {
             aspect,
             near: 0.1,
             far: 100.0,
+            projection: Projection::Perspective,
             moving_forward: false,
             moving_backward: false,
             moving_left: false,
@@ -38,22 +93,112 @@ impl Camera {
     }
 
     pub fn build_view_projection_matrix(&self) -> Mat4 {
-        let projection = Mat4::perspective_rh_gl(
-            self.fov.to_radians(),
-            self.aspect,
-            self.near,
-            self.far,
-        );
-        
+        self.build_projection_matrix() * self.build_view_matrix()
+    }
+
+    pub fn build_view_matrix(&self) -> Mat4 {
         let view_dir = self.get_view_direction();
         let target = self.position + view_dir;
-        let view = Mat4::look_at_rh(
+        Mat4::look_at_rh(
             self.position,
             target,
             Vec3::Y,
-        );
+        )
+    }
+
+    pub fn build_projection_matrix(&self) -> Mat4 {
+        match self.projection {
+            Projection::Perspective => Mat4::perspective_rh_gl(
+                self.fov.to_radians(),
+                self.aspect,
+                self.near,
+                self.far,
+            ),
+            // Not the GL depth range: wgpu clips below 0, which with a linear depth would cut
+            // away the near half of the view
+            Projection::Orthographic { half_height } => {
+                let half_width = half_height * self.aspect;
+                Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, self.near, self.far)
+            }
+        }
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self.projection, Projection::Orthographic { .. })
+    }
+
+    /// Switch to a parallel projection showing `half_height` units either side of the view center
+    pub fn set_orthographic(&mut self, half_height: f32) {
+        self.projection = Projection::Orthographic { half_height: half_height.max(MIN_HALF_HEIGHT) };
+    }
+
+    pub fn set_perspective(&mut self) {
+        self.projection = Projection::Perspective;
+    }
+
+    /// Switch between projections, keeping things `focus_distance` ahead the same size on screen
+    pub fn toggle_orthographic(&mut self, focus_distance: f32) {
+        match self.projection {
+            Projection::Perspective => self.set_orthographic(focus_distance * (self.fov.to_radians() * 0.5).tan()),
+            Projection::Orthographic { .. } => self.set_perspective(),
+        }
+    }
+
+    /// Scale the orthographic half-height by `factor`, below 1 zooming in; perspective cameras
+    /// zoom by moving instead, so they're left alone
+    pub fn zoom(&mut self, factor: f32) {
+        if let Projection::Orthographic { half_height } = &mut self.projection {
+            *half_height = (*half_height * factor).max(MIN_HALF_HEIGHT);
+        }
+    }
+
+    /// Turn to a preset angle, keeping the position; `frame_bounds` afterwards to fit a model
+    pub fn set_view_angle(&mut self, angle: ViewAngle) {
+        (self.yaw, self.pitch) = angle.yaw_pitch();
+    }
+
+    /// The world-space ray through surface pixel `(x, y)` (origin top left), starting on the
+    /// near plane. `viewport` is where the scene is drawn, e.g. `Renderer::viewport`; points
+    /// over letterbox bars extend the view beyond its edges.
+    pub fn screen_to_ray(&self, x: f32, y: f32, viewport: &Viewport) -> Ray {
+        // Pixel -> NDC, with +Y up
+        let ndc_x = (x - viewport.x as f32) / viewport.width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y - viewport.y as f32) / viewport.height as f32 * 2.0;
+
+        // The GL-style perspective puts the near plane at NDC z = -1 and the far plane at 1;
+        // orthographic uses 0..1. Orthographic rays are parallel, each from its own pixel.
+        let near_z = if self.is_orthographic() { 0.0 } else { -1.0 };
+        let inverse = self.build_view_projection_matrix().inverse();
+        let unproject = |z: f32| {
+            let point = inverse * Vec4::new(ndc_x, ndc_y, z, 1.0);
+            point.truncate() / point.w
+        };
+        let near = unproject(near_z);
+        Ray::new(near, unproject(1.0) - near)
+    }
+
+    /// Aim the camera at the center of the box `min..max` from its current viewing angle,
+    /// backed off until the whole box fits in view with a small margin. Orthographic cameras
+    /// zoom to fit instead, backing off only to keep the box past the near plane.
+    pub fn frame_bounds(&mut self, min: Vec3, max: Vec3) {
+        let center = (min + max) * 0.5;
+        let radius = ((max - min).length() * 0.5).max(0.01);
+
+        if let Projection::Orthographic { half_height } = &mut self.projection {
+            *half_height = (radius / self.aspect.min(1.0) * FRAME_MARGIN).max(MIN_HALF_HEIGHT);
+            let distance = self.near + radius * FRAME_MARGIN;
+            self.position = center - self.get_view_direction() * distance;
+            self.far = self.far.max(distance + radius * 2.0);
+            return;
+        }
 
-        projection * view
+        // Fit the bounding sphere inside the narrower of the two view angles
+        let half_fov_y = self.fov.to_radians() * 0.5;
+        let half_fov_x = (half_fov_y.tan() * self.aspect).atan();
+        let distance = radius / half_fov_y.min(half_fov_x).sin() * FRAME_MARGIN;
+
+        self.position = center - self.get_view_direction() * distance;
+        self.far = self.far.max(distance + radius * 2.0);
     }
 
     pub fn get_forward(&self) -> Vec3 {
@@ -66,7 +211,19 @@ impl Camera {
         forward.cross(Vec3::Y).normalize()
     }
 
-    fn get_view_direction(&self) -> Vec3 {
+    /// Turn the camera to look along `direction`, keeping the pitch limits of mouse look
+    pub fn set_view_direction(&mut self, direction: Vec3) {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return;
+        }
+        if direction.x != 0.0 || direction.z != 0.0 {
+            self.yaw = direction.z.atan2(direction.x).to_degrees();
+        }
+        self.pitch = direction.y.clamp(-1.0, 1.0).asin().to_degrees().clamp(-89.0, 89.0);
+    }
+
+    pub fn get_view_direction(&self) -> Vec3 {
         let (yaw_sin, yaw_cos) = self.yaw.to_radians().sin_cos();
         let (pitch_sin, pitch_cos) = self.pitch.to_radians().sin_cos();
         Vec3::new(
@@ -111,17 +268,39 @@ impl Camera {
         }
     }
 
-    pub fn process_keyboard(&mut self, key: KeyCode, pressed: bool) {
+    pub fn process_keyboard(&mut self, key: Key, pressed: bool) {
         match key {
-            KeyCode::KeyW => self.moving_forward = pressed,
-            KeyCode::KeyS => self.moving_backward = pressed,
-            KeyCode::KeyA => self.moving_left = pressed,
-            KeyCode::KeyD => self.moving_right = pressed,
-            KeyCode::Space => self.moving_up = pressed,
-            KeyCode::ShiftLeft => self.moving_down = pressed,
+            Key::KeyW => self.moving_forward = pressed,
+            Key::KeyS => self.moving_backward = pressed,
+            Key::KeyA => self.moving_left = pressed,
+            Key::KeyD => self.moving_right = pressed,
+            Key::Space => self.moving_up = pressed,
+            Key::ShiftLeft => self.moving_down = pressed,
             _ => (),
         }
     }
+
+    /// Fly from a frame's input: WASD/Space/Shift or the left stick move, the mouse looks
+    pub fn process_input(&mut self, input: &InputState) {
+        const STICK_DEADZONE: f32 = 0.5;
+
+        let stick = |axis| input.gamepad_axis(axis);
+        self.moving_forward = input.is_key_down(Key::KeyW) || stick(GamepadAxis::LeftStickY) > STICK_DEADZONE;
+        self.moving_backward = input.is_key_down(Key::KeyS) || stick(GamepadAxis::LeftStickY) < -STICK_DEADZONE;
+        self.moving_right = input.is_key_down(Key::KeyD) || stick(GamepadAxis::LeftStickX) > STICK_DEADZONE;
+        self.moving_left = input.is_key_down(Key::KeyA) || stick(GamepadAxis::LeftStickX) < -STICK_DEADZONE;
+        self.moving_up = input.is_key_down(Key::Space);
+        self.moving_down = input.is_key_down(Key::ShiftLeft);
+
+        let delta = input.mouse_delta();
+        if delta != glam::Vec2::ZERO {
+            self.process_mouse(delta.x, delta.y);
+        }
+        // Scrolling up zooms in
+        if input.scroll() != 0.0 {
+            self.zoom(SCROLL_ZOOM.powf(input.scroll()));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -169,6 +348,22 @@ mod tests {
         assert_relative_eq!(dir.z, 0.0, epsilon = 0.001);
     }
 
+    #[test]
+    fn test_set_view_direction() {
+        let mut camera = Camera::new(Vec3::ZERO, 1.0);
+        camera.set_view_direction(Vec3::new(1.0, 1.0, 0.0));
+        assert_relative_eq!(camera.yaw, 0.0, epsilon = 0.001);
+        assert_relative_eq!(camera.pitch, 45.0, epsilon = 0.001);
+        camera.set_view_direction(Vec3::new(0.0, 0.0, 2.0));
+        assert_relative_eq!(camera.yaw, 90.0, epsilon = 0.001);
+        assert_relative_eq!(camera.get_view_direction().z, 1.0, epsilon = 0.001);
+
+        // Straight up keeps the heading and stops short of the pole
+        camera.set_view_direction(Vec3::Y);
+        assert_relative_eq!(camera.yaw, 90.0, epsilon = 0.001);
+        assert_relative_eq!(camera.pitch, 89.0, epsilon = 0.001);
+    }
+
     #[test]
     fn test_movement_directions() {
         let mut camera = Camera::new(Vec3::ZERO, 1.0);
@@ -199,12 +394,12 @@ mod tests {
         
         // Test each key individually
         let test_cases = [
-            (KeyCode::KeyW, "moving_forward"),
-            (KeyCode::KeyS, "moving_backward"),
-            (KeyCode::KeyA, "moving_left"),
-            (KeyCode::KeyD, "moving_right"),
-            (KeyCode::Space, "moving_up"),
-            (KeyCode::ShiftLeft, "moving_down"),
+            (Key::KeyW, "moving_forward"),
+            (Key::KeyS, "moving_backward"),
+            (Key::KeyA, "moving_left"),
+            (Key::KeyD, "moving_right"),
+            (Key::Space, "moving_up"),
+            (Key::ShiftLeft, "moving_down"),
         ];
 
         for (key, flag_name) in test_cases {
@@ -247,6 +442,51 @@ mod tests {
         assert_relative_eq!(camera.pitch, 50.0, epsilon = 0.001);  // -50 units * 1.0 sensitivity = 50 degrees
     }
 
+    #[test]
+    fn test_process_input() {
+        use crate::input::InputEvent;
+
+        let mut camera = Camera::new(Vec3::ZERO, 1.0);
+        let mut input = InputState::new();
+        input.begin_frame([
+            InputEvent::Key { key: Key::KeyW, pressed: true },
+            InputEvent::GamepadAxis { axis: GamepadAxis::LeftStickX, value: -0.9 },
+            InputEvent::MouseMotion { dx: 10.0, dy: 0.0 },
+        ]);
+        camera.process_input(&input);
+        assert!(camera.moving_forward && camera.moving_left && !camera.moving_right);
+        assert_relative_eq!(camera.yaw, -80.0, epsilon = 0.001);
+
+        // Held keys keep moving, but motion only counts in the frame it happened
+        input.begin_frame([]);
+        camera.process_input(&input);
+        assert!(camera.moving_forward);
+        assert_relative_eq!(camera.yaw, -80.0, epsilon = 0.001);
+    }
+
+    #[test]
+    fn test_screen_to_ray() {
+        let camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 2.0);
+        let viewport = Viewport::full(800, 400);
+
+        // The center looks straight down the view direction
+        let ray = camera.screen_to_ray(400.0, 200.0, &viewport);
+        assert_relative_eq!(ray.direction.x, 0.0, epsilon = 0.001);
+        assert_relative_eq!(ray.direction.z, -1.0, epsilon = 0.001);
+        assert_relative_eq!(ray.origin.z, 3.0 - camera.near, epsilon = 0.001);
+
+        // The top edge is half the vertical field of view up
+        let ray = camera.screen_to_ray(400.0, 0.0, &viewport);
+        assert_relative_eq!(ray.direction.y.asin().to_degrees(), camera.fov * 0.5, epsilon = 0.01);
+
+        // The right edge of a pillarboxed viewport, not of the surface
+        let pillarbox = Viewport { x: 100, ..viewport };
+        let ray = camera.screen_to_ray(900.0, 200.0, &pillarbox);
+        let edge = camera.screen_to_ray(800.0, 200.0, &viewport);
+        assert_relative_eq!(ray.direction.x, edge.direction.x, epsilon = 0.001);
+        assert!(ray.direction.x > 0.0);
+    }
+
     #[test]
     fn test_movement_update() {
         let mut camera = Camera::new(Vec3::ZERO, 1.0);
@@ -270,6 +510,121 @@ mod tests {
         assert_relative_eq!(camera.position.y, 5.0, epsilon = 0.001);
     }
 
+    #[test]
+    fn test_frame_bounds() {
+        let mut camera = Camera::new(Vec3::new(0.0, 8.0, 16.0), 16.0 / 9.0);
+        camera.pitch = -30.0;
+        let (min, max) = (Vec3::new(10.0, 0.0, 10.0), Vec3::new(14.0, 4.0, 14.0));
+        camera.frame_bounds(min, max);
+
+        // Every corner of the box must project inside the viewport
+        let view_proj = camera.build_view_projection_matrix();
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            let clip = view_proj * corner.extend(1.0);
+            assert!(clip.w > 0.0);
+            let ndc = clip.truncate() / clip.w;
+            assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "corner {:?} off screen", corner);
+        }
+        assert_eq!(camera.pitch, -30.0);
+    }
+
+    #[test]
+    fn test_orthographic_projection() {
+        let mut camera = Camera::new(Vec3::new(0.0, 0.0, 10.0), 2.0);
+        camera.set_orthographic(5.0);
+        assert!(camera.is_orthographic());
+
+        // Size on screen doesn't change with distance, and depth spans 0..1
+        let view_proj = camera.build_view_projection_matrix();
+        for z in [5.0, -50.0] {
+            let ndc = view_proj.project_point3(Vec3::new(10.0, 5.0, z));
+            assert_relative_eq!(ndc.x, 1.0, epsilon = 0.001);
+            assert_relative_eq!(ndc.y, 1.0, epsilon = 0.001);
+        }
+        assert_relative_eq!(view_proj.project_point3(Vec3::new(0.0, 0.0, 10.0 - camera.near)).z, 0.0, epsilon = 0.001);
+        assert_relative_eq!(view_proj.project_point3(Vec3::new(0.0, 0.0, 10.0 - camera.far)).z, 1.0, epsilon = 0.001);
+
+        // Zooming scales the half-height, down to a floor
+        camera.zoom(0.5);
+        assert_eq!(camera.projection, Projection::Orthographic { half_height: 2.5 });
+        camera.zoom(0.0);
+        assert_eq!(camera.projection, Projection::Orthographic { half_height: MIN_HALF_HEIGHT });
+        camera.set_perspective();
+        camera.zoom(0.5);
+        assert_eq!(camera.projection, Projection::Perspective);
+    }
+
+    #[test]
+    fn test_orthographic_movement_directions() {
+        // Forward and right follow the heading alone, whatever the projection
+        let mut camera = Camera::new(Vec3::ZERO, 1.0);
+        camera.set_orthographic(10.0);
+        assert_relative_eq!(camera.get_forward().z, -1.0, epsilon = 0.001);
+        assert_relative_eq!(camera.get_right().x, 1.0, epsilon = 0.001);
+
+        camera.set_view_angle(ViewAngle::Isometric);
+        let forward = camera.get_forward();
+        let right = camera.get_right();
+        assert_relative_eq!(forward.y, 0.0, epsilon = 0.001);
+        assert!(forward.abs_diff_eq(Vec3::new(-1.0, 0.0, -1.0).normalize(), 0.001));
+        assert!(right.abs_diff_eq(Vec3::new(1.0, 0.0, -1.0).normalize(), 0.001));
+        assert_relative_eq!(forward.dot(right), 0.0, epsilon = 0.001);
+
+        // Looking down the cube diagonal
+        assert!(camera.get_view_direction().abs_diff_eq(-Vec3::ONE.normalize(), 0.001));
+        camera.set_view_angle(ViewAngle::Top);
+        assert_relative_eq!(camera.get_right().x, 1.0, epsilon = 0.001);
+        assert!(camera.get_view_direction().y < -0.99);
+
+        // Flying moves along the ground whatever the pitch
+        camera.set_view_angle(ViewAngle::Dimetric);
+        camera.moving_forward = true;
+        camera.update(1.0);
+        assert_relative_eq!(camera.position.y, 0.0, epsilon = 0.001);
+        assert_relative_eq!(camera.position.length(), 5.0, epsilon = 0.001);
+    }
+
+    #[test]
+    fn test_orthographic_screen_to_ray() {
+        let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 2.0);
+        camera.set_orthographic(4.0);
+        let viewport = Viewport::full(800, 400);
+
+        // Parallel rays, each starting on the near plane over its own pixel
+        let center = camera.screen_to_ray(400.0, 200.0, &viewport);
+        let corner = camera.screen_to_ray(800.0, 0.0, &viewport);
+        assert!(center.direction.abs_diff_eq(Vec3::NEG_Z, 0.001));
+        assert!(corner.direction.abs_diff_eq(Vec3::NEG_Z, 0.001));
+        assert!(center.origin.abs_diff_eq(Vec3::new(1.0, 2.0, 3.0 - camera.near), 0.001));
+        assert!(corner.origin.abs_diff_eq(Vec3::new(9.0, 6.0, 3.0 - camera.near), 0.001));
+    }
+
+    #[test]
+    fn test_orthographic_frame_bounds() {
+        let mut camera = Camera::new(Vec3::new(0.0, 8.0, 16.0), 16.0 / 9.0);
+        camera.set_orthographic(1.0);
+        camera.set_view_angle(ViewAngle::Isometric);
+        let (min, max) = (Vec3::new(10.0, 0.0, 10.0), Vec3::new(14.0, 4.0, 14.0));
+        camera.frame_bounds(min, max);
+
+        let view_proj = camera.build_view_projection_matrix();
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            let ndc = view_proj.project_point3(corner);
+            assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "corner {:?} off screen", corner);
+            assert!((0.0..=1.0).contains(&ndc.z), "corner {:?} clipped", corner);
+        }
+    }
+
     #[test]
     fn test_view_matrix_changes() {
         let mut camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0);
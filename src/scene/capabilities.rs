@@ -0,0 +1,447 @@
+use std::fmt;
+use std::future::Future;
+use anyhow::anyhow;
+use wgpu::{DeviceType, TextureFormat, TextureFormatFeatureFlags, TextureFormatFeatures, TextureUsages};
+use crate::settings::{RendererSettings, ShadowQuality};
+use super::post::SsrQuality;
+
+/// Formats the renderer uses or could use, reported on in `Capabilities::formats`
+pub const REPORTED_FORMATS: [TextureFormat; 11] = [
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Bgra8UnormSrgb,
+    TextureFormat::Rgba16Float,
+    TextureFormat::Rgba32Float,
+    TextureFormat::R32Float,
+    TextureFormat::R32Uint,
+    TextureFormat::Depth32Float,
+    TextureFormat::Depth24PlusStencil8,
+    TextureFormat::Bc7RgbaUnormSrgb,
+    TextureFormat::Etc2Rgba8UnormSrgb,
+    TextureFormat::Astc { block: wgpu::AstcBlock::B4x4, channel: wgpu::AstcChannel::UnormSrgb },
+];
+
+/// Optional device features, most wanted first, with what goes without them. A failing device
+/// request drops them from the end of this list one at a time. The subsystems using them check
+/// the device's features, as `Capabilities::timestamps` and `Capabilities::multiview` do.
+pub const OPTIONAL_FEATURES: [(wgpu::Features, &str); 2] = [
+    (wgpu::Features::TIMESTAMP_QUERY, "GPU pass timings in the profiler"),
+    (wgpu::Features::MULTIVIEW, "single-pass stereo; headset eyes are drawn in a pass each"),
+];
+
+/// A limit the device was created below what was asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitLoss {
+    pub name: &'static str,
+    pub wanted: u64,
+    pub granted: u64,
+}
+
+/// What `request_device` gave up to get a device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Downgrade {
+    /// Wanted features the device doesn't have, because the adapter lacks them or requesting
+    /// them failed
+    pub features: wgpu::Features,
+    pub limits: Vec<LimitLoss>,
+}
+
+impl Downgrade {
+    pub fn new(wanted_features: wgpu::Features, wanted_limits: &wgpu::Limits, device_features: wgpu::Features, device_limits: &wgpu::Limits) -> Self {
+        let mut limits = Vec::new();
+        wanted_limits.check_limits_with_fail_fn(device_limits, false, |name, wanted, granted| {
+            limits.push(LimitLoss { name, wanted, granted });
+        });
+        Self { features: wanted_features.difference(device_features), limits }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty() && self.limits.is_empty()
+    }
+}
+
+impl fmt::Display for Downgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("nothing lost");
+        }
+        let mut parts: Vec<String> = OPTIONAL_FEATURES.iter()
+            .filter(|(feature, _)| self.features.intersects(*feature))
+            .map(|(_, name)| format!("no {}", name))
+            .collect();
+        let other = OPTIONAL_FEATURES.iter().fold(self.features, |rest, (feature, _)| rest.difference(*feature));
+        if !other.is_empty() {
+            parts.push(format!("no {:?}", other));
+        }
+        parts.extend(self.limits.iter().map(|loss| format!("{} {} instead of {}", loss.name, loss.granted, loss.wanted)));
+        f.write_str(&parts.join("; "))
+    }
+}
+
+/// Request a device with the wanted features the adapter offers and the wanted limits. When
+/// that fails, the `OPTIONAL_FEATURES` are dropped one at a time from the least wanted, then
+/// the limits fall back to the downlevel and WebGL2 defaults (keeping the adapter's texture
+/// size and alignments) with the features restored. Each step is logged, and what the device
+/// ended up without is returned for the capability report.
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+    label: &str,
+    wanted_features: wgpu::Features,
+    wanted_limits: wgpu::Limits,
+) -> anyhow::Result<(wgpu::Device, wgpu::Queue, Downgrade)> {
+    let offered = adapter.features();
+    if !offered.contains(wanted_features) {
+        log::info!("The adapter lacks {:?}", wanted_features.difference(offered));
+    }
+    let adapter_limits = adapter.limits();
+    wanted_limits.check_limits_with_fail_fn(&adapter_limits, false, |name, wanted, allowed| {
+        log::warn!("Wanted limit {} of {} is beyond the adapter's {}", name, wanted, allowed);
+    });
+    let mut limit_tiers = vec![wanted_limits.clone()];
+    for fallback in [wgpu::Limits::downlevel_defaults(), wgpu::Limits::downlevel_webgl2_defaults()] {
+        let fallback = fallback.using_resolution(adapter_limits.clone()).using_alignment(adapter_limits.clone());
+        if !limit_tiers.contains(&fallback) {
+            limit_tiers.push(fallback);
+        }
+    }
+
+    let (device, queue) = request_with_fallbacks(wanted_features.intersection(offered), limit_tiers, |features, limits| {
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some(label),
+            required_features: features,
+            required_limits: limits.clone(),
+            memory_hints: Default::default(),
+        };
+        async move { adapter.request_device(&descriptor, None).await }
+    })
+    .await?;
+    let downgrade = Downgrade::new(wanted_features, &wanted_limits, device.features(), &device.limits());
+    if !downgrade.is_empty() {
+        log::warn!("Device created with {}", downgrade);
+    }
+    Ok((device, queue, downgrade))
+}
+
+/// `request_device`'s retries: `request` with `features` and each of `limit_tiers` in turn,
+/// dropping `OPTIONAL_FEATURES` from the least wanted after every failure
+async fn request_with_fallbacks<T, E, F>(
+    features: wgpu::Features,
+    limit_tiers: Vec<wgpu::Limits>,
+    mut request: impl FnMut(wgpu::Features, &wgpu::Limits) -> F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut last_error = None;
+    for (tier, limits) in limit_tiers.into_iter().enumerate() {
+        if tier > 0 {
+            log::warn!("Retrying the device request with lower limits");
+        }
+        let mut features = features;
+        loop {
+            match request(features, &limits).await {
+                Ok(device) => return Ok(device),
+                Err(e) => {
+                    log::warn!("Device request with {:?} failed: {}", features, e);
+                    last_error = Some(e);
+                }
+            }
+            let Some((feature, name)) = OPTIONAL_FEATURES.iter().rev().find(|(feature, _)| features.intersects(*feature)) else {
+                break;
+            };
+            log::warn!("Retrying the device request without {}", name);
+            features.remove(*feature);
+        }
+    }
+    Err(match last_error {
+        Some(e) => anyhow!(e).context("No feature set or limits the adapter accepted"),
+        None => anyhow!("No device request was made"),
+    })
+}
+
+/// What can be done with one texture format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatSupport {
+    pub format: TextureFormat,
+    /// False when the format needs a feature the device doesn't have; the rest are then false too
+    pub supported: bool,
+    /// Can be sampled with linear filtering
+    pub filterable: bool,
+    /// Can be a render target
+    pub renderable: bool,
+    /// Can be bound as a storage texture
+    pub storage: bool,
+    /// Highest MSAA sample count, 1 without multisampling
+    pub max_samples: u32,
+}
+
+impl FormatSupport {
+    fn new(format: TextureFormat, supported: bool, features: TextureFormatFeatures) -> Self {
+        if !supported {
+            return Self { format, supported, filterable: false, renderable: false, storage: false, max_samples: 1 };
+        }
+        Self {
+            format,
+            supported,
+            filterable: features.flags.contains(TextureFormatFeatureFlags::FILTERABLE),
+            renderable: features.allowed_usages.contains(TextureUsages::RENDER_ATTACHMENT),
+            storage: features.allowed_usages.contains(TextureUsages::STORAGE_BINDING),
+            max_samples: features.flags.supported_sample_counts().into_iter().max().unwrap_or(1),
+        }
+    }
+}
+
+/// Broad device class used to pick default settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QualityTier {
+    /// Software rasterizers, downlevel and mobile-class GPUs
+    Low,
+    Medium,
+    /// Discrete GPUs with large textures and 4x MSAA on the HDR target
+    High,
+}
+
+impl QualityTier {
+    /// Classify a device from its limits and format support. Without a device type (a device
+    /// known only by its features) the best it can be is `Medium`.
+    pub fn derive(device_type: Option<DeviceType>, limits: &wgpu::Limits, formats: &[FormatSupport]) -> Self {
+        let hdr = formats.iter().find(|support| support.format == TextureFormat::Rgba16Float);
+        let hdr_usable = hdr.is_some_and(|hdr| hdr.filterable && hdr.renderable);
+        if device_type == Some(DeviceType::Cpu) || !hdr_usable || limits.max_texture_dimension_2d < 8192 {
+            return QualityTier::Low;
+        }
+        let hdr_msaa = hdr.is_some_and(|hdr| hdr.max_samples >= 4);
+        if device_type == Some(DeviceType::DiscreteGpu) && hdr_msaa && limits.max_texture_dimension_2d >= 16384 {
+            return QualityTier::High;
+        }
+        QualityTier::Medium
+    }
+}
+
+impl fmt::Display for QualityTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QualityTier::Low => "low",
+            QualityTier::Medium => "medium",
+            QualityTier::High => "high",
+        })
+    }
+}
+
+/// What the adapter and device support, gathered once at startup so optional features can be
+/// checked instead of assumed
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// Name, backend and device type, when the report was made with the adapter at hand
+    pub adapter: Option<wgpu::AdapterInfo>,
+    /// Features enabled on the device
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    /// Support for each of `REPORTED_FORMATS`
+    pub formats: Vec<FormatSupport>,
+    /// Single-pass stereo for headset views
+    pub multiview: bool,
+    /// Bytes of push constants available, 0 without push constant support
+    pub push_constant_size: u32,
+    /// GPU pass timings for the profiler
+    pub timestamps: bool,
+    pub tier: QualityTier,
+    /// What the device request gave up, set by whoever requested the device
+    pub downgrade: Downgrade,
+}
+
+impl Capabilities {
+    /// Report on a device, with the adapter's format support and device type; format support
+    /// beyond what wgpu guarantees is only used when the device enabled
+    /// `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`
+    pub fn new(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Self {
+        let features = device.features();
+        let adapter_formats = features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        Self::build(Some(adapter.get_info()), features, device.limits(), |format| {
+            if adapter_formats {
+                adapter.get_texture_format_features(format)
+            } else {
+                format.guaranteed_format_features(features)
+            }
+        })
+    }
+
+    /// Report on a device alone: format support is what wgpu guarantees for its features, and
+    /// the tier is at most `Medium`
+    pub fn of_device(device: &wgpu::Device) -> Self {
+        let features = device.features();
+        Self::build(None, features, device.limits(), |format| format.guaranteed_format_features(features))
+    }
+
+    fn build(
+        adapter: Option<wgpu::AdapterInfo>,
+        features: wgpu::Features,
+        limits: wgpu::Limits,
+        format_features: impl Fn(TextureFormat) -> TextureFormatFeatures,
+    ) -> Self {
+        let formats: Vec<FormatSupport> = REPORTED_FORMATS.iter()
+            .map(|&format| FormatSupport::new(format, features.contains(format.required_features()), format_features(format)))
+            .collect();
+        let tier = QualityTier::derive(adapter.as_ref().map(|info| info.device_type), &limits, &formats);
+        Self {
+            multiview: features.contains(wgpu::Features::MULTIVIEW),
+            push_constant_size: if features.contains(wgpu::Features::PUSH_CONSTANTS) { limits.max_push_constant_size } else { 0 },
+            timestamps: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+            adapter,
+            features,
+            limits,
+            formats,
+            tier,
+            downgrade: Downgrade::default(),
+        }
+    }
+
+    /// Support for a format, if it's one of `REPORTED_FORMATS`
+    pub fn format(&self, format: TextureFormat) -> Option<&FormatSupport> {
+        self.formats.iter().find(|support| support.format == format)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(info) = &self.adapter {
+            writeln!(f, "Adapter: {} ({:?}, {:?})", info.name, info.backend, info.device_type)?;
+        }
+        writeln!(f, "Quality tier: {}", self.tier)?;
+        if !self.downgrade.is_empty() {
+            writeln!(f, "Downgraded: {}", self.downgrade)?;
+        }
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        writeln!(f, "Multiview: {}, timestamps: {}, push constants: {} bytes",
+            yes_no(self.multiview), yes_no(self.timestamps), self.push_constant_size)?;
+        writeln!(f, "Max texture size: {}, bind groups: {}, storage buffers per stage: {}",
+            self.limits.max_texture_dimension_2d, self.limits.max_bind_groups, self.limits.max_storage_buffers_per_shader_stage)?;
+        write!(f, "Formats:")?;
+        for support in &self.formats {
+            write!(f, "\n  {:?}: ", support.format)?;
+            if !support.supported {
+                write!(f, "unsupported")?;
+                continue;
+            }
+            let flags = [(support.filterable, "filterable"), (support.renderable, "renderable"), (support.storage, "storage")];
+            let names: Vec<&str> = flags.iter().filter(|(set, _)| *set).map(|&(_, name)| name).collect();
+            write!(f, "{}", if names.is_empty() { "sample only".to_string() } else { names.join(", ") })?;
+            if support.max_samples > 1 {
+                write!(f, ", {}x MSAA", support.max_samples)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RendererSettings {
+    /// Defaults suited to a quality tier, used when there's no settings file yet
+    pub fn for_tier(tier: QualityTier) -> Self {
+        let mut settings = Self::default();
+        match tier {
+            QualityTier::Low => {
+                settings.shadow_quality = ShadowQuality::Low;
+                settings.msaa_samples = 1;
+                settings.post.ssr = SsrQuality::Off;
+                settings.lights.shadow_budget = 1;
+                settings.lights.shadow_updates_per_frame = 1;
+            }
+            QualityTier::Medium => {}
+            QualityTier::High => {
+                settings.shadow_quality = ShadowQuality::High;
+                settings.msaa_samples = 4;
+                settings.post.ssr = SsrQuality::High;
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::FutureExt;
+
+    fn support(format: TextureFormat, max_samples: u32) -> FormatSupport {
+        FormatSupport { format, supported: true, filterable: true, renderable: true, storage: false, max_samples }
+    }
+
+    #[test]
+    fn test_quality_tiers() {
+        let limits = wgpu::Limits { max_texture_dimension_2d: 16384, ..wgpu::Limits::default() };
+        let formats = [support(TextureFormat::Rgba16Float, 4)];
+        assert_eq!(QualityTier::derive(Some(DeviceType::DiscreteGpu), &limits, &formats), QualityTier::High);
+        assert_eq!(QualityTier::derive(Some(DeviceType::IntegratedGpu), &limits, &formats), QualityTier::Medium);
+        assert_eq!(QualityTier::derive(None, &limits, &formats), QualityTier::Medium);
+        assert_eq!(QualityTier::derive(Some(DeviceType::Cpu), &limits, &formats), QualityTier::Low);
+
+        // Without MSAA on the HDR target a discrete GPU is only medium
+        let single = [support(TextureFormat::Rgba16Float, 1)];
+        assert_eq!(QualityTier::derive(Some(DeviceType::DiscreteGpu), &limits, &single), QualityTier::Medium);
+        // Downlevel limits, or no filterable HDR target, are low
+        assert_eq!(QualityTier::derive(Some(DeviceType::DiscreteGpu), &wgpu::Limits::downlevel_defaults(), &formats), QualityTier::Low);
+        let unfiltered = [FormatSupport { filterable: false, ..formats[0] }];
+        assert_eq!(QualityTier::derive(Some(DeviceType::DiscreteGpu), &limits, &unfiltered), QualityTier::Low);
+        assert_eq!(QualityTier::derive(Some(DeviceType::DiscreteGpu), &limits, &[]), QualityTier::Low);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let wanted = wgpu::Features::TIMESTAMP_QUERY | wgpu::Features::MULTIVIEW;
+        let limits = wgpu::Limits::default();
+        let downgrade = Downgrade::new(wanted, &limits, wgpu::Features::TIMESTAMP_QUERY, &limits);
+        assert_eq!(downgrade.features, wgpu::Features::MULTIVIEW);
+        assert!(downgrade.limits.is_empty());
+        assert_eq!(downgrade.to_string(), "no single-pass stereo; headset eyes are drawn in a pass each");
+
+        let lower = wgpu::Limits { max_bind_groups: 2, ..limits.clone() };
+        let downgrade = Downgrade::new(wanted, &limits, wanted, &lower);
+        assert_eq!(downgrade.limits, vec![LimitLoss { name: "max_bind_groups", wanted: 4, granted: 2 }]);
+        assert!(Downgrade::new(wanted, &limits, wanted, &limits).is_empty());
+    }
+
+    #[test]
+    fn test_request_fallbacks() {
+        let all = OPTIONAL_FEATURES.iter().fold(wgpu::Features::empty(), |all, (feature, _)| all | *feature);
+        let tiers = vec![wgpu::Limits::default(), wgpu::Limits::downlevel_defaults()];
+        // Requests that `accept` turns down fail; the features of each attempt are recorded
+        let request = |accept: fn(wgpu::Features, &wgpu::Limits) -> bool| {
+            let mut attempts = Vec::new();
+            let granted = request_with_fallbacks(all, tiers.clone(), |features, limits| {
+                attempts.push((features, limits.max_texture_dimension_2d));
+                std::future::ready(if accept(features, limits) { Ok(features) } else { Err(fmt::Error) })
+            })
+            .block_on();
+            (granted.ok(), attempts)
+        };
+
+        // An adapter rejecting multiview: the least wanted features go first until it's gone
+        let (granted, attempts) = request(|features, _| !features.contains(wgpu::Features::MULTIVIEW));
+        assert_eq!(granted, Some(wgpu::Features::TIMESTAMP_QUERY));
+        assert_eq!(attempts, vec![(all, 8192), (wgpu::Features::TIMESTAMP_QUERY, 8192)]);
+
+        // One rejecting timestamps drops every feature before the limits are lowered
+        let (granted, attempts) = request(|features, _| !features.contains(wgpu::Features::TIMESTAMP_QUERY));
+        assert_eq!(granted, Some(wgpu::Features::empty()));
+        assert_eq!(attempts, vec![(all, 8192), (wgpu::Features::TIMESTAMP_QUERY, 8192), (wgpu::Features::empty(), 8192)]);
+
+        // Limits that are too high come back down with the features restored
+        let (granted, attempts) = request(|_, limits| limits.max_texture_dimension_2d <= 2048);
+        assert_eq!(granted, Some(all));
+        assert_eq!(attempts.len(), OPTIONAL_FEATURES.len() + 2);
+        assert_eq!(attempts.last(), Some(&(all, 2048)));
+
+        let (granted, attempts) = request(|_, _| false);
+        assert_eq!(granted, None);
+        assert_eq!(attempts.len(), 2 * (OPTIONAL_FEATURES.len() + 1));
+    }
+
+    #[test]
+    fn test_tier_settings() {
+        assert_eq!(RendererSettings::for_tier(QualityTier::Medium), RendererSettings::default());
+        let low = RendererSettings::for_tier(QualityTier::Low);
+        assert_eq!((low.shadow_quality, low.post.ssr, low.msaa_samples), (ShadowQuality::Low, SsrQuality::Off, 1));
+        let high = RendererSettings::for_tier(QualityTier::High);
+        assert_eq!(high.sanitized().msaa_samples, 4);
+    }
+}
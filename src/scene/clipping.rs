@@ -0,0 +1,101 @@
+use glam::{Vec3, Vec4};
+use crate::color::Color;
+
+/// Planes the scene shader can clip against at once; extra planes are ignored
+pub const MAX_CLIP_PLANES: usize = 4;
+
+/// Keeps the half-space the normal points into: points with `normal · p + distance >= 0`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipPlane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl ClipPlane {
+    /// Plane through `point`, keeping the side `normal` points to
+    pub fn new(point: Vec3, normal: Vec3) -> Self {
+        let normal = normal.normalize_or_zero();
+        Self { normal, distance: -normal.dot(point) }
+    }
+
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+
+    /// Move the plane by `offset`
+    pub fn translate(&mut self, offset: Vec3) {
+        self.distance -= self.normal.dot(offset);
+    }
+
+    pub(crate) fn to_vec4(self) -> Vec4 {
+        self.normal.extend(self.distance)
+    }
+}
+
+/// Section cuts through the scene. Geometry outside any plane is discarded; with `caps` on,
+/// the cut surfaces of closed meshes are filled in `cap_color`, lit as if they faced the cut.
+/// Shadows and the ground grid ignore the planes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipping {
+    pub planes: Vec<ClipPlane>,
+    pub caps: bool,
+    pub cap_color: Color,
+}
+
+impl Default for Clipping {
+    fn default() -> Self {
+        Self {
+            planes: Vec::new(),
+            caps: true,
+            cap_color: Color::linear(0.85, 0.45, 0.25),
+        }
+    }
+}
+
+impl Clipping {
+    pub fn is_active(&self) -> bool {
+        !self.planes.is_empty()
+    }
+
+    /// The planes the renderer applies
+    pub fn active_planes(&self) -> &[ClipPlane] {
+        &self.planes[..self.planes.len().min(MAX_CLIP_PLANES)]
+    }
+
+    pub fn is_clipped(&self, point: Vec3) -> bool {
+        self.active_planes().iter().any(|plane| plane.signed_distance(point) < 0.0)
+    }
+
+    pub fn translate(&mut self, offset: Vec3) {
+        for plane in &mut self.planes {
+            plane.translate(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clip_plane() {
+        let plane = ClipPlane::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -3.0, 0.0));
+        assert_eq!(plane.normal, Vec3::NEG_Y);
+        assert_eq!(plane.signed_distance(Vec3::ZERO), 2.0);
+
+        let mut clipping = Clipping { planes: vec![plane], ..Clipping::default() };
+        assert!(!clipping.is_clipped(Vec3::new(5.0, 1.0, 5.0)));
+        assert!(clipping.is_clipped(Vec3::new(0.0, 3.0, 0.0)));
+
+        // Moving the plane up keeps more of the scene
+        clipping.translate(Vec3::new(0.0, 2.0, 0.0));
+        assert!(!clipping.is_clipped(Vec3::new(0.0, 3.0, 0.0)));
+    }
+
+    #[test]
+    fn test_active_planes_capped() {
+        let planes = vec![ClipPlane::new(Vec3::ZERO, Vec3::X); MAX_CLIP_PLANES + 2];
+        let clipping = Clipping { planes, ..Clipping::default() };
+        assert_eq!(clipping.active_planes().len(), MAX_CLIP_PLANES);
+    }
+}
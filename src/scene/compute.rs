@@ -0,0 +1,279 @@
+use anyhow::{bail, Result};
+use crate::diagnostics;
+use super::profiler::{PassQueries, Profiler};
+
+/// Stable identifier of a compute task added to the renderer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComputeTaskId(pub u32);
+
+/// Where in the frame a compute task is dispatched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComputeStage {
+    /// Submitted on its own as soon as the frame starts, before the CPU prepares and records
+    /// the raster work, so the GPU runs it while it would otherwise wait for the frame. For
+    /// work that doesn't need this frame's raster results, like particle simulation or culling
+    /// against last frame's data; the auto-exposure histogram goes in the same submission.
+    /// wgpu has a single queue, so this interleaves rather than running on a separate compute
+    /// queue; later passes using the same resources still see the results, as wgpu orders
+    /// them with barriers.
+    Async,
+    /// First in the frame's main encoder, after the `Async` submission, so shadows and the
+    /// scene see the results, e.g. simulated geometry
+    BeforeShadows,
+    /// After the shadow maps, before the scene pass
+    BeforeScene,
+    /// After the scene pass, before post-processing
+    AfterScene,
+    /// After post-processing, before the debug overlay
+    AfterPost,
+}
+
+impl ComputeStage {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Async => "Compute (async)",
+            Self::BeforeShadows => "Compute (before shadows)",
+            Self::BeforeScene => "Compute (before scene)",
+            Self::AfterScene => "Compute (after scene)",
+            Self::AfterPost => "Compute (after post)",
+        }
+    }
+}
+
+/// A resource bound to a compute task; the n-th binding is `@group(0) @binding(n)`
+#[derive(Debug, Clone, Copy)]
+pub enum ComputeBinding<'a> {
+    /// `var<storage, read>` when `read_only`, otherwise `var<storage, read_write>`
+    Storage { buffer: &'a wgpu::Buffer, read_only: bool },
+    /// `var<uniform>`
+    Uniform(&'a wgpu::Buffer),
+    /// A float `texture_2d`, read with `textureLoad`
+    Texture(&'a wgpu::TextureView),
+    /// A `texture_storage_2d` of the given format and access
+    StorageTexture {
+        view: &'a wgpu::TextureView,
+        format: wgpu::TextureFormat,
+        access: wgpu::StorageTextureAccess,
+    },
+}
+
+impl ComputeBinding<'_> {
+    fn layout_entry(&self, binding: u32) -> wgpu::BindGroupLayoutEntry {
+        let buffer = |ty| wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let ty = match *self {
+            Self::Storage { read_only, .. } => buffer(wgpu::BufferBindingType::Storage { read_only }),
+            Self::Uniform(_) => buffer(wgpu::BufferBindingType::Uniform),
+            Self::Texture(_) => wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            Self::StorageTexture { format, access, .. } => wgpu::BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        }
+    }
+
+    fn resource(&self) -> wgpu::BindingResource<'_> {
+        match *self {
+            Self::Storage { buffer, .. } | Self::Uniform(buffer) => buffer.as_entire_binding(),
+            Self::Texture(view) | Self::StorageTexture { view, .. } => wgpu::BindingResource::TextureView(view),
+        }
+    }
+}
+
+/// User-defined GPU work: a WGSL compute shader over resources in bind group 0.
+///
+/// Add it to the renderer with `Renderer::add_compute_task` to dispatch it every frame at a
+/// `ComputeStage`, or record it yourself with `encode` for one-off work.
+pub struct ComputeTask {
+    label: String,
+    pipeline: wgpu::ComputePipeline,
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// Workgroup counts in x, y and z for each dispatch
+    pub workgroups: [u32; 3],
+    /// Disabled tasks stay registered but aren't dispatched
+    pub enabled: bool,
+}
+
+impl ComputeTask {
+    /// Compile `entry_point` of `source` against `bindings`. Shader and layout errors are
+    /// returned rather than logged.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+        entry_point: &str,
+        bindings: &[ComputeBinding],
+        workgroups: [u32; 3],
+    ) -> Result<Self> {
+        let layout_entries: Vec<_> = bindings.iter()
+            .enumerate()
+            .map(|(binding, resource)| resource.layout_entry(binding as u32))
+            .collect();
+
+        let (pipeline, bind_group_layout, bind_group) = diagnostics::checked(device, &format!("compute task '{}'", label), || {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries: &layout_entries,
+            });
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            });
+            let bind_group = create_bind_group(device, label, &bind_group_layout, bindings);
+            (pipeline, bind_group_layout, bind_group)
+        })?;
+
+        Ok(Self {
+            label: label.to_string(),
+            pipeline,
+            layout_entries,
+            bind_group_layout,
+            bind_group,
+            workgroups,
+            enabled: true,
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Swap in new resources, e.g. to ping-pong between buffers. They must match the kinds
+    /// (and texture formats) the task was created with.
+    pub fn set_bindings(&mut self, device: &wgpu::Device, bindings: &[ComputeBinding]) -> Result<()> {
+        if bindings.len() != self.layout_entries.len() {
+            bail!("compute task '{}' takes {} bindings, got {}", self.label, self.layout_entries.len(), bindings.len());
+        }
+        for (binding, (resource, expected)) in bindings.iter().zip(&self.layout_entries).enumerate() {
+            if resource.layout_entry(binding as u32) != *expected {
+                bail!("compute task '{}': binding {} doesn't match the task's layout", self.label, binding);
+            }
+        }
+        let context = format!("compute task '{}'", self.label);
+        self.bind_group = diagnostics::checked(device, &context, || {
+            create_bind_group(device, &self.label, &self.bind_group_layout, bindings)
+        })?;
+        Ok(())
+    }
+
+    /// Record the task in its own compute pass, outside the renderer's frame
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&self.label),
+            timestamp_writes: None,
+        });
+        self.dispatch(&mut pass);
+    }
+
+    fn dispatch(&self, pass: &mut wgpu::ComputePass) {
+        let [x, y, z] = self.workgroups;
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(x, y, z);
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    bindings: &[ComputeBinding],
+) -> wgpu::BindGroup {
+    let entries: Vec<_> = bindings.iter()
+        .enumerate()
+        .map(|(binding, resource)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: resource.resource(),
+        })
+        .collect();
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &entries,
+    })
+}
+
+/// Compute tasks registered with the renderer, dispatched in the order they were added
+#[derive(Default)]
+pub(crate) struct ComputeTasks {
+    tasks: Vec<(ComputeTaskId, ComputeStage, ComputeTask)>,
+    next_id: u32,
+}
+
+impl ComputeTasks {
+    pub fn add(&mut self, stage: ComputeStage, task: ComputeTask) -> ComputeTaskId {
+        let id = ComputeTaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push((id, stage, task));
+        id
+    }
+
+    pub fn remove(&mut self, id: ComputeTaskId) -> Option<ComputeTask> {
+        let index = self.tasks.iter().position(|(task_id, _, _)| *task_id == id)?;
+        Some(self.tasks.remove(index).2)
+    }
+
+    pub fn get_mut(&mut self, id: ComputeTaskId) -> Option<&mut ComputeTask> {
+        self.tasks.iter_mut()
+            .find(|(task_id, _, _)| *task_id == id)
+            .map(|(_, _, task)| task)
+    }
+
+    /// The enabled tasks of `stage`, in dispatch order
+    fn enabled(&self, stage: ComputeStage) -> impl Iterator<Item = &ComputeTask> {
+        self.tasks.iter()
+            .filter(move |(_, task_stage, task)| *task_stage == stage && task.enabled)
+            .map(|(_, _, task)| task)
+    }
+
+    pub fn has_enabled(&self, stage: ComputeStage) -> bool {
+        self.enabled(stage).next().is_some()
+    }
+
+    /// Dispatch the enabled tasks of `stage` in one profiled compute pass
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, stage: ComputeStage, profiler: &mut Profiler) {
+        if !self.has_enabled(stage) {
+            return;
+        }
+
+        let queries = profiler.begin_pass(stage.label());
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(stage.label()),
+            timestamp_writes: queries.as_ref().map(PassQueries::compute_writes),
+        });
+        for task in self.enabled(stage) {
+            task.dispatch(&mut pass);
+        }
+        drop(pass);
+        profiler.end_scope();
+    }
+}
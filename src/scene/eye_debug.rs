@@ -0,0 +1,479 @@
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+use crate::diagnostics;
+use crate::settings::ViewportMode;
+use crate::shaders;
+use super::post::texture_entry;
+use super::profiler::Profiler;
+use super::viewport::Viewport;
+
+/// Far end of the depth panel's grey ramp when the scene's far plane is further
+const MAX_DEPTH_RANGE: f32 = 1000.0;
+/// Velocity panel color change per pixel of motion; 10 pixels saturates
+const VELOCITY_SCALE: f32 = 0.05;
+
+/// What the desktop mirror shows of each view rendered by `Renderer::render_views`, to spot
+/// artifacts that only one eye has without wearing the headset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EyeDebugMode {
+    #[default]
+    Off,
+    /// Each view's scene color, side by side
+    Color,
+    /// Each view's depth buffer, near surfaces bright
+    Depth,
+    /// Each view's screen motion since the last frame
+    Velocity,
+    /// A row per view with its color, depth and velocity
+    All,
+}
+
+impl EyeDebugMode {
+    /// The order F4 cycles through
+    pub const CYCLE: [Self; 5] = [Self::Off, Self::Color, Self::Depth, Self::Velocity, Self::All];
+
+    pub fn next(self) -> Self {
+        let index = Self::CYCLE.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::CYCLE[(index + 1) % Self::CYCLE.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Color => "color",
+            Self::Depth => "depth",
+            Self::Velocity => "velocity",
+            Self::All => "all",
+        }
+    }
+
+    /// Panels captured for every view
+    pub fn panels(self) -> &'static [EyeDebugPanel] {
+        match self {
+            Self::Off => &[],
+            Self::Color => &[EyeDebugPanel::Color],
+            Self::Depth => &[EyeDebugPanel::Depth],
+            Self::Velocity => &[EyeDebugPanel::Velocity],
+            Self::All => &[EyeDebugPanel::Color, EyeDebugPanel::Depth, EyeDebugPanel::Velocity],
+        }
+    }
+}
+
+/// One image of one view in the debug atlas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EyeDebugPanel {
+    /// The scene before post-processing, tonemapped with Reinhard
+    Color,
+    /// Distance from the eye on a log scale, black where nothing was drawn
+    Depth,
+    /// Screen-space motion from the view moving since last frame, reprojected from depth;
+    /// grey is still. Objects moving on their own don't show.
+    Velocity,
+}
+
+impl EyeDebugPanel {
+    fn kind(self) -> f32 {
+        match self {
+            Self::Color => 0.0,
+            Self::Depth => 1.0,
+            Self::Velocity => 2.0,
+        }
+    }
+}
+
+/// Where each panel goes in the atlas: the views side by side for a single panel kind, or a
+/// row per view with every kind for `EyeDebugMode::All`. Panels are half the render size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EyeDebugLayout {
+    pub mode: EyeDebugMode,
+    pub views: usize,
+    pub panel_width: u32,
+    pub panel_height: u32,
+}
+
+impl EyeDebugLayout {
+    /// `None` when there's nothing to show
+    pub fn new(mode: EyeDebugMode, views: usize, render_width: u32, render_height: u32) -> Option<Self> {
+        (mode != EyeDebugMode::Off && views > 0).then(|| Self {
+            mode,
+            views,
+            panel_width: (render_width / 2).max(1),
+            panel_height: (render_height / 2).max(1),
+        })
+    }
+
+    /// Columns and rows of panels
+    pub fn grid(&self) -> (u32, u32) {
+        match self.mode {
+            EyeDebugMode::All => (self.mode.panels().len() as u32, self.views as u32),
+            _ => (self.views as u32, 1),
+        }
+    }
+
+    /// Atlas size in pixels
+    pub fn size(&self) -> (u32, u32) {
+        let (columns, rows) = self.grid();
+        (columns * self.panel_width, rows * self.panel_height)
+    }
+
+    /// The atlas region of `view`'s `panel`, `None` when the mode doesn't show it
+    pub fn panel(&self, view: usize, panel: EyeDebugPanel) -> Option<Viewport> {
+        let index = self.mode.panels().iter().position(|shown| *shown == panel)?;
+        if view >= self.views {
+            return None;
+        }
+        let (column, row) = match self.mode {
+            EyeDebugMode::All => (index as u32, view as u32),
+            _ => (view as u32, 0),
+        };
+        Some(Viewport {
+            x: column * self.panel_width,
+            y: row * self.panel_height,
+            width: self.panel_width,
+            height: self.panel_height,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PanelUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    params: [f32; 4],
+}
+
+/// The debug atlas and the layout it was made for
+struct Atlas {
+    layout: EyeDebugLayout,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Captures `EyeDebugMode` panels of each view while the frame renders, then draws them onto
+/// the desktop mirror
+pub struct EyeDebugPass {
+    mode: EyeDebugMode,
+    format: wgpu::TextureFormat,
+    panel_layout: wgpu::BindGroupLayout,
+    panel_pipeline: wgpu::RenderPipeline,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    atlas: Option<Atlas>,
+    /// Whether the atlas has been cleared this frame
+    cleared: bool,
+    /// Each view's view-projection last frame, for the velocity panel
+    previous: Vec<Mat4>,
+    current: Vec<Mat4>,
+}
+
+impl EyeDebugPass {
+    /// `format` is the mirror window's surface format, which the atlas shares
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let shader = shaders::create_module(device, "Eye Debug Shader", include_str!("../../shaders/eye_debug.wgsl"));
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let panel_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Eye Debug Panel Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_entry(2),
+                // Depth, read as unfilterable float so GL backends can load it
+                texture_entry(3, wgpu::TextureSampleType::Float { filterable: false }),
+            ],
+        });
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Eye Debug Composite Bind Group Layout"),
+            entries: &[
+                texture_entry(4, wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_entry(5),
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Eye Debug Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            mode: EyeDebugMode::Off,
+            format,
+            panel_pipeline: create_pipeline(device, "Eye Debug Panel Pipeline", &shader, &panel_layout, "fs_main", format),
+            composite_pipeline: create_pipeline(device, "Eye Debug Composite Pipeline", &shader, &composite_layout, "fs_composite", format),
+            panel_layout,
+            composite_layout,
+            sampler,
+            atlas: None,
+            cleared: false,
+            previous: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> EyeDebugMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: EyeDebugMode) {
+        self.mode = mode;
+        if mode == EyeDebugMode::Off {
+            self.atlas = None;
+        }
+    }
+
+    /// Layout of the panels captured last frame
+    pub fn layout(&self) -> Option<EyeDebugLayout> {
+        self.atlas.as_ref().map(|atlas| atlas.layout)
+    }
+
+    /// The atlas of panels captured last frame, readable with `readback::read_texture_region`
+    /// for screenshots of every eye at once
+    pub fn texture(&self) -> Option<&wgpu::Texture> {
+        self.atlas.as_ref().map(|atlas| &atlas.texture)
+    }
+
+    /// Size the atlas for a frame of `views` views rendered at `render_size`
+    pub fn begin_frame(&mut self, device: &wgpu::Device, views: &[Mat4], render_size: (u32, u32)) {
+        let Some(layout) = EyeDebugLayout::new(self.mode, views.len(), render_size.0, render_size.1) else {
+            self.atlas = None;
+            return;
+        };
+        if self.atlas.as_ref().map(|atlas| atlas.layout) != Some(layout) {
+            self.atlas = Some(self.create_atlas(device, layout));
+        }
+        self.cleared = false;
+        // Views without a previous frame, e.g. just after switching on, show no motion
+        self.current = views.to_vec();
+        if self.previous.len() != views.len() {
+            self.previous = self.current.clone();
+        }
+    }
+
+    /// Draw `view`'s panels from the scene color and single-sample depth it just rendered
+    #[allow(clippy::too_many_arguments)]
+    pub fn capture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        view: usize,
+        position: Vec3,
+        far: f32,
+        color: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        profiler: &mut Profiler,
+    ) {
+        let Some(atlas) = &self.atlas else {
+            return;
+        };
+        let (Some(view_proj), Some(prev_view_proj)) = (self.current.get(view), self.previous.get(view)) else {
+            return;
+        };
+        let bind_groups: Vec<_> = self.mode.panels().iter()
+            .filter_map(|panel| {
+                let region = atlas.layout.panel(view, *panel)?;
+                let uniform = PanelUniform {
+                    inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    prev_view_proj: prev_view_proj.to_cols_array_2d(),
+                    eye: position.extend(panel.kind()).to_array(),
+                    params: [far.min(MAX_DEPTH_RANGE), VELOCITY_SCALE, 0.0, 0.0],
+                };
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Eye Debug Panel Buffer"),
+                    contents: bytemuck::bytes_of(&uniform),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Eye Debug Panel Bind Group"),
+                    layout: &self.panel_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() },
+                        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(color) },
+                        wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                        wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(depth) },
+                    ],
+                });
+                Some((region, bind_group))
+            })
+            .collect();
+
+        let target = atlas.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let load = if self.cleared { wgpu::LoadOp::Load } else { wgpu::LoadOp::Clear(wgpu::Color::BLACK) };
+        self.cleared = true;
+        let queries = profiler.begin_pass("Eye Debug");
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Eye Debug Panel Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.panel_pipeline);
+            for (region, bind_group) in &bind_groups {
+                pass.set_viewport(region.x as f32, region.y as f32, region.width as f32, region.height as f32, 0.0, 1.0);
+                pass.set_bind_group(0, bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        profiler.end_scope();
+    }
+
+    /// Remember this frame's views for the next frame's velocity panels
+    pub fn end_frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Draw the atlas onto `target`, a `width` by `height` mirror surface, scaled to fit with
+    /// black bars. Nothing is drawn while the mode is off.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, width: u32, height: u32) {
+        let Some(atlas) = &self.atlas else {
+            return;
+        };
+        let (atlas_width, atlas_height) = atlas.layout.size();
+        let fit = Viewport::fit(&ViewportMode::Fixed { width: atlas_width, height: atlas_height }, width, height);
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Eye Debug Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_viewport(fit.x as f32, fit.y as f32, fit.width as f32, fit.height as f32, 0.0, 1.0);
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &atlas.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn create_atlas(&self, device: &wgpu::Device, layout: EyeDebugLayout) -> Atlas {
+        let (width, height) = layout.size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Eye Debug Atlas"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Eye Debug Composite Bind Group"),
+            layout: &self.composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 4, resource: wgpu::BindingResource::TextureView(&view) },
+                wgpu::BindGroupEntry { binding: 5, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+            ],
+        });
+        Atlas { layout, texture, bind_group }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    shader: &wgpu::ShaderModule,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    fragment: &str,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    diagnostics::scoped(device, label, || {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some(fragment),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_cycle() {
+        let mut mode = EyeDebugMode::default();
+        let mut seen = Vec::new();
+        for _ in 0..EyeDebugMode::CYCLE.len() {
+            seen.push(mode);
+            mode = mode.next();
+        }
+        assert_eq!(seen, EyeDebugMode::CYCLE);
+        assert_eq!(mode, EyeDebugMode::Off);
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(EyeDebugLayout::new(EyeDebugMode::Off, 2, 800, 600), None);
+        assert_eq!(EyeDebugLayout::new(EyeDebugMode::Depth, 0, 800, 600), None);
+
+        // Eyes side by side
+        let depth = EyeDebugLayout::new(EyeDebugMode::Depth, 2, 800, 600).unwrap();
+        assert_eq!(depth.size(), (800, 300));
+        assert_eq!(depth.panel(1, EyeDebugPanel::Depth), Some(Viewport { x: 400, y: 0, width: 400, height: 300 }));
+        assert_eq!(depth.panel(0, EyeDebugPanel::Color), None);
+        assert_eq!(depth.panel(2, EyeDebugPanel::Depth), None);
+
+        // A row per eye, a column per panel
+        let all = EyeDebugLayout::new(EyeDebugMode::All, 2, 800, 600).unwrap();
+        assert_eq!(all.grid(), (3, 2));
+        assert_eq!(all.size(), (1200, 600));
+        assert_eq!(all.panel(0, EyeDebugPanel::Color), Some(Viewport { x: 0, y: 0, width: 400, height: 300 }));
+        assert_eq!(all.panel(1, EyeDebugPanel::Velocity), Some(Viewport { x: 800, y: 300, width: 400, height: 300 }));
+    }
+}
@@ -0,0 +1,232 @@
+use glam::{Mat3, Mat4, Vec3, Vec4, Vec4Swizzles};
+
+/// Most views `ViewCulling` can tell apart, one bit each
+pub const MAX_VIEWS: usize = 32;
+
+/// Allowed error when checking that one view's plane bounds another view
+const EPSILON: f32 = 1e-5;
+
+/// The volume a view projection sees, as inward-facing planes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    /// `xyz` is the unit normal and `w` the offset, so `plane.dot(point.extend(1.0)) >= 0` inside
+    planes: Vec<Vec4>,
+}
+
+impl Frustum {
+    /// Planes of `view_projection`. Degenerate ones, such as the far plane of an infinite
+    /// projection, are dropped; the near plane is taken at -1 clip depth, which also holds
+    /// conservatively for projections whose depth starts at 0.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let row = |i| view_projection.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(3) + row(2),
+            row(3) - row(2),
+        ];
+        Self {
+            planes: planes.into_iter()
+                .filter_map(|plane| {
+                    let length = plane.xyz().length();
+                    (length > 1e-6).then(|| plane / length)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn planes(&self) -> &[Vec4] {
+        &self.planes
+    }
+
+    /// Whether any of the axis-aligned box may be inside; boxes near a corner can pass
+    /// without being visible, but visible ones never fail
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // The box corner furthest along the plane's normal
+            let corner = Vec3::select(plane.xyz().cmpge(Vec3::ZERO), max, min);
+            plane.dot(corner.extend(1.0)) >= 0.0
+        })
+    }
+
+    /// A frustum bounding all of `view_projections`, such as a headset's two eyes, so one test
+    /// per object covers every view. It keeps each side plane that bounds the other views too;
+    /// near and far planes never bound another view's cone and are left out, as is everything
+    /// when a view has no apex (orthographic ones).
+    pub fn merged(view_projections: &[Mat4]) -> Self {
+        if let [single] = view_projections {
+            return Self::from_view_projection(*single);
+        }
+        let frusta: Vec<_> = view_projections.iter().map(|matrix| Self::from_view_projection(*matrix)).collect();
+        let Some(cones) = frusta.iter().map(Cone::of).collect::<Option<Vec<_>>>() else {
+            return Self { planes: Vec::new() };
+        };
+        let planes = frusta.iter().enumerate()
+            .flat_map(|(view, frustum)| {
+                let cones = &cones;
+                frustum.planes.iter().copied().filter(move |plane| {
+                    cones.iter().enumerate().all(|(other, cone)| other == view || cone.inside(*plane))
+                })
+            })
+            .collect();
+        Self { planes }
+    }
+}
+
+/// The infinite pyramid a perspective view's side planes enclose
+struct Cone {
+    apex: Vec3,
+    /// Unit directions of the four edges
+    edges: [Vec3; 4],
+}
+
+impl Cone {
+    /// `None` without four side planes meeting at a point
+    fn of(frustum: &Frustum) -> Option<Self> {
+        let [left, right, bottom, top] = frustum.planes.get(..4)?.try_into().ok()?;
+        let normals = Mat3::from_cols(left.xyz(), right.xyz(), bottom.xyz()).transpose();
+        if normals.determinant().abs() < 1e-6 {
+            return None;
+        }
+        let apex = normals.inverse() * -Vec3::new(left.w, right.w, bottom.w);
+        // Inward normals add up to a direction inside the cone
+        let inward = left.xyz() + right.xyz() + bottom.xyz() + top.xyz();
+        let edge = |a: Vec4, b: Vec4| {
+            let direction = a.xyz().cross(b.xyz()).normalize_or_zero();
+            if direction.dot(inward) < 0.0 { -direction } else { direction }
+        };
+        Some(Self {
+            apex,
+            edges: [edge(left, bottom), edge(bottom, right), edge(right, top), edge(top, left)],
+        })
+    }
+
+    /// Whether the whole cone lies inside `plane`
+    fn inside(&self, plane: Vec4) -> bool {
+        plane.dot(self.apex.extend(1.0)) >= -EPSILON
+            && self.edges.iter().all(|edge| plane.xyz().dot(*edge) >= -EPSILON)
+    }
+}
+
+/// Culling for a frame rendered from several views, e.g. the eyes of a headset. Objects are
+/// tested once against a frustum merging all views; with `precise` set, those that pass are
+/// also tested per view, so an object seen by one eye isn't drawn for the other.
+#[derive(Debug, Clone)]
+pub struct ViewCulling {
+    merged: Frustum,
+    views: Vec<Frustum>,
+    precise: bool,
+}
+
+impl ViewCulling {
+    /// At most `MAX_VIEWS` views
+    pub fn new(view_projections: &[Mat4], precise: bool) -> Self {
+        assert!(view_projections.len() <= MAX_VIEWS, "At most {} views can be culled together", MAX_VIEWS);
+        Self {
+            merged: Frustum::merged(view_projections),
+            views: view_projections.iter().map(|matrix| Frustum::from_view_projection(*matrix)).collect(),
+            precise,
+        }
+    }
+
+    /// Mask with a bit for every view
+    pub fn all(&self) -> u32 {
+        if self.views.len() == MAX_VIEWS { u32::MAX } else { (1 << self.views.len()) - 1 }
+    }
+
+    /// Bit `i` is set when the box may be seen by view `i`; 0 when no view sees it
+    pub fn visibility(&self, min: Vec3, max: Vec3) -> u32 {
+        if !self.intersects(min, max) {
+            return 0;
+        }
+        self.view_mask(min, max)
+    }
+
+    /// Whether any view may see the box, from the merged frustum alone
+    pub fn intersects(&self, min: Vec3, max: Vec3) -> bool {
+        self.merged.intersects_aabb(min, max)
+    }
+
+    /// `visibility` without the merged test, for a box already known to pass it: every view's
+    /// bit unless `precise`
+    pub fn view_mask(&self, min: Vec3, max: Vec3) -> u32 {
+        if !self.precise || self.views.len() == 1 {
+            return self.all();
+        }
+        self.views.iter().enumerate()
+            .filter(|(_, frustum)| frustum.intersects_aabb(min, max))
+            .fold(0, |mask, (view, _)| mask | 1 << view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An eye at `x` looking down -Z, with a 90° field of view
+    fn eye(x: f32) -> Mat4 {
+        Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0)
+            * Mat4::look_at_rh(Vec3::new(x, 0.0, 0.0), Vec3::new(x, 0.0, -1.0), Vec3::Y)
+    }
+
+    #[test]
+    fn test_frustum_aabb() {
+        let frustum = Frustum::from_view_projection(eye(0.0));
+        assert_eq!(frustum.planes().len(), 6);
+        let unit = |center: Vec3| (center - Vec3::splat(0.5), center + Vec3::splat(0.5));
+        let visible = |(min, max)| frustum.intersects_aabb(min, max);
+        assert!(visible(unit(Vec3::new(0.0, 0.0, -5.0))));
+        // Straddling the edge of the view still counts
+        assert!(visible(unit(Vec3::new(5.4, 0.0, -5.0))));
+        assert!(!visible(unit(Vec3::new(6.2, 0.0, -5.0))));
+        assert!(!visible(unit(Vec3::new(0.0, 0.0, 5.0))));
+        assert!(!visible(unit(Vec3::new(0.0, 0.0, -101.0))));
+
+        // Infinite projections have no far plane
+        let infinite = Frustum::from_view_projection(Mat4::perspective_infinite_rh(1.0, 1.0, 0.1));
+        assert_eq!(infinite.planes().len(), 5);
+    }
+
+    #[test]
+    fn test_merged_stereo() {
+        let (left, right) = (eye(-0.5), eye(0.5));
+        let merged = Frustum::merged(&[left, right]);
+        // Left eye's left plane, right eye's right plane and the shared top and bottom
+        assert_eq!(merged.planes().len(), 6);
+
+        let culling = ViewCulling::new(&[left, right], false);
+        let precise = ViewCulling::new(&[left, right], true);
+        let point = |x: f32, z: f32| {
+            let center = Vec3::new(x, 0.0, z);
+            (center - Vec3::splat(0.1), center + Vec3::splat(0.1))
+        };
+        let (min, max) = point(0.0, -5.0);
+        assert_eq!(culling.visibility(min, max), 0b11);
+        assert_eq!(precise.visibility(min, max), 0b11);
+        // Only the left eye sees as far left as this
+        let (min, max) = point(-5.2, -5.0);
+        assert_eq!(culling.visibility(min, max), 0b11);
+        assert_eq!(precise.visibility(min, max), 0b01);
+        let (min, max) = point(5.2, -5.0);
+        assert_eq!(precise.visibility(min, max), 0b10);
+        // Out of both eyes' view
+        let (min, max) = point(7.0, -5.0);
+        assert_eq!(culling.visibility(min, max), 0);
+        assert!(!culling.intersects(min, max));
+        // The per-view mask trusts the caller on the merged test
+        assert_eq!(culling.view_mask(min, max), 0b11);
+        assert_eq!(precise.view_mask(min, max), 0);
+        let (min, max) = point(0.0, 5.0);
+        assert_eq!(culling.visibility(min, max), 0);
+
+        // Eyes turned apart still merge conservatively
+        let turned = Mat4::perspective_rh(1.0, 1.0, 0.1, 100.0) * Mat4::from_rotation_y(0.3);
+        let merged = Frustum::merged(&[left, turned]);
+        for view in [left, turned] {
+            let ahead = view.inverse().project_point3(Vec3::new(0.0, 0.0, 0.5));
+            assert!(merged.intersects_aabb(ahead, ahead));
+        }
+    }
+}
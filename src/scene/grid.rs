@@ -0,0 +1,217 @@
+use bytemuck::Zeroable;
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+use crate::diagnostics;
+use super::post::{ALBEDO_FORMAT, HDR_FORMAT, NORMAL_FORMAT, VELOCITY_FORMAT};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    camera_pos: [f32; 4],
+    params: [f32; 4],
+    origin: [f32; 4],
+}
+
+/// Appearance of the ground grid. Major line spacing that divides `origin::REBASE_SNAP`
+/// keeps the lines continuous across floating-origin rebases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridSettings {
+    /// Minor line spacing in meters
+    pub cell_size: f32,
+    /// Minor cells between major lines
+    pub major_every: f32,
+    /// Distance from the camera at which the grid has faded out completely
+    pub fade_distance: f32,
+    /// Length of the origin axis segments in meters
+    pub axis_length: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            major_every: 10.0,
+            fade_distance: 60.0,
+            axis_length: 1.0,
+        }
+    }
+}
+
+/// Procedural infinite grid on the ground plane plus XYZ origin axes
+pub struct GridPass {
+    pub enabled: bool,
+    pub settings: GridSettings,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    grid_pipeline: wgpu::RenderPipeline,
+    axis_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GridPass {
+    pub fn new(device: &wgpu::Device, sample_count: u32, depth_format: wgpu::TextureFormat) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[GridUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/grid.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (grid_pipeline, axis_pipeline) = Self::create_pipelines(device, &layout, &shader, sample_count, depth_format);
+
+        Self {
+            enabled: true,
+            settings: GridSettings::default(),
+            shader,
+            layout,
+            grid_pipeline,
+            axis_pipeline,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_pipelines(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+        depth_format: wgpu::TextureFormat,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let create = |label, vs_entry, fs_entry, topology| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some(vs_entry),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some(fs_entry),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: HDR_FORMAT,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: NORMAL_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: ALBEDO_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        diagnostics::scoped(device, "Grid Pipelines", || (
+            create("Grid Pipeline", "vs_grid", "fs_grid", wgpu::PrimitiveTopology::TriangleList),
+            create("Grid Axis Pipeline", "vs_axis", "fs_axis", wgpu::PrimitiveTopology::LineList),
+        ))
+    }
+
+    /// Rebuild the pipelines after the scene pass sample count or depth format changed
+    pub fn set_targets(&mut self, device: &wgpu::Device, sample_count: u32, depth_format: wgpu::TextureFormat) {
+        let (grid_pipeline, axis_pipeline) = Self::create_pipelines(device, &self.layout, &self.shader, sample_count, depth_format);
+        self.grid_pipeline = grid_pipeline;
+        self.axis_pipeline = axis_pipeline;
+    }
+
+    /// Upload the view's matrices; call before the scene pass begins.
+    /// `origin` is where the absolute world origin sits in local space, for the axes.
+    pub fn prepare(&self, queue: &wgpu::Queue, view_proj: Mat4, camera_pos: Vec3, origin: Vec3) {
+        if !self.enabled {
+            return;
+        }
+        let uniform = GridUniform {
+            view_proj: view_proj.to_cols_array_2d(),
+            inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+            camera_pos: camera_pos.extend(1.0).to_array(),
+            params: [
+                self.settings.cell_size.max(0.001),
+                self.settings.major_every.max(1.0),
+                self.settings.fade_distance,
+                self.settings.axis_length,
+            ],
+            origin: origin.extend(0.0).to_array(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Draw into the scene pass after opaque geometry so it blends over the depth-tested background
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_pipeline(&self.grid_pipeline);
+        render_pass.draw(0..3, 0..1);
+        render_pass.set_pipeline(&self.axis_pipeline);
+        render_pass.draw(0..6, 0..1);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use glam::Mat4;
+use super::{ObjectId, SceneObjects, Transform};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Link {
+    parent: ObjectId,
+    /// The child's transform in its parent's space
+    local: Mat4,
+}
+
+/// Parent-child links between objects, e.g. the nodes of a glTF model or a lamp on a cart:
+/// `Scene::update` places each child at its parent's world transform times its local one,
+/// parents before children. Children's own transforms are overwritten; move them with
+/// `set_local`. Links to removed objects are dropped, leaving the child where it was.
+#[derive(Debug, Default)]
+pub struct Hierarchy {
+    links: BTreeMap<ObjectId, Link>,
+}
+
+impl Hierarchy {
+    /// Make `child` follow `parent` at `local`, replacing any parent it had. Returns false,
+    /// changing nothing, when that would make the object its own ancestor.
+    pub fn attach(&mut self, child: ObjectId, parent: ObjectId, local: Mat4) -> bool {
+        if self.ancestors(parent).any(|ancestor| ancestor == child) {
+            return false;
+        }
+        self.links.insert(child, Link { parent, local });
+        true
+    }
+
+    /// Stop `child` following its parent; it stays where it is. Returns its former parent.
+    pub fn detach(&mut self, child: ObjectId) -> Option<ObjectId> {
+        self.links.remove(&child).map(|link| link.parent)
+    }
+
+    pub fn parent(&self, child: ObjectId) -> Option<ObjectId> {
+        self.links.get(&child).map(|link| link.parent)
+    }
+
+    pub fn children(&self, parent: ObjectId) -> impl Iterator<Item = ObjectId> + '_ {
+        self.links.iter()
+            .filter(move |(_, link)| link.parent == parent)
+            .map(|(&child, _)| child)
+    }
+
+    /// `object` followed by its parent, its parent's parent and so on
+    pub fn ancestors(&self, object: ObjectId) -> impl Iterator<Item = ObjectId> + '_ {
+        std::iter::successors(Some(object), |&object| self.parent(object))
+    }
+
+    /// The child's transform relative to its parent
+    pub fn local(&self, child: ObjectId) -> Option<Mat4> {
+        self.links.get(&child).map(|link| link.local)
+    }
+
+    /// Move a child relative to its parent; returns false if it has none
+    pub fn set_local(&mut self, child: ObjectId, local: Mat4) -> bool {
+        let Some(link) = self.links.get_mut(&child) else {
+            return false;
+        };
+        link.local = local;
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Place every child under its parent, dropping links whose objects were removed
+    pub(crate) fn update(&mut self, objects: &mut SceneObjects) {
+        self.links.retain(|&child, link| objects.index_of(child).is_some() && objects.index_of(link.parent).is_some());
+        // Shallower children first, so every parent is placed before its own children read it
+        let mut order: Vec<(usize, ObjectId)> = self.links.keys()
+            .map(|&child| (self.ancestors(child).count(), child))
+            .collect();
+        order.sort_unstable();
+        for (_, child) in order {
+            let link = self.links[&child];
+            let (Some(parent), Some(index)) = (objects.index_of(link.parent), objects.index_of(child)) else {
+                continue;
+            };
+            let world = objects.transforms()[parent].to_matrix() * link.local;
+            objects.transforms_mut()[index] = Transform::from_matrix(world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn test_hierarchy_links() {
+        let [a, b, c] = [ObjectId(0), ObjectId(1), ObjectId(2)];
+        let mut hierarchy = Hierarchy::default();
+        assert!(hierarchy.attach(b, a, Mat4::IDENTITY));
+        assert!(hierarchy.attach(c, b, Mat4::from_translation(Vec3::X)));
+        assert_eq!(hierarchy.ancestors(c).collect::<Vec<_>>(), vec![c, b, a]);
+        assert_eq!(hierarchy.children(a).collect::<Vec<_>>(), vec![b]);
+
+        // Cycles are refused
+        assert!(!hierarchy.attach(a, c, Mat4::IDENTITY));
+        assert!(!hierarchy.attach(a, a, Mat4::IDENTITY));
+        assert_eq!(hierarchy.parent(a), None);
+
+        assert_eq!(hierarchy.detach(b), Some(a));
+        assert!(hierarchy.attach(a, c, Mat4::IDENTITY));
+        assert!(!hierarchy.set_local(b, Mat4::IDENTITY));
+        assert!(hierarchy.set_local(c, Mat4::IDENTITY));
+        assert_eq!(hierarchy.local(c), Some(Mat4::IDENTITY));
+    }
+}
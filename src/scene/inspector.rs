@@ -0,0 +1,420 @@
+use std::fmt;
+use std::sync::mpsc;
+use glam::{Mat4, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+use crate::color::Color;
+use crate::shaders;
+use super::ObjectId;
+
+// Where each target's texel lands in a readback buffer; offsets are multiples of the texel size
+const COLOR_OFFSET: usize = 0;
+const NORMAL_OFFSET: usize = 8;
+const ALBEDO_OFFSET: usize = 16;
+const DEPTH_OFFSET: usize = 20;
+const ID_OFFSET: usize = 24;
+const READBACK_SIZE: usize = 28;
+
+/// Identifies an inspection until its result arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InspectId(pub u32);
+
+/// What the scene pass left at one pixel, read back from the G-buffer and the object ID target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelInfo {
+    pub id: InspectId,
+    /// Texel of the scene's render targets
+    pub texel: (u32, u32),
+    /// Depth buffer value, 1 where nothing was drawn
+    pub depth: f32,
+    /// World-space point the depth reconstructs to, `None` over the background
+    pub position: Option<Vec3>,
+    pub object: Option<ObjectId>,
+    /// Linear base color (texture times vertex color) and alpha, before lighting
+    pub albedo: Vec4,
+    /// World-space shading normal, after normal mapping
+    pub normal: Vec3,
+    pub reflectivity: f32,
+    /// Lit HDR color before post-processing
+    pub color: Vec3,
+}
+
+impl PixelInfo {
+    /// Decode a readback buffer; `size` is the render target size and `inverse_view_proj`
+    /// the inverse of the view's projection when the texels were copied
+    fn decode(id: InspectId, texel: (u32, u32), size: (u32, u32), inverse_view_proj: Mat4, bytes: &[u8]) -> Self {
+        let half = |offset: usize, index: usize| {
+            let start = offset + index * 2;
+            f16_to_f32(u16::from_le_bytes([bytes[start], bytes[start + 1]]))
+        };
+        let word = |offset: usize| <[u8; 4]>::try_from(&bytes[offset..offset + 4]).unwrap();
+        let albedo = &bytes[ALBEDO_OFFSET..ALBEDO_OFFSET + 4];
+        let depth = f32::from_le_bytes(word(DEPTH_OFFSET));
+
+        // The texel center back through the projection; depth is NDC z as stored
+        let position = (depth < 1.0).then(|| {
+            let ndc_x = (texel.0 as f32 + 0.5) / size.0 as f32 * 2.0 - 1.0;
+            let ndc_y = 1.0 - (texel.1 as f32 + 0.5) / size.1 as f32 * 2.0;
+            inverse_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, depth))
+        });
+        Self {
+            id,
+            texel,
+            depth,
+            position,
+            object: u32::from_le_bytes(word(ID_OFFSET)).checked_sub(1).map(ObjectId),
+            albedo: Color::srgba8(albedo[0], albedo[1], albedo[2], albedo[3]).to_vec4(),
+            normal: Vec3::new(half(NORMAL_OFFSET, 0), half(NORMAL_OFFSET, 1), half(NORMAL_OFFSET, 2)),
+            reflectivity: half(NORMAL_OFFSET, 3),
+            color: Vec3::new(half(COLOR_OFFSET, 0), half(COLOR_OFFSET, 1), half(COLOR_OFFSET, 2)),
+        }
+    }
+}
+
+impl fmt::Display for PixelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Pixel ({}, {}):", self.texel.0, self.texel.1)?;
+        match (self.position, self.object) {
+            (Some(position), Some(object)) => writeln!(f, "  object   {} at {:.3}", object.0, position)?,
+            (Some(position), None) => writeln!(f, "  surface  at {:.3} (grid or unpickable)", position)?,
+            (None, _) => writeln!(f, "  background")?,
+        }
+        writeln!(f, "  depth    {:.6}", self.depth)?;
+        writeln!(f, "  albedo   {:.3}", self.albedo)?;
+        writeln!(f, "  normal   {:.3} (reflectivity {:.2})", self.normal, self.reflectivity)?;
+        write!(f, "  color    {:.3} (HDR, before post)", self.color)
+    }
+}
+
+/// The textures an inspection reads, all at the scene's render size and single-sampled
+pub(crate) struct InspectTargets<'a> {
+    /// `HDR_FORMAT`
+    pub color: &'a wgpu::Texture,
+    /// `NORMAL_FORMAT`
+    pub normal: &'a wgpu::Texture,
+    /// `ALBEDO_FORMAT`
+    pub albedo: &'a wgpu::Texture,
+    /// Depth aspect of the depth buffer
+    pub depth: &'a wgpu::TextureView,
+    /// `picking::ID_FORMAT`
+    pub ids: &'a wgpu::Texture,
+}
+
+/// Signals when an inspection's staging buffer is mapped
+type MapReceiver = mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>;
+
+/// A readback on its way: what's needed to decode it once mapped
+struct Readback {
+    id: InspectId,
+    texel: (u32, u32),
+    size: (u32, u32),
+    inverse_view_proj: Mat4,
+    buffer: wgpu::Buffer,
+}
+
+/// Reads back everything the scene pass knows about single pixels: depth, object ID, albedo,
+/// normal and lit color, for debugging why a pixel looks wrong without a capture tool.
+///
+/// Like picks, inspections are copied out during the next frame and read back without
+/// stalling it. With MSAA the resolved values are read.
+pub struct PixelInspector {
+    /// Loads the depth texel's bits into an `R32Uint` texture, which unlike depth any backend
+    /// can copy and, unlike `R32Float`, render to
+    depth_layout: wgpu::BindGroupLayout,
+    depth_pipeline: wgpu::RenderPipeline,
+    requests: Vec<(InspectId, u32, u32)>,
+    /// Copied this frame, mapped once the frame is submitted
+    copied: Vec<Readback>,
+    mapping: Vec<(Readback, MapReceiver)>,
+    next_id: u32,
+}
+
+impl PixelInspector {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Inspector Depth Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Depth, read as unfilterable float so GL backends can load it
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Inspector Depth Pipeline Layout"),
+            bind_group_layouts: &[&depth_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders::create_module(device, "Inspector Shader", include_str!("../../shaders/inspector.wgsl"));
+        let depth_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Inspector Depth Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_depth"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::R32Uint,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            depth_layout,
+            depth_pipeline,
+            requests: Vec::new(),
+            copied: Vec::new(),
+            mapping: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Inspect texel `(x, y)` of the scene's render targets, clamped to their size
+    pub fn inspect(&mut self, x: u32, y: u32) -> InspectId {
+        let id = InspectId(self.next_id);
+        self.next_id += 1;
+        self.requests.push((id, x, y));
+        id
+    }
+
+    /// Inspections not yet answered, including those waiting for the next frame
+    pub fn pending(&self) -> usize {
+        self.requests.len() + self.copied.len() + self.mapping.len()
+    }
+
+    /// Whether the next frame copies texels, and so needs the object ID target drawn
+    pub(crate) fn has_requests(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    /// Copy the texels of the waiting inspections, once the scene pass has drawn into `targets`
+    pub(crate) fn copy(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: &InspectTargets,
+        view_proj: Mat4,
+    ) {
+        let size = (targets.color.width(), targets.color.height());
+        let requests = std::mem::take(&mut self.requests);
+        for (id, x, y) in requests {
+            let texel = (x.min(size.0 - 1), y.min(size.1 - 1));
+            let depth = self.load_depth(device, encoder, targets.depth, texel);
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Inspector Readback Buffer"),
+                size: READBACK_SIZE as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            let sources = [
+                (targets.color, COLOR_OFFSET, texel),
+                (targets.normal, NORMAL_OFFSET, texel),
+                (targets.albedo, ALBEDO_OFFSET, texel),
+                (&depth, DEPTH_OFFSET, (0, 0)),
+                (targets.ids, ID_OFFSET, texel),
+            ];
+            for (texture, offset, (x, y)) in sources {
+                encoder.copy_texture_to_buffer(
+                    wgpu::ImageCopyTexture {
+                        texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d { x, y, z: 0 },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::ImageCopyBuffer {
+                        buffer: &buffer,
+                        // A single row needs no row pitch
+                        layout: wgpu::ImageDataLayout {
+                            offset: offset as u64,
+                            bytes_per_row: None,
+                            rows_per_image: None,
+                        },
+                    },
+                    wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+                );
+            }
+            self.copied.push(Readback { id, texel, size, inverse_view_proj: view_proj.inverse(), buffer });
+        }
+    }
+
+    /// A 1x1 `R32Uint` texture holding the bits of `texel` of the depth buffer
+    fn load_depth(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+        texel: (u32, u32),
+    ) -> wgpu::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Inspector Depth Texel"),
+            size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Inspector Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[texel.0, texel.1, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Inspector Depth Bind Group"),
+            layout: &self.depth_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: uniform.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(depth) },
+            ],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Inspector Depth Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.depth_pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        texture
+    }
+
+    /// Start mapping the texels copied by the frame just submitted
+    pub(crate) fn after_submit(&mut self) {
+        for readback in self.copied.drain(..) {
+            let (sender, receiver) = mpsc::channel();
+            readback.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            self.mapping.push((readback, receiver));
+        }
+    }
+
+    /// Inspections whose readback has finished, without waiting for the GPU
+    pub fn poll(&mut self, device: &wgpu::Device) -> Vec<PixelInfo> {
+        let mut results = Vec::new();
+        if self.mapping.is_empty() {
+            return results;
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        self.mapping.retain(|(readback, receiver)| {
+            let mapped = match receiver.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return true,
+                Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+            };
+            match mapped {
+                Ok(()) => {
+                    let bytes = readback.buffer.slice(..).get_mapped_range();
+                    results.push(PixelInfo::decode(readback.id, readback.texel, readback.size, readback.inverse_view_proj, &bytes));
+                    drop(bytes);
+                    readback.buffer.unmap();
+                }
+                Err(e) => log::warn!("Inspection {} readback failed: {}", readback.id.0, e),
+            }
+            false
+        });
+        results
+    }
+}
+
+/// An IEEE half-precision float, as `Rgba16Float` texels hold them
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f16_to_f32() {
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xb800), -0.5);
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn test_decode() {
+        let view_proj = Mat4::perspective_rh(1.0, 1.0, 0.1, 100.0) * Mat4::look_at_rh(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let depth = view_proj.project_point3(Vec3::ZERO).z;
+
+        let mut bytes = vec![0u8; READBACK_SIZE];
+        let halves = |values: [u16; 4]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        // Color (2, 1, 0.5), normal +Z with reflectivity 0.5
+        bytes[COLOR_OFFSET..COLOR_OFFSET + 8].copy_from_slice(&halves([0x4000, 0x3c00, 0x3800, 0x3c00]));
+        bytes[NORMAL_OFFSET..NORMAL_OFFSET + 8].copy_from_slice(&halves([0x0000, 0x0000, 0x3c00, 0x3800]));
+        bytes[ALBEDO_OFFSET..ALBEDO_OFFSET + 4].copy_from_slice(&[255, 0, 188, 255]);
+        bytes[DEPTH_OFFSET..DEPTH_OFFSET + 4].copy_from_slice(&depth.to_le_bytes());
+        bytes[ID_OFFSET..ID_OFFSET + 4].copy_from_slice(&5u32.to_le_bytes());
+
+        // The center texel of an odd-sized target looks straight at the origin
+        let info = PixelInfo::decode(InspectId(3), (50, 50), (101, 101), view_proj.inverse(), &bytes);
+        assert_eq!(info.object, Some(ObjectId(4)));
+        assert_eq!(info.color, Vec3::new(2.0, 1.0, 0.5));
+        assert_eq!((info.normal, info.reflectivity), (Vec3::Z, 0.5));
+        assert!((info.albedo - Vec4::new(1.0, 0.0, 0.5, 1.0)).abs().max_element() < 0.01);
+        assert!(info.position.unwrap().length() < 1e-3);
+        assert!(info.to_string().contains("object   4"));
+
+        // Cleared depth is the background, and 0 in the ID target no object
+        bytes[DEPTH_OFFSET..DEPTH_OFFSET + 4].copy_from_slice(&1.0f32.to_le_bytes());
+        bytes[ID_OFFSET..ID_OFFSET + 4].copy_from_slice(&0u32.to_le_bytes());
+        let info = PixelInfo::decode(InspectId(4), (0, 0), (101, 101), view_proj.inverse(), &bytes);
+        assert_eq!((info.position, info.object), (None, None));
+        assert!(info.to_string().contains("background"));
+    }
+}
@@ -0,0 +1,399 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use crate::session::SessionObject;
+use super::{distance_to_aabb, ObjectId, Transform};
+
+/// A large environment split into volumes that are streamed in as the camera nears them, stored
+/// as TOML:
+///
+/// ```toml
+/// [[volumes]]
+/// name = "courtyard"
+/// min = [-50.0, -5.0, -50.0]
+/// max = [50.0, 30.0, 50.0]
+/// load_distance = 40.0
+/// priority = 1
+///
+/// [[volumes.objects]]
+/// model = "models/fountain.glb"
+/// position = [0.0, 0.0, 0.0]
+/// rotation = [0.0, 0.0, 0.0]
+/// scale = [1.0, 1.0, 1.0]
+/// ```
+///
+/// Model paths are relative to the level file once loaded with [`Level::load`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Level {
+    pub volumes: Vec<StreamingVolume>,
+}
+
+impl Level {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Read a level file, resolving its model paths against the file's directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read level {}", path.display()))?;
+        let mut level = Self::from_toml(&source).with_context(|| format!("Invalid level {}", path.display()))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for object in level.volumes.iter_mut().flat_map(|volume| &mut volume.objects) {
+            object.model = directory.join(&object.model);
+        }
+        Ok(level)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+}
+
+/// A box of the world and the objects placed in it. The objects are loaded once the camera is
+/// within `load_distance` of the box and unloaded again beyond `unload_distance`, so walking
+/// along the edge doesn't load and unload over and over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamingVolume {
+    pub name: String,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub load_distance: f32,
+    /// At least `load_distance`
+    pub unload_distance: f32,
+    /// Volumes with higher priority load first and stay within the memory budget longest
+    pub priority: i32,
+    pub objects: Vec<SessionObject>,
+}
+
+impl Default for StreamingVolume {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            min: [0.0; 3],
+            max: [0.0; 3],
+            load_distance: 50.0,
+            unload_distance: 60.0,
+            priority: 0,
+            objects: Vec::new(),
+        }
+    }
+}
+
+impl StreamingVolume {
+    /// Each model file the objects use, once
+    pub fn model_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = Vec::new();
+        for object in &self.objects {
+            if !paths.contains(&object.model.as_path()) {
+                paths.push(&object.model);
+            }
+        }
+        paths
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VolumeId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeState {
+    Unloaded,
+    /// Waiting for this many model files
+    Loading(usize),
+    Loaded,
+}
+
+/// A load `LevelStreaming::update` asks for: every model file of the volume, whose objects are
+/// handed back through `LevelStreaming::loaded` tagged with `generation`, so loads finishing
+/// after the volume was unloaded again can be told apart and dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeLoad {
+    pub volume: VolumeId,
+    pub generation: u32,
+    pub models: Vec<PathBuf>,
+}
+
+/// What changed in one `LevelStreaming::update`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamingChanges {
+    pub load: Vec<VolumeLoad>,
+    /// Objects of unloaded volumes, to remove from the scene
+    pub unload: Vec<ObjectId>,
+}
+
+#[derive(Debug)]
+struct VolumeEntry {
+    id: VolumeId,
+    volume: StreamingVolume,
+    state: VolumeState,
+    generation: u32,
+    objects: Vec<ObjectId>,
+    /// GPU memory of the models loaded for the volume, kept after unloading as the estimate
+    /// for its next load
+    memory: u64,
+}
+
+/// Decides which streaming volumes are resident: those near the camera, by priority and then
+/// distance, as many as `memory_budget` allows and `max_loading` at a time. It only plans; the
+/// application loads the models (`State` does so on its background loader), places the
+/// objects and reports them back with `loaded`.
+#[derive(Debug)]
+pub struct LevelStreaming {
+    volumes: Vec<VolumeEntry>,
+    next_id: u32,
+    /// Bytes of GPU memory resident volumes may take; volumes not loaded yet count with what
+    /// they took last time, so a budget is only kept from their second load on
+    pub memory_budget: Option<u64>,
+    /// Volumes loading at once
+    pub max_loading: usize,
+    /// Added to every volume's box and objects, after origin rebases
+    offset: Vec3,
+}
+
+impl Default for LevelStreaming {
+    fn default() -> Self {
+        Self { volumes: Vec::new(), next_id: 0, memory_budget: None, max_loading: 2, offset: Vec3::ZERO }
+    }
+}
+
+impl LevelStreaming {
+    pub fn add_volume(&mut self, volume: StreamingVolume) -> VolumeId {
+        let id = VolumeId(self.next_id);
+        self.next_id += 1;
+        self.volumes.push(VolumeEntry { id, volume, state: VolumeState::Unloaded, generation: 0, objects: Vec::new(), memory: 0 });
+        id
+    }
+
+    /// Add every volume of `level`
+    pub fn add_level(&mut self, level: Level) -> Vec<VolumeId> {
+        level.volumes.into_iter().map(|volume| self.add_volume(volume)).collect()
+    }
+
+    /// Forget a volume, returning its objects to remove from the scene
+    pub fn remove_volume(&mut self, id: VolumeId) -> Vec<ObjectId> {
+        let Some(index) = self.volumes.iter().position(|entry| entry.id == id) else {
+            return Vec::new();
+        };
+        self.volumes.remove(index).objects
+    }
+
+    pub fn volume(&self, id: VolumeId) -> Option<&StreamingVolume> {
+        self.entry(id).map(|entry| &entry.volume)
+    }
+
+    pub fn state(&self, id: VolumeId) -> Option<VolumeState> {
+        self.entry(id).map(|entry| entry.state)
+    }
+
+    /// Objects placed for a loaded or loading volume
+    pub fn objects(&self, id: VolumeId) -> &[ObjectId] {
+        self.entry(id).map_or(&[], |entry| entry.objects.as_slice())
+    }
+
+    /// GPU memory of the volumes loaded or loading
+    pub fn resident_memory(&self) -> u64 {
+        self.volumes.iter().filter(|entry| entry.state != VolumeState::Unloaded).map(|entry| entry.memory).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.volumes.is_empty()
+    }
+
+    /// Where an object of a volume goes in the current world, after origin rebases
+    pub fn placement(&self, object: &SessionObject) -> Transform {
+        let mut transform = object.transform();
+        transform.position += self.offset;
+        transform
+    }
+
+    /// Plan loads and unloads for the camera at `position`
+    pub fn update(&mut self, position: Vec3) -> StreamingChanges {
+        let offset = self.offset;
+        let distances: Vec<f32> = self.volumes.iter()
+            .map(|entry| distance_to_aabb(position, Vec3::from(entry.volume.min) + offset, Vec3::from(entry.volume.max) + offset))
+            .collect();
+
+        // Volumes in range, most important first, while they fit in the budget
+        let mut candidates: Vec<usize> = (0..self.volumes.len())
+            .filter(|&index| {
+                let entry = &self.volumes[index];
+                let reach = match entry.state {
+                    VolumeState::Unloaded => entry.volume.load_distance,
+                    _ => entry.volume.unload_distance.max(entry.volume.load_distance),
+                };
+                distances[index] <= reach
+            })
+            .collect();
+        candidates.sort_by(|&a, &b| {
+            self.volumes[b].volume.priority.cmp(&self.volumes[a].volume.priority)
+                .then(distances[a].total_cmp(&distances[b]))
+        });
+        let mut wanted = vec![false; self.volumes.len()];
+        let mut memory = 0;
+        for index in candidates {
+            memory += self.volumes[index].memory;
+            if self.memory_budget.is_some_and(|budget| memory > budget) {
+                break;
+            }
+            wanted[index] = true;
+        }
+
+        let mut changes = StreamingChanges::default();
+        for (index, entry) in self.volumes.iter_mut().enumerate() {
+            if !wanted[index] && entry.state != VolumeState::Unloaded {
+                log::debug!("Unloading streaming volume '{}'", entry.volume.name);
+                entry.state = VolumeState::Unloaded;
+                entry.generation += 1;
+                changes.unload.append(&mut entry.objects);
+            }
+        }
+        let mut loading = self.volumes.iter().filter(|entry| matches!(entry.state, VolumeState::Loading(_))).count();
+        let mut order: Vec<usize> = (0..self.volumes.len()).filter(|&index| wanted[index]).collect();
+        order.sort_by(|&a, &b| {
+            self.volumes[b].volume.priority.cmp(&self.volumes[a].volume.priority)
+                .then(distances[a].total_cmp(&distances[b]))
+        });
+        for index in order {
+            let entry = &mut self.volumes[index];
+            if entry.state != VolumeState::Unloaded {
+                continue;
+            }
+            if loading >= self.max_loading {
+                break;
+            }
+            let models: Vec<PathBuf> = entry.volume.model_paths().into_iter().map(Path::to_path_buf).collect();
+            log::debug!("Loading streaming volume '{}' ({} models)", entry.volume.name, models.len());
+            entry.memory = 0;
+            if models.is_empty() {
+                entry.state = VolumeState::Loaded;
+                continue;
+            }
+            entry.state = VolumeState::Loading(models.len());
+            loading += 1;
+            changes.load.push(VolumeLoad { volume: entry.id, generation: entry.generation, models });
+        }
+        changes
+    }
+
+    /// Report one model file of a `VolumeLoad` done, with the objects placed for it and the
+    /// model's GPU memory. Returns false when the volume was unloaded or removed meanwhile; the
+    /// objects then have to be removed again. Failed loads are reported with no objects.
+    pub fn loaded(&mut self, volume: VolumeId, generation: u32, objects: Vec<ObjectId>, memory: u64) -> bool {
+        let Some(entry) = self.volumes.iter_mut().find(|entry| entry.id == volume) else {
+            return false;
+        };
+        let VolumeState::Loading(remaining) = entry.state else {
+            return false;
+        };
+        if entry.generation != generation {
+            return false;
+        }
+        entry.objects.extend(objects);
+        entry.memory += memory;
+        entry.state = match remaining {
+            1 => VolumeState::Loaded,
+            remaining => VolumeState::Loading(remaining - 1),
+        };
+        true
+    }
+
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        self.offset += offset;
+    }
+
+    fn entry(&self, id: VolumeId) -> Option<&VolumeEntry> {
+        self.volumes.iter().find(|entry| entry.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(name: &str, x: f32, priority: i32, models: &[&str]) -> StreamingVolume {
+        StreamingVolume {
+            name: name.to_string(),
+            min: [x - 5.0, 0.0, -5.0],
+            max: [x + 5.0, 5.0, 5.0],
+            load_distance: 10.0,
+            unload_distance: 20.0,
+            priority,
+            objects: models.iter().map(|model| SessionObject::new(*model, &Transform::new(), true)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_volume_streaming() {
+        let mut streaming = LevelStreaming::default();
+        let near = streaming.add_volume(volume("near", 0.0, 0, &["a.glb", "b.glb", "a.glb"]));
+        let far = streaming.add_volume(volume("far", 100.0, 0, &["c.glb"]));
+
+        let changes = streaming.update(Vec3::ZERO);
+        assert_eq!(changes.load.len(), 1);
+        assert_eq!(changes.load[0].models, vec![PathBuf::from("a.glb"), PathBuf::from("b.glb")]);
+        assert_eq!(streaming.state(near), Some(VolumeState::Loading(2)));
+        assert_eq!(streaming.state(far), Some(VolumeState::Unloaded));
+        let generation = changes.load[0].generation;
+        assert!(streaming.loaded(near, generation, vec![ObjectId(1), ObjectId(2)], 100));
+        assert!(streaming.loaded(near, generation, vec![ObjectId(3)], 50));
+        assert_eq!(streaming.state(near), Some(VolumeState::Loaded));
+        assert_eq!(streaming.resident_memory(), 150);
+        assert!(streaming.update(Vec3::ZERO).load.is_empty());
+
+        // Stays loaded between the load and unload distances
+        assert!(streaming.update(Vec3::new(18.0, 0.0, 0.0)).unload.is_empty());
+        let changes = streaming.update(Vec3::new(100.0, 0.0, 0.0));
+        assert_eq!(changes.unload, vec![ObjectId(1), ObjectId(2), ObjectId(3)]);
+        assert_eq!(changes.load[0].volume, far);
+
+        // Loads finishing after their volume went away are refused
+        let changes = streaming.update(Vec3::ZERO);
+        let stale = changes.load.iter().find(|load| load.volume == near).unwrap().generation;
+        streaming.update(Vec3::new(200.0, 0.0, 0.0));
+        assert!(!streaming.loaded(near, stale, vec![ObjectId(4)], 100));
+    }
+
+    #[test]
+    fn test_streaming_budget() {
+        let mut streaming = LevelStreaming { memory_budget: Some(100), max_loading: 1, ..LevelStreaming::default() };
+        let low = streaming.add_volume(volume("low", 0.0, 0, &["a.glb"]));
+        let high = streaming.add_volume(volume("high", 8.0, 5, &["b.glb"]));
+
+        // One load at a time, the higher priority first
+        let changes = streaming.update(Vec3::ZERO);
+        assert_eq!(changes.load.len(), 1);
+        assert_eq!(changes.load[0].volume, high);
+        assert!(streaming.loaded(high, changes.load[0].generation, vec![ObjectId(1)], 80));
+        let changes = streaming.update(Vec3::ZERO);
+        assert!(streaming.loaded(low, changes.load[0].generation, vec![ObjectId(2)], 80));
+
+        // Now the sizes are known, only the higher priority volume fits the budget
+        let changes = streaming.update(Vec3::ZERO);
+        assert_eq!(changes.unload, vec![ObjectId(2)]);
+        assert_eq!(streaming.state(low), Some(VolumeState::Unloaded));
+        assert_eq!(streaming.state(high), Some(VolumeState::Loaded));
+        assert!(streaming.update(Vec3::ZERO).load.is_empty());
+
+        // Origin rebases move the volumes with the world
+        streaming.translate(Vec3::new(-50.0, 0.0, 0.0));
+        assert_eq!(streaming.update(Vec3::ZERO).unload, vec![ObjectId(1)]);
+        assert_eq!(streaming.placement(&streaming.volume(high).unwrap().objects[0]).position, Vec3::new(-50.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_level_toml() {
+        let level = Level { volumes: vec![volume("hall", 0.0, 2, &["hall.glb"])] };
+        assert_eq!(Level::from_toml(&level.to_toml().unwrap()).unwrap(), level);
+        let level = Level::from_toml("[[volumes]]\nname = \"empty\"").unwrap();
+        assert_eq!(level.volumes[0].load_distance, 50.0);
+    }
+}
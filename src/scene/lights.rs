@@ -0,0 +1,275 @@
+use glam::Vec3;
+use crate::color::Color;
+use crate::settings::LightSettings;
+
+/// Point lights shaded per frame; less important lights are dropped
+pub const MAX_POINT_LIGHTS: usize = 16;
+/// Point light shadow maps that can be resident at once
+pub const MAX_SHADOWED_LIGHTS: usize = 4;
+/// LOD levels with shadows; level `n` renders at `1 / 2^n` resolution every `2^n` frames
+const SHADOW_LOD_LEVELS: u32 = 3;
+
+/// Stable identifier of a scene light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LightId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Color,
+    pub intensity: f32,
+    /// Distance at which the light's contribution reaches zero
+    pub range: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            color: Color::WHITE,
+            intensity: 1.0,
+            range: 10.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+impl PointLight {
+    /// Rough screen contribution as seen from `eye`, used to rank lights
+    pub fn importance(&self, eye: Vec3) -> f32 {
+        let distance_squared = self.position.distance_squared(eye).max(1.0);
+        self.intensity * self.color.rgb().max_element() * self.range * self.range / distance_squared
+    }
+
+    /// Shadow LOD level at `distance`: 0 within `lod_distance` ranges, then one more per doubling
+    fn lod(&self, distance: f32, lod_distance: f32) -> u32 {
+        let ratio = distance / (self.range * lod_distance).max(f32::EPSILON);
+        if ratio < 1.0 {
+            0
+        } else {
+            ratio.log2() as u32 + 1
+        }
+    }
+}
+
+pub struct SceneLight {
+    pub id: LightId,
+    pub light: PointLight,
+}
+
+/// Where a light's shadow lives in the point shadow atlas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSlot {
+    pub index: usize,
+    pub lod: u32,
+}
+
+impl ShadowSlot {
+    /// Fraction of the slot's faces covered by the shadow at this LOD
+    pub fn scale(&self) -> f32 {
+        1.0 / (1u32 << self.lod) as f32
+    }
+}
+
+/// A light chosen for shading this frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlannedLight {
+    pub id: LightId,
+    pub light: PointLight,
+    /// Set once the slot holds a rendered shadow map
+    pub shadow: Option<ShadowSlot>,
+}
+
+/// Lights to shade and shadow maps to re-render this frame
+#[derive(Debug, Clone, Default)]
+pub struct LightPlan {
+    /// Most important first, at most `MAX_POINT_LIGHTS`
+    pub lights: Vec<PlannedLight>,
+    /// Indices into `lights` whose shadow maps are rendered this frame
+    pub shadow_updates: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SlotState {
+    light: LightId,
+    lod: u32,
+    last_update: Option<u64>,
+}
+
+/// Decides each frame which point lights are shaded, which get shadow maps at what resolution,
+/// and which shadow maps are re-rendered, within the budgets in [`LightSettings`].
+///
+/// Shadow maps persist between frames: distant lights are re-rendered every few frames and keep
+/// their slot while they stay within budget.
+#[derive(Debug, Default)]
+pub struct LightManager {
+    slots: [Option<SlotState>; MAX_SHADOWED_LIGHTS],
+    frame: u64,
+}
+
+impl LightManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget every rendered shadow map, e.g. after the atlas was reallocated
+    pub fn invalidate(&mut self) {
+        for state in self.slots.iter_mut().flatten() {
+            state.last_update = None;
+        }
+    }
+
+    /// `max_distance` is how far from `eye` lit surfaces can be seen, usually the camera's far plane
+    pub fn plan(&mut self, lights: &[SceneLight], eye: Vec3, max_distance: f32, settings: &LightSettings) -> LightPlan {
+        self.frame += 1;
+
+        let mut ranked: Vec<(f32, &SceneLight)> = lights.iter()
+            .filter(|entry| entry.light.position.distance(eye) - entry.light.range < max_distance)
+            .map(|entry| (entry.light.importance(eye), entry))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        ranked.truncate(MAX_POINT_LIGHTS);
+
+        // The most important shadow casters close enough for a shadow LOD get slots
+        let budget = (settings.shadow_budget as usize).min(MAX_SHADOWED_LIGHTS);
+        let candidates: Vec<(usize, u32)> = ranked.iter().enumerate()
+            .filter(|(_, (_, entry))| entry.light.casts_shadows)
+            .map(|(index, (_, entry))| (index, entry.light.lod(entry.light.position.distance(eye), settings.lod_distance)))
+            .filter(|(_, lod)| *lod < SHADOW_LOD_LEVELS)
+            .take(budget)
+            .collect();
+
+        for slot in &mut self.slots {
+            let kept = slot.is_some_and(|state| candidates.iter().any(|(index, _)| ranked[*index].1.id == state.light));
+            if !kept {
+                *slot = None;
+            }
+        }
+
+        let mut assigned = Vec::with_capacity(candidates.len());
+        for (index, lod) in candidates {
+            let id = ranked[index].1.id;
+            let slot = match self.slots.iter().position(|slot| slot.is_some_and(|state| state.light == id)) {
+                Some(slot) => slot,
+                None => {
+                    // Always available: at most `budget` candidates and stale slots were freed
+                    let slot = self.slots.iter().position(Option::is_none).unwrap_or(0);
+                    self.slots[slot] = Some(SlotState { light: id, lod, last_update: None });
+                    slot
+                }
+            };
+            if let Some(state) = &mut self.slots[slot] {
+                if state.lod != lod {
+                    // A new resolution invalidates the map
+                    state.lod = lod;
+                    state.last_update = None;
+                }
+            }
+            assigned.push((index, slot));
+        }
+
+        // Never-rendered maps first, then the longest overdue so no light starves, then by importance
+        let frame = self.frame;
+        let mut due: Vec<(usize, usize)> = assigned.iter().copied()
+            .filter(|(_, slot)| {
+                self.slots[*slot].is_some_and(|state| match state.last_update {
+                    Some(last) => frame - last >= 1 << state.lod,
+                    None => true,
+                })
+            })
+            .collect();
+        due.sort_by_key(|(index, slot)| {
+            let overdue = self.slots[*slot]
+                .and_then(|state| state.last_update.map(|last| frame - last - (1 << state.lod)));
+            (overdue.is_some(), std::cmp::Reverse(overdue), *index)
+        });
+        due.truncate(settings.shadow_updates_per_frame as usize);
+
+        let mut plan = LightPlan::default();
+        for (_, entry) in &ranked {
+            plan.lights.push(PlannedLight { id: entry.id, light: entry.light, shadow: None });
+        }
+        for (index, slot) in due {
+            if let Some(state) = &mut self.slots[slot] {
+                state.last_update = Some(frame);
+            }
+            plan.shadow_updates.push(index);
+        }
+        for (index, slot) in assigned {
+            if let Some(state) = self.slots[slot].filter(|state| state.last_update.is_some()) {
+                plan.lights[index].shadow = Some(ShadowSlot { index: slot, lod: state.lod });
+            }
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(id: u32, x: f32) -> SceneLight {
+        SceneLight {
+            id: LightId(id),
+            light: PointLight { position: Vec3::new(x, 0.0, 0.0), range: 5.0, ..PointLight::default() },
+        }
+    }
+
+    #[test]
+    fn test_light_lod_levels() {
+        let light = PointLight { range: 5.0, ..PointLight::default() };
+        assert_eq!(light.lod(4.0, 2.0), 0);
+        assert_eq!(light.lod(15.0, 2.0), 1);
+        assert_eq!(light.lod(30.0, 2.0), 2);
+        assert_eq!(light.lod(90.0, 2.0), 4);
+    }
+
+    #[test]
+    fn test_shadow_budget() {
+        let settings = LightSettings { shadow_budget: 2, shadow_updates_per_frame: 8, lod_distance: 2.0 };
+        let mut manager = LightManager::new();
+        let lights = [light(0, 30.0), light(1, 1.0), light(2, 12.0), light(3, 500.0)];
+
+        // The far light is culled; the two nearest get shadows, full and half resolution
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        let ids: Vec<_> = plan.lights.iter().map(|planned| planned.id.0).collect();
+        assert_eq!(ids, vec![1, 2, 0]);
+        assert_eq!(plan.shadow_updates, vec![0, 1]);
+        assert_eq!(plan.lights[0].shadow.unwrap().lod, 0);
+        assert_eq!(plan.lights[1].shadow.unwrap().scale(), 0.5);
+        assert_eq!(plan.lights[2].shadow, None);
+
+        // Next frame only the full-resolution map is due; both keep their slots
+        let slots: Vec<_> = plan.lights.iter().map(|planned| planned.shadow).collect();
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        assert_eq!(plan.shadow_updates, vec![0]);
+        let next: Vec<_> = plan.lights.iter().map(|planned| planned.shadow).collect();
+        assert_eq!(next, slots);
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        assert_eq!(plan.shadow_updates, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_shadow_update_budget() {
+        let settings = LightSettings { shadow_budget: 3, shadow_updates_per_frame: 1, lod_distance: 2.0 };
+        let mut manager = LightManager::new();
+        let lights = [light(0, 1.0), light(1, 2.0), light(2, 3.0)];
+
+        // One map per frame; lights wait unshadowed until their first render
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        assert_eq!(plan.shadow_updates, vec![0]);
+        assert!(plan.lights[1].shadow.is_none());
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        assert_eq!(plan.shadow_updates, vec![1]);
+        let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+        assert_eq!(plan.shadow_updates, vec![2]);
+        assert!(plan.lights.iter().all(|planned| planned.shadow.is_some()));
+
+        // With every map rendered, updates rotate through the longest overdue
+        for expected in [0, 1, 2, 0] {
+            let plan = manager.plan(&lights, Vec3::ZERO, 100.0, &settings);
+            assert_eq!(plan.shadow_updates, vec![expected]);
+        }
+    }
+}
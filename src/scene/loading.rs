@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use crate::model::{BackgroundLoader, ImportOptions, LoadedModel, MeshData, ModelHandle, ModelVertex, StreamedTexture};
+use super::{ObjectId, Transform};
+
+/// A model file requested from `AssetLoader`, resolved to its `ModelHandle` once uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LoadHandle(u64);
+
+/// How far a load got
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadState {
+    /// Parsing on the worker thread, or waiting for its upload
+    Loading,
+    /// Uploaded and in `Scene::assets`, which holds its own reference as with `add_model`
+    Ready(ModelHandle),
+    Failed(String),
+}
+
+/// GPU work for the thread owning the device, queued as the worker finishes loads
+pub(super) enum Upload {
+    Model(LoadHandle, LoadedModel),
+    Texture(LoadHandle, StreamedTexture),
+}
+
+/// An object waiting for a load
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Waiting {
+    /// In the scene, showing the placeholder model
+    Placeholder(ObjectId),
+    /// Its id handed out, added once the model is ready; without a placeholder
+    Reserved(ObjectId, Transform),
+}
+
+/// Loads model files without stalling frames: a `BackgroundLoader` parses and decodes them on
+/// a worker thread, and `Scene::upload_loads` works through the uploads they leave queued, a
+/// few per frame, on the thread owning the device. `State` loads every file through here. Objects placed with
+/// `Scene::add_object_loading` show the placeholder model until theirs is ready.
+pub struct AssetLoader {
+    /// Started with the first request, so scenes that never load files run no worker
+    loader: Option<BackgroundLoader>,
+    pub(super) uploads: VecDeque<Upload>,
+    states: BTreeMap<LoadHandle, LoadState>,
+    pub(super) waiting: BTreeMap<LoadHandle, Vec<Waiting>>,
+    /// Models of finished loads with textures still to come, and how many
+    pub(super) streaming: BTreeMap<LoadHandle, (ModelHandle, usize)>,
+    placeholder: Option<ModelHandle>,
+    /// Uploads `Scene::upload_loads` does per call; a model and each streamed texture count one
+    pub uploads_per_frame: usize,
+}
+
+impl Default for AssetLoader {
+    fn default() -> Self {
+        Self {
+            loader: None,
+            uploads: VecDeque::new(),
+            states: BTreeMap::new(),
+            waiting: BTreeMap::new(),
+            streaming: BTreeMap::new(),
+            placeholder: None,
+            uploads_per_frame: 2,
+        }
+    }
+}
+
+impl AssetLoader {
+    /// Start loading `path` on the worker thread
+    pub fn request(&mut self, path: impl Into<PathBuf>, options: ImportOptions) -> LoadHandle {
+        let handle = LoadHandle(self.loader.get_or_insert_with(BackgroundLoader::new).request_with(path, options));
+        self.states.insert(handle, LoadState::Loading);
+        handle
+    }
+
+    pub fn state(&self, handle: LoadHandle) -> Option<&LoadState> {
+        self.states.get(&handle)
+    }
+
+    /// The loaded model, once uploaded
+    pub fn model(&self, handle: LoadHandle) -> Option<ModelHandle> {
+        match self.states.get(&handle)? {
+            LoadState::Ready(model) => Some(*model),
+            _ => None,
+        }
+    }
+
+    /// Loads still parsing, or with a model or textures still to upload
+    pub fn pending(&self) -> usize {
+        self.states.values().filter(|state| **state == LoadState::Loading).count() + self.streaming.len()
+    }
+
+    /// Drop what's known about a finished load; the handle resolves to nothing afterwards
+    pub fn forget(&mut self, handle: LoadHandle) {
+        if self.states.get(&handle).is_some_and(|state| *state != LoadState::Loading) {
+            self.states.remove(&handle);
+        }
+    }
+
+    /// Model shown by objects waiting for a load, e.g. a model of `placeholder_mesh`. Scenes
+    /// without one add those objects once their model is ready instead.
+    pub fn set_placeholder(&mut self, model: Option<ModelHandle>) {
+        self.placeholder = model;
+    }
+
+    pub fn placeholder(&self) -> Option<ModelHandle> {
+        self.placeholder
+    }
+
+    /// Queue the uploads of loads and textures the worker has finished since the last call
+    pub(super) fn poll(&mut self) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        for loaded in loader.poll() {
+            self.uploads.push_back(Upload::Model(LoadHandle(loaded.id), loaded));
+        }
+        // Polled after the models, so each texture is queued behind its model
+        for (id, texture) in loader.poll_textures() {
+            self.uploads.push_back(Upload::Texture(LoadHandle(id), texture));
+        }
+    }
+
+    /// Stop waiting on behalf of a removed object; true if it was only reserved, not yet added
+    pub(super) fn cancel(&mut self, id: ObjectId) -> bool {
+        let mut reserved = false;
+        for objects in self.waiting.values_mut() {
+            objects.retain(|waiting| match *waiting {
+                Waiting::Placeholder(object) => object != id,
+                Waiting::Reserved(object, _) => {
+                    reserved |= object == id;
+                    object != id
+                }
+            });
+        }
+        reserved
+    }
+
+    pub(super) fn finish(&mut self, handle: LoadHandle, state: LoadState) {
+        self.states.insert(handle, state);
+    }
+}
+
+/// A grey half-meter box standing on its origin, the usual placeholder for loading models
+pub fn placeholder_mesh() -> MeshData {
+    const GREY: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for axis in 0..3 {
+        for sign in [-1.0f32, 1.0] {
+            let mut normal = [0.0; 3];
+            normal[axis] = sign;
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let base = vertices.len() as u32;
+            for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let mut position = [0.0; 3];
+                position[axis] = sign * 0.25;
+                position[u] = a * 0.25;
+                position[v] = b * 0.25;
+                position[1] += 0.25;
+                let mut tangent = [0.0, 0.0, 0.0, 1.0];
+                tangent[u] = 1.0;
+                vertices.push(ModelVertex { position, tex_coords: [(a + 1.0) * 0.5, (b + 1.0) * 0.5], normal, tangent, color: GREY });
+            }
+            // u x v is the normal along +axis, so the negative faces wind the other way
+            let quad = if sign > 0.0 { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+            indices.extend(quad.map(|index| base + index));
+        }
+    }
+    MeshData { name: "placeholder".to_string(), vertices, indices, material_index: 0, skin: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn test_placeholder_mesh() {
+        let mesh = placeholder_mesh();
+        assert_eq!((mesh.vertices.len(), mesh.indices.len()), (24, 36));
+        // Every triangle faces out along its normal
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(mesh.vertices[triangle[i] as usize].position));
+            let normal = Vec3::from(mesh.vertices[triangle[0] as usize].normal);
+            assert!((b - a).cross(c - a).dot(normal) > 0.0);
+        }
+        let lowest = mesh.vertices.iter().map(|vertex| vertex.position[1]).fold(f32::INFINITY, f32::min);
+        assert_eq!(lowest, 0.0);
+    }
+
+    #[test]
+    fn test_unknown_loads() {
+        let mut loading = AssetLoader::default();
+        assert_eq!(loading.state(LoadHandle(3)), None);
+        loading.poll();
+        assert!(loading.uploads.is_empty());
+        assert!(loading.loader.is_none());
+    }
+}
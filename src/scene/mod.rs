@@ -1,16 +1,101 @@
 mod renderer;
+mod msaa;
+mod variants;
+pub mod anchor;
+pub mod avatar;
+pub mod bvh;
+pub mod capabilities;
+pub mod clipping;
+pub mod compute;
+pub mod eye_debug;
+pub mod frustum;
+pub mod grid;
+pub mod hierarchy;
+pub mod inspector;
+pub mod level;
+pub mod lights;
+pub mod loading;
+pub mod objects;
+pub mod occlusion;
+pub mod origin;
+pub mod overlay;
+pub mod picking;
+pub mod placement;
+pub mod portals;
+#[cfg(feature = "f64-transforms")]
+pub mod precise;
+pub mod prefab;
+pub mod probes;
+pub mod post;
+pub mod profiler;
+pub mod ray;
+pub mod scatter;
+pub mod sequencer;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod shadow;
+pub mod skinning;
+pub mod spectator;
+pub mod stencil;
+pub mod transparency;
+pub mod tween;
+pub mod viewport;
+pub mod visibility;
 #[cfg(test)]
 mod tests;
 
-pub use renderer::Renderer;
+pub use renderer::{RenderView, Renderer};
+pub use variants::ShaderFeatures;
+pub use anchor::{Anchor, AnchorPoses, AnchorTarget, Anchors};
+pub use avatar::{Avatar, AvatarCalibration, AvatarParts, AvatarPose};
+pub use bvh::{distance_to_aabb, Bvh, BvhItem};
+pub use capabilities::{Capabilities, Downgrade, FormatSupport, LimitLoss, QualityTier};
+pub use clipping::{ClipPlane, Clipping};
+pub use compute::{ComputeBinding, ComputeStage, ComputeTask, ComputeTaskId};
+pub use eye_debug::{EyeDebugLayout, EyeDebugMode, EyeDebugPanel, EyeDebugPass};
+pub use frustum::{Frustum, ViewCulling};
+pub use grid::{GridPass, GridSettings};
+pub use hierarchy::Hierarchy;
+pub use inspector::{InspectId, PixelInfo, PixelInspector};
+pub use level::{Level, LevelStreaming, StreamingChanges, StreamingVolume, VolumeId, VolumeLoad, VolumeState};
+pub use overlay::DebugOverlay;
+pub use picking::{PickId, PickResult, PickingPass};
+pub use placement::Placement;
+pub use lights::{LightId, LightManager, PointLight, SceneLight};
+pub use loading::{placeholder_mesh, AssetLoader, LoadHandle, LoadState};
+pub use objects::{ComponentsMut, SceneObjects};
+pub use occlusion::{DepthBuffer, Occluder, OccluderId, Occluders};
+pub use origin::FloatingOrigin;
+pub use portals::{CellId, PortalGraph};
+#[cfg(feature = "f64-transforms")]
+pub use precise::{PreciseTransform, PreciseTransforms};
+pub use prefab::{Prefab, PrefabInstance, PrefabOverrides};
+pub use probes::{ProbeBakeSettings, ProbeGrid, ShIrradiance};
+pub use profiler::{FrameTiming, PassTiming, Profiler};
+pub use ray::{Ray, RayHit};
+pub use scatter::{Density, DensityMap, MeshSurface, Scatter, ScatterSurface};
+pub use sequencer::{CameraKey, ClipTrigger, LightKey, LightTrack, ObjectTrack, Sequence, SequenceEvent, Sequencer, TransformKey};
+pub use skinning::{Playback, Skinning};
+pub use spectator::{Spectator, SpectatorMode, SpectatorRecording, SpectatorSettings};
+pub use stencil::{Stencil, StencilOverlay};
+pub use transparency::Transparency;
+pub use tween::{Animation, Easing, TweenId, Tweens};
+pub use viewport::Viewport;
+pub use visibility::{AllVisible, CullingStats, FrustumVisibility, ViewInfo, Visibility};
+#[cfg(feature = "scripting")]
+pub use scripting::{AnimationRequest, ScriptHost};
+pub use post::{PostStack, SsrQuality, EnvironmentProbe, ColorGrading, ColorLut, LensDistortion, LensPresets, MotionBlur, OutputAlpha, Tonemapper};
 use glam::{Mat4, Vec3};
-use crate::model::Model;
-use winit::keyboard::KeyCode;
+use crate::color::Color;
+use crate::model::{world_matrices, AssetRegistry, LoadedModel, MaterialHandle, Model, ModelHandle, Node};
+use crate::input::{InputState, Key};
 use std::time::Instant;
 
 pub mod camera;
 use camera::Camera;
+use loading::{Upload, Waiting};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: Vec3,
@@ -32,52 +117,819 @@ impl Transform {
         let scale = Mat4::from_scale(self.scale);
         translation * rotation * scale
     }
+
+    /// Decompose an affine matrix; shear (non-uniform scale under rotation) is lost
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let (scale, rotation, position) = matrix.to_scale_rotation_translation();
+        let (x, y, z) = rotation.to_euler(glam::EulerRot::XYZ);
+        Self {
+            position,
+            rotation: Vec3::new(x, y, z),
+            scale,
+        }
+    }
 }
 
+/// Stable identifier of a scene object; stays valid while other objects are added or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(pub u32);
+
+/// A copy of one object's components; change them through `Scene` or the component arrays
+/// of [`SceneObjects`]
+#[derive(Debug, Clone, Copy)]
 pub struct SceneObject {
-    pub model: Model,
+    pub id: ObjectId,
+    // Handles are reference counted, so they only change through `Scene`
+    model: ModelHandle,
+    material: Option<MaterialHandle>,
     pub transform: Transform,
+    /// Hidden objects are skipped by every render pass
+    pub visible: bool,
+}
+
+impl SceneObject {
+    pub fn model(&self) -> ModelHandle {
+        self.model
+    }
+
+    /// Replaces the material of every mesh in the model when set
+    pub fn material(&self) -> Option<MaterialHandle> {
+        self.material
+    }
+
+    /// World-space axis-aligned bounds of the transformed model, or `None` if the model isn't in `assets`
+    pub fn world_bounds(&self, assets: &AssetRegistry) -> Option<(Vec3, Vec3)> {
+        self.bounds_at(assets, &self.transform)
+    }
+
+    /// World-space bounds the object would have with `transform`
+    pub fn bounds_at(&self, assets: &AssetRegistry, transform: &Transform) -> Option<(Vec3, Vec3)> {
+        let model = assets.model(self.model)?;
+        let matrix = transform.to_matrix();
+        let min = Vec3::from(model.bounds_min);
+        let max = Vec3::from(model.bounds_max);
+        let mut bounds = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            let world = matrix.transform_point3(corner);
+            bounds = (bounds.0.min(world), bounds.1.max(world));
+        }
+        Some(bounds)
+    }
+}
+
+/// The camera riding along with a scene object; see `Scene::attach_camera`
+#[derive(Debug, Clone, Copy)]
+struct CameraRig {
+    parent: ObjectId,
+    follow_rotation: bool,
+    /// The parent's world matrix when the camera last followed it
+    last: Mat4,
+}
+
+/// `Scene::bvh` and the object revisions it was made at
+#[derive(Debug, Clone)]
+struct BvhCache {
+    bvh: Bvh,
+    revision: u64,
+    layout_revision: u64,
 }
 
 pub struct Scene {
     pub camera: Camera,
-    pub objects: Vec<(Model, Transform)>,
+    pub objects: SceneObjects,
+    /// Point lights, in addition to the directional light
+    pub lights: Vec<SceneLight>,
+    /// GPU resources referenced by `objects`
+    pub assets: AssetRegistry,
+    pub origin: FloatingOrigin,
+    /// Interior visibility; empty graphs cull nothing
+    pub portals: PortalGraph,
+    /// Section cuts; no planes draws everything
+    pub clipping: Clipping,
+    /// Per-object stencil marks and the overlays they mask
+    pub stencil: Stencil,
+    /// How transparent materials are blended
+    pub transparency: Transparency,
+    /// Transform animations, advanced by `update`
+    pub tweens: Tweens,
+    /// Objects following the viewer's head or controllers, like UI panels
+    pub anchors: Anchors,
+    /// Body parts posed from the viewer's head and controllers, for embodied VR
+    pub avatar: Avatar,
+    /// A smoothed third view of the VR viewer for onlookers, off by default
+    pub spectator: Spectator,
+    /// Objects carried along by a parent object; see `set_parent`
+    pub hierarchy: Hierarchy,
+    /// Skeletal animation of skinned models; see `play_animation`
+    pub skinning: Skinning,
+    /// Authored camera, object and light timelines for scripted demos
+    pub sequencer: Sequencer,
+    /// Volumes of a large level loaded and unloaded around the camera
+    pub streaming: LevelStreaming,
+    /// Simplified geometry hiding the objects behind it from the default culling
+    pub occluders: Occluders,
+    /// Model files loading in the background; see `add_object_loading`
+    pub loading: AssetLoader,
+    /// Objects positioned in absolute f64 coordinates; see `set_precise_transform`
+    #[cfg(feature = "f64-transforms")]
+    pub precise: PreciseTransforms,
     pub light_direction: Vec3,
-    pub directional_light: Vec3,
-    pub ambient_light: Vec3,
+    pub directional_light: Color,
+    pub ambient_light: Color,
+    /// Background behind everything drawn, ignored by transparent windows
+    pub clear_color: Color,
+    /// Baked ambient light for the objects inside it, in place of `ambient_light`
+    pub probes: Option<ProbeGrid>,
+    camera_rig: Option<CameraRig>,
+    bvh: Option<BvhCache>,
+    next_object_id: u32,
+    next_light_id: u32,
     last_update: Instant,
+    frame_time: f32,
+    time: f32,
+    paused: bool,
+    /// Single updates queued while paused
+    pending_steps: u32,
+    advanced: bool,
 }
 
 impl Scene {
     pub fn new(camera: Camera) -> Self {
         Self {
             camera,
-            objects: Vec::new(),
+            objects: SceneObjects::default(),
+            lights: Vec::new(),
+            assets: AssetRegistry::new(),
+            origin: FloatingOrigin::default(),
+            portals: PortalGraph::new(),
+            clipping: Clipping::default(),
+            stencil: Stencil::default(),
+            transparency: Transparency::default(),
+            tweens: Tweens::default(),
+            anchors: Anchors::default(),
+            avatar: Avatar::default(),
+            spectator: Spectator::default(),
+            hierarchy: Hierarchy::default(),
+            skinning: Skinning::default(),
+            sequencer: Sequencer::default(),
+            streaming: LevelStreaming::default(),
+            occluders: Occluders::default(),
+            loading: AssetLoader::default(),
+            #[cfg(feature = "f64-transforms")]
+            precise: PreciseTransforms::default(),
             light_direction: Vec3::new(-1.0, -1.0, -1.0).normalize(),
-            directional_light: Vec3::new(1.0, 1.0, 1.0),
-            ambient_light: Vec3::new(0.1, 0.1, 0.1),
+            directional_light: Color::WHITE,
+            ambient_light: Color::linear(0.1, 0.1, 0.1),
+            clear_color: Color::linear(0.1, 0.2, 0.3),
+            probes: None,
+            camera_rig: None,
+            bvh: None,
+            next_object_id: 0,
+            next_light_id: 0,
             last_update: Instant::now(),
+            frame_time: 0.0,
+            time: 0.0,
+            paused: false,
+            pending_steps: 0,
+            advanced: false,
         }
     }
 
-    pub fn update(&mut self) {
-        let now = Instant::now();
-        let dt = (now - self.last_update).as_secs_f32();
-        self.last_update = now;
+    /// Advance the camera; returns the shift if the floating origin was rebased this frame,
+    /// so systems holding their own world positions (VR stage, physics, audio) can follow
+    pub fn update(&mut self) -> Option<Vec3> {
+        let dt = self.last_update.elapsed().as_secs_f32();
+        self.step(dt)
+    }
 
+    /// `update` by exactly `dt` seconds whatever the wall clock says, for fixed-timestep and
+    /// deterministic simulation
+    pub fn step(&mut self, dt: f32) -> Option<Vec3> {
+        self.last_update = Instant::now();
+        self.frame_time = dt;
+        self.advanced = self.advancing();
+        if self.paused && self.advanced {
+            self.pending_steps -= 1;
+        }
+
+        // The camera keeps flying while paused, to look at the frozen frame from anywhere
         self.camera.update(dt);
+        if self.advanced {
+            self.time += dt;
+            self.update_tweens(dt);
+        }
+        // Runs while paused too, so scrubbing a frozen frame shows the sequence there
+        self.update_sequencer(if self.advanced { dt } else { 0.0 });
+        self.follow_camera_rig();
+        let head = self.camera.build_view_matrix().inverse();
+        self.anchors.update(dt, head, &mut self.objects);
+        self.avatar.update(self.anchors.poses(), head, &mut self.objects);
+        self.hierarchy.update(&mut self.objects);
+        // Sampled while paused too, so clips started on a frozen frame show their first pose
+        self.skinning.update(if self.advanced { dt } else { 0.0 }, &self.objects, &self.assets);
+        self.spectator.update(self.anchors.poses().head.unwrap_or(head), dt);
+
+        let shift = self.origin.rebase_shift(self.camera.position);
+        if let Some(shift) = shift {
+            self.rebase(shift);
+        }
+        #[cfg(feature = "f64-transforms")]
+        {
+            self.precise.absorb(&self.objects);
+            self.precise.apply(&self.origin, &mut self.objects);
+        }
+        self.update_bvh();
+        shift
+    }
+
+    /// Advance animations, then run the completion callbacks of those that finished; tweens
+    /// the callbacks start begin next frame
+    fn update_tweens(&mut self, dt: f32) {
+        for on_complete in self.tweens.advance(dt, &mut self.objects) {
+            on_complete(self);
+        }
+    }
+
+    fn update_sequencer(&mut self, dt: f32) {
+        let Some(frame) = self.sequencer.advance(dt) else {
+            return;
+        };
+        if let Some(pose) = frame.camera {
+            if self.sequencer.drive_camera_rotation {
+                pose.apply(&mut self.camera);
+            } else {
+                self.camera.position = Vec3::from(pose.position);
+            }
+        }
+        for (id, transform) in frame.objects {
+            if let Some(target) = self.transform_mut(id) {
+                *target = transform;
+            }
+        }
+        for (id, key) in frame.lights {
+            if let Some(light) = self.light_mut(id) {
+                light.color = key.color;
+                light.intensity = key.intensity;
+                light.range = key.range;
+            }
+        }
+        for (id, clip, looping) in frame.clips {
+            if !self.play_animation(id, &clip, looping) {
+                log::warn!("Sequence clip '{}' not found on object {:?}", clip, id);
+            }
+        }
+    }
+
+    /// Attach the camera to `parent` so it's carried along as the object moves (and turns, with
+    /// `follow_rotation`): vehicle rides, elevators, cutscene rigs driven by tweens. The camera
+    /// keeps its own movement on top, like walking around inside the elevator. Returns false
+    /// for unknown ids.
+    pub fn attach_camera(&mut self, parent: ObjectId, follow_rotation: bool) -> bool {
+        let Some(last) = self.world_matrix(parent) else {
+            return false;
+        };
+        self.camera_rig = Some(CameraRig { parent, follow_rotation, last });
+        true
+    }
+
+    /// Leave the camera where it is, no longer following its parent
+    pub fn detach_camera(&mut self) {
+        self.camera_rig = None;
+    }
+
+    /// The object the camera is attached to
+    pub fn camera_parent(&self) -> Option<ObjectId> {
+        self.camera_rig.map(|rig| rig.parent)
+    }
+
+    /// An object's world matrix, e.g. for `VRSystem::set_stage_parent`
+    pub fn world_matrix(&self, id: ObjectId) -> Option<Mat4> {
+        self.object(id).map(|object| object.transform.to_matrix())
+    }
+
+    /// Make `child` follow `parent` from where it is now, or detach it with `None`, leaving it
+    /// in place. Returns false, changing nothing, for unknown ids or when `child` would become
+    /// its own ancestor.
+    pub fn set_parent(&mut self, child: ObjectId, parent: Option<ObjectId>) -> bool {
+        let Some(child_matrix) = self.world_matrix(child) else {
+            return false;
+        };
+        let Some(parent) = parent else {
+            self.hierarchy.detach(child);
+            return true;
+        };
+        let Some(parent_matrix) = self.world_matrix(parent) else {
+            return false;
+        };
+        self.hierarchy.attach(child, parent, parent_matrix.inverse() * child_matrix)
+    }
+
+    /// Play the animation clip called `name` on an object whose model has a skeleton, from the
+    /// start; control it further through `skinning`. Returns false for unknown ids, models
+    /// without a skeleton and unknown clips.
+    pub fn play_animation(&mut self, id: ObjectId, name: &str, looping: bool) -> bool {
+        let clip = self.objects.index_of(id)
+            .and_then(|index| self.objects.get(index))
+            .and_then(|object| self.assets.model(object.model))
+            .and_then(|model| model.skeleton.as_ref())
+            .and_then(|skeleton| skeleton.clip(name));
+        let Some(clip) = clip else {
+            return false;
+        };
+        self.skinning.play(id, clip, looping);
+        true
+    }
+
+    /// Place a model's node hierarchy (`ModelData::nodes`) at `root`, one object per node with
+    /// a model in `models`, which holds each node's model (see `ModelData::split_nodes`).
+    /// Objects follow the nearest ancestor node that has one, so moving the root object moves
+    /// the whole model. Returns each node's object.
+    pub fn add_node_hierarchy(&mut self, nodes: &[Node], models: &[Option<ModelHandle>], root: &Transform) -> Vec<Option<ObjectId>> {
+        let root = root.to_matrix();
+        let matrices = world_matrices(nodes);
+        let mut objects: Vec<Option<ObjectId>> = Vec::with_capacity(nodes.len());
+        for (index, node) in nodes.iter().enumerate() {
+            let Some(model) = models.get(index).copied().flatten() else {
+                objects.push(None);
+                continue;
+            };
+            let id = self.add_object(model, Transform::from_matrix(root * matrices[index]));
+            let parent = std::iter::successors(node.parent, |&parent| nodes[parent].parent)
+                .find_map(|parent| objects[parent].map(|object| (parent, object)));
+            if let Some((parent, object)) = parent {
+                self.hierarchy.attach(id, object, matrices[parent].inverse() * matrices[index]);
+            }
+            objects.push(Some(id));
+        }
+        objects
+    }
+
+    /// Carry the camera by however much its parent moved since last frame
+    fn follow_camera_rig(&mut self) {
+        let Some(rig) = self.camera_rig else {
+            return;
+        };
+        let Some(matrix) = self.world_matrix(rig.parent) else {
+            log::debug!("The camera's parent {:?} was removed; detaching", rig.parent);
+            self.camera_rig = None;
+            return;
+        };
+        let delta = matrix * rig.last.inverse();
+        if rig.follow_rotation {
+            self.camera.position = delta.transform_point3(self.camera.position);
+            self.camera.set_view_direction(delta.transform_vector3(self.camera.get_view_direction()));
+        } else {
+            // Only the parent's pivot moving carries the camera; its turning doesn't swing it around
+            self.camera.position += matrix.w_axis.truncate() - rig.last.w_axis.truncate();
+        }
+        self.camera_rig = Some(CameraRig { last: matrix, ..rig });
+    }
+
+    /// Seconds covered by the last `update`
+    pub fn frame_time(&self) -> f32 {
+        self.frame_time
+    }
+
+    /// Seconds animations have advanced by, which shader effects like wind are driven by;
+    /// stands still while paused
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Freeze animations while the camera, and rendering, carry on; see `step_once`
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.pending_steps = 0;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// While paused, let the next update advance animations by one frame; steps queued in a
+    /// row run one per update. Does nothing when not paused.
+    pub fn step_once(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+        }
+    }
+
+    /// Whether the next update advances animations: not paused, or a step is queued
+    pub fn advancing(&self) -> bool {
+        !self.paused || self.pending_steps > 0
+    }
+
+    /// Whether the last update advanced animations, so systems driven alongside the scene
+    /// (scripts, particles) can pause and step with it
+    pub fn advanced(&self) -> bool {
+        self.advanced
+    }
+
+    /// Move the local origin by `shift`: every local position moves by `-shift`
+    /// while absolute positions stay unchanged
+    pub fn rebase(&mut self, shift: Vec3) {
+        #[cfg(feature = "f64-transforms")]
+        self.precise.absorb(&self.objects);
+        self.camera.position -= shift;
+        self.objects.par_update_transforms(|_, transform| transform.position -= shift);
+        for entry in &mut self.lights {
+            entry.light.position -= shift;
+        }
+        self.portals.translate(-shift);
+        self.clipping.translate(-shift);
+        self.tweens.translate(-shift);
+        self.anchors.translate(-shift);
+        self.spectator.translate(-shift);
+        self.sequencer.translate(-shift);
+        self.streaming.translate(-shift);
+        self.occluders.translate(-shift);
+        if let Some(probes) = &mut self.probes {
+            probes.translate(-shift);
+        }
+        // The parent moved with everything else, which mustn't carry the camera a second time
+        if let Some(rig) = &mut self.camera_rig {
+            rig.last = Mat4::from_translation(-shift) * rig.last;
+        }
+        self.origin.apply(shift);
+        // Precise objects are placed from their absolute positions rather than rounded shifts
+        #[cfg(feature = "f64-transforms")]
+        self.precise.apply(&self.origin, &mut self.objects);
+    }
+
+    /// Apply a frame's input to the camera; call before `update`
+    pub fn process_input(&mut self, input: &InputState) {
+        // F frames the most recently added object
+        if input.key_pressed(Key::KeyF) {
+            if let Some(id) = self.objects.last().map(|object| object.id) {
+                self.focus_object(id);
+            }
+        }
+        // O switches projection, keeping the scene's center the same size on screen
+        if input.key_pressed(Key::KeyO) {
+            let focus = self.bounds().map_or(10.0, |(min, max)| self.camera.position.distance((min + max) * 0.5));
+            self.camera.toggle_orthographic(focus);
+        }
+        self.camera.process_input(input);
+    }
+
+    /// Place an instance of a registered model; any number of objects may share one handle.
+    /// The object holds a reference, so the model survives `AssetRegistry::unload` until it is removed.
+    pub fn add_object(&mut self, model: ModelHandle, transform: Transform) -> ObjectId {
+        if !self.assets.retain_model(model) {
+            log::warn!("Adding object with unknown model {:?}", model);
+        }
+        let id = ObjectId(self.next_object_id);
+        self.next_object_id += 1;
+        self.objects.push(id, model, transform);
+        id
+    }
+
+    /// Remove an object, releasing its model and material; returns false for unknown ids
+    pub fn remove_object(&mut self, id: ObjectId) -> bool {
+        let reserved = self.loading.cancel(id);
+        let Some(index) = self.objects.index_of(id) else {
+            return reserved;
+        };
+        let (model, material) = self.objects.remove(index);
+        self.portals.unassign(id);
+        self.stencil.unmark(id);
+        self.skinning.remove(id);
+        self.occluders.remove_object(id);
+        #[cfg(feature = "f64-transforms")]
+        self.precise.remove(id);
+        self.assets.release_model(model);
+        if let Some(material) = material {
+            self.assets.release_material(material);
+        }
+        true
+    }
+
+    /// Swap the model an object shows, keeping its transform, material and the rest; returns
+    /// false for unknown ids and models. A clip it played stops, as the skeleton may differ.
+    pub fn set_object_model(&mut self, id: ObjectId, model: ModelHandle) -> bool {
+        let Some(index) = self.objects.index_of(id) else {
+            return false;
+        };
+        if !self.assets.retain_model(model) {
+            return false;
+        }
+        let previous = self.objects.replace_model(index, model);
+        self.assets.release_model(previous);
+        self.skinning.remove(id);
+        true
+    }
+
+    /// Add an object showing the model `load` brings: at once if it's ready, and otherwise
+    /// with the loader's placeholder until `upload_loads` uploads it. Without a placeholder the
+    /// id is handed out now and the object joins the scene with its model. Objects waiting for
+    /// a load that fails are removed. Returns `None` for failed and unknown loads.
+    pub fn add_object_loading(&mut self, load: LoadHandle, transform: Transform) -> Option<ObjectId> {
+        let waiting = match self.loading.state(load)? {
+            LoadState::Ready(model) => {
+                let model = *model;
+                return Some(self.add_object(model, transform));
+            }
+            LoadState::Failed(_) => return None,
+            LoadState::Loading => match self.loading.placeholder() {
+                Some(placeholder) => Waiting::Placeholder(self.add_object(placeholder, transform)),
+                None => {
+                    let id = ObjectId(self.next_object_id);
+                    self.next_object_id += 1;
+                    Waiting::Reserved(id, transform)
+                }
+            },
+        };
+        self.loading.waiting.entry(load).or_default().push(waiting);
+        match waiting {
+            Waiting::Placeholder(id) | Waiting::Reserved(id, _) => Some(id),
+        }
+    }
+
+    /// Queue the loads the worker finished and do the next `loading.uploads_per_frame` uploads,
+    /// resolving load handles and giving waiting objects their models. Call once a frame on the
+    /// thread owning the device, with the renderer's material layout.
+    pub fn upload_loads(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, material_layout: &wgpu::BindGroupLayout) {
+        self.loading.poll();
+        for _ in 0..self.loading.uploads_per_frame {
+            let Some(upload) = self.loading.uploads.pop_front() else {
+                break;
+            };
+            match upload {
+                Upload::Model(handle, loaded) => self.upload_loaded_model(device, queue, material_layout, handle, loaded),
+                Upload::Texture(handle, texture) => {
+                    let Some((model, remaining)) = self.loading.streaming.get_mut(&handle) else {
+                        continue;
+                    };
+                    let model = *model;
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        self.loading.streaming.remove(&handle);
+                    }
+                    // Models unloaded while their textures were on the way are skipped
+                    if let Some(model) = self.assets.model_mut(model) {
+                        model.apply_streamed_texture(device, queue, material_layout, &texture);
+                    }
+                }
+            }
+        }
+    }
+
+    fn upload_loaded_model(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_layout: &wgpu::BindGroupLayout,
+        handle: LoadHandle,
+        loaded: LoadedModel,
+    ) {
+        let waiting = self.loading.waiting.remove(&handle).unwrap_or_default();
+        let data = match loaded.data {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to load {}: {:#}", loaded.path.display(), e);
+                for waiting in waiting {
+                    if let Waiting::Placeholder(id) = waiting {
+                        self.remove_object(id);
+                    }
+                }
+                self.loading.finish(handle, LoadState::Failed(format!("{:#}", e)));
+                return;
+            }
+        };
+        match &loaded.validation {
+            Some(report) if report.has_errors() => log::warn!("{}: {}", loaded.path.display(), report),
+            Some(report) if !report.is_clean() => log::info!("{}: {}", loaded.path.display(), report),
+            _ => {}
+        }
+        if let Some(units) = loaded.suspected_units {
+            log::warn!(
+                "{} is {:.0} {} across; it may be in {} (or inches) - load it with ImportOptions::units to convert",
+                loaded.path.display(), data.size(), loaded.world_units.abbreviation(), units.abbreviation(),
+            );
+        }
+        let model = Model::from_data_streamed(device, queue, &data, material_layout, &loaded.waiting_materials);
+        let model = self.assets.add_model(model);
+        if loaded.streamed_textures > 0 {
+            self.loading.streaming.insert(handle, (model, loaded.streamed_textures));
+        }
+        for waiting in waiting {
+            match waiting {
+                Waiting::Placeholder(id) => {
+                    self.set_object_model(id, model);
+                }
+                Waiting::Reserved(id, transform) => {
+                    self.assets.retain_model(model);
+                    self.objects.push(id, model, transform);
+                }
+            }
+        }
+        self.loading.finish(handle, LoadState::Ready(model));
+        log::info!("Loaded {}", loaded.path.display());
+    }
+
+    /// Override (or with `None`, restore) the material of every mesh of an object
+    pub fn set_object_material(&mut self, id: ObjectId, material: Option<MaterialHandle>) -> bool {
+        let Some(index) = self.objects.index_of(id) else {
+            return false;
+        };
+        if let Some(handle) = material {
+            if !self.assets.retain_material(handle) {
+                return false;
+            }
+        }
+        if let Some(previous) = self.objects.replace_material(index, material) {
+            self.assets.release_material(previous);
+        }
+        true
+    }
+
+    pub fn add_light(&mut self, light: PointLight) -> LightId {
+        let id = LightId(self.next_light_id);
+        self.next_light_id += 1;
+        self.lights.push(SceneLight { id, light });
+        id
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> bool {
+        let count = self.lights.len();
+        self.lights.retain(|entry| entry.id != id);
+        self.lights.len() != count
+    }
+
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut PointLight> {
+        self.lights.iter_mut().find(|entry| entry.id == id).map(|entry| &mut entry.light)
+    }
+
+    pub fn object(&self, id: ObjectId) -> Option<SceneObject> {
+        self.objects.index_of(id).and_then(|index| self.objects.get(index))
+    }
+
+    pub fn transform_mut(&mut self, id: ObjectId) -> Option<&mut Transform> {
+        let index = self.objects.index_of(id)?;
+        Some(&mut self.objects.transforms_mut()[index])
+    }
+
+    /// Position an object in absolute f64 coordinates, so it stays exact however far it is
+    /// from the world origin; its local transform follows. Returns false for unknown ids.
+    #[cfg(feature = "f64-transforms")]
+    pub fn set_precise_transform(&mut self, id: ObjectId, transform: PreciseTransform) -> bool {
+        let Some(index) = self.objects.index_of(id) else {
+            return false;
+        };
+        let local = transform.to_local(&self.origin);
+        self.objects.transforms_mut()[index] = local;
+        self.precise.insert(id, transform, local);
+        true
     }
 
-    pub fn process_keyboard(&mut self, key: KeyCode, pressed: bool) {
-        self.camera.process_keyboard(key, pressed);
+    /// An object's absolute transform: the precise one if set, else its local transform
+    /// offset by the floating origin
+    #[cfg(feature = "f64-transforms")]
+    pub fn precise_transform(&self, id: ObjectId) -> Option<PreciseTransform> {
+        if let Some(transform) = self.precise.get(id) {
+            return Some(*transform);
+        }
+        self.object(id).map(|object| PreciseTransform::from_local(&object.transform, &self.origin))
+    }
+
+    /// Go back to positioning an object by its local f32 transform alone
+    #[cfg(feature = "f64-transforms")]
+    pub fn clear_precise_transform(&mut self, id: ObjectId) -> bool {
+        self.precise.remove(id)
     }
 
-    pub fn process_mouse(&mut self, dx: f32, dy: f32) {
-        self.camera.process_mouse(dx, dy);
+    /// Show or hide an object in every render pass; returns false for unknown ids
+    pub fn set_visible(&mut self, id: ObjectId, visible: bool) -> bool {
+        let Some(index) = self.objects.index_of(id) else {
+            return false;
+        };
+        self.objects.visibility_mut()[index] = visible;
+        true
     }
 
-    pub fn add_object(&mut self, model: Model, transform: Transform) {
-        self.objects.push((model, transform));
+    /// Point the camera at an object and fit its bounds in view; returns false for unknown ids or models
+    pub fn focus_object(&mut self, id: ObjectId) -> bool {
+        match self.object(id).and_then(|object| object.world_bounds(&self.assets)) {
+            Some((min, max)) => {
+                self.camera.frame_bounds(min, max);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// World-space bounds of all objects, or `None` for an empty scene
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.objects.iter()
+            .filter_map(|object| object.world_bounds(&self.assets))
+            .reduce(|(lo, hi), (min, max)| (lo.min(min), hi.max(max)))
+    }
+
+    /// Bring the bounding volume hierarchy of object bounds up to date: refit after objects
+    /// moved, rebuilt after objects were added or removed. `update` does this every frame.
+    pub fn update_bvh(&mut self) {
+        let (revision, layout_revision) = (self.objects.revision(), self.objects.layout_revision());
+        match &mut self.bvh {
+            Some(cache) if cache.revision == revision => {}
+            Some(cache) if cache.layout_revision == layout_revision => {
+                let (objects, assets) = (&self.objects, &self.assets);
+                cache.bvh.refit(|item| {
+                    objects.get(item.index)
+                        .and_then(|object| object.world_bounds(assets))
+                        .unwrap_or((item.min, item.max))
+                });
+                cache.revision = revision;
+            }
+            _ => {
+                // Objects whose model isn't registered have no bounds and are left out
+                let items = self.objects.iter().enumerate()
+                    .filter_map(|(index, object)| {
+                        let (min, max) = object.world_bounds(&self.assets)?;
+                        Some(BvhItem { id: object.id, index, min, max })
+                    })
+                    .collect();
+                self.bvh = Some(BvhCache { bvh: Bvh::build(items), revision, layout_revision });
+            }
+        }
+    }
+
+    /// Hierarchy of every object's world bounds, `None` if objects changed since the last
+    /// `update_bvh`. Item indices are component array indices in `objects`.
+    pub fn bvh(&self) -> Option<&Bvh> {
+        self.bvh.as_ref()
+            .filter(|cache| cache.revision == self.objects.revision())
+            .map(|cache| &cache.bvh)
+    }
+
+    /// The nearest object whose world bounds `ray` enters, skipping `ignore`. Searches the
+    /// BVH when it's up to date, else every object.
+    pub fn raycast(&self, ray: &Ray, ignore: Option<ObjectId>) -> Option<RayHit> {
+        self.raycast_filtered(ray, |id| Some(id) != ignore)
+    }
+
+    /// `raycast` against only the objects `accept` returns true for
+    pub fn raycast_filtered(&self, ray: &Ray, mut accept: impl FnMut(ObjectId) -> bool) -> Option<RayHit> {
+        if let Some(bvh) = self.bvh() {
+            return bvh.raycast(ray, |item| {
+                if !accept(item.id) {
+                    return None;
+                }
+                let (distance, normal) = ray.intersect_aabb(item.min, item.max)?;
+                Some((distance, RayHit { object: item.id, distance, point: ray.at(distance), normal }))
+            })
+            .map(|(_, hit)| hit);
+        }
+        self.objects.iter()
+            .filter(|object| accept(object.id))
+            .filter_map(|object| {
+                let (min, max) = object.world_bounds(&self.assets)?;
+                let (distance, normal) = ray.intersect_aabb(min, max)?;
+                Some(RayHit { object: object.id, distance, point: ray.at(distance), normal })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// Objects whose world bounds touch the sphere, in id order, e.g. what a VR hand can grab
+    pub fn overlap_sphere(&self, center: Vec3, radius: f32) -> Vec<ObjectId> {
+        self.overlapping(|min, max| distance_to_aabb(center, min, max) <= radius)
+    }
+
+    /// Objects whose world bounds intersect the box `min..max`, in id order
+    pub fn overlap_aabb(&self, min: Vec3, max: Vec3) -> Vec<ObjectId> {
+        self.overlapping(|other_min, other_max| other_min.cmple(max).all() && other_max.cmpge(min).all())
+    }
+
+    /// The object `filter` accepts whose world bounds are nearest `point`, and the distance to
+    /// them (0 inside). Like `raycast`, hidden objects count and the BVH is used when up to date.
+    pub fn nearest_object(&self, point: Vec3, mut filter: impl FnMut(ObjectId) -> bool) -> Option<(ObjectId, f32)> {
+        if let Some(bvh) = self.bvh() {
+            return bvh.nearest(point, |item| filter(item.id)).map(|(item, distance)| (item.id, distance));
+        }
+        self.objects.iter()
+            .filter_map(|object| {
+                let (min, max) = object.world_bounds(&self.assets)?;
+                Some((object.id, distance_to_aabb(point, min, max)))
+            })
+            .filter(|(id, _)| filter(*id))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Objects with world bounds `overlaps` accepts, from the BVH when it's up to date
+    fn overlapping(&self, overlaps: impl Fn(Vec3, Vec3) -> bool) -> Vec<ObjectId> {
+        let mut found = Vec::new();
+        match self.bvh() {
+            Some(bvh) => bvh.query(&overlaps, |item| found.push(item.id)),
+            None => found.extend(self.objects.iter()
+                .filter(|object| object.world_bounds(&self.assets).is_some_and(|(min, max)| overlaps(min, max)))
+                .map(|object| object.id)),
+        }
+        found.sort_unstable();
+        found
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -85,11 +937,11 @@ impl Scene {
     }
 
     pub fn set_ambient_light(&mut self, intensity: f32) {
-        self.ambient_light = Vec3::splat(intensity.clamp(0.0, 1.0));
+        self.ambient_light = Color::from(Vec3::splat(intensity.clamp(0.0, 1.0)));
     }
 
-    pub fn set_directional_light(&mut self, color: Vec3, direction: Vec3) {
-        self.directional_light = color.clamp(Vec3::ZERO, Vec3::ONE);
+    pub fn set_directional_light(&mut self, color: Color, direction: Vec3) {
+        self.directional_light = Color::from(color.rgb().clamp(Vec3::ZERO, Vec3::ONE));
         self.light_direction = direction.normalize();
     }
 } 
\ No newline at end of file
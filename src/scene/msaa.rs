@@ -0,0 +1,167 @@
+use crate::shaders;
+use super::post::{ALBEDO_FORMAT, HDR_FORMAT, NORMAL_FORMAT, VELOCITY_FORMAT};
+use super::profiler::Profiler;
+
+/// A view of just the depth aspect, for passes that read the depth buffer as a texture;
+/// stencil formats can only be bound one aspect at a time
+pub fn depth_sample_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        aspect: wgpu::TextureAspect::DepthOnly,
+        ..Default::default()
+    })
+}
+
+/// Multisampled scene targets, resolved into the post stack's single-sample inputs
+pub struct MsaaTargets {
+    color_view: wgpu::TextureView,
+    normal_view: wgpu::TextureView,
+    albedo_view: wgpu::TextureView,
+    velocity_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    resolve_pipeline: wgpu::RenderPipeline,
+    resolve_bind_group: wgpu::BindGroup,
+}
+
+impl MsaaTargets {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, sample_count: u32, depth_format: wgpu::TextureFormat) -> Self {
+        let create_texture = |label, format, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let create_view = |label, format, usage| {
+            create_texture(label, format, usage).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let color_view = create_view("MSAA Color Target", HDR_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let normal_view = create_view("MSAA Normal Target", NORMAL_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let albedo_view = create_view("MSAA Albedo Target", ALBEDO_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let velocity_view = create_view("MSAA Velocity Target", VELOCITY_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let depth_texture = create_texture(
+            "MSAA Depth Target",
+            depth_format,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Resolve Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+        });
+        let resolve_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth Resolve Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth_sample_view(&depth_texture)),
+            }],
+        });
+
+        let shader = shaders::create_module(device, "Depth Resolve Shader", include_str!("../../shaders/depth_resolve.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Resolve Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            color_view,
+            normal_view,
+            albedo_view,
+            velocity_view,
+            depth_view,
+            resolve_pipeline,
+            resolve_bind_group,
+        }
+    }
+
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+
+    pub fn normal_view(&self) -> &wgpu::TextureView {
+        &self.normal_view
+    }
+
+    pub fn albedo_view(&self) -> &wgpu::TextureView {
+        &self.albedo_view
+    }
+
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.velocity_view
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    /// Copy the nearest depth sample into the single-sample depth target
+    pub fn resolve_depth(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, profiler: &mut Profiler) {
+        let queries = profiler.begin_pass("Depth Resolve");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.resolve_pipeline);
+        pass.set_bind_group(0, &self.resolve_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
@@ -0,0 +1,159 @@
+use rayon::prelude::*;
+use crate::model::{MaterialHandle, ModelHandle};
+use super::{ObjectId, SceneObject, Transform};
+
+/// Reference-counted handles of one object; cold data that only changes through `Scene`
+#[derive(Debug, Clone, Copy)]
+struct ObjectHandles {
+    model: ModelHandle,
+    material: Option<MaterialHandle>,
+}
+
+/// Scene objects stored as parallel component arrays, one entry per object at the same index.
+/// Hot per-frame data (transforms, visibility) lives in its own plain slice, so systems such as
+/// animation, physics, culling and audio can each borrow the component they touch, split it
+/// across threads with rayon, and run alongside each other without aliasing.
+#[derive(Debug, Default)]
+pub struct SceneObjects {
+    ids: Vec<ObjectId>,
+    handles: Vec<ObjectHandles>,
+    transforms: Vec<Transform>,
+    visible: Vec<bool>,
+    /// Bumped by every borrow that can move an object, and by adding or removing objects
+    revision: u64,
+    /// Bumped by adding or removing objects
+    layout_revision: u64,
+}
+
+/// Disjoint borrows of every component array, from [`SceneObjects::components_mut`]
+pub struct ComponentsMut<'a> {
+    pub ids: &'a [ObjectId],
+    pub transforms: &'a mut [Transform],
+    pub visible: &'a mut [bool],
+}
+
+impl SceneObjects {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The object at `index`, in the order objects were added
+    pub fn get(&self, index: usize) -> Option<SceneObject> {
+        let handles = self.handles.get(index)?;
+        Some(SceneObject {
+            id: self.ids[index],
+            model: handles.model,
+            material: handles.material,
+            transform: self.transforms[index],
+            visible: self.visible[index],
+        })
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = SceneObject> + ExactSizeIterator + '_ {
+        (0..self.len()).map(|index| self.get(index).unwrap())
+    }
+
+    pub fn last(&self) -> Option<SceneObject> {
+        self.len().checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Component array index of an object
+    pub fn index_of(&self, id: ObjectId) -> Option<usize> {
+        self.ids.iter().position(|object| *object == id)
+    }
+
+    /// Changes whenever objects may have moved or were added or removed, so caches of their
+    /// bounds (like `Scene::bvh`) can tell they're stale
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Changes whenever objects are added or removed
+    pub fn layout_revision(&self) -> u64 {
+        self.layout_revision
+    }
+
+    pub fn ids(&self) -> &[ObjectId] {
+        &self.ids
+    }
+
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+
+    pub fn transforms_mut(&mut self) -> &mut [Transform] {
+        self.revision += 1;
+        &mut self.transforms
+    }
+
+    /// Hidden objects are skipped by every render pass
+    pub fn visibility(&self) -> &[bool] {
+        &self.visible
+    }
+
+    pub fn visibility_mut(&mut self) -> &mut [bool] {
+        &mut self.visible
+    }
+
+    /// Every component at once, for systems that read one array while writing another
+    pub fn components_mut(&mut self) -> ComponentsMut<'_> {
+        self.revision += 1;
+        ComponentsMut {
+            ids: &self.ids,
+            transforms: &mut self.transforms,
+            visible: &mut self.visible,
+        }
+    }
+
+    /// Run `update` on every transform, spread across the rayon thread pool
+    pub fn par_update_transforms(&mut self, update: impl Fn(ObjectId, &mut Transform) + Send + Sync) {
+        self.revision += 1;
+        self.ids.par_iter()
+            .zip(self.transforms.par_iter_mut())
+            .for_each(|(id, transform)| update(*id, transform));
+    }
+
+    /// Set each object's visibility from its transform in parallel, e.g. for a culling pass
+    pub fn par_update_visibility(&mut self, test: impl Fn(ObjectId, &Transform) -> bool + Send + Sync) {
+        self.ids.par_iter()
+            .zip(self.transforms.par_iter())
+            .zip(self.visible.par_iter_mut())
+            .for_each(|((id, transform), visible)| *visible = test(*id, transform));
+    }
+
+    pub(super) fn push(&mut self, id: ObjectId, model: ModelHandle, transform: Transform) {
+        self.revision += 1;
+        self.layout_revision += 1;
+        self.ids.push(id);
+        self.handles.push(ObjectHandles { model, material: None });
+        self.transforms.push(transform);
+        self.visible.push(true);
+    }
+
+    /// Remove the object at `index`, returning its handles for release
+    pub(super) fn remove(&mut self, index: usize) -> (ModelHandle, Option<MaterialHandle>) {
+        self.revision += 1;
+        self.layout_revision += 1;
+        self.ids.remove(index);
+        self.transforms.remove(index);
+        self.visible.remove(index);
+        let handles = self.handles.remove(index);
+        (handles.model, handles.material)
+    }
+
+    /// Replace the model at `index`, returning the previous one
+    pub(super) fn replace_model(&mut self, index: usize, model: ModelHandle) -> ModelHandle {
+        // The object's bounds come from its model
+        self.revision += 1;
+        std::mem::replace(&mut self.handles[index].model, model)
+    }
+
+    /// Replace the material override at `index`, returning the previous one
+    pub(super) fn replace_material(&mut self, index: usize, material: Option<MaterialHandle>) -> Option<MaterialHandle> {
+        std::mem::replace(&mut self.handles[index].material, material)
+    }
+}
@@ -0,0 +1,316 @@
+use glam::{Mat4, Vec3, Vec4};
+use crate::model::MeshData;
+use super::{ObjectId, SceneObjects};
+
+/// Default size of the depth buffers occluders are drawn into
+pub const DEFAULT_OCCLUSION_RESOLUTION: [u32; 2] = [256, 144];
+
+/// Simplified geometry that hides whatever is behind it, such as a building's walls as a few
+/// boxes. Occluders must lie inside what they stand for, or objects they wrongly cover vanish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occluder {
+    pub positions: Vec<Vec3>,
+    /// Triangle list; either winding occludes
+    pub indices: Vec<u32>,
+}
+
+impl Occluder {
+    /// A solid box, e.g. hand-authored around the core of a building
+    pub fn cuboid(min: Vec3, max: Vec3) -> Self {
+        let positions = (0..8)
+            .map(|i| Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            ))
+            .collect();
+        let faces: [[u32; 4]; 6] = [[0, 1, 3, 2], [4, 5, 7, 6], [0, 1, 5, 4], [2, 3, 7, 6], [0, 2, 6, 4], [1, 3, 7, 5]];
+        let indices = faces.iter().flat_map(|&[a, b, c, d]| [a, b, c, a, c, d]).collect();
+        Self { positions, indices }
+    }
+
+    /// The triangles of a loaded mesh, e.g. an occluder modelled alongside the detailed one
+    pub fn from_mesh(mesh: &MeshData) -> Self {
+        Self {
+            positions: mesh.vertices.iter().map(|vertex| Vec3::from(vertex.position)).collect(),
+            indices: mesh.indices.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OccluderId(pub u32);
+
+#[derive(Debug)]
+struct Entry {
+    id: OccluderId,
+    occluder: Occluder,
+    /// Carried by this object, in its local space; world space when `None`
+    object: Option<ObjectId>,
+}
+
+/// Occluders of the scene. When there are any, `FrustumVisibility` draws them into a small
+/// software depth buffer per view and leaves out objects whose bounds are entirely behind them,
+/// which frustum culling alone can't in dense city blocks. Occluders carried by hidden objects
+/// don't occlude.
+#[derive(Debug)]
+pub struct Occluders {
+    entries: Vec<Entry>,
+    next_id: u32,
+    /// Width and height of the depth buffers; coarser is cheaper but culls less
+    pub resolution: [u32; 2],
+    /// Off to compare against frustum culling alone
+    pub enabled: bool,
+}
+
+impl Default for Occluders {
+    fn default() -> Self {
+        Self { entries: Vec::new(), next_id: 0, resolution: DEFAULT_OCCLUSION_RESOLUTION, enabled: true }
+    }
+}
+
+impl Occluders {
+    /// Add an occluder fixed in the world
+    pub fn add(&mut self, occluder: Occluder) -> OccluderId {
+        self.insert(occluder, None)
+    }
+
+    /// Add an occluder in `object`'s local space that moves with it and goes when it's removed
+    pub fn attach(&mut self, object: ObjectId, occluder: Occluder) -> OccluderId {
+        self.insert(occluder, Some(object))
+    }
+
+    pub fn remove(&mut self, id: OccluderId) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() != count
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether `FrustumVisibility` should consult the occluders
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.entries.is_empty()
+    }
+
+    /// Rasterize every occluder as `view_projection` sees it
+    pub fn depth_buffer(&self, objects: &SceneObjects, view_projection: Mat4) -> DepthBuffer {
+        let mut buffer = DepthBuffer::new(self.resolution[0], self.resolution[1]);
+        for entry in &self.entries {
+            let matrix = match entry.object {
+                None => view_projection,
+                Some(id) => match objects.index_of(id).and_then(|index| objects.get(index)) {
+                    Some(object) if object.visible => view_projection * object.transform.to_matrix(),
+                    _ => continue,
+                },
+            };
+            buffer.rasterize(matrix, &entry.occluder);
+        }
+        buffer
+    }
+
+    pub(crate) fn remove_object(&mut self, object: ObjectId) {
+        self.entries.retain(|entry| entry.object != Some(object));
+    }
+
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.object.is_none()) {
+            for position in &mut entry.occluder.positions {
+                *position += offset;
+            }
+        }
+    }
+
+    fn insert(&mut self, occluder: Occluder, object: Option<ObjectId>) -> OccluderId {
+        let id = OccluderId(self.next_id);
+        self.next_id += 1;
+        self.entries.push(Entry { id, occluder, object });
+        id
+    }
+}
+
+/// Nearest occluder depth (clip z / w) per pixel, sampled at pixel centers
+#[derive(Debug, Clone)]
+pub struct DepthBuffer {
+    width: u32,
+    height: u32,
+    depth: Vec<f32>,
+}
+
+impl DepthBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, depth: vec![f32::INFINITY; (width * height) as usize] }
+    }
+
+    /// Draw `occluder`'s triangles, with `matrix` taking its positions to clip space
+    pub fn rasterize(&mut self, matrix: Mat4, occluder: &Occluder) {
+        let clip: Vec<Vec4> = occluder.positions.iter().map(|position| matrix * position.extend(1.0)).collect();
+        for triangle in occluder.indices.chunks_exact(3) {
+            let Some(corners) = triangle.iter().map(|&index| clip.get(index as usize).copied()).collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            let polygon = clip_near(&corners);
+            let screen: Vec<Vec3> = polygon.iter().map(|&corner| self.to_screen(corner)).collect();
+            for i in 1..screen.len().saturating_sub(1) {
+                self.fill(screen[0], screen[i], screen[i + 1]);
+            }
+        }
+    }
+
+    /// Whether the box from `min` to `max` is behind occluders over all of its screen extent.
+    /// Boxes crossing the near plane or off screen count as visible.
+    pub fn occludes(&self, view_projection: Mat4, min: Vec3, max: Vec3) -> bool {
+        let mut screen_min = Vec3::splat(f32::INFINITY);
+        let mut screen_max = Vec3::splat(f32::NEG_INFINITY);
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            let clip = view_projection * corner.extend(1.0);
+            if clip.w <= NEAR_W || clip.z < -clip.w {
+                return false;
+            }
+            let screen = self.to_screen(clip);
+            screen_min = screen_min.min(screen);
+            screen_max = screen_max.max(screen);
+        }
+        let x0 = screen_min.x.floor().max(0.0) as u32;
+        let y0 = screen_min.y.floor().max(0.0) as u32;
+        let x1 = (screen_max.x.ceil().max(0.0) as u32).min(self.width);
+        let y1 = (screen_max.y.ceil().max(0.0) as u32).min(self.height);
+        if x0 >= x1 || y0 >= y1 {
+            return false;
+        }
+        // Every pixel the box may touch has to have an occluder in front of its nearest point
+        (y0..y1).all(|y| {
+            let row = (y * self.width) as usize;
+            self.depth[row + x0 as usize..row + x1 as usize].iter().all(|&depth| depth < screen_min.z)
+        })
+    }
+
+    /// Pixels covered by any occluder
+    pub fn coverage(&self) -> usize {
+        self.depth.iter().filter(|depth| depth.is_finite()).count()
+    }
+
+    /// Pixel coordinates with y down, and depth
+    fn to_screen(&self, clip: Vec4) -> Vec3 {
+        let ndc = clip.truncate() / clip.w;
+        Vec3::new(
+            (ndc.x * 0.5 + 0.5) * self.width as f32,
+            (0.5 - ndc.y * 0.5) * self.height as f32,
+            ndc.z,
+        )
+    }
+
+    fn fill(&mut self, a: Vec3, b: Vec3, c: Vec3) {
+        let edge = |from: Vec3, to: Vec3, x: f32, y: f32| (to.x - from.x) * (y - from.y) - (to.y - from.y) * (x - from.x);
+        let area = edge(a, b, c.x, c.y);
+        if area.abs() < 1e-8 {
+            return;
+        }
+        let x0 = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+        let y0 = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+        let x1 = (a.x.max(b.x).max(c.x).ceil().max(0.0) as u32).min(self.width);
+        let y1 = (a.y.max(b.y).max(c.y).ceil().max(0.0) as u32).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                // Barycentric weights, positive inside whichever way the triangle winds
+                let wa = edge(b, c, px, py) / area;
+                let wb = edge(c, a, px, py) / area;
+                let wc = edge(a, b, px, py) / area;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let depth = wa * a.z + wb * b.z + wc * c.z;
+                let texel = &mut self.depth[(y * self.width + x) as usize];
+                *texel = texel.min(depth);
+            }
+        }
+    }
+}
+
+/// Smallest clip w kept, so nothing at or behind the eye is divided by
+const NEAR_W: f32 = 1e-5;
+
+/// Cut a clip-space triangle to the part in front of the near plane and the eye
+fn clip_near(triangle: &[Vec4]) -> Vec<Vec4> {
+    // Signed distances to the near plane (-1 clip depth) and to just in front of the eye
+    let planes: [fn(Vec4) -> f32; 2] = [|point| point.z + point.w, |point| point.w - NEAR_W];
+    let mut polygon = triangle.to_vec();
+    for distance in planes {
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, &current) in polygon.iter().enumerate() {
+            let next = polygon[(i + 1) % polygon.len()];
+            let (d0, d1) = (distance(current), distance(next));
+            if d0 >= 0.0 {
+                clipped.push(current);
+            }
+            if (d0 >= 0.0) != (d1 >= 0.0) {
+                clipped.push(current.lerp(next, d0 / (d0 - d1)));
+            }
+        }
+        polygon = clipped;
+        if polygon.len() < 3 {
+            return Vec::new();
+        }
+    }
+    polygon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view_projection() -> Mat4 {
+        Mat4::perspective_rh_gl(60f32.to_radians(), 16.0 / 9.0, 0.1, 100.0)
+            * Mat4::look_at_rh(Vec3::new(0.0, 0.0, 10.0), Vec3::ZERO, Vec3::Y)
+    }
+
+    #[test]
+    fn test_occlusion() {
+        let view_projection = view_projection();
+        let mut buffer = DepthBuffer::new(64, 36);
+        // A wall across the view at the origin
+        buffer.rasterize(view_projection, &Occluder::cuboid(Vec3::new(-4.0, -3.0, -0.5), Vec3::new(4.0, 3.0, 0.5)));
+        assert!(buffer.coverage() > 0);
+
+        // Behind the wall, beside it, in front of it, and around the eye
+        assert!(buffer.occludes(view_projection, Vec3::new(-1.0, -1.0, -6.0), Vec3::new(1.0, 1.0, -4.0)));
+        assert!(!buffer.occludes(view_projection, Vec3::new(8.0, -1.0, -6.0), Vec3::new(10.0, 1.0, -4.0)));
+        assert!(!buffer.occludes(view_projection, Vec3::new(-1.0, -1.0, 2.0), Vec3::new(1.0, 1.0, 4.0)));
+        assert!(!buffer.occludes(view_projection, Vec3::new(-1.0, -1.0, 9.0), Vec3::new(1.0, 1.0, 11.0)));
+        // Peeking out above the wall
+        assert!(!buffer.occludes(view_projection, Vec3::new(-1.0, 2.0, -6.0), Vec3::new(1.0, 6.0, -4.0)));
+    }
+
+    #[test]
+    fn test_occluders() {
+        let objects = SceneObjects::default();
+        let mut occluders = Occluders::default();
+        assert!(!occluders.is_active());
+        let wall = occluders.attach(ObjectId(7), Occluder::cuboid(Vec3::splat(-1.0), Vec3::ONE));
+        // Carried by an object that doesn't exist, so nothing occludes
+        assert_eq!(occluders.depth_buffer(&objects, view_projection()).coverage(), 0);
+
+        occluders.add(Occluder::cuboid(Vec3::new(-4.0, -3.0, -0.5), Vec3::new(4.0, 3.0, 0.5)));
+        assert_eq!(occluders.len(), 2);
+        let covered = occluders.depth_buffer(&objects, view_projection()).coverage();
+        assert!(covered > 0);
+
+        // World occluders follow origin rebases
+        occluders.translate(Vec3::new(100.0, 0.0, 0.0));
+        assert_eq!(occluders.depth_buffer(&objects, view_projection()).coverage(), 0);
+        occluders.remove_object(ObjectId(7));
+        assert!(!occluders.remove(wall));
+    }
+}
@@ -0,0 +1,90 @@
+use glam::{DVec3, Vec3};
+
+/// Horizontal camera distance from the local origin that triggers a rebase
+pub const DEFAULT_REBASE_DISTANCE: f32 = 2048.0;
+/// Shifts are whole multiples of this so the ground grid and other periodic patterns
+/// line up across a rebase
+pub const REBASE_SNAP: f32 = 100.0;
+
+/// Keeps render-space coordinates small in large worlds by moving the local origin along with
+/// the camera. Absolute positions are `offset + local`, held in f64.
+///
+/// Only the horizontal plane is rebased; altitude stays absolute so the ground plane remains at y = 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatingOrigin {
+    pub enabled: bool,
+    pub rebase_distance: f32,
+    offset: DVec3,
+}
+
+impl Default for FloatingOrigin {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rebase_distance: DEFAULT_REBASE_DISTANCE,
+            offset: DVec3::ZERO,
+        }
+    }
+}
+
+impl FloatingOrigin {
+    /// Absolute position of the local origin
+    pub fn offset(&self) -> DVec3 {
+        self.offset
+    }
+
+    pub fn to_absolute(&self, local: Vec3) -> DVec3 {
+        self.offset + local.as_dvec3()
+    }
+
+    pub fn to_local(&self, absolute: DVec3) -> Vec3 {
+        (absolute - self.offset).as_vec3()
+    }
+
+    /// Shift to subtract from every local position once `camera` is too far out, if any
+    pub fn rebase_shift(&self, camera: Vec3) -> Option<Vec3> {
+        let horizontal = Vec3::new(camera.x, 0.0, camera.z);
+        if !self.enabled || horizontal.length() < self.rebase_distance {
+            return None;
+        }
+        Some((horizontal / REBASE_SNAP).round() * REBASE_SNAP)
+    }
+
+    /// Record that local positions were moved by `-shift`
+    pub(super) fn apply(&mut self, shift: Vec3) {
+        self.offset += shift.as_dvec3();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_shift() {
+        let mut origin = FloatingOrigin::default();
+        assert_eq!(origin.rebase_shift(Vec3::new(100.0, 5000.0, -100.0)), None);
+
+        // Snapped and horizontal only
+        let camera = Vec3::new(2100.4, 35.0, -1249.0);
+        let shift = origin.rebase_shift(camera).unwrap();
+        assert_eq!(shift, Vec3::new(2100.0, 0.0, -1200.0));
+
+        origin.apply(shift);
+        assert_eq!(origin.to_absolute(camera - shift), camera.as_dvec3());
+        assert_eq!(origin.to_local(DVec3::ZERO), Vec3::new(-2100.0, 0.0, 1200.0));
+
+        origin.enabled = false;
+        assert_eq!(origin.rebase_shift(camera * 10.0), None);
+    }
+
+    #[test]
+    fn test_absolute_precision() {
+        // Far beyond f32's centimeter range the offset keeps local positions exact
+        let mut origin = FloatingOrigin::default();
+        origin.apply(Vec3::new(40_000_000.0, 0.0, 0.0));
+        let absolute = origin.to_absolute(Vec3::new(0.25, 0.0, 0.0));
+        assert_eq!(absolute.x, 40_000_000.25);
+        assert_eq!(origin.to_local(absolute).x, 0.25);
+    }
+}
@@ -0,0 +1,260 @@
+use std::time::{Duration, Instant};
+use crate::diagnostics::{self, LogEntry};
+use super::profiler::{FrameTiming, Profiler, HISTORY_LEN};
+
+// Panel layout in pixels
+const MARGIN: f32 = 10.0;
+const BAR_WIDTH: f32 = 1.5;
+const GRAPH_HEIGHT: f32 = 100.0;
+const ROW_HEIGHT: f32 = 8.0;
+const ROW_GAP: f32 = 2.0;
+const MAX_ROWS: usize = 3;
+// Recent warnings/errors shown as a row of markers under the pass rows
+const LOG_WINDOW: Duration = Duration::from_secs(10);
+const MAX_LOG_MARKERS: usize = 32;
+// Frame budgets drawn as reference lines (60 and 30 fps)
+const BUDGETS_MS: [f32; 2] = [1000.0 / 60.0, 1000.0 / 30.0];
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const BUDGET_LINE: [f32; 4] = [0.6, 0.6, 0.6, 0.5];
+const CPU_COLOR: [f32; 4] = [0.2, 0.8, 0.3, 0.8];
+const GPU_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+const WORST_COLOR: [f32; 4] = [1.0, 0.1, 0.1, 1.0];
+const LOG_ERROR_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const LOG_WARN_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const PASS_COLORS: [[f32; 3]; 6] = [
+    [0.3, 0.5, 0.9],
+    [0.9, 0.4, 0.4],
+    [0.4, 0.8, 0.8],
+    [0.8, 0.7, 0.3],
+    [0.7, 0.4, 0.9],
+    [0.5, 0.8, 0.4],
+];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OverlayVertex {
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl OverlayVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Alpha-blended pipeline drawing `OverlayVertex` triangles, shared with the VR HUD
+pub(crate) fn create_overlay_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Overlay Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/overlay.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Overlay Pipeline Layout"),
+        bind_group_layouts: &[],
+        push_constant_ranges: &[],
+    });
+    diagnostics::scoped(device, "Overlay Pipeline", || {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[OverlayVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    })
+}
+
+/// Accumulates screen-space rectangles as triangles in NDC
+struct QuadBuilder {
+    vertices: Vec<OverlayVertex>,
+    width: f32,
+    height: f32,
+}
+
+impl QuadBuilder {
+    fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: [f32; 4]) {
+        let to_ndc = |px: f32, py: f32| [px / self.width * 2.0 - 1.0, 1.0 - py / self.height * 2.0];
+        let corners = [to_ndc(x, y), to_ndc(x + w, y), to_ndc(x + w, y + h), to_ndc(x, y + h)];
+        for index in [0, 3, 2, 0, 2, 1] {
+            self.vertices.push(OverlayVertex { position: corners[index], color });
+        }
+    }
+}
+
+/// Profiler HUD: scrolling CPU/GPU frame-time graph with a worst-frame marker,
+/// the latest frame's pass hierarchy as stacked rows underneath, and a marker per recent warning or error
+pub struct DebugOverlay {
+    pub enabled: bool,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl DebugOverlay {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let pipeline = create_overlay_pipeline(device, format);
+
+        let capacity = 4096;
+        Self {
+            enabled: false,
+            pipeline,
+            vertex_buffer: Self::create_vertex_buffer(device, capacity),
+            capacity,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<OverlayVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Overlay geometry for a `width` x `height` target; `log` holds the warnings and errors to flag
+    pub fn build_vertices(profiler: &Profiler, log: &[LogEntry], width: u32, height: u32) -> Vec<OverlayVertex> {
+        let mut quads = QuadBuilder {
+            vertices: Vec::new(),
+            width: width.max(1) as f32,
+            height: height.max(1) as f32,
+        };
+        let history: Vec<&FrameTiming> = profiler.history().collect();
+        let graph_width = HISTORY_LEN as f32 * BAR_WIDTH;
+        let panel_height = GRAPH_HEIGHT + ROW_GAP + (MAX_ROWS + 1) as f32 * (ROW_HEIGHT + ROW_GAP);
+        quads.rect(MARGIN, MARGIN, graph_width, panel_height, BACKGROUND);
+
+        // Scale so the worst frame and both budget lines fit
+        let worst = profiler.worst_frame();
+        let worst_ms = worst.map_or(0.0, |(_, frame)| frame.cpu_ms);
+        let scale_ms = worst_ms.max(BUDGETS_MS[1]) * 1.1;
+        let bar_height = |ms: f32| (ms / scale_ms).min(1.0) * GRAPH_HEIGHT;
+        let graph_bottom = MARGIN + GRAPH_HEIGHT;
+
+        for budget in BUDGETS_MS {
+            quads.rect(MARGIN, graph_bottom - bar_height(budget), graph_width, 1.0, BUDGET_LINE);
+        }
+
+        // Newest frame on the right
+        let first_x = MARGIN + graph_width - history.len() as f32 * BAR_WIDTH;
+        for (i, frame) in history.iter().enumerate() {
+            let x = first_x + i as f32 * BAR_WIDTH;
+            let cpu = bar_height(frame.cpu_ms);
+            quads.rect(x, graph_bottom - cpu, BAR_WIDTH, cpu, CPU_COLOR);
+            if let Some(gpu_ms) = frame.gpu_ms {
+                quads.rect(x, graph_bottom - bar_height(gpu_ms) - 1.0, BAR_WIDTH, 2.0, GPU_COLOR);
+            }
+        }
+        if let Some((index, _)) = worst {
+            quads.rect(first_x + index as f32 * BAR_WIDTH, MARGIN, 1.0, GRAPH_HEIGHT, WORST_COLOR);
+        }
+
+        // Pass hierarchy: children start under their parent and split its width
+        if let Some(frame) = profiler.latest() {
+            let use_gpu = frame.gpu_ms.is_some();
+            let time = |pass: &super::profiler::PassTiming| {
+                if use_gpu { pass.gpu_ms.unwrap_or(0.0) } else { pass.cpu_ms }
+            };
+            let total: f32 = frame.passes.iter().filter(|pass| pass.depth == 0).map(time).sum();
+            if total > 0.0 {
+                let mut cursor = [MARGIN; MAX_ROWS + 1];
+                for (i, pass) in frame.passes.iter().enumerate() {
+                    if pass.depth >= MAX_ROWS {
+                        continue;
+                    }
+                    let w = time(pass) / total * graph_width;
+                    let x = cursor[pass.depth];
+                    let y = graph_bottom + ROW_GAP + pass.depth as f32 * (ROW_HEIGHT + ROW_GAP);
+                    let [r, g, b] = PASS_COLORS[i % PASS_COLORS.len()];
+                    quads.rect(x, y, w, ROW_HEIGHT, [r, g, b, 0.9]);
+                    cursor[pass.depth] += w;
+                    cursor[pass.depth + 1] = x;
+                }
+            }
+        }
+
+        // One marker per entry (repeats are already folded), newest on the right
+        let log_y = graph_bottom + ROW_GAP + MAX_ROWS as f32 * (ROW_HEIGHT + ROW_GAP);
+        let markers = log.iter().filter(|entry| entry.level <= log::Level::Warn);
+        let skip = markers.clone().count().saturating_sub(MAX_LOG_MARKERS);
+        for (i, entry) in markers.skip(skip).enumerate() {
+            let color = if entry.level == log::Level::Error { LOG_ERROR_COLOR } else { LOG_WARN_COLOR };
+            quads.rect(MARGIN + i as f32 * (ROW_HEIGHT + ROW_GAP), log_y, ROW_HEIGHT, ROW_HEIGHT, color);
+        }
+
+        quads.vertices
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &mut Profiler,
+        target: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let since = Instant::now().checked_sub(LOG_WINDOW);
+        let log: Vec<LogEntry> = diagnostics::recent(diagnostics::LOG_CAPACITY).into_iter()
+            .filter(|entry| since.is_none_or(|since| entry.time >= since))
+            .collect();
+        let vertices = Self::build_vertices(profiler, &log, width, height);
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+
+        let queries = profiler.begin_pass("Overlay");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw(0..vertices.len() as u32, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
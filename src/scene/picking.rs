@@ -0,0 +1,442 @@
+use std::sync::mpsc;
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+use crate::diagnostics;
+use crate::model::{Material, Model, ModelVertex};
+use super::clipping::{Clipping, MAX_CLIP_PLANES};
+use super::profiler::Profiler;
+use super::ObjectId;
+
+pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+const ID_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+/// Field of view of the one-texel view rendered for ray picks; its texel center lies on the ray
+const RAY_PICK_FOV: f32 = 0.001;
+const RAY_PICK_NEAR: f32 = 0.01;
+
+/// Identifies a pick request until its result arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PickId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PickResult {
+    pub id: PickId,
+    /// The object covering the pixel or hit first by the ray, `None` over empty space
+    pub object: Option<ObjectId>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PickTarget {
+    /// Texel of the ID target
+    Pixel(u32, u32),
+    Ray { origin: Vec3, direction: Vec3 },
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickCameraUniform {
+    view_proj: [[f32; 4]; 4],
+    clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
+    clip: [f32; 4],
+}
+
+impl PickCameraUniform {
+    fn new(view_proj: Mat4, clipping: &Clipping) -> Self {
+        let planes = clipping.active_planes();
+        let mut uniform = Self {
+            view_proj: view_proj.to_cols_array_2d(),
+            clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+            clip: [planes.len() as f32, 0.0, 0.0, 0.0],
+        };
+        for (uniform, plane) in uniform.clip_planes.iter_mut().zip(planes) {
+            *uniform = plane.to_vec4().to_array();
+        }
+        uniform
+    }
+}
+
+/// Signals when a pick's staging buffer is mapped
+type MapReceiver = mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>;
+
+/// A mesh-drawing object for the ID pass: its model, object material override and model bind group
+pub(crate) type PickObject<'a> = (&'a Model, Option<&'a Material>, &'a wgpu::BindGroup);
+
+/// Object ID buffer for pixel-accurate selection.
+///
+/// The ID target is rendered at the scene's render size while `enabled` or while pixel picks are
+/// waiting. Ray picks (e.g. from a VR pointer) render their own one-texel view along the ray.
+/// Results are copied to small staging buffers and read back without stalling the frame.
+pub struct PickingPass {
+    /// Render the ID target every frame, not just when a pixel pick needs it
+    pub enabled: bool,
+    culled: wgpu::RenderPipeline,
+    double_sided: wgpu::RenderPipeline,
+    camera_layout: wgpu::BindGroupLayout,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    requests: Vec<(PickId, PickTarget)>,
+    /// Copied this frame, mapped once the frame is submitted
+    copied: Vec<(PickId, wgpu::Buffer)>,
+    mapping: Vec<(PickId, wgpu::Buffer, MapReceiver)>,
+    /// Answered without touching the GPU, reported by the next `poll`
+    missed: Vec<PickId>,
+    /// Render the ID target next frame for something other than a pick, like the pixel inspector
+    ids_requested: bool,
+    next_id: u32,
+}
+
+impl PickingPass {
+    pub fn new(device: &wgpu::Device, model_bind_group_layout: &wgpu::BindGroupLayout, width: u32, height: u32) -> Self {
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Picking Camera Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Camera Buffer"),
+            size: std::mem::size_of::<PickCameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let camera_bind_group = create_camera_bind_group(device, &camera_layout, &camera_buffer);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/picking.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[&camera_layout, model_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, double_sided| {
+            diagnostics::scoped(device, label, || {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: Some("vs_main"),
+                        buffers: &[ModelVertex::desc()],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: Some("fs_main"),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: ID_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        front_face: wgpu::FrontFace::Ccw,
+                        cull_mode: if double_sided { None } else { Some(wgpu::Face::Back) },
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: ID_DEPTH_FORMAT,
+                        depth_write_enabled: true,
+                        depth_compare: wgpu::CompareFunction::Less,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                    cache: None,
+                })
+            })
+        };
+        let culled = pipeline("Picking Pipeline", false);
+        let double_sided = pipeline("Double-Sided Picking Pipeline", true);
+
+        let (id_texture, id_view, depth_view) = create_targets(device, width, height);
+        Self {
+            enabled: false,
+            culled,
+            double_sided,
+            camera_layout,
+            camera_buffer,
+            camera_bind_group,
+            id_texture,
+            id_view,
+            depth_view,
+            requests: Vec::new(),
+            copied: Vec::new(),
+            mapping: Vec::new(),
+            missed: Vec::new(),
+            ids_requested: false,
+            next_id: 0,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.id_texture, self.id_view, self.depth_view) = create_targets(device, width, height);
+    }
+
+    /// The ID target: `R32Uint` holding object ID + 1, 0 where nothing was drawn
+    pub fn id_view(&self) -> &wgpu::TextureView {
+        &self.id_view
+    }
+
+    pub(crate) fn id_texture(&self) -> &wgpu::Texture {
+        &self.id_texture
+    }
+
+    /// Draw the ID target next frame even if nothing is picked
+    pub(crate) fn request_ids(&mut self) {
+        self.ids_requested = true;
+    }
+
+    /// Pick the object at texel `(x, y)` of the ID target, clamped to its size
+    pub fn pick(&mut self, x: u32, y: u32) -> PickId {
+        let x = x.min(self.id_texture.width() - 1);
+        let y = y.min(self.id_texture.height() - 1);
+        self.request(PickTarget::Pixel(x, y))
+    }
+
+    /// Pick the first object along a world-space ray
+    pub fn pick_ray(&mut self, origin: Vec3, direction: Vec3) -> PickId {
+        self.request(PickTarget::Ray { origin, direction: direction.normalize_or_zero() })
+    }
+
+    /// A pick known to hit nothing, such as one outside the viewport
+    pub fn miss(&mut self) -> PickId {
+        let id = PickId(self.next_id);
+        self.next_id += 1;
+        self.missed.push(id);
+        id
+    }
+
+    fn request(&mut self, target: PickTarget) -> PickId {
+        let id = PickId(self.next_id);
+        self.next_id += 1;
+        self.requests.push((id, target));
+        id
+    }
+
+    /// Requests not yet answered, including those waiting to be rendered
+    pub fn pending(&self) -> usize {
+        self.requests.len() + self.copied.len() + self.mapping.len() + self.missed.len()
+    }
+
+    /// Render the ID views the frame needs and copy out the texels of this frame's picks
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view_proj: Mat4,
+        far: f32,
+        clipping: &Clipping,
+        objects: &[PickObject],
+        profiler: &mut Profiler,
+    ) {
+        let requests = std::mem::take(&mut self.requests);
+        let pixel_picks = requests.iter().any(|(_, target)| matches!(target, PickTarget::Pixel(..)));
+        let draw_ids = self.enabled || pixel_picks || std::mem::take(&mut self.ids_requested);
+        if !draw_ids && requests.is_empty() {
+            return;
+        }
+
+        profiler.begin_scope("Picking");
+        if draw_ids {
+            queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&PickCameraUniform::new(view_proj, clipping)));
+            self.draw(encoder, &self.camera_bind_group, &self.id_view, &self.depth_view, objects, profiler);
+        }
+
+        for (id, target) in requests {
+            let texel = match target {
+                PickTarget::Pixel(x, y) => {
+                    let origin = wgpu::Origin3d { x, y, z: 0 };
+                    copy_texel(device, encoder, &self.id_texture, origin)
+                }
+                PickTarget::Ray { origin, direction } => {
+                    let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+                    let view_proj = Mat4::perspective_rh(RAY_PICK_FOV, 1.0, RAY_PICK_NEAR, far)
+                        * Mat4::look_to_rh(origin, direction, up);
+                    let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Ray Pick Camera Buffer"),
+                        contents: bytemuck::bytes_of(&PickCameraUniform::new(view_proj, clipping)),
+                        usage: wgpu::BufferUsages::UNIFORM,
+                    });
+                    let camera_bind_group = create_camera_bind_group(device, &self.camera_layout, &camera_buffer);
+                    let (texture, view, depth_view) = create_targets(device, 1, 1);
+                    self.draw(encoder, &camera_bind_group, &view, &depth_view, objects, profiler);
+                    copy_texel(device, encoder, &texture, wgpu::Origin3d::ZERO)
+                }
+            };
+            self.copied.push((id, texel));
+        }
+        profiler.end_scope();
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        camera_bind_group: &wgpu::BindGroup,
+        id_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        objects: &[PickObject],
+        profiler: &mut Profiler,
+    ) {
+        let queries = profiler.begin_pass("Object IDs");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+            occlusion_query_set: None,
+        });
+        pass.set_bind_group(0, camera_bind_group, &[]);
+        for (model, material_override, model_bind_group) in objects {
+            pass.set_bind_group(1, *model_bind_group, &[]);
+            for mesh in model.all_meshes() {
+                let material = material_override.or_else(|| model.materials.get(mesh.material_index));
+                let double_sided = material.is_some_and(|material| material.double_sided);
+                pass.set_pipeline(if double_sided { &self.double_sided } else { &self.culled });
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+        drop(pass);
+        profiler.end_scope();
+    }
+
+    /// Start mapping the texels copied by the frame just submitted
+    pub(crate) fn after_submit(&mut self) {
+        for (id, buffer) in self.copied.drain(..) {
+            let (sender, receiver) = mpsc::channel();
+            buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            self.mapping.push((id, buffer, receiver));
+        }
+    }
+
+    /// Results of picks whose readback has finished, without waiting for the GPU
+    pub fn poll(&mut self, device: &wgpu::Device) -> Vec<PickResult> {
+        let mut results: Vec<_> = self.missed.drain(..).map(|id| PickResult { id, object: None }).collect();
+        if self.mapping.is_empty() {
+            return results;
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        self.mapping.retain(|(id, buffer, receiver)| {
+            let mapped = match receiver.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return true,
+                Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+            };
+            let object = match mapped {
+                Ok(()) => {
+                    let value = u32::from_le_bytes(buffer.slice(..).get_mapped_range()[..4].try_into().unwrap());
+                    buffer.unmap();
+                    value.checked_sub(1).map(ObjectId)
+                }
+                Err(e) => {
+                    log::warn!("Pick {} readback failed: {}", id.0, e);
+                    None
+                }
+            };
+            results.push(PickResult { id: *id, object });
+            false
+        });
+        results
+    }
+}
+
+fn create_camera_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Picking Camera Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    })
+}
+
+fn create_targets(device: &wgpu::Device, width: u32, height: u32) -> (wgpu::Texture, wgpu::TextureView, wgpu::TextureView) {
+    let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Object ID Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ID_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Object ID Depth Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ID_DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let id_view = id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (id_texture, id_view, depth_view)
+}
+
+/// Copy one ID texel into a staging buffer that can be mapped once the frame is submitted
+fn copy_texel(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture, origin: wgpu::Origin3d) -> wgpu::Buffer {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Pick Readback Buffer"),
+        size: 4,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging,
+            // A single row needs no row pitch
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: None,
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+    );
+    staging
+}
@@ -0,0 +1,166 @@
+use glam::{EulerRot, Quat, Vec3};
+use super::{ObjectId, Ray, Scene, Transform};
+
+/// Rounds of pushing a placed object out of the objects it overlaps
+const DEPENETRATION_PASSES: usize = 4;
+/// Overlaps thinner than this count as touching
+const CONTACT_EPSILON: f32 = 1e-4;
+
+/// How `place` puts an object on the surface under a cursor or controller ray.
+/// Surfaces are object bounds, so a model rests on its bounding box rather than its triangles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    /// Snap along the surface to a grid with this spacing, in meters
+    pub grid: Option<f32>,
+    /// Turn the object's up axis to the surface normal, e.g. to hang it on a wall
+    pub align_to_normal: bool,
+    /// Snap the object's turn about its up axis to multiples of this angle, in radians
+    pub angle_step: Option<f32>,
+    /// Push the object out of neighbours it would overlap, never into the surface
+    pub depenetrate: bool,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            grid: None,
+            align_to_normal: false,
+            angle_step: None,
+            depenetrate: true,
+        }
+    }
+}
+
+impl Placement {
+    /// The transform that rests `object` on the surface `ray` hits first, ignoring the object
+    /// itself; `None` if the ray hits nothing or the object isn't in the scene
+    pub fn place(&self, scene: &Scene, object: ObjectId, ray: &Ray) -> Option<Transform> {
+        let current = scene.object(object)?;
+        let hit = scene.raycast(ray, Some(object))?;
+
+        let mut transform = current.transform;
+        transform.rotation = self.orientation(current.transform.rotation, hit.normal);
+        transform.position = self.snap(hit.point, hit.normal);
+
+        // Rest the bounds on the surface rather than the object's origin
+        let (min, max) = current.bounds_at(&scene.assets, &transform)?;
+        transform.position += hit.normal * (hit.point.dot(hit.normal) - lowest(min, max, hit.normal));
+
+        if self.depenetrate {
+            for _ in 0..DEPENETRATION_PASSES {
+                let (min, max) = current.bounds_at(&scene.assets, &transform)?;
+                let push = scene.objects.iter()
+                    .filter(|other| other.id != object)
+                    .filter_map(|other| other.world_bounds(&scene.assets))
+                    .filter_map(|(other_min, other_max)| separation((min, max), (other_min, other_max), hit.normal))
+                    .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()));
+                match push {
+                    Some(push) => transform.position += push,
+                    None => break,
+                }
+            }
+        }
+        Some(transform)
+    }
+
+    /// Euler rotation for an object currently at `rotation` placed on a surface with `normal`
+    fn orientation(&self, rotation: Vec3, normal: Vec3) -> Vec3 {
+        let mut yaw = rotation.y;
+        if let Some(step) = self.angle_step.filter(|step| *step > 0.0) {
+            yaw = (yaw / step).round() * step;
+        }
+        if !self.align_to_normal {
+            return Vec3::new(rotation.x, yaw, rotation.z);
+        }
+        let aligned = Quat::from_rotation_arc(Vec3::Y, normal) * Quat::from_rotation_y(yaw);
+        let (x, y, z) = aligned.to_euler(EulerRot::XYZ);
+        Vec3::new(x, y, z)
+    }
+
+    /// `point` snapped to the grid across the surface; its height off the surface is kept
+    fn snap(&self, point: Vec3, normal: Vec3) -> Vec3 {
+        let Some(grid) = self.grid.filter(|grid| *grid > 0.0) else {
+            return point;
+        };
+        let normal_axis = dominant_axis(normal);
+        let mut snapped = point;
+        for axis in 0..3 {
+            if axis != normal_axis {
+                snapped[axis] = (point[axis] / grid).round() * grid;
+            }
+        }
+        snapped
+    }
+}
+
+fn dominant_axis(v: Vec3) -> usize {
+    let v = v.abs();
+    if v.x >= v.y && v.x >= v.z {
+        0
+    } else if v.y >= v.z {
+        1
+    } else {
+        2
+    }
+}
+
+/// The smallest projection of the box `min..max` onto `direction`
+fn lowest(min: Vec3, max: Vec3, direction: Vec3) -> f32 {
+    let lowest = (min * direction).min(max * direction);
+    lowest.x + lowest.y + lowest.z
+}
+
+/// The shortest move taking box `a` out of box `b`, or `None` if they don't overlap.
+/// Moves against `normal` would push the object into the surface it rests on, so aren't used.
+fn separation(a: (Vec3, Vec3), b: (Vec3, Vec3), normal: Vec3) -> Option<Vec3> {
+    let overlap = a.1.min(b.1) - a.0.max(b.0);
+    if overlap.min_element() <= CONTACT_EPSILON {
+        return None;
+    }
+    let mut best: Option<Vec3> = None;
+    for axis in 0..3 {
+        for (sign, distance) in [(1.0, b.1[axis] - a.0[axis]), (-1.0, a.1[axis] - b.0[axis])] {
+            let mut push = Vec3::ZERO;
+            push[axis] = sign * distance;
+            if push.dot(normal) < -CONTACT_EPSILON {
+                continue;
+            }
+            if best.is_none_or(|best| distance < best.length()) {
+                best = Some(push);
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_separation_keeps_to_surface() {
+        let crate_box = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 2.0, 2.0));
+        // Overlapping the right side of a wall by 0.5
+        let wall = (Vec3::new(-1.0, 0.0, -5.0), Vec3::new(0.5, 3.0, 5.0));
+        assert_eq!(separation(crate_box, wall, Vec3::Y), Some(Vec3::new(0.5, 0.0, 0.0)));
+        // Pushing down would be shortest, but the object rests on a floor below
+        let shelf = (Vec3::new(-5.0, 1.9, -5.0), Vec3::new(5.0, 3.0, 5.0));
+        assert_eq!(separation(crate_box, shelf, Vec3::Y), Some(Vec3::new(0.0, 3.0, 0.0)));
+        // Touching isn't overlapping
+        let neighbour = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(4.0, 2.0, 2.0));
+        assert_eq!(separation(crate_box, neighbour, Vec3::Y), None);
+    }
+
+    #[test]
+    fn test_snap_and_orientation() {
+        let placement = Placement { grid: Some(0.5), angle_step: Some(std::f32::consts::FRAC_PI_2), ..Placement::default() };
+        assert_eq!(placement.snap(Vec3::new(1.3, 0.7, -0.2), Vec3::Y), Vec3::new(1.5, 0.7, 0.0));
+        assert_eq!(placement.orientation(Vec3::new(0.0, 1.2, 0.0), Vec3::Y), Vec3::new(0.0, std::f32::consts::FRAC_PI_2, 0.0));
+
+        // Up turned to face +X off a wall
+        let aligned = Placement { align_to_normal: true, ..Placement::default() };
+        let rotation = aligned.orientation(Vec3::ZERO, Vec3::X);
+        let up = Quat::from_euler(EulerRot::XYZ, rotation.x, rotation.y, rotation.z) * Vec3::Y;
+        assert!((up - Vec3::X).length() < 1e-5);
+    }
+}
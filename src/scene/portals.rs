@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use super::ObjectId;
+use super::camera::Camera;
+
+// Guards against cycles through portal loops; real buildings rarely see more than a few deep
+const MAX_PORTAL_DEPTH: usize = 16;
+// Clip-space w below which portal vertices count as behind the eye
+const MIN_W: f32 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CellId(pub u32);
+
+/// A convex region of space, e.g. a room; the box is used to find which cell the camera is in
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub name: String,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Cell {
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+/// A convex opening (door, window) joining two cells; visible from either side
+#[derive(Debug, Clone)]
+pub struct Portal {
+    pub cells: [CellId; 2],
+    /// Polygon corners in winding order
+    pub corners: Vec<Vec3>,
+}
+
+/// Screen-space rectangle in NDC that the view is currently restricted to
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ScreenRect {
+    min: Vec2,
+    max: Vec2,
+}
+
+impl ScreenRect {
+    const FULL: Self = Self { min: Vec2::NEG_ONE, max: Vec2::ONE };
+
+    fn intersect(&self, other: &Self) -> Option<Self> {
+        let min = self.min.max(other.min);
+        let max = self.max.min(other.max);
+        (min.x < max.x && min.y < max.y).then_some(Self { min, max })
+    }
+}
+
+/// Cell-and-portal visibility for interiors: objects assigned to cells are drawn only when
+/// their cell can be seen through a chain of portals from the camera's cell.
+/// Objects without a cell, or any object while the camera is outside every cell, are always drawn.
+#[derive(Debug, Clone, Default)]
+pub struct PortalGraph {
+    cells: Vec<Cell>,
+    portals: Vec<Portal>,
+    assignments: HashMap<ObjectId, CellId>,
+}
+
+impl PortalGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_cell(&mut self, name: &str, min: Vec3, max: Vec3) -> CellId {
+        self.cells.push(Cell { name: name.to_string(), min: min.min(max), max: min.max(max) });
+        CellId(self.cells.len() as u32 - 1)
+    }
+
+    /// Connect two cells through a convex polygon; returns false for unknown cells or degenerate polygons
+    pub fn add_portal(&mut self, a: CellId, b: CellId, corners: &[Vec3]) -> bool {
+        if self.cell(a).is_none() || self.cell(b).is_none() || corners.len() < 3 {
+            return false;
+        }
+        self.portals.push(Portal { cells: [a, b], corners: corners.to_vec() });
+        true
+    }
+
+    pub fn cell(&self, id: CellId) -> Option<&Cell> {
+        self.cells.get(id.0 as usize)
+    }
+
+    pub fn portals(&self) -> &[Portal] {
+        &self.portals
+    }
+
+    /// Put an object in a cell; it is then culled whenever that cell isn't visible
+    pub fn assign(&mut self, object: ObjectId, cell: CellId) {
+        self.assignments.insert(object, cell);
+    }
+
+    pub fn unassign(&mut self, object: ObjectId) {
+        self.assignments.remove(&object);
+    }
+
+    pub fn cell_of(&self, object: ObjectId) -> Option<CellId> {
+        self.assignments.get(&object).copied()
+    }
+
+    /// Move every cell and portal, e.g. for a floating-origin rebase
+    pub fn translate(&mut self, delta: Vec3) {
+        for cell in &mut self.cells {
+            cell.min += delta;
+            cell.max += delta;
+        }
+        for corner in self.portals.iter_mut().flat_map(|portal| portal.corners.iter_mut()) {
+            *corner += delta;
+        }
+    }
+
+    /// First cell containing `point`
+    pub fn cell_at(&self, point: Vec3) -> Option<CellId> {
+        self.cells.iter().position(|cell| cell.contains(point)).map(|index| CellId(index as u32))
+    }
+
+    /// Cells visible from the camera, indexed by `CellId`, or `None` when the camera isn't inside
+    /// any cell and portal culling doesn't apply
+    pub fn visible_cells(&self, camera: &Camera) -> Option<Vec<bool>> {
+        let start = self.cell_at(camera.position)?;
+        let view_proj = camera.build_view_projection_matrix();
+        let mut visible = vec![false; self.cells.len()];
+        let mut path = Vec::new();
+        self.visit(start, ScreenRect::FULL, &view_proj, camera, &mut visible, &mut path);
+        Some(visible)
+    }
+
+    /// Whether an object passes portal culling given the result of `visible_cells`
+    pub fn is_visible(&self, object: ObjectId, visible_cells: Option<&[bool]>) -> bool {
+        match (visible_cells, self.cell_of(object)) {
+            (Some(visible), Some(cell)) => visible.get(cell.0 as usize).copied().unwrap_or(true),
+            _ => true,
+        }
+    }
+
+    // Mark `cell` visible, then recurse through each portal whose screen bounds overlap `rect`,
+    // narrowing the rect to the overlap. `path` holds the portals crossed to get here.
+    fn visit(
+        &self,
+        cell: CellId,
+        rect: ScreenRect,
+        view_proj: &Mat4,
+        camera: &Camera,
+        visible: &mut [bool],
+        path: &mut Vec<usize>,
+    ) {
+        visible[cell.0 as usize] = true;
+        if path.len() >= MAX_PORTAL_DEPTH {
+            return;
+        }
+
+        for (index, portal) in self.portals.iter().enumerate() {
+            let next = match portal.cells {
+                [a, b] if a == cell => b,
+                [a, b] if b == cell => a,
+                _ => continue,
+            };
+            if path.contains(&index) {
+                continue;
+            }
+            let Some(bounds) = portal_screen_rect(portal, view_proj, camera) else {
+                continue;
+            };
+            if let Some(narrowed) = rect.intersect(&bounds) {
+                path.push(index);
+                self.visit(next, narrowed, view_proj, camera, visible, path);
+                path.pop();
+            }
+        }
+    }
+}
+
+/// NDC bounds of a portal polygon clipped to the part in front of the eye
+fn portal_screen_rect(portal: &Portal, view_proj: &Mat4, camera: &Camera) -> Option<ScreenRect> {
+    // Standing in the doorway: the portal may be clipped away entirely but the next cell is in view
+    let center = portal.corners.iter().copied().sum::<Vec3>() / portal.corners.len() as f32;
+    let radius = portal.corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max);
+    if camera.position.distance(center) < radius.max(camera.near * 2.0) && distance_to_plane(portal, camera.position) < camera.near * 2.0 {
+        return Some(ScreenRect::FULL);
+    }
+
+    let clip: Vec<Vec4> = portal.corners.iter().map(|corner| *view_proj * corner.extend(1.0)).collect();
+    let clipped = clip_in_front(&clip);
+    if clipped.is_empty() {
+        return None;
+    }
+    let mut rect = ScreenRect { min: Vec2::splat(f32::INFINITY), max: Vec2::splat(f32::NEG_INFINITY) };
+    for point in clipped {
+        let ndc = Vec2::new(point.x, point.y) / point.w;
+        rect.min = rect.min.min(ndc);
+        rect.max = rect.max.max(ndc);
+    }
+    Some(rect)
+}
+
+fn distance_to_plane(portal: &Portal, point: Vec3) -> f32 {
+    let [a, b, c] = [portal.corners[0], portal.corners[1], portal.corners[2]];
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    (point - a).dot(normal).abs()
+}
+
+/// Sutherland-Hodgman against the plane w = MIN_W
+fn clip_in_front(polygon: &[Vec4]) -> Vec<Vec4> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, &current) in polygon.iter().enumerate() {
+        let previous = polygon[(i + polygon.len() - 1) % polygon.len()];
+        let (current_in, previous_in) = (current.w >= MIN_W, previous.w >= MIN_W);
+        if current_in != previous_in {
+            let t = (MIN_W - previous.w) / (current.w - previous.w);
+            out.push(previous + (current - previous) * t);
+        }
+        if current_in {
+            out.push(current);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three rooms in a row along -z: hall (0) -> office (1) -> closet (2), plus a side room (3)
+    // reached from the hall through a door in the +x wall
+    fn building() -> (PortalGraph, [CellId; 4]) {
+        let mut graph = PortalGraph::new();
+        let hall = graph.add_cell("hall", Vec3::new(-5.0, 0.0, -10.0), Vec3::new(5.0, 3.0, 0.0));
+        let office = graph.add_cell("office", Vec3::new(-5.0, 0.0, -20.0), Vec3::new(5.0, 3.0, -10.0));
+        let closet = graph.add_cell("closet", Vec3::new(-5.0, 0.0, -30.0), Vec3::new(5.0, 3.0, -20.0));
+        let side = graph.add_cell("side", Vec3::new(5.0, 0.0, -10.0), Vec3::new(15.0, 3.0, 0.0));
+        let door = |x: f32, z: f32| vec![
+            Vec3::new(x - 0.5, 0.0, z), Vec3::new(x + 0.5, 0.0, z),
+            Vec3::new(x + 0.5, 2.0, z), Vec3::new(x - 0.5, 2.0, z),
+        ];
+        assert!(graph.add_portal(hall, office, &door(0.0, -10.0)));
+        // Offset so it's hidden behind the office door's frame when looking down the hall
+        assert!(graph.add_portal(office, closet, &door(4.0, -20.0)));
+        assert!(graph.add_portal(hall, side, &[
+            Vec3::new(5.0, 0.0, -1.0), Vec3::new(5.0, 0.0, -2.0),
+            Vec3::new(5.0, 2.0, -2.0), Vec3::new(5.0, 2.0, -1.0),
+        ]));
+        assert!(!graph.add_portal(hall, CellId(9), &door(0.0, 0.0)));
+        (graph, [hall, office, closet, side])
+    }
+
+    fn camera_at(position: Vec3, yaw: f32) -> Camera {
+        Camera { yaw, ..Camera::new(position, 1.0) }
+    }
+
+    #[test]
+    fn test_visible_through_portals() {
+        let (graph, [hall, office, closet, side]) = building();
+
+        // Looking down the hall (-z) through the office door
+        let camera = camera_at(Vec3::new(0.0, 1.5, -2.0), -90.0);
+        let visible = graph.visible_cells(&camera).unwrap();
+        assert!(visible[hall.0 as usize] && visible[office.0 as usize]);
+        // The closet door is outside the office door's screen rect, and the side door is behind us
+        assert!(!visible[closet.0 as usize]);
+        assert!(!visible[side.0 as usize]);
+
+        // Turning to face the side door (+x) shows the side room but not the office
+        let camera = camera_at(Vec3::new(0.0, 1.5, -1.5), 0.0);
+        let visible = graph.visible_cells(&camera).unwrap();
+        assert!(visible[side.0 as usize]);
+        assert!(!visible[office.0 as usize]);
+
+        // Outside every cell nothing is culled
+        assert!(graph.visible_cells(&camera_at(Vec3::new(50.0, 1.0, 50.0), 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_object_visibility() {
+        let (mut graph, [hall, office, closet, _]) = building();
+        graph.assign(ObjectId(1), closet);
+        graph.assign(ObjectId(2), office);
+        graph.assign(ObjectId(3), hall);
+
+        let visible = graph.visible_cells(&camera_at(Vec3::new(0.0, 1.5, -2.0), -90.0));
+        let visible = visible.as_deref();
+        assert!(!graph.is_visible(ObjectId(1), visible));
+        assert!(graph.is_visible(ObjectId(2), visible));
+        assert!(graph.is_visible(ObjectId(3), visible));
+        // Unassigned objects are never culled
+        assert!(graph.is_visible(ObjectId(4), visible));
+
+        graph.unassign(ObjectId(1));
+        assert!(graph.is_visible(ObjectId(1), visible));
+    }
+
+    #[test]
+    fn test_standing_in_doorway() {
+        let (graph, [_, office, _, _]) = building();
+        // Right at the office door facing sideways: the door is clipped but the office must stay visible
+        let camera = camera_at(Vec3::new(0.0, 1.0, -9.99), 0.0);
+        let visible = graph.visible_cells(&camera).unwrap();
+        assert!(visible[office.0 as usize]);
+    }
+
+    #[test]
+    fn test_clip_in_front() {
+        let square = [
+            Vec4::new(-1.0, -1.0, 0.0, 1.0),
+            Vec4::new(1.0, -1.0, 0.0, 1.0),
+            Vec4::new(1.0, 1.0, 0.0, -1.0),
+            Vec4::new(-1.0, 1.0, 0.0, -1.0),
+        ];
+        let clipped = clip_in_front(&square);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|point| point.w >= MIN_W - 1e-6));
+        assert!(clip_in_front(&square.map(|point| Vec4::new(point.x, point.y, 0.0, -1.0))).is_empty());
+    }
+}
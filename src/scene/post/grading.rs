@@ -0,0 +1,686 @@
+use std::path::Path;
+use anyhow::Result;
+use wgpu::util::DeviceExt;
+use crate::scene::profiler::{PassQueries, Profiler};
+use crate::scene::viewport::Viewport;
+use crate::shaders;
+use super::{begin_fullscreen_pass, fullscreen_pipeline, texture_entry, RenderTarget};
+
+const HISTOGRAM_BINS: u64 = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+// Middle grey the auto-exposure aims the average luminance at
+const EXPOSURE_KEY_VALUE: f32 = 0.18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemapper {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemapper {
+    fn index(&self) -> u32 {
+        match self {
+            Tonemapper::None => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::Aces => 2,
+        }
+    }
+}
+
+/// How the final pass writes alpha, matching the surface's composite alpha mode. The scene
+/// clears to transparent black for the transparent modes, so the desktop shows through
+/// wherever nothing was drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputAlpha {
+    /// Alpha is ignored and the window is opaque
+    #[default]
+    Opaque,
+    /// Color already multiplied by alpha
+    PreMultiplied,
+    /// Straight alpha; the compositor multiplies color by it
+    PostMultiplied,
+}
+
+impl OutputAlpha {
+    pub fn is_transparent(self) -> bool {
+        self != OutputAlpha::Opaque
+    }
+
+    /// The composite alpha mode to configure the surface with, if it supports one for this;
+    /// opaque output takes the first supported mode when `Opaque` isn't among them
+    pub fn composite_mode(self, supported: &[wgpu::CompositeAlphaMode]) -> Option<wgpu::CompositeAlphaMode> {
+        let wanted = match self {
+            OutputAlpha::Opaque => wgpu::CompositeAlphaMode::Opaque,
+            OutputAlpha::PreMultiplied => wgpu::CompositeAlphaMode::PreMultiplied,
+            OutputAlpha::PostMultiplied => wgpu::CompositeAlphaMode::PostMultiplied,
+        };
+        if supported.contains(&wanted) {
+            Some(wanted)
+        } else if self == OutputAlpha::Opaque {
+            supported.first().copied()
+        } else {
+            None
+        }
+    }
+
+    /// Clear color of the scene and of the surface outside the viewport
+    pub fn clear_color(self, opaque: wgpu::Color) -> wgpu::Color {
+        if self.is_transparent() { wgpu::Color::TRANSPARENT } else { opaque }
+    }
+
+    fn index(self) -> u32 {
+        match self {
+            OutputAlpha::Opaque => 0,
+            OutputAlpha::PreMultiplied => 1,
+            OutputAlpha::PostMultiplied => 2,
+        }
+    }
+}
+
+/// Exposure and grading controls applied when resolving HDR to the surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorGrading {
+    /// Exposure compensation in stops, applied on top of auto-exposure
+    pub exposure_ev: f32,
+    pub auto_exposure: bool,
+    /// How quickly auto-exposure adapts to luminance changes (per second)
+    pub adaptation_speed: f32,
+    /// Luminance range (log2) covered by the auto-exposure histogram
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    pub tonemapper: Tonemapper,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Blend between the graded color (0) and the LUT output (1)
+    pub lut_strength: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            exposure_ev: 0.0,
+            auto_exposure: false,
+            adaptation_speed: 1.5,
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            tonemapper: Tonemapper::None,
+            contrast: 1.0,
+            saturation: 1.0,
+            lut_strength: 0.0,
+        }
+    }
+}
+
+impl ColorGrading {
+    /// Fraction of the remaining luminance gap closed after `dt` seconds
+    pub fn adaptation_factor(&self, dt: f32) -> f32 {
+        1.0 - (-dt.max(0.0) * self.adaptation_speed.max(0.0)).exp()
+    }
+}
+
+/// A 3D color lookup table stored as RGBA8 texels (`size`³ entries)
+#[derive(Debug, Clone)]
+pub struct ColorLut {
+    pub size: u32,
+    pub data: Vec<u8>,
+}
+
+impl ColorLut {
+    pub fn identity(size: u32) -> Self {
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        let scale = 255.0 / (size - 1).max(1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&[
+                        (r as f32 * scale).round() as u8,
+                        (g as f32 * scale).round() as u8,
+                        (b as f32 * scale).round() as u8,
+                        255,
+                    ]);
+                }
+            }
+        }
+        Self { size, data }
+    }
+
+    /// Parse the common horizontal strip layout: `size` tiles of `size`x`size`, one per blue slice
+    pub fn from_strip_rgba(width: u32, height: u32, pixels: &[u8]) -> Result<Self> {
+        let size = height;
+        if size < 2 || width != size * size {
+            return Err(anyhow::anyhow!("LUT strip must be (N*N)xN pixels, got {}x{}", width, height));
+        }
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(anyhow::anyhow!("LUT strip has {} bytes, expected {}", pixels.len(), width * height * 4));
+        }
+
+        let mut data = Vec::with_capacity(pixels.len());
+        for b in 0..size {
+            for g in 0..size {
+                let row = (g * width + b * size) as usize * 4;
+                data.extend_from_slice(&pixels[row..row + size as usize * 4]);
+            }
+        }
+        Ok(Self { size, data })
+    }
+
+    pub fn from_strip_image(path: &Path) -> Result<Self> {
+        let img = image::open(path)?.to_rgba8();
+        Self::from_strip_rgba(img.width(), img.height(), img.as_raw())
+    }
+
+    fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+        let size = wgpu::Extent3d {
+            width: self.size,
+            height: self.size,
+            depth_or_array_layers: self.size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Grading LUT"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            &self.data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * self.size),
+                rows_per_image: Some(self.size),
+            },
+            size,
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GradingUniform {
+    exposure: f32,
+    contrast: f32,
+    saturation: f32,
+    lut_strength: f32,
+    tonemapper: u32,
+    auto_exposure: u32,
+    key_value: f32,
+    lut_size: f32,
+    // x is overwritten on the GPU with the adapted scene luminance
+    luminance: [f32; 4],
+    output_alpha: u32,
+    _padding: [u32; 3],
+}
+
+// Byte offset of `luminance` inside GradingUniform
+const LUMINANCE_OFFSET: u64 = 32;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    pixel_count: f32,
+}
+
+/// Histogram-based average luminance with eye adaptation, computed on the GPU
+struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    histogram_buffer: wgpu::Buffer,
+    luminance_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+}
+
+impl AutoExposure {
+    fn new(device: &wgpu::Device) -> Self {
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram"),
+            size: HISTOGRAM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Adapted Luminance"),
+            contents: bytemuck::cast_slice(&[EXPOSURE_KEY_VALUE]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Params"),
+            size: std::mem::size_of::<ExposureParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = shaders::create_module(device, "Exposure Shader", include_str!("../../../shaders/exposure.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let histogram_pipeline = compute_pipeline("Luminance Histogram Pipeline", "build_histogram");
+        let average_pipeline = compute_pipeline("Luminance Average Pipeline", "average_luminance");
+
+        Self {
+            histogram_pipeline,
+            average_pipeline,
+            bind_group_layout,
+            histogram_buffer,
+            luminance_buffer,
+            params_buffer,
+        }
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, source: &RenderTarget) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.luminance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        bind_group: &wgpu::BindGroup,
+        grading: &ColorGrading,
+        dt: f32,
+        source: &RenderTarget,
+        profiler: &mut Profiler,
+    ) {
+        let wgpu::Extent3d { width, height, .. } = source.texture.size();
+        let params = ExposureParams {
+            min_log_luminance: grading.min_log_luminance,
+            log_luminance_range: (grading.max_log_luminance - grading.min_log_luminance).max(0.001),
+            adaptation: grading.adaptation_factor(dt),
+            pixel_count: (width * height) as f32,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+
+        let queries = profiler.begin_pass("Auto Exposure");
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Auto Exposure Pass"),
+            timestamp_writes: queries.as_ref().map(PassQueries::compute_writes),
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.dispatch_workgroups(
+            width.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            height.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            1,
+        );
+        pass.set_pipeline(&self.average_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+/// Final pass: exposure, tonemapping, contrast/saturation and LUT, written to the surface
+pub struct GradingPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    lut_view: wgpu::TextureView,
+    lut_size: u32,
+    lut_sampler: wgpu::Sampler,
+    exposure: AutoExposure,
+    from_hdr: (wgpu::BindGroup, wgpu::BindGroup),
+    from_ssr: (wgpu::BindGroup, wgpu::BindGroup),
+}
+
+impl GradingPass {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+        hdr: &RenderTarget,
+        ssr_output: &RenderTarget,
+        sampler: &wgpu::Sampler,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Grading Uniform Buffer"),
+            size: std::mem::size_of::<GradingUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grading Bind Group Layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Float { filterable: true }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = shaders::create_module(device, "Grading Shader", include_str!("../../../shaders/grading.wgsl"));
+        let pipeline = fullscreen_pipeline(device, "Grading Pipeline", &shader, &bind_group_layout, output_format);
+
+        let lut = ColorLut::identity(16);
+        let lut_view = lut.create_texture(device, queue);
+        let lut_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("LUT Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let exposure = AutoExposure::new(device);
+
+        let from_hdr = (
+            Self::create_bind_group(device, &bind_group_layout, hdr, sampler, &uniform_buffer, &lut_view, &lut_sampler),
+            exposure.create_bind_group(device, hdr),
+        );
+        let from_ssr = (
+            Self::create_bind_group(device, &bind_group_layout, ssr_output, sampler, &uniform_buffer, &lut_view, &lut_sampler),
+            exposure.create_bind_group(device, ssr_output),
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            lut_view,
+            lut_size: lut.size,
+            lut_sampler,
+            exposure,
+            from_hdr,
+            from_ssr,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &RenderTarget,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        lut_view: &wgpu::TextureView,
+        lut_sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grading Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(lut_sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreate bind groups after the source targets or LUT changed
+    pub fn rebind(&mut self, device: &wgpu::Device, hdr: &RenderTarget, ssr_output: &RenderTarget, sampler: &wgpu::Sampler) {
+        self.from_hdr = (
+            Self::create_bind_group(device, &self.bind_group_layout, hdr, sampler, &self.uniform_buffer, &self.lut_view, &self.lut_sampler),
+            self.exposure.create_bind_group(device, hdr),
+        );
+        self.from_ssr = (
+            Self::create_bind_group(device, &self.bind_group_layout, ssr_output, sampler, &self.uniform_buffer, &self.lut_view, &self.lut_sampler),
+            self.exposure.create_bind_group(device, ssr_output),
+        );
+    }
+
+    /// Replace the LUT texture; call `rebind` afterwards
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: &ColorLut) {
+        self.lut_view = lut.create_texture(device, queue);
+        self.lut_size = lut.size;
+    }
+
+    /// Adapt the auto-exposure to the luminance of `source`, which `render` then grades with.
+    /// Nothing in it depends on the rest of the frame, so it can go in a submission of its own.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_exposure(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        grading: &ColorGrading,
+        dt: f32,
+        source: &RenderTarget,
+        use_ssr_output: bool,
+        profiler: &mut Profiler,
+    ) {
+        let (_, exposure_bind_group) = if use_ssr_output { &self.from_ssr } else { &self.from_hdr };
+        self.exposure.dispatch(encoder, queue, exposure_bind_group, grading, dt, source, profiler);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        grading: &ColorGrading,
+        output_alpha: OutputAlpha,
+        use_ssr_output: bool,
+        output: &wgpu::TextureView,
+        viewport: &Viewport,
+        profiler: &mut Profiler,
+    ) {
+        let (bind_group, _) = if use_ssr_output { &self.from_ssr } else { &self.from_hdr };
+
+        let uniform = GradingUniform {
+            exposure: grading.exposure_ev.exp2(),
+            contrast: grading.contrast,
+            saturation: grading.saturation,
+            lut_strength: grading.lut_strength.clamp(0.0, 1.0),
+            tonemapper: grading.tonemapper.index(),
+            auto_exposure: grading.auto_exposure as u32,
+            key_value: EXPOSURE_KEY_VALUE,
+            lut_size: self.lut_size as f32,
+            luminance: [EXPOSURE_KEY_VALUE, 0.0, 0.0, 0.0],
+            output_alpha: output_alpha.index(),
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        if grading.auto_exposure {
+            // Feed the luminance adapted by `update_exposure` into the grading uniform without a
+            // CPU readback
+            encoder.copy_buffer_to_buffer(&self.exposure.luminance_buffer, 0, &self.uniform_buffer, LUMINANCE_OFFSET, 4);
+        }
+
+        let queries = profiler.begin_pass("Grading");
+        // The clear leaves the surface outside the viewport black, or transparent
+        let clear = output_alpha.clear_color(wgpu::Color::BLACK);
+        let mut pass = begin_fullscreen_pass(encoder, "Grading Pass", output, clear, queries.as_ref());
+        pass.set_viewport(viewport.x as f32, viewport.y as f32, viewport.width as f32, viewport.height as f32, 0.0, 1.0);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_lut() {
+        let lut = ColorLut::identity(4);
+        assert_eq!(lut.data.len(), 4 * 4 * 4 * 4);
+        // First texel is black, last is white
+        assert_eq!(&lut.data[0..4], &[0, 0, 0, 255]);
+        assert_eq!(&lut.data[lut.data.len() - 4..], &[255, 255, 255, 255]);
+        // Red increases fastest
+        assert_eq!(lut.data[4], 85);
+    }
+
+    #[test]
+    fn test_lut_from_strip() {
+        // A 2x2x2 strip: two 2x2 tiles side by side
+        let size = 2u32;
+        let width = size * size;
+        let mut pixels = Vec::new();
+        for y in 0..size {
+            for x in 0..width {
+                pixels.extend_from_slice(&[x as u8, y as u8, (x / size) as u8, 255]);
+            }
+        }
+        let lut = ColorLut::from_strip_rgba(width, size, &pixels).unwrap();
+        assert_eq!(lut.size, 2);
+        // Texel (r=1, g=1, b=1) comes from the second tile's bottom-right pixel
+        let index = ((size + 1) * size + 1) as usize * 4;
+        assert_eq!(&lut.data[index..index + 4], &[3, 1, 1, 255]);
+
+        assert!(ColorLut::from_strip_rgba(3, 2, &pixels).is_err());
+    }
+
+    #[test]
+    fn test_adaptation_factor() {
+        let grading = ColorGrading::default();
+        assert_eq!(grading.adaptation_factor(0.0), 0.0);
+        let fast = ColorGrading { adaptation_speed: 10.0, ..grading };
+        assert!(fast.adaptation_factor(0.1) > grading.adaptation_factor(0.1));
+        assert!(grading.adaptation_factor(100.0) <= 1.0);
+    }
+
+    #[test]
+    fn test_output_alpha_composite_mode() {
+        use wgpu::CompositeAlphaMode as Mode;
+        let supported = [Mode::Inherit, Mode::PreMultiplied];
+        assert_eq!(OutputAlpha::PreMultiplied.composite_mode(&supported), Some(Mode::PreMultiplied));
+        assert_eq!(OutputAlpha::PostMultiplied.composite_mode(&supported), None);
+        // Opaque prefers `Opaque` but takes whatever the surface has
+        assert_eq!(OutputAlpha::Opaque.composite_mode(&supported), Some(Mode::Inherit));
+        assert_eq!(OutputAlpha::Opaque.composite_mode(&[Mode::PreMultiplied, Mode::Opaque]), Some(Mode::Opaque));
+    }
+
+    #[test]
+    fn test_grading_uniform_layout() {
+        assert_eq!(std::mem::size_of::<GradingUniform>(), 64);
+        assert_eq!(LUMINANCE_OFFSET as usize, std::mem::offset_of!(GradingUniform, luminance));
+        let source = shaders::resolve(include_str!("../../../shaders/grading.wgsl")).unwrap().source;
+        shaders::check_uniform_layout(&source, "GradingUniform", &crate::uniform_layout!(GradingUniform {
+            exposure, contrast, saturation, lut_strength, tonemapper, auto_exposure, key_value, lut_size, luminance, output_alpha,
+        })).unwrap();
+    }
+}
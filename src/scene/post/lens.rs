@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use anyhow::{Context, Result};
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+use crate::scene::profiler::Profiler;
+use crate::shaders;
+use super::{begin_fullscreen_pass, fullscreen_pipeline, texture_entry, RenderTarget, HDR_FORMAT};
+
+/// Approximation of a headset lens for previewing the in-headset look on the desktop mirror:
+/// radial distortion plus lateral chromatic aberration
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LensDistortion {
+    /// Radial terms: a pixel at radius r (in half screen heights) shows the image at
+    /// r * (1 + k1 r² + k2 r⁴)
+    pub k1: f32,
+    pub k2: f32,
+    /// Red is magnified by (1 - this) and blue by (1 + this) relative to green
+    pub chromatic_aberration: f32,
+    /// Zoom after distortion; below 1 shows more of the image
+    pub scale: f32,
+}
+
+impl Default for LensDistortion {
+    /// No distortion
+    fn default() -> Self {
+        Self { k1: 0.0, k2: 0.0, chromatic_aberration: 0.0, scale: 1.0 }
+    }
+}
+
+/// Named `LensDistortion`s, one TOML table per headset (see `assets/lens_presets.toml`)
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct LensPresets {
+    pub presets: BTreeMap<String, LensDistortion>,
+}
+
+impl LensPresets {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read lens presets {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid lens presets {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<LensDistortion> {
+        self.presets.get(name).copied()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.presets.keys().map(String::as_str)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LensUniform {
+    params: [f32; 4],
+}
+
+/// Distorts the scene color as a headset lens would, for desktop views only
+pub struct LensPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    /// Reading the HDR target, for writing to the SSR output
+    from_hdr: wgpu::BindGroup,
+    /// Reading the SSR output, for writing back to the HDR target
+    from_ssr: wgpu::BindGroup,
+}
+
+impl LensPass {
+    pub fn new(device: &wgpu::Device, hdr: &RenderTarget, ssr_output: &RenderTarget, sampler: &wgpu::Sampler) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Lens Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[LensUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Lens Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = shaders::create_module(device, "Lens Shader", include_str!("../../../shaders/lens.wgsl"));
+        let pipeline = fullscreen_pipeline(device, "Lens Pipeline", &shader, &bind_group_layout, HDR_FORMAT);
+
+        let from_hdr = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, hdr, sampler);
+        let from_ssr = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, ssr_output, sampler);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            from_hdr,
+            from_ssr,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        source: &RenderTarget,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lens Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreate the bind groups after the targets were reallocated
+    pub fn rebind(&mut self, device: &wgpu::Device, hdr: &RenderTarget, ssr_output: &RenderTarget, sampler: &wgpu::Sampler) {
+        self.from_hdr = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, hdr, sampler);
+        self.from_ssr = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, ssr_output, sampler);
+    }
+
+    /// Distort the HDR target into `output`, or the SSR output when `from_ssr`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        lens: &LensDistortion,
+        from_ssr: bool,
+        output: &wgpu::TextureView,
+        profiler: &mut Profiler,
+    ) {
+        let uniform = LensUniform {
+            params: [lens.k1, lens.k2, lens.chromatic_aberration, lens.scale],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let queries = profiler.begin_pass("Lens");
+        let mut pass = begin_fullscreen_pass(encoder, "Lens Pass", output, wgpu::Color::TRANSPARENT, queries.as_ref());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, if from_ssr { &self.from_ssr } else { &self.from_hdr }, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lens_presets() {
+        let presets = LensPresets::from_toml(
+            r#"
+            [wide]
+            k1 = 0.3
+            chromatic_aberration = 0.02
+
+            [plain]
+            "#,
+        ).unwrap();
+        assert_eq!(presets.names().collect::<Vec<_>>(), ["plain", "wide"]);
+        let wide = presets.get("wide").unwrap();
+        assert_eq!((wide.k1, wide.k2, wide.scale), (0.3, 0.0, 1.0), "Missing terms use the defaults");
+        assert_eq!(presets.get("plain"), Some(LensDistortion::default()));
+        assert!(presets.get("missing").is_none());
+        assert!(LensPresets::from_toml("[bad]\nk1 = \"strong\"").is_err());
+
+        // The presets shipped with the viewer
+        let bundled = LensPresets::from_toml(include_str!("../../../assets/lens_presets.toml")).unwrap();
+        assert!(bundled.get("generic").is_some());
+    }
+
+    #[test]
+    fn test_lens_uniform_layout() {
+        let source = shaders::resolve(include_str!("../../../shaders/lens.wgsl")).unwrap().source;
+        shaders::check_uniform_layout(&source, "LensUniform", &crate::uniform_layout!(LensUniform {
+            params,
+        })).unwrap();
+    }
+}
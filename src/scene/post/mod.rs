@@ -0,0 +1,305 @@
+use std::time::Instant;
+use glam::Mat4;
+use crate::diagnostics;
+use super::profiler::{PassQueries, Profiler};
+use super::viewport::Viewport;
+
+pub mod grading;
+pub mod lens;
+pub mod motion_blur;
+pub mod ssr;
+
+pub use grading::{ColorGrading, ColorLut, GradingPass, OutputAlpha, Tonemapper};
+pub use lens::{LensDistortion, LensPass, LensPresets};
+pub use motion_blur::{MotionBlur, MotionBlurPass};
+pub use ssr::{EnvironmentProbe, SsrPass, SsrQuality, SsrSettings};
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Surface base color, kept for the pixel inspector; sRGB keeps dark albedo precise in 8 bits
+pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+/// Screen motion since the last frame in UV units: xy in total, zw from the camera alone
+pub const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+pub struct RenderTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl RenderTarget {
+    pub fn new(device: &wgpu::Device, label: &str, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // Copyable for the pixel inspector
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+}
+
+pub struct PostStack {
+    pub ssr_quality: SsrQuality,
+    pub environment: EnvironmentProbe,
+    pub grading: ColorGrading,
+    pub output_alpha: OutputAlpha,
+    pub motion_blur: MotionBlur,
+    /// Headset lens preview for desktop views; `None` leaves the image undistorted
+    pub lens: Option<LensDistortion>,
+    hdr: RenderTarget,
+    normal: RenderTarget,
+    albedo: RenderTarget,
+    velocity: RenderTarget,
+    ssr_output: RenderTarget,
+    sampler: wgpu::Sampler,
+    ssr: SsrPass,
+    motion_blur_pass: MotionBlurPass,
+    lens_pass: LensPass,
+    grading_pass: GradingPass,
+    /// Which target the last view was graded from, while it still holds that image; auto-exposure
+    /// adapts to it at the start of the next frame
+    graded_from_ssr_output: Option<bool>,
+    last_exposure: Instant,
+}
+
+impl PostStack {
+    /// `width`/`height` is the scene render size, which may differ from the output surface
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        depth_view: &wgpu::TextureView,
+    ) -> Self {
+        let hdr = RenderTarget::new(device, "HDR Color Target", width, height, HDR_FORMAT);
+        let normal = RenderTarget::new(device, "Normal Reflectivity Target", width, height, NORMAL_FORMAT);
+        let albedo = RenderTarget::new(device, "Albedo Target", width, height, ALBEDO_FORMAT);
+        let velocity = RenderTarget::new(device, "Velocity Target", width, height, VELOCITY_FORMAT);
+        let ssr_output = RenderTarget::new(device, "SSR Output Target", width, height, HDR_FORMAT);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let ssr = SsrPass::new(device, &hdr, &normal, depth_view, &sampler);
+        let motion_blur_pass = MotionBlurPass::new(device, &hdr, &ssr_output, &velocity, &sampler);
+        let lens_pass = LensPass::new(device, &hdr, &ssr_output, &sampler);
+        let grading_pass = GradingPass::new(device, queue, output_format, &hdr, &ssr_output, &sampler);
+
+        Self {
+            ssr_quality: SsrQuality::Medium,
+            environment: EnvironmentProbe::default(),
+            grading: ColorGrading::default(),
+            output_alpha: OutputAlpha::default(),
+            motion_blur: MotionBlur::default(),
+            lens: None,
+            hdr,
+            normal,
+            albedo,
+            velocity,
+            ssr_output,
+            sampler,
+            ssr,
+            motion_blur_pass,
+            lens_pass,
+            grading_pass,
+            graded_from_ssr_output: None,
+            last_exposure: Instant::now(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, depth_view: &wgpu::TextureView) {
+        self.hdr = RenderTarget::new(device, "HDR Color Target", width, height, HDR_FORMAT);
+        self.normal = RenderTarget::new(device, "Normal Reflectivity Target", width, height, NORMAL_FORMAT);
+        self.albedo = RenderTarget::new(device, "Albedo Target", width, height, ALBEDO_FORMAT);
+        self.velocity = RenderTarget::new(device, "Velocity Target", width, height, VELOCITY_FORMAT);
+        self.ssr_output = RenderTarget::new(device, "SSR Output Target", width, height, HDR_FORMAT);
+
+        self.ssr.rebind(device, &self.hdr, &self.normal, depth_view, &self.sampler);
+        self.motion_blur_pass.rebind(device, &self.hdr, &self.ssr_output, &self.velocity, &self.sampler);
+        self.lens_pass.rebind(device, &self.hdr, &self.ssr_output, &self.sampler);
+        self.grading_pass.rebind(device, &self.hdr, &self.ssr_output, &self.sampler);
+        // The new targets hold no frame to adapt to
+        self.graded_from_ssr_output = None;
+    }
+
+    /// Replace the color grading LUT (see `ColorLut::from_strip_image`)
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: &ColorLut) {
+        self.grading_pass.set_lut(device, queue, lut);
+        self.grading_pass.rebind(device, &self.hdr, &self.ssr_output, &self.sampler);
+    }
+
+    /// Color target the scene pass renders into
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.hdr.view
+    }
+
+    /// Normal/reflectivity target written alongside the scene color
+    pub fn normal_view(&self) -> &wgpu::TextureView {
+        &self.normal.view
+    }
+
+    /// Albedo target written alongside the scene color
+    pub fn albedo_view(&self) -> &wgpu::TextureView {
+        &self.albedo.view
+    }
+
+    /// Velocity target written alongside the scene color
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.velocity.view
+    }
+
+    /// The scene pass's color, normal/reflectivity and albedo textures, for reading back texels
+    pub(crate) fn scene_textures(&self) -> [&wgpu::Texture; 3] {
+        [&self.hdr.texture, &self.normal.texture, &self.albedo.texture]
+    }
+
+    /// Adapt auto-exposure to the last frame's final HDR image, before this frame's scene pass
+    /// overwrites it. Only depends on last frame's work, so the renderer puts it in the
+    /// submission ahead of the frame, alongside `ComputeStage::Async` tasks. Returns whether
+    /// anything was recorded.
+    pub fn update_exposure(&mut self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, profiler: &mut Profiler) -> bool {
+        let now = Instant::now();
+        let dt = (now - self.last_exposure).as_secs_f32();
+        self.last_exposure = now;
+        let Some(from_ssr_output) = self.graded_from_ssr_output.filter(|_| self.grading.auto_exposure) else {
+            return false;
+        };
+        let source = if from_ssr_output { &self.ssr_output } else { &self.hdr };
+        self.grading_pass.update_exposure(encoder, queue, &self.grading, dt, source, from_ssr_output, profiler);
+        true
+    }
+
+    /// Run the enabled post passes for a view with these matrices and write the graded result
+    /// to the surface. Motion blur and the lens preview only apply to `desktop` views, not the
+    /// eyes of a headset.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        view: Mat4,
+        projection: Mat4,
+        output: &wgpu::TextureView,
+        viewport: &Viewport,
+        desktop: bool,
+        profiler: &mut Profiler,
+    ) {
+        let ssr_settings = self.ssr_quality.settings();
+        if let Some(settings) = &ssr_settings {
+            self.ssr.render(encoder, queue, view, projection, settings, &self.environment, &self.ssr_output.view, profiler);
+        }
+
+        // Motion blur and the lens preview ping-pong between the HDR target and the SSR output
+        let mut from_ssr_output = ssr_settings.is_some();
+        if desktop && self.motion_blur.is_active() {
+            let target = if from_ssr_output { &self.hdr.view } else { &self.ssr_output.view };
+            self.motion_blur_pass.render(encoder, queue, &self.motion_blur, from_ssr_output, target, profiler);
+            from_ssr_output = !from_ssr_output;
+        }
+        if let Some(lens) = self.lens.filter(|_| desktop) {
+            let target = if from_ssr_output { &self.hdr.view } else { &self.ssr_output.view };
+            self.lens_pass.render(encoder, queue, &lens, from_ssr_output, target, profiler);
+            from_ssr_output = !from_ssr_output;
+        }
+
+        self.grading_pass.render(encoder, queue, &self.grading, self.output_alpha, from_ssr_output, output, viewport, profiler);
+        self.graded_from_ssr_output = Some(from_ssr_output);
+    }
+}
+
+pub(super) fn texture_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+pub(super) fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    shader: &wgpu::ShaderModule,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    diagnostics::scoped(device, label, || {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    })
+}
+
+pub(super) fn begin_fullscreen_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    target: &'a wgpu::TextureView,
+    clear: wgpu::Color,
+    queries: Option<&PassQueries>,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: queries.map(PassQueries::render_writes),
+        occlusion_query_set: None,
+    })
+}
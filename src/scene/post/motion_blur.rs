@@ -0,0 +1,217 @@
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+use crate::scene::profiler::Profiler;
+use crate::shaders;
+use super::{begin_fullscreen_pass, fullscreen_pipeline, texture_entry, RenderTarget, HDR_FORMAT};
+
+/// Blur along the scene's motion vectors, for cinematic desktop captures. Never applied to
+/// stereo views, where it causes discomfort.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct MotionBlur {
+    pub enabled: bool,
+    /// How long the virtual shutter stays open per frame, in degrees of a 360° frame: 180
+    /// blurs over half of each frame's motion, like film
+    pub shutter_angle: f32,
+    /// Scale of the blur from the camera's own motion; 0 keeps pans and turns sharp
+    pub camera: f32,
+    /// Scale of the blur from objects moving on screen
+    pub objects: f32,
+    /// Color taps along each pixel's motion
+    pub samples: u32,
+    /// Longest blur as a fraction of the screen
+    pub max_length: f32,
+}
+
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shutter_angle: 180.0,
+            camera: 1.0,
+            objects: 1.0,
+            samples: 12,
+            max_length: 0.05,
+        }
+    }
+}
+
+impl MotionBlur {
+    /// Fraction of the frame the shutter is open
+    pub fn shutter_fraction(&self) -> f32 {
+        (self.shutter_angle / 360.0).clamp(0.0, 1.0)
+    }
+
+    /// Whether the pass changes anything
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.shutter_fraction() > 0.0 && (self.camera > 0.0 || self.objects > 0.0)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurUniform {
+    params: [f32; 4],
+    samples: [u32; 4],
+}
+
+/// Blurs the scene color along the velocity target the scene pass writes
+pub struct MotionBlurPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    /// Reading the HDR target, for writing to the SSR output
+    from_hdr: wgpu::BindGroup,
+    /// Reading the SSR output, for writing back to the HDR target
+    from_ssr: wgpu::BindGroup,
+}
+
+impl MotionBlurPass {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr: &RenderTarget,
+        ssr_output: &RenderTarget,
+        velocity: &RenderTarget,
+        sampler: &wgpu::Sampler,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Motion Blur Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[MotionBlurUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Motion Blur Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Scene color
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                // Velocity
+                texture_entry(2, wgpu::TextureSampleType::Float { filterable: false }),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = shaders::create_module(device, "Motion Blur Shader", include_str!("../../../shaders/motion_blur.wgsl"));
+        let pipeline = fullscreen_pipeline(device, "Motion Blur Pipeline", &shader, &bind_group_layout, HDR_FORMAT);
+
+        let from_hdr = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, hdr, velocity, sampler);
+        let from_ssr = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, ssr_output, velocity, sampler);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+            from_hdr,
+            from_ssr,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        source: &RenderTarget,
+        velocity: &RenderTarget,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Motion Blur Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&velocity.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreate the bind groups after the targets were reallocated
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        hdr: &RenderTarget,
+        ssr_output: &RenderTarget,
+        velocity: &RenderTarget,
+        sampler: &wgpu::Sampler,
+    ) {
+        self.from_hdr = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, hdr, velocity, sampler);
+        self.from_ssr = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, ssr_output, velocity, sampler);
+    }
+
+    /// Blur the HDR target into `output`, or the SSR output when `from_ssr`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        settings: &MotionBlur,
+        from_ssr: bool,
+        output: &wgpu::TextureView,
+        profiler: &mut Profiler,
+    ) {
+        let uniform = MotionBlurUniform {
+            params: [settings.shutter_fraction(), settings.camera.max(0.0), settings.objects.max(0.0), settings.max_length.max(0.0)],
+            samples: [settings.samples.clamp(2, 64), 0, 0, 0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let queries = profiler.begin_pass("Motion Blur");
+        let mut pass = begin_fullscreen_pass(encoder, "Motion Blur Pass", output, wgpu::Color::TRANSPARENT, queries.as_ref());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, if from_ssr { &self.from_ssr } else { &self.from_hdr }, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_motion_blur_activity() {
+        let blur = MotionBlur { enabled: true, ..MotionBlur::default() };
+        assert!(blur.is_active());
+        assert_eq!(blur.shutter_fraction(), 0.5);
+        assert!(!MotionBlur::default().is_active(), "Off unless enabled");
+        assert!(!MotionBlur { shutter_angle: 0.0, ..blur }.is_active());
+        assert!(!MotionBlur { camera: 0.0, objects: 0.0, ..blur }.is_active());
+        assert_eq!(MotionBlur { shutter_angle: 720.0, ..blur }.shutter_fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_motion_blur_uniform_layout() {
+        let source = shaders::resolve(include_str!("../../../shaders/motion_blur.wgsl")).unwrap().source;
+        shaders::check_uniform_layout(&source, "MotionBlurUniform", &crate::uniform_layout!(MotionBlurUniform {
+            params, samples,
+        })).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use bytemuck::Zeroable;
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+use crate::scene::profiler::Profiler;
+use crate::shaders;
+use super::{begin_fullscreen_pass, fullscreen_pipeline, texture_entry, RenderTarget, HDR_FORMAT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SsrQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsrSettings {
+    pub max_steps: u32,
+    pub stride: f32,
+    pub thickness: f32,
+    pub max_distance: f32,
+    pub refine_steps: u32,
+}
+
+impl SsrQuality {
+    pub fn settings(&self) -> Option<SsrSettings> {
+        match self {
+            SsrQuality::Off => None,
+            SsrQuality::Low => Some(SsrSettings {
+                max_steps: 16,
+                stride: 0.5,
+                thickness: 0.5,
+                max_distance: 8.0,
+                refine_steps: 0,
+            }),
+            SsrQuality::Medium => Some(SsrSettings {
+                max_steps: 48,
+                stride: 0.25,
+                thickness: 0.35,
+                max_distance: 12.0,
+                refine_steps: 4,
+            }),
+            SsrQuality::High => Some(SsrSettings {
+                max_steps: 128,
+                stride: 0.1,
+                thickness: 0.25,
+                max_distance: 20.0,
+                refine_steps: 8,
+            }),
+        }
+    }
+}
+
+/// Colors returned by the reflection pass when a ray leaves the screen
+#[derive(Debug, Clone, Copy)]
+pub struct EnvironmentProbe {
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+}
+
+impl Default for EnvironmentProbe {
+    fn default() -> Self {
+        // Matches the renderer's clear color towards the horizon
+        Self {
+            zenith: Vec3::new(0.05, 0.1, 0.25),
+            horizon: Vec3::new(0.1, 0.2, 0.3),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrUniform {
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    inv_view: [[f32; 4]; 4],
+    params: [f32; 4],
+    refine: [f32; 4],
+    env_zenith: [f32; 4],
+    env_horizon: [f32; 4],
+}
+
+pub struct SsrPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl SsrPass {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr: &RenderTarget,
+        normal: &RenderTarget,
+        depth_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSR Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[SsrUniform::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSR Bind Group Layout"),
+            entries: &[
+                // SSR uniform
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Scene color
+                texture_entry(1, wgpu::TextureSampleType::Float { filterable: true }),
+                // Normal and reflectivity
+                texture_entry(2, wgpu::TextureSampleType::Float { filterable: true }),
+                // Depth, read as unfilterable float so GL backends can load it
+                texture_entry(3, wgpu::TextureSampleType::Float { filterable: false }),
+                // Sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = shaders::create_module(device, "SSR Shader", include_str!("../../../shaders/ssr.wgsl"));
+        let pipeline = fullscreen_pipeline(device, "SSR Pipeline", &shader, &bind_group_layout, HDR_FORMAT);
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, hdr, normal, depth_view, sampler);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        hdr: &RenderTarget,
+        normal: &RenderTarget,
+        depth_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SSR Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&hdr.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreate the bind group after the input targets were reallocated
+    pub fn rebind(
+        &mut self,
+        device: &wgpu::Device,
+        hdr: &RenderTarget,
+        normal: &RenderTarget,
+        depth_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, hdr, normal, depth_view, sampler);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        view: Mat4,
+        proj: Mat4,
+        settings: &SsrSettings,
+        environment: &EnvironmentProbe,
+        output: &wgpu::TextureView,
+        profiler: &mut Profiler,
+    ) {
+        let uniform = SsrUniform {
+            view: view.to_cols_array_2d(),
+            proj: proj.to_cols_array_2d(),
+            inv_proj: proj.inverse().to_cols_array_2d(),
+            inv_view: view.inverse().to_cols_array_2d(),
+            params: [
+                settings.max_steps as f32,
+                settings.stride,
+                settings.thickness,
+                settings.max_distance,
+            ],
+            refine: [settings.refine_steps as f32, 0.0, 0.0, 0.0],
+            env_zenith: environment.zenith.extend(1.0).to_array(),
+            env_horizon: environment.horizon.extend(1.0).to_array(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let queries = profiler.begin_pass("SSR");
+        let mut pass = begin_fullscreen_pass(encoder, "SSR Pass", output, wgpu::Color::BLACK, queries.as_ref());
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssr_presets_scale_with_quality() {
+        assert!(SsrQuality::Off.settings().is_none());
+
+        let low = SsrQuality::Low.settings().unwrap();
+        let medium = SsrQuality::Medium.settings().unwrap();
+        let high = SsrQuality::High.settings().unwrap();
+
+        assert!(low.max_steps < medium.max_steps && medium.max_steps < high.max_steps);
+        assert!(low.stride > medium.stride && medium.stride > high.stride);
+        assert!(high.refine_steps >= medium.refine_steps);
+    }
+
+    #[test]
+    fn test_ssr_uniform_layout() {
+        // 4 matrices + 4 vec4s, matching the WGSL struct
+        assert_eq!(std::mem::size_of::<SsrUniform>(), 4 * 64 + 4 * 16);
+        let source = shaders::resolve(include_str!("../../../shaders/ssr.wgsl")).unwrap().source;
+        shaders::check_uniform_layout(&source, "SsrUniform", &crate::uniform_layout!(SsrUniform {
+            view, proj, inv_proj, inv_view, params, refine, env_zenith, env_horizon,
+        })).unwrap();
+    }
+
+    #[test]
+    fn test_default_environment_probe() {
+        let probe = EnvironmentProbe::default();
+        assert!(probe.zenith.cmple(Vec3::ONE).all());
+        assert!(probe.horizon.cmpge(Vec3::ZERO).all());
+    }
+}
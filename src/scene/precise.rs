@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use glam::{DMat4, DVec3, Mat4};
+use super::{FloatingOrigin, ObjectId, SceneObjects, Transform};
+
+/// An object transform held in f64, positioned in absolute coordinates rather than relative
+/// to the floating origin, for objects that must stay exact far from the world origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreciseTransform {
+    pub position: DVec3,
+    /// Euler angles in XYZ order, like `Transform::rotation`
+    pub rotation: DVec3,
+    pub scale: DVec3,
+}
+
+impl Default for PreciseTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreciseTransform {
+    pub fn new() -> Self {
+        Self {
+            position: DVec3::ZERO,
+            rotation: DVec3::ZERO,
+            scale: DVec3::ONE,
+        }
+    }
+
+    /// The absolute transform of a local `transform`
+    pub fn from_local(transform: &Transform, origin: &FloatingOrigin) -> Self {
+        Self {
+            position: origin.to_absolute(transform.position),
+            rotation: transform.rotation.as_dvec3(),
+            scale: transform.scale.as_dvec3(),
+        }
+    }
+
+    /// The f32 transform relative to `origin`'s local space, subtracted in f64 so nothing
+    /// of the offset is lost
+    pub fn to_local(&self, origin: &FloatingOrigin) -> Transform {
+        Transform {
+            position: (self.position - origin.offset()).as_vec3(),
+            rotation: self.rotation.as_vec3(),
+            scale: self.scale.as_vec3(),
+        }
+    }
+
+    pub fn to_dmatrix(&self) -> DMat4 {
+        let translation = DMat4::from_translation(self.position);
+        let rotation = DMat4::from_euler(glam::EulerRot::XYZ, self.rotation.x, self.rotation.y, self.rotation.z);
+        let scale = DMat4::from_scale(self.scale);
+        translation * rotation * scale
+    }
+
+    /// Model matrix relative to `eye`, composed in f64 and only then narrowed to f32
+    pub fn relative_matrix(&self, eye: DVec3) -> Mat4 {
+        (DMat4::from_translation(-eye) * self.to_dmatrix()).as_mat4()
+    }
+}
+
+/// A precise transform and the local transform last written from it
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    transform: PreciseTransform,
+    local: Transform,
+}
+
+/// The objects positioned by a `PreciseTransform`. Their local f32 transforms are rewritten
+/// from it every `Scene::step` and rebase, so rebasing never rounds them; edits made to the
+/// local transforms in between (tweens, scripts, gizmos) are folded back in first.
+#[derive(Debug, Clone, Default)]
+pub struct PreciseTransforms {
+    entries: HashMap<ObjectId, Entry>,
+}
+
+impl PreciseTransforms {
+    pub fn get(&self, id: ObjectId) -> Option<&PreciseTransform> {
+        self.entries.get(&id).map(|entry| &entry.transform)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Model matrix of a precise object relative to `origin`'s local space; `None` if `local`,
+    /// its current local transform, was edited since the last step and isn't folded in yet
+    pub fn matrix(&self, id: ObjectId, local: &Transform, origin: &FloatingOrigin) -> Option<Mat4> {
+        self.entries.get(&id)
+            .filter(|entry| entry.local == *local)
+            .map(|entry| entry.transform.relative_matrix(origin.offset()))
+    }
+
+    pub(super) fn insert(&mut self, id: ObjectId, transform: PreciseTransform, local: Transform) {
+        self.entries.insert(id, Entry { transform, local });
+    }
+
+    pub(super) fn remove(&mut self, id: ObjectId) -> bool {
+        self.entries.remove(&id).is_some()
+    }
+
+    /// Fold changes made to the local transforms since they were last written into the
+    /// precise ones; objects no longer in `objects` are dropped
+    pub(super) fn absorb(&mut self, objects: &SceneObjects) {
+        let transforms = objects.transforms();
+        self.entries.retain(|&id, entry| {
+            let Some(index) = objects.index_of(id) else {
+                return false;
+            };
+            let local = transforms[index];
+            if local != entry.local {
+                // Only the difference is taken from f32, so the absolute position keeps its precision
+                entry.transform.position += (local.position - entry.local.position).as_dvec3();
+                if local.rotation != entry.local.rotation {
+                    entry.transform.rotation = local.rotation.as_dvec3();
+                }
+                if local.scale != entry.local.scale {
+                    entry.transform.scale = local.scale.as_dvec3();
+                }
+                entry.local = local;
+            }
+            true
+        });
+    }
+
+    /// Write every local transform from its precise one in `origin`'s local space
+    pub(super) fn apply(&mut self, origin: &FloatingOrigin, objects: &mut SceneObjects) {
+        let updates: Vec<(usize, Transform)> = self.entries.iter_mut()
+            .filter_map(|(&id, entry)| {
+                let index = objects.index_of(id)?;
+                let local = entry.transform.to_local(origin);
+                entry.local = local;
+                (objects.transforms()[index] != local).then_some((index, local))
+            })
+            .collect();
+        // Untouched transforms leave the object revision, and so the BVH, as it was
+        if !updates.is_empty() {
+            let transforms = objects.transforms_mut();
+            for (index, local) in updates {
+                transforms[index] = local;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    #[test]
+    fn test_local_round_trip() {
+        let mut origin = FloatingOrigin::default();
+        origin.apply(Vec3::new(6_400_000.0, 0.0, -3_200_000.0));
+        let precise = PreciseTransform {
+            position: DVec3::new(6_400_012.125, 3.5, -3_199_990.062_5),
+            rotation: DVec3::new(0.0, 1.0, 0.0),
+            ..PreciseTransform::new()
+        };
+        let local = precise.to_local(&origin);
+        assert_eq!(local.position, Vec3::new(12.125, 3.5, 9.9375));
+        assert_eq!(PreciseTransform::from_local(&local, &origin), precise);
+    }
+
+    #[test]
+    fn test_relative_matrix() {
+        // 0.01 apart at 40,000 km, where f32 can't tell them apart
+        let precise = PreciseTransform { position: DVec3::new(4.0e7 + 0.01, 0.0, 0.0), ..PreciseTransform::new() };
+        let eye = DVec3::new(4.0e7, 0.0, 0.0);
+        assert_eq!(4.0e7f32 + 0.01, 4.0e7f32);
+        let matrix = precise.relative_matrix(eye);
+        assert!((matrix.w_axis.x - 0.01).abs() < 1e-6);
+
+        let turned = PreciseTransform { rotation: DVec3::new(0.0, std::f64::consts::FRAC_PI_2, 0.0), ..precise };
+        let corner = turned.relative_matrix(eye).transform_point3(Vec3::X);
+        assert!((corner - Vec3::new(0.01, 0.0, -1.0)).length() < 1e-5);
+    }
+}
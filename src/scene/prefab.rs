@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::color::Color;
+use crate::model::ModelHandle;
+use super::{LightId, ObjectId, PointLight, Scene, Transform};
+
+/// A reusable assembly of nodes, lights and colliders, stored as TOML:
+///
+/// ```toml
+/// name = "desk_lamp"
+///
+/// [[nodes]]
+/// name = "base"
+/// model = "lamp_base.glb"
+///
+/// [[nodes]]
+/// name = "shade"
+/// parent = "base"
+/// model = "lamp_shade.glb"
+/// position = [0.0, 0.6, 0.0]
+///
+/// [[lights]]
+/// node = "shade"
+/// color = [1.0, 0.9, 0.7]
+/// intensity = 4.0
+///
+/// [[colliders]]
+/// node = "base"
+/// shape = { box = { half_extents = [0.2, 0.05, 0.2] } }
+/// ```
+///
+/// Node transforms are relative to their parent, or to the instance for root nodes.
+/// Nodes without a model group others. Model paths are resolved by the caller.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prefab {
+    pub name: String,
+    pub nodes: Vec<PrefabNode>,
+    pub lights: Vec<PrefabLight>,
+    pub colliders: Vec<PrefabCollider>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefabNode {
+    pub name: String,
+    pub parent: Option<String>,
+    pub model: Option<String>,
+    pub position: [f32; 3],
+    /// Euler angles in radians, as in [`Transform`]
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for PrefabNode {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            parent: None,
+            model: None,
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl PrefabNode {
+    pub fn transform(&self) -> Transform {
+        Transform {
+            position: Vec3::from(self.position),
+            rotation: Vec3::from(self.rotation),
+            scale: Vec3::from(self.scale),
+        }
+    }
+}
+
+/// Point light, positioned relative to `node` (or the instance)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefabLight {
+    pub node: Option<String>,
+    pub position: [f32; 3],
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for PrefabLight {
+    fn default() -> Self {
+        Self {
+            node: None,
+            position: [0.0; 3],
+            color: Color::WHITE,
+            intensity: 1.0,
+            range: 10.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColliderShape {
+    Box { half_extents: [f32; 3] },
+    Sphere { radius: f32 },
+    Capsule { radius: f32, half_height: f32 },
+}
+
+/// Collision shape centered at `position` relative to `node` (or the instance)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabCollider {
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub position: [f32; 3],
+    pub shape: ColliderShape,
+}
+
+/// Per-instance changes applied on top of a prefab, keyed by node name
+#[derive(Debug, Clone, Default)]
+pub struct PrefabOverrides {
+    /// Replaces a node's local transform
+    pub transforms: HashMap<String, Transform>,
+    /// Replaces a node's model path
+    pub models: HashMap<String, String>,
+    /// Leaves a node, everything beneath it and the lights and colliders attached to them out
+    pub hidden: HashSet<String>,
+    /// Multiplies the color of every light
+    pub light_tint: Option<Color>,
+}
+
+/// A collider placed in the scene by an instance; `transform` maps the shape's local space to world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacedCollider {
+    pub shape: ColliderShape,
+    pub transform: Mat4,
+}
+
+/// What one call to [`Prefab::instantiate`] added. Objects and lights live in the scene;
+/// colliders are handed to whichever system consumes them.
+#[derive(Debug, Clone, Default)]
+pub struct PrefabInstance {
+    pub prefab: String,
+    /// Scene objects by node name
+    pub objects: Vec<(String, ObjectId)>,
+    pub lights: Vec<LightId>,
+    pub colliders: Vec<PlacedCollider>,
+}
+
+impl PrefabInstance {
+    pub fn object(&self, node: &str) -> Option<ObjectId> {
+        self.objects.iter().find(|(name, _)| name == node).map(|(_, id)| *id)
+    }
+
+    /// Remove the instance's objects and lights from the scene
+    pub fn despawn(&self, scene: &mut Scene) {
+        for (_, id) in &self.objects {
+            scene.remove_object(*id);
+        }
+        for id in &self.lights {
+            scene.remove_light(*id);
+        }
+    }
+}
+
+impl Prefab {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let prefab: Self = toml::from_str(source)?;
+        prefab.validate()?;
+        Ok(prefab)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prefab {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid prefab {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.validate()?;
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Check node names are unique and every parent or attachment names a node, without cycles
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(node.name.as_str()) {
+                bail!("Duplicate prefab node '{}'", node.name);
+            }
+        }
+        let references = self.nodes.iter().filter_map(|node| node.parent.as_deref())
+            .chain(self.lights.iter().filter_map(|light| light.node.as_deref()))
+            .chain(self.colliders.iter().filter_map(|collider| collider.node.as_deref()));
+        for name in references {
+            if !names.contains(name) {
+                bail!("Unknown prefab node '{}'", name);
+            }
+        }
+        for node in &self.nodes {
+            // A chain longer than the node count must revisit a node
+            let mut parent = node.parent.as_deref();
+            for _ in 0..self.nodes.len() {
+                parent = parent.and_then(|name| self.node(name)).and_then(|node| node.parent.as_deref());
+            }
+            if parent.is_some() {
+                bail!("Prefab node '{}' is its own ancestor", node.name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn node(&self, name: &str) -> Option<&PrefabNode> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    /// Model paths an instance with these overrides uses, without duplicates
+    pub fn model_paths(&self, overrides: &PrefabOverrides) -> Vec<String> {
+        let mut paths = Vec::new();
+        for node in &self.nodes {
+            if let Some(path) = overrides.models.get(&node.name).or(node.model.as_ref()) {
+                if !paths.contains(path) {
+                    paths.push(path.clone());
+                }
+            }
+        }
+        paths
+    }
+
+    /// World matrix of every node, or `None` for hidden nodes and their descendants
+    fn world_matrices(&self, root: &Transform, overrides: &PrefabOverrides) -> Vec<Option<Mat4>> {
+        fn resolve(
+            prefab: &Prefab,
+            index: usize,
+            root: Mat4,
+            overrides: &PrefabOverrides,
+            matrices: &mut Vec<Option<Option<Mat4>>>,
+        ) -> Option<Mat4> {
+            if let Some(matrix) = matrices[index] {
+                return matrix;
+            }
+            // Marked hidden while resolving so a parent cycle in an unvalidated prefab terminates
+            matrices[index] = Some(None);
+            let node = &prefab.nodes[index];
+            let parent = match &node.parent {
+                Some(name) => {
+                    let parent = prefab.nodes.iter().position(|node| &node.name == name);
+                    parent.and_then(|parent| resolve(prefab, parent, root, overrides, matrices))
+                }
+                None => Some(root),
+            };
+            let local = overrides.transforms.get(&node.name).copied().unwrap_or_else(|| node.transform());
+            let matrix = parent.filter(|_| !overrides.hidden.contains(&node.name))
+                .map(|parent| parent * local.to_matrix());
+            matrices[index] = Some(matrix);
+            matrix
+        }
+
+        let root = root.to_matrix();
+        let mut matrices = vec![None; self.nodes.len()];
+        (0..self.nodes.len())
+            .map(|index| resolve(self, index, root, overrides, &mut matrices))
+            .collect()
+    }
+
+    /// Place an instance at `root`. `models` maps model paths (after overrides) to registered models;
+    /// nodes whose model it doesn't know are skipped with a warning.
+    pub fn instantiate(
+        &self,
+        scene: &mut Scene,
+        root: &Transform,
+        overrides: &PrefabOverrides,
+        models: impl Fn(&str) -> Option<ModelHandle>,
+    ) -> PrefabInstance {
+        let matrices = self.world_matrices(root, overrides);
+        let attachment = |node: &Option<String>| match node {
+            Some(name) => self.nodes.iter().position(|node| &node.name == name).and_then(|index| matrices[index]),
+            None => Some(root.to_matrix()),
+        };
+        let mut instance = PrefabInstance { prefab: self.name.clone(), ..Default::default() };
+
+        for (node, matrix) in self.nodes.iter().zip(&matrices) {
+            let (Some(matrix), Some(path)) = (matrix, overrides.models.get(&node.name).or(node.model.as_ref())) else {
+                continue;
+            };
+            match models(path) {
+                Some(model) => {
+                    let id = scene.add_object(model, Transform::from_matrix(*matrix));
+                    instance.objects.push((node.name.clone(), id));
+                }
+                None => log::warn!("Prefab '{}': no model '{}' for node '{}'", self.name, path, node.name),
+            }
+        }
+
+        let tint = overrides.light_tint.unwrap_or(Color::WHITE);
+        for light in &self.lights {
+            if let Some(matrix) = attachment(&light.node) {
+                instance.lights.push(scene.add_light(PointLight {
+                    position: matrix.transform_point3(Vec3::from(light.position)),
+                    color: light.color * tint,
+                    intensity: light.intensity,
+                    range: light.range,
+                    casts_shadows: light.casts_shadows,
+                }));
+            }
+        }
+        for collider in &self.colliders {
+            if let Some(matrix) = attachment(&collider.node) {
+                instance.colliders.push(PlacedCollider {
+                    shape: collider.shape,
+                    transform: matrix * Mat4::from_translation(Vec3::from(collider.position)),
+                });
+            }
+        }
+        instance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAMP: &str = r#"
+        name = "lamp"
+
+        [[nodes]]
+        name = "base"
+        model = "base.glb"
+        position = [0.0, 0.0, 1.0]
+
+        [[nodes]]
+        name = "shade"
+        parent = "base"
+        model = "shade.glb"
+        position = [0.0, 2.0, 0.0]
+
+        [[lights]]
+        node = "shade"
+        color = [1.0, 0.5, 0.5]
+
+        [[colliders]]
+        node = "base"
+        shape = { sphere = { radius = 0.5 } }
+    "#;
+
+    #[test]
+    fn test_prefab_round_trip() {
+        let prefab = Prefab::from_toml(LAMP).unwrap();
+        assert_eq!(prefab.nodes.len(), 2);
+        assert_eq!(prefab.nodes[1].scale, [1.0; 3]);
+        assert_eq!(prefab.colliders[0].shape, ColliderShape::Sphere { radius: 0.5 });
+        assert_eq!(Prefab::from_toml(&prefab.to_toml().unwrap()).unwrap(), prefab);
+
+        let mut overrides = PrefabOverrides::default();
+        assert_eq!(prefab.model_paths(&overrides), vec!["base.glb", "shade.glb"]);
+        overrides.models.insert("shade".to_string(), "base.glb".to_string());
+        assert_eq!(prefab.model_paths(&overrides), vec!["base.glb"]);
+    }
+
+    #[test]
+    fn test_prefab_validation() {
+        let cycle = r#"
+            [[nodes]]
+            name = "a"
+            parent = "b"
+            [[nodes]]
+            name = "b"
+            parent = "a"
+        "#;
+        assert!(Prefab::from_toml(cycle).is_err());
+        assert!(Prefab::from_toml("[[nodes]]\nname = \"a\"\n[[nodes]]\nname = \"a\"").is_err());
+        assert!(Prefab::from_toml("[[lights]]\nnode = \"missing\"").is_err());
+    }
+
+    #[test]
+    fn test_prefab_world_matrices() {
+        let prefab = Prefab::from_toml(LAMP).unwrap();
+        let root = Transform { position: Vec3::new(10.0, 0.0, 0.0), ..Transform::new() };
+
+        let matrices = prefab.world_matrices(&root, &PrefabOverrides::default());
+        let shade = matrices[1].unwrap().transform_point3(Vec3::ZERO);
+        assert!(shade.abs_diff_eq(Vec3::new(10.0, 2.0, 1.0), 1e-5));
+
+        // Overriding a parent moves its children; hiding it hides them
+        let mut overrides = PrefabOverrides::default();
+        overrides.transforms.insert("base".to_string(), Transform { scale: Vec3::splat(2.0), ..Transform::new() });
+        let matrices = prefab.world_matrices(&root, &overrides);
+        let shade = matrices[1].unwrap().transform_point3(Vec3::ZERO);
+        assert!(shade.abs_diff_eq(Vec3::new(10.0, 4.0, 0.0), 1e-5));
+
+        overrides.hidden.insert("base".to_string());
+        assert_eq!(prefab.world_matrices(&root, &overrides), vec![None, None]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::f32::consts::PI;
+use glam::{UVec3, Vec3};
+use crate::color::Color;
+use super::{ObjectId, Ray, Scene};
+
+// Real spherical harmonic basis constants for bands 0 and 1
+const SH_Y0: f32 = 0.282_095;
+const SH_Y1: f32 = 0.488_603;
+
+/// Pushes shadow and bounce rays off the surface they start on
+const SURFACE_OFFSET: f32 = 1e-3;
+
+/// Diffuse light arriving around a point, as L1 spherical harmonics per color channel.
+/// Stored convolved with the cosine lobe and divided by π, so `evaluate` gives what the
+/// shader multiplies albedo by in place of the flat ambient term.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ShIrradiance {
+    /// The constant term, then the x, y and z terms
+    pub coefficients: [Vec3; 4],
+}
+
+impl ShIrradiance {
+    /// The same radiance from every direction, lighting like a flat ambient term of `radiance`
+    pub fn uniform(radiance: Vec3) -> Self {
+        Self { coefficients: [radiance, Vec3::ZERO, Vec3::ZERO, Vec3::ZERO] }
+    }
+
+    /// Project `(direction, radiance)` samples, with unit directions spread evenly over the sphere
+    pub fn from_samples(samples: impl IntoIterator<Item = (Vec3, Vec3)>) -> Self {
+        let mut sums = [Vec3::ZERO; 4];
+        let mut count = 0;
+        for (direction, radiance) in samples {
+            sums[0] += radiance * SH_Y0;
+            sums[1] += radiance * (SH_Y1 * direction.x);
+            sums[2] += radiance * (SH_Y1 * direction.y);
+            sums[3] += radiance * (SH_Y1 * direction.z);
+            count += 1;
+        }
+        if count == 0 {
+            return Self::default();
+        }
+        // Each sample stands for 4π / count steradians. The cosine lobe scales band 0 by π and
+        // band 1 by 2π/3; the basis is folded in so evaluating is a dot product with the normal.
+        let solid_angle = 4.0 * PI / count as f32;
+        let band1 = solid_angle * (2.0 / 3.0) * SH_Y1;
+        Self {
+            coefficients: [
+                sums[0] * (solid_angle * SH_Y0),
+                sums[1] * band1,
+                sums[2] * band1,
+                sums[3] * band1,
+            ],
+        }
+    }
+
+    /// Light reaching a surface facing `normal`, in the units of a flat ambient term
+    pub fn evaluate(&self, normal: Vec3) -> Vec3 {
+        let [constant, x, y, z] = self.coefficients;
+        (constant + x * normal.x + y * normal.y + z * normal.z).max(Vec3::ZERO)
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mut coefficients = self.coefficients;
+        for (coefficient, other) in coefficients.iter_mut().zip(other.coefficients) {
+            *coefficient = coefficient.lerp(other, t);
+        }
+        Self { coefficients }
+    }
+
+    /// Layout of `irradiance` in the scene shader's `ModelUniform`; `w` of the first row
+    /// marks it as set, as zeroed rows fall back to the flat ambient light
+    pub(crate) fn to_uniform(self) -> [[f32; 4]; 4] {
+        let mut rows = self.coefficients.map(|coefficient| coefficient.extend(0.0).to_array());
+        rows[0][3] = 1.0;
+        rows
+    }
+}
+
+/// How `ProbeGrid::bake` lights the scene it traces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeBakeSettings {
+    /// Rays traced from each probe
+    pub samples: u32,
+    /// Color of every surface hit, as object textures aren't readable on the CPU
+    pub surface_albedo: Color,
+    /// Radiance of rays that leave the scene; `None` takes `Scene::ambient_light`, so probes
+    /// out in the open light objects as if there were no probes
+    pub sky: Option<Color>,
+}
+
+impl Default for ProbeBakeSettings {
+    fn default() -> Self {
+        Self {
+            samples: 256,
+            surface_albedo: Color::linear(0.5, 0.5, 0.5),
+            sky: None,
+        }
+    }
+}
+
+/// A grid of irradiance probes over a box of the scene, baked from the static objects and
+/// interpolated at each object's center for its ambient light, so dynamic objects pick up
+/// the shadowing and bounce light of their surroundings. Set as `Scene::probes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeGrid {
+    min: Vec3,
+    max: Vec3,
+    counts: UVec3,
+    probes: Vec<ShIrradiance>,
+}
+
+impl ProbeGrid {
+    /// `counts` probes along each axis spanning `min..max`, unbaked (black); an axis with a
+    /// single probe puts it in the middle
+    pub fn new(min: Vec3, max: Vec3, counts: UVec3) -> Self {
+        let counts = counts.max(UVec3::ONE);
+        Self {
+            min: min.min(max),
+            max: min.max(max),
+            counts,
+            probes: vec![ShIrradiance::default(); (counts.x * counts.y * counts.z) as usize],
+        }
+    }
+
+    /// Probes about `spacing` apart over `min..max`, with one at each corner
+    pub fn with_spacing(min: Vec3, max: Vec3, spacing: f32) -> Self {
+        let steps = ((max - min).abs() / spacing.max(1e-3)).ceil().as_uvec3();
+        Self::new(min, max, steps + UVec3::ONE)
+    }
+
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        (self.min, self.max)
+    }
+
+    pub fn counts(&self) -> UVec3 {
+        self.counts
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn position(&self, cell: UVec3) -> Vec3 {
+        let steps = (self.counts - UVec3::ONE).as_vec3();
+        let t = Vec3::select(steps.cmpgt(Vec3::ZERO), cell.as_vec3() / steps, Vec3::splat(0.5));
+        self.min + (self.max - self.min) * t
+    }
+
+    pub fn probe(&self, cell: UVec3) -> Option<&ShIrradiance> {
+        self.index(cell).map(|index| &self.probes[index])
+    }
+
+    /// Replace one probe, e.g. with lighting baked offline; returns false outside the grid
+    pub fn set_probe(&mut self, cell: UVec3, irradiance: ShIrradiance) -> bool {
+        let Some(index) = self.index(cell) else {
+            return false;
+        };
+        self.probes[index] = irradiance;
+        true
+    }
+
+    fn index(&self, cell: UVec3) -> Option<usize> {
+        cell.cmplt(self.counts).all()
+            .then(|| (cell.x + self.counts.x * (cell.y + self.counts.y * cell.z)) as usize)
+    }
+
+    /// Irradiance at `point`, blended from the eight probes around it; points outside the
+    /// grid take the nearest probes on its boundary
+    pub fn sample(&self, point: Vec3) -> ShIrradiance {
+        let steps = (self.counts - UVec3::ONE).as_vec3();
+        let extent = (self.max - self.min).max(Vec3::splat(1e-6));
+        let grid = ((point - self.min) / extent * steps).clamp(Vec3::ZERO, steps);
+        let base = grid.floor().as_uvec3().min(self.counts.saturating_sub(UVec3::splat(2)));
+        let t = grid - base.as_vec3();
+
+        let probe = |dx: u32, dy: u32, dz: u32| {
+            let cell = (base + UVec3::new(dx, dy, dz)).min(self.counts - UVec3::ONE);
+            self.probes[self.index(cell).unwrap()]
+        };
+        let x00 = probe(0, 0, 0).lerp(&probe(1, 0, 0), t.x);
+        let x10 = probe(0, 1, 0).lerp(&probe(1, 1, 0), t.x);
+        let x01 = probe(0, 0, 1).lerp(&probe(1, 0, 1), t.x);
+        let x11 = probe(0, 1, 1).lerp(&probe(1, 1, 1), t.x);
+        x00.lerp(&x10, t.y).lerp(&x01.lerp(&x11, t.y), t.z)
+    }
+
+    /// Trace every probe against the bounds of the visible objects `is_static` accepts, lit by
+    /// the scene's directional light (shadowed), point lights (unshadowed) and sky. One bounce,
+    /// off object bounds rather than meshes, so expect soft, approximate results; rebake after
+    /// the static scene changes.
+    pub fn bake(&mut self, scene: &Scene, settings: &ProbeBakeSettings, is_static: impl Fn(ObjectId) -> bool) {
+        let sky = settings.sky.unwrap_or(scene.ambient_light).rgb();
+        let to_sun = -scene.light_direction.normalize_or_zero();
+        let visible = |id: ObjectId| is_static(id) && scene.object(id).is_some_and(|object| object.visible);
+        let directions = sphere_directions(settings.samples);
+
+        for index in 0..self.probes.len() {
+            let cell = UVec3::new(
+                index as u32 % self.counts.x,
+                index as u32 / self.counts.x % self.counts.y,
+                index as u32 / (self.counts.x * self.counts.y),
+            );
+            let origin = self.position(cell);
+            let samples = directions.iter().map(|&direction| {
+                let radiance = match scene.raycast_filtered(&Ray::new(origin, direction), &visible) {
+                    None => sky,
+                    Some(hit) => {
+                        let point = hit.point + hit.normal * SURFACE_OFFSET;
+                        let sun_facing = hit.normal.dot(to_sun).max(0.0);
+                        let sunlit = sun_facing > 0.0
+                            && scene.raycast_filtered(&Ray::new(point, to_sun), &visible).is_none();
+                        let mut light = sky;
+                        if sunlit {
+                            light += scene.directional_light.rgb() * sun_facing;
+                        }
+                        for entry in &scene.lights {
+                            light += point_light(&entry.light, point, hit.normal);
+                        }
+                        light * settings.surface_albedo.rgb()
+                    }
+                };
+                (direction, radiance)
+            });
+            self.probes[index] = ShIrradiance::from_samples(samples);
+        }
+    }
+
+    pub(super) fn translate(&mut self, offset: Vec3) {
+        self.min += offset;
+        self.max += offset;
+    }
+}
+
+/// Diffuse light from `light` on a surface at `point` facing `normal`, with the scene shader's falloff
+fn point_light(light: &super::PointLight, point: Vec3, normal: Vec3) -> Vec3 {
+    let to_light = light.position - point;
+    let distance = to_light.length();
+    if distance >= light.range || distance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let window = (1.0 - (distance / light.range).powi(4)).clamp(0.0, 1.0);
+    let attenuation = window * window / (distance * distance + 1.0);
+    light.color.rgb() * light.intensity * attenuation * normal.dot(to_light / distance).max(0.0)
+}
+
+/// `count` unit vectors spread evenly over the sphere, on a Fibonacci spiral
+fn sphere_directions(count: u32) -> Vec<Vec3> {
+    let golden_angle = PI * (3.0 - 5f32.sqrt());
+    (0..count.max(1))
+        .map(|i| {
+            let y = 1.0 - (i as f32 + 0.5) / count.max(1) as f32 * 2.0;
+            let radius = (1.0 - y * y).sqrt();
+            let angle = golden_angle * i as f32;
+            Vec3::new(angle.cos() * radius, y, angle.sin() * radius)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_projection() {
+        // Uniform light projects to the flat ambient term it replaces
+        let radiance = Vec3::new(0.2, 0.4, 0.8);
+        let sh = ShIrradiance::from_samples(sphere_directions(512).into_iter().map(|d| (d, radiance)));
+        for normal in [Vec3::X, Vec3::NEG_Y, Vec3::new(1.0, 1.0, 0.0).normalize()] {
+            assert!(sh.evaluate(normal).abs_diff_eq(radiance, 0.01), "{}", sh.evaluate(normal));
+        }
+        assert_eq!(ShIrradiance::uniform(radiance).evaluate(Vec3::Z), radiance);
+    }
+
+    #[test]
+    fn test_directional_projection() {
+        // Light only from above: a surface facing up gets the most, one facing down nearly none
+        let samples = sphere_directions(1024).into_iter()
+            .map(|d| (d, if d.y > 0.0 { Vec3::ONE } else { Vec3::ZERO }));
+        let sh = ShIrradiance::from_samples(samples);
+        let (up, side, down) = (sh.evaluate(Vec3::Y).x, sh.evaluate(Vec3::X).x, sh.evaluate(Vec3::NEG_Y).x);
+        assert!(up > 0.9 && (side - 0.5).abs() < 0.02 && down < 0.1, "{up} {side} {down}");
+    }
+
+    #[test]
+    fn test_grid_sample() {
+        let mut grid = ProbeGrid::new(Vec3::ZERO, Vec3::new(2.0, 1.0, 1.0), UVec3::new(3, 2, 2));
+        assert_eq!(grid.len(), 12);
+        assert_eq!(grid.position(UVec3::new(1, 1, 0)), Vec3::new(1.0, 1.0, 0.0));
+        for z in 0..2 {
+            for y in 0..2 {
+                grid.set_probe(UVec3::new(2, y, z), ShIrradiance::uniform(Vec3::ONE));
+            }
+        }
+        assert!(!grid.set_probe(UVec3::new(3, 0, 0), ShIrradiance::default()));
+
+        // Halfway between the middle and lit probes, and clamped beyond the lit side
+        assert!(grid.sample(Vec3::new(1.5, 0.3, 0.7)).evaluate(Vec3::Y).abs_diff_eq(Vec3::splat(0.5), 1e-5));
+        assert_eq!(grid.sample(Vec3::new(5.0, 0.5, 0.5)).evaluate(Vec3::Y), Vec3::ONE);
+        assert_eq!(grid.sample(Vec3::new(-1.0, 0.5, 0.5)).evaluate(Vec3::Y), Vec3::ZERO);
+
+        // A single probe along an axis sits in the middle and lights everything
+        let mut single = ProbeGrid::new(Vec3::ZERO, Vec3::splat(2.0), UVec3::ONE);
+        assert_eq!(single.position(UVec3::ZERO), Vec3::ONE);
+        single.set_probe(UVec3::ZERO, ShIrradiance::uniform(Vec3::ONE));
+        assert_eq!(single.sample(Vec3::splat(10.0)).evaluate(Vec3::X), Vec3::ONE);
+
+        assert_eq!(ProbeGrid::with_spacing(Vec3::ZERO, Vec3::new(4.0, 1.0, 0.0), 1.0).counts(), UVec3::new(5, 2, 1));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::diagnostics;
+
+/// Frames kept for the frame-time graph
+pub const HISTORY_LEN: usize = 240;
+// Each timed pass uses a begin and an end query
+const MAX_TIMED_PASSES: u32 = 32;
+
+/// Timing of one scope within a frame; `depth` gives its place in the hierarchy
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub depth: usize,
+    pub cpu_ms: f32,
+    /// Filled in a few frames later, once the timestamp readback completes
+    pub gpu_ms: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameTiming {
+    pub frame: u64,
+    /// Wall-clock time since the previous frame started
+    pub cpu_ms: f32,
+    /// GPU time from the first to the last timed pass
+    pub gpu_ms: Option<f32>,
+    pub passes: Vec<PassTiming>,
+}
+
+/// Timestamp query slots for a single render or compute pass
+pub struct PassQueries {
+    query_set: Arc<wgpu::QuerySet>,
+    begin: u32,
+}
+
+impl PassQueries {
+    pub fn render_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(self.begin),
+            end_of_pass_write_index: Some(self.begin + 1),
+        }
+    }
+
+    pub fn compute_writes(&self) -> wgpu::ComputePassTimestampWrites<'_> {
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(self.begin),
+            end_of_pass_write_index: Some(self.begin + 1),
+        }
+    }
+}
+
+struct ScopeRecord {
+    name: &'static str,
+    depth: usize,
+    start: Instant,
+    cpu_ms: f32,
+    query: Option<u32>,
+}
+
+struct GpuTimer {
+    query_set: Arc<wgpu::QuerySet>,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period_ns: f32,
+    next_query: u32,
+    // Frame number and per-scope query indices waiting on the readback buffer
+    in_flight: Option<(u64, Vec<Option<u32>>)>,
+    map_requested: bool,
+    // One of the `MAP_` states, set by the map callback
+    map_state: Arc<AtomicU8>,
+}
+
+const MAP_PENDING: u8 = 0;
+const MAP_DONE: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let count = MAX_TIMED_PASSES * 2;
+        let size = count as u64 * std::mem::size_of::<u64>() as u64;
+        Self {
+            query_set: Arc::new(device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Profiler Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count,
+            })),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            period_ns: queue.get_timestamp_period(),
+            next_query: 0,
+            in_flight: None,
+            map_requested: false,
+            map_state: Arc::new(AtomicU8::new(MAP_PENDING)),
+        }
+    }
+}
+
+/// Hierarchical CPU/GPU frame profiler feeding the debug overlay.
+///
+/// Scopes nest: `begin_scope` groups child passes, `begin_pass` additionally reserves
+/// timestamp queries (when the device supports them) for the pass it wraps.
+pub struct Profiler {
+    history: VecDeque<FrameTiming>,
+    scopes: Vec<ScopeRecord>,
+    open: Vec<usize>,
+    frame: u64,
+    frame_start: Option<Instant>,
+    gpu: Option<GpuTimer>,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = device.features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| GpuTimer::new(device, queue));
+        Self {
+            history: VecDeque::with_capacity(HISTORY_LEN),
+            scopes: Vec::new(),
+            open: Vec::new(),
+            frame: 0,
+            frame_start: None,
+            gpu,
+        }
+    }
+
+    pub fn gpu_timing_supported(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// Start a new frame and collect any GPU timings that have finished reading back
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        let now = Instant::now();
+        let cpu_ms = self.frame_start.map_or(0.0, |start| (now - start).as_secs_f32() * 1000.0);
+        self.frame_start = Some(now);
+        self.frame += 1;
+        diagnostics::breadcrumb_frame(self.frame);
+        self.scopes.clear();
+        self.open.clear();
+
+        if let Some(gpu) = &mut self.gpu {
+            gpu.next_query = 0;
+        }
+        if let Some(last) = self.history.back_mut() {
+            last.cpu_ms = cpu_ms;
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        self.collect_gpu_results();
+    }
+
+    /// Open a CPU-only scope; its GPU time spans the timed passes nested inside it
+    pub fn begin_scope(&mut self, name: &'static str) {
+        diagnostics::breadcrumb(name);
+        self.open.push(self.scopes.len());
+        self.scopes.push(ScopeRecord {
+            name,
+            depth: self.open.len() - 1,
+            start: Instant::now(),
+            cpu_ms: 0.0,
+            query: None,
+        });
+    }
+
+    /// Open a scope for a single GPU pass; returns the timestamp slots to attach to it
+    pub fn begin_pass(&mut self, name: &'static str) -> Option<PassQueries> {
+        self.begin_scope(name);
+        let gpu = self.gpu.as_mut()?;
+        if gpu.next_query >= MAX_TIMED_PASSES * 2 {
+            return None;
+        }
+        let begin = gpu.next_query;
+        gpu.next_query += 2;
+        self.scopes.last_mut().unwrap().query = Some(begin);
+        Some(PassQueries {
+            query_set: gpu.query_set.clone(),
+            begin,
+        })
+    }
+
+    pub fn end_scope(&mut self) {
+        if let Some(index) = self.open.pop() {
+            let scope = &mut self.scopes[index];
+            scope.cpu_ms = scope.start.elapsed().as_secs_f32() * 1000.0;
+        }
+    }
+
+    /// Close the frame's scopes and queue the timestamp resolve; call before submitting `encoder`
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        while !self.open.is_empty() {
+            self.end_scope();
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(FrameTiming {
+            frame: self.frame,
+            cpu_ms: 0.0,
+            gpu_ms: None,
+            passes: self.scopes.iter().map(|scope| PassTiming {
+                name: scope.name,
+                depth: scope.depth,
+                cpu_ms: scope.cpu_ms,
+                gpu_ms: None,
+            }).collect(),
+        });
+
+        let Some(gpu) = &mut self.gpu else { return };
+        // Only one readback in flight; frames finishing while it is busy go without GPU times
+        if gpu.next_query == 0 || gpu.in_flight.is_some() {
+            return;
+        }
+        encoder.resolve_query_set(&gpu.query_set, 0..gpu.next_query, &gpu.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &gpu.resolve_buffer,
+            0,
+            &gpu.readback_buffer,
+            0,
+            gpu.next_query as u64 * std::mem::size_of::<u64>() as u64,
+        );
+        gpu.in_flight = Some((self.frame, self.scopes.iter().map(|scope| scope.query).collect()));
+    }
+
+    /// Start mapping the timestamps resolved by `end_frame`; call after the queue submit
+    pub fn after_submit(&mut self) {
+        let Some(gpu) = &mut self.gpu else { return };
+        if gpu.in_flight.is_none() || gpu.map_requested {
+            return;
+        }
+        gpu.map_requested = true;
+        let map_state = gpu.map_state.clone();
+        gpu.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let state = match result {
+                Ok(()) => MAP_DONE,
+                Err(e) => {
+                    log::warn!("Failed to read back GPU timestamps: {}", e);
+                    MAP_FAILED
+                }
+            };
+            map_state.store(state, Ordering::Release);
+        });
+    }
+
+    fn collect_gpu_results(&mut self) {
+        let Some(gpu) = &mut self.gpu else { return };
+        match gpu.map_state.load(Ordering::Acquire) {
+            MAP_DONE => {}
+            MAP_FAILED => {
+                // That frame goes without GPU times; the next one gets a fresh readback
+                gpu.in_flight = None;
+                gpu.map_requested = false;
+                gpu.map_state.store(MAP_PENDING, Ordering::Release);
+                return;
+            }
+            _ => return,
+        }
+        let Some((frame, queries)) = gpu.in_flight.take() else { return };
+
+        let timestamps: Vec<u64> = {
+            let data = gpu.readback_buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice(&data).to_vec()
+        };
+        gpu.readback_buffer.unmap();
+        gpu.map_state.store(MAP_PENDING, Ordering::Release);
+        gpu.map_requested = false;
+
+        let spans: Vec<Option<(u64, u64)>> = queries.iter()
+            .map(|query| query.map(|begin| (timestamps[begin as usize], timestamps[begin as usize + 1])))
+            .collect();
+        let period_ns = gpu.period_ns;
+        if let Some(timing) = self.history.iter_mut().find(|timing| timing.frame == frame) {
+            apply_gpu_spans(timing, &spans, period_ns);
+        }
+    }
+
+    pub fn history(&self) -> impl ExactSizeIterator<Item = &FrameTiming> {
+        self.history.iter()
+    }
+
+    pub fn latest(&self) -> Option<&FrameTiming> {
+        // The newest frame's interval is only known once the next one begins
+        self.history.iter().rev().nth(1)
+    }
+
+    /// Index into `history` and timing of the slowest frame currently in the window
+    pub fn worst_frame(&self) -> Option<(usize, &FrameTiming)> {
+        self.history.iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.cpu_ms.total_cmp(&b.cpu_ms))
+    }
+
+    /// Indented per-scope timings of the latest complete frame
+    pub fn report(&self) -> String {
+        let Some(frame) = self.latest() else { return String::new() };
+        let mut report = format!("frame {}: {:.2} ms cpu", frame.frame, frame.cpu_ms);
+        if let Some(gpu_ms) = frame.gpu_ms {
+            report += &format!(", {:.2} ms gpu", gpu_ms);
+        }
+        for pass in &frame.passes {
+            report += &format!("\n{}{}: {:.3} ms cpu", "  ".repeat(pass.depth + 1), pass.name, pass.cpu_ms);
+            if let Some(gpu_ms) = pass.gpu_ms {
+                report += &format!(", {:.3} ms gpu", gpu_ms);
+            }
+        }
+        report
+    }
+}
+
+/// Fill in GPU times: timed passes use their own queries, enclosing scopes span their children
+fn apply_gpu_spans(timing: &mut FrameTiming, spans: &[Option<(u64, u64)>], period_ns: f32) {
+    let to_ms = |(begin, end): (u64, u64)| end.saturating_sub(begin) as f32 * period_ns / 1_000_000.0;
+    let merge = |a: Option<(u64, u64)>, (begin, end): (u64, u64)| {
+        Some(a.map_or((begin, end), |(b, e)| (b.min(begin), e.max(end))))
+    };
+    let depths: Vec<usize> = timing.passes.iter().map(|pass| pass.depth).collect();
+
+    let mut frame_span = None;
+    for i in 0..depths.len() {
+        let mut span = spans.get(i).copied().flatten();
+        if span.is_none() {
+            // Children directly follow their parent with a greater depth
+            for j in (i + 1)..depths.len() {
+                if depths[j] <= depths[i] {
+                    break;
+                }
+                if let Some(child) = spans.get(j).copied().flatten() {
+                    span = merge(span, child);
+                }
+            }
+        }
+        if let Some(span) = span {
+            timing.passes[i].gpu_ms = Some(to_ms(span));
+            frame_span = merge(frame_span, span);
+        }
+    }
+    timing.gpu_ms = frame_span.map(to_ms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass(name: &'static str, depth: usize) -> PassTiming {
+        PassTiming { name, depth, cpu_ms: 0.0, gpu_ms: None }
+    }
+
+    #[test]
+    fn test_gpu_spans_roll_up_to_parents() {
+        let mut timing = FrameTiming {
+            passes: vec![pass("Shadow", 0), pass("Post", 0), pass("SSR", 1), pass("Grading", 1)],
+            ..FrameTiming::default()
+        };
+        // One tick per nanosecond: shadow 0-1ms, SSR 2-3ms, grading 3-5ms
+        let spans = [
+            Some((0, 1_000_000)),
+            None,
+            Some((2_000_000, 3_000_000)),
+            Some((3_000_000, 5_000_000)),
+        ];
+        apply_gpu_spans(&mut timing, &spans, 1.0);
+
+        let gpu: Vec<f32> = timing.passes.iter().map(|p| p.gpu_ms.unwrap()).collect();
+        assert_eq!(gpu, vec![1.0, 3.0, 1.0, 2.0]);
+        assert_eq!(timing.gpu_ms, Some(5.0));
+    }
+
+    #[test]
+    fn test_untimed_scopes_have_no_gpu_time() {
+        let mut timing = FrameTiming {
+            passes: vec![pass("Update", 0), pass("Scene", 0)],
+            ..FrameTiming::default()
+        };
+        apply_gpu_spans(&mut timing, &[None, Some((10, 20))], 1.0);
+        assert_eq!(timing.passes[0].gpu_ms, None);
+        assert!(timing.passes[1].gpu_ms.is_some());
+    }
+}
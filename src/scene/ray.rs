@@ -0,0 +1,88 @@
+use glam::{Mat4, Vec3};
+use super::ObjectId;
+
+/// A world-space ray with a unit direction, e.g. from the cursor or a VR controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// `direction` is normalized; a zero direction stays zero
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction: direction.normalize_or_zero() }
+    }
+
+    /// The point `distance` along the ray
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// The ray moved by `matrix`, e.g. from tracking space into the world
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        Self::new(matrix.transform_point3(self.origin), matrix.transform_vector3(self.direction))
+    }
+
+    /// Distance to where the ray crosses the plane through `point` with `normal`;
+    /// `None` if parallel or behind the origin
+    pub fn intersect_plane(&self, point: Vec3, normal: Vec3) -> Option<f32> {
+        let denominator = self.direction.dot(normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (point - self.origin).dot(normal) / denominator;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    /// Distance to where the ray enters the box `min..max` and the normal of the face it enters
+    /// through; `None` on a miss or if the ray starts inside
+    pub fn intersect_aabb(&self, min: Vec3, max: Vec3) -> Option<(f32, Vec3)> {
+        let inverse = self.direction.recip();
+        let t0 = (min - self.origin) * inverse;
+        let t1 = (max - self.origin) * inverse;
+        let near = t0.min(t1);
+        let far = t0.max(t1).min_element();
+        let entry = near.max_element();
+        if entry < 0.0 || entry > far {
+            return None;
+        }
+        // The slab entered last is the face hit; its normal faces back along the ray
+        let axis = if entry == near.x { 0 } else if entry == near.y { 1 } else { 2 };
+        let mut normal = Vec3::ZERO;
+        normal[axis] = -self.direction[axis].signum();
+        Some((entry, normal))
+    }
+}
+
+/// Where a ray met a scene object's bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub object: ObjectId,
+    pub distance: f32,
+    pub point: Vec3,
+    /// Normal of the bounds face hit
+    pub normal: Vec3,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intersect_aabb() {
+        let (min, max) = (Vec3::splat(-1.0), Vec3::splat(1.0));
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+        assert_eq!(ray.intersect_aabb(min, max), Some((4.0, Vec3::Y)));
+        let ray = Ray::new(Vec3::new(-3.0, 0.5, 0.0), Vec3::X);
+        assert_eq!(ray.intersect_aabb(min, max), Some((2.0, Vec3::NEG_X)));
+
+        // Flat boxes like the floor still catch rays from above
+        let ray = Ray::new(Vec3::new(0.5, 2.0, 0.5), Vec3::NEG_Y);
+        assert_eq!(ray.intersect_aabb(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0)), Some((2.0, Vec3::Y)));
+
+        // Pointing away, and starting inside
+        assert_eq!(Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::Y).intersect_aabb(min, max), None);
+        assert_eq!(Ray::new(Vec3::ZERO, Vec3::X).intersect_aabb(min, max), None);
+    }
+}
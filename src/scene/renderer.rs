@@ -1,12 +1,47 @@
-use crate::model::ModelVertex;
-use super::Scene;
+use std::collections::{HashMap, HashSet};
+use anyhow::Result;
+use glam::{DVec3, Mat4, Vec3};
+use crate::diagnostics;
+use crate::model::{Material, MaterialUniform, Mesh, Model, MAX_JOINTS};
+use crate::settings::{RendererSettings, SettingsChanges, ViewportMode};
+use super::{ObjectId, Scene, SceneObject};
+use super::camera::Camera;
+use super::capabilities::Capabilities;
+use super::eye_debug::{EyeDebugMode, EyeDebugPass};
+use super::frustum::ViewCulling;
+use super::grid::GridPass;
+use super::overlay::DebugOverlay;
+use super::profiler::Profiler;
+use super::msaa::{depth_sample_view, MsaaTargets};
+use super::post::{ColorGrading, ColorLut, PostStack};
+use super::variants::{Blending, PipelineKind, ShaderFeatures, ShaderVariants};
+use super::clipping::MAX_CLIP_PLANES;
+use super::compute::{ComputeStage, ComputeTask, ComputeTaskId, ComputeTasks};
+use super::inspector::{InspectId, InspectTargets, PixelInfo, PixelInspector};
+use super::picking::{PickId, PickResult, PickingPass};
+use super::viewport::Viewport;
+use super::visibility::{CullingStats, FrustumVisibility, ViewInfo, Visibility};
+use super::lights::{LightManager, LightPlan, MAX_POINT_LIGHTS, MAX_SHADOWED_LIGHTS};
+use super::shadow::{PointShadowMaps, ShadowMap, CUBE_FACES};
+use super::stencil::StencilOverlayPass;
+use super::transparency::{back_to_front, OitPass, Transparency};
+use bytemuck::Zeroable;
 use wgpu::util::DeviceExt;
 
+const SCENE_SHADER: &str = include_str!("../../shaders/shader.wgsl");
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
     view_proj: [[f32; 4]; 4],
     camera_pos: [f32; 4],
+    clip_planes: [[f32; 4]; MAX_CLIP_PLANES],
+    // x = number of clip planes
+    clip: [f32; 4],
+    cap_color: [f32; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    // x = scene time, y = last frame's
+    time: [f32; 4],
 }
 
 #[repr(C)]
@@ -15,33 +50,163 @@ struct LightUniform {
     direction: [f32; 4],
     color: [f32; 4],
     ambient: [f32; 4],
+    view_proj: [[f32; 4]; 4],
+    shadow: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointLightData {
+    position_range: [f32; 4],
+    color_intensity: [f32; 4],
+    // x = first shadow layer or -1, y = fraction of each face in use, z = texel size
+    shadow: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PointLightUniform {
+    count: [u32; 4],
+    lights: [PointLightData; MAX_POINT_LIGHTS],
+    face_view_proj: [[[f32; 4]; 4]; MAX_SHADOWED_LIGHTS * CUBE_FACES],
+}
+
+/// Check the scene uniforms against the structs in `shaders/shader.wgsl`
+#[cfg(any(test, debug_assertions))]
+pub(crate) fn check_uniform_layouts() -> Result<()> {
+    use crate::shaders;
+    use super::variants::preprocess;
+    let source = preprocess(&shaders::resolve(SCENE_SHADER)?.source, ShaderFeatures::NONE)?;
+    shaders::check_uniform_layout(&source, "CameraUniform", &crate::uniform_layout!(CameraUniform {
+        view_proj, camera_pos, clip_planes, clip, cap_color, prev_view_proj, time,
+    }))?;
+    shaders::check_uniform_layout(&source, "LightUniform", &crate::uniform_layout!(LightUniform {
+        direction, color, ambient, view_proj, shadow,
+    }))?;
+    shaders::check_uniform_layout(&source, "PointLight", &crate::uniform_layout!(PointLightData {
+        position_range, color_intensity, shadow,
+    }))?;
+    shaders::check_uniform_layout(&source, "PointLightUniform", &crate::uniform_layout!(PointLightUniform {
+        count, lights, face_view_proj,
+    }))?;
+    shaders::check_uniform_layout(&source, "ModelUniform", &crate::uniform_layout!(ModelUniform {
+        model_matrix, object_id, irradiance, prev_model_matrix,
+    }))?;
+    shaders::check_uniform_layout(&source, "MaterialUniform", &crate::uniform_layout!(MaterialUniform {
+        reflectivity, roughness, double_sided, alpha_cutoff, base_color, wind, wind_weight, chroma_key, chroma_params,
+    }))?;
+    let skinned = preprocess(&shaders::resolve(SCENE_SHADER)?.source, ShaderFeatures::SKINNED)?;
+    shaders::check_uniform_layout(&skinned, "JointUniform", &crate::uniform_layout!(JointUniform {
+        matrices,
+    }))
+}
+
+impl PointLightUniform {
+    fn new(plan: &LightPlan, shadows: &PointShadowMaps) -> Self {
+        let mut uniform = Self::zeroed();
+        uniform.count[0] = plan.lights.len() as u32;
+        for (data, planned) in uniform.lights.iter_mut().zip(&plan.lights) {
+            let light = &planned.light;
+            data.position_range = light.position.extend(light.range).to_array();
+            data.color_intensity = light.color.rgb().extend(light.intensity).to_array();
+            data.shadow = match planned.shadow {
+                Some(slot) => [(slot.index * CUBE_FACES) as f32, slot.scale(), 1.0 / shadows.face_size() as f32, 0.0],
+                None => [-1.0, 1.0, 1.0, 0.0],
+            };
+        }
+        for (face, matrix) in uniform.face_view_proj.iter_mut().zip(shadows.face_matrices()) {
+            *face = matrix.to_cols_array_2d();
+        }
+        uniform
+    }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct ModelUniform {
     model_matrix: [[f32; 4]; 4],
+    // x = object ID + 1, read by the picking pass
+    object_id: [u32; 4],
+    // Light probe irradiance at the object; zeroed for the flat ambient light
+    irradiance: [[f32; 4]; 4],
+    prev_model_matrix: [[f32; 4]; 4],
+}
+
+/// Joint matrices of a posed skinned object, read by the `SKINNED` variants
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct JointUniform {
+    matrices: [[[f32; 4]; 4]; MAX_JOINTS],
+}
+
+/// An object's model uniforms: the group every pass binds, and for skinned models posed by
+/// `Scene::skinning`, one adding their joint matrices for the scene passes. Shadows and picks
+/// use the bind pose.
+struct ModelBindings {
+    group: wgpu::BindGroup,
+    skinned: Option<wgpu::BindGroup>,
 }
 
 pub struct Renderer {
-    pub pipeline: wgpu::RenderPipeline,
+    variants: ShaderVariants,
+    compute: ComputeTasks,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     light_buffer: wgpu::Buffer,
+    point_light_buffer: wgpu::Buffer,
+    light_bind_group_layout: wgpu::BindGroupLayout,
     light_bind_group: wgpu::BindGroup,
     depth_texture: wgpu::Texture,
     depth_view: wgpu::TextureView,
+    /// Depth aspect only, for the passes that read depth
+    depth_sample_view: wgpu::TextureView,
     model_bind_group_layout: wgpu::BindGroupLayout,
-    pub material_bind_group_layout: wgpu::BindGroupLayout,
+    material_bind_group_layout: wgpu::BindGroupLayout,
     default_material_bind_group: wgpu::BindGroup,
+    shadow: ShadowMap,
+    point_shadows: PointShadowMaps,
+    lights: LightManager,
+    msaa: Option<MsaaTargets>,
+    settings: RendererSettings,
+    grid: GridPass,
+    stencil: StencilOverlayPass,
+    oit: OitPass,
+    post: PostStack,
+    picking: PickingPass,
+    inspector: PixelInspector,
+    profiler: Profiler,
+    overlay: DebugOverlay,
+    eye_debug: EyeDebugPass,
+    visibility: Box<dyn Visibility>,
+    culling_stats: CullingStats,
+    capabilities: Capabilities,
+    surface_size: (u32, u32),
+    viewport: Viewport,
+    /// Last frame's matrices, for the velocity target, in the local space of `previous_origin`
+    previous_view_projections: Vec<Mat4>,
+    previous_models: HashMap<ObjectId, Mat4>,
+    previous_origin: DVec3,
+    previous_time: Option<f32>,
 }
 
 impl Renderer {
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) -> Self {
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/shader.wgsl").into()),
-        });
+        Self::with_capabilities(device, queue, config, Capabilities::of_device(device))
+    }
+
+    /// Create a renderer reporting `capabilities`, e.g. `Capabilities::new` with the adapter's
+    /// format support and device type
+    pub fn with_capabilities(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        capabilities: Capabilities,
+    ) -> Self {
+        // A uniform the shader reads differently from how it's written renders garbage, not errors
+        #[cfg(debug_assertions)]
+        if let Err(e) = check_uniform_layouts() {
+            panic!("{:#}", e);
+        }
 
         // Create camera uniform buffer
         let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -59,6 +224,13 @@ impl Renderer {
             mapped_at_creation: false,
         });
 
+        let point_light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point Light Buffer"),
+            size: std::mem::size_of::<PointLightUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         // Create bind group layouts
         let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Camera Bind Group Layout"),
@@ -76,23 +248,66 @@ impl Renderer {
 
         let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Light Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                // Shadow map
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Shadow comparison sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                // Point lights
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Point light cube shadows, six layers per slot
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
         });
 
         let model_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Model Bind Group Layout"),
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                // The fragment stage reads the probe irradiance
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -141,6 +356,17 @@ impl Renderer {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                // Material parameters, read by the vertex stage for wind
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -154,14 +380,17 @@ impl Renderer {
             }],
         });
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Light Bind Group"),
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
+        let settings = RendererSettings::default();
+        let shadow = ShadowMap::new(device, &model_bind_group_layout, settings.shadow_quality);
+        let point_shadows = PointShadowMaps::new(device, &model_bind_group_layout, settings.shadow_quality);
+        let light_bind_group = Self::create_light_bind_group(
+            device,
+            &light_bind_group_layout,
+            &light_buffer,
+            &point_light_buffer,
+            &shadow,
+            &point_shadows,
+        );
 
         // Create default texture for meshes without textures
         let default_texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -203,6 +432,21 @@ impl Renderer {
 
         let default_texture_view = default_texture.create_view(&wgpu::TextureViewDescriptor::default());
         let default_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let default_material_params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Default Material Params"),
+            contents: bytemuck::cast_slice(&[MaterialUniform {
+                reflectivity: 0.0,
+                roughness: 1.0,
+                double_sided: 0.0,
+                alpha_cutoff: 0.0,
+                base_color: [1.0; 4],
+                wind: [0.0; 4],
+                wind_weight: [0.0; 4],
+                chroma_key: [0.0; 4],
+                chroma_params: [0.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
 
         let default_material_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Default Material Bind Group"),
@@ -228,115 +472,370 @@ impl Renderer {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&default_sampler),
                 },
+                // Material parameters
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: default_material_params.as_entire_binding(),
+                },
             ],
         });
 
         // Create depth texture
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth Texture"),
-            size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
+        let viewport = Viewport::fit(&settings.viewport, config.width, config.height);
+        let (width, height) = render_size(&viewport, &settings);
+        let depth_format = settings.depth_format.texture_format();
+        let depth_texture = create_depth_texture(device, width, height, depth_format);
         let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_sample_view = depth_sample_view(&depth_texture);
 
-        // Create pipeline layout
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[
-                &camera_bind_group_layout,
-                &light_bind_group_layout,
-                &model_bind_group_layout,
-                &material_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
-        });
+        // Scene pipelines are compiled per shader variant as meshes need them
+        let mut variants = ShaderVariants::new(
+            device,
+            SCENE_SHADER,
+            [&camera_bind_group_layout, &light_bind_group_layout, &model_bind_group_layout, &material_bind_group_layout],
+            settings.msaa_samples,
+            depth_format,
+        );
+        variants.prepare(device, ShaderFeatures::NONE, PipelineKind::Standard, Blending::Opaque);
 
-        // Create render pipeline
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[ModelVertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
+        let grid = GridPass::new(device, settings.msaa_samples, depth_format);
+        let stencil = StencilOverlayPass::new(device, settings.msaa_samples, depth_format);
+        let post = PostStack::new(device, queue, config.format, width, height, &depth_sample_view);
+        let picking = PickingPass::new(device, &model_bind_group_layout, width, height);
+        let profiler = Profiler::new(device, queue);
+        let overlay = DebugOverlay::new(device, config.format);
 
         Self {
-            pipeline,
+            variants,
+            compute: ComputeTasks::default(),
             camera_buffer,
             camera_bind_group,
             light_buffer,
+            point_light_buffer,
+            light_bind_group_layout,
             light_bind_group,
             depth_texture,
             depth_view,
+            depth_sample_view,
             model_bind_group_layout,
             material_bind_group_layout,
             default_material_bind_group,
+            shadow,
+            point_shadows,
+            lights: LightManager::new(),
+            msaa: None,
+            settings,
+            grid,
+            stencil,
+            oit: OitPass::new(device),
+            post,
+            picking,
+            inspector: PixelInspector::new(device),
+            profiler,
+            overlay,
+            eye_debug: EyeDebugPass::new(device, config.format),
+            visibility: Box::new(FrustumVisibility),
+            culling_stats: CullingStats::default(),
+            capabilities,
+            surface_size: (config.width, config.height),
+            viewport,
+            previous_view_projections: Vec::new(),
+            previous_models: HashMap::new(),
+            previous_origin: DVec3::ZERO,
+            previous_time: None,
         }
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
-        self.depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth Texture"),
-            size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
+    fn create_light_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        light_buffer: &wgpu::Buffer,
+        point_light_buffer: &wgpu::Buffer,
+        shadow: &ShadowMap,
+        point_shadows: &PointShadowMaps,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(shadow.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(shadow.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: point_light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(point_shadows.view()),
+                },
+            ],
+        })
+    }
+
+    pub fn settings(&self) -> &RendererSettings {
+        &self.settings
+    }
+
+    /// Layout that model materials must be created against, e.g. for `Model::from_data`
+    pub fn material_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_bind_group_layout
+    }
+
+    pub fn grid(&self) -> &GridPass {
+        &self.grid
+    }
+
+    pub fn post(&self) -> &PostStack {
+        &self.post
+    }
+
+    /// Direct access to post-processing parameters not covered by `RendererSettings`
+    pub fn post_mut(&mut self) -> &mut PostStack {
+        &mut self.post
+    }
+
+    pub fn picking(&self) -> &PickingPass {
+        &self.picking
+    }
+
+    pub fn picking_enabled(&self) -> bool {
+        self.picking.enabled
+    }
+
+    /// Keep the object ID target rendered every frame, e.g. to inspect it or pick on hover
+    pub fn set_picking_enabled(&mut self, enabled: bool) {
+        self.picking.enabled = enabled;
+    }
+
+    /// Pick the object under surface pixel `(x, y)`. The ID target is rendered by the next frame
+    /// and the result arrives from `poll_picks` a frame or two later; picks on the bars around
+    /// a letterboxed viewport find nothing.
+    pub fn pick(&mut self, x: f32, y: f32) -> PickId {
+        let Some((u, v)) = self.viewport.to_normalized(x, y) else {
+            return self.picking.miss();
+        };
+        // The ID target matches the scene's render size, not the viewport's
+        let (width, height) = render_size(&self.viewport, &self.settings);
+        self.picking.pick((u * width as f32) as u32, (v * height as f32) as u32)
+    }
+
+    /// Pick the first object along a world-space ray, e.g. a VR controller's pointer
+    pub fn pick_ray(&mut self, origin: Vec3, direction: Vec3) -> PickId {
+        self.picking.pick_ray(origin, direction)
+    }
+
+    /// Results of earlier picks whose readback has finished; never blocks
+    pub fn poll_picks(&mut self, device: &wgpu::Device) -> Vec<PickResult> {
+        self.picking.poll(device)
+    }
+
+    /// Read back depth, object ID, albedo, normal and lit color at surface pixel `(x, y)`
+    /// next frame; `None` outside the viewport
+    pub fn inspect(&mut self, x: f32, y: f32) -> Option<InspectId> {
+        let (u, v) = self.viewport.to_normalized(x, y)?;
+        let (width, height) = render_size(&self.viewport, &self.settings);
+        Some(self.inspector.inspect((u * width as f32) as u32, (v * height as f32) as u32))
+    }
+
+    /// Results of earlier inspections whose readback has finished; never blocks
+    pub fn poll_inspections(&mut self, device: &wgpu::Device) -> Vec<PixelInfo> {
+        self.inspector.poll(device)
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// Objects drawn and culled in the last frame
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    /// Features, limits and format support of the device, and the quality tier derived from them
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Scene pipeline variants compiled so far for the current MSAA setting
+    pub fn shader_variant_count(&self) -> usize {
+        self.variants.pipeline_count()
+    }
+
+    /// Dispatch `task` every frame at `stage`, after the tasks already added there
+    pub fn add_compute_task(&mut self, stage: ComputeStage, task: ComputeTask) -> ComputeTaskId {
+        self.compute.add(stage, task)
+    }
+
+    pub fn remove_compute_task(&mut self, id: ComputeTaskId) -> Option<ComputeTask> {
+        self.compute.remove(id)
+    }
+
+    /// Adjust a task's workgroups, bindings or enabled flag between frames
+    pub fn compute_task_mut(&mut self, id: ComputeTaskId) -> Option<&mut ComputeTask> {
+        self.compute.get_mut(id)
+    }
+
+    pub fn overlay_enabled(&self) -> bool {
+        self.overlay.enabled
+    }
+
+    pub fn set_overlay_enabled(&mut self, enabled: bool) {
+        self.overlay.enabled = enabled;
+    }
+
+    /// Replace how objects are culled, e.g. with `AllVisible` or an application's own spatial
+    /// structure; `FrustumVisibility` is the default
+    pub fn set_visibility(&mut self, visibility: impl Visibility + 'static) {
+        self.visibility = Box::new(visibility);
+    }
+
+    pub fn eye_debug(&self) -> &EyeDebugPass {
+        &self.eye_debug
+    }
+
+    /// Capture per-view debug panels from the next frame on; see `render_eye_debug`
+    pub fn set_eye_debug_mode(&mut self, mode: EyeDebugMode) {
+        self.eye_debug.set_mode(mode);
+    }
+
+    /// Draw the debug panels of the views last rendered onto `target`, the `width` by `height`
+    /// desktop mirror, replacing what was there. Does nothing while `EyeDebugMode::Off`.
+    pub fn render_eye_debug(&self, device: &wgpu::Device, queue: &wgpu::Queue, target: &wgpu::TextureView, width: u32, height: u32) {
+        if self.eye_debug.layout().is_none() {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Eye Debug Encoder"),
         });
+        self.eye_debug.render(&mut encoder, target, width, height);
+        diagnostics::scoped(device, "Eye Debug", || queue.submit(std::iter::once(encoder.finish())));
+    }
+
+    /// Apply new settings, rebuilding only the resources affected by the change
+    pub fn apply_settings(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        settings: &RendererSettings,
+    ) -> Result<SettingsChanges> {
+        let settings = settings.clone().sanitized();
+        let changes = self.settings.changes(&settings);
+        let previous = std::mem::replace(&mut self.settings, settings);
+
+        if changes.shadows {
+            self.shadow.set_quality(device, self.settings.shadow_quality);
+            self.point_shadows.set_quality(device, self.settings.shadow_quality);
+            self.lights.invalidate();
+            self.light_bind_group = Self::create_light_bind_group(
+                device,
+                &self.light_bind_group_layout,
+                &self.light_buffer,
+                &self.point_light_buffer,
+                &self.shadow,
+                &self.point_shadows,
+            );
+        }
+
+        if changes.msaa || changes.depth_format {
+            let (sample_count, depth_format) = (self.settings.msaa_samples, self.settings.depth_format.texture_format());
+            self.variants.set_targets(sample_count, depth_format);
+            self.variants.prepare(device, ShaderFeatures::NONE, PipelineKind::Standard, Blending::Opaque);
+            self.grid.set_targets(device, sample_count, depth_format);
+            self.stencil.set_targets(sample_count, depth_format);
+        }
+
+        if changes.grid {
+            self.grid.enabled = self.settings.show_grid;
+        }
+
+        if changes.window_alpha {
+            self.post.output_alpha = self.settings.window_alpha;
+        }
+
+        if changes.msaa || changes.depth_format || changes.resolution {
+            self.resize(device, config);
+        }
+
+        if changes.post {
+            let post = &self.settings.post;
+            self.post.ssr_quality = post.ssr;
+            self.post.grading = ColorGrading {
+                exposure_ev: post.exposure_ev,
+                auto_exposure: post.auto_exposure,
+                adaptation_speed: post.adaptation_speed,
+                tonemapper: post.tonemapper,
+                contrast: post.contrast,
+                saturation: post.saturation,
+                lut_strength: if post.lut.is_some() { post.lut_strength } else { 0.0 },
+                ..self.post.grading
+            };
+            self.post.motion_blur = post.motion_blur;
+        }
+
+        if changes.lut {
+            let lut = match &self.settings.post.lut {
+                Some(path) => ColorLut::from_strip_image(path)
+                    .map_err(|e| e.context(format!("Failed to load LUT {}", path.display()))),
+                None => Ok(ColorLut::identity(16)),
+            };
+            match lut {
+                Ok(lut) => self.post.set_lut(device, queue, &lut),
+                Err(e) => {
+                    // Keep the previous LUT; everything else has already been applied
+                    self.settings.post.lut = previous.post.lut;
+                    if self.settings.post.lut.is_none() {
+                        self.post.grading.lut_strength = 0.0;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        if changes.lens_preview {
+            match self.settings.post.lens_preview.load() {
+                Ok(lens) => self.post.lens = lens,
+                Err(e) => {
+                    // Keep the previous preview, as with the LUT
+                    self.settings.post.lens_preview = previous.post.lens_preview;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Where the scene lands on the surface; the camera's aspect ratio should match it
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.surface_size = (config.width, config.height);
+        self.viewport = Viewport::fit(&self.settings.viewport, config.width, config.height);
+        let (width, height) = render_size(&self.viewport, &self.settings);
+        let depth_format = self.settings.depth_format.texture_format();
+        self.depth_texture = create_depth_texture(device, width, height, depth_format);
         self.depth_view = self.depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.depth_sample_view = depth_sample_view(&self.depth_texture);
+        self.msaa = (self.settings.msaa_samples > 1)
+            .then(|| MsaaTargets::new(device, width, height, self.settings.msaa_samples, depth_format));
+        self.post.resize(device, width, height, &self.depth_sample_view);
+        self.picking.resize(device, width, height);
     }
 
+    /// Render the scene from its camera into `view`
     pub fn render(
         &mut self,
         device: &wgpu::Device,
@@ -344,72 +843,146 @@ impl Renderer {
         view: &wgpu::TextureView,
         scene: &Scene,
     ) -> Result<(), wgpu::SurfaceError> {
-        // Update camera uniform buffer
-        let camera_uniform = CameraUniform {
-            view_proj: scene.camera.build_view_projection_matrix().to_cols_array_2d(),
-            camera_pos: [scene.camera.position.x, scene.camera.position.y, scene.camera.position.z, 1.0],
-        };
-        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+        self.render_views(device, queue, scene, &[RenderView::from_camera(&scene.camera, view)])
+    }
+
+    /// Render the scene from each of `views`, such as the eyes of a headset. Culling, shadow
+    /// maps and picking run once per frame whatever the number of views: the `Visibility` set
+    /// with `set_visibility` picks the objects to draw (by default those in one frustum bounding
+    /// every view), which are then tested against each view's own frustum when
+    /// `VrStereoSettings::precise_culling` is on. The debug overlay only draws over a single view.
+    pub fn render_views(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        views: &[RenderView],
+    ) -> Result<(), wgpu::SurfaceError> {
+        self.profiler.begin_frame(device);
+        // Independent compute goes to the GPU first, to run while the rest of the frame is
+        // recorded: auto-exposure over the last frame's image, and the `Async` compute tasks
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Async Compute Encoder"),
+        });
+        let exposure = self.post.update_exposure(&mut encoder, queue, &mut self.profiler);
+        if exposure || self.compute.has_enabled(ComputeStage::Async) {
+            self.compute.run(&mut encoder, ComputeStage::Async, &mut self.profiler);
+            diagnostics::scoped(device, "Async compute", || queue.submit(std::iter::once(encoder.finish())));
+        }
+        self.profiler.begin_scope("Prepare");
 
         // Update light uniform buffer
+        let (bounds_min, bounds_max) = scene.bounds().unwrap_or((Vec3::splat(-1.0), Vec3::ONE));
+        let light_view_proj = ShadowMap::light_view_projection(
+            scene.light_direction,
+            (bounds_min + bounds_max) * 0.5,
+            (bounds_max - bounds_min).length() * 0.5,
+        );
         let light_uniform = LightUniform {
             direction: [scene.light_direction.x, scene.light_direction.y, scene.light_direction.z, 0.0],
-            color: [scene.directional_light.x, scene.directional_light.y, scene.directional_light.z, 1.0],
-            ambient: [scene.ambient_light.x, scene.ambient_light.y, scene.ambient_light.z, 1.0],
+            color: scene.directional_light.rgb().extend(1.0).to_array(),
+            ambient: scene.ambient_light.rgb().extend(1.0).to_array(),
+            view_proj: light_view_proj.to_cols_array_2d(),
+            shadow: self.shadow.params(),
         };
         queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[light_uniform]));
 
-        // Create command encoder
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
-        });
+        // Pick the point lights to shade and the shadow maps to refresh, around the point between
+        // the views; no shadows when they're off
+        let mut light_settings = self.settings.lights.clone();
+        if self.shadow.quality().map_size().is_none() {
+            light_settings.shadow_budget = 0;
+        }
+        let viewer = views.iter().map(RenderView::position).sum::<Vec3>() / views.len().max(1) as f32;
+        let light_plan = self.lights.plan(&scene.lights, viewer, scene.camera.far, &light_settings);
 
-        // Begin render pass
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+        // Resolve model handles once; hidden objects and those whose model isn't registered are skipped.
+        // Objects culled by portals or left out by `self.visibility` still cast shadows into the
+        // visible ones.
+        let view_projections: Vec<_> = views.iter().map(RenderView::view_projection).collect();
+        let culling = ViewCulling::new(&view_projections, self.settings.vr_stereo.precise_culling);
+        self.eye_debug.begin_frame(device, &view_projections, render_size(&self.viewport, &self.settings));
+        let in_view: HashSet<ObjectId> = self.visibility
+            .visible_objects(&ViewInfo { scene, view_projections: &view_projections, viewer, culling: &culling })
+            .collect();
+        let visible_cells = scene.portals.visible_cells(&scene.camera);
+        let drawables: Vec<Drawable> = scene.objects.iter()
+            .filter(|object| object.visible)
+            .filter_map(|object| {
+                let model = scene.assets.model(object.model)?;
+                let visible = scene.portals.is_visible(object.id, visible_cells.as_deref());
+                let bounds = object.world_bounds(&scene.assets);
+                let views = match bounds {
+                    _ if !visible || !in_view.contains(&object.id) => 0,
+                    Some((min, max)) => culling.view_mask(min, max),
+                    None => culling.all(),
+                };
+                let material_override = object.material.and_then(|handle| scene.assets.material(handle));
+                let transparent = model.all_meshes().any(|mesh| {
+                    material_override.or_else(|| model.materials.get(mesh.material_index))
+                        .is_some_and(|material| material.transparent)
+                });
+                let center = bounds.map_or(object.transform.position, |(min, max)| (min + max) * 0.5);
+                Some(Drawable { object, model, visible, views, transparent, center })
+            })
+            .collect();
+        self.culling_stats = CullingStats {
+            objects: drawables.len(),
+            drawn: drawables.iter().filter(|drawable| drawable.views != 0).count(),
+            culled: drawables.iter().filter(|drawable| drawable.visible && !in_view.contains(&drawable.object.id)).count(),
+            portal_culled: drawables.iter().filter(|drawable| !drawable.visible).count(),
+            view_draws: drawables.iter().map(|drawable| drawable.views.count_ones() as usize).sum(),
+        };
 
-            // Set pipeline and bind groups
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+        let model_matrices: Vec<Mat4> = drawables.iter()
+            .map(|drawable| {
+                let object = &drawable.object;
+                // Precise objects are composed in f64 relative to the local origin, then narrowed
+                #[cfg(feature = "f64-transforms")]
+                let model_matrix = scene.precise.matrix(object.id, &object.transform, &scene.origin)
+                    .unwrap_or_else(|| object.transform.to_matrix());
+                #[cfg(not(feature = "f64-transforms"))]
+                let model_matrix = object.transform.to_matrix();
+                model_matrix
+            })
+            .collect();
 
-            // Draw each object
-            for (model, transform) in &scene.objects {
-                // Create model uniform buffer and bind group
+        // Last frame's matrices move with the local origin when it was rebased since, so a rebase
+        // doesn't read as motion
+        let origin = scene.origin.offset();
+        if origin != self.previous_origin {
+            let shift = (origin - self.previous_origin).as_vec3();
+            for view_proj in &mut self.previous_view_projections {
+                *view_proj *= Mat4::from_translation(shift);
+            }
+            for model_matrix in self.previous_models.values_mut() {
+                *model_matrix = Mat4::from_translation(-shift) * *model_matrix;
+            }
+            self.previous_origin = origin;
+        }
+
+        // Per-object model uniforms, shared by the shadow and scene passes
+        let model_bind_groups: Vec<ModelBindings> = drawables.iter()
+            .zip(&model_matrices)
+            .map(|(drawable, model_matrix)| {
+                let object = &drawable.object;
+                // Probes are sampled once per object, at the center of its bounds
+                let irradiance = scene.probes.as_ref()
+                    .map_or([[0.0; 4]; 4], |probes| probes.sample(drawable.center).to_uniform());
+                // Objects that weren't drawn last frame haven't moved
+                let prev_model_matrix = self.previous_models.get(&object.id).unwrap_or(model_matrix);
                 let model_uniform = ModelUniform {
-                    model_matrix: transform.to_matrix().to_cols_array_2d(),
+                    model_matrix: model_matrix.to_cols_array_2d(),
+                    object_id: [object.id.0 + 1, 0, 0, 0],
+                    irradiance,
+                    prev_model_matrix: prev_model_matrix.to_cols_array_2d(),
                 };
                 let model_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: Some("Model Buffer"),
                     contents: bytemuck::cast_slice(&[model_uniform]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
-                let model_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                     label: Some("Model Bind Group"),
                     layout: &self.model_bind_group_layout,
                     entries: &[wgpu::BindGroupEntry {
@@ -417,29 +990,499 @@ impl Renderer {
                         resource: model_buffer.as_entire_binding(),
                     }],
                 });
+                let skinned = scene.skinning.joint_matrices(object.id)
+                    .filter(|_| drawable.model.all_meshes().any(Mesh::is_skinned))
+                    .map(|palette| {
+                        // Joints past the palette keep the bind pose
+                        let mut joints = JointUniform { matrices: [Mat4::IDENTITY.to_cols_array_2d(); MAX_JOINTS] };
+                        for (slot, matrix) in joints.matrices.iter_mut().zip(palette) {
+                            *slot = matrix.to_cols_array_2d();
+                        }
+                        let joint_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Joint Buffer"),
+                            contents: bytemuck::bytes_of(&joints),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        });
+                        device.create_bind_group(&wgpu::BindGroupDescriptor {
+                            label: Some("Skinned Model Bind Group"),
+                            layout: self.variants.skinned_model_layout(),
+                            entries: &[
+                                wgpu::BindGroupEntry {
+                                    binding: 0,
+                                    resource: model_buffer.as_entire_binding(),
+                                },
+                                wgpu::BindGroupEntry {
+                                    binding: 1,
+                                    resource: joint_buffer.as_entire_binding(),
+                                },
+                            ],
+                        })
+                    });
+                ModelBindings { group, skinned }
+            })
+            .collect();
+        self.profiler.end_scope();
+
+        // Compile the shader variants this frame draws with that aren't cached yet
+        let section = scene.clipping.is_active() && scene.clipping.caps;
+        for (drawable, bindings) in drawables.iter().zip(&model_bind_groups) {
+            if drawable.views == 0 {
+                continue;
+            }
+            let material_override = drawable.object.material.and_then(|handle| scene.assets.material(handle));
+            for mesh in drawable.model.all_meshes() {
+                let material = material_override.or_else(|| drawable.model.materials.get(mesh.material_index));
+                let (features, kind, blending) = pipeline_key(mesh, material, bindings.skinned.is_some(), section, scene.transparency);
+                self.variants.prepare(device, features, kind, blending);
+            }
+        }
+        if scene.transparency == Transparency::WeightedBlended && drawables.iter().any(|drawable| drawable.transparent) {
+            let (width, height) = render_size(&self.viewport, &self.settings);
+            self.oit.prepare(device, width, height, self.settings.msaa_samples);
+        }
 
-                render_pass.set_bind_group(2, &model_bind_group, &[]);
+        // Create command encoder
+        let encoder_descriptor = wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        };
+        let mut encoder = device.create_command_encoder(&encoder_descriptor);
+        self.compute.run(&mut encoder, ComputeStage::BeforeShadows, &mut self.profiler);
 
-                for mesh in &model.meshes {
-                    // Set material bind group if available, otherwise use default
-                    if let Some(material) = model.materials.get(mesh.material_index) {
-                        if let Some(bind_group) = &material.bind_group {
-                            render_pass.set_bind_group(3, bind_group, &[]);
-                        } else {
-                            render_pass.set_bind_group(3, &self.default_material_bind_group, &[]);
-                        }
-                    } else {
-                        render_pass.set_bind_group(3, &self.default_material_bind_group, &[]);
-                    }
+        if self.shadow.quality().map_size().is_some() {
+            let casters: Vec<_> = drawables.iter()
+                .zip(&model_bind_groups)
+                .map(|(drawable, bindings)| (drawable.model, &bindings.group))
+                .collect();
+            self.shadow.render(&mut encoder, queue, light_view_proj, &casters, &mut self.profiler);
+        }
 
-                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                    render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
-                }
+        if !light_plan.shadow_updates.is_empty() {
+            let updates: Vec<_> = light_plan.shadow_updates.iter()
+                .filter_map(|index| {
+                    let planned = &light_plan.lights[*index];
+                    planned.shadow.map(|slot| (slot, planned.light))
+                })
+                .collect();
+            let casters: Vec<_> = drawables.iter()
+                .zip(&model_bind_groups)
+                .filter_map(|(drawable, bindings)| {
+                    drawable.object.world_bounds(&scene.assets).map(|bounds| (drawable.model, &bindings.group, bounds))
+                })
+                .collect();
+            self.point_shadows.render(&mut encoder, queue, &updates, &casters, &mut self.profiler);
+        }
+        // Written after the shadow pass so the face matrices match what was just rendered
+        let point_light_uniform = PointLightUniform::new(&light_plan, &self.point_shadows);
+        queue.write_buffer(&self.point_light_buffer, 0, bytemuck::bytes_of(&point_light_uniform));
+
+        // Object IDs of what's visible, for this frame's picks; rays may point out of every view
+        let pick_objects: Vec<_> = drawables.iter()
+            .zip(&model_bind_groups)
+            .filter(|(drawable, _)| drawable.visible)
+            .map(|(drawable, bindings)| {
+                let material_override = drawable.object.material.and_then(|handle| scene.assets.material(handle));
+                (drawable.model, material_override, &bindings.group)
+            })
+            .collect();
+        if self.inspector.has_requests() {
+            self.picking.request_ids();
+        }
+        self.picking.render(
+            device,
+            queue,
+            &mut encoder,
+            scene.camera.build_view_projection_matrix(),
+            scene.camera.far,
+            &scene.clipping,
+            &pick_objects,
+            &mut self.profiler,
+        );
+
+        self.compute.run(&mut encoder, ComputeStage::BeforeScene, &mut self.profiler);
+        self.stencil.prepare(device, queue, &scene.stencil.overlays);
+
+        for (index, view) in views.iter().enumerate() {
+            if index > 0 {
+                // Each view rewrites the camera and post uniforms, which take effect at the next submit
+                let finished = std::mem::replace(&mut encoder, device.create_command_encoder(&encoder_descriptor));
+                diagnostics::scoped(device, "Frame", || queue.submit(std::iter::once(finished.finish())));
             }
+            self.render_view(device, queue, &mut encoder, scene, index, views.len() > 1, view, &drawables, &model_bind_groups);
+        }
+        self.eye_debug.end_frame();
+        self.previous_models = drawables.iter()
+            .zip(model_matrices)
+            .map(|(drawable, model_matrix)| (drawable.object.id, model_matrix))
+            .collect();
+        self.previous_view_projections = view_projections;
+        self.previous_time = Some(scene.time());
+
+        if let [view] = views {
+            let (width, height) = self.surface_size;
+            self.overlay.render(device, queue, &mut encoder, &mut self.profiler, view.target, width, height);
         }
 
-        queue.submit(std::iter::once(encoder.finish()));
+        self.profiler.end_frame(&mut encoder);
+        // Pass encoding errors surface when the encoder is finished
+        diagnostics::scoped(device, "Frame", || queue.submit(std::iter::once(encoder.finish())));
+        self.profiler.after_submit();
+        self.picking.after_submit();
+        self.inspector.after_submit();
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Encode the scene and post passes of view `index`, which is bit `index` in `Drawable::views`
+    /// and one of a headset's eyes when `stereo`
+    #[allow(clippy::too_many_arguments)]
+    fn render_view(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &Scene,
+        index: usize,
+        stereo: bool,
+        view: &RenderView,
+        drawables: &[Drawable],
+        model_bind_groups: &[ModelBindings],
+    ) {
+        let mask = 1 << index;
+        // Update camera uniform buffer
+        let view_proj = view.view_projection();
+        let position = view.position();
+        let clip_planes = scene.clipping.active_planes();
+        let mut camera_uniform = CameraUniform {
+            view_proj: view_proj.to_cols_array_2d(),
+            camera_pos: position.extend(1.0).to_array(),
+            clip_planes: [[0.0; 4]; MAX_CLIP_PLANES],
+            clip: [clip_planes.len() as f32, 0.0, 0.0, 0.0],
+            cap_color: scene.clipping.cap_color.with_alpha(1.0).to_array(),
+            prev_view_proj: self.previous_view_projections.get(index).unwrap_or(&view_proj).to_cols_array_2d(),
+            time: [scene.time(), self.previous_time.unwrap_or(scene.time()), 0.0, 0.0],
+        };
+        for (uniform, plane) in camera_uniform.clip_planes.iter_mut().zip(clip_planes) {
+            *uniform = plane.to_vec4().to_array();
+        }
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+        self.grid.prepare(queue, view_proj, position, scene.origin.to_local(glam::DVec3::ZERO));
+
+        // With MSAA the scene is drawn multisampled and resolved into the post inputs
+        let (color_view, color_resolve) = match &self.msaa {
+            Some(msaa) => (msaa.color_view(), Some(self.post.hdr_view())),
+            None => (self.post.hdr_view(), None),
+        };
+        let (normal_view, normal_resolve) = match &self.msaa {
+            Some(msaa) => (msaa.normal_view(), Some(self.post.normal_view())),
+            None => (self.post.normal_view(), None),
+        };
+        let (albedo_view, albedo_resolve) = match &self.msaa {
+            Some(msaa) => (msaa.albedo_view(), Some(self.post.albedo_view())),
+            None => (self.post.albedo_view(), None),
+        };
+        let (velocity_view, velocity_resolve) = match &self.msaa {
+            Some(msaa) => (msaa.velocity_view(), Some(self.post.velocity_view())),
+            None => (self.post.velocity_view(), None),
+        };
+        let depth_view = self.msaa.as_ref().map_or(&self.depth_view, |msaa| msaa.depth_view());
+        let stencil = self.settings.depth_format.has_stencil();
+        let section = scene.clipping.is_active() && scene.clipping.caps;
+        let meshes = MeshDraws {
+            variants: &self.variants,
+            camera_bind_group: &self.camera_bind_group,
+            light_bind_group: &self.light_bind_group,
+            default_material: &self.default_material_bind_group,
+            scene,
+            mask,
+            section,
+            stencil,
+        };
+        // Transparent objects this view sees, drawn after the opaque ones
+        let transparent: Vec<usize> = drawables.iter()
+            .enumerate()
+            .filter(|(_, drawable)| drawable.transparent && drawable.views & mask != 0)
+            .map(|(index, _)| index)
+            .collect();
+
+        // Begin render pass
+        let queries = self.profiler.begin_pass("Scene");
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: color_view,
+                        resolve_target: color_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(self.post.output_alpha.clear_color(scene.clear_color.into())),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: normal_view,
+                        resolve_target: normal_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: albedo_view,
+                        resolve_target: albedo_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    // The background doesn't move
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: velocity_view,
+                        resolve_target: velocity_resolve,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    // Cleared to 0, so pixels no object covers match no mark
+                    stencil_ops: stencil.then_some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                }),
+                timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+                occlusion_query_set: None,
+            });
+
+            // Draw each object this view sees
+            meshes.draw(&mut render_pass, drawables.iter().zip(model_bind_groups), Blending::Opaque);
+
+            // Grid blends over the finished opaque scene, then sorted transparent objects farthest
+            // first, and stencil overlays over everything
+            self.grid.draw(&mut render_pass);
+            if scene.transparency == Transparency::Sorted {
+                let centers: Vec<Vec3> = transparent.iter().map(|&index| drawables[index].center).collect();
+                let sorted = back_to_front(&centers, position).into_iter().map(|order| transparent[order]);
+                meshes.draw(&mut render_pass, sorted.map(|index| (&drawables[index], &model_bind_groups[index])), Blending::Alpha);
+            }
+            self.stencil.draw(&mut render_pass);
+        }
+        self.profiler.end_scope();
+
+        // Weighted blended transparency accumulates against the scene depth, then composites
+        // over the resolved color
+        if scene.transparency == Transparency::WeightedBlended && !transparent.is_empty() {
+            let queries = self.profiler.begin_pass("Transparency");
+            if let Some(mut render_pass) = self.oit.begin_accumulation(encoder, depth_view, stencil, queries.as_ref()) {
+                let layers = transparent.iter().map(|&index| (&drawables[index], &model_bind_groups[index]));
+                meshes.draw(&mut render_pass, layers, Blending::WeightedBlended);
+            }
+            self.oit.composite(encoder, self.post.hdr_view());
+            self.profiler.end_scope();
+        }
+
+        // Post passes read single-sample depth
+        if let Some(msaa) = &self.msaa {
+            msaa.resolve_depth(encoder, &self.depth_view, &mut self.profiler);
+        }
+        // Inspections read the first view, like picks
+        if index == 0 && self.inspector.has_requests() {
+            let [color, normal, albedo] = self.post.scene_textures();
+            let targets = InspectTargets { color, normal, albedo, depth: &self.depth_sample_view, ids: self.picking.id_texture() };
+            self.inspector.copy(device, encoder, &targets, view_proj);
+        }
+        self.compute.run(encoder, ComputeStage::AfterScene, &mut self.profiler);
+
+        // Post-processing writes the final image to the view's target; stereo views get neither
+        // motion blur, which is uncomfortable in a headset, nor the lens preview, which the
+        // headset's own lenses provide
+        self.profiler.begin_scope("Post");
+        self.post.render(encoder, queue, view.view, view.projection, view.target, &self.viewport, !stereo, &mut self.profiler);
+        self.profiler.end_scope();
+        self.compute.run(encoder, ComputeStage::AfterPost, &mut self.profiler);
+
+        // Debug panels read the scene color and depth before the next view overwrites them
+        self.eye_debug.capture(
+            device,
+            encoder,
+            index,
+            position,
+            scene.camera.far,
+            self.post.hdr_view(),
+            &self.depth_sample_view,
+            &mut self.profiler,
+        );
+    }
+}
+
+/// A scene object resolved for drawing this frame
+struct Drawable<'a> {
+    object: SceneObject,
+    model: &'a Model,
+    /// Not culled by portals, so it can be picked
+    visible: bool,
+    /// Bit per view that may see it
+    views: u32,
+    /// Has a mesh with a transparent material
+    transparent: bool,
+    /// Center of its world bounds, or its position without any
+    center: Vec3,
+}
+
+/// What drawing scene meshes into one view needs
+struct MeshDraws<'a> {
+    variants: &'a ShaderVariants,
+    camera_bind_group: &'a wgpu::BindGroup,
+    light_bind_group: &'a wgpu::BindGroup,
+    default_material: &'a wgpu::BindGroup,
+    scene: &'a Scene,
+    /// Bit of the view in `Drawable::views`
+    mask: u32,
+    section: bool,
+    stencil: bool,
+}
+
+impl MeshDraws<'_> {
+    /// Draw the meshes of `drawables` that this view sees and that are drawn with `blending`,
+    /// in the order given. The pipeline is picked per mesh.
+    fn draw<'d>(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        drawables: impl Iterator<Item = (&'d Drawable<'d>, &'d ModelBindings)>,
+        blending: Blending,
+    ) {
+        // Passes drawn in between, like the grid, bind their own groups
+        render_pass.set_bind_group(0, self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, self.light_bind_group, &[]);
+        let mut current_key = None;
+        for (drawable, bindings) in drawables {
+            if drawable.views & self.mask == 0 || (blending != Blending::Opaque && !drawable.transparent) {
+                continue;
+            }
+            let (object, model) = (&drawable.object, drawable.model);
+            let posed = bindings.skinned.is_some();
+            if self.stencil {
+                render_pass.set_stencil_reference(u32::from(self.scene.stencil.reference(object.id)));
+            }
+            diagnostics::breadcrumb(format!("Scene: object {}", object.id.0));
+            let material_override = object.material.and_then(|handle| self.scene.assets.material(handle));
+
+            for mesh in model.all_meshes() {
+                let material = material_override.or_else(|| model.materials.get(mesh.material_index));
+                let key = pipeline_key(mesh, material, posed, self.section, self.scene.transparency);
+                if key.2 != blending {
+                    continue;
+                }
+                // Skinned variants take the joint matrices with the model uniform
+                let skinned = key.0.contains(ShaderFeatures::SKINNED);
+                let model_bind_group = match &bindings.skinned {
+                    Some(group) if skinned => group,
+                    _ => &bindings.group,
+                };
+                render_pass.set_bind_group(2, model_bind_group, &[]);
+                // Set material bind group if available, otherwise use default
+                let bind_group = material
+                    .and_then(|material| material.bind_group.as_ref())
+                    .unwrap_or(self.default_material);
+                render_pass.set_bind_group(3, bind_group, &[]);
+
+                if current_key != Some(key) {
+                    let Some(pipeline) = self.variants.get(key.0, key.1, key.2) else {
+                        continue;
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    current_key = Some(key);
+                }
+
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                if let Some(skin_buffer) = mesh.skin_buffer.as_ref().filter(|_| skinned) {
+                    render_pass.set_vertex_buffer(1, skin_buffer.slice(..));
+                }
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+    }
+}
+
+/// A view to render the scene from, such as one eye of a headset
+pub struct RenderView<'a> {
+    pub view: Mat4,
+    /// Same depth convention as `Camera`'s: the scene is depth tested with `Less` against a
+    /// depth buffer cleared to 1
+    pub projection: Mat4,
+    /// Where the finished image goes, at the size the renderer was last resized to
+    pub target: &'a wgpu::TextureView,
+}
+
+impl<'a> RenderView<'a> {
+    pub fn from_camera(camera: &Camera, target: &'a wgpu::TextureView) -> Self {
+        Self { view: camera.build_view_matrix(), projection: camera.build_projection_matrix(), target }
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection * self.view
+    }
+
+    /// Where the view is seen from
+    pub fn position(&self) -> Vec3 {
+        self.view.inverse().w_axis.truncate()
+    }
+}
+
+/// Shader variant, pipeline kind and blending `mesh` is drawn with; `posed` when its object has
+/// joint matrices, `section` while section caps are on
+fn pipeline_key(
+    mesh: &Mesh,
+    material: Option<&Material>,
+    posed: bool,
+    section: bool,
+    transparency: Transparency,
+) -> (ShaderFeatures, PipelineKind, Blending) {
+    let blending = match transparency {
+        _ if !material.is_some_and(|material| material.transparent) => Blending::Opaque,
+        Transparency::Sorted => Blending::Alpha,
+        Transparency::WeightedBlended => Blending::WeightedBlended,
+    };
+    // The section pipeline handles double-sided materials itself; transparent surfaces are
+    // clipped but not capped
+    let kind = if section && blending == Blending::Opaque {
+        PipelineKind::Section
+    } else if material.is_some_and(|material| material.double_sided) {
+        PipelineKind::DoubleSided
+    } else {
+        PipelineKind::Standard
+    };
+    (ShaderFeatures::for_mesh(mesh, material, posed), kind, blending)
+}
+
+/// Scene render size: the fixed internal resolution, or the viewport at the resolution scale
+fn render_size(viewport: &Viewport, settings: &RendererSettings) -> (u32, u32) {
+    if let ViewportMode::Fixed { width, height } = settings.viewport {
+        return (width, height);
+    }
+    let scale = |size: u32| ((size as f32 * settings.resolution_scale).round() as u32).max(1);
+    (scale(viewport.width), scale(viewport.height))
+}
+
+fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
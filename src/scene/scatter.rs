@@ -0,0 +1,396 @@
+use std::f32::consts::TAU;
+use anyhow::{bail, Result};
+use glam::{EulerRot, Quat, Vec2, Vec3, Vec3Swizzles};
+use crate::model::{ImageData, MeshData, ModelHandle};
+use crate::noise::Fbm;
+use crate::simulation::Rng;
+use super::{ObjectId, Scene, Transform};
+
+/// Ground instances are scattered on, seen from above
+pub trait ScatterSurface {
+    /// Height and upward normal of the ground at (`x`, `z`); `None` where there is none
+    fn sample(&self, x: f32, z: f32) -> Option<(f32, Vec3)>;
+}
+
+/// A heightfield `height(x, z)`, with normals from central differences
+impl<F: Fn(f32, f32) -> f32> ScatterSurface for F {
+    fn sample(&self, x: f32, z: f32) -> Option<(f32, Vec3)> {
+        const STEP: f32 = 0.01;
+        let height = self(x, z);
+        let dx = self(x + STEP, z) - self(x - STEP, z);
+        let dz = self(x, z + STEP) - self(x, z - STEP);
+        let normal = Vec3::new(-dx, 2.0 * STEP, -dz).try_normalize()?;
+        height.is_finite().then_some((height, normal))
+    }
+}
+
+/// Terrain triangles, binned in a grid over XZ so a sample only tests the triangles around it
+pub struct MeshSurface {
+    triangles: Vec<[Vec3; 3]>,
+    min: Vec2,
+    cell: Vec2,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<u32>>,
+}
+
+impl MeshSurface {
+    /// The triangles of `meshes` placed at `transform`, e.g. `MeshSurface::new(&terrain.meshes, &transform)`
+    pub fn new<'a>(meshes: impl IntoIterator<Item = &'a MeshData>, transform: &Transform) -> Self {
+        let matrix = transform.to_matrix();
+        let triangles: Vec<[Vec3; 3]> = meshes.into_iter()
+            .flat_map(|mesh| mesh.indices.chunks_exact(3).map(move |triangle| {
+                [0, 1, 2].map(|corner| matrix.transform_point3(Vec3::from(mesh.vertices[triangle[corner] as usize].position)))
+            }))
+            .collect();
+
+        let (min, max) = triangles.iter().flatten()
+            .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), v| (min.min(v.xz()), max.max(v.xz())));
+        // About one triangle per cell for evenly tessellated terrain
+        let resolution = ((triangles.len() as f32).sqrt().ceil() as usize).max(1);
+        let (columns, rows) = (resolution, resolution);
+        let cell = ((max - min) / resolution as f32).max(Vec2::splat(1e-6));
+        let mut surface = Self { triangles: Vec::new(), min, cell, columns, rows, cells: vec![Vec::new(); columns * rows] };
+
+        for (index, triangle) in triangles.iter().enumerate() {
+            let low = triangle.iter().fold(Vec2::INFINITY, |low, v| low.min(v.xz()));
+            let high = triangle.iter().fold(Vec2::NEG_INFINITY, |high, v| high.max(v.xz()));
+            let (first, last) = (surface.cell_of(low), surface.cell_of(high));
+            for row in first.1..=last.1 {
+                for column in first.0..=last.0 {
+                    surface.cells[row * columns + column].push(index as u32);
+                }
+            }
+        }
+        surface.triangles = triangles;
+        surface
+    }
+
+    fn cell_of(&self, point: Vec2) -> (usize, usize) {
+        let cell = ((point - self.min) / self.cell).floor();
+        (
+            (cell.x.max(0.0) as usize).min(self.columns - 1),
+            (cell.y.max(0.0) as usize).min(self.rows - 1),
+        )
+    }
+}
+
+impl ScatterSurface for MeshSurface {
+    /// The highest triangle over the point, so overhangs put instances on top
+    fn sample(&self, x: f32, z: f32) -> Option<(f32, Vec3)> {
+        let point = Vec2::new(x, z);
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let (column, row) = self.cell_of(point);
+        self.cells[row * self.columns + column].iter()
+            .filter_map(|&index| {
+                let triangle = &self.triangles[index as usize];
+                let height = height_at(triangle, point)?;
+                let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).try_normalize()?;
+                Some((height, if normal.y < 0.0 { -normal } else { normal }))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+/// Height of `triangle` above `point` on the XZ plane, if the point is inside it seen from above
+fn height_at(triangle: &[Vec3; 3], point: Vec2) -> Option<f32> {
+    const EPSILON: f32 = 1e-5;
+    let [a, b, c] = triangle.map(|v| v.xz());
+    let denominator = (b - a).perp_dot(c - a);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    // Barycentric weights of b and c
+    let u = (point - a).perp_dot(c - a) / denominator;
+    let v = (b - a).perp_dot(point - a) / denominator;
+    if u < -EPSILON || v < -EPSILON || u + v > 1.0 + EPSILON {
+        return None;
+    }
+    Some(triangle[0].y + u * (triangle[1].y - triangle[0].y) + v * (triangle[2].y - triangle[0].y))
+}
+
+/// Grayscale grid of densities in 0..1 stretched over the scattered area
+#[derive(Debug, Clone, PartialEq)]
+pub struct DensityMap {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+}
+
+impl DensityMap {
+    /// `values` row by row: columns run along +X and rows along +Z from the area's `min` corner
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> Result<Self> {
+        if width == 0 || height == 0 || values.len() != width as usize * height as usize {
+            bail!("A {}x{} density map needs {} values, got {}", width, height, width as usize * height as usize, values.len());
+        }
+        Ok(Self { width, height, values })
+    }
+
+    /// The red channel of a painted mask, white where instances grow thickest
+    pub fn from_image(image: &ImageData) -> Result<Self> {
+        let values = image.pixels.chunks_exact(4).map(|pixel| pixel[0] as f32 / 255.0).collect();
+        Self::new(image.width, image.height, values)
+    }
+
+    /// Bilinear sample at `uv` in 0..1, clamped at the edges
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let texel = (uv * size - 0.5).clamp(Vec2::ZERO, size - 1.0);
+        let (x0, y0) = (texel.x.floor() as u32, texel.y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let t = texel - texel.floor();
+        let value = |x: u32, y: u32| self.values[(y * self.width + x) as usize];
+        let top = value(x0, y0) + (value(x1, y0) - value(x0, y0)) * t.x;
+        let bottom = value(x0, y1) + (value(x1, y1) - value(x0, y1)) * t.x;
+        (top + (bottom - top) * t.y).clamp(0.0, 1.0)
+    }
+}
+
+/// Chance a candidate instance is kept, across the scattered area
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Density {
+    /// Every candidate is kept
+    #[default]
+    Uniform,
+    /// Patches: 0 where `noise` is at or below `low`, rising to 1 where it reaches `high`
+    Noise { noise: Fbm, low: f32, high: f32 },
+    /// Painted by hand or baked from another tool
+    Map(DensityMap),
+}
+
+impl Density {
+    /// Density at world `point` on the XZ plane, `uv` across the scattered area
+    fn sample(&self, point: Vec2, uv: Vec2) -> f32 {
+        match self {
+            Density::Uniform => 1.0,
+            Density::Noise { noise, low, high } => {
+                let value = noise.sample2(point);
+                if high > low {
+                    ((value - low) / (high - low)).clamp(0.0, 1.0)
+                } else if value > *low {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Density::Map(map) => map.sample(uv),
+        }
+    }
+}
+
+/// How `Scatter::populate` spreads instances of a model, such as grass, rocks or trees, over
+/// the ground. Candidates sit on a jittered grid, and each is kept with the chance its
+/// `density` gives and where the ground under it is within the slope and height limits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scatter {
+    /// The same seed, settings and ground always give the same instances
+    pub seed: u32,
+    /// Corners of the area covered, on the XZ plane
+    pub min: Vec2,
+    pub max: Vec2,
+    /// Candidates per square meter, so instances per square meter where the density is 1
+    pub per_square_meter: f32,
+    pub density: Density,
+    /// Uniform scale picked between these
+    pub scale: (f32, f32),
+    /// Widest random turn about the up axis, in radians; a full turn faces instances every way
+    pub yaw_jitter: f32,
+    /// Largest random lean away from upright, in radians
+    pub tilt_jitter: f32,
+    /// Lean instances with the ground, e.g. for rocks, rather than standing them upright like trees
+    pub align_to_normal: bool,
+    /// Steepest ground instances are placed on, in radians from flat
+    pub max_slope: f32,
+    /// Lowest and highest ground instances are placed on, e.g. to keep them above the waterline
+    pub height: (f32, f32),
+}
+
+impl Default for Scatter {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min: Vec2::splat(-10.0),
+            max: Vec2::splat(10.0),
+            per_square_meter: 1.0,
+            density: Density::Uniform,
+            scale: (0.8, 1.2),
+            yaw_jitter: TAU,
+            tilt_jitter: 0.0,
+            align_to_normal: false,
+            max_slope: 35f32.to_radians(),
+            height: (f32::NEG_INFINITY, f32::INFINITY),
+        }
+    }
+}
+
+impl Scatter {
+    /// Transforms of the instances on `surface`, origins on the ground
+    pub fn transforms(&self, surface: &impl ScatterSurface) -> Vec<Transform> {
+        let size = self.max - self.min;
+        if !(self.per_square_meter > 0.0 && size.x > 0.0 && size.y > 0.0) {
+            return Vec::new();
+        }
+        let spacing = self.per_square_meter.recip().sqrt();
+        let columns = (size.x / spacing).ceil().max(1.0) as u32;
+        let rows = (size.y / spacing).ceil().max(1.0) as u32;
+        let cell = size / Vec2::new(columns as f32, rows as f32);
+        let max_slope_cos = self.max_slope.cos();
+
+        let mut rng = Rng::new(self.seed);
+        let mut transforms = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                // Draw every number up front, so rejecting a candidate doesn't change the others
+                let jitter = Vec2::new(rng.next_f32(), rng.next_f32());
+                let keep = rng.next_f32();
+                let yaw = rng.range(-0.5, 0.5) * self.yaw_jitter;
+                let tilt = rng.next_f32() * self.tilt_jitter;
+                let tilt_heading = rng.range(0.0, TAU);
+                let scale = rng.range(self.scale.0, self.scale.1);
+
+                let point = self.min + (Vec2::new(column as f32, row as f32) + jitter) * cell;
+                if keep >= self.density.sample(point, (point - self.min) / size) {
+                    continue;
+                }
+                let Some((height, normal)) = surface.sample(point.x, point.y) else {
+                    continue;
+                };
+                if normal.y < max_slope_cos - 1e-6 || height < self.height.0 || height > self.height.1 {
+                    continue;
+                }
+
+                let up = if self.align_to_normal { Quat::from_rotation_arc(Vec3::Y, normal) } else { Quat::IDENTITY };
+                let lean = Quat::from_axis_angle(Vec3::new(tilt_heading.cos(), 0.0, tilt_heading.sin()), tilt);
+                let (x, y, z) = (up * lean * Quat::from_rotation_y(yaw)).to_euler(EulerRot::XYZ);
+                transforms.push(Transform {
+                    position: Vec3::new(point.x, height, point.y),
+                    rotation: Vec3::new(x, y, z),
+                    scale: Vec3::splat(scale),
+                });
+            }
+        }
+        transforms
+    }
+
+    /// Add the instances to `scene`. They all share `model`, so its buffers are uploaded once
+    /// however many there are.
+    pub fn populate(&self, scene: &mut Scene, model: ModelHandle, surface: &impl ScatterSurface) -> Vec<ObjectId> {
+        self.transforms(surface).into_iter()
+            .map(|transform| scene.add_object(model, transform))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ModelVertex;
+
+    fn flat(_x: f32, _z: f32) -> f32 {
+        0.0
+    }
+
+    #[test]
+    fn test_scatter_uniform() {
+        let scatter = Scatter { seed: 7, per_square_meter: 4.0, ..Scatter::default() };
+        let transforms = scatter.transforms(&flat);
+        // Every candidate of the 40 x 40 grid is kept
+        assert_eq!(transforms.len(), 1600);
+        for transform in &transforms {
+            assert!(transform.position.xz().cmpge(scatter.min).all() && transform.position.xz().cmple(scatter.max).all());
+            assert_eq!(transform.position.y, 0.0);
+            assert!((0.8..=1.2).contains(&transform.scale.x));
+        }
+        assert_eq!(scatter.transforms(&flat), transforms, "Deterministic");
+        assert_ne!(Scatter { seed: 8, ..scatter.clone() }.transforms(&flat), transforms);
+
+        let upright = Scatter { yaw_jitter: 0.0, scale: (1.0, 1.0), ..scatter.clone() }.transforms(&flat);
+        assert!(upright.iter().all(|transform| transform.rotation.abs().max_element() < 1e-6 && transform.scale == Vec3::ONE));
+        assert!(Scatter { per_square_meter: 0.0, ..scatter }.transforms(&flat).is_empty());
+    }
+
+    #[test]
+    fn test_scatter_constraints() {
+        // A 45° ramp
+        let ramp = |x: f32, _z: f32| x;
+        let scatter = Scatter::default();
+        assert!(scatter.transforms(&ramp).is_empty(), "Steeper than the default limit");
+        let steep = Scatter { max_slope: 50f32.to_radians(), ..scatter.clone() };
+        assert_eq!(steep.transforms(&ramp).len(), 400);
+
+        let high = Scatter { height: (2.0, f32::INFINITY), ..steep.clone() }.transforms(&ramp);
+        assert!(!high.is_empty() && high.iter().all(|transform| transform.position.y >= 2.0));
+
+        // Standing upright, or leaning with the ground
+        let up = |transforms: &[Transform]| transforms[0].to_matrix().transform_vector3(Vec3::Y).normalize();
+        assert!(up(&steep.transforms(&ramp)).abs_diff_eq(Vec3::Y, 1e-5));
+        let aligned = Scatter { align_to_normal: true, ..steep.clone() }.transforms(&ramp);
+        assert!(up(&aligned).abs_diff_eq(Vec3::new(-1.0, 1.0, 0.0).normalize(), 1e-3));
+
+        let tilted = Scatter { tilt_jitter: 0.2, ..steep }.transforms(&ramp);
+        assert!(tilted.iter().all(|transform| {
+            let up = transform.to_matrix().transform_vector3(Vec3::Y).normalize();
+            up.angle_between(Vec3::Y) <= 0.2 + 1e-4
+        }));
+    }
+
+    #[test]
+    fn test_scatter_density() {
+        // Bare on the left half, full on the right
+        let map = DensityMap::new(2, 1, vec![0.0, 1.0]).unwrap();
+        assert_eq!(map.sample(Vec2::new(0.1, 0.5)), 0.0);
+        assert_eq!(map.sample(Vec2::new(0.5, 0.5)), 0.5);
+        assert_eq!(map.sample(Vec2::new(2.0, -1.0)), 1.0);
+        let painted = Scatter { density: Density::Map(map), ..Scatter::default() }.transforms(&flat);
+        assert!(painted.iter().all(|transform| transform.position.x > -5.0));
+        assert!(painted.iter().filter(|transform| transform.position.x > 5.0).count() >= 90);
+
+        assert!(DensityMap::new(2, 2, vec![0.0; 3]).is_err());
+        let image = ImageData { width: 1, height: 1, pixels: vec![255, 0, 0, 255] };
+        assert_eq!(DensityMap::from_image(&image).unwrap().sample(Vec2::ZERO), 1.0);
+
+        // Noise thresholds: never reached keeps nothing, always exceeded keeps everything
+        let noise = |low, high| Scatter { density: Density::Noise { noise: Fbm::default(), low, high }, ..Scatter::default() };
+        assert!(noise(2.0, 3.0).transforms(&flat).is_empty());
+        assert_eq!(noise(-3.0, -2.0).transforms(&flat).len(), 400);
+        let patches = noise(0.0, 0.2).transforms(&flat);
+        assert!(!patches.is_empty() && patches.len() < 400);
+        assert!(patches.iter().all(|transform| Fbm::default().sample2(transform.position.xz()) > 0.0));
+    }
+
+    #[test]
+    fn test_mesh_surface() {
+        let vertex = |x: f32, y: f32, z: f32| ModelVertex {
+            position: [x, y, z],
+            tex_coords: [0.0; 2],
+            normal: [0.0, 1.0, 0.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color: ModelVertex::WHITE,
+        };
+        // A 2 x 2 quad rising by 1 along +X, wound either way
+        let mesh = MeshData {
+            name: "terrain".to_string(),
+            vertices: vec![vertex(-1.0, 0.0, -1.0), vertex(1.0, 1.0, -1.0), vertex(1.0, 1.0, 1.0), vertex(-1.0, 0.0, 1.0)],
+            indices: vec![0, 1, 2, 0, 3, 2],
+            material_index: 0,
+            skin: None,
+        };
+        let transform = Transform { position: Vec3::new(0.0, 5.0, 0.0), ..Transform::new() };
+        let surface = MeshSurface::new([&mesh], &transform);
+
+        let (height, normal) = surface.sample(0.0, 0.5).unwrap();
+        assert!((height - 5.5).abs() < 1e-5);
+        assert!(normal.abs_diff_eq(Vec3::new(-1.0, 2.0, 0.0).normalize(), 1e-5));
+        assert_eq!(surface.sample(0.5, -0.5).map(|(height, _)| (height - 5.75).abs() < 1e-5), Some(true));
+        assert!(surface.sample(1.5, 0.0).is_none());
+
+        let scatter = Scatter { min: Vec2::splat(-2.0), max: Vec2::splat(2.0), per_square_meter: 16.0, ..Scatter::default() };
+        let transforms = scatter.transforms(&surface);
+        assert!(!transforms.is_empty() && transforms.len() < 256, "Only over the quad");
+        assert!(transforms.iter().all(|transform| {
+            (transform.position.y - (5.5 + transform.position.x * 0.5)).abs() < 1e-4
+        }));
+    }
+}
@@ -0,0 +1,386 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
+use anyhow::Context;
+use glam::Vec3;
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST, FLOAT, INT};
+use crate::input::{InputState, Key};
+use crate::simulation::Rng;
+use super::{ObjectId, Scene, Transform};
+
+/// Operations a single callback may run, so a runaway loop can't stall the frame
+pub const MAX_SCRIPT_OPERATIONS: u64 = 100_000;
+
+/// Animation a script asked an object to play; collected with [`ScriptHost::take_animation_requests`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationRequest {
+    pub object: ObjectId,
+    pub name: String,
+}
+
+/// The object a script is attached to, bound as `this` in every callback.
+/// Indexing (`this["timer"]`) reads and writes state that persists between calls.
+#[derive(Clone)]
+struct ScriptObject {
+    id: ObjectId,
+    state: Map,
+}
+
+/// Scene state visible to scripts during one batch of callbacks. Transform writes and spawns
+/// are applied to the scene once the batch finishes; raycasts see bounds from its start.
+#[derive(Default)]
+struct ScriptWorld {
+    transforms: HashMap<ObjectId, Transform>,
+    bounds: Vec<(ObjectId, Vec3, Vec3)>,
+    modified: HashSet<ObjectId>,
+    spawns: Vec<(ObjectId, Transform)>,
+    animations: Vec<AnimationRequest>,
+    input: InputState,
+    rng: Rng,
+}
+
+impl ScriptWorld {
+    fn transform(&self, id: ObjectId) -> Transform {
+        self.transforms.get(&id).copied().unwrap_or_else(Transform::new)
+    }
+
+    fn update_transform(&mut self, id: ObjectId, f: impl FnOnce(&mut Transform)) {
+        if let Some(transform) = self.transforms.get_mut(&id) {
+            f(transform);
+            self.modified.insert(id);
+        }
+    }
+}
+
+struct Script {
+    ast: AST,
+    this: Dynamic,
+    initialized: bool,
+    on_init: bool,
+    on_update: bool,
+    on_event: bool,
+}
+
+/// Runs Rhai scripts attached to scene objects.
+///
+/// A script may define `on_init()`, `on_update(dt)` and `on_event(name)`; top-level statements
+/// are not run. Inside them `this` is the object, with `position`, `rotation` (Euler radians) and
+/// `scale` properties and the methods `spawn_instance(position)` (another instance of the same
+/// model; `spawn` is a reserved word in Rhai) and `play_animation(name)`. `raycast(origin, direction)`
+/// returns `#{ id, distance }` for the nearest object bounds hit, or `()`. `key_down(name)` and
+/// `key_pressed(name)` read the frame's input by key name (e.g. `"KeyW"`) and `vr_action(name)`
+/// its VR action values. `random()` (0 - 1) and `random_range(min, max)` draw from a generator
+/// seeded by the simulation, so replays repeat them. Vectors are built with `vec3(x, y, z)`.
+///
+/// Scripts can't reach the filesystem or the GPU; runtime errors are logged and the script keeps running.
+pub struct ScriptHost {
+    engine: Engine,
+    world: Rc<RefCell<ScriptWorld>>,
+    scripts: BTreeMap<ObjectId, Script>,
+    animations: Vec<AnimationRequest>,
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptHost {
+    pub fn new() -> Self {
+        let world = Rc::new(RefCell::new(ScriptWorld::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+        engine.on_print(|text| log::info!(target: "script", "{}", text));
+        engine.on_debug(|text, _, position| log::debug!(target: "script", "{:?}: {}", position, text));
+        register_vec3(&mut engine);
+        register_object(&mut engine, &world);
+
+        Self {
+            engine,
+            world,
+            scripts: BTreeMap::new(),
+            animations: Vec::new(),
+        }
+    }
+
+    /// Compile `source` and attach it to an object, replacing any script it already has.
+    /// `on_init` runs on the next update.
+    pub fn attach(&mut self, object: ObjectId, source: &str) -> anyhow::Result<()> {
+        let ast = self.engine.compile(source)
+            .map_err(|e| anyhow::anyhow!("Failed to compile script for {:?}: {}", object, e))?;
+        let defines = |name: &str, params: usize| {
+            ast.iter_functions().any(|f| f.name == name && f.params.len() == params)
+        };
+        let script = Script {
+            on_init: defines("on_init", 0),
+            on_update: defines("on_update", 1),
+            on_event: defines("on_event", 1),
+            this: Dynamic::from(ScriptObject { id: object, state: Map::new() }),
+            initialized: false,
+            ast,
+        };
+        self.scripts.insert(object, script);
+        Ok(())
+    }
+
+    pub fn attach_file(&mut self, object: ObjectId, path: &Path) -> anyhow::Result<()> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read script {}", path.display()))?;
+        self.attach(object, &source)
+    }
+
+    /// Returns false if the object had no script
+    pub fn detach(&mut self, object: ObjectId) -> bool {
+        self.scripts.remove(&object).is_some()
+    }
+
+    pub fn is_attached(&self, object: ObjectId) -> bool {
+        self.scripts.contains_key(&object)
+    }
+
+    /// Run `on_init` for newly attached scripts, then `on_update` for all of them.
+    /// Scripts of objects that have left the scene are dropped.
+    pub fn update(&mut self, scene: &mut Scene, dt: f32) {
+        self.scripts.retain(|id, _| scene.object(*id).is_some());
+        if self.scripts.is_empty() {
+            return;
+        }
+
+        self.begin(scene);
+        for (id, script) in &mut self.scripts {
+            if !script.initialized {
+                script.initialized = true;
+                if script.on_init {
+                    call(&self.engine, *id, script, "on_init", ());
+                }
+            }
+            if script.on_update {
+                call(&self.engine, *id, script, "on_update", (dt as FLOAT,));
+            }
+        }
+        self.finish(scene);
+    }
+
+    /// Deliver a named event (e.g. a key name such as `"KeyE"`) to every initialized script
+    pub fn dispatch_event(&mut self, scene: &mut Scene, event: &str) {
+        if !self.scripts.values().any(|script| script.initialized && script.on_event) {
+            return;
+        }
+
+        self.begin(scene);
+        for (id, script) in &mut self.scripts {
+            if script.initialized && script.on_event && scene.object(*id).is_some() {
+                call(&self.engine, *id, script, "on_event", (event.to_string(),));
+            }
+        }
+        self.finish(scene);
+    }
+
+    /// The input snapshot scripts read until the next call; set it before `update` each frame
+    pub fn set_input(&mut self, input: &InputState) {
+        self.world.borrow_mut().input = input.clone();
+    }
+
+    /// Restart the generator behind `random()`
+    pub fn seed_random(&mut self, seed: u32) {
+        self.world.borrow_mut().rng = Rng::new(seed);
+    }
+
+    /// Animations requested by scripts since the last call
+    pub fn take_animation_requests(&mut self) -> Vec<AnimationRequest> {
+        std::mem::take(&mut self.animations)
+    }
+
+    /// Snapshot the scene for a batch of callbacks
+    fn begin(&mut self, scene: &Scene) {
+        let mut world = self.world.borrow_mut();
+        world.transforms = scene.objects.iter().map(|object| (object.id, object.transform)).collect();
+        world.bounds = scene.objects.iter()
+            .filter_map(|object| object.world_bounds(&scene.assets).map(|(min, max)| (object.id, min, max)))
+            .collect();
+    }
+
+    /// Write back what the callbacks changed
+    fn finish(&mut self, scene: &mut Scene) {
+        let world = &mut *self.world.borrow_mut();
+        for id in world.modified.drain() {
+            if let (Some(current), Some(transform)) = (scene.transform_mut(id), world.transforms.get(&id)) {
+                *current = *transform;
+            }
+        }
+        for (source, transform) in world.spawns.drain(..) {
+            let Some(object) = scene.object(source) else {
+                continue;
+            };
+            let (model, material) = (object.model(), object.material());
+            let id = scene.add_object(model, transform);
+            if material.is_some() {
+                scene.set_object_material(id, material);
+            }
+        }
+        self.animations.append(&mut world.animations);
+    }
+}
+
+fn call(engine: &Engine, id: ObjectId, script: &mut Script, name: &str, args: impl FuncArgs) {
+    let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.this);
+    if let Err(e) = engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &script.ast, name, args) {
+        log::warn!(target: "script", "{:?} {}: {}", id, name, e);
+    }
+}
+
+fn register_vec3(engine: &mut Engine) {
+    engine.register_type_with_name::<Vec3>("Vec3")
+        .register_fn("vec3", |x: FLOAT, y: FLOAT, z: FLOAT| Vec3::new(x as f32, y as f32, z as f32))
+        .register_get_set("x", |v: &mut Vec3| v.x as FLOAT, |v: &mut Vec3, x: FLOAT| v.x = x as f32)
+        .register_get_set("y", |v: &mut Vec3| v.y as FLOAT, |v: &mut Vec3, y: FLOAT| v.y = y as f32)
+        .register_get_set("z", |v: &mut Vec3| v.z as FLOAT, |v: &mut Vec3, z: FLOAT| v.z = z as f32)
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("-", |v: Vec3| -v)
+        .register_fn("*", |v: Vec3, s: FLOAT| v * s as f32)
+        .register_fn("*", |s: FLOAT, v: Vec3| v * s as f32)
+        .register_fn("length", |v: &mut Vec3| v.length() as FLOAT)
+        .register_fn("normalize", |v: &mut Vec3| v.normalize_or_zero())
+        .register_fn("to_string", |v: &mut Vec3| format!("{:?}", v))
+        .register_fn("to_debug", |v: &mut Vec3| format!("{:?}", v));
+}
+
+fn register_object(engine: &mut Engine, world: &Rc<RefCell<ScriptWorld>>) {
+    engine.register_type_with_name::<ScriptObject>("Object")
+        .register_get("id", |object: &mut ScriptObject| object.id.0 as INT)
+        .register_indexer_get(|object: &mut ScriptObject, key: &str| {
+            object.state.get(key).cloned().unwrap_or(Dynamic::UNIT)
+        })
+        .register_indexer_set(|object: &mut ScriptObject, key: &str, value: Dynamic| {
+            object.state.insert(key.into(), value);
+        });
+
+    let (get, set) = (world.clone(), world.clone());
+    engine.register_get_set(
+        "position",
+        move |object: &mut ScriptObject| get.borrow().transform(object.id).position,
+        move |object: &mut ScriptObject, position: Vec3| {
+            set.borrow_mut().update_transform(object.id, |transform| transform.position = position)
+        },
+    );
+    let (get, set) = (world.clone(), world.clone());
+    engine.register_get_set(
+        "rotation",
+        move |object: &mut ScriptObject| get.borrow().transform(object.id).rotation,
+        move |object: &mut ScriptObject, rotation: Vec3| {
+            set.borrow_mut().update_transform(object.id, |transform| transform.rotation = rotation)
+        },
+    );
+    let (get, set) = (world.clone(), world.clone());
+    engine.register_get_set(
+        "scale",
+        move |object: &mut ScriptObject| get.borrow().transform(object.id).scale,
+        move |object: &mut ScriptObject, scale: Vec3| {
+            set.borrow_mut().update_transform(object.id, |transform| transform.scale = scale)
+        },
+    );
+
+    let spawns = world.clone();
+    engine.register_fn("spawn_instance", move |object: &mut ScriptObject, position: Vec3| {
+        let mut world = spawns.borrow_mut();
+        let mut transform = world.transform(object.id);
+        transform.position = position;
+        world.spawns.push((object.id, transform));
+    });
+
+    let animations = world.clone();
+    engine.register_fn("play_animation", move |object: &mut ScriptObject, name: &str| {
+        animations.borrow_mut().animations.push(AnimationRequest { object: object.id, name: name.to_string() });
+    });
+
+    let input = world.clone();
+    engine.register_fn("key_down", move |name: &str| {
+        Key::from_name(name).is_some_and(|key| input.borrow().input.is_key_down(key))
+    });
+    let input = world.clone();
+    engine.register_fn("key_pressed", move |name: &str| {
+        Key::from_name(name).is_some_and(|key| input.borrow().input.key_pressed(key))
+    });
+    let input = world.clone();
+    engine.register_fn("vr_action", move |name: &str| input.borrow().input.vr_action(name) as FLOAT);
+
+    let rng = world.clone();
+    engine.register_fn("random", move || rng.borrow_mut().rng.next_f32() as FLOAT);
+    let rng = world.clone();
+    engine.register_fn("random_range", move |min: FLOAT, max: FLOAT| {
+        rng.borrow_mut().rng.range(min as f32, max as f32) as FLOAT
+    });
+
+    let bounds = world.clone();
+    engine.register_fn("raycast", move |origin: Vec3, direction: Vec3| -> Dynamic {
+        let world = bounds.borrow();
+        let hit = world.bounds.iter()
+            .filter_map(|(id, min, max)| ray_aabb(origin, direction, *min, *max).map(|distance| (*id, distance)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match hit {
+            Some((id, distance)) => {
+                let mut result = Map::new();
+                result.insert("id".into(), Dynamic::from(id.0 as INT));
+                result.insert("distance".into(), Dynamic::from(distance as FLOAT));
+                result.into()
+            }
+            None => Dynamic::UNIT,
+        }
+    });
+}
+
+/// Distance along `direction` (in its own units) to where the ray enters the box, or 0 if it starts inside
+fn ray_aabb(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let inverse = direction.recip();
+    let t0 = (min - origin) * inverse;
+    let t1 = (max - origin) * inverse;
+    let near = t0.min(t1).max_element();
+    let far = t0.max(t1).min_element();
+    (near <= far && far >= 0.0).then_some(near.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::camera::Camera;
+
+    #[test]
+    fn test_ray_aabb() {
+        let (min, max) = (Vec3::splat(-1.0), Vec3::splat(1.0));
+        assert_eq!(ray_aabb(Vec3::new(0.0, 0.0, -5.0), Vec3::Z, min, max), Some(4.0));
+        assert_eq!(ray_aabb(Vec3::ZERO, Vec3::X, min, max), Some(0.0));
+        // Behind the origin, and parallel to a face outside the slab
+        assert_eq!(ray_aabb(Vec3::new(0.0, 0.0, 5.0), Vec3::Z, min, max), None);
+        assert_eq!(ray_aabb(Vec3::new(0.0, 2.0, -5.0), Vec3::Z, min, max), None);
+    }
+
+    #[test]
+    fn test_attach_and_detach() {
+        let mut host = ScriptHost::new();
+        assert!(host.attach(ObjectId(0), "fn on_update(dt) { this.position = vec3(0.0, dt, 0.0); }").is_ok());
+        assert!(host.attach(ObjectId(1), "fn on_update(dt) {").is_err());
+        assert!(host.is_attached(ObjectId(0)));
+        assert!(!host.is_attached(ObjectId(1)));
+
+        // Scripts of objects that aren't in the scene are dropped on update
+        let mut scene = Scene::new(Camera::new(Vec3::ZERO, 1.0));
+        host.update(&mut scene, 0.1);
+        assert!(!host.is_attached(ObjectId(0)));
+        assert!(!host.detach(ObjectId(0)));
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let draw = |seed| {
+            let mut host = ScriptHost::new();
+            host.seed_random(seed);
+            (0..4).map(|_| host.engine.eval::<FLOAT>("random_range(2.0, 3.0)").unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(5), draw(5));
+        assert_ne!(draw(5), draw(6));
+        assert!(draw(5).iter().all(|value| (2.0..3.0).contains(value)));
+    }
+}
@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use glam::{EulerRot, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::color::Color;
+use crate::session::CameraPose;
+use super::{Easing, LightId, ObjectId, Transform};
+
+/// A scripted sequence of camera moves, object motion, light changes, animation clip triggers
+/// and named events, stored as TOML:
+///
+/// ```toml
+/// name = "intro"
+///
+/// [[camera]]
+/// time = 0.0
+/// position = [0.0, 1.6, 8.0]
+/// yaw = -90.0
+/// easing = "sine_in_out"
+///
+/// [[camera]]
+/// time = 4.0
+/// position = [2.0, 1.6, 3.0]
+/// yaw = -120.0
+///
+/// [[objects]]
+/// target = "door"
+/// keys = [
+///     { time = 1.0 },
+///     { time = 2.5, rotation = [0.0, 1.57, 0.0] },
+/// ]
+///
+/// [[lights]]
+/// target = "lamp"
+/// keys = [{ time = 0.0, intensity = 0.0 }, { time = 1.0, intensity = 4.0 }]
+///
+/// [[clips]]
+/// time = 3.0
+/// target = "robot"
+/// clip = "wave"
+///
+/// [[events]]
+/// time = 4.0
+/// name = "door_opened"
+/// ```
+///
+/// Object and light tracks name their targets, which `Sequencer::bind_object` and
+/// `bind_light` map to scene ids when the sequence is played. Each key's easing shapes the
+/// motion from it to the next key; before the first key and after the last, tracks hold.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sequence {
+    pub name: String,
+    /// Seconds the sequence lasts; the last key when unset
+    pub duration: Option<f32>,
+    pub camera: Vec<CameraKey>,
+    pub objects: Vec<ObjectTrack>,
+    pub lights: Vec<LightTrack>,
+    pub clips: Vec<ClipTrigger>,
+    pub events: Vec<SequenceEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKey {
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Degrees, as on `Camera`
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectTrack {
+    pub target: String,
+    pub keys: Vec<TransformKey>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransformKey {
+    pub time: f32,
+    pub position: [f32; 3],
+    /// Euler angles in radians, as in [`Transform`]
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    pub easing: Easing,
+}
+
+impl Default for TransformKey {
+    fn default() -> Self {
+        Self { time: 0.0, position: [0.0; 3], rotation: [0.0; 3], scale: [1.0; 3], easing: Easing::Linear }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LightTrack {
+    pub target: String,
+    pub keys: Vec<LightKey>,
+}
+
+/// Every key sets the light's whole state; position is left to the light
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightKey {
+    pub time: f32,
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+    pub easing: Easing,
+}
+
+impl Default for LightKey {
+    fn default() -> Self {
+        Self { time: 0.0, color: Color::WHITE, intensity: 1.0, range: 10.0, easing: Easing::Linear }
+    }
+}
+
+/// Start a skeletal animation clip on the target object, as `Scene::play_animation` does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipTrigger {
+    pub time: f32,
+    pub target: String,
+    pub clip: String,
+    #[serde(default)]
+    pub looping: bool,
+}
+
+/// A named moment, handed to the application by `Sequencer::take_events` when playback passes it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SequenceEvent {
+    pub time: f32,
+    pub name: String,
+}
+
+/// Shared by the key types, for sampling any track
+trait Key {
+    fn time(&self) -> f32;
+    fn easing(&self) -> Easing;
+}
+
+macro_rules! impl_key {
+    ($($key:ty),*) => {
+        $(impl Key for $key {
+            fn time(&self) -> f32 {
+                self.time
+            }
+
+            fn easing(&self) -> Easing {
+                self.easing
+            }
+        })*
+    };
+}
+
+impl_key!(CameraKey, TransformKey, LightKey);
+
+/// The keys around `time` and the eased progress between them; both are the same key before
+/// the first and after the last
+fn sample<K: Key>(keys: &[K], time: f32) -> Option<(&K, &K, f32)> {
+    let next = keys.partition_point(|key| key.time() <= time);
+    let (first, last) = (keys.first()?, keys.last()?);
+    if next == 0 {
+        return Some((first, first, 0.0));
+    }
+    if next == keys.len() {
+        return Some((last, last, 0.0));
+    }
+    let (from, to) = (&keys[next - 1], &keys[next]);
+    let length = to.time() - from.time();
+    let t = if length > 0.0 { (time - from.time()) / length } else { 1.0 };
+    Some((from, to, from.easing().apply(t)))
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+impl Sequence {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let sequence: Self = toml::from_str(source)?;
+        sequence.validate()?;
+        Ok(sequence)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read sequence {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid sequence {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.validate()?;
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Check every track's keys are in time order and no time is negative
+    pub fn validate(&self) -> Result<()> {
+        fn check<K: Key>(track: &str, keys: &[K]) -> Result<()> {
+            if keys.iter().any(|key| key.time().is_nan() || key.time() < 0.0) {
+                bail!("Negative or NaN key time in the {} track", track);
+            }
+            if keys.windows(2).any(|pair| pair[1].time() < pair[0].time()) {
+                bail!("Keys of the {} track are out of order", track);
+            }
+            Ok(())
+        }
+        check("camera", &self.camera)?;
+        for track in &self.objects {
+            check(&format!("'{}' object", track.target), &track.keys)?;
+        }
+        for track in &self.lights {
+            check(&format!("'{}' light", track.target), &track.keys)?;
+        }
+        if self.clips.iter().map(|clip| clip.time).chain(self.events.iter().map(|event| event.time)).any(|time| time.is_nan() || time < 0.0) {
+            bail!("Negative or NaN trigger time");
+        }
+        Ok(())
+    }
+
+    /// Seconds until the end: `duration` if set, else the last key or trigger
+    pub fn length(&self) -> f32 {
+        self.duration.unwrap_or_else(|| {
+            let keys = self.camera.iter().map(|key| key.time)
+                .chain(self.objects.iter().flat_map(|track| track.keys.iter().map(|key| key.time)))
+                .chain(self.lights.iter().flat_map(|track| track.keys.iter().map(|key| key.time)))
+                .chain(self.clips.iter().map(|clip| clip.time))
+                .chain(self.events.iter().map(|event| event.time));
+            keys.fold(0.0, f32::max)
+        })
+    }
+
+    /// The camera pose at `time`, if there's a camera track
+    pub fn camera_at(&self, time: f32) -> Option<CameraPose> {
+        let (from, to, t) = sample(&self.camera, time)?;
+        Some(CameraPose {
+            position: Vec3::from(from.position).lerp(Vec3::from(to.position), t).to_array(),
+            yaw: lerp(from.yaw, to.yaw, t),
+            pitch: lerp(from.pitch, to.pitch, t),
+        })
+    }
+
+    /// A track's transform at `time`, rotating the short way between keys
+    pub fn transform_at(track: &ObjectTrack, time: f32) -> Option<Transform> {
+        let (from, to, t) = sample(&track.keys, time)?;
+        let quat = |rotation: [f32; 3]| Quat::from_euler(EulerRot::XYZ, rotation[0], rotation[1], rotation[2]);
+        let (x, y, z) = quat(from.rotation).slerp(quat(to.rotation), t).to_euler(EulerRot::XYZ);
+        Some(Transform {
+            position: Vec3::from(from.position).lerp(Vec3::from(to.position), t),
+            rotation: if t == 0.0 { Vec3::from(from.rotation) } else { Vec3::new(x, y, z) },
+            scale: Vec3::from(from.scale).lerp(Vec3::from(to.scale), t),
+        })
+    }
+
+    /// A track's light parameters at `time`, as a key
+    pub fn light_at(track: &LightTrack, time: f32) -> Option<LightKey> {
+        let (from, to, t) = sample(&track.keys, time)?;
+        Some(LightKey {
+            time,
+            color: from.color.lerp(to.color, t),
+            intensity: lerp(from.intensity, to.intensity, t),
+            range: lerp(from.range, to.range, t),
+            easing: from.easing,
+        })
+    }
+}
+
+/// What the scene applies after the sequencer moved: every track sampled at `time`, and the
+/// clips triggered on the way there
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SequenceFrame {
+    pub camera: Option<CameraPose>,
+    pub objects: Vec<(ObjectId, Transform)>,
+    pub lights: Vec<(LightId, LightKey)>,
+    /// Object, clip name and whether it loops
+    pub clips: Vec<(ObjectId, String, bool)>,
+}
+
+/// Plays a `Sequence` on the scene: `Scene::update` advances it and applies the camera, object
+/// and light tracks and clip triggers, and queues its events for `take_events`. `seek` scrubs
+/// to any time, applied on the next update even while paused; triggers only fire during
+/// playback. Positions follow floating origin rebases.
+#[derive(Debug)]
+pub struct Sequencer {
+    sequence: Option<Sequence>,
+    objects: HashMap<String, ObjectId>,
+    lights: HashMap<String, LightId>,
+    time: f32,
+    playing: bool,
+    /// Playback rate; zero and below hold the current time
+    pub speed: f32,
+    /// Start over at the end rather than stopping there
+    pub looping: bool,
+    /// Turn the camera as the track says. Headsets own the view direction, so VR frontends turn
+    /// this off and only the position is driven.
+    pub drive_camera_rotation: bool,
+    /// Triggers at the current time haven't fired yet, as after `play` or `seek`
+    fresh: bool,
+    /// A seek waiting to be applied
+    dirty: bool,
+    /// Added to every position in the sequence, after origin rebases
+    offset: Vec3,
+    events: Vec<String>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self {
+            sequence: None,
+            objects: HashMap::new(),
+            lights: HashMap::new(),
+            time: 0.0,
+            playing: false,
+            speed: 1.0,
+            looping: false,
+            drive_camera_rotation: true,
+            fresh: true,
+            dirty: false,
+            offset: Vec3::ZERO,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl Sequencer {
+    /// Replace the sequence, stopped at its start; bindings are kept
+    pub fn load(&mut self, sequence: Sequence) {
+        self.sequence = Some(sequence);
+        self.stop();
+    }
+
+    pub fn sequence(&self) -> Option<&Sequence> {
+        self.sequence.as_ref()
+    }
+
+    /// Unload the sequence, leaving everything where it was
+    pub fn clear(&mut self) -> Option<Sequence> {
+        self.playing = false;
+        self.sequence.take()
+    }
+
+    /// Drive `id` with the object tracks and clip triggers targeting `name`
+    pub fn bind_object(&mut self, name: &str, id: ObjectId) {
+        self.objects.insert(name.to_string(), id);
+    }
+
+    /// Drive `id` with the light tracks targeting `name`
+    pub fn bind_light(&mut self, name: &str, id: LightId) {
+        self.lights.insert(name.to_string(), id);
+    }
+
+    /// Play from the current time, or from the start once the end was reached
+    pub fn play(&mut self) {
+        if !self.looping && self.time >= self.duration() {
+            self.seek(0.0);
+        }
+        self.playing = self.sequence.is_some();
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Pause and rewind to the start
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.seek(0.0);
+    }
+
+    /// Jump to `time`, clamped to the sequence, skipping the triggers in between
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration());
+        self.fresh = true;
+        self.dirty = true;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Seconds into the sequence
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.sequence.as_ref().map_or(0.0, Sequence::length)
+    }
+
+    /// Events playback passed since the last call, in order
+    pub fn take_events(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        self.offset += offset;
+    }
+
+    /// Move the playhead by `dt` seconds of playback and sample the tracks there; `None` when
+    /// neither playback nor a seek moved it
+    pub(crate) fn advance(&mut self, dt: f32) -> Option<SequenceFrame> {
+        let sequence = self.sequence.as_ref()?;
+        if !self.playing && !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+
+        let mut frame = SequenceFrame::default();
+        if self.playing {
+            let duration = sequence.length();
+            let from = self.time;
+            let to = from + dt * self.speed.max(0.0);
+            let mut windows = Vec::with_capacity(2);
+            if to < duration || !self.looping || duration <= 0.0 {
+                self.time = to.min(duration);
+                windows.push((from, self.fresh, self.time));
+                if to >= duration && !self.looping {
+                    self.playing = false;
+                }
+            } else {
+                self.time = to.rem_euclid(duration);
+                windows.push((from, self.fresh, duration));
+                windows.push((0.0, true, self.time));
+            }
+            self.fresh = false;
+            // Triggers between where the playhead was and where it is now
+            let inside = |time: f32| windows.iter().any(|&(from, inclusive, to)| (time > from || (inclusive && time == from)) && time <= to);
+            let mut events: Vec<&SequenceEvent> = sequence.events.iter().filter(|event| inside(event.time)).collect();
+            events.sort_by(|a, b| a.time.total_cmp(&b.time));
+            self.events.extend(events.into_iter().map(|event| event.name.clone()));
+            frame.clips = sequence.clips.iter()
+                .filter(|clip| inside(clip.time))
+                .filter_map(|clip| Some((*self.objects.get(&clip.target)?, clip.clip.clone(), clip.looping)))
+                .collect();
+        }
+
+        let time = self.time;
+        frame.camera = sequence.camera_at(time).map(|mut pose| {
+            pose.position = (Vec3::from(pose.position) + self.offset).to_array();
+            pose
+        });
+        frame.objects = sequence.objects.iter()
+            .filter_map(|track| {
+                let mut transform = Sequence::transform_at(track, time)?;
+                transform.position += self.offset;
+                Some((*self.objects.get(&track.target)?, transform))
+            })
+            .collect();
+        frame.lights = sequence.lights.iter()
+            .filter_map(|track| Some((*self.lights.get(&track.target)?, Sequence::light_at(track, time)?)))
+            .collect();
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQUENCE: &str = r#"
+        name = "intro"
+
+        [[camera]]
+        time = 0.0
+        position = [0.0, 0.0, 0.0]
+
+        [[camera]]
+        time = 2.0
+        position = [4.0, 0.0, 0.0]
+        yaw = 90.0
+
+        [[objects]]
+        target = "door"
+        keys = [{ time = 0.0 }, { time = 1.0, position = [0.0, 2.0, 0.0], easing = "quad_in" }, { time = 2.0, position = [0.0, 4.0, 0.0] }]
+
+        [[lights]]
+        target = "lamp"
+        keys = [{ time = 0.0, intensity = 0.0 }, { time = 2.0, intensity = 4.0 }]
+
+        [[clips]]
+        time = 0.5
+        target = "door"
+        clip = "open"
+
+        [[events]]
+        time = 1.5
+        name = "halfway"
+
+        [[events]]
+        time = 0.0
+        name = "start"
+    "#;
+
+    #[test]
+    fn test_sequence_sampling() {
+        let sequence = Sequence::from_toml(SEQUENCE).unwrap();
+        assert_eq!(sequence.length(), 2.0);
+        let pose = sequence.camera_at(1.0).unwrap();
+        assert_eq!((pose.position, pose.yaw), ([2.0, 0.0, 0.0], 45.0));
+        assert_eq!(sequence.camera_at(5.0).unwrap().position, [4.0, 0.0, 0.0]);
+
+        let door = &sequence.objects[0];
+        assert_eq!(Sequence::transform_at(door, 0.5).unwrap().position, Vec3::new(0.0, 1.0, 0.0));
+        // The second segment eases in from its key
+        assert_eq!(Sequence::transform_at(door, 1.5).unwrap().position, Vec3::new(0.0, 2.5, 0.0));
+        assert_eq!(Sequence::transform_at(door, 0.0).unwrap().scale, Vec3::ONE);
+        assert_eq!(Sequence::light_at(&sequence.lights[0], 1.0).unwrap().intensity, 2.0);
+
+        // Round trips through TOML
+        assert_eq!(Sequence::from_toml(&sequence.to_toml().unwrap()).unwrap(), sequence);
+
+        let mut unordered = sequence.clone();
+        unordered.camera.reverse();
+        assert!(unordered.validate().is_err());
+    }
+
+    #[test]
+    fn test_sequencer_playback() {
+        let mut sequencer = Sequencer::default();
+        assert!(sequencer.advance(0.1).is_none());
+        sequencer.load(Sequence::from_toml(SEQUENCE).unwrap());
+        let (door, lamp) = (ObjectId(4), LightId(2));
+        sequencer.bind_object("door", door);
+        sequencer.bind_light("lamp", lamp);
+
+        // Paused, only seeks move the playhead, without firing triggers
+        sequencer.seek(1.0);
+        let frame = sequencer.advance(0.1).unwrap();
+        assert_eq!(frame.objects, vec![(door, Transform { position: Vec3::new(0.0, 2.0, 0.0), ..Transform::new() })]);
+        assert!(frame.clips.is_empty());
+        assert!(sequencer.advance(0.1).is_none());
+
+        sequencer.seek(0.0);
+        sequencer.play();
+        let frame = sequencer.advance(0.6).unwrap();
+        assert_eq!(frame.clips, vec![(door, "open".to_string(), false)]);
+        assert_eq!(frame.lights[0].0, lamp);
+        assert_eq!(sequencer.take_events(), vec!["start".to_string()]);
+
+        // Playback stops at the end, firing what it passes once
+        let frame = sequencer.advance(5.0).unwrap();
+        assert!(frame.clips.is_empty());
+        assert_eq!(frame.camera.unwrap().position, [4.0, 0.0, 0.0]);
+        assert_eq!(sequencer.take_events(), vec!["halfway".to_string()]);
+        assert!(!sequencer.is_playing());
+        assert_eq!(sequencer.time(), 2.0);
+
+        // Looping wraps around, firing the triggers at the start again
+        sequencer.looping = true;
+        sequencer.play();
+        sequencer.seek(1.9);
+        let frame = sequencer.advance(0.7).unwrap();
+        assert!((sequencer.time() - 0.6).abs() < 1e-5);
+        assert_eq!(frame.clips.len(), 1);
+        assert_eq!(sequencer.take_events(), vec!["start".to_string()]);
+
+        // Rebases shift the authored positions with the world
+        sequencer.translate(Vec3::new(-1.0, 0.0, 0.0));
+        sequencer.seek(0.0);
+        let frame = sequencer.advance(0.0).unwrap();
+        assert_eq!(frame.camera.unwrap().position, [-1.0, 0.0, 0.0]);
+    }
+}
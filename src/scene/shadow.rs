@@ -0,0 +1,519 @@
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+use crate::diagnostics;
+use crate::model::{Model, ModelVertex};
+use super::profiler::Profiler;
+use crate::settings::ShadowQuality;
+use super::lights::{PointLight, ShadowSlot, MAX_SHADOWED_LIGHTS};
+
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// Depth offset applied when comparing against the shadow map
+const SHADOW_BIAS: f32 = 0.002;
+/// Layers per point light: +X, -X, +Y, -Y, +Z, -Z, the order `shader.wgsl` selects faces in
+pub const CUBE_FACES: usize = 6;
+const CUBE_FACE_VIEWS: [(Vec3, Vec3); CUBE_FACES] = [
+    (Vec3::X, Vec3::NEG_Y),
+    (Vec3::NEG_X, Vec3::NEG_Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::Z, Vec3::NEG_Y),
+    (Vec3::NEG_Z, Vec3::NEG_Y),
+];
+const POINT_SHADOW_NEAR: f32 = 0.05;
+// Dynamic uniform offsets must be multiples of `min_uniform_buffer_offset_alignment`, at most 256
+const FACE_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+
+/// Directional light shadow map covering the whole scene
+pub struct ShadowMap {
+    quality: ShadowQuality,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    pipelines: DepthPipelines,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, model_bind_group_layout: &wgpu::BindGroupLayout, quality: ShadowQuality) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&Mat4::IDENTITY.to_cols_array_2d()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipelines = DepthPipelines::new(
+            device,
+            ["Shadow Pipeline", "Double-Sided Shadow Pipeline"],
+            &bind_group_layout,
+            model_bind_group_layout,
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let (texture, view) = Self::create_texture(device, quality);
+
+        Self {
+            quality,
+            texture,
+            view,
+            sampler,
+            pipelines,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, quality: ShadowQuality) -> (wgpu::Texture, wgpu::TextureView) {
+        // Disabled shadows keep a 1x1 map so the light bind group stays valid
+        let size = quality.map_size().unwrap_or(1).min(device.limits().max_texture_dimension_2d);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    /// Reallocate the map for a new quality level; the light bind group must be recreated afterwards
+    pub fn set_quality(&mut self, device: &wgpu::Device, quality: ShadowQuality) {
+        if quality.map_size() != self.quality.map_size() {
+            let (texture, view) = Self::create_texture(device, quality);
+            self.texture = texture;
+            self.view = view;
+        }
+        self.quality = quality;
+    }
+
+    pub fn quality(&self) -> ShadowQuality {
+        self.quality
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    /// x = enabled, y = texel size, z = PCF radius, w = depth bias
+    pub fn params(&self) -> [f32; 4] {
+        let size = self.texture.width() as f32;
+        [
+            if self.quality.map_size().is_some() { 1.0 } else { 0.0 },
+            1.0 / size,
+            self.quality.filter_radius() as f32,
+            SHADOW_BIAS,
+        ]
+    }
+
+    /// Orthographic light projection enclosing a bounding sphere
+    pub fn light_view_projection(light_direction: Vec3, center: Vec3, radius: f32) -> Mat4 {
+        let direction = light_direction.normalize_or_zero();
+        let direction = if direction == Vec3::ZERO { Vec3::NEG_Y } else { direction };
+        let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let radius = radius.max(0.01);
+
+        let eye = center - direction * radius * 2.0;
+        let view = Mat4::look_at_rh(eye, center, up);
+        let proj = Mat4::orthographic_rh(-radius, radius, -radius, radius, radius, radius * 3.0);
+        proj * view
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        light_view_proj: Mat4,
+        objects: &[(&Model, &wgpu::BindGroup)],
+        profiler: &mut Profiler,
+    ) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&light_view_proj.to_cols_array_2d()));
+
+        let queries = profiler.begin_pass("Shadow");
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: queries.as_ref().map(|queries| queries.render_writes()),
+            occlusion_query_set: None,
+        });
+
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        for (model, model_bind_group) in objects {
+            pass.set_bind_group(1, *model_bind_group, &[]);
+            self.pipelines.draw_model(&mut pass, model);
+        }
+        drop(pass);
+        profiler.end_scope();
+    }
+}
+
+/// View-projection for one cube face of a point light, reaching out to its range
+pub fn point_face_view_projection(position: Vec3, range: f32, face: usize) -> Mat4 {
+    let (direction, up) = CUBE_FACE_VIEWS[face];
+    let far = range.max(POINT_SHADOW_NEAR * 2.0);
+    let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, POINT_SHADOW_NEAR, far);
+    proj * Mat4::look_at_rh(position, position + direction, up)
+}
+
+/// Cube shadow maps for point lights: a depth array with `CUBE_FACES` layers per shadow slot.
+/// Lower LODs render into the top-left corner of their slot's layers.
+pub struct PointShadowMaps {
+    face_size: u32,
+    texture: wgpu::Texture,
+    array_view: wgpu::TextureView,
+    face_views: Vec<wgpu::TextureView>,
+    pipelines: DepthPipelines,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Matrices each face was last rendered with, which shading must match even once the light moves
+    face_matrices: [Mat4; MAX_SHADOWED_LIGHTS * CUBE_FACES],
+}
+
+impl PointShadowMaps {
+    pub fn new(device: &wgpu::Device, model_bind_group_layout: &wgpu::BindGroupLayout, quality: ShadowQuality) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point Shadow Uniform Buffer"),
+            size: FACE_UNIFORM_STRIDE * (MAX_SHADOWED_LIGHTS * CUBE_FACES) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Point Shadow Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Point Shadow Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<[[f32; 4]; 4]>() as u64),
+                }),
+            }],
+        });
+        let pipelines = DepthPipelines::new(
+            device,
+            ["Point Shadow Pipeline", "Double-Sided Point Shadow Pipeline"],
+            &bind_group_layout,
+            model_bind_group_layout,
+        );
+
+        let face_size = Self::size_for(device, quality);
+        let (texture, array_view, face_views) = Self::create_texture(device, face_size);
+
+        Self {
+            face_size,
+            texture,
+            array_view,
+            face_views,
+            pipelines,
+            uniform_buffer,
+            bind_group,
+            face_matrices: [Mat4::IDENTITY; MAX_SHADOWED_LIGHTS * CUBE_FACES],
+        }
+    }
+
+    /// A quarter of the directional map's resolution per face; 1 when shadows are off
+    fn size_for(device: &wgpu::Device, quality: ShadowQuality) -> u32 {
+        quality.map_size().map_or(1, |size| size / 4).min(device.limits().max_texture_dimension_2d)
+    }
+
+    fn create_texture(device: &wgpu::Device, face_size: u32) -> (wgpu::Texture, wgpu::TextureView, Vec<wgpu::TextureView>) {
+        let layers = (MAX_SHADOWED_LIGHTS * CUBE_FACES) as u32;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Point Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Point Shadow Array View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let face_views = (0..layers)
+            .map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Point Shadow Face View"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            }))
+            .collect();
+        (texture, array_view, face_views)
+    }
+
+    /// Reallocate for a new quality level, dropping every rendered map; the light bind group
+    /// must be recreated afterwards
+    pub fn set_quality(&mut self, device: &wgpu::Device, quality: ShadowQuality) {
+        let face_size = Self::size_for(device, quality);
+        if face_size != self.face_size {
+            (self.texture, self.array_view, self.face_views) = Self::create_texture(device, face_size);
+            self.face_size = face_size;
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.array_view
+    }
+
+    pub fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    /// Matrices of every face in every slot, slot-major
+    pub fn face_matrices(&self) -> &[Mat4] {
+        &self.face_matrices
+    }
+
+    /// Render the six faces of each updated light. Casters carry their world bounds so each
+    /// light only draws the objects within its range.
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        updates: &[(ShadowSlot, PointLight)],
+        casters: &[(&Model, &wgpu::BindGroup, (Vec3, Vec3))],
+        profiler: &mut Profiler,
+    ) {
+        if updates.is_empty() {
+            return;
+        }
+        profiler.begin_scope("Point Shadows");
+        for (slot, light) in updates {
+            let size = (self.face_size >> slot.lod).max(1);
+            let in_range: Vec<_> = casters.iter()
+                .filter(|(_, _, (min, max))| light.position.clamp(*min, *max).distance(light.position) < light.range)
+                .collect();
+
+            for face in 0..CUBE_FACES {
+                let layer = slot.index * CUBE_FACES + face;
+                let matrix = point_face_view_projection(light.position, light.range, face);
+                self.face_matrices[layer] = matrix;
+                let offset = layer as wgpu::BufferAddress * FACE_UNIFORM_STRIDE;
+                queue.write_buffer(&self.uniform_buffer, offset, bytemuck::cast_slice(&matrix.to_cols_array_2d()));
+
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Point Shadow Pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.face_views[layer],
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                pass.set_viewport(0.0, 0.0, size as f32, size as f32, 0.0, 1.0);
+                pass.set_bind_group(0, &self.bind_group, &[offset as u32]);
+                for (model, model_bind_group, _) in &in_range {
+                    pass.set_bind_group(1, *model_bind_group, &[]);
+                    self.pipelines.draw_model(&mut pass, model);
+                }
+            }
+        }
+        profiler.end_scope();
+    }
+}
+
+/// Depth-only pipelines drawing models with `shadow.wgsl`, with and without back-face culling
+struct DepthPipelines {
+    culled: wgpu::RenderPipeline,
+    double_sided: wgpu::RenderPipeline,
+}
+
+impl DepthPipelines {
+    /// `labels` are for the culled and the double-sided pipeline
+    fn new(
+        device: &wgpu::Device,
+        labels: [&'static str; 2],
+        bind_group_layout: &wgpu::BindGroupLayout,
+        model_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self {
+            culled: create_depth_pipeline(device, labels[0], bind_group_layout, model_bind_group_layout, false),
+            double_sided: create_depth_pipeline(device, labels[1], bind_group_layout, model_bind_group_layout, true),
+        }
+    }
+
+    /// Draw every mesh of `model` with the pipeline its own material asks for; object material
+    /// overrides don't reach the shadow passes. Group 1 must hold the model's uniform.
+    fn draw_model<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, model: &'a Model) {
+        for mesh in model.all_meshes() {
+            let double_sided = model.materials.get(mesh.material_index).is_some_and(|material| material.double_sided);
+            pass.set_pipeline(if double_sided { &self.double_sided } else { &self.culled });
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+        }
+    }
+}
+
+/// Depth-only pipeline drawing models with `shadow.wgsl`; group 0 holds the light's view-projection
+fn create_depth_pipeline(
+    device: &wgpu::Device,
+    label: &'static str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    model_bind_group_layout: &wgpu::BindGroupLayout,
+    double_sided: bool,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/shadow.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout, model_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    diagnostics::scoped(device, label, || {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[ModelVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: if double_sided { None } else { Some(wgpu::Face::Back) },
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_projection_encloses_sphere() {
+        let center = Vec3::new(1.0, 0.0, -2.0);
+        let radius = 5.0;
+        let view_proj = ShadowMap::light_view_projection(Vec3::new(-0.5, -1.0, -0.5), center, radius);
+
+        for offset in [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z] {
+            let clip = view_proj * (center + offset * radius * 0.99).extend(1.0);
+            let ndc = clip.truncate() / clip.w;
+            assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "{:?} outside light frustum", offset);
+            assert!((0.0..=1.0).contains(&ndc.z), "{:?} outside light depth range", offset);
+        }
+    }
+
+    #[test]
+    fn test_point_faces_cover_their_axis() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        for (face, (direction, _)) in CUBE_FACE_VIEWS.iter().enumerate() {
+            let clip = point_face_view_projection(position, 10.0, face) * (position + *direction * 5.0).extend(1.0);
+            let ndc = clip.truncate() / clip.w;
+            assert!(ndc.x.abs() < 1e-5 && ndc.y.abs() < 1e-5, "face {} not centered on its axis", face);
+            assert!(ndc.z > 0.0 && ndc.z < 1.0);
+
+            // 45 degrees off axis is the face's edge
+            let (u, v) = (direction.any_orthonormal_pair().0, direction.any_orthonormal_pair().1);
+            let edge = point_face_view_projection(position, 10.0, face) * (position + *direction + u * 0.999 + v * 0.5).extend(1.0);
+            assert!((edge.truncate() / edge.w).abs().max_element() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_light_projection_straight_down() {
+        // A vertical light must not produce a degenerate view matrix
+        let view_proj = ShadowMap::light_view_projection(Vec3::NEG_Y, Vec3::ZERO, 1.0);
+        assert!(view_proj.is_finite());
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use glam::Mat4;
+use crate::model::{AssetRegistry, NodePose};
+use super::{ObjectId, SceneObjects};
+
+/// Which clip an object plays, and how far through it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playback {
+    /// Index into the model skeleton's clips
+    pub clip: usize,
+    /// Seconds into the clip
+    pub time: f32,
+    /// Playback rate; negative plays backwards
+    pub speed: f32,
+    /// Wrap around at the end rather than holding the last pose
+    pub looping: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Player {
+    playback: Option<Playback>,
+    /// Joint matrices of the last sampled pose, uploaded by the renderer
+    palette: Vec<Mat4>,
+}
+
+/// Skeletal animation of objects whose models have a skeleton: `Scene::update` samples each
+/// object's clip and keeps its joint palette, which the renderer uploads for the `SKINNED`
+/// shader variant. Skinned models of objects not registered here draw in their bind pose.
+#[derive(Debug, Default)]
+pub struct Skinning {
+    players: BTreeMap<ObjectId, Player>,
+}
+
+impl Skinning {
+    /// Start `clip` from the beginning on `object`, replacing what it played
+    pub fn play(&mut self, object: ObjectId, clip: usize, looping: bool) {
+        let playback = Playback { clip, time: 0.0, speed: 1.0, looping };
+        self.players.entry(object)
+            .and_modify(|player| player.playback = Some(playback))
+            .or_insert(Player { playback: Some(playback), palette: Vec::new() });
+    }
+
+    /// Stop advancing `object`'s clip, holding its current pose; returns what it played
+    pub fn stop(&mut self, object: ObjectId) -> Option<Playback> {
+        self.players.get_mut(&object)?.playback.take()
+    }
+
+    /// Forget `object`, returning its model to the bind pose
+    pub fn remove(&mut self, object: ObjectId) {
+        self.players.remove(&object);
+    }
+
+    pub fn playback(&self, object: ObjectId) -> Option<&Playback> {
+        self.players.get(&object)?.playback.as_ref()
+    }
+
+    /// Seek, change speed or looping of a playing clip
+    pub fn playback_mut(&mut self, object: ObjectId) -> Option<&mut Playback> {
+        self.players.get_mut(&object)?.playback.as_mut()
+    }
+
+    /// The joint matrices `object` is drawn with, once a pose was sampled
+    pub fn joint_matrices(&self, object: ObjectId) -> Option<&[Mat4]> {
+        self.players.get(&object)
+            .map(|player| player.palette.as_slice())
+            .filter(|palette| !palette.is_empty())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    /// Advance every clip by `dt` and sample the poses, dropping players of removed objects
+    pub(crate) fn update(&mut self, dt: f32, objects: &SceneObjects, assets: &AssetRegistry) {
+        self.players.retain(|&object, _| objects.index_of(object).is_some());
+        for (&object, player) in &mut self.players {
+            let Some(skeleton) = objects.index_of(object)
+                .and_then(|index| objects.get(index))
+                .and_then(|object| assets.model(object.model))
+                .and_then(|model| model.skeleton.as_ref())
+            else {
+                continue;
+            };
+            let mut pose: Vec<NodePose> = skeleton.rest_pose();
+            if let Some(playback) = &mut player.playback {
+                let Some(clip) = skeleton.clips.get(playback.clip) else {
+                    continue;
+                };
+                playback.time += dt * playback.speed;
+                playback.time = if playback.looping && clip.duration > 0.0 {
+                    playback.time.rem_euclid(clip.duration)
+                } else {
+                    playback.time.clamp(0.0, clip.duration)
+                };
+                clip.sample(playback.time, &mut pose);
+            } else if !player.palette.is_empty() {
+                // Stopped players hold their last pose
+                continue;
+            }
+            player.palette = skeleton.joint_matrices(&pose);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playback_controls() {
+        let object = ObjectId(3);
+        let mut skinning = Skinning::default();
+        assert!(skinning.is_empty());
+        skinning.play(object, 1, true);
+        assert_eq!(skinning.playback(object).map(|playback| playback.clip), Some(1));
+        skinning.playback_mut(object).unwrap().speed = 2.0;
+        // Nothing has been sampled yet, so the object still draws in its bind pose
+        assert_eq!(skinning.joint_matrices(object), None);
+
+        let stopped = skinning.stop(object).unwrap();
+        assert_eq!(stopped.speed, 2.0);
+        assert!(skinning.playback(object).is_none());
+        skinning.remove(object);
+        assert!(skinning.is_empty());
+    }
+}
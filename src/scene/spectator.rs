@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use glam::{Mat4, Quat, Vec3};
+use crate::readback::{self, TextureRegion};
+use super::anchor::heading;
+use super::RenderView;
+
+/// Where the spectator camera is put relative to the viewer's head
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectatorMode {
+    /// Through the viewer's eyes, with the head's small movements smoothed out
+    FirstPerson,
+    /// `distance` behind the viewer and `height` above their eyes, looking at their head
+    ThirdPerson { distance: f32, height: f32 },
+    /// Standing still at a world transform, e.g. matched to a real camera for mixed reality
+    Fixed(Mat4),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectatorSettings {
+    pub mode: SpectatorMode,
+    /// Vertical field of view, in degrees; wider than a desktop camera's reads better when
+    /// watching someone look around
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    /// Seconds the camera takes to catch up most (63%) of the way to where the head puts it;
+    /// 0 follows rigidly. Turning lags separately from moving, and shows head jitter more.
+    pub position_lag: f32,
+    pub rotation_lag: f32,
+    /// Keep the horizon level however the viewer tilts their head
+    pub level: bool,
+}
+
+impl Default for SpectatorSettings {
+    fn default() -> Self {
+        Self {
+            mode: SpectatorMode::FirstPerson,
+            fov: 70.0,
+            near: 0.05,
+            far: 100.0,
+            position_lag: 0.1,
+            rotation_lag: 0.25,
+            level: true,
+        }
+    }
+}
+
+/// A smoothed extra view following the VR viewer, for the desktop window or a recording, so
+/// others can watch without the shaky first-person mirror. `Scene::update` moves it after
+/// the head pose in `Scene::anchors`; render it with `render_view` after the eyes.
+#[derive(Debug, Default)]
+pub struct Spectator {
+    pub settings: SpectatorSettings,
+    pub enabled: bool,
+    /// Position and rotation in the world, `None` until the first update
+    pose: Option<(Vec3, Quat)>,
+}
+
+impl Spectator {
+    pub fn new(settings: SpectatorSettings) -> Self {
+        Self { settings, enabled: true, pose: None }
+    }
+
+    /// Jump straight to the head on the next update instead of gliding there, e.g. after a
+    /// teleport
+    pub fn reset(&mut self) {
+        self.pose = None;
+    }
+
+    /// World transform of the camera, `None` before the first update
+    pub fn transform(&self) -> Option<Mat4> {
+        self.pose.map(|(position, rotation)| Mat4::from_rotation_translation(rotation, position))
+    }
+
+    pub fn view_matrix(&self) -> Option<Mat4> {
+        self.transform().map(|transform| transform.inverse())
+    }
+
+    /// Projection in `Camera`'s depth convention for a target of `aspect` (width / height)
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        Mat4::perspective_rh_gl(self.settings.fov.to_radians(), aspect, self.settings.near, self.settings.far)
+    }
+
+    /// The view for `Renderer::render_views` into `target`, of `aspect`; `None` while disabled
+    /// or before the first update
+    pub fn render_view<'a>(&self, aspect: f32, target: &'a wgpu::TextureView) -> Option<RenderView<'a>> {
+        if !self.enabled {
+            return None;
+        }
+        Some(RenderView { view: self.view_matrix()?, projection: self.projection_matrix(aspect), target })
+    }
+
+    /// Move towards where `head` (its world transform) puts the camera
+    pub(crate) fn update(&mut self, head: Mat4, dt: f32) {
+        if !self.enabled {
+            return;
+        }
+        let (position, rotation) = self.target(head);
+        self.pose = Some(match self.pose {
+            Some((current_position, current_rotation)) => (
+                current_position.lerp(position, follow(self.settings.position_lag, dt)),
+                current_rotation.slerp(rotation, follow(self.settings.rotation_lag, dt)),
+            ),
+            None => (position, rotation),
+        });
+    }
+
+    /// Follow a floating-origin rebase
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        if let Some((position, _)) = &mut self.pose {
+            *position += offset;
+        }
+        if let SpectatorMode::Fixed(transform) = &mut self.settings.mode {
+            *transform = Mat4::from_translation(offset) * *transform;
+        }
+    }
+
+    /// Where the camera would be without smoothing
+    fn target(&self, head: Mat4) -> (Vec3, Quat) {
+        let (_, head_rotation, eyes) = head.to_scale_rotation_translation();
+        let (position, rotation) = match self.settings.mode {
+            SpectatorMode::FirstPerson => (eyes, head_rotation),
+            SpectatorMode::ThirdPerson { distance, height } => {
+                let position = eyes + heading(head_rotation) * Vec3::new(0.0, height, distance);
+                (position, look_rotation(eyes - position).unwrap_or(head_rotation))
+            }
+            SpectatorMode::Fixed(transform) => {
+                let (_, rotation, position) = transform.to_scale_rotation_translation();
+                (position, rotation)
+            }
+        };
+        let rotation = if self.settings.level {
+            look_rotation(rotation * Vec3::NEG_Z).unwrap_or(rotation)
+        } else {
+            rotation
+        };
+        (position, rotation)
+    }
+}
+
+/// The rotation facing along `direction` with no roll; `None` straight up or down
+fn look_rotation(direction: Vec3) -> Option<Quat> {
+    let forward = direction.try_normalize()?;
+    let right = forward.cross(Vec3::Y).try_normalize()?;
+    let up = right.cross(forward);
+    Some(Quat::from_mat3(&glam::Mat3::from_cols(right, up, -forward)))
+}
+
+/// Share of the remaining distance an exponential lag closes in `dt`, the same at any frame rate
+fn follow(lag: f32, dt: f32) -> f32 {
+    if lag <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-dt / lag).exp()
+    }
+}
+
+/// Numbered PNGs of spectator frames, for making a video of a session. Each frame is read back
+/// from the GPU as it's saved, which stalls the frame, so keep the target small.
+pub struct SpectatorRecording {
+    directory: PathBuf,
+    frame: u32,
+}
+
+impl SpectatorRecording {
+    /// Save frames into `directory`, creating it if needed
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Failed to create spectator recording directory {}", directory.display()))?;
+        Ok(Self { directory, frame: 0 })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Frames saved so far
+    pub fn frame_count(&self) -> u32 {
+        self.frame
+    }
+
+    /// Save `texture` (8-bit RGBA or BGRA, with `COPY_SRC` usage) as the next frame, after the
+    /// spectator view was rendered into it; returns the file written
+    pub fn save_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<PathBuf> {
+        let data = readback::read_texture_region(device, queue, texture, TextureRegion::full(texture))?;
+        let rgba = data.to_rgba8().ok_or_else(|| anyhow!("Can't save spectator frames of format {:?}", data.format))?;
+        let path = self.directory.join(format!("spectator_{:06}.png", self.frame));
+        image::save_buffer(&path, &rgba, data.width, data.height, image::ColorType::Rgba8)
+            .with_context(|| format!("Failed to write spectator frame {}", path.display()))?;
+        self.frame += 1;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(position: Vec3, yaw: f32, roll: f32) -> Mat4 {
+        Mat4::from_translation(position) * Mat4::from_rotation_y(yaw) * Mat4::from_rotation_z(roll)
+    }
+
+    #[test]
+    fn test_spectator_smoothing() {
+        let mut spectator = Spectator::new(SpectatorSettings { position_lag: 0.2, ..SpectatorSettings::default() });
+        // The first update snaps to the head, leveled
+        spectator.update(head(Vec3::new(0.0, 1.6, 0.0), 0.0, 0.3), 0.016);
+        let transform = spectator.transform().unwrap();
+        assert!((transform.w_axis.truncate() - Vec3::new(0.0, 1.6, 0.0)).length() < 1e-5);
+        assert!(transform.transform_vector3(Vec3::X).y.abs() < 1e-5);
+
+        // Then it trails the head, closing the same share of the gap at any frame rate
+        let moved = head(Vec3::new(1.0, 1.6, 0.0), 0.0, 0.0);
+        let mut stepped = Spectator::new(spectator.settings);
+        stepped.update(head(Vec3::new(0.0, 1.6, 0.0), 0.0, 0.0), 0.0);
+        stepped.update(moved, 0.1);
+        stepped.update(moved, 0.1);
+        spectator.update(moved, 0.2);
+        let x = spectator.transform().unwrap().w_axis.x;
+        assert!((x - (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+        assert!((stepped.transform().unwrap().w_axis.x - x).abs() < 1e-4);
+
+        // Disabled spectators have no view
+        spectator.enabled = false;
+        spectator.update(moved, 10.0);
+        assert!((spectator.transform().unwrap().w_axis.x - x).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_spectator_modes() {
+        let settings = SpectatorSettings { position_lag: 0.0, rotation_lag: 0.0, ..SpectatorSettings::default() };
+        // Facing +X, looking down a little
+        let viewer = Mat4::from_translation(Vec3::new(0.0, 1.6, 0.0))
+            * Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2)
+            * Mat4::from_rotation_x(-0.4);
+
+        // Behind and above, looking at the head
+        let mut third = Spectator::new(SpectatorSettings { mode: SpectatorMode::ThirdPerson { distance: 2.0, height: 0.5 }, ..settings });
+        third.update(viewer, 0.016);
+        let view = third.view_matrix().unwrap();
+        assert!((third.transform().unwrap().w_axis.truncate() - Vec3::new(-2.0, 2.1, 0.0)).length() < 1e-5);
+        let head_in_view = view.transform_point3(Vec3::new(0.0, 1.6, 0.0));
+        assert!(head_in_view.x.abs() < 1e-5 && head_in_view.y.abs() < 1e-5 && head_in_view.z < 0.0);
+
+        // Fixed cameras ignore the head, and move with a rebase
+        let mount = Mat4::from_translation(Vec3::new(3.0, 2.0, 3.0));
+        let mut fixed = Spectator::new(SpectatorSettings { mode: SpectatorMode::Fixed(mount), ..settings });
+        fixed.update(viewer, 0.016);
+        assert_eq!(fixed.transform().unwrap().w_axis.truncate(), Vec3::new(3.0, 2.0, 3.0));
+        fixed.translate(Vec3::new(-1.0, 0.0, 0.0));
+        fixed.update(viewer, 0.016);
+        assert!((fixed.transform().unwrap().w_axis.truncate() - Vec3::new(2.0, 2.0, 3.0)).length() < 1e-6);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use crate::color::Color;
+use crate::diagnostics;
+use crate::shaders;
+use super::ObjectId;
+use super::post::{ALBEDO_FORMAT, HDR_FORMAT, NORMAL_FORMAT, VELOCITY_FORMAT};
+
+/// Scene pipelines write the object's stencil reference wherever it passes the depth test, so
+/// each pixel ends up holding the mark of its nearest surface
+pub(crate) const MARK: wgpu::StencilState = wgpu::StencilState {
+    front: MARK_FACE,
+    back: MARK_FACE,
+    read_mask: 0xff,
+    write_mask: 0xff,
+};
+
+const MARK_FACE: wgpu::StencilFaceState = wgpu::StencilFaceState {
+    compare: wgpu::CompareFunction::Always,
+    fail_op: wgpu::StencilOperation::Keep,
+    depth_fail_op: wgpu::StencilOperation::Keep,
+    pass_op: wgpu::StencilOperation::Replace,
+};
+
+/// A fullscreen fill of the pixels whose stencil value passes a test against `reference`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilOverlay {
+    pub reference: u8,
+    /// Passes where `reference <compare> stencil value` holds
+    pub compare: wgpu::CompareFunction,
+    /// HDR color, alpha blended over the scene
+    pub color: Color,
+}
+
+impl StencilOverlay {
+    /// Tints the pixels whose nearest surface is marked `reference`, e.g. to highlight a selection
+    pub fn tint(reference: u8, color: Color) -> Self {
+        Self { reference, compare: wgpu::CompareFunction::Equal, color }
+    }
+
+    /// Covers everything not marked `reference`, e.g. to mask the world outside a portal or
+    /// mirror surface
+    pub fn outside(reference: u8, color: Color) -> Self {
+        Self { reference, compare: wgpu::CompareFunction::NotEqual, color }
+    }
+}
+
+/// Per-object stencil marks and the overlays masked by them. Needs a stencil depth format
+/// (`DepthFormat::Depth24Stencil8`); with `Depth32` both are ignored.
+///
+/// Unmarked objects write 0. Overlays are drawn in order at the end of the scene pass, over
+/// the grid, and don't write to the normal, albedo or velocity targets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stencil {
+    marks: HashMap<ObjectId, u8>,
+    pub overlays: Vec<StencilOverlay>,
+}
+
+impl Stencil {
+    /// Mark an object's pixels with `reference`; 0 removes the mark
+    pub fn mark(&mut self, id: ObjectId, reference: u8) {
+        if reference == 0 {
+            self.marks.remove(&id);
+        } else {
+            self.marks.insert(id, reference);
+        }
+    }
+
+    pub fn unmark(&mut self, id: ObjectId) -> bool {
+        self.marks.remove(&id).is_some()
+    }
+
+    /// The reference an object writes, 0 when unmarked
+    pub fn reference(&self, id: ObjectId) -> u8 {
+        self.marks.get(&id).copied().unwrap_or(0)
+    }
+
+    pub fn marked(&self) -> usize {
+        self.marks.len()
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marks.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayUniform {
+    color: [f32; 4],
+}
+
+/// Draws `Stencil::overlays` inside the scene pass, with a pipeline per compare function
+pub(crate) struct StencilOverlayPass {
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    pipelines: HashMap<wgpu::CompareFunction, wgpu::RenderPipeline>,
+    /// One overlay's uniform per `stride` bytes, bound at a dynamic offset
+    stride: u64,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    capacity: usize,
+    /// Overlays uploaded by `prepare`, with the pipeline each uses
+    prepared: Vec<(u8, wgpu::CompareFunction)>,
+}
+
+impl StencilOverlayPass {
+    pub fn new(device: &wgpu::Device, sample_count: u32, depth_format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Stencil Overlay Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<OverlayUniform>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stencil Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders::create_module(device, "Stencil Overlay Shader", include_str!("../../shaders/stencil.wgsl"));
+        let stride = u64::from(device.limits().min_uniform_buffer_offset_alignment)
+            .max(std::mem::size_of::<OverlayUniform>() as u64);
+        let (uniform_buffer, bind_group) = Self::create_buffer(device, &bind_group_layout, stride, 1);
+
+        Self {
+            shader,
+            layout,
+            bind_group_layout,
+            sample_count,
+            depth_format,
+            pipelines: HashMap::new(),
+            stride,
+            uniform_buffer,
+            bind_group,
+            capacity: 1,
+            prepared: Vec::new(),
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: u64,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Stencil Overlay Uniform Buffer"),
+            size: stride * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Stencil Overlay Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<OverlayUniform>() as u64),
+                }),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    /// Drop the cached pipelines when the scene pass sample count or depth format changes
+    pub fn set_targets(&mut self, sample_count: u32, depth_format: wgpu::TextureFormat) {
+        if (sample_count, depth_format) != (self.sample_count, self.depth_format) {
+            self.sample_count = sample_count;
+            self.depth_format = depth_format;
+            self.pipelines.clear();
+        }
+    }
+
+    /// Upload `overlays` and compile the pipelines they need; call before the scene pass
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, overlays: &[StencilOverlay]) {
+        self.prepared.clear();
+        if overlays.is_empty() || !self.depth_format.has_stencil_aspect() {
+            return;
+        }
+        if overlays.len() > self.capacity {
+            self.capacity = overlays.len().next_power_of_two();
+            (self.uniform_buffer, self.bind_group) =
+                Self::create_buffer(device, &self.bind_group_layout, self.stride, self.capacity);
+        }
+
+        let mut contents = vec![0u8; self.stride as usize * overlays.len()];
+        for (index, overlay) in overlays.iter().enumerate() {
+            let uniform = OverlayUniform { color: overlay.color.to_array() };
+            let start = index * self.stride as usize;
+            contents[start..start + std::mem::size_of::<OverlayUniform>()].copy_from_slice(bytemuck::bytes_of(&uniform));
+
+            if !self.pipelines.contains_key(&overlay.compare) {
+                let pipeline = self.create_pipeline(device, overlay.compare);
+                self.pipelines.insert(overlay.compare, pipeline);
+            }
+            self.prepared.push((overlay.reference, overlay.compare));
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &contents);
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, compare: wgpu::CompareFunction) -> wgpu::RenderPipeline {
+        let label = format!("Stencil Overlay Pipeline [{:?}]", compare);
+        let face = wgpu::StencilFaceState {
+            compare,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        diagnostics::scoped(device, &label, || {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&label),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[
+                        Some(wgpu::ColorTargetState {
+                            format: HDR_FORMAT,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: NORMAL_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: ALBEDO_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                        Some(wgpu::ColorTargetState {
+                            format: VELOCITY_FORMAT,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::empty(),
+                        }),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: self.depth_format,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState { front: face, back: face, read_mask: 0xff, write_mask: 0 },
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: self.sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        })
+    }
+
+    /// Draw the overlays uploaded by `prepare`
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        for (index, (reference, compare)) in self.prepared.iter().enumerate() {
+            let Some(pipeline) = self.pipelines.get(compare) else {
+                continue;
+            };
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_stencil_reference(u32::from(*reference));
+            render_pass.set_bind_group(0, &self.bind_group, &[(index as u64 * self.stride) as u32]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marks() {
+        let mut stencil = Stencil::default();
+        stencil.mark(ObjectId(3), 2);
+        stencil.mark(ObjectId(4), 1);
+        assert_eq!((stencil.reference(ObjectId(3)), stencil.reference(ObjectId(5))), (2, 0));
+
+        // Marking with 0 is the same as unmarking
+        stencil.mark(ObjectId(3), 0);
+        assert_eq!(stencil.reference(ObjectId(3)), 0);
+        assert!(stencil.unmark(ObjectId(4)));
+        assert!(!stencil.unmark(ObjectId(4)));
+        assert_eq!(stencil.marked(), 0);
+    }
+}
@@ -1,13 +1,15 @@
 use super::*;
+use crate::color::Color;
 use crate::model::Model;
 use pollster::FutureExt;
 use wgpu::{Instance, util::DeviceExt};
 use glam::Vec4Swizzles;
+use crate::readback::{self, TextureRegion};
+use rayon::prelude::*;
 
 struct TestContext {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    #[allow(dead_code)]  // Kept for future use
     adapter: wgpu::Adapter,
 }
 
@@ -58,6 +60,11 @@ macro_rules! gpu_test {
     };
 }
 
+#[test]
+fn test_uniforms_match_wgsl() {
+    renderer::check_uniform_layouts().unwrap();
+}
+
 #[test]
 fn test_transform_new() {
     let transform = Transform::new();
@@ -122,22 +129,20 @@ fn test_scene_new() {
     let camera = Camera::new(Vec3::new(0.0, 1.0, 2.0), 800.0 / 600.0);
     let scene = Scene::new(camera);
     assert!(scene.objects.is_empty());
-    assert_eq!(scene.ambient_light, Vec3::new(0.1, 0.1, 0.1));
-    assert_eq!(scene.directional_light, Vec3::new(1.0, 1.0, 1.0));
+    assert_eq!(scene.ambient_light, Color::linear(0.1, 0.1, 0.1));
+    assert_eq!(scene.directional_light, Color::WHITE);
     assert!(scene.light_direction.is_normalized());
 }
 
-gpu_test!(test_scene_add_object, |context: TestContext| {
-    let camera = Camera::new(Vec3::new(0.0, 0.0, -5.0), 800.0 / 600.0);
-    let mut scene = Scene::new(camera);
-
-    let vertex_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+/// Single-mesh model spanning the unit cube around the origin
+fn test_model(device: &wgpu::Device) -> Model {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Test Vertex Buffer"),
-        contents: &[0u8; 48],  // Size of one ModelVertex
+        contents: &[0u8; 64],  // Size of one ModelVertex
         usage: wgpu::BufferUsages::VERTEX,
     });
 
-    let index_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("Test Index Buffer"),
         contents: &[0u8; 4],  // One u32 index
         usage: wgpu::BufferUsages::INDEX,
@@ -149,19 +154,762 @@ gpu_test!(test_scene_add_object, |context: TestContext| {
         index_buffer,
         num_elements: 1,
         material_index: 0,
+        vertex_colors: false,
+        skin_buffer: None,
     };
 
-    let model = Model {
+    Model {
         meshes: vec![mesh],
+        dynamic_meshes: Vec::new(),
         materials: vec![],
+        skeleton: None,
         bounds_min: [-1.0, -1.0, -1.0],
         bounds_max: [1.0, 1.0, 1.0],
+    }
+}
+
+/// A 64x64 render target and a surface configuration matching it
+fn test_target(device: &wgpu::Device) -> (wgpu::SurfaceConfiguration, wgpu::TextureView) {
+    let (config, _, view) = readback_target(device, wgpu::TextureFormat::Rgba8UnormSrgb, 64, 64);
+    (config, view)
+}
+
+/// A render target that can be read back, with a surface configuration matching it
+fn readback_target(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) -> (wgpu::SurfaceConfiguration, wgpu::Texture, wgpu::TextureView) {
+    let config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+        desired_maximum_frame_latency: 2,
     };
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Test Surface"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    (config, target, view)
+}
 
-    let transform = Transform::new();
-    scene.add_object(model, transform);
-    
+gpu_test!(test_scene_add_object, |context: TestContext| {
+    let camera = Camera::new(Vec3::new(0.0, 0.0, -5.0), 800.0 / 600.0);
+    let mut scene = Scene::new(camera);
+
+    let model = scene.assets.add_model(test_model(&context.device));
+    let mut transform = Transform::new();
+    transform.position = Vec3::new(10.0, 0.0, 0.0);
+    let id = scene.add_object(model, transform);
+
+    // A second instance shares the registered model instead of duplicating its buffers
+    let mut transform = Transform::new();
+    transform.position = Vec3::new(-10.0, 0.0, 0.0);
+    let other = scene.add_object(model, transform);
+
+    assert_eq!(scene.objects.len(), 2);
+    assert_eq!(scene.assets.model_count(), 1);
+    assert_eq!(scene.object(id).unwrap().world_bounds(&scene.assets), Some((Vec3::new(9.0, -1.0, -1.0), Vec3::new(11.0, 1.0, 1.0))));
+    assert_eq!(scene.object(other).unwrap().world_bounds(&scene.assets), Some((Vec3::new(-11.0, -1.0, -1.0), Vec3::new(-9.0, 1.0, 1.0))));
+    assert_eq!(scene.bounds(), Some((Vec3::new(-11.0, -1.0, -1.0), Vec3::new(11.0, 1.0, 1.0))));
+
+    // Focusing aims the camera at the object's center
+    assert!(scene.focus_object(id));
+    let view_proj = scene.camera.build_view_projection_matrix();
+    let center = view_proj.project_point3(Vec3::new(10.0, 0.0, 0.0));
+    assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+    assert!(!scene.focus_object(crate::scene::ObjectId(other.0 + 1)));
+});
+
+gpu_test!(test_scene_loading_placeholder, |context: TestContext| {
+    use std::time::{Duration, Instant};
+    let (config, _) = test_target(&context.device);
+    let renderer = Renderer::new(&context.device, &context.queue, &config);
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let placeholder = scene.assets.add_model(test_model(&context.device));
+    scene.loading.set_placeholder(Some(placeholder));
+
+    let models = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/models");
+    let options = crate::model::ImportOptions::default();
+    let load = scene.loading.request(models.join("cube.obj"), options);
+    let id = scene.add_object_loading(load, Transform::new()).unwrap();
+    assert_eq!(scene.object(id).unwrap().model(), placeholder);
+    let failed = scene.loading.request(models.join("missing.obj"), options);
+    let doomed = scene.add_object_loading(failed, Transform::new()).unwrap();
+    // Without a placeholder the object only joins the scene with its model
+    scene.loading.set_placeholder(None);
+    let reserved = scene.add_object_loading(load, Transform::new()).unwrap();
+    assert!(scene.object(reserved).is_none());
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while scene.loading.pending() > 0 && Instant::now() < deadline {
+        scene.upload_loads(&context.device, &context.queue, renderer.material_bind_group_layout());
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let model = scene.loading.model(load).expect("The cube should have loaded");
+    assert_eq!(scene.object(id).unwrap().model(), model);
+    assert_eq!(scene.object(reserved).unwrap().model(), model);
+    assert_eq!(scene.assets.model_refs(model), 3);
+    // The object waiting for the missing file is removed with its placeholder reference
+    assert!(matches!(scene.loading.state(failed), Some(LoadState::Failed(_))));
+    assert!(scene.object(doomed).is_none());
+    assert_eq!(scene.assets.model_refs(placeholder), 1);
+    // Finished loads place their model at once
+    let again = scene.add_object_loading(load, Transform::new()).unwrap();
+    assert_eq!(scene.object(again).unwrap().model(), model);
+});
+
+gpu_test!(test_scatter_populate, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let hills = |x: f32, z: f32| (x * 0.3).sin() + (z * 0.2).cos();
+    let scatter = Scatter { seed: 3, per_square_meter: 0.5, ..Scatter::default() };
+
+    let ids = scatter.populate(&mut scene, model, &hills);
+    assert!(!ids.is_empty());
+    assert_eq!(scene.objects.len(), ids.len());
+    assert_eq!(scene.assets.model_count(), 1, "Instances share the one model");
+    // Each instance stands on the ground
+    for (id, transform) in ids.iter().zip(scatter.transforms(&hills)) {
+        let object = scene.object(*id).unwrap();
+        assert_eq!(object.transform, transform);
+        assert!((transform.position.y - hills(transform.position.x, transform.position.z)).abs() < 1e-5);
+    }
+});
+
+gpu_test!(test_scene_floating_origin, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let mut transform = Transform::new();
+    transform.position = Vec3::new(3000.0, 0.0, 5.0);
+    let id = scene.add_object(model, transform);
+
+    // Nothing happens near the origin
+    assert_eq!(scene.update(), None);
+
+    // Flying out past the threshold rebases around the camera
+    scene.camera.position = Vec3::new(3010.0, 2.0, 0.0);
+    let shift = scene.update().expect("camera is past the rebase distance");
+    assert_eq!(shift, Vec3::new(3000.0, 0.0, 0.0));
+    assert!(scene.camera.position.length() < 20.0);
+
+    // Local positions moved, absolute ones didn't
+    let object = scene.object(id).unwrap();
+    assert_eq!(object.transform.position, Vec3::new(0.0, 0.0, 5.0));
+    assert_eq!(scene.origin.to_absolute(object.transform.position), glam::DVec3::new(3000.0, 0.0, 5.0));
+    let (min, _) = object.world_bounds(&scene.assets).unwrap();
+    assert_eq!(min, Vec3::new(-1.0, -1.0, 4.0));
+});
+
+#[cfg(feature = "f64-transforms")]
+gpu_test!(test_scene_precise_transforms, |context: TestContext| {
+    use glam::DVec3;
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    // Ten thousand km out, where f32 steps are a whole meter
+    let far = DVec3::new(1.0e7 + 0.25, 0.0, 0.0);
+    let plain = scene.add_object(model, Transform { position: far.as_vec3(), ..Transform::new() });
+    let precise = scene.add_object(model, Transform::new());
+    assert!(scene.set_precise_transform(precise, PreciseTransform { position: far, ..PreciseTransform::new() }));
+
+    scene.camera.position = Vec3::new(1.0e7, 2.0, 0.0);
+    assert!(scene.update().is_some());
+    assert_eq!(scene.object(plain).unwrap().transform.position.x, 0.0);
+    assert_eq!(scene.object(precise).unwrap().transform.position.x, 0.25);
+    let matrix = scene.precise.matrix(precise, &scene.object(precise).unwrap().transform, &scene.origin);
+    assert_eq!(matrix.unwrap().w_axis.x, 0.25);
+
+    // Local edits are folded back in, keeping the precision
+    scene.transform_mut(precise).unwrap().position.x += 1.0;
+    assert!(scene.precise.matrix(precise, &scene.object(precise).unwrap().transform, &scene.origin).is_none());
+    scene.step(0.0);
+    assert_eq!(scene.precise_transform(precise).unwrap().position, DVec3::new(1.0e7 + 1.25, 0.0, 0.0));
+    assert_eq!(scene.precise_transform(plain).unwrap().position, DVec3::new(1.0e7, 0.0, 0.0));
+
+    assert!(scene.clear_precise_transform(precise));
+    assert!(scene.precise.is_empty());
+    scene.remove_object(plain);
+    assert!(!scene.set_precise_transform(plain, PreciseTransform::new()));
+});
+
+gpu_test!(test_scene_tweens, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let door = scene.add_object(model, Transform::new());
+    let lift = scene.add_object(model, Transform::new());
+
+    // Open, wait, close, while the lift rises and grows alongside
+    let swing = Animation::rotation(door, Vec3::new(0.0, std::f32::consts::FRAC_PI_2, 0.0), 1.0)
+        .then(Animation::wait(0.5))
+        .then(Animation::rotation(door, Vec3::ZERO, 1.0))
+        .eased(Easing::SineInOut);
+    let rise = Animation::parallel([
+        Animation::move_by(lift, Vec3::new(0.0, 4.0, 0.0), 2.0),
+        Animation::scale(lift, Vec3::splat(2.0), 1.0),
+    ]);
+    assert_eq!(swing.duration(), 2.5);
+    let finished = std::rc::Rc::new(std::cell::Cell::new(false));
+    let flag = finished.clone();
+    let id = scene.tweens.play_then(swing, move |scene| {
+        flag.set(true);
+        scene.tweens.play(Animation::position(door, Vec3::new(1.0, 0.0, 0.0), 0.0));
+    });
+    scene.tweens.play(rise);
+
+    scene.step(0.5);
+    let rotation = scene.object(door).unwrap().transform.rotation;
+    assert!((rotation.y - std::f32::consts::FRAC_PI_4).abs() < 1e-4, "{rotation}");
+    let lift_transform = scene.object(lift).unwrap().transform;
+    assert!((lift_transform.position.y - 1.0).abs() < 1e-5);
+    assert!((lift_transform.scale.x - 1.5).abs() < 1e-5);
+
+    // Leftover time carries into the next step of the sequence, so 1.5s in the door is shut again at 2.5
+    scene.step(1.0);
+    assert!((scene.object(door).unwrap().transform.rotation.y - std::f32::consts::FRAC_PI_2).abs() < 1e-4);
+    let lift_transform = scene.object(lift).unwrap().transform;
+    assert!((lift_transform.position.y - 3.0).abs() < 1e-5);
+    assert_eq!(lift_transform.scale, Vec3::splat(2.0));
+    assert_eq!(scene.tweens.len(), 2);
+
+    scene.step(1.0);
+    assert!(finished.get() && !scene.tweens.is_playing(id));
+    assert!(scene.object(door).unwrap().transform.rotation.abs().max_element() < 1e-5);
+    // The callback's tween runs on the next step
+    assert_eq!(scene.tweens.len(), 1);
+    scene.step(0.1);
+    assert_eq!(scene.object(door).unwrap().transform.position, Vec3::new(1.0, 0.0, 0.0));
+    assert!(scene.tweens.is_empty());
+
+    // Removing an object ends its tweens; stopping leaves the object where it is
+    let slide = scene.tweens.play(Animation::position(lift, Vec3::ZERO, 10.0));
+    scene.step(1.0);
+    assert!(scene.tweens.stop(slide) && !scene.tweens.stop(slide));
+    scene.tweens.play(Animation::position(lift, Vec3::ZERO, 10.0));
+    scene.remove_object(lift);
+    scene.step(0.1);
+    assert!(scene.tweens.is_empty());
+});
+
+gpu_test!(test_scene_pause_step, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let lift = scene.add_object(model, Transform::new());
+    scene.tweens.play(Animation::move_by(lift, Vec3::new(0.0, 10.0, 0.0), 10.0));
+    let height = |scene: &Scene| scene.object(lift).unwrap().transform.position.y;
+
+    scene.step(1.0);
+    assert!(scene.advanced() && (height(&scene) - 1.0).abs() < 1e-5);
+
+    // Paused, the tween holds still while the camera keeps moving
+    scene.set_paused(true);
+    scene.camera.moving_up = true;
+    scene.step(1.0);
+    assert!(!scene.advanced() && (height(&scene) - 1.0).abs() < 1e-5);
+    assert!(scene.camera.position.y > 2.0);
+    scene.camera.moving_up = false;
+
+    // Each queued step advances exactly one update
+    scene.step_once();
+    scene.step_once();
+    assert!(scene.advancing());
+    scene.step(1.0);
+    scene.step(1.0);
+    scene.step(1.0);
+    assert!((height(&scene) - 3.0).abs() < 1e-5);
+    assert!(!scene.advancing());
+
+    // Stepping does nothing unpaused, and resuming drops leftover steps
+    scene.step_once();
+    scene.set_paused(false);
+    scene.step(1.0);
+    assert!((height(&scene) - 4.0).abs() < 1e-5);
+    scene.step_once();
+    scene.set_paused(true);
+    assert!(!scene.advancing());
+});
+
+gpu_test!(test_scene_light_probes, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 10.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let slab = Transform { scale: Vec3::new(10.0, 0.5, 10.0), ..Transform::new() };
+    scene.add_object(model, Transform { position: Vec3::new(0.0, -1.0, 0.0), ..slab });
+    let roof = scene.add_object(model, Transform { position: Vec3::new(0.0, 3.0, 0.0), ..slab });
+    scene.directional_light = Color::BLACK;
+    scene.light_direction = Vec3::NEG_Y;
+    scene.update_bvh();
+    let settings = ProbeBakeSettings { sky: Some(Color::WHITE), ..ProbeBakeSettings::default() };
+    let baked = |scene: &Scene, point: Vec3, is_static: &dyn Fn(ObjectId) -> bool| {
+        let mut probes = ProbeGrid::new(point, point, glam::UVec3::ONE);
+        probes.bake(scene, &settings, is_static);
+        probes.sample(point)
+    };
+
+    // Out in the open nothing changes the flat sky light
+    let open = baked(&scene, Vec3::new(0.0, 1000.0, 0.0), &|_| true);
+    assert!(open.evaluate(Vec3::Y).abs_diff_eq(Vec3::ONE, 0.02), "{}", open.evaluate(Vec3::Y));
+
+    // Between the slabs the sky is mostly hidden, least so towards the open sides
+    let covered = baked(&scene, Vec3::new(0.0, 1.0, 0.0), &|_| true);
+    let (up, side) = (covered.evaluate(Vec3::Y).x, covered.evaluate(Vec3::X).x);
+    assert!(up < 0.7 && side > up, "{up} {side}");
+
+    // Dynamic objects are left out of the bake, and the sun bounces off lit surfaces
+    let no_roof = baked(&scene, Vec3::new(0.0, 1.0, 0.0), &|id| id != roof);
+    assert!(no_roof.evaluate(Vec3::Y).x > 0.95);
+    let above_roof = baked(&scene, Vec3::new(0.0, 5.0, 0.0), &|_| true);
+    scene.directional_light = Color::WHITE;
+    let sunlit = baked(&scene, Vec3::new(0.0, 5.0, 0.0), &|_| true);
+    assert!(sunlit.evaluate(Vec3::NEG_Y).x > above_roof.evaluate(Vec3::NEG_Y).x + 0.2);
+
+    // The grid moves with the floating origin
+    scene.probes = Some(ProbeGrid::new(Vec3::ZERO, Vec3::ONE, glam::UVec3::splat(2)));
+    scene.rebase(Vec3::splat(10.0));
+    assert_eq!(scene.probes.as_ref().unwrap().bounds(), (Vec3::splat(-10.0), Vec3::splat(-9.0)));
+});
+
+gpu_test!(test_scene_camera_rig, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 2.0, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let lift = scene.add_object(model, Transform::new());
+    assert!(scene.attach_camera(lift, false));
+    assert_eq!(scene.camera_parent(), Some(lift));
+
+    // Riding up with the lift, plus the camera's own movement
+    scene.tweens.play(Animation::move_by(lift, Vec3::new(0.0, 10.0, 0.0), 1.0));
+    scene.camera.moving_forward = true;
+    scene.step(0.5);
+    scene.camera.moving_forward = false;
+    assert!((scene.camera.position - Vec3::new(0.0, 7.0, -2.5)).length() < 1e-4, "{}", scene.camera.position);
+
+    // A turning carousel swings the camera around it and turns its view along
+    let carousel = scene.add_object(model, Transform::new());
+    scene.camera.position = Vec3::new(0.0, 0.0, -5.0);
+    scene.camera.yaw = -90.0;
+    assert!(scene.attach_camera(carousel, true));
+    scene.transform_mut(carousel).unwrap().rotation.y = -std::f32::consts::FRAC_PI_2;
+    scene.step(0.0);
+    assert!((scene.camera.position - Vec3::new(5.0, 0.0, 0.0)).length() < 1e-4, "{}", scene.camera.position);
+    assert!((scene.camera.yaw - 0.0).abs() < 1e-3, "{}", scene.camera.yaw);
+
+    // A floating-origin rebase moves both and doesn't carry the camera twice
+    scene.origin.rebase_distance = 100.0;
+    scene.transform_mut(carousel).unwrap().position.x = 1000.0;
+    assert!(scene.step(0.0).is_some());
+    let offset = scene.camera.position - scene.object(carousel).unwrap().transform.position;
+    assert!((offset - Vec3::new(5.0, 0.0, 0.0)).length() < 1e-3, "{}", offset);
+    let before = scene.camera.position;
+    scene.step(0.0);
+    assert!((scene.camera.position - before).length() < 1e-4);
+
+    // Removing the parent detaches the camera where it is
+    scene.remove_object(carousel);
+    scene.step(0.0);
+    assert_eq!(scene.camera_parent(), None);
+    assert!((scene.camera.position - before).length() < 1e-4);
+});
+
+gpu_test!(test_scene_anchors, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.6, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let panel = scene.add_object(model, Transform::new());
+    let tool = scene.add_object(model, Transform::new());
+    scene.anchors.attach(panel, Anchor::head(2.0, 0.0));
+    scene.anchors.attach(tool, Anchor::controller(AnchorTarget::RightController, Transform::new()));
+
+    // Without poses the camera is the head, and the untracked controller leaves the tool alone
+    scene.step(0.016);
+    assert!((scene.object(panel).unwrap().transform.position - Vec3::new(0.0, 1.6, -2.0)).length() < 1e-4);
+    assert_eq!(scene.object(tool).unwrap().transform.position, Vec3::ZERO);
+
+    let hand = Mat4::from_translation(Vec3::new(0.3, 1.0, -0.4));
+    scene.anchors.set_poses(AnchorPoses { right_controller: Some(hand), ..AnchorPoses::default() });
+    scene.step(0.016);
+    assert!((scene.object(tool).unwrap().transform.position - Vec3::new(0.3, 1.0, -0.4)).length() < 1e-5);
+
+    // Pinning to the world leaves the panel where it was when the head turns away
+    scene.anchors.attach(panel, Anchor::world());
+    scene.camera.yaw = 0.0;
+    scene.step(0.016);
+    assert!((scene.object(panel).unwrap().transform.position - Vec3::new(0.0, 1.6, -2.0)).length() < 1e-4);
+
+    scene.remove_object(tool);
+    scene.step(0.016);
+    assert!(scene.anchors.get(tool).is_none());
+});
+
+gpu_test!(test_scene_avatar, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.6, 0.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let forearm = scene.add_object(model, Transform { scale: Vec3::splat(0.1), ..Transform::new() });
+    let hand = scene.add_object(model, Transform::new());
+
+    // Without parts nothing is solved
+    scene.step(0.016);
+    assert!(scene.avatar.pose().is_none());
+
+    scene.avatar.parts = AvatarParts { right_forearm: Some(forearm), right_hand: Some(hand), ..AvatarParts::default() };
+    let controller = Mat4::from_translation(Vec3::new(0.2, 1.2, -0.4));
+    scene.anchors.set_poses(AnchorPoses { right_controller: Some(controller), ..AnchorPoses::default() });
+    scene.step(0.016);
+
+    // The hand is on the controller, and the forearm runs from the elbow to it keeping its scale
+    let arm = scene.avatar.pose().unwrap().right;
+    assert!((scene.object(hand).unwrap().transform.position - Vec3::new(0.2, 1.2, -0.4)).length() < 1e-4);
+    let transform = scene.object(forearm).unwrap().transform;
+    assert!((transform.position - arm.elbow).length() < 1e-5);
+    assert!((transform.scale - Vec3::splat(0.1)).length() < 1e-5);
+    let along = transform.to_matrix().transform_vector3(Vec3::Y).normalize();
+    assert!((along - (arm.hand - arm.elbow).normalize()).length() < 1e-4);
+});
+
+gpu_test!(test_scene_hierarchy, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    // A wheel two nodes below the body, under a mesh-less pivot
+    let node = |name: &str, parent, transform| Node { name: name.to_string(), parent, transform, meshes: vec![0] };
+    let nodes = [
+        node("body", None, Mat4::from_translation(Vec3::new(1.0, 0.0, 0.0))),
+        Node { meshes: Vec::new(), ..node("pivot", Some(0), Mat4::from_rotation_y(std::f32::consts::FRAC_PI_2)) },
+        node("wheel", Some(1), Mat4::from_translation(Vec3::new(0.0, 0.0, 2.0))),
+    ];
+    let root = Transform { position: Vec3::new(0.0, 1.0, 0.0), ..Transform::new() };
+    let objects = scene.add_node_hierarchy(&nodes, &[Some(model), None, Some(model)], &root);
+    let (body, wheel) = (objects[0].unwrap(), objects[2].unwrap());
+    assert_eq!(objects[1], None);
+    assert_eq!(scene.hierarchy.parent(wheel), Some(body));
+    assert!((scene.object(wheel).unwrap().transform.position - Vec3::new(3.0, 1.0, 0.0)).length() < 1e-5);
+
+    // Moving the body carries the wheel along
+    let index = scene.objects.index_of(body).unwrap();
+    scene.objects.transforms_mut()[index].position.z = -4.0;
+    scene.step(0.016);
+    assert!((scene.object(wheel).unwrap().transform.position - Vec3::new(3.0, 1.0, -4.0)).length() < 1e-5);
+
+    // Reparenting keeps the world pose; cycles and unknown ids are refused
+    let lamp = scene.add_object(model, Transform { position: Vec3::new(0.0, 2.0, 0.0), ..Transform::new() });
+    assert!(scene.set_parent(lamp, Some(wheel)));
+    assert!(!scene.set_parent(body, Some(lamp)));
+    assert!(!scene.set_parent(lamp, Some(ObjectId(999))));
+    scene.step(0.016);
+    assert!((scene.object(lamp).unwrap().transform.position - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-5);
+
+    // Removing a parent leaves its children where they are
+    assert!(scene.remove_object(wheel));
+    scene.step(0.016);
+    assert_eq!(scene.hierarchy.parent(lamp), None);
+    assert!(scene.set_parent(lamp, None));
+});
+
+gpu_test!(test_scene_remove_object, |context: TestContext| {
+    use crate::model::FRAMES_IN_FLIGHT;
+
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, -5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let first = scene.add_object(model, Transform::new());
+    let second = scene.add_object(model, Transform::new());
+
+    // Unloading only drops the registry's reference while objects still use the model
+    scene.assets.unload(model);
+    assert_eq!(scene.assets.model_refs(model), 2);
+    assert!(scene.remove_object(first));
+    assert!(!scene.remove_object(first));
+    assert!(scene.assets.model(model).is_some());
+    assert_eq!(scene.assets.pending_deletions(), 0);
+
+    // The last reference hands the buffers to the deletion queue
+    assert!(scene.remove_object(second));
+    assert!(scene.assets.model(model).is_none());
+    assert_eq!(scene.assets.model_count(), 0);
+    assert_eq!(scene.assets.pending_deletions(), 1);
+
+    // ...which waits for frames that may still reference them
+    for _ in 1..FRAMES_IN_FLIGHT {
+        scene.assets.end_frame();
+        assert_eq!(scene.assets.pending_deletions(), 1);
+    }
+    scene.assets.end_frame();
+    assert_eq!(scene.assets.pending_deletions(), 0);
+
+    // A new model reusing the slot isn't reachable through the stale handle
+    let replacement = scene.assets.add_model(test_model(&context.device));
+    assert_ne!(replacement, model);
+    assert!(scene.assets.model(model).is_none());
+    assert_eq!(scene.assets.model_refs(replacement), 1);
+});
+
+gpu_test!(test_scene_parallel_components, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, -5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let ids: Vec<_> = (0..1000)
+        .map(|i| {
+            let mut transform = Transform::new();
+            transform.position.x = i as f32;
+            scene.add_object(model, transform)
+        })
+        .collect();
+
+    scene.objects.par_update_transforms(|id, transform| transform.position.y = id.0 as f32 * 2.0);
+    scene.objects.par_update_visibility(|_, transform| transform.position.x < 500.0);
+    assert!(scene.objects.iter().all(|object| object.transform.position.y == object.transform.position.x * 2.0));
+    assert_eq!(scene.objects.visibility().iter().filter(|visible| **visible).count(), 500);
+
+    // Two systems on disjoint components at once
+    let ComponentsMut { ids: object_ids, transforms, visible } = scene.objects.components_mut();
+    rayon::join(
+        || transforms.par_iter_mut().for_each(|transform| transform.scale = Vec3::splat(2.0)),
+        || visible.par_iter_mut().zip(object_ids).for_each(|(visible, id)| *visible = id.0 % 2 == 0),
+    );
+    let object = scene.object(ids[3]).unwrap();
+    assert_eq!(object.transform.scale, Vec3::splat(2.0));
+    assert!(!object.visible);
+
+    // Removal keeps the arrays in step
+    assert!(scene.set_visible(ids[3], true));
+    assert!(scene.remove_object(ids[0]));
+    assert_eq!(scene.objects.len(), 999);
+    assert_eq!(scene.objects.get(2).unwrap().id, ids[3]);
+    assert!(scene.objects.visibility()[2]);
+    assert_eq!(scene.objects.transforms()[2].position.x, 3.0);
+});
+
+gpu_test!(test_scene_prefab_instances, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, -5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let prefab = Prefab::from_toml(r#"
+        name = "doorway"
+        [[nodes]]
+        name = "frame"
+        model = "frame.glb"
+        [[nodes]]
+        name = "door"
+        parent = "frame"
+        model = "door.glb"
+        position = [0.5, 0.0, 0.0]
+        [[lights]]
+        node = "door"
+        position = [0.0, 2.0, 0.0]
+    "#).unwrap();
+    let models = |path: &str| (path == "frame.glb").then_some(model);
+
+    // Two instances share the registered model; the door's model is unknown and skipped
+    let first = prefab.instantiate(&mut scene, &Transform::new(), &PrefabOverrides::default(), models);
+    let root = Transform { position: Vec3::new(4.0, 0.0, 0.0), ..Transform::new() };
+    let mut overrides = PrefabOverrides::default();
+    overrides.models.insert("door".to_string(), "frame.glb".to_string());
+    overrides.light_tint = Some(Color::linear(1.0, 0.0, 0.0));
+    let second = prefab.instantiate(&mut scene, &root, &overrides, models);
+
+    assert_eq!(first.objects.len(), 1);
+    assert_eq!(second.objects.len(), 2);
+    assert_eq!(scene.assets.model_refs(model), 4);
+    let door = scene.object(second.object("door").unwrap()).unwrap();
+    assert!(door.transform.position.abs_diff_eq(Vec3::new(4.5, 0.0, 0.0), 1e-5));
+    let light = scene.light_mut(second.lights[0]).unwrap();
+    assert!(light.position.abs_diff_eq(Vec3::new(4.5, 2.0, 0.0), 1e-5));
+    assert_eq!(light.color, Color::linear(1.0, 0.0, 0.0));
+
+    second.despawn(&mut scene);
+    assert_eq!(scene.objects.len(), 1);
+    assert_eq!(scene.lights.len(), 1);
+    assert_eq!(scene.assets.model_refs(model), 2);
+});
+
+#[cfg(feature = "scripting")]
+gpu_test!(test_scene_scripts, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, -5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    let id = scene.add_object(model, Transform::new());
+
+    let mut scripts = ScriptHost::new();
+    scripts.attach(id, r#"
+        fn on_init() { this["speed"] = 2.0; }
+        fn on_update(dt) { this.position = this.position + vec3(0.0, this["speed"] * dt, 0.0); }
+        fn on_event(name) {
+            if name == "KeyE" {
+                let hit = raycast(vec3(0.0, 1.0, -5.0), vec3(0.0, 0.0, 1.0));
+                this.spawn_instance(vec3(hit.distance, 0.0, 0.0));
+                this.play_animation("wave");
+            }
+        }
+    "#).unwrap();
+
+    scripts.update(&mut scene, 0.5);
+    assert_eq!(scene.object(id).unwrap().transform.position, Vec3::new(0.0, 1.0, 0.0));
+
+    // Scripts read the input snapshot without any windowing types
+    use crate::input::InputEvent;
+    let other = scene.add_object(model, Transform::new());
+    scripts.attach(other, r#"
+        fn on_update(dt) {
+            if key_down("KeyW") { this.position = vec3(0.0, 0.0, vr_action("trigger")); }
+        }
+    "#).unwrap();
+    let mut input = InputState::new();
+    input.begin_frame([
+        InputEvent::Key { key: Key::KeyW, pressed: true },
+        InputEvent::VrAction { name: "trigger".to_string(), value: 0.5 },
+    ]);
+    scripts.set_input(&input);
+    scripts.update(&mut scene, 0.0);
+    assert_eq!(scene.object(other).unwrap().transform.position, Vec3::new(0.0, 0.0, 0.5));
+    scene.remove_object(other);
+
+    // The ray hits the moved object's bounds, which reach from z = -1
+    scripts.dispatch_event(&mut scene, "KeyQ");
     assert_eq!(scene.objects.len(), 1);
+    scripts.dispatch_event(&mut scene, "KeyE");
+    assert_eq!(scene.objects.len(), 2);
+    assert_eq!(scene.objects.get(1).unwrap().model(), model);
+    assert_eq!(scene.objects.get(1).unwrap().transform.position, Vec3::new(4.0, 0.0, 0.0));
+    assert_eq!(scene.assets.model_refs(model), 3);
+    assert_eq!(scripts.take_animation_requests(), vec![AnimationRequest { object: id, name: "wave".to_string() }]);
+
+    // Runtime errors are logged without touching the scene
+    scripts.attach(id, "fn on_update(dt) { this.position = 1; }").unwrap();
+    scripts.update(&mut scene, 0.5);
+    assert_eq!(scene.object(id).unwrap().transform.position, Vec3::new(0.0, 1.0, 0.0));
+});
+
+gpu_test!(test_scene_placement, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 5.0, 10.0), 1.0));
+    let cube = scene.assets.add_model(test_model(&context.device));
+    let slab = |position: Vec3, scale: Vec3| Transform { position, scale, ..Transform::new() };
+    let floor = scene.add_object(cube, slab(Vec3::new(0.0, -0.5, 0.0), Vec3::new(10.0, 0.5, 10.0)));
+    let wall = scene.add_object(cube, slab(Vec3::new(3.0, 2.0, 0.0), Vec3::new(0.5, 2.0, 10.0)));
+    let crate_id = scene.add_object(cube, slab(Vec3::new(0.0, 5.0, 0.0), Vec3::ONE));
+    scene.assets.unload(cube);
+
+    let down = Ray::new(Vec3::new(2.2, 10.0, 0.0), Vec3::NEG_Y);
+    let hit = scene.raycast(&down, Some(crate_id)).unwrap();
+    assert_eq!((hit.object, hit.point, hit.normal), (floor, Vec3::new(2.2, 0.0, 0.0), Vec3::Y));
+    assert_eq!(scene.raycast(&Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y), None).unwrap().object, crate_id);
+
+    // Resting on the floor would put the crate half into the wall, so it's pushed clear
+    let transform = Placement::default().place(&scene, crate_id, &down).unwrap();
+    assert!((transform.position - Vec3::new(1.5, 1.0, 0.0)).length() < 1e-4);
+    let placed = Placement { depenetrate: false, ..Placement::default() }.place(&scene, crate_id, &down).unwrap();
+    assert!((placed.position - Vec3::new(2.2, 1.0, 0.0)).length() < 1e-4);
+
+    // Against the wall's face, standing out from it
+    let sideways = Ray::new(Vec3::new(-5.0, 2.0, 0.0), Vec3::X);
+    let aligned = Placement { align_to_normal: true, grid: Some(1.0), ..Placement::default() };
+    let transform = aligned.place(&scene, crate_id, &sideways).unwrap();
+    assert_eq!(scene.raycast(&sideways, Some(crate_id)).unwrap().object, wall);
+    assert!((transform.position - Vec3::new(1.5, 2.0, 0.0)).length() < 1e-4);
+
+    assert!(Placement::default().place(&scene, crate_id, &Ray::new(Vec3::ZERO, Vec3::Y)).is_none());
+});
+
+gpu_test!(test_scene_bvh, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 5.0, 10.0), 1.0));
+    let cube = scene.assets.add_model(test_model(&context.device));
+    let ids: Vec<_> = (0..400)
+        .map(|i| {
+            let position = Vec3::new((i % 20) as f32 * 4.0, 0.0, (i / 20) as f32 * -4.0);
+            scene.add_object(cube, Transform { position, ..Transform::new() })
+        })
+        .collect();
+    assert!(scene.bvh().is_none());
+    scene.step(0.0);
+    assert_eq!(scene.bvh().unwrap().len(), 400);
+
+    // The hierarchy finds what testing every object finds
+    let rays = [
+        Ray::new(Vec3::new(-10.0, 0.0, -12.0), Vec3::X),
+        Ray::new(Vec3::new(30.0, 20.0, -30.0), Vec3::new(0.3, -1.0, -0.2)),
+        Ray::new(Vec3::new(2.0, 0.0, 10.0), Vec3::NEG_Z),
+    ];
+    let hits: Vec<_> = rays.iter().map(|ray| scene.raycast(ray, None)).collect();
+    assert_eq!(hits[0].unwrap().object, ids[60]);
+    assert!(hits[2].is_none());
+    scene.transform_mut(ids[0]);
+    assert!(scene.bvh().is_none());
+    let linear: Vec<_> = rays.iter().map(|ray| scene.raycast(ray, None)).collect();
+    assert_eq!(hits, linear);
+
+    // Moved objects are refit, removed ones rebuilt away
+    scene.transform_mut(ids[60]).unwrap().position.y = 10.0;
+    scene.update_bvh();
+    assert_eq!(scene.raycast(&rays[0], None).unwrap().object, ids[61]);
+    assert_eq!(scene.raycast(&rays[0], Some(ids[61])).unwrap().object, ids[62]);
+    scene.remove_object(ids[61]);
+    scene.update_bvh();
+    assert_eq!(scene.bvh().unwrap().len(), 399);
+    assert_eq!(scene.raycast(&rays[0], None).unwrap().object, ids[62]);
+});
+
+gpu_test!(test_scene_spatial_queries, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 5.0, 10.0), 1.0));
+    let cube = scene.assets.add_model(test_model(&context.device));
+    let ids: Vec<_> = (0..400)
+        .map(|i| {
+            let position = Vec3::new((i % 20) as f32 * 4.0, 0.0, (i / 20) as f32 * -4.0);
+            scene.add_object(cube, Transform { position, ..Transform::new() })
+        })
+        .collect();
+    scene.update_bvh();
+
+    let queries = |scene: &Scene| (
+        scene.overlap_sphere(Vec3::new(2.0, 0.0, -2.0), 1.5),
+        scene.overlap_sphere(Vec3::new(4.0, 2.0, 0.0), 1.5),
+        scene.overlap_aabb(Vec3::new(3.5, -1.0, -8.5), Vec3::new(8.5, 0.0, -3.5)),
+        scene.nearest_object(Vec3::new(10.5, 0.0, 1.5), |_| true),
+        scene.nearest_object(Vec3::new(10.5, 0.0, 1.5), |id| id != ids[3]),
+    );
+    let found = queries(&scene);
+    assert_eq!(found.0, vec![ids[0], ids[1], ids[20], ids[21]]);
+    assert_eq!(found.1, vec![ids[1]]);
+    assert_eq!(found.2, vec![ids[21], ids[22], ids[41], ids[42]]);
+    assert_eq!(found.3.unwrap().0, ids[3]);
+    assert!((found.3.unwrap().1 - 0.5f32.hypot(0.5)).abs() < 1e-5);
+    assert_eq!(found.4.unwrap().0, ids[2]);
+    assert!(scene.overlap_sphere(Vec3::new(2.0, 0.0, 2.0), 0.5).is_empty());
+
+    // Testing every object finds the same
+    scene.transform_mut(ids[399]);
+    assert!(scene.bvh().is_none());
+    assert_eq!(queries(&scene), found);
+});
+
+gpu_test!(test_scene_occluders, |context: TestContext| {
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 10.0), 1.0));
+    let cube = scene.assets.add_model(test_model(&context.device));
+    let wall = scene.add_object(cube, Transform { scale: Vec3::new(4.0, 4.0, 0.5), ..Transform::new() });
+    let hidden = scene.add_object(cube, Transform { position: Vec3::new(0.0, 0.0, -6.0), ..Transform::new() });
+    let beside = scene.add_object(cube, Transform { position: Vec3::new(6.0, 0.0, -6.0), ..Transform::new() });
+    // Inside the wall's bounds, so the wall doesn't hide itself
+    scene.occluders.attach(wall, Occluder::cuboid(Vec3::splat(-0.9), Vec3::splat(0.9)));
+
+    let visible = |scene: &Scene| -> Vec<ObjectId> {
+        let view_projections = [scene.camera.build_view_projection_matrix()];
+        let culling = ViewCulling::new(&view_projections, false);
+        let view = ViewInfo { scene, view_projections: &view_projections, viewer: scene.camera.position, culling: &culling };
+        let mut ids: Vec<_> = FrustumVisibility.visible_objects(&view).collect();
+        ids.sort();
+        ids
+    };
+    // Partly behind the wall is still visible
+    assert_eq!(visible(&scene), vec![wall, beside]);
+    scene.occluders.enabled = false;
+    assert_eq!(visible(&scene), vec![wall, hidden, beside]);
+    scene.occluders.enabled = true;
+
+    // Occluders of hidden objects hide nothing, and go with their object
+    scene.set_visible(wall, false);
+    assert_eq!(visible(&scene), vec![hidden, beside]);
+    scene.remove_object(wall);
+    assert!(scene.occluders.is_empty());
 });
 
 #[test]
@@ -198,4 +946,1308 @@ gpu_test!(test_renderer_creation, |context: TestContext| {
     let _renderer = Renderer::new(&context.device, &context.queue, &config);
     // Just verify that we can create the renderer without panicking
     assert!(true);
-}); 
\ No newline at end of file
+}); 
+
+gpu_test!(test_renderer_capabilities, |context: TestContext| {
+    let (config, _) = test_target(&context.device);
+
+    let renderer = Renderer::new(&context.device, &context.queue, &config);
+    let capabilities = renderer.capabilities();
+    // The test device has no optional features and downlevel limits
+    assert!(capabilities.adapter.is_none());
+    assert!(!capabilities.multiview && !capabilities.timestamps);
+    assert_eq!(capabilities.push_constant_size, 0);
+    assert_eq!(capabilities.tier, QualityTier::Low);
+    let depth = capabilities.format(wgpu::TextureFormat::Depth32Float).unwrap();
+    assert!(depth.supported && depth.renderable);
+    assert!(!capabilities.format(wgpu::TextureFormat::Bc7RgbaUnormSrgb).unwrap().supported);
+
+    let with_adapter = Capabilities::new(&context.adapter, &context.device);
+    assert!(with_adapter.adapter.is_some());
+    assert_eq!(with_adapter.tier, QualityTier::Low);
+    assert!(with_adapter.to_string().contains("Rgba16Float"));
+});
+
+gpu_test!(test_request_device_downgrade, |context: TestContext| {
+    let wanted = capabilities::OPTIONAL_FEATURES.iter().fold(wgpu::Features::empty(), |all, (feature, _)| all | *feature);
+    let (device, _queue, downgrade) = capabilities::request_device(&context.adapter, "Downgraded Device", wanted, wgpu::Limits::default())
+        .block_on()
+        .unwrap();
+    // An adapter accepts the features it offers; the ones it lacks are recorded rather than
+    // failing the request
+    let offered = wanted & context.adapter.features();
+    assert_eq!(device.features() & wanted, offered);
+    assert_eq!(downgrade.features, wanted - offered);
+});
+
+gpu_test!(test_renderer_render_frame, |context: TestContext| {
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+
+    // Render once with reflections and once without to cover both post paths
+    for quality in [SsrQuality::High, SsrQuality::Off] {
+        renderer.post_mut().ssr_quality = quality;
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    }
+
+    // Auto-exposure compute pass and LUT grading
+    renderer.post_mut().grading = ColorGrading {
+        auto_exposure: true,
+        tonemapper: Tonemapper::Aces,
+        lut_strength: 1.0,
+        ..ColorGrading::default()
+    };
+    renderer.post_mut().set_lut(&context.device, &context.queue, &ColorLut::identity(8));
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    // Exposure adapts to the previous frame ahead of each frame, and skips the first after a
+    // resize, when there's none
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    renderer.resize(&context.device, &config);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_point_light_shadows, |context: TestContext| {
+    use crate::settings::{RendererSettings, ShadowQuality};
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    scene.add_object(model, Transform::new());
+
+    // More lights than can be shaded, near and far, with and without shadows
+    for i in 0..20 {
+        scene.add_light(PointLight {
+            position: Vec3::new(i as f32 * 3.0, 2.0, 0.0),
+            range: 8.0,
+            casts_shadows: i % 3 != 0,
+            ..PointLight::default()
+        });
+    }
+
+    // Enough frames for every LOD to refresh, then again after the atlas is reallocated
+    for _ in 0..4 {
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    }
+    let settings = RendererSettings { shadow_quality: ShadowQuality::High, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_section_cut, |context: TestContext| {
+    use crate::settings::RendererSettings;
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let model = scene.assets.add_model(test_model(&context.device));
+    scene.add_object(model, Transform::new());
+
+    // More planes than the shader takes, with caps and without
+    for normal in [Vec3::NEG_Z, Vec3::X, Vec3::NEG_Y, Vec3::new(1.0, 1.0, 0.0), Vec3::Y] {
+        scene.clipping.planes.push(ClipPlane::new(Vec3::splat(0.1), normal));
+    }
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    scene.clipping.caps = false;
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+
+    // The section pipeline is rebuilt along with the scene pipeline for MSAA
+    scene.clipping.caps = true;
+    let settings = RendererSettings { msaa_samples: 4, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+
+    // The fifth plane is ignored; rebasing moves the planes with the geometry
+    let inside = Vec3::new(0.5, 0.0, -0.5);
+    assert!(!scene.clipping.is_clipped(inside));
+    scene.rebase(Vec3::splat(10.0));
+    assert!(!scene.clipping.is_clipped(inside - Vec3::splat(10.0)));
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_double_sided, |context: TestContext| {
+    use crate::model::Material;
+    use crate::settings::{RendererSettings, ShadowQuality};
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let settings = RendererSettings { shadow_quality: ShadowQuality::Medium, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+
+    // A single-sided and a double-sided instance, lit by a shadowed point light
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let single = scene.assets.add_model(test_model(&context.device));
+    let mut model = test_model(&context.device);
+    model.materials.push(Material { double_sided: true, ..Material::new("leaf", None, None) });
+    let double = scene.assets.add_model(model);
+    scene.add_object(single, Transform::new());
+    scene.add_object(double, Transform::new());
+    scene.add_light(PointLight { position: Vec3::new(0.0, 2.0, 0.0), ..PointLight::default() });
+
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    scene.clipping.planes.push(ClipPlane::new(Vec3::ZERO, Vec3::NEG_Z));
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_shader_variants, |context: TestContext| {
+    use crate::model::Material;
+    use crate::settings::RendererSettings;
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    assert_eq!(renderer.shader_variant_count(), 1);
+
+    // Plain meshes share the base variant
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let plain = scene.assets.add_model(test_model(&context.device));
+    scene.add_object(plain, Transform::new());
+    scene.add_object(plain, Transform::new());
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 1);
+
+    // A vertex-colored, alpha-masked mesh compiles one more variant, once
+    let mut model = test_model(&context.device);
+    model.meshes[0].vertex_colors = true;
+    model.materials.push(Material { alpha_cutoff: Some(0.5), ..Material::new("fence", None, None) });
+    let features = ShaderFeatures::for_mesh(&model.meshes[0], model.materials.first(), false);
+    assert_eq!(features, ShaderFeatures::HAS_VERTEX_COLOR | ShaderFeatures::ALPHA_MASK);
+    let masked = scene.assets.add_model(model);
+    scene.add_object(masked, Transform::new());
+    for _ in 0..2 {
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+        assert_eq!(renderer.shader_variant_count(), 2);
+    }
+
+    // Changing MSAA drops the cache; variants come back as they're drawn
+    let settings = RendererSettings { msaa_samples: 4, ..renderer.settings().clone() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 1);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 2);
+
+    // Alpha-to-coverage is its own variant under MSAA, and falls back to the cutoff without
+    let fence = scene.assets.model_mut(masked).unwrap();
+    fence.materials[0].alpha_to_coverage = true;
+    let features = ShaderFeatures::for_mesh(&fence.meshes[0], fence.materials.first(), false);
+    assert!(features.contains(ShaderFeatures::ALPHA_MASK | ShaderFeatures::ALPHA_TO_COVERAGE));
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 3);
+    let settings = RendererSettings { msaa_samples: 1, ..renderer.settings().clone() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 2);
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_skinned_animation, |context: TestContext| {
+    use crate::model::{AnimationClip, Channel, Interpolation, Keyframes, SkinVertex, Skeleton};
+
+    let (config, view) = test_target(&context.device);
+
+    // One joint sliding a unit along X over a second
+    let mut model = test_model(&context.device);
+    model.meshes[0].skin_buffer = Some(context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Test Skin Buffer"),
+        contents: bytemuck::bytes_of(&SkinVertex { joints: [0; 4], weights: [1.0, 0.0, 0.0, 0.0] }),
+        usage: wgpu::BufferUsages::VERTEX,
+    }));
+    model.skeleton = Some(Skeleton {
+        nodes: vec![Node { name: "root".to_string(), parent: None, transform: Mat4::IDENTITY, meshes: vec![0] }],
+        joints: vec![0],
+        inverse_bind_matrices: vec![Mat4::IDENTITY],
+        clips: vec![AnimationClip::new("slide", vec![Channel {
+            node: 0,
+            interpolation: Interpolation::Linear,
+            times: vec![0.0, 1.0],
+            keyframes: Keyframes::Translation(vec![Vec3::ZERO, Vec3::X]),
+        }])],
+        root: Mat4::IDENTITY,
+    });
+
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let skinned = scene.assets.add_model(model);
+    let object = scene.add_object(skinned, Transform::new());
+    let rigid = scene.assets.add_model(test_model(&context.device));
+    let plain = scene.add_object(rigid, Transform::new());
+    assert!(!scene.play_animation(object, "walk", true));
+    assert!(!scene.play_animation(plain, "slide", true));
+    assert!(scene.play_animation(object, "slide", true));
+
+    let offset = |scene: &Scene| scene.skinning.joint_matrices(object).unwrap()[0].w_axis.x;
+    scene.step(0.5);
+    assert!((offset(&scene) - 0.5).abs() < 1e-5);
+    // Looping wraps around the end
+    scene.step(0.75);
+    assert!((offset(&scene) - 0.25).abs() < 1e-5);
+    // Stopped clips hold their pose
+    assert!(scene.skinning.stop(object).is_some());
+    scene.step(0.5);
+    assert!((offset(&scene) - 0.25).abs() < 1e-5);
+
+    // The posed object draws with the skinned variant, the plain one with the base variant
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(renderer.shader_variant_count(), 2);
+    context.device.poll(wgpu::Maintain::Wait);
+
+    assert!(scene.remove_object(object));
+    assert!(scene.skinning.is_empty());
+});
+
+gpu_test!(test_renderer_stereo_views, |context: TestContext| {
+    use crate::model::Material;
+    use crate::scene::RenderView;
+
+    let (config, left_view) = test_target(&context.device);
+    let (_, right_view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let plain = scene.assets.add_model(test_model(&context.device));
+    scene.add_object(plain, Transform::new());
+
+    // An alpha-masked object behind both eyes: culled, so its shader variant is never needed
+    let mut model = test_model(&context.device);
+    model.materials.push(Material { alpha_cutoff: Some(0.5), ..Material::new("fence", None, None) });
+    let masked = scene.assets.add_model(model);
+    let mut behind = Transform::new();
+    behind.position = Vec3::new(0.0, 1.0, 20.0);
+    scene.add_object(masked, behind);
+
+    let projection = scene.camera.build_projection_matrix();
+    let eyes = |ahead: f32| {
+        let eye = |x: f32, target| RenderView {
+            view: Mat4::look_at_rh(Vec3::new(x, 1.0, 5.0), Vec3::new(x, 1.0, 5.0 + ahead), Vec3::Y),
+            projection,
+            target,
+        };
+        [eye(-0.03, &left_view), eye(0.03, &right_view)]
+    };
+    let views = eyes(-1.0);
+    renderer.render_views(&context.device, &context.queue, &scene, &views).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+    assert_eq!(renderer.shader_variant_count(), 1);
+
+    // Shadows render once for both eyes; each eye gets its own scene and post passes
+    let latest = renderer.profiler().history().last().unwrap();
+    let count = |name: &str| latest.passes.iter().filter(|pass| pass.name == name).count();
+    assert_eq!(count("Shadow"), 1);
+    assert_eq!(count("Scene"), 2);
+    assert_eq!(count("Post"), 2);
+
+    // Turned around, the eyes see it
+    let views = eyes(1.0);
+    renderer.render_views(&context.device, &context.queue, &scene, &views).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+    assert_eq!(renderer.shader_variant_count(), 2);
+});
+
+gpu_test!(test_renderer_visibility, |context: TestContext| {
+    use crate::model::Material;
+
+    /// Draws only the objects it was given
+    struct Chosen(Vec<ObjectId>);
+
+    impl Visibility for Chosen {
+        fn visible_objects<'a>(&'a self, _view: &ViewInfo<'a>) -> Box<dyn Iterator<Item = ObjectId> + 'a> {
+            Box::new(self.0.iter().copied())
+        }
+    }
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let plain = scene.assets.add_model(test_model(&context.device));
+    let ahead = scene.add_object(plain, Transform::new());
+
+    // An alpha-masked object behind the camera; its shader variant shows whether it was drawn
+    let mut model = test_model(&context.device);
+    model.materials.push(Material { alpha_cutoff: Some(0.5), ..Material::new("fence", None, None) });
+    let masked = scene.assets.add_model(model);
+    let mut behind = Transform::new();
+    behind.position = Vec3::new(0.0, 1.0, 20.0);
+    let behind = scene.add_object(masked, behind);
+
+    let render = |renderer: &mut Renderer| {
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+        context.device.poll(wgpu::Maintain::Wait);
+        renderer.shader_variant_count()
+    };
+    // Frustum culled by default
+    assert_eq!(render(&mut renderer), 1);
+    assert_eq!(renderer.culling_stats(), CullingStats { objects: 2, drawn: 1, culled: 1, portal_culled: 0, view_draws: 1 });
+    renderer.set_visibility(Chosen(vec![ahead]));
+    assert_eq!(render(&mut renderer), 1);
+    // Drawn when the application says so, even out of view
+    renderer.set_visibility(Chosen(vec![behind]));
+    assert_eq!(render(&mut renderer), 2);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    renderer.set_visibility(AllVisible);
+    assert_eq!(render(&mut renderer), 2);
+});
+
+gpu_test!(test_renderer_eye_debug, |context: TestContext| {
+    use crate::scene::{EyeDebugMode, RenderView};
+
+    let (config, left_view) = test_target(&context.device);
+    let (_, right_view) = test_target(&context.device);
+    let (_, mirror, mirror_view) = readback_target(&context.device, config.format, config.width, config.height);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let projection = scene.camera.build_projection_matrix();
+    let eyes = |right_yaw: f32| {
+        let eye = |x: f32, yaw: f32, target| RenderView {
+            view: Mat4::from_rotation_y(-yaw) * Mat4::look_at_rh(Vec3::new(x, 1.0, 5.0), Vec3::new(x, 1.0, 4.0), Vec3::Y),
+            projection,
+            target,
+        };
+        [eye(-0.03, 0.0, &left_view), eye(0.03, right_yaw, &right_view)]
+    };
+    let read_atlas = |renderer: &Renderer| {
+        let atlas = renderer.eye_debug().texture().unwrap();
+        readback::read_texture_region(&context.device, &context.queue, atlas, TextureRegion::full(atlas)).unwrap()
+    };
+
+    // Off by default: nothing captured
+    renderer.render_views(&context.device, &context.queue, &scene, &eyes(0.0)).unwrap();
+    assert!(renderer.eye_debug().texture().is_none());
+
+    // A row per eye of 32x32 panels: color, depth, velocity
+    renderer.set_eye_debug_mode(EyeDebugMode::All);
+    renderer.render_views(&context.device, &context.queue, &scene, &eyes(0.0)).unwrap();
+    renderer.render_views(&context.device, &context.queue, &scene, &eyes(0.0)).unwrap();
+    let latest = renderer.profiler().history().last().unwrap();
+    assert_eq!(latest.passes.iter().filter(|pass| pass.name == "Eye Debug").count(), 2);
+    let atlas = read_atlas(&renderer);
+    assert_eq!((atlas.width, atlas.height), (96, 64));
+    for row in [4, 36] {
+        // The blue clear color above the grid, nothing in the depth buffer and no motion
+        let color = atlas.texel(16, row);
+        assert!(color[2] > color[1] && color[1] > color[0], "color panel {:?}", color);
+        assert_eq!(&atlas.texel(48, row)[..3], &[0, 0, 0]);
+        let velocity = atlas.texel(80, row);
+        assert!(velocity[0].abs_diff(velocity[2]) <= 2 && velocity[1].abs_diff(velocity[2]) <= 2, "velocity panel {:?}", velocity);
+    }
+
+    // Turning only the right eye shows sideways motion in its panel alone
+    renderer.render_views(&context.device, &context.queue, &scene, &eyes(0.1)).unwrap();
+    let atlas = read_atlas(&renderer);
+    let still = atlas.texel(80, 16);
+    let turned = atlas.texel(80, 48);
+    assert!(still[0].abs_diff(still[2]) <= 2);
+    assert!(turned[0].abs_diff(turned[2]) > 10, "turned eye's velocity {:?}", turned);
+
+    // The mirror shows the atlas letterboxed: 64x43 with bars above and below
+    renderer.render_eye_debug(&context.device, &context.queue, &mirror_view, config.width, config.height);
+    let shown = readback::read_texture_region(&context.device, &context.queue, &mirror, TextureRegion::full(&mirror)).unwrap();
+    assert_eq!(&shown.texel(32, 2)[..3], &[0, 0, 0]);
+    let color = shown.texel(5, 32);
+    assert!(color[2] > color[0], "mirrored color panel {:?}", color);
+
+    renderer.set_eye_debug_mode(EyeDebugMode::Off);
+    assert!(renderer.eye_debug().texture().is_none());
+});
+
+gpu_test!(test_renderer_compute_task, |context: TestContext| {
+    let (config, view) = test_target(&context.device);
+
+    let initial: Vec<u32> = (0..64).collect();
+    let values = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Values"),
+        contents: bytemuck::cast_slice(&initial),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+    let read = || -> Vec<u32> { readback::read_buffer(&context.device, &context.queue, &values).unwrap() };
+
+    let source = "
+        @group(0) @binding(0) var<storage, read_write> values: array<u32>;
+
+        @compute @workgroup_size(64)
+        fn double(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] *= 2u;
+        }
+    ";
+    let bindings = [ComputeBinding::Storage { buffer: &values, read_only: false }];
+    let task = ComputeTask::new(&context.device, "Double", source, "double", &bindings, [1, 1, 1]).unwrap();
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    let id = renderer.add_compute_task(ComputeStage::BeforeScene, task);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(read(), initial.iter().map(|value| value * 2).collect::<Vec<_>>());
+
+    // Disabled tasks are skipped
+    renderer.compute_task_mut(id).unwrap().enabled = false;
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(read()[1], 2);
+
+    // Bindings must keep the task's layout
+    let task = renderer.compute_task_mut(id).unwrap();
+    assert!(task.set_bindings(&context.device, &[ComputeBinding::Uniform(&values)]).is_err());
+    assert!(task.set_bindings(&context.device, &bindings).is_ok());
+    assert!(renderer.remove_compute_task(id).is_some());
+    assert!(renderer.compute_task_mut(id).is_none());
+
+    // Async tasks are submitted ahead of the frame, so later stages see their results
+    let increment = source.replace("fn double", "fn increment").replace("*= 2u", "+= 1u");
+    let task = ComputeTask::new(&context.device, "Increment", &increment, "increment", &bindings, [1, 1, 1]).unwrap();
+    let increment_id = renderer.add_compute_task(ComputeStage::Async, task);
+    let task = ComputeTask::new(&context.device, "Double", source, "double", &bindings, [1, 1, 1]).unwrap();
+    let double_id = renderer.add_compute_task(ComputeStage::BeforeShadows, task);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert_eq!(read()[1], 6);
+    renderer.remove_compute_task(increment_id);
+    renderer.remove_compute_task(double_id);
+
+    // Shader errors are returned, not just logged
+    let broken = ComputeTask::new(&context.device, "Broken", "fn double(", "double", &bindings, [1, 1, 1]);
+    assert!(broken.is_err());
+    let missing = ComputeTask::new(&context.device, "Missing", source, "triple", &bindings, [1, 1, 1]);
+    assert!(missing.is_err());
+});
+
+gpu_test!(test_readback_regions, |context: TestContext| {
+    // Rows of 3 texels are far from the 256-byte copy alignment
+    let texture = context.device.create_texture_with_data(
+        &context.queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Readback Texture"),
+            size: wgpu::Extent3d { width: 3, height: 2, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &(0..24).collect::<Vec<u8>>(),
+    );
+    let region = TextureRegion { x: 1, y: 0, width: 2, height: 2, ..TextureRegion::full(&texture) };
+    let data = readback::read_texture_region(&context.device, &context.queue, &texture, region).unwrap();
+    assert_eq!((data.width, data.height, data.bytes_per_texel), (2, 2, 4));
+    assert_eq!(data.texel(0, 0), &[4, 5, 6, 7]);
+    assert_eq!(data.texel(1, 1), &[20, 21, 22, 23]);
+    let outside = TextureRegion { x: 2, ..region };
+    assert!(readback::read_texture_region(&context.device, &context.queue, &texture, outside).is_err());
+
+    // Unaligned buffer ranges are widened for the copy and trimmed
+    let buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Readback Buffer"),
+        contents: &(0..16).collect::<Vec<u8>>(),
+        usage: wgpu::BufferUsages::COPY_SRC,
+    });
+    let bytes: Vec<u8> = readback::read_buffer_range(&context.device, &context.queue, &buffer, 3, 6).unwrap();
+    assert_eq!(bytes, vec![3, 4, 5, 6, 7, 8]);
+    let words: Vec<u32> = readback::read_buffer(&context.device, &context.queue, &buffer).unwrap();
+    assert_eq!(words.len(), 4);
+    assert_eq!(words[1], u32::from_le_bytes([4, 5, 6, 7]));
+    assert!(readback::read_buffer_range::<u8>(&context.device, &context.queue, &buffer, 12, 8).is_err());
+
+    let unreadable = context.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Vertex Only"),
+        size: 16,
+        usage: wgpu::BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    });
+    assert!(readback::read_buffer::<u32>(&context.device, &context.queue, &unreadable).is_err());
+});
+
+gpu_test!(test_renderer_picking, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex};
+
+    let (config, view) = test_target(&context.device);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A unit quad facing the camera with a larger one behind it
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: ModelVertex::WHITE,
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let model = Model::from_dynamic(mesh, Material::new("quad", None, None));
+
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let quad = scene.assets.add_model(model);
+    let front = scene.add_object(quad, Transform::new());
+    let mut transform = Transform::new();
+    transform.position = Vec3::new(0.0, 0.0, -2.0);
+    transform.scale = Vec3::splat(2.0);
+    let back = scene.add_object(quad, transform);
+    scene.assets.unload(quad);
+
+    let center = renderer.pick(32.0, 32.0);
+    let corner = renderer.pick(1.0, 1.0);
+    let through = renderer.pick_ray(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+    let beside = renderer.pick_ray(Vec3::new(1.5, 0.0, 5.0), Vec3::NEG_Z);
+    let miss = renderer.pick_ray(Vec3::new(0.0, 5.0, 5.0), Vec3::NEG_Z);
+    assert_eq!(renderer.picking().pending(), 5);
+
+    // Results arrive asynchronously once the frame's copies finish
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+    let mut results = renderer.poll_picks(&context.device);
+    results.sort_by_key(|result| result.id);
+    let objects: Vec<_> = results.iter().map(|result| (result.id, result.object)).collect();
+    assert_eq!(objects, vec![
+        (center, Some(front)),
+        (corner, None),
+        (through, Some(front)),
+        (beside, Some(back)),
+        (miss, None),
+    ]);
+    assert_eq!(renderer.picking().pending(), 0);
+
+    // Without picks or `enabled` the ID pass is skipped entirely
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    assert!(renderer.poll_picks(&context.device).is_empty());
+});
+
+gpu_test!(test_renderer_inspector, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex};
+
+    let (config, view) = test_target(&context.device);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A white quad facing the camera, half red through its vertex colors
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0, 0.5, 0.5, 1.0],
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let model = Model::from_dynamic(mesh, Material::new("quad", None, None));
+
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let quad = scene.assets.add_model(model);
+    let front = scene.add_object(quad, Transform::new());
+
+    let center = renderer.inspect(32.0, 32.0).unwrap();
+    let corner = renderer.inspect(1.0, 1.0).unwrap();
+    assert!(renderer.inspect(-10.0, 1.0).is_none());
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+    let mut results = renderer.poll_inspections(&context.device);
+    results.sort_by_key(|info| info.id);
+    assert_eq!(results.iter().map(|info| info.id).collect::<Vec<_>>(), vec![center, corner]);
+
+    let hit = &results[0];
+    assert_eq!(hit.object, Some(front));
+    assert!(hit.position.unwrap().length() < 0.1, "{}", hit);
+    assert!((hit.normal - Vec3::Z).length() < 0.01, "{}", hit);
+    assert!((hit.albedo - glam::Vec4::new(1.0, 0.5, 0.5, 1.0)).abs().max_element() < 0.02, "{}", hit);
+    assert!(hit.color.x > hit.color.y, "{}", hit);
+
+    let background = &results[1];
+    assert_eq!((background.object, background.position, background.depth), (None, None, 1.0));
+    assert_eq!(background.albedo, glam::Vec4::ZERO);
+    assert!(renderer.poll_inspections(&context.device).is_empty());
+});
+
+gpu_test!(test_renderer_light_probes, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex};
+
+    let (config, view) = test_target(&context.device);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A white quad facing the camera, lit by ambient light alone
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let model = Model::from_dynamic(mesh, Material::new("quad", None, None));
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let quad = scene.assets.add_model(model);
+    scene.add_object(quad, Transform::new());
+    scene.directional_light = Color::BLACK;
+
+    let center_color = |renderer: &mut Renderer, scene: &Scene| {
+        renderer.inspect(32.0, 32.0).unwrap();
+        renderer.render(&context.device, &context.queue, &view, scene).unwrap();
+        context.device.poll(wgpu::Maintain::Wait);
+        renderer.poll_inspections(&context.device)[0].color
+    };
+    let flat = center_color(&mut renderer, &scene);
+
+    // Probes lighting the quad's face brightly, then only its back
+    let mut probes = ProbeGrid::new(Vec3::splat(-5.0), Vec3::splat(5.0), glam::UVec3::ONE);
+    let front_lit = ShIrradiance { coefficients: [Vec3::splat(0.5), Vec3::ZERO, Vec3::ZERO, Vec3::splat(0.5)] };
+    probes.set_probe(glam::UVec3::ZERO, front_lit);
+    scene.probes = Some(probes.clone());
+    let lit = center_color(&mut renderer, &scene);
+    assert!(lit.x > flat.x * 5.0, "{lit} vs {flat}");
+
+    let back_lit = ShIrradiance { coefficients: [Vec3::splat(0.5), Vec3::ZERO, Vec3::ZERO, Vec3::splat(-0.5)] };
+    probes.set_probe(glam::UVec3::ZERO, back_lit);
+    scene.probes = Some(probes);
+    let dark = center_color(&mut renderer, &scene);
+    assert!(dark.x < flat.x * 0.1, "{dark} vs {flat}");
+});
+
+gpu_test!(test_renderer_stencil_overlays, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex};
+    use crate::settings::{DepthFormat, RendererSettings};
+
+    let (config, view) = test_target(&context.device);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // Two quads side by side, the left one marked
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let model = Model::from_dynamic(mesh, Material::new("quad", None, None));
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let quad = scene.assets.add_model(model);
+    let left = scene.add_object(quad, Transform { position: Vec3::new(-1.2, 0.0, 0.0), ..Transform::new() });
+    scene.add_object(quad, Transform { position: Vec3::new(1.2, 0.0, 0.0), ..Transform::new() });
+    scene.stencil.mark(left, 1);
+    scene.stencil.overlays.push(StencilOverlay::tint(1, Color::linear(1.0, 0.0, 0.0)));
+
+    let colors = |renderer: &mut Renderer, scene: &Scene| {
+        renderer.inspect(13.0, 32.0).unwrap();
+        renderer.inspect(51.0, 32.0).unwrap();
+        renderer.render(&context.device, &context.queue, &view, scene).unwrap();
+        context.device.poll(wgpu::Maintain::Wait);
+        let mut infos = renderer.poll_inspections(&context.device);
+        infos.sort_by_key(|info| info.id);
+        (infos[0].color, infos[1].color)
+    };
+
+    // Without a stencil the marks and overlays are ignored
+    let (plain_left, plain_right) = colors(&mut renderer, &scene);
+    assert!(plain_left.y > 0.1 && plain_left.z > 0.1, "{plain_left}");
+
+    let mut settings = RendererSettings { depth_format: DepthFormat::Depth24Stencil8, ..RendererSettings::default() };
+    let changes = renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert!(changes.depth_format);
+    let (tinted, right) = colors(&mut renderer, &scene);
+    assert_eq!(tinted, Vec3::X);
+    assert_eq!(right, plain_right);
+
+    // Masking everything outside the mark, multisampled
+    settings.msaa_samples = 4;
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    scene.stencil.overlays = vec![StencilOverlay::outside(1, Color::BLACK)];
+    let (unmasked, masked) = colors(&mut renderer, &scene);
+    // llvmpipe's GL driver loses the stencil of multisampled depth textures, so there only the
+    // pipelines are checked
+    if context.adapter.get_info().backend != wgpu::Backend::Gl {
+        assert!(unmasked.abs_diff_eq(plain_left, 0.01), "{unmasked} vs {plain_left}");
+        assert_eq!(masked, Vec3::ZERO);
+    }
+});
+
+gpu_test!(test_renderer_transparency, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex, Texture};
+    use crate::settings::RendererSettings;
+
+    let (config, view) = test_target(&context.device);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // Half-transparent red in front of half-transparent green, over black, lit so the scene
+    // color is the base color
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let quad_model = |color: Color| {
+        let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+        let mut material = Material {
+            base_color: color.with_alpha(0.5),
+            transparent: true,
+            ..Material::new("glass", Some(Texture::white(&context.device, &context.queue)), None)
+        };
+        material.create_bind_group(&context.device, renderer.material_bind_group_layout());
+        Model::from_dynamic(mesh, material)
+    };
+    let build = |red_first: bool| {
+        let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+        scene.clear_color = Color::BLACK;
+        scene.directional_light = Color::BLACK;
+        // Cancels the sky occlusion of surfaces facing the camera
+        scene.ambient_light = Color::linear(1.25, 1.25, 1.25);
+        let red = scene.assets.add_model(quad_model(Color::linear(1.0, 0.0, 0.0)));
+        let green = scene.assets.add_model(quad_model(Color::linear(0.0, 1.0, 0.0)));
+        let front = Transform::new();
+        let back = Transform { position: Vec3::new(0.0, 0.0, -1.0), ..Transform::new() };
+        if red_first {
+            scene.add_object(red, front);
+            scene.add_object(green, back);
+        } else {
+            scene.add_object(green, back);
+            scene.add_object(red, front);
+        }
+        scene
+    };
+
+    let color = |renderer: &mut Renderer, scene: &Scene| {
+        renderer.inspect(32.0, 32.0).unwrap();
+        renderer.render(&context.device, &context.queue, &view, scene).unwrap();
+        context.device.poll(wgpu::Maintain::Wait);
+        renderer.poll_inspections(&context.device)[0].color
+    };
+
+    // Sorted: green over black, then red over that, whatever order they were added in
+    let (mut first, mut second) = (build(true), build(false));
+    let sorted = color(&mut renderer, &first);
+    assert!(sorted.abs_diff_eq(Vec3::new(0.5, 0.25, 0.0), 0.01), "{sorted}");
+    assert!(color(&mut renderer, &second).abs_diff_eq(sorted, 1e-3));
+
+    // Weighted blended: both layers cover 75% between them, the nearer one weighted a little more
+    first.transparency = Transparency::WeightedBlended;
+    second.transparency = Transparency::WeightedBlended;
+    let blended = color(&mut renderer, &first);
+    assert!((blended.x + blended.y - 0.75).abs() < 0.02 && blended.x > blended.y && blended.z < 0.01, "{blended}");
+    assert!(color(&mut renderer, &second).abs_diff_eq(blended, 1e-3));
+
+    // Multisampled, accumulated at the scene's sample count and resolved
+    let settings = RendererSettings { msaa_samples: 4, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    let multisampled = color(&mut renderer, &first);
+    // llvmpipe's GL driver reads multisampled scenes back black, so there only the pipelines
+    // and targets are checked
+    if context.adapter.get_info().backend != wgpu::Backend::Gl {
+        assert!(multisampled.abs_diff_eq(blended, 0.02), "{multisampled} vs {blended}");
+    }
+});
+
+gpu_test!(test_renderer_motion_blur, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex, Texture};
+    use crate::settings::RendererSettings;
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8Unorm, 64, 64);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A white quad over black, moving sideways between frames
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-0.5, -0.5), vertex(0.5, -0.5), vertex(0.5, 0.5), vertex(-0.5, 0.5)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let mut material = Material::new("white", Some(Texture::white(&context.device, &context.queue)), None);
+    material.create_bind_group(&context.device, renderer.material_bind_group_layout());
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    scene.clear_color = Color::BLACK;
+    scene.directional_light = Color::BLACK;
+    scene.ambient_light = Color::linear(1.25, 1.25, 1.25);
+    let model = scene.assets.add_model(Model::from_dynamic(mesh, material));
+    let id = scene.add_object(model, Transform::new());
+
+    // Brightness at the center after moving the quad there from the left
+    let center = |renderer: &mut Renderer, scene: &mut Scene, stereo: bool| {
+        scene.transform_mut(id).unwrap().position.x = -2.0;
+        renderer.render(&context.device, &context.queue, &view, scene).unwrap();
+        scene.transform_mut(id).unwrap().position.x = 0.0;
+        let eye = || RenderView::from_camera(&scene.camera, &view);
+        let views = if stereo { vec![eye(), eye()] } else { vec![eye()] };
+        renderer.render_views(&context.device, &context.queue, scene, &views).unwrap();
+        let region = TextureRegion { x: 32, y: 32, width: 1, height: 1, ..TextureRegion::full(&target) };
+        readback::read_texture_region(&context.device, &context.queue, &target, region).unwrap().texel(0, 0)[0]
+    };
+
+    let mut settings = RendererSettings { show_grid: false, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    let sharp = center(&mut renderer, &mut scene, false);
+    assert!(sharp > 200, "{sharp}");
+
+    // Blurred along its motion, the quad's middle mixes in the background, with and without
+    // SSR, which the pass ping-pongs with
+    for ssr in [SsrQuality::Medium, SsrQuality::Off] {
+        settings.post.ssr = ssr;
+        settings.post.motion_blur = MotionBlur { enabled: true, shutter_angle: 360.0, max_length: 0.5, ..MotionBlur::default() };
+        renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+        let blurred = center(&mut renderer, &mut scene, false);
+        assert!(blurred < sharp - 40, "{ssr:?}: {blurred} vs {sharp}");
+
+        // Headset views are never blurred
+        assert_eq!(center(&mut renderer, &mut scene, true), sharp, "{ssr:?}");
+    }
+
+    // Only the object moved, so without its share of the blur it stays sharp
+    settings.post.motion_blur.objects = 0.0;
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert_eq!(center(&mut renderer, &mut scene, false), sharp);
+
+    // Standing still, nothing blurs
+    settings.post.motion_blur.objects = 1.0;
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    let region = TextureRegion { x: 32, y: 32, width: 1, height: 1, ..TextureRegion::full(&target) };
+    let still = readback::read_texture_region(&context.device, &context.queue, &target, region).unwrap().texel(0, 0)[0];
+    assert_eq!(still, sharp);
+});
+
+gpu_test!(test_renderer_wind, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex, Texture, Wind, WindWeight};
+    use crate::settings::RendererSettings;
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8Unorm, 64, 64);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let settings = RendererSettings { show_grid: false, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+
+    // A white blade of grass over black, rooted at the origin
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let blade = [vertex(-0.3, 0.0), vertex(0.3, 0.0), vertex(0.3, 1.5), vertex(-0.3, 1.5)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "blade", &blade, &[0, 1, 2, 0, 2, 3], 0);
+    let mut material = Material::new("grass", Some(Texture::white(&context.device, &context.queue)), None);
+    material.create_bind_group(&context.device, renderer.material_bind_group_layout());
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.75, 5.0), 1.0));
+    scene.clear_color = Color::BLACK;
+    scene.directional_light = Color::BLACK;
+    scene.ambient_light = Color::linear(1.25, 1.25, 1.25);
+    let model = scene.assets.add_model(Model::from_dynamic(mesh, material));
+    scene.add_object(model, Transform::new());
+
+    let frame = |renderer: &mut Renderer, scene: &Scene| {
+        renderer.render(&context.device, &context.queue, &view, scene).unwrap();
+        readback::read_texture_region(&context.device, &context.queue, &target, TextureRegion::full(&target)).unwrap()
+    };
+    // Mean column of the blade's pixels in a row
+    let centroid = |image: &readback::TextureData, y: u32| {
+        let columns: Vec<u32> = (0..image.width).filter(|&x| image.texel(x, y)[0] > 128).collect();
+        (!columns.is_empty()).then(|| columns.iter().sum::<u32>() as f32 / columns.len() as f32)
+    };
+
+    // Still air: time passing changes nothing
+    let still = frame(&mut renderer, &scene);
+    scene.step(1.0);
+    assert!(frame(&mut renderer, &scene).data == still.data);
+    let variants = renderer.shader_variant_count();
+    let rows: Vec<u32> = (0..still.height).filter(|&y| centroid(&still, y).is_some()).collect();
+    let (tip, root) = (rows[1], rows[rows.len() - 1]);
+
+    let wind = Wind { direction: glam::Vec2::X, strength: 0.5, gust_frequency: 0.25, weight: WindWeight::Height(1.5) };
+    scene.assets.model_mut(model).unwrap().materials[0].set_wind(&context.queue, Some(wind));
+    let before = frame(&mut renderer, &scene);
+    assert_eq!(renderer.shader_variant_count(), variants + 1, "Wind compiles its own variant");
+    scene.step(1.0);
+    let after = frame(&mut renderer, &scene);
+    let sway = |a: &readback::TextureData, b: &readback::TextureData, y: u32| {
+        centroid(a, y).unwrap_or(f32::NAN) - centroid(b, y).unwrap_or(f32::NAN)
+    };
+    // Leaning downwind, the tip sways with the gusts while the root stays put
+    assert!(sway(&before, &still, tip) > 1.0, "{}", sway(&before, &still, tip));
+    assert!(sway(&after, &before, tip).abs() > 1.0, "{}", sway(&after, &before, tip));
+    assert!(sway(&after, &before, root).abs() < 0.5, "{}", sway(&after, &before, root));
+
+    // Paused scenes freeze the sway
+    scene.set_paused(true);
+    scene.step(1.0);
+    assert_eq!(scene.time(), 2.0);
+    assert!(frame(&mut renderer, &scene).data == after.data);
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_lens_preview, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex, Texture};
+    use crate::settings::{LensPreviewSettings, RendererSettings};
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8Unorm, 64, 64);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A white wall filling the view
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-20.0, -20.0), vertex(20.0, -20.0), vertex(20.0, 20.0), vertex(-20.0, 20.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "wall", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let mut material = Material::new("white", Some(Texture::white(&context.device, &context.queue)), None);
+    material.create_bind_group(&context.device, renderer.material_bind_group_layout());
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    scene.clear_color = Color::BLACK;
+    scene.directional_light = Color::BLACK;
+    scene.ambient_light = Color::linear(1.25, 1.25, 1.25);
+    let model = scene.assets.add_model(Model::from_dynamic(mesh, material));
+    scene.add_object(model, Transform::new());
+
+    // Red of the center and a corner texel
+    let read = |renderer: &mut Renderer, stereo: bool| {
+        let eye = || RenderView::from_camera(&scene.camera, &view);
+        let views = if stereo { vec![eye(), eye()] } else { vec![eye()] };
+        renderer.render_views(&context.device, &context.queue, &scene, &views).unwrap();
+        let data = readback::read_texture_region(&context.device, &context.queue, &target, TextureRegion::full(&target)).unwrap();
+        (data.texel(32, 32)[0], data.texel(1, 1)[0])
+    };
+
+    let mut settings = RendererSettings { show_grid: false, ..RendererSettings::default() };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    let (center, corner) = read(&mut renderer, false);
+    assert!(center > 200 && corner > 200, "{center} {corner}");
+
+    // Through the lens the corners fall outside the image, while the middle still shows the wall
+    let dir = tempfile::tempdir().unwrap();
+    let presets = dir.path().join("lenses.toml");
+    std::fs::write(&presets, "[strong]\nk1 = 0.5\nk2 = 0.5\nchromatic_aberration = 0.02\n").unwrap();
+    settings.post.lens_preview = LensPreviewSettings { enabled: true, preset: "strong".to_string(), presets };
+    renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert_eq!(read(&mut renderer, false), (center, 0));
+
+    // Headsets have lenses of their own
+    assert_eq!(read(&mut renderer, true), (center, corner));
+
+    // An unknown preset is an error, and the previous one stays
+    let mut unknown = settings.clone();
+    unknown.post.lens_preview.preset = "missing".to_string();
+    assert!(renderer.apply_settings(&context.device, &context.queue, &config, &unknown).is_err());
+    assert_eq!(renderer.settings().post.lens_preview.preset, "strong");
+    assert_eq!(read(&mut renderer, false), (center, 0));
+});
+
+gpu_test!(test_renderer_apply_settings, |context: TestContext| {
+    use crate::settings::{RendererSettings, ShadowQuality};
+
+    let (config, view) = test_target(&context.device);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    let scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+
+    let mut settings = RendererSettings {
+        shadow_quality: ShadowQuality::High,
+        msaa_samples: 4,
+        resolution_scale: 0.5,
+        ..RendererSettings::default()
+    };
+    settings.post.ssr = SsrQuality::Low;
+
+    let changes = renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert!(changes.shadows && changes.msaa && changes.resolution && changes.post);
+    assert!(!changes.lut);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+
+    // Re-applying identical settings rebuilds nothing
+    let changes = renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert!(!changes.any());
+
+    // A missing LUT is reported but the other settings still take effect
+    settings.shadow_quality = ShadowQuality::Off;
+    settings.post.lut = Some(std::path::PathBuf::from("does/not/exist.png"));
+    assert!(renderer.apply_settings(&context.device, &context.queue, &config, &settings).is_err());
+    assert_eq!(renderer.settings().shadow_quality, ShadowQuality::Off);
+    assert!(renderer.settings().post.lut.is_none());
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    context.device.poll(wgpu::Maintain::Wait);
+});
+
+gpu_test!(test_renderer_grid_toggle, |context: TestContext| {
+    use crate::settings::RendererSettings;
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8UnormSrgb, 64, 64);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    // Looking down at the empty ground plane
+    let mut camera = Camera::new(Vec3::new(0.0, 3.0, 5.0), 1.0);
+    camera.pitch = -30.0;
+    let scene = Scene::new(camera);
+
+    let render = |renderer: &mut Renderer| -> Vec<u8> {
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+        readback::read_texture_region(&context.device, &context.queue, &target, TextureRegion::full(&target))
+            .unwrap()
+            .data
+    };
+
+    let with_grid = render(&mut renderer);
+
+    let settings = RendererSettings {
+        show_grid: false,
+        ..renderer.settings().clone()
+    };
+    let changes = renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert!(changes.grid && !changes.msaa);
+    assert!(!renderer.grid().enabled);
+    let without_grid = render(&mut renderer);
+
+    let changed = with_grid.chunks(4).zip(without_grid.chunks(4)).filter(|(a, b)| a != b).count();
+    assert!(changed > 0, "grid should be visible below the horizon");
+});
+
+gpu_test!(test_renderer_letterbox, |context: TestContext| {
+    use crate::scene::Viewport;
+    use crate::settings::{RendererSettings, ViewportMode};
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8UnormSrgb, 64, 32);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    assert_eq!(renderer.viewport(), Viewport::full(64, 32));
+
+    // A square viewport in a 2:1 surface leaves bars at the sides
+    let settings = RendererSettings {
+        viewport: ViewportMode::Aspect { ratio: 1.0 },
+        ..renderer.settings().clone()
+    };
+    let changes = renderer.apply_settings(&context.device, &context.queue, &config, &settings).unwrap();
+    assert!(changes.resolution);
+    assert_eq!(renderer.viewport(), Viewport { x: 16, y: 0, width: 32, height: 32 });
+
+    // Looking down at the grid so the viewport isn't black
+    let mut camera = Camera::new(Vec3::new(0.0, 3.0, 5.0), renderer.viewport().aspect());
+    camera.pitch = -30.0;
+    let scene = Scene::new(camera);
+    renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    let image = readback::read_texture_region(&context.device, &context.queue, &target, TextureRegion::full(&target)).unwrap();
+    assert_eq!(image.texel(2, 16), &[0, 0, 0, 255]);
+    assert_eq!(image.texel(61, 16), &[0, 0, 0, 255]);
+    assert_ne!(image.texel(32, 16), &[0, 0, 0, 255]);
+
+    // Picks over the bars resolve to nothing without waiting on the GPU
+    let bar = renderer.pick(4.0, 16.0);
+    assert_eq!(renderer.poll_picks(&context.device), vec![PickResult { id: bar, object: None }]);
+});
+
+gpu_test!(test_renderer_window_alpha, |context: TestContext| {
+    use crate::model::{DynamicMesh, Material, ModelVertex};
+    use crate::scene::OutputAlpha;
+    use crate::settings::{RendererSettings, ViewportMode};
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8UnormSrgb, 64, 32);
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+
+    // A quad in the middle of a square viewport, with bars at the sides
+    let vertex = |x: f32, y: f32| ModelVertex {
+        position: [x, y, 0.0],
+        tex_coords: [0.0; 2],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [1.0, 0.0, 0.0, 1.0],
+        color: [1.0; 4],
+    };
+    let quad = [vertex(-1.0, -1.0), vertex(1.0, -1.0), vertex(1.0, 1.0), vertex(-1.0, 1.0)];
+    let mesh = DynamicMesh::new(&context.device, &context.queue, "quad", &quad, &[0, 1, 2, 0, 2, 3], 0);
+    let model = Model::from_dynamic(mesh, Material::new("quad", None, None));
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 0.0, 5.0), 1.0));
+    let quad = scene.assets.add_model(model);
+    scene.add_object(quad, Transform::new());
+
+    let mut settings = RendererSettings {
+        viewport: ViewportMode::Aspect { ratio: 1.0 },
+        show_grid: false,
+        ..RendererSettings::default()
+    };
+    let render = |renderer: &mut Renderer, settings: &RendererSettings| {
+        renderer.apply_settings(&context.device, &context.queue, &config, settings).unwrap();
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+        let image = readback::read_texture_region(&context.device, &context.queue, &target, TextureRegion::full(&target)).unwrap();
+        // Bar, background and quad
+        [(2, 16), (18, 2), (32, 16)].map(|(x, y)| <[u8; 4]>::try_from(image.texel(x, y)).unwrap())
+    };
+
+    let [bar, background, opaque_quad] = render(&mut renderer, &settings);
+    assert_eq!(bar, [0, 0, 0, 255]);
+    assert_eq!(background[3], 255);
+    assert_ne!(&background[..3], &[0, 0, 0]);
+    assert_eq!(opaque_quad[3], 255);
+
+    // Transparent windows see through everything but the quad
+    for alpha in [OutputAlpha::PreMultiplied, OutputAlpha::PostMultiplied] {
+        settings.window_alpha = alpha;
+        let [bar, background, quad] = render(&mut renderer, &settings);
+        assert_eq!(bar, [0, 0, 0, 0], "{alpha:?}");
+        assert_eq!(background, [0, 0, 0, 0], "{alpha:?}");
+        assert_eq!(quad, opaque_quad, "{alpha:?}");
+    }
+});
+
+gpu_test!(test_profiler_hud, |context: TestContext| {
+    use crate::scene::DebugOverlay;
+
+    let (config, target, view) = readback_target(&context.device, wgpu::TextureFormat::Rgba8UnormSrgb, 400, 300);
+
+    let mut renderer = Renderer::new(&context.device, &context.queue, &config);
+    renderer.set_overlay_enabled(true);
+    let scene = Scene::new(Camera::new(Vec3::new(0.0, 1.0, 5.0), 1.0));
+    for _ in 0..4 {
+        renderer.render(&context.device, &context.queue, &view, &scene).unwrap();
+    }
+    context.device.poll(wgpu::Maintain::Wait);
+
+    assert_eq!(renderer.profiler().history().len(), 4);
+    let latest = renderer.profiler().latest().unwrap();
+    let names: Vec<(&str, usize)> = latest.passes.iter().map(|pass| (pass.name, pass.depth)).collect();
+    assert!(names.contains(&("Scene", 0)));
+    assert!(names.contains(&("Post", 0)));
+    assert!(names.contains(&("Grading", 1)), "post passes nest under Post: {:?}", names);
+    assert!(names.contains(&("Overlay", 0)));
+    assert!(renderer.profiler().report().contains("\n    Grading"));
+
+    // Geometry stays on screen and includes the worst-frame and log markers
+    let log = [crate::diagnostics::LogEntry {
+        time: std::time::Instant::now(),
+        level: log::Level::Error,
+        target: "wgpu".to_string(),
+        message: "test".to_string(),
+        repeats: 0,
+    }];
+    let vertices = DebugOverlay::build_vertices(renderer.profiler(), &log, config.width, config.height);
+    assert!(!vertices.is_empty());
+    assert!(vertices.iter().all(|v| v.position.iter().all(|c| (-1.0..=1.0).contains(c))));
+    assert!(vertices.iter().any(|v| v.color == [1.0, 0.1, 0.1, 1.0]));
+    assert!(vertices.iter().any(|v| v.color == [1.0, 0.2, 0.2, 1.0]));
+});
+
+gpu_test!(test_error_scope_logging, |context: TestContext| {
+    use crate::diagnostics;
+
+    diagnostics::init();
+
+    // A bind group with an entry its layout doesn't declare is a validation error
+    let layout = context.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Empty Layout"),
+        entries: &[],
+    });
+    let buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Stray Buffer"),
+        size: 16,
+        usage: wgpu::BufferUsages::UNIFORM,
+        mapped_at_creation: false,
+    });
+    for _ in 0..3 {
+        diagnostics::scoped(&context.device, "material 'broken'", || {
+            context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Broken Bind Group"),
+                layout: &layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            })
+        });
+    }
+
+    // Captured with its context, and the repeats were folded into one entry
+    let entries: Vec<_> = diagnostics::recent(diagnostics::LOG_CAPACITY).into_iter()
+        .filter(|entry| entry.message.starts_with("material 'broken'"))
+        .collect();
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert_eq!(entries[0].level, log::Level::Error);
+    assert_eq!(entries[0].target, "wgpu");
+    assert_eq!(entries[0].repeats, 2);
+});
+
+gpu_test!(test_noise_matches_cpu, |context: TestContext| {
+    use crate::noise::{self, Fbm, NoiseKind};
+
+    // Points over several cells, negative coordinates included
+    let points: Vec<Vec3> = (0..64)
+        .map(|index| {
+            let t = index as f32;
+            Vec3::new((t * 0.731).sin() * 7.3, (t * 0.377).cos() * 5.1 - 0.4, t * 0.153 - 4.9)
+        })
+        .collect();
+    let inputs: Vec<[f32; 4]> = points.iter().map(|p| p.extend(0.0).to_array()).collect();
+    let input_buffer = context.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Noise Points"),
+        contents: bytemuck::cast_slice(&inputs),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    const FUNCTIONS: usize = 12;
+    let output_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Noise Values"),
+        size: (points.len() * FUNCTIONS * 4) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+
+    let source = crate::shaders::resolve("
+        #include \"noise\"
+
+        @group(0) @binding(0) var<storage, read> points: array<vec4<f32>>;
+        @group(0) @binding(1) var<storage, read_write> values: array<f32>;
+
+        @compute @workgroup_size(64)
+        fn sample(@builtin(global_invocation_id) id: vec3<u32>) {
+            let p = points[id.x].xyz;
+            let base = id.x * 12u;
+            values[base] = perlin2(p.xy, 5u);
+            values[base + 1u] = perlin3(p, 5u);
+            values[base + 2u] = simplex2(p.xy, 5u);
+            values[base + 3u] = simplex3(p, 5u);
+            values[base + 4u] = worley2(p.xy, 5u);
+            values[base + 5u] = worley3(p, 5u);
+            values[base + 6u] = fbm_perlin2(p.xy * 0.5, 9u, 5u, 2.0, 0.5);
+            values[base + 7u] = fbm_perlin3(p * 0.5, 9u, 5u, 2.0, 0.5);
+            values[base + 8u] = fbm_simplex2(p.xy * 0.5, 9u, 5u, 2.0, 0.5);
+            values[base + 9u] = fbm_simplex3(p * 0.5, 9u, 5u, 2.0, 0.5);
+            values[base + 10u] = fbm_worley2(p.xy * 0.5, 9u, 5u, 2.0, 0.5);
+            values[base + 11u] = fbm_worley3(p * 0.5, 9u, 5u, 2.0, 0.5);
+        }
+    ").unwrap().source;
+    let bindings = [
+        ComputeBinding::Storage { buffer: &input_buffer, read_only: true },
+        ComputeBinding::Storage { buffer: &output_buffer, read_only: false },
+    ];
+    let task = ComputeTask::new(&context.device, "Noise", &source, "sample", &bindings, [1, 1, 1]).unwrap();
+    let mut encoder = context.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    task.encode(&mut encoder);
+    context.queue.submit(Some(encoder.finish()));
+    let values: Vec<f32> = readback::read_buffer(&context.device, &context.queue, &output_buffer).unwrap();
+
+    let fbm = |kind| Fbm { kind, seed: 9, frequency: 0.5, octaves: 5, lacunarity: 2.0, gain: 0.5 };
+    for (p, gpu) in points.iter().zip(values.chunks_exact(FUNCTIONS)) {
+        let cpu = [
+            noise::perlin2(p.truncate(), 5),
+            noise::perlin3(*p, 5),
+            noise::simplex2(p.truncate(), 5),
+            noise::simplex3(*p, 5),
+            noise::worley2(p.truncate(), 5),
+            noise::worley3(*p, 5),
+            fbm(NoiseKind::Perlin).sample2(p.truncate()),
+            fbm(NoiseKind::Perlin).sample3(*p),
+            fbm(NoiseKind::Simplex).sample2(p.truncate()),
+            fbm(NoiseKind::Simplex).sample3(*p),
+            fbm(NoiseKind::Worley).sample2(p.truncate()),
+            fbm(NoiseKind::Worley).sample3(*p),
+        ];
+        for (function, (cpu, gpu)) in cpu.iter().zip(gpu).enumerate() {
+            assert!((cpu - gpu).abs() < 1e-4, "function {function} at {p}: CPU {cpu}, GPU {gpu}");
+        }
+    }
+});
@@ -0,0 +1,229 @@
+use glam::Vec3;
+use crate::shaders;
+use super::post::HDR_FORMAT;
+use super::profiler::PassQueries;
+
+/// Accumulated weighted premultiplied color and alpha
+pub(crate) const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Product of every layer's (1 - alpha)
+pub(crate) const REVEAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// How materials marked `transparent` are blended over the opaque scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transparency {
+    /// Alpha blended object by object, farthest first. Exact for separate objects, but
+    /// intersecting or nested surfaces can pop as the view moves
+    #[default]
+    Sorted,
+    /// Weighted blended order-independent transparency: no sorting and no popping, at the
+    /// cost of approximate colors where many layers overlap and an extra pass
+    WeightedBlended,
+}
+
+/// Indices of `centers` ordered farthest from `eye` first, the order `Transparency::Sorted`
+/// draws objects in
+pub(crate) fn back_to_front(centers: &[Vec3], eye: Vec3) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..centers.len()).collect();
+    order.sort_by(|&a, &b| eye.distance_squared(centers[b]).total_cmp(&eye.distance_squared(centers[a])));
+    order
+}
+
+/// Accumulation targets at the scene pass sample count, resolved for the composite when
+/// multisampled
+struct OitTargets {
+    size: (u32, u32, u32),
+    accum_view: wgpu::TextureView,
+    reveal_view: wgpu::TextureView,
+    accum_resolve: Option<wgpu::TextureView>,
+    reveal_resolve: Option<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Weighted blended transparency: transparent meshes are drawn in any order into the
+/// accumulation targets, then composited over the resolved HDR scene
+pub(crate) struct OitPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Allocated by the first `prepare`, so scenes without it don't pay for the targets
+    targets: Option<OitTargets>,
+}
+
+impl OitPass {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders::create_module(device, "OIT Composite Shader", include_str!("../../shaders/oit.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("OIT Composite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self { pipeline, bind_group_layout, targets: None }
+    }
+
+    /// (Re)allocate the accumulation targets for the scene pass size and sample count
+    pub fn prepare(&mut self, device: &wgpu::Device, width: u32, height: u32, sample_count: u32) {
+        let size = (width.max(1), height.max(1), sample_count);
+        if self.targets.as_ref().is_some_and(|targets| targets.size == size) {
+            return;
+        }
+        let create_view = |label, format, sample_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d { width: size.0, height: size.1, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            }).create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let sampled = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+        let (accum_view, reveal_view, accum_resolve, reveal_resolve) = if sample_count > 1 {
+            let attachment = wgpu::TextureUsages::RENDER_ATTACHMENT;
+            (
+                create_view("MSAA OIT Accum Target", ACCUM_FORMAT, sample_count, attachment),
+                create_view("MSAA OIT Reveal Target", REVEAL_FORMAT, sample_count, attachment),
+                Some(create_view("OIT Accum Target", ACCUM_FORMAT, 1, sampled)),
+                Some(create_view("OIT Reveal Target", REVEAL_FORMAT, 1, sampled)),
+            )
+        } else {
+            (
+                create_view("OIT Accum Target", ACCUM_FORMAT, 1, sampled),
+                create_view("OIT Reveal Target", REVEAL_FORMAT, 1, sampled),
+                None,
+                None,
+            )
+        };
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(accum_resolve.as_ref().unwrap_or(&accum_view)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(reveal_resolve.as_ref().unwrap_or(&reveal_view)),
+                },
+            ],
+        });
+        self.targets = Some(OitTargets { size, accum_view, reveal_view, accum_resolve, reveal_resolve, bind_group });
+    }
+
+    /// Begin the pass transparent meshes are accumulated in, depth tested against the scene
+    /// pass's `depth_view` without writing to it. `None` before `prepare`.
+    pub fn begin_accumulation<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        stencil: bool,
+        queries: Option<&PassQueries>,
+    ) -> Option<wgpu::RenderPass<'e>> {
+        let targets = self.targets.as_ref()?;
+        let pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Accumulation Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &targets.accum_view,
+                    resolve_target: targets.accum_resolve.as_ref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                }),
+                // Fully revealed until a layer covers it
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &targets.reveal_view,
+                    resolve_target: targets.reveal_resolve.as_ref(),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store }),
+                stencil_ops: stencil.then_some(wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store }),
+            }),
+            timestamp_writes: queries.map(|queries| queries.render_writes()),
+            occlusion_query_set: None,
+        });
+        Some(pass)
+    }
+
+    /// Blend the accumulated layers over `hdr_view`, the single-sample scene color
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, hdr_view: &wgpu::TextureView) {
+        let Some(targets) = &self.targets else {
+            return;
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &targets.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_back_to_front() {
+        let centers = [Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, -8.0), Vec3::new(1.0, 0.0, -4.0)];
+        assert_eq!(back_to_front(&centers, Vec3::ZERO), vec![1, 2, 0]);
+        // Seen from the other side the order flips
+        assert_eq!(back_to_front(&centers, Vec3::new(0.0, 0.0, -10.0)), vec![0, 2, 1]);
+    }
+}
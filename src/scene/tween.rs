@@ -0,0 +1,376 @@
+use std::f32::consts::PI;
+use glam::{EulerRot, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use super::{ObjectId, SceneObjects, Transform};
+
+/// How a tween's progress is shaped over its duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineInOut,
+    /// Overshoots the end a little before settling
+    BackOut,
+    /// Bounces against the end like a dropped ball
+    BounceOut,
+}
+
+impl Easing {
+    /// Eased progress for linear progress `t` in 0..=1; 0 and 1 map to themselves
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut if t < 0.5 => 2.0 * t * t,
+            Easing::QuadInOut => 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0,
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::CubicInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Easing::BackOut => {
+                const C1: f32 = 1.70158;
+                const C3: f32 = C1 + 1.0;
+                1.0 + C3 * (t - 1.0).powi(3) + C1 * (t - 1.0).powi(2)
+            }
+            Easing::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+/// The part of a transform a tween drives, and where it ends up
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Position(Vec3),
+    /// Relative to the position when the tween starts
+    Offset(Vec3),
+    /// Euler angles (XYZ, radians), like `Transform::rotation`
+    Rotation(Vec3),
+    Scale(Vec3),
+    Transform(Transform),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Tween {
+        object: ObjectId,
+        target: Target,
+        duration: f32,
+        easing: Easing,
+        /// The object's transform when the tween started
+        from: Option<Transform>,
+        elapsed: f32,
+    },
+    Wait {
+        duration: f32,
+        elapsed: f32,
+    },
+    Sequence {
+        steps: Vec<Node>,
+        current: usize,
+    },
+    Parallel(Vec<Node>),
+}
+
+/// Animation of scene object transforms: single tweens combined into sequences and parallel
+/// groups, e.g. a door that swings open, waits, then swings shut while its frame slides.
+/// Tweens start from wherever the object is when they begin, so chained steps compose.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    root: Node,
+}
+
+impl Animation {
+    fn tween(object: ObjectId, target: Target, duration: f32) -> Self {
+        Self { root: Node::Tween { object, target, duration, easing: Easing::Linear, from: None, elapsed: 0.0 } }
+    }
+
+    /// Move `object` to `position` over `duration` seconds
+    pub fn position(object: ObjectId, position: Vec3, duration: f32) -> Self {
+        Self::tween(object, Target::Position(position), duration)
+    }
+
+    /// Move `object` by `offset` from wherever it is when the tween starts
+    pub fn move_by(object: ObjectId, offset: Vec3, duration: f32) -> Self {
+        Self::tween(object, Target::Offset(offset), duration)
+    }
+
+    /// Turn `object` to Euler angles `rotation` along the shortest arc
+    pub fn rotation(object: ObjectId, rotation: Vec3, duration: f32) -> Self {
+        Self::tween(object, Target::Rotation(rotation), duration)
+    }
+
+    pub fn scale(object: ObjectId, scale: Vec3, duration: f32) -> Self {
+        Self::tween(object, Target::Scale(scale), duration)
+    }
+
+    /// Tween the whole transform of `object` to `transform`
+    pub fn transform(object: ObjectId, transform: Transform, duration: f32) -> Self {
+        Self::tween(object, Target::Transform(transform), duration)
+    }
+
+    /// Do nothing for `duration` seconds, as a pause in a sequence
+    pub fn wait(duration: f32) -> Self {
+        Self { root: Node::Wait { duration, elapsed: 0.0 } }
+    }
+
+    /// Play `steps` one after another
+    pub fn sequence(steps: impl IntoIterator<Item = Animation>) -> Self {
+        Self { root: Node::Sequence { steps: steps.into_iter().map(|step| step.root).collect(), current: 0 } }
+    }
+
+    /// Play `animations` together; done when the longest is
+    pub fn parallel(animations: impl IntoIterator<Item = Animation>) -> Self {
+        Self { root: Node::Parallel(animations.into_iter().map(|animation| animation.root).collect()) }
+    }
+
+    /// Play `next` after this
+    pub fn then(self, next: Animation) -> Self {
+        match self.root {
+            Node::Sequence { mut steps, current } => {
+                steps.push(next.root);
+                Self { root: Node::Sequence { steps, current } }
+            }
+            root => Self::sequence([Self { root }, next]),
+        }
+    }
+
+    /// Ease every tween in the animation that is still linear
+    pub fn eased(mut self, easing: Easing) -> Self {
+        fn ease(node: &mut Node, with: Easing) {
+            match node {
+                Node::Tween { easing, .. } if *easing == Easing::Linear => *easing = with,
+                Node::Sequence { steps, .. } | Node::Parallel(steps) => steps.iter_mut().for_each(|step| ease(step, with)),
+                _ => {}
+            }
+        }
+        ease(&mut self.root, easing);
+        self
+    }
+
+    /// Seconds from start to finish
+    pub fn duration(&self) -> f32 {
+        fn duration(node: &Node) -> f32 {
+            match node {
+                Node::Tween { duration, .. } | Node::Wait { duration, .. } => duration.max(0.0),
+                Node::Sequence { steps, .. } => steps.iter().map(duration).sum(),
+                Node::Parallel(nodes) => nodes.iter().map(duration).fold(0.0, f32::max),
+            }
+        }
+        duration(&self.root)
+    }
+
+    /// Advance by `dt` seconds; returns the part of `dt` left over once the animation is done
+    fn advance(&mut self, dt: f32, objects: &mut SceneObjects) -> Option<f32> {
+        advance(&mut self.root, dt, objects)
+    }
+}
+
+/// Apply `dt` seconds to `node`: `None` while it's still running, else the unused time, which
+/// the next step of a sequence gets so chains don't drift with the frame rate
+fn advance(node: &mut Node, dt: f32, objects: &mut SceneObjects) -> Option<f32> {
+    match node {
+        Node::Tween { object, target, duration, easing, from, elapsed } => {
+            let Some(index) = objects.index_of(*object) else {
+                // The object was removed; nothing left to animate
+                return Some(dt);
+            };
+            let duration = duration.max(0.0);
+            if from.is_some() && *elapsed >= duration {
+                // Done already, e.g. the short half of a parallel group; leave the object alone
+                *elapsed += dt;
+                return Some(*elapsed - duration);
+            }
+            let transform = &mut objects.transforms_mut()[index];
+            let start = *from.get_or_insert(*transform);
+            *elapsed += dt;
+            let progress = if duration > 0.0 { (*elapsed / duration).min(1.0) } else { 1.0 };
+            interpolate(transform, &start, target, easing.apply(progress));
+            (progress >= 1.0).then_some(*elapsed - duration)
+        }
+        Node::Wait { duration, elapsed } => {
+            *elapsed += dt;
+            (*elapsed >= *duration).then_some(*elapsed - *duration)
+        }
+        Node::Sequence { steps, current } => {
+            let mut dt = dt;
+            while let Some(step) = steps.get_mut(*current) {
+                dt = advance(step, dt, objects)?;
+                *current += 1;
+            }
+            Some(dt)
+        }
+        Node::Parallel(nodes) => {
+            // Finished children keep reporting the time they had left, so advancing them again is harmless
+            let left: Vec<_> = nodes.iter_mut().map(|node| advance(node, dt, objects)).collect();
+            left.into_iter().try_fold(dt, |left, node_left| node_left.map(|node_left| left.min(node_left)))
+        }
+    }
+}
+
+/// Set the parts of `transform` that `target` drives to `t` of the way from `start`, leaving the
+/// rest to other tweens; `t` may leave 0..1 for overshooting easings
+fn interpolate(transform: &mut Transform, start: &Transform, target: &Target, t: f32) {
+    let rotate = |to: Vec3| {
+        let from = Quat::from_euler(EulerRot::XYZ, start.rotation.x, start.rotation.y, start.rotation.z);
+        let to = Quat::from_euler(EulerRot::XYZ, to.x, to.y, to.z);
+        if t >= 1.0 {
+            return to;
+        }
+        // slerp takes the short way round; extrapolate past the ends for overshoot
+        from.slerp(to, t)
+    };
+    let euler = |rotation: Quat| {
+        let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+        Vec3::new(x, y, z)
+    };
+    match *target {
+        Target::Position(position) => transform.position = start.position.lerp(position, t),
+        Target::Offset(offset) => transform.position = start.position + offset * t,
+        Target::Rotation(rotation) if t >= 1.0 => transform.rotation = rotation,
+        Target::Rotation(rotation) => transform.rotation = euler(rotate(rotation)),
+        Target::Scale(scale) => transform.scale = start.scale.lerp(scale, t),
+        Target::Transform(end) => {
+            transform.position = start.position.lerp(end.position, t);
+            transform.rotation = if t >= 1.0 { end.rotation } else { euler(rotate(end.rotation)) };
+            transform.scale = start.scale.lerp(end.scale, t);
+        }
+    }
+}
+
+/// Identifies a playing animation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TweenId(u32);
+
+/// Called with the scene when an animation finishes, e.g. to start the next one
+pub type TweenCallback = Box<dyn FnOnce(&mut super::Scene)>;
+
+struct Playing {
+    id: TweenId,
+    animation: Animation,
+    on_complete: Option<TweenCallback>,
+}
+
+/// The animations playing in a scene, advanced by `Scene::step`
+#[derive(Default)]
+pub struct Tweens {
+    playing: Vec<Playing>,
+    next_id: u32,
+}
+
+impl Tweens {
+    pub fn play(&mut self, animation: Animation) -> TweenId {
+        self.start(animation, None)
+    }
+
+    /// Play `animation` and call `on_complete` once it finishes (not if it's stopped)
+    pub fn play_then(&mut self, animation: Animation, on_complete: impl FnOnce(&mut super::Scene) + 'static) -> TweenId {
+        self.start(animation, Some(Box::new(on_complete)))
+    }
+
+    fn start(&mut self, animation: Animation, on_complete: Option<TweenCallback>) -> TweenId {
+        let id = TweenId(self.next_id);
+        self.next_id += 1;
+        self.playing.push(Playing { id, animation, on_complete });
+        id
+    }
+
+    /// Stop an animation where it is; returns false if it isn't playing
+    pub fn stop(&mut self, id: TweenId) -> bool {
+        let count = self.playing.len();
+        self.playing.retain(|playing| playing.id != id);
+        self.playing.len() != count
+    }
+
+    pub fn is_playing(&self, id: TweenId) -> bool {
+        self.playing.iter().any(|playing| playing.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.playing.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.playing.is_empty()
+    }
+
+    /// Advance every animation by `dt`, returning the callbacks of those that finished
+    pub(crate) fn advance(&mut self, dt: f32, objects: &mut SceneObjects) -> Vec<TweenCallback> {
+        let mut finished = Vec::new();
+        self.playing.retain_mut(|playing| {
+            if playing.animation.advance(dt, objects).is_none() {
+                return true;
+            }
+            finished.extend(playing.on_complete.take());
+            false
+        });
+        finished
+    }
+
+    /// Keep absolute targets in place when the floating origin moves local positions by `offset`
+    pub(crate) fn translate(&mut self, offset: Vec3) {
+        fn translate(node: &mut Node, offset: Vec3) {
+            match node {
+                Node::Tween { target, from, .. } => {
+                    match target {
+                        Target::Position(position) => *position += offset,
+                        Target::Transform(transform) => transform.position += offset,
+                        _ => {}
+                    }
+                    if let Some(from) = from {
+                        from.position += offset;
+                    }
+                }
+                Node::Sequence { steps, .. } | Node::Parallel(steps) => steps.iter_mut().for_each(|step| translate(step, offset)),
+                Node::Wait { .. } => {}
+            }
+        }
+        for playing in &mut self.playing {
+            translate(&mut playing.animation.root, offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_easing() {
+        for easing in [
+            Easing::Linear, Easing::QuadIn, Easing::QuadOut, Easing::QuadInOut, Easing::CubicIn,
+            Easing::CubicOut, Easing::CubicInOut, Easing::SineInOut, Easing::BackOut, Easing::BounceOut,
+        ] {
+            assert_relative_eq!(easing.apply(0.0), 0.0, epsilon = 1e-5);
+            assert_relative_eq!(easing.apply(1.0), 1.0, epsilon = 1e-5);
+        }
+        assert_relative_eq!(Easing::QuadInOut.apply(0.5), 0.5);
+        assert!(Easing::QuadIn.apply(0.25) < 0.25 && Easing::QuadOut.apply(0.25) > 0.25);
+        assert!(Easing::BackOut.apply(0.8) > 1.0);
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::ops::BitOr;
+use anyhow::{anyhow, bail, Result};
+use crate::diagnostics;
+use crate::model::{Material, Mesh, ModelVertex, SkinVertex};
+use crate::shaders::{self, content_hash};
+use super::post::{ALBEDO_FORMAT, HDR_FORMAT, NORMAL_FORMAT, VELOCITY_FORMAT};
+use super::transparency::{ACCUM_FORMAT, REVEAL_FORMAT};
+
+/// Optional features of the scene shader. Each one compiles the matching `#ifdef` block of
+/// `shader.wgsl`, so simple materials don't pay for the ones they don't use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ShaderFeatures(u32);
+
+impl ShaderFeatures {
+    pub const NONE: Self = Self(0);
+    /// Perturb normals with the material's normal map
+    pub const HAS_NORMAL_MAP: Self = Self(1);
+    /// Multiply vertex colors into the base color
+    pub const HAS_VERTEX_COLOR: Self = Self(1 << 1);
+    /// Blend joint matrices by per-vertex weights from a second vertex stream
+    pub const SKINNED: Self = Self(1 << 2);
+    /// Discard fragments below the material's alpha cutoff
+    pub const ALPHA_MASK: Self = Self(1 << 3);
+    /// Turn masked alpha into MSAA sample coverage instead of discarding; dropped without MSAA
+    pub const ALPHA_TO_COVERAGE: Self = Self(1 << 4);
+    /// Sway vertices with the material's wind
+    pub const WIND: Self = Self(1 << 5);
+    /// Cut the material's chroma key color out of the diffuse texture
+    pub const CHROMA_KEY: Self = Self(1 << 6);
+
+    const NAMES: [(Self, &'static str); 7] = [
+        (Self::HAS_NORMAL_MAP, "HAS_NORMAL_MAP"),
+        (Self::HAS_VERTEX_COLOR, "HAS_VERTEX_COLOR"),
+        (Self::SKINNED, "SKINNED"),
+        (Self::ALPHA_MASK, "ALPHA_MASK"),
+        (Self::ALPHA_TO_COVERAGE, "ALPHA_TO_COVERAGE"),
+        (Self::WIND, "WIND"),
+        (Self::CHROMA_KEY, "CHROMA_KEY"),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// These features without those in `other`
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Features needed to draw `mesh` with `material`, or with the default material when `None`;
+    /// `posed` when the object has joint matrices for skinned meshes to be drawn with
+    pub fn for_mesh(mesh: &Mesh, material: Option<&Material>, posed: bool) -> Self {
+        let mut features = Self::NONE;
+        if mesh.vertex_colors {
+            features = features | Self::HAS_VERTEX_COLOR;
+        }
+        if posed && mesh.is_skinned() {
+            features = features | Self::SKINNED;
+        }
+        if let Some(material) = material {
+            if material.has_normal_map() {
+                features = features | Self::HAS_NORMAL_MAP;
+            }
+            if material.mask_cutoff().is_some() {
+                features = features | Self::ALPHA_MASK;
+                // Blended materials have real alpha to keep
+                if material.alpha_to_coverage && !material.transparent {
+                    features = features | Self::ALPHA_TO_COVERAGE;
+                }
+            }
+            if material.wind.is_some_and(|wind| wind.strength != 0.0) {
+                features = features | Self::WIND;
+            }
+            if material.chroma_key.is_some() {
+                features = features | Self::CHROMA_KEY;
+            }
+        }
+        features
+    }
+
+    /// Names of the enabled features, as used by `#ifdef`
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES.into_iter()
+            .filter(move |(feature, _)| self.contains(*feature))
+            .map(|(_, name)| name)
+    }
+
+    fn label(self) -> String {
+        let names: Vec<_> = self.names().collect();
+        if names.is_empty() { "base".to_string() } else { names.join("|") }
+    }
+}
+
+impl BitOr for ShaderFeatures {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Resolve `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` lines (which may nest) for
+/// `features`. Dropped lines are left blank so shader errors keep their line numbers.
+pub fn preprocess(source: &str, features: ShaderFeatures) -> Result<String> {
+    // Whether the current branch of each open block is taken
+    let mut stack: Vec<bool> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+    for (number, line) in source.lines().enumerate() {
+        let emitting = stack.iter().all(|taken| *taken);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some(directive @ ("#ifdef" | "#ifndef")) => {
+                let Some(name) = tokens.next() else {
+                    bail!("line {}: {} without a feature name", number + 1, directive);
+                };
+                let Some((feature, _)) = ShaderFeatures::NAMES.iter().find(|(_, known)| *known == name) else {
+                    bail!("line {}: unknown shader feature '{}'", number + 1, name);
+                };
+                stack.push(features.contains(*feature) == (directive == "#ifdef"));
+            }
+            Some("#else") => match stack.last_mut() {
+                Some(taken) => *taken = !*taken,
+                None => bail!("line {}: #else without #ifdef", number + 1),
+            },
+            Some("#endif") => {
+                stack.pop().ok_or_else(|| anyhow!("line {}: #endif without #ifdef", number + 1))?;
+            }
+            _ if emitting => output.push_str(line),
+            _ => {}
+        }
+        output.push('\n');
+    }
+    if !stack.is_empty() {
+        bail!("{} unterminated #ifdef blocks", stack.len());
+    }
+    Ok(output)
+}
+
+/// Scene pipeline variants; they share a layout, so bind groups survive switching between them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum PipelineKind {
+    Standard,
+    /// No back-face culling, for double-sided materials
+    DoubleSided,
+    /// No culling, drawing back faces as caps while clip planes are active
+    Section,
+}
+
+impl PipelineKind {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Standard => "Render Pipeline",
+            Self::DoubleSided => "Double-Sided Render Pipeline",
+            Self::Section => "Section Render Pipeline",
+        }
+    }
+}
+
+/// How a scene pipeline writes its fragments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Blending {
+    /// Replaces the scene targets and writes depth
+    Opaque,
+    /// Blended over the HDR target without writing depth, normals or albedo, for transparent
+    /// materials drawn back to front
+    Alpha,
+    /// Accumulated into the weighted blended transparency targets, in any order
+    WeightedBlended,
+}
+
+impl Blending {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Opaque => "",
+            Self::Alpha => " (alpha blended)",
+            Self::WeightedBlended => " (weighted blended)",
+        }
+    }
+}
+
+/// Scene pipelines compiled on demand for each feature set and pipeline kind, and cached.
+///
+/// Layouts are derived per variant: skinned variants add joint matrices to the model group.
+/// The material group keeps one layout for every variant, so a material's bind group works with
+/// any of them; variants without a normal map simply leave its bindings unused. Shader modules
+/// are cached by a hash of the variant's preprocessed source, so feature sets that compile to
+/// the same code share one.
+pub(crate) struct ShaderVariants {
+    /// With `#include`s expanded
+    source: String,
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    layout: wgpu::PipelineLayout,
+    /// Model group of skinned variants
+    skinned_model: wgpu::BindGroupLayout,
+    skinned_layout: wgpu::PipelineLayout,
+    modules: HashMap<u64, wgpu::ShaderModule>,
+    pipelines: HashMap<(ShaderFeatures, PipelineKind, Blending), wgpu::RenderPipeline>,
+}
+
+impl ShaderVariants {
+    /// `bind_group_layouts` are the camera, light, model and material groups
+    pub fn new(
+        device: &wgpu::Device,
+        source: &'static str,
+        bind_group_layouts: [&wgpu::BindGroupLayout; 4],
+        sample_count: u32,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        let [camera, light, model, material] = bind_group_layouts;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[camera, light, model, material],
+            push_constant_ranges: &[],
+        });
+
+        let uniform = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let skinned_model = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skinned Model Bind Group Layout"),
+            // Model uniform, whose probe irradiance the fragment stage reads, then joint matrices
+            entries: &[uniform(0, wgpu::ShaderStages::VERTEX_FRAGMENT), uniform(1, wgpu::ShaderStages::VERTEX)],
+        });
+        let skinned_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinned Render Pipeline Layout"),
+            bind_group_layouts: &[camera, light, &skinned_model, material],
+            push_constant_ranges: &[],
+        });
+
+        let source = shaders::resolve(source)
+            .unwrap_or_else(|e| panic!("Invalid includes in the scene shader: {:#}", e))
+            .source;
+
+        Self {
+            source,
+            sample_count,
+            depth_format,
+            layout,
+            skinned_model,
+            skinned_layout,
+            modules: HashMap::new(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Drop the cached pipelines when the MSAA sample count or depth format changes; shader
+    /// modules are kept
+    pub fn set_targets(&mut self, sample_count: u32, depth_format: wgpu::TextureFormat) {
+        if (sample_count, depth_format) != (self.sample_count, self.depth_format) {
+            self.sample_count = sample_count;
+            self.depth_format = depth_format;
+            self.pipelines.clear();
+        }
+    }
+
+    /// Layout of the model bind groups skinned variants draw with: the model uniform, then the
+    /// joint matrices
+    pub fn skinned_model_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.skinned_model
+    }
+
+    /// Number of pipelines compiled so far
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    /// Alpha-to-coverage needs samples to cover, so single-sampled targets fall back to the
+    /// hard cutoff
+    fn resolve(&self, features: ShaderFeatures) -> ShaderFeatures {
+        if self.sample_count > 1 { features } else { features.without(ShaderFeatures::ALPHA_TO_COVERAGE) }
+    }
+
+    /// Compile the variant if it isn't cached yet
+    pub fn prepare(&mut self, device: &wgpu::Device, features: ShaderFeatures, kind: PipelineKind, blending: Blending) {
+        let features = self.resolve(features);
+        if self.pipelines.contains_key(&(features, kind, blending)) {
+            return;
+        }
+        let source = preprocess(&self.source, features)
+            .unwrap_or_else(|e| panic!("Invalid shader variant {}: {}", features.label(), e));
+        let module = self.modules.entry(content_hash(&source)).or_insert_with(|| {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&format!("Shader [{}]", features.label())),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            })
+        });
+        let layout = if features.contains(ShaderFeatures::SKINNED) { &self.skinned_layout } else { &self.layout };
+        let pipeline = create_pipeline(device, layout, module, self.sample_count, self.depth_format, features, kind, blending);
+        self.pipelines.insert((features, kind, blending), pipeline);
+    }
+
+    /// A variant compiled by `prepare`
+    pub fn get(&self, features: ShaderFeatures, kind: PipelineKind, blending: Blending) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&(self.resolve(features), kind, blending))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    features: ShaderFeatures,
+    kind: PipelineKind,
+    blending: Blending,
+) -> wgpu::RenderPipeline {
+    let label = format!("{}{} [{}]", kind.label(), blending.label(), features.label());
+    let skinned_buffers = [ModelVertex::desc(), SkinVertex::desc()];
+    let buffers = if features.contains(ShaderFeatures::SKINNED) { &skinned_buffers[..] } else { &skinned_buffers[..1] };
+    let opaque = blending == Blending::Opaque;
+    diagnostics::scoped(device, &label, || {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&label),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module,
+                entry_point: Some("vs_main"),
+                buffers,
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module,
+                entry_point: Some(match (kind, blending) {
+                    (_, Blending::WeightedBlended) => "fs_oit",
+                    (PipelineKind::Section, _) => "fs_section",
+                    _ => "fs_main",
+                }),
+                targets: &color_targets(blending),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: match kind {
+                    PipelineKind::Standard => Some(wgpu::Face::Back),
+                    PipelineKind::DoubleSided | PipelineKind::Section => None,
+                },
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Transparent surfaces are depth tested against the opaque scene but leave depth and
+            // stencil marks to what's behind them
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: opaque,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: if opaque && depth_format.has_stencil_aspect() { super::stencil::MARK } else { wgpu::StencilState::default() },
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: features.contains(ShaderFeatures::ALPHA_TO_COVERAGE),
+            },
+            multiview: None,
+            cache: None,
+        })
+    })
+}
+
+/// The scene pass targets, or the accumulation targets for weighted blended transparency
+fn color_targets(blending: Blending) -> Vec<Option<wgpu::ColorTargetState>> {
+    let target = |format, blend| Some(wgpu::ColorTargetState { format, blend: Some(blend), write_mask: wgpu::ColorWrites::ALL });
+    let masked = |format| Some(wgpu::ColorTargetState { format, blend: None, write_mask: wgpu::ColorWrites::empty() });
+    match blending {
+        Blending::Opaque => vec![
+            target(HDR_FORMAT, wgpu::BlendState::REPLACE),
+            target(NORMAL_FORMAT, wgpu::BlendState::REPLACE),
+            target(ALBEDO_FORMAT, wgpu::BlendState::REPLACE),
+            target(VELOCITY_FORMAT, wgpu::BlendState::REPLACE),
+        ],
+        Blending::Alpha => vec![
+            target(HDR_FORMAT, wgpu::BlendState::ALPHA_BLENDING),
+            masked(NORMAL_FORMAT),
+            masked(ALBEDO_FORMAT),
+            masked(VELOCITY_FORMAT),
+        ],
+        Blending::WeightedBlended => {
+            let add = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            };
+            // Revealage is the product of every layer's (1 - alpha)
+            let reveal = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                operation: wgpu::BlendOperation::Add,
+            };
+            vec![
+                target(ACCUM_FORMAT, wgpu::BlendState { color: add, alpha: add }),
+                target(REVEAL_FORMAT, wgpu::BlendState { color: reveal, alpha: reveal }),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::naga;
+
+    #[test]
+    fn test_preprocess() {
+        let source = "a\n#ifdef HAS_NORMAL_MAP\nb\n#ifndef SKINNED\nc\n#endif\n#else\nd\n#endif\ne";
+        let features = ShaderFeatures::HAS_NORMAL_MAP;
+        assert_eq!(preprocess(source, features).unwrap(), "a\n\nb\n\nc\n\n\n\n\ne\n");
+        let features = ShaderFeatures::HAS_NORMAL_MAP | ShaderFeatures::SKINNED;
+        assert_eq!(preprocess(source, features).unwrap(), "a\n\nb\n\n\n\n\n\n\ne\n");
+        assert_eq!(preprocess(source, ShaderFeatures::NONE).unwrap(), "a\n\n\n\n\n\n\nd\n\ne\n");
+    }
+
+    #[test]
+    fn test_preprocess_errors() {
+        assert!(preprocess("#ifdef HAS_NORMAL_MAP\n", ShaderFeatures::NONE).is_err());
+        assert!(preprocess("#endif\n", ShaderFeatures::NONE).is_err());
+        assert!(preprocess("#ifdef HAS_NORMAL_MAPS\n#endif\n", ShaderFeatures::NONE).is_err());
+    }
+
+    #[test]
+    fn test_every_variant_validates() {
+        let source = shaders::resolve(include_str!("../../shaders/shader.wgsl")).unwrap().source;
+        for bits in 0..1 << ShaderFeatures::NAMES.len() {
+            let features = ShaderFeatures(bits);
+            let wgsl = preprocess(&source, features).unwrap();
+            let module = naga::front::wgsl::parse_str(&wgsl)
+                .unwrap_or_else(|e| panic!("{}: {}", features.label(), e.emit_to_string(&wgsl)));
+            naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+                .validate(&module)
+                .unwrap_or_else(|e| panic!("{}: {:?}", features.label(), e));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::settings::ViewportMode;
+
+/// Where the scene image lands on the surface, in surface pixels. Outside it the surface is
+/// left black: bars above and below (letterbox) or at the sides (pillarbox).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// The whole surface
+    pub fn full(width: u32, height: u32) -> Self {
+        Self { x: 0, y: 0, width: width.max(1), height: height.max(1) }
+    }
+
+    /// The viewport `mode` gives on a surface of the given size, centered and at least a pixel
+    pub fn fit(mode: &ViewportMode, surface_width: u32, surface_height: u32) -> Self {
+        let ratio = match *mode {
+            ViewportMode::Stretch => return Self::full(surface_width, surface_height),
+            ViewportMode::Aspect { ratio } => ratio,
+            ViewportMode::Fixed { width, height } => width as f32 / height as f32,
+        };
+        let (surface_width, surface_height) = (surface_width.max(1), surface_height.max(1));
+        let (width, height) = if surface_width as f32 / surface_height as f32 > ratio {
+            // Wider than the target: pillarbox
+            (((surface_height as f32 * ratio).round() as u32).clamp(1, surface_width), surface_height)
+        } else {
+            (surface_width, ((surface_width as f32 / ratio).round() as u32).clamp(1, surface_height))
+        };
+        Self {
+            x: (surface_width - width) / 2,
+            y: (surface_height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// Position within the viewport, 0 - 1 on each axis, of surface pixel `(x, y)`;
+    /// `None` over the bars
+    pub fn to_normalized(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let u = (x - self.x as f32) / self.width as f32;
+        let v = (y - self.y as f32) / self.height as f32;
+        ((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)).then_some((u, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_letterbox_and_pillarbox() {
+        let widescreen = ViewportMode::Aspect { ratio: 16.0 / 9.0 };
+        // A square window gets bars above and below
+        assert_eq!(Viewport::fit(&widescreen, 1600, 1600), Viewport { x: 0, y: 350, width: 1600, height: 900 });
+        // An ultrawide one gets them at the sides
+        assert_eq!(Viewport::fit(&widescreen, 3440, 1440), Viewport { x: 440, y: 0, width: 2560, height: 1440 });
+
+        let fixed = ViewportMode::Fixed { width: 640, height: 480 };
+        assert_eq!(Viewport::fit(&fixed, 800, 480), Viewport { x: 80, y: 0, width: 640, height: 480 });
+        assert_eq!(Viewport::fit(&ViewportMode::Stretch, 800, 480), Viewport::full(800, 480));
+    }
+
+    #[test]
+    fn test_to_normalized_skips_bars() {
+        let viewport = Viewport { x: 100, y: 0, width: 200, height: 100 };
+        assert_eq!(viewport.to_normalized(200.0, 50.0), Some((0.5, 0.5)));
+        assert_eq!(viewport.to_normalized(50.0, 50.0), None);
+        assert_eq!(viewport.to_normalized(300.0, 50.0), None);
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use glam::{Mat4, Vec3};
+use super::{ObjectId, Scene};
+use super::frustum::ViewCulling;
+use super::occlusion::DepthBuffer;
+
+/// What the renderer is about to draw, for a `Visibility` to decide against
+pub struct ViewInfo<'a> {
+    pub scene: &'a Scene,
+    /// One per view, e.g. a headset's two eyes
+    pub view_projections: &'a [Mat4],
+    /// The point between the views
+    pub viewer: Vec3,
+    /// Frustum tests against every view at once
+    pub culling: &'a ViewCulling,
+}
+
+/// What culling did to the objects of the last frame rendered, from `Renderer::culling_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    /// Shown objects whose model is registered, the ones culling decides about
+    pub objects: usize,
+    /// Drawn into at least one view
+    pub drawn: usize,
+    /// Left out by the `Visibility`: outside the frustum, or behind occluders
+    pub culled: usize,
+    /// Behind closed portals
+    pub portal_culled: usize,
+    /// Objects drawn summed over the views; below `drawn` times the views when precise stereo
+    /// culling drops objects only one eye sees from the other
+    pub view_draws: usize,
+}
+
+impl fmt::Display for CullingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} of {} objects drawn ({} draws over all views), {} culled, {} behind portals",
+            self.drawn, self.objects, self.view_draws, self.culled, self.portal_culled,
+        )
+    }
+}
+
+/// Decides which scene objects the renderer draws each frame, so applications with their own
+/// spatial structures (chunks, octrees) can cull with them instead of the built-in frustum test.
+///
+/// Objects left out still cast shadows and can still be hit by ray picks; hidden objects and
+/// those behind closed portals are skipped whatever is returned. With
+/// `VrStereoSettings::precise_culling` the renderer also drops, per view, the returned objects
+/// whose bounds that view can't see.
+pub trait Visibility {
+    /// Objects any of the views may see, each at most once
+    fn visible_objects<'a>(&'a self, view: &ViewInfo<'a>) -> Box<dyn Iterator<Item = ObjectId> + 'a>;
+}
+
+/// Every object is drawn, e.g. to rule culling out while debugging popping
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllVisible;
+
+impl Visibility for AllVisible {
+    fn visible_objects<'a>(&'a self, view: &ViewInfo<'a>) -> Box<dyn Iterator<Item = ObjectId> + 'a> {
+        Box::new(view.scene.objects.ids().iter().copied())
+    }
+}
+
+/// The default: objects whose world bounds intersect the frustum bounding all views. Walks
+/// `Scene::bvh` when it's up to date, else tests every object. Objects then hidden in every
+/// view behind `Scene::occluders` are left out too.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrustumVisibility;
+
+impl Visibility for FrustumVisibility {
+    fn visible_objects<'a>(&'a self, view: &ViewInfo<'a>) -> Box<dyn Iterator<Item = ObjectId> + 'a> {
+        let in_frustum = Self::in_frustum(view);
+        let scene = view.scene;
+        if !scene.occluders.is_active() {
+            return in_frustum;
+        }
+        let depth_buffers: Vec<DepthBuffer> = view.view_projections.iter()
+            .map(|&view_projection| scene.occluders.depth_buffer(&scene.objects, view_projection))
+            .collect();
+        let view_projections = view.view_projections;
+        Box::new(in_frustum.filter(move |&id| {
+            let Some((min, max)) = scene.object(id).and_then(|object| object.world_bounds(&scene.assets)) else {
+                return true;
+            };
+            !depth_buffers.iter()
+                .zip(view_projections)
+                .all(|(buffer, &view_projection)| buffer.occludes(view_projection, min, max))
+        }))
+    }
+}
+
+impl FrustumVisibility {
+    fn in_frustum<'a>(view: &ViewInfo<'a>) -> Box<dyn Iterator<Item = ObjectId> + 'a> {
+        let (scene, culling) = (view.scene, view.culling);
+        if let Some(bvh) = scene.bvh() {
+            // Objects without bounds have no model to draw, so the hierarchy leaving them out is fine
+            let visible = scene.objects.visibility();
+            let mut found = Vec::new();
+            bvh.query(|min, max| culling.intersects(min, max), |item| {
+                if visible[item.index] {
+                    found.push(item.id);
+                }
+            });
+            return Box::new(found.into_iter());
+        }
+        Box::new(scene.objects.iter()
+            .filter(|object| object.visible)
+            .filter(move |object| match object.world_bounds(&scene.assets) {
+                Some((min, max)) => culling.intersects(min, max),
+                None => true,
+            })
+            .map(|object| object.id))
+    }
+}
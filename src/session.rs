@@ -0,0 +1,229 @@
+//! Crash recovery: the camera pose and the file and transform of every loaded object, saved
+//! periodically so a scene arranged before a GPU crash or device loss can be restored on the
+//! next launch.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use crate::scene::camera::Camera;
+use crate::scene::Transform;
+
+/// Where the viewer keeps its recovery file, relative to the working directory
+pub const RECOVERY_FILE: &str = "session-recovery.toml";
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraPose {
+    pub fn of(camera: &Camera) -> Self {
+        Self { position: camera.position.to_array(), yaw: camera.yaw, pitch: camera.pitch }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = Vec3::from(self.position);
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+    }
+}
+
+/// One scene object: the file its model was loaded from and where it was placed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionObject {
+    pub model: PathBuf,
+    pub position: [f32; 3],
+    /// Euler XYZ, in radians
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    #[serde(default = "visible_by_default")]
+    pub visible: bool,
+}
+
+fn visible_by_default() -> bool {
+    true
+}
+
+impl SessionObject {
+    pub fn new(model: impl Into<PathBuf>, transform: &Transform, visible: bool) -> Self {
+        Self {
+            model: model.into(),
+            position: transform.position.to_array(),
+            rotation: transform.rotation.to_array(),
+            scale: transform.scale.to_array(),
+            visible,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform {
+            position: Vec3::from(self.position),
+            rotation: Vec3::from(self.rotation),
+            scale: Vec3::from(self.scale),
+        }
+    }
+}
+
+/// A snapshot of the scene that can be rebuilt from model files. Objects without a file
+/// (procedural models, the floor) aren't included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub camera: CameraPose,
+    #[serde(default)]
+    pub objects: Vec<SessionObject>,
+}
+
+impl Session {
+    pub fn new(camera: &Camera) -> Self {
+        Self { camera: CameraPose::of(camera), objects: Vec::new() }
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid session {}", path.display()))
+    }
+
+    /// Write through a temporary file and rename it into place, so a crash mid-write leaves
+    /// the previous save intact
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, &self.to_toml()?)
+    }
+
+    /// Model files the session uses, each once, in order of first use
+    pub fn model_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = Vec::new();
+        for object in &self.objects {
+            if !paths.contains(&object.model.as_path()) {
+                paths.push(&object.model);
+            }
+        }
+        paths
+    }
+}
+
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    std::fs::write(&temporary, contents)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    std::fs::rename(&temporary, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Saves sessions to a recovery file at most once per interval, skipping unchanged ones.
+/// `finish` deletes the file on a clean exit, so one found at startup means the last run crashed.
+pub struct Autosave {
+    path: PathBuf,
+    pub interval: Duration,
+    last_save: Option<Instant>,
+    last_written: Option<String>,
+}
+
+impl Autosave {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self { path: path.into(), interval, last_save: None, last_written: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The session a previous run left behind, if it didn't exit cleanly
+    pub fn recover(&self) -> Option<Session> {
+        if !self.path.exists() {
+            return None;
+        }
+        Session::load(&self.path)
+            .map_err(|e| log::warn!("Ignoring recovery file: {:#}", e))
+            .ok()
+    }
+
+    /// Whether the interval has passed since the last save
+    pub fn is_due(&self) -> bool {
+        self.last_save.is_none_or(|last| last.elapsed() >= self.interval)
+    }
+
+    /// Write `session` unless it matches the last save; returns whether the file was written
+    pub fn save(&mut self, session: &Session) -> Result<bool> {
+        self.last_save = Some(Instant::now());
+        let contents = session.to_toml()?;
+        if self.last_written.as_ref() == Some(&contents) {
+            return Ok(false);
+        }
+        write_atomically(&self.path, &contents)?;
+        self.last_written = Some(contents);
+        Ok(true)
+    }
+
+    /// Remove the recovery file after a clean exit
+    pub fn finish(&mut self) -> Result<()> {
+        self.last_written = None;
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut camera = Camera::new(Vec3::new(1.0, 2.0, 3.0), 1.0);
+        camera.yaw = 30.0;
+        let mut session = Session::new(&camera);
+        let transform = Transform { position: Vec3::new(4.0, 0.0, -1.0), ..Transform::new() };
+        session.objects.push(SessionObject::new("assets/chair.glb", &transform, true));
+        session.objects.push(SessionObject::new("assets/table.obj", &Transform::new(), false));
+        session.objects.push(SessionObject::new("assets/chair.glb", &Transform::new(), true));
+
+        let loaded = Session::from_toml(&session.to_toml().unwrap()).unwrap();
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.objects[0].transform().position, transform.position);
+        assert_eq!(loaded.model_paths(), vec![Path::new("assets/chair.glb"), Path::new("assets/table.obj")]);
+
+        let mut restored = Camera::new(Vec3::ZERO, 1.0);
+        loaded.camera.apply(&mut restored);
+        assert_eq!((restored.position, restored.yaw), (camera.position, 30.0));
+    }
+
+    #[test]
+    fn test_autosave() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(RECOVERY_FILE);
+        let mut autosave = Autosave::new(&path, Duration::from_secs(3600));
+        assert!(autosave.recover().is_none());
+        assert!(autosave.is_due());
+
+        let session = Session::new(&Camera::new(Vec3::ZERO, 1.0));
+        assert!(autosave.save(&session).unwrap());
+        assert!(!autosave.is_due());
+        assert!(!autosave.save(&session).unwrap());
+
+        // Left behind by a crash
+        assert_eq!(Autosave::new(&path, Duration::ZERO).recover(), Some(session));
+
+        autosave.finish().unwrap();
+        assert!(!path.exists());
+        assert!(autosave.finish().is_ok());
+    }
+}
@@ -0,0 +1,489 @@
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use crate::scene::{LensDistortion, LensPresets, MotionBlur, OutputAlpha, SsrQuality, Tonemapper};
+use crate::scene::lights::MAX_SHADOWED_LIGHTS;
+
+/// Default location of the settings file, relative to the working directory
+pub const SETTINGS_PATH: &str = "renderer.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    /// Shadow map resolution, or `None` when shadows are disabled
+    pub fn map_size(&self) -> Option<u32> {
+        match self {
+            ShadowQuality::Off => None,
+            ShadowQuality::Low => Some(1024),
+            ShadowQuality::Medium => Some(2048),
+            ShadowQuality::High => Some(4096),
+        }
+    }
+
+    /// PCF kernel radius in texels
+    pub fn filter_radius(&self) -> u32 {
+        match self {
+            ShadowQuality::Off | ShadowQuality::Low => 0,
+            ShadowQuality::Medium => 1,
+            ShadowQuality::High => 2,
+        }
+    }
+}
+
+/// Format of the scene's depth buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthFormat {
+    #[default]
+    Depth32,
+    /// 24-bit depth plus an 8-bit stencil, for `Scene::stencil` marks and stencil overlays
+    Depth24Stencil8,
+}
+
+impl DepthFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            DepthFormat::Depth32 => wgpu::TextureFormat::Depth32Float,
+            DepthFormat::Depth24Stencil8 => wgpu::TextureFormat::Depth24PlusStencil8,
+        }
+    }
+
+    pub fn has_stencil(self) -> bool {
+        self.texture_format().has_stencil_aspect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostSettings {
+    pub ssr: SsrQuality,
+    pub auto_exposure: bool,
+    pub exposure_ev: f32,
+    pub adaptation_speed: f32,
+    pub tonemapper: Tonemapper,
+    pub contrast: f32,
+    pub saturation: f32,
+    /// Strip-format LUT image (see `ColorLut::from_strip_image`)
+    pub lut: Option<PathBuf>,
+    pub lut_strength: f32,
+    /// Desktop only; headset views are never blurred
+    pub motion_blur: MotionBlur,
+    pub lens_preview: LensPreviewSettings,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            ssr: SsrQuality::Medium,
+            auto_exposure: false,
+            exposure_ev: 0.0,
+            adaptation_speed: 1.5,
+            tonemapper: Tonemapper::None,
+            contrast: 1.0,
+            saturation: 1.0,
+            lut: None,
+            lut_strength: 1.0,
+            motion_blur: MotionBlur::default(),
+            lens_preview: LensPreviewSettings::default(),
+        }
+    }
+}
+
+/// Distorts desktop views like a headset's lenses, so captures resemble the in-headset look
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LensPreviewSettings {
+    pub enabled: bool,
+    /// Name of a preset in `presets`
+    pub preset: String,
+    /// TOML file of named `LensDistortion` presets
+    pub presets: PathBuf,
+}
+
+impl Default for LensPreviewSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preset: "generic".to_string(),
+            presets: PathBuf::from("assets/lens_presets.toml"),
+        }
+    }
+}
+
+impl LensPreviewSettings {
+    /// The selected preset's distortion, or `None` when disabled
+    pub fn load(&self) -> Result<Option<LensDistortion>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let presets = LensPresets::load(&self.presets)?;
+        presets.get(&self.preset).map(Some).ok_or_else(|| {
+            let names: Vec<_> = presets.names().collect();
+            anyhow!("No lens preset named {:?} in {} (has {})", self.preset, self.presets.display(), names.join(", "))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VrComfortSettings {
+    /// Raises the tracking origin, e.g. for seated play (meters)
+    pub height_offset: f32,
+    /// Angle per snap turn in degrees; 0 disables snap turning
+    pub snap_turn_degrees: f32,
+}
+
+impl Default for VrComfortSettings {
+    fn default() -> Self {
+        Self {
+            height_offset: 0.0,
+            snap_turn_degrees: 30.0,
+        }
+    }
+}
+
+/// Which eye positions VR views are rendered from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StereoMode {
+    #[default]
+    Stereo,
+    /// Each eye rendered from the other's position; fixes a runtime or driver with inverted eyes
+    Swapped,
+    /// Both eyes rendered from the point between them, so the scene has no depth
+    Mono,
+}
+
+/// Overrides for the per-eye offsets the VR runtime reports, to diagnose stereo problems
+/// such as inverted eyes or a wrong sense of scale, and how the eyes are culled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VrStereoSettings {
+    pub mode: StereoMode,
+    /// Multiplies the eye separation; above 1 makes the world look smaller
+    pub ipd_scale: f32,
+    /// Eye separation to use instead of the runtime's (meters)
+    pub ipd: Option<f32>,
+    /// Also cull each eye against its own frustum, skipping objects at the outer edge of the
+    /// other eye's view; saves draws for a little CPU time per object
+    pub precise_culling: bool,
+}
+
+impl Default for VrStereoSettings {
+    fn default() -> Self {
+        Self {
+            mode: StereoMode::Stereo,
+            ipd_scale: 1.0,
+            ipd: None,
+            precise_culling: false,
+        }
+    }
+}
+
+/// Point light level of detail
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightSettings {
+    /// Shadow maps kept for the most important lights
+    pub shadow_budget: u32,
+    /// Shadow maps re-rendered per frame; the rest keep their previous contents
+    pub shadow_updates_per_frame: u32,
+    /// Lights closer than this many ranges get full-resolution shadows every frame;
+    /// each doubling of distance halves the resolution and update rate, and after two doublings shadows are dropped
+    pub lod_distance: f32,
+}
+
+impl Default for LightSettings {
+    fn default() -> Self {
+        Self {
+            shadow_budget: MAX_SHADOWED_LIGHTS as u32,
+            shadow_updates_per_frame: 2,
+            lod_distance: 2.0,
+        }
+    }
+}
+
+/// How the scene image is fitted to the window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ViewportMode {
+    /// Fill the window at whatever aspect ratio it has
+    Stretch,
+    /// Keep a width / height ratio, with black bars filling the rest of the window
+    Aspect { ratio: f32 },
+    /// Render at a fixed internal resolution, scaled to fit the window with bars
+    Fixed { width: u32, height: u32 },
+}
+
+/// Largest fixed internal resolution on either axis
+pub const MAX_FIXED_RESOLUTION: u32 = 8192;
+
+/// User-facing renderer configuration, persisted as TOML
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RendererSettings {
+    pub shadow_quality: ShadowQuality,
+    pub msaa_samples: u32,
+    pub depth_format: DepthFormat,
+    /// Scene render resolution relative to the window; unused by `ViewportMode::Fixed`
+    pub resolution_scale: f32,
+    pub viewport: ViewportMode,
+    /// Ground grid and origin axes
+    pub show_grid: bool,
+    /// Window transparency; the transparent modes need a surface and compositor that support
+    /// them and otherwise fall back to opaque
+    pub window_alpha: OutputAlpha,
+    pub post: PostSettings,
+    pub lights: LightSettings,
+    /// Not used by the renderer; `VRSystem::apply_settings` takes these up
+    pub vr_comfort: VrComfortSettings,
+    pub vr_stereo: VrStereoSettings,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            shadow_quality: ShadowQuality::Medium,
+            msaa_samples: 1,
+            depth_format: DepthFormat::default(),
+            resolution_scale: 1.0,
+            viewport: ViewportMode::Stretch,
+            show_grid: true,
+            window_alpha: OutputAlpha::default(),
+            post: PostSettings::default(),
+            lights: LightSettings::default(),
+            vr_comfort: VrComfortSettings::default(),
+            vr_stereo: VrStereoSettings::default(),
+        }
+    }
+}
+
+/// Which groups of resources differ between two settings snapshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettingsChanges {
+    pub shadows: bool,
+    pub msaa: bool,
+    pub depth_format: bool,
+    pub resolution: bool,
+    pub grid: bool,
+    pub window_alpha: bool,
+    pub post: bool,
+    pub lut: bool,
+    pub lens_preview: bool,
+    pub vr_comfort: bool,
+    pub vr_stereo: bool,
+}
+
+impl SettingsChanges {
+    pub fn any(&self) -> bool {
+        self.shadows || self.msaa || self.depth_format || self.resolution || self.grid || self.window_alpha || self.post || self.lut || self.lens_preview || self.vr_comfort || self.vr_stereo
+    }
+}
+
+impl RendererSettings {
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let settings: Self = toml::from_str(source)?;
+        Ok(settings.sanitized())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        Self::from_toml(&source)
+    }
+
+    /// Load settings from `path`, falling back to defaults if the file is missing or invalid
+    pub fn load_or_default(path: &Path) -> Self {
+        Self::load_or(path, Self::default())
+    }
+
+    /// Load settings from `path`, falling back to `defaults` if the file is missing or invalid,
+    /// e.g. `RendererSettings::for_tier` for the device's quality tier
+    pub fn load_or(path: &Path, defaults: Self) -> Self {
+        if !path.exists() {
+            return defaults;
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            log::warn!("Ignoring invalid settings file {}: {}", path.display(), e);
+            defaults
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Clamp values into ranges the renderer supports
+    pub fn sanitized(mut self) -> Self {
+        // Only 1x and 4x are guaranteed for the HDR and depth formats
+        self.msaa_samples = if self.msaa_samples > 1 { 4 } else { 1 };
+        self.resolution_scale = self.resolution_scale.clamp(0.25, 2.0);
+        self.viewport = match self.viewport {
+            ViewportMode::Aspect { ratio } if !ratio.is_finite() => ViewportMode::Stretch,
+            ViewportMode::Aspect { ratio } => ViewportMode::Aspect { ratio: ratio.clamp(0.1, 10.0) },
+            ViewportMode::Fixed { width, height } => ViewportMode::Fixed {
+                width: width.clamp(1, MAX_FIXED_RESOLUTION),
+                height: height.clamp(1, MAX_FIXED_RESOLUTION),
+            },
+            ViewportMode::Stretch => ViewportMode::Stretch,
+        };
+        self.post.lut_strength = self.post.lut_strength.clamp(0.0, 1.0);
+        self.post.adaptation_speed = self.post.adaptation_speed.max(0.0);
+        self.lights.shadow_budget = self.lights.shadow_budget.min(MAX_SHADOWED_LIGHTS as u32);
+        self.lights.lod_distance = self.lights.lod_distance.max(0.0);
+        self.vr_comfort.snap_turn_degrees = self.vr_comfort.snap_turn_degrees.clamp(0.0, 180.0);
+        self.vr_stereo.ipd_scale = self.vr_stereo.ipd_scale.clamp(0.0, 4.0);
+        self.vr_stereo.ipd = self.vr_stereo.ipd.filter(|ipd| ipd.is_finite()).map(|ipd| ipd.clamp(0.0, 0.2));
+        self
+    }
+
+    pub fn changes(&self, other: &Self) -> SettingsChanges {
+        SettingsChanges {
+            shadows: self.shadow_quality != other.shadow_quality,
+            msaa: self.msaa_samples != other.msaa_samples,
+            depth_format: self.depth_format != other.depth_format,
+            resolution: self.resolution_scale != other.resolution_scale || self.viewport != other.viewport,
+            grid: self.show_grid != other.show_grid,
+            window_alpha: self.window_alpha != other.window_alpha,
+            post: self.post != other.post,
+            lut: self.post.lut != other.post.lut,
+            lens_preview: self.post.lens_preview != other.post.lens_preview,
+            vr_comfort: self.vr_comfort != other.vr_comfort,
+            vr_stereo: self.vr_stereo != other.vr_stereo,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings = RendererSettings {
+            shadow_quality: ShadowQuality::High,
+            msaa_samples: 4,
+            depth_format: DepthFormat::Depth24Stencil8,
+            window_alpha: OutputAlpha::PreMultiplied,
+            post: PostSettings {
+                ssr: SsrQuality::Off,
+                tonemapper: Tonemapper::Aces,
+                lut: Some(PathBuf::from("assets/luts/warm.png")),
+                motion_blur: MotionBlur { enabled: true, shutter_angle: 90.0, ..MotionBlur::default() },
+                ..PostSettings::default()
+            },
+            ..RendererSettings::default()
+        };
+
+        let toml = settings.to_toml().unwrap();
+        let loaded = RendererSettings::from_toml(&toml).unwrap();
+        assert_eq!(loaded, settings);
+    }
+
+    #[test]
+    fn test_partial_settings_use_defaults() {
+        let settings = RendererSettings::from_toml(
+            r#"
+            shadow_quality = "low"
+
+            [post]
+            ssr = "high"
+            "#,
+        ).unwrap();
+
+        assert_eq!(settings.shadow_quality, ShadowQuality::Low);
+        assert_eq!(settings.post.ssr, SsrQuality::High);
+        assert_eq!(settings.msaa_samples, 1);
+        assert_eq!(settings.depth_format, DepthFormat::Depth32);
+        assert_eq!(settings.window_alpha, OutputAlpha::Opaque);
+        assert_eq!(settings.vr_comfort, VrComfortSettings::default());
+    }
+
+    #[test]
+    fn test_settings_sanitized() {
+        let settings = RendererSettings::from_toml("msaa_samples = 8\nresolution_scale = 10.0").unwrap();
+        assert_eq!(settings.msaa_samples, 4);
+        assert_eq!(settings.resolution_scale, 2.0);
+
+        let settings = RendererSettings::from_toml("[vr_stereo]\nmode = \"swapped\"\nipd_scale = -1.0\nipd = 0.5").unwrap();
+        assert_eq!(settings.vr_stereo, VrStereoSettings { mode: StereoMode::Swapped, ipd_scale: 0.0, ipd: Some(0.2), precise_culling: false });
+
+        assert!(RendererSettings::from_toml("shadow_quality = \"ultra\"").is_err());
+    }
+
+    #[test]
+    fn test_viewport_mode_toml() {
+        let settings = RendererSettings::from_toml("[viewport]\nmode = \"aspect\"\nratio = 1.5").unwrap();
+        assert_eq!(settings.viewport, ViewportMode::Aspect { ratio: 1.5 });
+
+        let settings = RendererSettings::from_toml("[viewport]\nmode = \"fixed\"\nwidth = 0\nheight = 100000").unwrap();
+        assert_eq!(settings.viewport, ViewportMode::Fixed { width: 1, height: MAX_FIXED_RESOLUTION });
+
+        let toml = RendererSettings { viewport: ViewportMode::Aspect { ratio: 2.0 }, ..RendererSettings::default() }
+            .to_toml()
+            .unwrap();
+        assert_eq!(RendererSettings::from_toml(&toml).unwrap().viewport, ViewportMode::Aspect { ratio: 2.0 });
+        assert!(RendererSettings::default().changes(&RendererSettings::from_toml(&toml).unwrap()).resolution);
+    }
+
+    #[test]
+    fn test_settings_changes() {
+        let base = RendererSettings::default();
+        assert!(!base.changes(&base).any());
+
+        let mut other = base.clone();
+        other.post.contrast = 1.2;
+        let changes = base.changes(&other);
+        assert!(changes.post && !changes.lut && !changes.shadows && !changes.msaa);
+
+        other.post.lut = Some(PathBuf::from("lut.png"));
+        other.resolution_scale = 0.5;
+        let changes = base.changes(&other);
+        assert!(changes.lut && changes.resolution && !changes.lens_preview);
+
+        other.post.lens_preview.preset = "index".to_string();
+        assert!(base.changes(&other).lens_preview);
+    }
+
+    #[test]
+    fn test_lens_preview_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let presets = dir.path().join("lenses.toml");
+        std::fs::write(&presets, "[wide]\nk1 = 0.4\n").unwrap();
+
+        let lens = LensPreviewSettings { preset: "wide".to_string(), presets, ..LensPreviewSettings::default() };
+        assert_eq!(lens.load().unwrap(), None, "Disabled previews don't read the presets");
+        let lens = LensPreviewSettings { enabled: true, ..lens };
+        assert_eq!(lens.load().unwrap().map(|distortion| distortion.k1), Some(0.4));
+
+        let missing = LensPreviewSettings { preset: "narrow".to_string(), ..lens.clone() };
+        assert!(missing.load().unwrap_err().to_string().contains("wide"), "Lists the presets there are");
+        let unreadable = LensPreviewSettings { presets: dir.path().join("none.toml"), ..lens };
+        assert!(unreadable.load().is_err());
+    }
+
+    #[test]
+    fn test_load_or_default_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("renderer.toml");
+        assert_eq!(RendererSettings::load_or_default(&path), RendererSettings::default());
+
+        let settings = RendererSettings {
+            resolution_scale: 0.75,
+            ..RendererSettings::default()
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(RendererSettings::load_or_default(&path), settings);
+    }
+}
@@ -0,0 +1,286 @@
+//! WGSL snippets shared between shaders, pulled in with `#include "name"` lines. Each snippet
+//! is expanded at most once per shader, so snippets can include what they need without
+//! clashing definitions. Resolved sources are identified by a hash of their content.
+
+use std::collections::HashMap;
+use anyhow::{anyhow, bail, Result};
+
+/// The snippets in `shaders/include`, plus the code the VR shaders share, by the name
+/// `#include` uses
+pub const BUILTIN_SNIPPETS: &[(&str, &str)] = &[
+    ("color", include_str!("../shaders/include/color.wgsl")),
+    ("fullscreen", include_str!("../shaders/include/fullscreen.wgsl")),
+    ("lighting", include_str!("../shaders/include/lighting.wgsl")),
+    ("noise", include_str!("../shaders/include/noise.wgsl")),
+    ("vr", include_str!("vr/shaders/common.wgsl")),
+];
+
+/// A shader with its includes expanded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedShader {
+    pub source: String,
+    /// `content_hash` of `source`: shaders that expand to the same code share it
+    pub hash: u64,
+}
+
+/// Named snippets `#include` can refer to
+#[derive(Debug, Clone, Default)]
+pub struct ShaderLibrary {
+    snippets: HashMap<String, String>,
+}
+
+impl ShaderLibrary {
+    /// The engine's own snippets, `BUILTIN_SNIPPETS`
+    pub fn builtin() -> Self {
+        let mut library = Self::default();
+        for (name, source) in BUILTIN_SNIPPETS {
+            library.add(name, source);
+        }
+        library
+    }
+
+    /// Add or replace a snippet
+    pub fn add(&mut self, name: &str, source: &str) {
+        self.snippets.insert(name.to_string(), source.to_string());
+    }
+
+    /// Expand `source`'s `#include "name"` lines, recursively. Including a snippet that is
+    /// already being expanded is an error; including one that was expanded earlier leaves a
+    /// blank line.
+    pub fn resolve(&self, source: &str) -> Result<ResolvedShader> {
+        let mut output = String::with_capacity(source.len());
+        let mut included = Vec::new();
+        self.expand(source, &mut Vec::new(), &mut included, &mut output)?;
+        Ok(ResolvedShader { hash: content_hash(&output), source: output })
+    }
+
+    fn expand<'a>(&'a self, source: &str, stack: &mut Vec<&'a str>, included: &mut Vec<&'a str>, output: &mut String) -> Result<()> {
+        for (number, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            if tokens.next() != Some("#include") {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+            let location = || match stack.last() {
+                Some(snippet) => format!("\"{}\" line {}", snippet, number + 1),
+                None => format!("line {}", number + 1),
+            };
+            let Some(name) = tokens.next().and_then(|name| name.strip_prefix('"')?.strip_suffix('"')) else {
+                bail!("{}: #include needs a quoted snippet name", location());
+            };
+            let Some((name, snippet)) = self.snippets.get_key_value(name) else {
+                bail!("{}: unknown shader snippet \"{}\"", location(), name);
+            };
+            if stack.contains(&name.as_str()) {
+                let cycle: Vec<_> = stack.iter().copied().chain([name.as_str()]).collect();
+                bail!("{}: include cycle {}", location(), cycle.join(" -> "));
+            }
+            if included.contains(&name.as_str()) {
+                output.push('\n');
+                continue;
+            }
+            included.push(name);
+            stack.push(name);
+            self.expand(snippet, stack, included, output)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+}
+
+/// Expand `source`'s includes from the built-in snippets
+pub fn resolve(source: &str) -> Result<ResolvedShader> {
+    ShaderLibrary::builtin().resolve(source)
+}
+
+/// A shader module from one of the engine's own shaders; their includes are fixed at build
+/// time, so a bad one is a bug
+pub fn create_module(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    let resolved = resolve(source).unwrap_or_else(|e| panic!("Invalid includes in {}: {:#}", label, e));
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(resolved.source.into()),
+    })
+}
+
+/// 64-bit FNV-1a of `source`, stable across runs and platforms so it can key cached pipelines
+pub fn content_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Where a field of a Rust uniform struct lives, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// The layout of a `#[repr(C)]` struct uploaded to a uniform buffer, from `uniform_layout!`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniformLayout {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: Vec<FieldLayout>,
+}
+
+/// `UniformLayout` of a struct, listing the fields the shader sees:
+/// `uniform_layout!(CameraUniform { view_proj, camera_pos })`
+#[macro_export]
+macro_rules! uniform_layout {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        $crate::shaders::UniformLayout {
+            name: stringify!($type),
+            size: std::mem::size_of::<$type>(),
+            fields: vec![$($crate::shaders::FieldLayout {
+                name: stringify!($field),
+                offset: std::mem::offset_of!($type, $field),
+                size: $crate::shaders::field_size(|value: &$type| &value.$field),
+            }),*],
+        }
+    };
+}
+
+#[doc(hidden)]
+pub fn field_size<T, F>(_field: impl FnOnce(&T) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+/// Check `host` against WGSL struct `name` in `source`, which must have its includes and
+/// `#ifdef`s resolved. Offsets, sizes and the total size must all match; Rust fields whose
+/// names start with `_` are padding the shader doesn't see. The error lists every mismatch,
+/// since std140-style surprises (a `vec3` taking 16 bytes, array strides) rarely come alone.
+pub fn check_uniform_layout(source: &str, name: &str, host: &UniformLayout) -> Result<()> {
+    use wgpu::naga;
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| anyhow!("{}", e.emit_to_string(source)))?;
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(module.to_ctx()).map_err(|e| anyhow!("{}", e))?;
+    let Some(ty) = module.types.iter().map(|(_, ty)| ty).find(|ty| ty.name.as_deref() == Some(name)) else {
+        bail!("WGSL struct {} not found", name);
+    };
+    let naga::TypeInner::Struct { members, span } = &ty.inner else {
+        bail!("WGSL type {} isn't a struct", name);
+    };
+
+    let mut problems = Vec::new();
+    if host.size != *span as usize {
+        problems.push(format!("size is {} bytes in Rust but {} in WGSL", host.size, span));
+    }
+    for member in members {
+        let member_name = member.name.as_deref().unwrap_or("<unnamed>");
+        let Some(field) = host.fields.iter().find(|field| field.name == member_name) else {
+            problems.push(format!("`{}` is missing in Rust", member_name));
+            continue;
+        };
+        if field.offset != member.offset as usize {
+            problems.push(format!("`{}` is at byte {} in Rust but {} in WGSL", member_name, field.offset, member.offset));
+        }
+        let size = layouter[member.ty].size as usize;
+        if field.size != size {
+            problems.push(format!("`{}` is {} bytes in Rust but {} in WGSL", member_name, field.size, size));
+        }
+    }
+    for field in host.fields.iter().filter(|field| !field.name.starts_with('_')) {
+        if !members.iter().any(|member| member.name.as_deref() == Some(field.name)) {
+            problems.push(format!("`{}` is missing in WGSL", field.name));
+        }
+    }
+    if !problems.is_empty() {
+        bail!("Rust {} doesn't match WGSL struct {}: {}", host.name, name, problems.join("; "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> ShaderLibrary {
+        let mut library = ShaderLibrary::default();
+        library.add("a", "fn a() {}");
+        library.add("b", "#include \"a\"\nfn b() { a(); }");
+        library
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let library = library();
+        let resolved = library.resolve("#include \"b\"\n#include \"a\"\nfn main() { b(); }").unwrap();
+        // `a` is pulled in by `b` and not repeated
+        assert_eq!(resolved.source, "fn a() {}\nfn b() { a(); }\n\nfn main() { b(); }\n");
+
+        // Same code, same hash, however it was split into snippets
+        let inline = library.resolve("fn a() {}\nfn b() { a(); }\n\nfn main() { b(); }").unwrap();
+        assert_eq!(inline.hash, resolved.hash);
+        assert_ne!(library.resolve("fn main() {}").unwrap().hash, resolved.hash);
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let mut library = library();
+        assert!(library.resolve("#include \"missing\"").is_err());
+        assert!(library.resolve("#include a").is_err());
+
+        library.add("a", "#include \"b\"");
+        let error = library.resolve("#include \"a\"").unwrap_err().to_string();
+        assert!(error.contains("a -> b -> a"), "{}", error);
+    }
+
+    #[test]
+    fn test_uniform_layout() {
+        #[repr(C)]
+        struct Light {
+            direction: [f32; 3],
+            intensity: f32,
+            color: [f32; 3],
+            _padding: f32,
+        }
+        let host = uniform_layout!(Light { direction, intensity, color, _padding });
+        assert_eq!(host.fields[1], FieldLayout { name: "intensity", offset: 12, size: 4 });
+
+        let wgsl = "struct Light { direction: vec3<f32>, intensity: f32, color: vec3<f32> };";
+        check_uniform_layout(wgsl, "Light", &host).unwrap();
+
+        // vec3s align to 16 bytes, so a scalar can only fill the gap after one
+        let wgsl = "struct Light { direction: vec3<f32>, color: vec3<f32>, intensity: f32 };";
+        let error = check_uniform_layout(wgsl, "Light", &host).unwrap_err().to_string();
+        assert!(error.contains("`intensity` is at byte 12 in Rust but 28 in WGSL"), "{}", error);
+
+        let error = check_uniform_layout("struct Light { direction: vec3<f32> };", "Light", &host).unwrap_err().to_string();
+        assert!(error.contains("size is 32 bytes in Rust but 16 in WGSL"), "{}", error);
+        assert!(error.contains("`intensity` is missing in WGSL"), "{}", error);
+        assert!(!error.contains("_padding"), "{}", error);
+        assert!(check_uniform_layout(wgsl, "Camera", &host).is_err());
+    }
+
+    #[test]
+    fn test_builtin_shaders_resolve() {
+        use wgpu::naga;
+        let shaders = [
+            include_str!("../shaders/depth_resolve.wgsl"),
+            include_str!("../shaders/exposure.wgsl"),
+            include_str!("../shaders/eye_debug.wgsl"),
+            include_str!("../shaders/grading.wgsl"),
+            include_str!("../shaders/inspector.wgsl"),
+            include_str!("../shaders/motion_blur.wgsl"),
+            include_str!("../shaders/lens.wgsl"),
+            include_str!("../shaders/oit.wgsl"),
+            include_str!("../shaders/ssr.wgsl"),
+            include_str!("../shaders/stencil.wgsl"),
+            include_str!("vr/shaders/vr.wgsl"),
+            include_str!("vr/shaders/vr_eye.wgsl"),
+            // Snippets only shaders of the application's own include
+            "#include \"noise\"",
+        ];
+        // The VR shader draws both eyes with `@builtin(view_index)`
+        let mut validator = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::MULTIVIEW);
+        for shader in shaders {
+            let resolved = resolve(shader).unwrap();
+            let module = naga::front::wgsl::parse_str(&resolved.source)
+                .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&resolved.source)));
+            validator.validate(&module)
+                .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&resolved.source)));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Deterministic simulation: a fixed timestep, a seeded random number generator and input
+//! recording, so a session replayed from the same starting scene runs exactly as recorded.
+//! Useful for reproducing interaction bugs and as input for regression tests.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::input::InputEvent;
+
+/// Seconds per update in deterministic mode unless chosen otherwise
+pub const DEFAULT_TIMESTEP: f32 = 1.0 / 60.0;
+
+/// Small, fast PCG32 generator; the same seed always gives the same sequence on every platform
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u32) -> Self {
+        let mut rng = Self { state: 0, increment: (0xda3e39cb94b95bdb << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed as u64);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.increment);
+        let shifted = (((old >> 18) ^ old) >> 27) as u32;
+        shifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform in 0 - 1, excluding 1
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Uniform in `min` - `max`, excluding `max`
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniform in 0 - `n`, excluding `n`; 0 when `n` is 0
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u32() as u64 * n as u64) >> 32) as u32
+    }
+}
+
+/// Input events that arrived in one update
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub frame: u64,
+    pub events: Vec<InputEvent>,
+}
+
+/// Everything needed to replay a deterministic session: its seed and timestep, and the input
+/// of every update that had any. Saved as TOML.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputRecording {
+    pub seed: u32,
+    pub timestep: f32,
+    /// Updates recorded, including those without input
+    pub frame_count: u64,
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl InputRecording {
+    pub fn new(seed: u32, timestep: f32) -> Self {
+        Self { seed, timestep, frame_count: 0, frames: Vec::new() }
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self> {
+        Ok(toml::from_str(source)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read input recording {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("Invalid input recording {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_toml()?)
+            .with_context(|| format!("Failed to write input recording {}", path.display()))
+    }
+
+    /// Input of update `frame`; empty for updates without any
+    pub fn events(&self, frame: u64) -> &[InputEvent] {
+        match self.frames.binary_search_by_key(&frame, |recorded| recorded.frame) {
+            Ok(index) => &self.frames[index].events,
+            Err(_) => &[],
+        }
+    }
+
+    fn push(&mut self, events: &[InputEvent]) {
+        if !events.is_empty() {
+            self.frames.push(RecordedFrame { frame: self.frame_count, events: events.to_vec() });
+        }
+        self.frame_count += 1;
+    }
+}
+
+enum Mode {
+    Live,
+    Deterministic,
+    Recording(InputRecording),
+    Replaying(InputRecording),
+}
+
+/// How `State::update` advances time and where its input comes from. Live simulations follow
+/// the wall clock; deterministic ones step by a fixed timestep and can record their input, or
+/// replay a recording in place of live input.
+///
+/// Background model loads and GPU picking finish on their own schedule, so scenes that
+/// depend on them aren't reproducible.
+pub struct Simulation {
+    mode: Mode,
+    seed: u32,
+    timestep: f32,
+    rng: Rng,
+    frame: u64,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::live()
+    }
+}
+
+impl Simulation {
+    /// Wall-clock time, with the random generator seeded from it
+    pub fn live() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+        Self::with_mode(Mode::Live, nanos, DEFAULT_TIMESTEP)
+    }
+
+    pub fn deterministic(seed: u32, timestep: f32) -> Self {
+        Self::with_mode(Mode::Deterministic, seed, timestep)
+    }
+
+    /// Deterministic, keeping each update's input for [`take_recording`](Self::take_recording)
+    pub fn recording(seed: u32, timestep: f32) -> Self {
+        Self::with_mode(Mode::Recording(InputRecording::new(seed, timestep)), seed, timestep)
+    }
+
+    /// Deterministic with the recording's seed and timestep, feeding it its recorded input
+    pub fn replay(recording: InputRecording) -> Self {
+        let (seed, timestep) = (recording.seed, recording.timestep);
+        Self::with_mode(Mode::Replaying(recording), seed, timestep)
+    }
+
+    fn with_mode(mode: Mode, seed: u32, timestep: f32) -> Self {
+        Self { mode, seed, timestep: timestep.max(1e-4), rng: Rng::new(seed), frame: 0 }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        !matches!(self.mode, Mode::Live)
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replaying(_))
+    }
+
+    /// Whether a replay has fed all its recorded updates
+    pub fn replay_finished(&self) -> bool {
+        match &self.mode {
+            Mode::Replaying(recording) => self.frame >= recording.frame_count,
+            _ => false,
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Seconds per update, or `None` when following the wall clock
+    pub fn timestep(&self) -> Option<f32> {
+        self.is_deterministic().then_some(self.timestep)
+    }
+
+    /// Updates run so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The random generator systems should draw from so replays make the same choices
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Start the next update: returns the input it should see, recording `live` events or
+    /// replacing them with recorded ones
+    pub fn next_frame(&mut self, live: impl IntoIterator<Item = InputEvent>) -> Vec<InputEvent> {
+        let events = match &mut self.mode {
+            Mode::Replaying(recording) => {
+                // Live input would make the replay diverge
+                live.into_iter().for_each(drop);
+                recording.events(self.frame).to_vec()
+            }
+            Mode::Recording(recording) => {
+                let events: Vec<_> = live.into_iter().collect();
+                recording.push(&events);
+                events
+            }
+            Mode::Live | Mode::Deterministic => live.into_iter().collect(),
+        };
+        self.frame += 1;
+        events
+    }
+
+    /// The input recorded so far, leaving the simulation deterministic but no longer recording
+    pub fn take_recording(&mut self) -> Option<InputRecording> {
+        match std::mem::replace(&mut self.mode, Mode::Deterministic) {
+            Mode::Recording(recording) => Some(recording),
+            mode => {
+                self.mode = mode;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Key;
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let first: Vec<_> = (0..8).map(|_| a.next_u32()).collect();
+        assert_eq!(first, (0..8).map(|_| b.next_u32()).collect::<Vec<_>>());
+        assert_ne!(first, (0..8).map(|_| Rng::new(43).next_u32()).collect::<Vec<_>>());
+
+        for _ in 0..1000 {
+            let value = a.range(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&value));
+            assert!(a.below(7) < 7);
+        }
+        assert_eq!(a.below(0), 0);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let press = InputEvent::Key { key: Key::KeyW, pressed: true };
+        let trigger = InputEvent::VrAction { name: "trigger".into(), value: 0.5 };
+
+        let mut recorder = Simulation::recording(7, 0.02);
+        assert_eq!(recorder.timestep(), Some(0.02));
+        assert_eq!(recorder.next_frame([press.clone()]), vec![press.clone()]);
+        recorder.next_frame([]);
+        recorder.next_frame([trigger.clone(), InputEvent::MouseMotion { dx: 1.0, dy: -2.0 }]);
+        let draws: Vec<_> = (0..4).map(|_| recorder.rng().next_u32()).collect();
+
+        // Through the file format and back
+        let recording = recorder.take_recording().unwrap();
+        assert!(recorder.take_recording().is_none());
+        let recording = InputRecording::from_toml(&recording.to_toml().unwrap()).unwrap();
+        assert_eq!(recording.frame_count, 3);
+        assert_eq!(recording.frames.len(), 2);
+
+        // Recorded input replaces live input, and random draws repeat
+        let mut replay = Simulation::replay(recording);
+        assert_eq!(replay.next_frame([InputEvent::Scroll { delta: 1.0 }]), vec![press]);
+        assert!(replay.next_frame([]).is_empty());
+        assert!(!replay.replay_finished());
+        assert_eq!(replay.next_frame([])[0], trigger);
+        assert!(replay.replay_finished());
+        assert_eq!((0..4).map(|_| replay.rng().next_u32()).collect::<Vec<_>>(), draws);
+
+        assert_eq!(Simulation::live().timestep(), None);
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use openxr as xr;
+use crate::input::InputEvent;
+use super::hud::TOGGLE_ACTION;
+
+/// Suggested controller buttons for the HUD toggle, by interaction profile; the runtime
+/// may remap them, and profiles not listed get no binding
+const HUD_BINDINGS: &[(&str, &str)] = &[
+    ("/interaction_profiles/khr/simple_controller", "/user/hand/left/input/menu/click"),
+    ("/interaction_profiles/oculus/touch_controller", "/user/hand/left/input/menu/click"),
+    ("/interaction_profiles/valve/index_controller", "/user/hand/left/input/b/click"),
+];
+
+/// The viewer's controller buttons, read once a frame into `InputEvent::VrAction`s
+pub struct VrActions {
+    action_set: xr::ActionSet,
+    hud_toggle: xr::Action<bool>,
+}
+
+impl VrActions {
+    /// Create the actions and attach them to `session`; OpenXR allows this once per session
+    pub fn new(instance: &xr::Instance, session: &xr::Session<xr::Vulkan>) -> Result<Self> {
+        let action_set = instance.create_action_set("viewer", "Viewer", 0)?;
+        let hud_toggle = action_set.create_action::<bool>(TOGGLE_ACTION, "Toggle HUD", &[])?;
+        for &(profile, path) in HUD_BINDINGS {
+            let binding = xr::Binding::new(&hud_toggle, instance.string_to_path(path)?);
+            // Runtimes may not know every profile; the others still apply
+            if let Err(e) = instance.suggest_interaction_profile_bindings(instance.string_to_path(profile)?, &[binding]) {
+                log::debug!("The VR runtime rejected the bindings for {}: {}", profile, e);
+            }
+        }
+        session.attach_action_sets(&[&action_set])?;
+        Ok(Self { action_set, hud_toggle })
+    }
+
+    /// Sync the actions and report each button as 1 while held, for `InputState::begin_frame`
+    pub fn poll(&self, session: &xr::Session<xr::Vulkan>) -> Result<Vec<InputEvent>> {
+        session.sync_actions(&[xr::ActiveActionSet::new(&self.action_set)])?;
+        let state = self.hud_toggle.state(session, xr::Path::NULL)?;
+        let held = state.is_active && state.current_state;
+        Ok(vec![InputEvent::VrAction { name: TOGGLE_ACTION.to_string(), value: if held { 1.0 } else { 0.0 } }])
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use super::system::VRSystem;
+use super::vulkan::get_vulkan_physical_device_from_adapter;
+
+/// Environment variable naming the adapter to use instead of the runtime's choice, matched
+/// case-insensitively against any part of the adapter name (e.g. `nvidia`)
+pub const ADAPTER_OVERRIDE_ENV: &str = "WGPU_ADAPTER_NAME";
+
+/// What adapter selection needs to know about one adapter
+struct Candidate {
+    name: String,
+    device_type: wgpu::DeviceType,
+    /// Whether this is the GPU the runtime drives the headset from
+    required: bool,
+}
+
+/// Index of the adapter to use: the one `name` matches if given, else the runtime's, else the
+/// first discrete GPU, else the first
+fn choose(candidates: &[Candidate], name: Option<&str>) -> Result<usize> {
+    if candidates.is_empty() {
+        anyhow::bail!("No Vulkan adapters found");
+    }
+    if let Some(name) = name {
+        let name = name.to_lowercase();
+        return candidates.iter()
+            .position(|candidate| candidate.name.to_lowercase().contains(&name))
+            .ok_or_else(|| {
+                let names: Vec<_> = candidates.iter().map(|candidate| candidate.name.as_str()).collect();
+                anyhow::anyhow!("No adapter matches {:?}; found {}", name, names.join(", "))
+            });
+    }
+    Ok(candidates.iter().position(|candidate| candidate.required)
+        .or_else(|| candidates.iter().position(|candidate| candidate.device_type == wgpu::DeviceType::DiscreteGpu))
+        .unwrap_or(0))
+}
+
+/// Pick the adapter to create a VR device on from a Vulkan `instance`. On machines with several
+/// GPUs (an integrated and a discrete one in a laptop) `request_adapter` may return one the
+/// headset isn't connected to, and session creation then fails; this asks the runtime which
+/// physical device it requires (XR_KHR_vulkan_enable2) and returns its adapter instead.
+/// `name_override`, or failing that `WGPU_ADAPTER_NAME`, forces a different one.
+pub fn select_vr_adapter(instance: &wgpu::Instance, vr: &VRSystem, name_override: Option<&str>) -> Result<wgpu::Adapter> {
+    let mut adapters = instance.enumerate_adapters(wgpu::Backends::VULKAN);
+    let required = vr.required_physical_device(instance)
+        .map_err(|e| log::warn!("The VR runtime didn't name a GPU: {:#}", e))
+        .ok();
+
+    let candidates: Vec<_> = adapters.iter()
+        .map(|adapter| {
+            let info = adapter.get_info();
+            Candidate {
+                name: info.name,
+                device_type: info.device_type,
+                required: required.is_some() && get_vulkan_physical_device_from_adapter(adapter) == required,
+            }
+        })
+        .collect();
+    let environment = std::env::var(ADAPTER_OVERRIDE_ENV).ok().filter(|name| !name.is_empty());
+    let index = choose(&candidates, name_override.or(environment.as_deref()))?;
+
+    let chosen = &candidates[index];
+    if required.is_some() && !candidates.iter().any(|candidate| candidate.required) {
+        log::warn!("None of the Vulkan adapters is the GPU the VR runtime requires");
+    } else if required.is_some() && !chosen.required {
+        log::warn!("Using {} for VR by request, though the runtime requires a different GPU", chosen.name);
+    }
+    log::info!("VR adapter: {} ({:?})", chosen.name, chosen.device_type);
+    Ok(adapters.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str, device_type: wgpu::DeviceType, required: bool) -> Candidate {
+        Candidate { name: name.to_string(), device_type, required }
+    }
+
+    #[test]
+    fn test_choose_adapter() {
+        let laptop = [
+            candidate("Intel(R) UHD Graphics", wgpu::DeviceType::IntegratedGpu, false),
+            candidate("NVIDIA GeForce RTX 3070 Laptop GPU", wgpu::DeviceType::DiscreteGpu, false),
+            candidate("AMD Radeon RX 6600", wgpu::DeviceType::DiscreteGpu, true),
+        ];
+        // The runtime's GPU wins over the first discrete one
+        assert_eq!(choose(&laptop, None).unwrap(), 2);
+        assert_eq!(choose(&laptop, Some("geforce")).unwrap(), 1);
+        assert!(choose(&laptop, Some("Apple")).unwrap_err().to_string().contains("Intel(R) UHD Graphics"));
+
+        // Without an answer from the runtime, prefer a discrete GPU
+        let unknown = [
+            candidate("llvmpipe", wgpu::DeviceType::Cpu, false),
+            candidate("Intel(R) UHD Graphics", wgpu::DeviceType::IntegratedGpu, false),
+            candidate("NVIDIA GeForce RTX 3070 Laptop GPU", wgpu::DeviceType::DiscreteGpu, false),
+        ];
+        assert_eq!(choose(&unknown, None).unwrap(), 2);
+        assert_eq!(choose(&unknown[..2], None).unwrap(), 0);
+        assert!(choose(&[], None).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::ffi::{c_char, c_void, CStr};
+use anyhow::Result;
+use openxr as xr;
+use xr::sys::{DebugUtilsMessageSeverityFlagsEXT as Severity, DebugUtilsMessageTypeFlagsEXT as MessageType};
+
+/// Khronos' validation layer, which checks every OpenXR call the engine makes
+pub const VALIDATION_LAYER: &str = "XR_APILAYER_LUNARG_core_validation";
+
+/// Environment variable turning on OpenXR debugging: `1`/`all` for everything, or a comma
+/// separated list of `validation` and `messages`
+pub const DEBUG_ENV: &str = "XR_DEBUG";
+
+/// Log target of messages from the runtime and layers, for filtering with `RUST_LOG`
+pub const LOG_TARGET: &str = "openxr";
+
+/// OpenXR debugging aids to turn on when creating the instance. Both cost frame time, so they
+/// stay off unless asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VrDebugOptions {
+    /// Load `VALIDATION_LAYER`, when installed
+    pub validation_layer: bool,
+    /// Route XR_EXT_debug_utils messages into the engine log
+    pub debug_messages: bool,
+}
+
+impl VrDebugOptions {
+    pub fn all() -> Self {
+        Self { validation_layer: true, debug_messages: true }
+    }
+
+    /// Options from `XR_DEBUG`
+    pub fn from_env() -> Self {
+        Self::parse(std::env::var(DEBUG_ENV).ok().as_deref().unwrap_or(""))
+    }
+
+    fn parse(value: &str) -> Self {
+        let mut options = Self::default();
+        for option in value.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            match option.to_lowercase().as_str() {
+                "1" | "all" | "true" => options = Self::all(),
+                "validation" => options.validation_layer = true,
+                "messages" => options.debug_messages = true,
+                "0" | "none" | "false" => options = Self::default(),
+                _ => log::warn!("Ignoring unknown {} option {:?}", DEBUG_ENV, option),
+            }
+        }
+        options
+    }
+}
+
+/// An XR_EXT_debug_utils messenger logging what the runtime and layers report, which would
+/// otherwise only show up as a bare error code. Destroyed on drop, which must happen before
+/// the instance is.
+pub struct DebugMessenger {
+    messenger: xr::sys::DebugUtilsMessengerEXT,
+    destroy: xr::sys::pfn::DestroyDebugUtilsMessengerEXT,
+}
+
+impl DebugMessenger {
+    /// Start logging messages of every severity; `instance` must have been created with
+    /// XR_EXT_debug_utils enabled
+    pub fn new(instance: &xr::Instance) -> Result<Self> {
+        let debug_utils = instance.exts().ext_debug_utils
+            .ok_or_else(|| anyhow::anyhow!("XR_EXT_debug_utils isn't enabled on the instance"))?;
+        let create_info = xr::sys::DebugUtilsMessengerCreateInfoEXT {
+            ty: xr::sys::DebugUtilsMessengerCreateInfoEXT::TYPE,
+            next: std::ptr::null(),
+            message_severities: Severity::VERBOSE | Severity::INFO | Severity::WARNING | Severity::ERROR,
+            message_types: MessageType::GENERAL | MessageType::VALIDATION | MessageType::PERFORMANCE | MessageType::CONFORMANCE,
+            user_callback: Some(log_message),
+            user_data: std::ptr::null_mut(),
+        };
+        let mut messenger = xr::sys::DebugUtilsMessengerEXT::NULL;
+        let result = unsafe { (debug_utils.create_debug_utils_messenger)(instance.as_raw(), &create_info, &mut messenger) };
+        if result != xr::sys::Result::SUCCESS {
+            anyhow::bail!("Failed to create the OpenXR debug messenger: {}", result);
+        }
+        Ok(Self { messenger, destroy: debug_utils.destroy_debug_utils_messenger })
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe { (self.destroy)(self.messenger) };
+    }
+}
+
+/// Log level for a message's severity; the runtime's info is chatty, so it goes to debug
+fn log_level(severity: Severity) -> log::Level {
+    if severity.contains(Severity::ERROR) {
+        log::Level::Error
+    } else if severity.contains(Severity::WARNING) {
+        log::Level::Warn
+    } else if severity.contains(Severity::INFO) {
+        log::Level::Debug
+    } else {
+        log::Level::Trace
+    }
+}
+
+/// What kind of message it is, for the log line
+fn message_kind(types: MessageType) -> &'static str {
+    if types.contains(MessageType::VALIDATION) {
+        "validation"
+    } else if types.contains(MessageType::PERFORMANCE) {
+        "performance"
+    } else if types.contains(MessageType::CONFORMANCE) {
+        "conformance"
+    } else {
+        "general"
+    }
+}
+
+unsafe fn c_str<'a>(text: *const c_char) -> std::borrow::Cow<'a, str> {
+    if text.is_null() {
+        "".into()
+    } else {
+        CStr::from_ptr(text).to_string_lossy()
+    }
+}
+
+unsafe extern "system" fn log_message(
+    severity: Severity,
+    types: MessageType,
+    data: *const xr::sys::DebugUtilsMessengerCallbackDataEXT,
+    _user_data: *mut c_void,
+) -> xr::sys::Bool32 {
+    if let Some(data) = data.as_ref() {
+        log::log!(
+            target: LOG_TARGET,
+            log_level(severity),
+            "[{}] {} ({}): {}",
+            message_kind(types),
+            c_str(data.message_id),
+            c_str(data.function_name),
+            c_str(data.message),
+        );
+    }
+    // Never abort the call that produced the message
+    xr::sys::FALSE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_options() {
+        assert_eq!(VrDebugOptions::parse(""), VrDebugOptions::default());
+        assert_eq!(VrDebugOptions::parse("1"), VrDebugOptions::all());
+        assert_eq!(VrDebugOptions::parse("Validation"), VrDebugOptions { validation_layer: true, debug_messages: false });
+        assert_eq!(VrDebugOptions::parse("validation, messages"), VrDebugOptions::all());
+        assert_eq!(VrDebugOptions::parse("messages,bogus"), VrDebugOptions { validation_layer: false, debug_messages: true });
+
+        assert_eq!(log_level(Severity::ERROR), log::Level::Error);
+        assert_eq!(log_level(Severity::WARNING), log::Level::Warn);
+        assert_eq!(log_level(Severity::VERBOSE), log::Level::Trace);
+        assert_eq!(message_kind(MessageType::GENERAL | MessageType::VALIDATION), "validation");
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use openxr as xr;
+
+/// Environment variable the OpenXR loader reads to override the active runtime's manifest
+pub const RUNTIME_JSON_ENV: &str = "XR_RUNTIME_JSON";
+
+/// A step of OpenXR setup that failed, with the runtime's error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupFailure {
+    pub step: &'static str,
+    pub error: xr::sys::Result,
+}
+
+/// What could be found out about the OpenXR setup, for telling users why VR doesn't start
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VrDiagnostics {
+    /// The manifest of the runtime the loader would pick, when it can be found on disk
+    pub runtime_manifest: Option<PathBuf>,
+    /// Name and version the runtime reports, once an instance could be created
+    pub runtime: Option<String>,
+    /// The headset's name, once the runtime found one
+    pub system: Option<String>,
+    pub api_layers: Vec<String>,
+    pub extensions: Vec<String>,
+    pub vulkan_enable2: bool,
+    pub failures: Vec<SetupFailure>,
+}
+
+impl VrDiagnostics {
+    /// Probe each step of VR setup, recording how far it gets rather than stopping at the
+    /// first error
+    pub fn gather() -> Self {
+        let mut diagnostics = Self {
+            runtime_manifest: find_runtime_manifest(|name| std::env::var_os(name), |path| path.is_file()),
+            ..Self::default()
+        };
+        let entry = xr::Entry::linked();
+
+        match entry.enumerate_layers() {
+            Ok(layers) => diagnostics.api_layers = layers.into_iter().map(|layer| layer.layer_name).collect(),
+            Err(error) => diagnostics.fail("enumerate API layers", error),
+        }
+        let extensions = match entry.enumerate_extensions() {
+            Ok(extensions) => extensions,
+            Err(error) => {
+                diagnostics.fail("enumerate extensions", error);
+                return diagnostics;
+            }
+        };
+        diagnostics.vulkan_enable2 = extensions.khr_vulkan_enable2;
+        diagnostics.extensions = extension_names(&extensions);
+
+        let required = xr::ExtensionSet {
+            khr_vulkan_enable2: extensions.khr_vulkan_enable2,
+            ..Default::default()
+        };
+        let app_info = xr::ApplicationInfo {
+            application_name: "WGPU 3D Viewer",
+            application_version: 1,
+            engine_name: "No Engine",
+            engine_version: 1,
+        };
+        let instance = match entry.create_instance(&app_info, &required, &[]) {
+            Ok(instance) => instance,
+            Err(error) => {
+                diagnostics.fail("create the instance", error);
+                return diagnostics;
+            }
+        };
+        if let Ok(properties) = instance.properties() {
+            diagnostics.runtime = Some(format!("{} {}", properties.runtime_name, properties.runtime_version));
+        }
+        match instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY) {
+            Ok(system) => {
+                diagnostics.system = instance.system_properties(system).ok().map(|properties| properties.system_name);
+            }
+            Err(error) => diagnostics.fail("find a headset", error),
+        }
+        diagnostics
+    }
+
+    fn fail(&mut self, step: &'static str, error: xr::sys::Result) {
+        self.failures.push(SetupFailure { step, error });
+    }
+
+    /// Whether everything VR needs was found
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty() && self.vulkan_enable2 && self.system.is_some()
+    }
+
+    /// What the user can try, most likely fix first
+    pub fn hints(&self) -> Vec<String> {
+        let mut hints = Vec::new();
+        let failed_with = |code| self.failures.iter().any(|failure| failure.error == code);
+        if failed_with(xr::sys::Result::ERROR_RUNTIME_UNAVAILABLE) || (self.runtime.is_none() && self.runtime_manifest.is_none()) {
+            hints.push(format!(
+                "No active OpenXR runtime was found: install one (SteamVR, Monado, WiVRn, ...) and set it as \
+                 the active runtime, or point {} at its manifest", RUNTIME_JSON_ENV
+            ));
+        }
+        if self.runtime.is_some() || !self.extensions.is_empty() {
+            if !self.vulkan_enable2 {
+                hints.push("The active runtime doesn't support XR_KHR_vulkan_enable2, which VR rendering needs; \
+                            switch to a runtime that does".to_string());
+            }
+            if failed_with(xr::sys::Result::ERROR_FORM_FACTOR_UNAVAILABLE) {
+                hints.push("The runtime is running but sees no headset: connect and power it on, and check \
+                            the runtime's own status window".to_string());
+            }
+        }
+        if hints.is_empty() && !self.failures.is_empty() {
+            hints.push("Check the active runtime's logs for the failure above".to_string());
+        }
+        hints
+    }
+}
+
+impl fmt::Display for VrDiagnostics {
+    /// A multi-line report for the log or a setup dialog
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".to_string());
+        let manifest = self.runtime_manifest.as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "not found".to_string());
+        writeln!(f, "OpenXR runtime manifest: {}", manifest)?;
+        writeln!(f, "Runtime: {}", or_unknown(&self.runtime))?;
+        writeln!(f, "Headset: {}", or_unknown(&self.system))?;
+        writeln!(f, "API layers: {}", if self.api_layers.is_empty() { "none".to_string() } else { self.api_layers.join(", ") })?;
+        writeln!(f, "Extensions: {}", if self.extensions.is_empty() { "none".to_string() } else { self.extensions.join(", ") })?;
+        for failure in &self.failures {
+            writeln!(f, "Failed to {}: {}", failure.step, failure.error)?;
+        }
+        for hint in self.hints() {
+            writeln!(f, "Hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// VR failed to start. Carries the diagnostics, so callers can show them or `downcast_ref`
+/// the `anyhow::Error` from `VRSystem::new` to inspect them
+#[derive(Debug)]
+pub struct VrSetupError {
+    pub failure: SetupFailure,
+    pub diagnostics: VrDiagnostics,
+}
+
+impl VrSetupError {
+    pub fn new(step: &'static str, error: xr::sys::Result) -> Self {
+        Self { failure: SetupFailure { step, error }, diagnostics: VrDiagnostics::gather() }
+    }
+}
+
+impl fmt::Display for VrSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to {}: {}\n{}", self.failure.step, self.failure.error, self.diagnostics)
+    }
+}
+
+impl std::error::Error for VrSetupError {}
+
+/// Names of the supported extensions VR cares about, then any the bindings don't know
+fn extension_names(extensions: &xr::ExtensionSet) -> Vec<String> {
+    [
+        ("XR_KHR_vulkan_enable", extensions.khr_vulkan_enable),
+        ("XR_KHR_vulkan_enable2", extensions.khr_vulkan_enable2),
+        ("XR_EXT_debug_utils", extensions.ext_debug_utils),
+        ("XR_KHR_composition_layer_depth", extensions.khr_composition_layer_depth),
+        ("XR_FB_display_refresh_rate", extensions.fb_display_refresh_rate),
+    ]
+    .into_iter()
+    .filter(|(_, supported)| *supported)
+    .map(|(name, _)| name.to_string())
+    .chain(extensions.other.iter().cloned())
+    .collect()
+}
+
+/// The active runtime's manifest as the loader finds it on Linux: `XR_RUNTIME_JSON`, then
+/// `active_runtime.json` under the XDG config directories and `/etc`. On Windows the active
+/// runtime lives in the registry (HKLM\SOFTWARE\Khronos\OpenXR\1\ActiveRuntime) and only the
+/// override is found.
+fn find_runtime_manifest(var: impl Fn(&str) -> Option<std::ffi::OsString>, exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    if let Some(path) = var(RUNTIME_JSON_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    if cfg!(windows) {
+        return None;
+    }
+    let config_home = var("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let config_dirs = var("XDG_CONFIG_DIRS")
+        .map(|dirs| std::env::split_paths(&dirs).collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![PathBuf::from("/etc/xdg")]);
+    config_home.into_iter()
+        .chain(config_dirs)
+        .chain([PathBuf::from("/etc")])
+        .map(|dir| dir.join("openxr/1/active_runtime.json"))
+        .find(|path| exists(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vr_diagnostics() {
+        // The override wins; otherwise the user's config comes before the system's
+        let env = |overridden: bool| move |name: &str| match name {
+            RUNTIME_JSON_ENV if overridden => Some("/opt/monado/openxr_monado.json".into()),
+            "HOME" => Some("/home/vr".into()),
+            _ => None,
+        };
+        let system_only = |path: &Path| path.starts_with("/etc/xdg");
+        assert_eq!(find_runtime_manifest(env(true), system_only), Some(PathBuf::from("/opt/monado/openxr_monado.json")));
+        if !cfg!(windows) {
+            assert_eq!(find_runtime_manifest(env(false), system_only), Some(PathBuf::from("/etc/xdg/openxr/1/active_runtime.json")));
+            assert_eq!(find_runtime_manifest(env(false), |path| path.starts_with("/home/vr/.config")),
+                Some(PathBuf::from("/home/vr/.config/openxr/1/active_runtime.json")));
+            assert_eq!(find_runtime_manifest(env(false), |_| false), None);
+        }
+
+        // No runtime at all
+        let missing = VrDiagnostics {
+            failures: vec![SetupFailure { step: "enumerate extensions", error: xr::sys::Result::ERROR_RUNTIME_UNAVAILABLE }],
+            ..VrDiagnostics::default()
+        };
+        assert!(!missing.is_ok());
+        assert_eq!(missing.hints().len(), 1);
+        assert!(missing.hints()[0].contains("No active OpenXR runtime"));
+        assert!(missing.to_string().contains("OpenXR runtime manifest: not found"));
+
+        // A runtime without a headset
+        let unplugged = VrDiagnostics {
+            runtime_manifest: Some(PathBuf::from("/etc/xdg/openxr/1/active_runtime.json")),
+            runtime: Some("Monado 21.0.0".to_string()),
+            extensions: vec!["XR_KHR_vulkan_enable2".to_string()],
+            vulkan_enable2: true,
+            failures: vec![SetupFailure { step: "find a headset", error: xr::sys::Result::ERROR_FORM_FACTOR_UNAVAILABLE }],
+            ..VrDiagnostics::default()
+        };
+        assert_eq!(unplugged.hints().len(), 1);
+        assert!(unplugged.hints()[0].contains("no headset"));
+
+        let ready = VrDiagnostics { failures: Vec::new(), system: Some("Valve Index".to_string()), ..unplugged };
+        assert!(ready.is_ok());
+        assert!(ready.hints().is_empty());
+        assert!(ready.to_string().contains("Headset: Valve Index"));
+    }
+}
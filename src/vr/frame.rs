@@ -1,7 +1,7 @@
 use openxr as xr;
 use anyhow::Result;
 
-use super::math::ViewProjection;
+use super::math::{ViewProjection, NEAR_PLANE};
 
 #[derive(Debug)]
 pub struct FrameResources {
@@ -47,35 +47,61 @@ impl FrameManager {
         self.views = Some(views);
     }
 
+    /// Swap in a swapchain recreated for `views`' new recommended size, destroying the old one
+    pub fn replace_swapchain(&mut self, swapchain: xr::Swapchain<xr::Vulkan>, views: Vec<xr::ViewConfigurationView>) {
+        self.swapchain = Some(swapchain);
+        self.views = Some(views);
+    }
+
     pub fn get_session(&self) -> Option<&xr::Session<xr::Vulkan>> {
         self.session.as_ref()
     }
 
     pub fn begin_frame(&mut self) -> Result<xr::FrameState> {
-        if let (Some(frame_waiter), Some(frame_stream)) = (&mut self.frame_waiter, &mut self.frame_stream) {
-            frame_waiter.wait()?;
-            let frame_state = xr::FrameState {
-                predicted_display_time: xr::Time::from_nanos(0),  // We'll get the actual time from the runtime later
-                predicted_display_period: xr::Duration::from_nanos(0),
-                should_render: true,  // We'll assume we should always render for now
-            };
+        let frame_state = self.wait_frame()?;
+        self.begin_stream()?;
+        Ok(frame_state)
+    }
+
+    /// Block in xrWaitFrame until the runtime wants the next frame
+    pub fn wait_frame(&mut self) -> Result<xr::FrameState> {
+        if let Some(frame_waiter) = &mut self.frame_waiter {
+            Ok(frame_waiter.wait()?)
+        } else {
+            Err(anyhow::anyhow!("Frame waiter not initialized"))
+        }
+    }
+
+    /// Start submitting the frame the last `wait_frame` returned
+    pub fn begin_stream(&mut self) -> Result<()> {
+        if let Some(frame_stream) = &mut self.frame_stream {
             frame_stream.begin().map_err(|e| anyhow::anyhow!("Failed to begin frame: {:?}", e))?;
-            Ok(frame_state)
+            Ok(())
         } else {
-            Err(anyhow::anyhow!("Frame waiter or stream not initialized"))
+            Err(anyhow::anyhow!("Frame stream not initialized"))
         }
     }
 
     pub fn acquire_swapchain_image(&mut self) -> Result<u32> {
         if let Some(swapchain) = &mut self.swapchain {
             let image_index = swapchain.acquire_image()?;
-            swapchain.wait_image(xr::Duration::from_nanos(100_000_000))?;
+            // A timed-out wait still returns success, and writing the image then races the
+            // compositor still reading it; the runtime bounds this wait itself
+            swapchain.wait_image(xr::Duration::INFINITE)?;
             Ok(image_index)
         } else {
             Err(anyhow::anyhow!("Swapchain not initialized"))
         }
     }
 
+    /// The swapchain's Vulkan images, for `SwapchainTextures`
+    pub fn swapchain_images(&self) -> Result<Vec<u64>> {
+        let swapchain = self.swapchain.as_ref().ok_or_else(|| anyhow::anyhow!("Swapchain not initialized"))?;
+        Ok(swapchain.enumerate_images()?)
+    }
+
+    /// Hand the acquired image back to the runtime. All work writing it must have been
+    /// submitted to the session's queue first; see `SwapchainTextures`
     pub fn release_swapchain_image(&mut self) -> Result<()> {
         if let Some(swapchain) = &mut self.swapchain {
             swapchain.release_image()?;
@@ -99,6 +125,38 @@ impl FrameManager {
         }
     }
 
+    /// End the frame with a projection layer of `views`, each eye reading its own array layer
+    /// of the swapchain image
+    pub fn end_frame_with_views(&mut self, frame_state: xr::FrameState, views: &[xr::View]) -> Result<()> {
+        let (width, height) = self.get_swapchain_image_layout()
+            .ok_or_else(|| anyhow::anyhow!("View configuration not initialized"))?;
+        if let (Some(frame_stream), Some(stage), Some(swapchain)) = (&mut self.frame_stream, &self.stage, &self.swapchain) {
+            let projection_views: Vec<_> = views.iter().enumerate()
+                .map(|(eye, view)| {
+                    xr::CompositionLayerProjectionView::new()
+                        .pose(view.pose)
+                        .fov(view.fov)
+                        .sub_image(xr::SwapchainSubImage::new()
+                            .swapchain(swapchain)
+                            .image_array_index(eye as u32)
+                            .image_rect(xr::Rect2Di {
+                                offset: xr::Offset2Di { x: 0, y: 0 },
+                                extent: xr::Extent2Di { width: width as i32, height: height as i32 },
+                            }))
+                })
+                .collect();
+            let projection_layer = xr::CompositionLayerProjection::new().space(stage).views(&projection_views);
+            frame_stream.end(
+                frame_state.predicted_display_time,
+                xr::EnvironmentBlendMode::OPAQUE,
+                &[&projection_layer],
+            )?;
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("Frame stream, stage or swapchain not initialized"))
+        }
+    }
+
     pub fn get_views(&self, frame_state: &xr::FrameState) -> Result<Vec<xr::View>> {
         if let (Some(session), Some(stage)) = (&self.session, &self.stage) {
             let (_, views) = session.locate_views(
@@ -117,20 +175,14 @@ impl FrameManager {
         
         let mut view_projections = Vec::new();
         for view in views {
-            view_projections.push(ViewProjection::from_xr_view(&view, 0.001));  // Near plane = 0.001
+            view_projections.push(ViewProjection::from_xr_view(&view, NEAR_PLANE));
         }
 
         Ok(view_projections)
     }
 
     pub fn get_swapchain_image_layout(&self) -> Option<(u32, u32)> {
-        self.views.as_ref().map(|views| {
-            let view = &views[0];  // Both eyes use the same resolution
-            (
-                view.recommended_image_rect_width,
-                view.recommended_image_rect_height,
-            )
-        })
+        self.views.as_deref().and_then(recommended_image_size)
     }
 
     pub fn take_session_components(self) -> Option<(
@@ -162,6 +214,16 @@ impl FrameManager {
     }
 }
 
+/// The eye image size `views` recommend. Both eyes share one swapchain, so it's the largest
+/// recommended size, kept within what every view allows; `None` without views.
+pub fn recommended_image_size(views: &[xr::ViewConfigurationView]) -> Option<(u32, u32)> {
+    let width = views.iter().map(|view| view.recommended_image_rect_width).max()?;
+    let height = views.iter().map(|view| view.recommended_image_rect_height).max()?;
+    let max_width = views.iter().map(|view| view.max_image_rect_width).min()?;
+    let max_height = views.iter().map(|view| view.max_image_rect_height).min()?;
+    Some((width.min(max_width).max(1), height.min(max_height).max(1)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +238,22 @@ mod tests {
         assert!(frame_manager.session.is_none());
         assert!(frame_manager.views.is_none());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_recommended_image_size() {
+        let view = |width, height| xr::ViewConfigurationView {
+            recommended_image_rect_width: width,
+            max_image_rect_width: 4096,
+            recommended_image_rect_height: height,
+            max_image_rect_height: 4096,
+            recommended_swapchain_sample_count: 1,
+            max_swapchain_sample_count: 4,
+        };
+        assert_eq!(recommended_image_size(&[]), None);
+        assert_eq!(recommended_image_size(&[view(2016, 2240), view(2016, 2240)]), Some((2016, 2240)));
+        // Eyes recommending different sizes share the larger
+        assert_eq!(recommended_image_size(&[view(2000, 2240), view(2016, 2200)]), Some((2016, 2240)));
+        // A render scale pushed past the runtime's maximum is clamped
+        assert_eq!(recommended_image_size(&[view(5000, 5600)]), Some((4096, 4096)));
+    }
+}
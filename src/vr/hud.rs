@@ -0,0 +1,346 @@
+use glam::{Mat4, Vec3, Vec4};
+use crate::input::InputState;
+use crate::scene::Profiler;
+use crate::scene::overlay::{create_overlay_pipeline, OverlayVertex};
+use super::math::ViewProjection;
+use super::timing::FrameTimingManager;
+
+/// VR action whose button shows and hides the HUD
+pub const TOGGLE_ACTION: &str = "hud";
+
+// Panel layout in meters
+const PIXEL: f32 = 0.003;
+const GLYPH_ADVANCE: f32 = 4.0 * PIXEL;
+const LINE_HEIGHT: f32 = 7.0 * PIXEL;
+const PADDING: f32 = 3.0 * PIXEL;
+const ROWS: usize = 5;
+const TEXT_COLUMNS: usize = 9;
+// A frame-time bar this long means exactly on budget; bars stop at 1.5x
+const BAR_LENGTH: f32 = 0.05;
+const PANEL_WIDTH: f32 = 2.0 * PADDING + TEXT_COLUMNS as f32 * GLYPH_ADVANCE + 1.5 * BAR_LENGTH;
+const PANEL_HEIGHT: f32 = 2.0 * PADDING + ROWS as f32 * LINE_HEIGHT - 2.0 * PIXEL;
+// Head anchor: below the line of sight, close enough to read
+const HEAD_OFFSET: Vec3 = Vec3::new(0.0, -0.15, -0.5);
+// Wrist anchor: just above the controller
+const WRIST_OFFSET: Vec3 = Vec3::new(0.0, 0.04, 0.05);
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+const TEXT_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const WARN_COLOR: [f32; 4] = [1.0, 0.3, 0.2, 1.0];
+const CPU_COLOR: [f32; 4] = [0.2, 0.8, 0.3, 0.9];
+const GPU_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 0.9];
+const BUDGET_LINE: [f32; 4] = [0.6, 0.6, 0.6, 0.8];
+
+/// The numbers the HUD shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HudStats {
+    pub fps: f32,
+    pub cpu_ms: f32,
+    pub gpu_ms: Option<f32>,
+    /// Frame time the display refresh allows
+    pub budget_ms: f32,
+    pub dropped_frames: u32,
+    pub resolution_scale: f32,
+}
+
+impl HudStats {
+    /// Frame rate, frame time and drops from the VR frame timings, GPU time from the
+    /// profiler's latest resolved frame when timestamp queries are available
+    pub fn gather(timing: &FrameTimingManager, profiler: Option<&Profiler>, resolution_scale: f32) -> Self {
+        let stats = timing.get_stats();
+        Self {
+            fps: stats.fps,
+            cpu_ms: stats.average_frame_time_ms,
+            gpu_ms: profiler.and_then(Profiler::latest).and_then(|frame| frame.gpu_ms),
+            budget_ms: timing.target_frame_time().as_secs_f32() * 1000.0,
+            dropped_frames: stats.dropped_frames,
+            resolution_scale,
+        }
+    }
+}
+
+/// Where the panel floats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HudAnchor {
+    /// Follows the head, below the line of sight
+    #[default]
+    Head,
+    /// Above the controller set with `VrHud::set_wrist`; falls back to the head while untracked
+    Wrist,
+}
+
+/// Accumulates panel-space rectangles as triangles projected into one eye's NDC
+struct PanelQuads {
+    vertices: Vec<OverlayVertex>,
+    to_clip: Mat4,
+}
+
+impl PanelQuads {
+    /// Rectangle in meters, x right and y down from the panel's top-left corner
+    fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: [f32; 4]) {
+        let corner = |x: f32, y: f32| self.to_clip * Vec4::new(x - PANEL_WIDTH * 0.5, PANEL_HEIGHT * 0.5 - y, 0.0, 1.0);
+        let corners = [corner(x, y), corner(x + w, y), corner(x + w, y + h), corner(x, y + h)];
+        // Flat colors need no clipping as long as nothing is behind the eye
+        if corners.iter().any(|corner| corner.w <= 1e-4) {
+            return;
+        }
+        let ndc = corners.map(|corner| [corner.x / corner.w, corner.y / corner.w]);
+        for index in [0, 3, 2, 0, 2, 1] {
+            self.vertices.push(OverlayVertex { position: ndc[index], color });
+        }
+    }
+
+    fn text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        for (column, c) in text.chars().enumerate() {
+            let left = x + column as f32 * GLYPH_ADVANCE;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for bit in 0..3 {
+                    if bits & (4 >> bit) != 0 {
+                        self.rect(left + bit as f32 * PIXEL, y + row as f32 * PIXEL, PIXEL, PIXEL, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 3x5 bitmap of the characters the HUD prints, one byte per row from the top
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' | 'O' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 1, 1],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        '.' => [0, 0, 0, 0, 2],
+        '-' => [0, 0, 7, 0, 0],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'P' => [6, 5, 6, 4, 4],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'U' => [5, 5, 5, 5, 7],
+        _ => [0; 5],
+    }
+}
+
+/// In-headset performance panel: FPS, CPU and GPU frame time against the display's budget,
+/// dropped frames and resolution scale. Desktop overlays aren't visible in the headset, so
+/// this is drawn into each eye's image after the scene.
+pub struct VrHud {
+    pub enabled: bool,
+    pub anchor: HudAnchor,
+    wrist: Option<Mat4>,
+    toggle_held: bool,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl VrHud {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let capacity = 8192;
+        Self {
+            enabled: false,
+            anchor: HudAnchor::Head,
+            wrist: None,
+            toggle_held: false,
+            pipeline: create_overlay_pipeline(device, format),
+            vertex_buffer: Self::create_vertex_buffer(device, capacity),
+            capacity,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("VR HUD Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<OverlayVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Show or hide the HUD each time the `TOGGLE_ACTION` button goes down
+    pub fn handle_input(&mut self, input: &InputState) {
+        let held = input.vr_action(TOGGLE_ACTION) > 0.5;
+        if held && !self.toggle_held {
+            self.enabled = !self.enabled;
+        }
+        self.toggle_held = held;
+    }
+
+    /// World transform of the controller the wrist anchor follows, or `None` while it isn't tracked
+    pub fn set_wrist(&mut self, wrist: Option<Mat4>) {
+        self.wrist = wrist;
+    }
+
+    /// World transform of the panel's center, facing +Z, for a head at `head`
+    pub fn panel_transform(&self, head: Mat4) -> Mat4 {
+        anchor_transform(self.anchor, self.wrist, head)
+    }
+
+    /// Panel geometry at `panel` as seen through `view_proj`
+    pub fn build_vertices(stats: &HudStats, panel: Mat4, view_proj: Mat4) -> Vec<OverlayVertex> {
+        let mut quads = PanelQuads { vertices: Vec::new(), to_clip: view_proj * panel };
+        quads.rect(0.0, 0.0, PANEL_WIDTH, PANEL_HEIGHT, BACKGROUND);
+
+        let gpu = stats.gpu_ms.map_or("-".to_string(), |ms| format!("{:.1}", ms));
+        let drop_color = if stats.dropped_frames > 0 { WARN_COLOR } else { TEXT_COLOR };
+        let rows = [
+            (format!("FPS  {:.0}", stats.fps), TEXT_COLOR, None),
+            (format!("CPU  {:.1}", stats.cpu_ms), TEXT_COLOR, Some((stats.cpu_ms, CPU_COLOR))),
+            (format!("GPU  {}", gpu), TEXT_COLOR, stats.gpu_ms.map(|ms| (ms, GPU_COLOR))),
+            (format!("DROP {}", stats.dropped_frames), drop_color, None),
+            (format!("RES  {:.2}", stats.resolution_scale), TEXT_COLOR, None),
+        ];
+
+        let bar_x = PADDING + TEXT_COLUMNS as f32 * GLYPH_ADVANCE;
+        for (i, (text, color, bar)) in rows.iter().enumerate() {
+            let y = PADDING + i as f32 * LINE_HEIGHT;
+            quads.text(PADDING, y, text, *color);
+            if let Some((ms, bar_color)) = bar {
+                let fraction = (ms / stats.budget_ms.max(1e-3)).min(1.5);
+                let color = if fraction > 1.0 { WARN_COLOR } else { *bar_color };
+                quads.rect(bar_x, y, fraction * BAR_LENGTH, 5.0 * PIXEL, color);
+                quads.rect(bar_x + BAR_LENGTH, y - PIXEL, 0.5 * PIXEL, 7.0 * PIXEL, BUDGET_LINE);
+            }
+        }
+        quads.vertices
+    }
+
+    /// Draw the panel over each eye's finished image
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        stats: &HudStats,
+        eyes: &[(&wgpu::TextureView, &ViewProjection)],
+    ) {
+        if !self.enabled || eyes.is_empty() {
+            return;
+        }
+        let panel = self.panel_transform(head_transform(eyes.iter().map(|(_, eye)| *eye)));
+
+        // Every eye's geometry goes in one upload, since writes land before the passes run
+        let mut vertices = Vec::new();
+        let mut ranges = Vec::with_capacity(eyes.len());
+        for (_, eye) in eyes {
+            let start = vertices.len() as u32;
+            vertices.extend(Self::build_vertices(stats, panel, eye.projection * eye.view));
+            ranges.push(start..vertices.len() as u32);
+        }
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+
+        for ((target, _), range) in eyes.iter().zip(ranges) {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("VR HUD Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            pass.draw(range, 0..1);
+        }
+    }
+}
+
+fn anchor_transform(anchor: HudAnchor, wrist: Option<Mat4>, head: Mat4) -> Mat4 {
+    match (anchor, wrist) {
+        // Lying on the forearm, facing up
+        (HudAnchor::Wrist, Some(wrist)) => {
+            wrist * Mat4::from_translation(WRIST_OFFSET) * Mat4::from_rotation_x(-std::f32::consts::FRAC_PI_2)
+        }
+        _ => head * Mat4::from_translation(HEAD_OFFSET),
+    }
+}
+
+/// World transform of the point between the eyes, oriented like the first eye
+pub(crate) fn head_transform<'a>(eyes: impl Iterator<Item = &'a ViewProjection>) -> Mat4 {
+    let worlds: Vec<Mat4> = eyes.map(|eye| eye.view.inverse()).collect();
+    let center = worlds.iter().map(|world| world.w_axis.truncate()).sum::<Vec3>() / worlds.len().max(1) as f32;
+    let (_, rotation, _) = worlds.first().copied().unwrap_or_default().to_scale_rotation_translation();
+    Mat4::from_rotation_translation(rotation, center)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> HudStats {
+        HudStats {
+            fps: 88.0,
+            cpu_ms: 9.5,
+            gpu_ms: Some(14.0),
+            budget_ms: 11.1,
+            dropped_frames: 3,
+            resolution_scale: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_hud_geometry() {
+        let projection = Mat4::perspective_rh(1.5, 1.0, 0.05, 100.0);
+        let panel = Mat4::from_translation(HEAD_OFFSET);
+        let vertices = VrHud::build_vertices(&stats(), panel, projection);
+        assert!(!vertices.is_empty() && vertices.len().is_multiple_of(6));
+        // Below the line of sight but in view
+        assert!(vertices.iter().all(|v| v.position[0].abs() < 1.0 && (-1.0..0.0).contains(&v.position[1])));
+        // Over-budget GPU time is flagged
+        assert!(vertices.iter().any(|v| v.color == WARN_COLOR));
+
+        // Nothing is drawn behind the eye
+        let behind = Mat4::from_translation(Vec3::new(0.0, 0.0, 0.5));
+        assert!(VrHud::build_vertices(&stats(), behind, projection).is_empty());
+
+        for c in "FPS CPU GPU DROP RES 0123456789.-".chars().filter(|c| *c != ' ') {
+            assert_ne!(glyph(c), [0; 5], "no glyph for {:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_hud_anchor() {
+        let head = Mat4::from_translation(Vec3::new(1.0, 1.6, 0.0));
+        let wrist = Mat4::from_translation(Vec3::new(0.3, 1.0, -0.2));
+        let anchored = |anchor, wrist| anchor_transform(anchor, wrist, head).w_axis.truncate();
+
+        assert!((anchored(HudAnchor::Head, Some(wrist)) - Vec3::new(1.0, 1.45, -0.5)).length() < 1e-5);
+        // An untracked wrist falls back to the head
+        assert!((anchored(HudAnchor::Wrist, None) - Vec3::new(1.0, 1.45, -0.5)).length() < 1e-5);
+        assert!((anchored(HudAnchor::Wrist, Some(wrist)) - Vec3::new(0.3, 1.04, -0.15)).length() < 1e-5);
+        // Facing up off the controller
+        let normal = anchor_transform(HudAnchor::Wrist, Some(wrist), head).transform_vector3(Vec3::Z);
+        assert!((normal - Vec3::Y).length() < 1e-5);
+
+        // The head sits between the eyes
+        let eye = |x: f32| ViewProjection {
+            view: Mat4::from_translation(Vec3::new(x, 1.6, 0.0)).inverse(),
+            projection: Mat4::IDENTITY,
+            fov: openxr::Fovf { angle_left: -0.8, angle_right: 0.8, angle_up: 0.8, angle_down: -0.8 },
+            pose: openxr::Posef::IDENTITY,
+        };
+        let eyes = [eye(-0.03), eye(0.03)];
+        assert!((head_transform(eyes.iter()).w_axis.truncate() - Vec3::new(0.0, 1.6, 0.0)).length() < 1e-5);
+    }
+}
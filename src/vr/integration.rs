@@ -0,0 +1,233 @@
+//! End-to-end VR frames against a headless OpenXR runtime, for CI.
+//!
+//! Built with the `vr-integration` feature, and run only when `XR_RUNTIME_JSON` names the
+//! runtime, so a developer's real headset is never picked up by accident. With Monado's null
+//! compositor and simulated headset:
+//!
+//! ```sh
+//! XRT_COMPOSITOR_NULL=1 monado-service &
+//! XR_RUNTIME_JSON=/usr/share/openxr/1/openxr_monado.json \
+//!     cargo test --features vr-integration vr::integration
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
+use glam::{Mat4, Vec3, Vec4};
+use log::Level;
+use openxr as xr;
+use pollster::FutureExt;
+use crate::diagnostics;
+use crate::input::InputState;
+use crate::model::Model;
+use crate::scene::{capabilities, RenderView, Renderer, Scene, Transform};
+use crate::scene::camera::Camera;
+use super::{select_vr_adapter, SwapchainTextures, VRPipeline, VRSystem, VrFrame};
+use super::diagnose::RUNTIME_JSON_ENV;
+use super::submit::{FillSwapchain, FRAME_SLOTS};
+
+/// Frames rendered and handed to the runtime
+const FRAMES: u64 = 120;
+/// How long the session may take to start and every frame to go through
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Eye clip planes; the renderer's forward depth needs a nearer far plane than `ViewProjection`'s
+const NEAR_PLANE: f32 = 0.05;
+const FAR_PLANE: f32 = 100.0;
+
+#[test]
+fn test_headless_vr_frames() {
+    if std::env::var_os(RUNTIME_JSON_ENV).is_none() {
+        println!("Skipping test 'test_headless_vr_frames' - {} doesn't name a headless runtime", RUNTIME_JSON_ENV);
+        return;
+    }
+    diagnostics::init();
+
+    let submitted = run_frames(FRAMES).unwrap();
+    assert!(submitted >= FRAMES, "The runtime waited for {} frames, fewer than the {} rendered", submitted, FRAMES);
+
+    // Validation layer messages, uncaptured wgpu errors and failed submits are all logged as errors
+    let mut errors: Vec<String> = diagnostics::gpu_messages().into_iter()
+        .filter(|message| message.level == Level::Error)
+        .map(|message| message.message)
+        .collect();
+    errors.extend(diagnostics::recent(diagnostics::LOG_CAPACITY).into_iter()
+        .filter(|entry| entry.level == Level::Error)
+        .map(|entry| format!("{}: {}", entry.target, entry.message)));
+    assert!(errors.is_empty(), "Errors during the VR frames:\n{}", errors.join("\n"));
+}
+
+/// Start a session, render `frames` frames of a small scene for both eyes through
+/// `Renderer::render_views` with the HUD over them, and submit them from a submit thread, as an application does.
+/// Returns how many frames the runtime waited for.
+fn run_frames(frames: u64) -> Result<u64> {
+    let mut vr = VRSystem::new()?;
+    anyhow::ensure!(vr.is_hmd_available(), "The runtime has no stereo headset");
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::VULKAN,
+        flags: wgpu::InstanceFlags::DEBUG | wgpu::InstanceFlags::VALIDATION,
+        ..Default::default()
+    });
+    let adapter = select_vr_adapter(&instance, &vr, None)?;
+    let (device, queue, _) = capabilities::request_device(&adapter, "VR Test Device", VRPipeline::MULTIVIEW_FEATURES, wgpu::Limits::default())
+        .block_on()?;
+    diagnostics::install_device_handler(&device);
+    let (device, queue) = (Arc::new(device), Arc::new(queue));
+    vr.initialize_session(&device)?;
+    // Shown from the start so its passes are validated too
+    vr.hud_mut().context("The session has no HUD")?.enabled = true;
+
+    let size = vr.get_swapchain_image_layout().context("The session has no swapchain")?;
+    let format = vr.get_swapchain_format();
+    let mut renderer = Renderer::new(&device, &queue, &eye_config(format, size));
+    vr.apply_settings(renderer.settings());
+    let mut scene = Scene::new(Camera::new(Vec3::new(0.0, 1.6, 3.0), size.0 as f32 / size.1 as f32));
+    let cube_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/models/cube.obj");
+    let cube = Model::load(&device, &queue, cube_path, renderer.material_bind_group_layout())?;
+    let cube = scene.assets.add_model(cube);
+    scene.add_object(cube, Transform { position: Vec3::new(0.0, 1.5, -2.0), ..Transform::new() });
+
+    let mut input = InputState::new();
+    let mut eye_images = create_eye_images(&device, size, format);
+    let fill = fill_swapchain(device.clone(), queue.clone(), eye_images.clone(), vr.swapchain_textures(&device)?);
+    let mut thread = vr.spawn_submit_thread(fill)?;
+
+    let started = Instant::now();
+    let mut rendered = 0;
+    let mut last_frame = 0;
+    // Frames waited for by submit threads already replaced
+    let mut submitted = 0;
+    while rendered < frames {
+        anyhow::ensure!(
+            started.elapsed() < TIMEOUT,
+            "Only {} of {} frames went through in {:?}", rendered, frames, TIMEOUT,
+        );
+        anyhow::ensure!(thread.is_running(), "The submit thread stopped");
+        vr.update_session_state()?;
+        if vr.pending_swapchain_size().is_some() {
+            // The runtime wants another resolution: new swapchain, eye images and render targets
+            submitted += thread.prediction().frame;
+            last_frame = 0;
+            thread = vr.recreate_submit_thread(&device, thread, |swapchain| {
+                let size = swapchain.texture(0).size();
+                eye_images = create_eye_images(&device, (size.width, size.height), format);
+                fill_swapchain(device.clone(), queue.clone(), eye_images.clone(), swapchain)
+            })?;
+            let size = vr.get_swapchain_image_layout().context("The session has no swapchain")?;
+            renderer.resize(&device, &eye_config(format, size));
+            continue;
+        }
+        let prediction = thread.prediction();
+        if prediction.frame == last_frame || !prediction.should_render {
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        last_frame = prediction.frame;
+        vr.timing_mut().begin_frame(xr::Time::from_nanos(prediction.display_time_nanos));
+        input.begin_frame(vr.poll_actions()?);
+        if let Some(hud) = vr.hud_mut() {
+            hud.handle_input(&input);
+        }
+
+        let views = vr.locate_views(&prediction)?;
+        let eyes = vr.project_views(&views);
+        let slot = thread.frame_mut().slot;
+        let targets: Vec<wgpu::TextureView> = (0..eyes.len() as u32)
+            .map(|eye| eye_images[slot].create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: eye,
+                array_layer_count: Some(1),
+                ..Default::default()
+            }))
+            .collect();
+        let render_views: Vec<RenderView> = eyes.iter().zip(&targets)
+            .map(|(eye, target)| RenderView { view: eye.view, projection: eye_projection(&eye.fov), target })
+            .collect();
+        scene.step(prediction.display_period_nanos as f32 * 1e-9);
+        renderer.render_views(&device, &queue, &scene, &render_views)
+            .map_err(|e| anyhow!("Rendering frame {} failed: {:?}", prediction.frame, e))?;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("VR Test HUD Encoder"),
+        });
+        let hud_eyes: Vec<_> = targets.iter().zip(&eyes).collect();
+        vr.render_hud(&device, &queue, &mut encoder, &hud_eyes, Some(renderer.profiler()), renderer.settings().resolution_scale);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let frame = thread.frame_mut();
+        frame.prediction = prediction;
+        frame.views = views;
+        thread.publish();
+        vr.timing_mut().end_frame();
+        rendered += 1;
+    }
+
+    submitted += thread.prediction().frame;
+    vr.join_submit_thread(thread)?;
+    device.poll(wgpu::Maintain::Wait);
+    Ok(submitted)
+}
+
+/// The renderer's targets for eye images of `size`
+fn eye_config(format: wgpu::TextureFormat, (width, height): (u32, u32)) -> wgpu::SurfaceConfiguration {
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Fifo,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![],
+        desired_maximum_frame_latency: 2,
+    }
+}
+
+/// Each frame slot's eye images, copied into the acquired swapchain image on the submit thread
+fn create_eye_images(device: &wgpu::Device, (width, height): (u32, u32), format: wgpu::TextureFormat) -> Arc<Vec<wgpu::Texture>> {
+    Arc::new((0..FRAME_SLOTS)
+        .map(|slot| device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("VR Test Eyes {}", slot)),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 2 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        }))
+        .collect())
+}
+
+/// Copy a frame slot's eye images into the acquired swapchain image
+fn fill_swapchain(
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    eye_images: Arc<Vec<wgpu::Texture>>,
+    swapchain: SwapchainTextures,
+) -> FillSwapchain {
+    Box::new(move |index: u32, frame: &VrFrame| -> Result<()> {
+        let eyes = &eye_images[frame.slot];
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("VR Test Fill Encoder"),
+        });
+        encoder.copy_texture_to_texture(eyes.as_image_copy(), swapchain.texture(index).as_image_copy(), eyes.size());
+        swapchain.prepare_release(&mut encoder, index);
+        queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    })
+}
+
+/// An eye's off-axis projection in the renderer's depth convention (`Camera`'s, not the
+/// reverse-Z of `ViewProjection`)
+fn eye_projection(fov: &xr::Fovf) -> Mat4 {
+    let left = fov.angle_left.tan() * NEAR_PLANE;
+    let right = fov.angle_right.tan() * NEAR_PLANE;
+    let down = fov.angle_down.tan() * NEAR_PLANE;
+    let up = fov.angle_up.tan() * NEAR_PLANE;
+    Mat4::from_cols(
+        Vec4::new(2.0 * NEAR_PLANE / (right - left), 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 2.0 * NEAR_PLANE / (up - down), 0.0, 0.0),
+        Vec4::new((right + left) / (right - left), (up + down) / (up - down), -(FAR_PLANE + NEAR_PLANE) / (FAR_PLANE - NEAR_PLANE), -1.0),
+        Vec4::new(0.0, 0.0, -2.0 * FAR_PLANE * NEAR_PLANE / (FAR_PLANE - NEAR_PLANE), 0.0),
+    )
+}
@@ -1,5 +1,10 @@
-use glam::{Mat4, Vec3, Quat};
+use glam::{Mat4, Vec3, Vec4, Quat};
 use openxr as xr;
+use crate::scene::Ray;
+use crate::settings::{StereoMode, VrStereoSettings};
+
+/// Distance to the near plane of VR projections (meters)
+pub const NEAR_PLANE: f32 = 0.001;
 
 #[derive(Debug)]
 pub struct ViewProjection {
@@ -32,7 +37,9 @@ impl ViewProjection {
     }
 }
 
-/// Creates a perspective projection matrix from FoV angles using reverse Z with infinite far plane
+/// Creates a perspective projection matrix from FoV angles using reverse Z with infinite far plane:
+/// depth is 1 at `near` and falls towards 0 with distance. The angles are OpenXR's, so `left`
+/// and `down` are negative for a view centered on the eye.
 pub fn perspective_infinite_reverse_rh(
     left: f32,
     right: f32,
@@ -54,12 +61,56 @@ pub fn perspective_infinite_reverse_rh(
     let a = (right + left) / width;
     let b = (up + down) / height;
 
-    Mat4::from_cols_array(&[
-        x,    0.0,  a,    0.0,
-        0.0,  y,    b,    0.0,
-        0.0,  0.0,  0.0,  near,
-        0.0,  0.0,  -1.0, 0.0,
-    ])
+    Mat4::from_cols(
+        Vec4::new(x, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, y, 0.0, 0.0),
+        Vec4::new(a, b, 0.0, -1.0),
+        Vec4::new(0.0, 0.0, near, 0.0),
+    )
+}
+
+/// The ray a tracking-space aim pose points along (its -Z axis), moved into the world
+pub fn aim_ray(pose: &xr::Posef, tracking_to_world: &Mat4) -> Ray {
+    let position = Vec3::new(pose.position.x, pose.position.y, pose.position.z);
+    let orientation = Quat::from_xyzw(
+        pose.orientation.x,
+        pose.orientation.y,
+        pose.orientation.z,
+        pose.orientation.w,
+    );
+    Ray::new(position, orientation * Vec3::NEG_Z).transform(tracking_to_world)
+}
+
+/// Move a stereo pair of eye poses as `stereo` asks, keeping the point between them; poses
+/// and FoVs are left as the runtime reported them otherwise. Submit the adjusted poses with
+/// the frame so the compositor's reprojection matches what was rendered.
+pub fn adjust_stereo(views: &mut [xr::View], stereo: &VrStereoSettings) {
+    let [left, right] = views else {
+        return;
+    };
+    let position = |pose: &xr::Posef| Vec3::new(pose.position.x, pose.position.y, pose.position.z);
+    let (left_position, right_position) = (position(&left.pose), position(&right.pose));
+    let center = (left_position + right_position) * 0.5;
+    let runtime_offset = (right_position - left_position) * 0.5;
+
+    let separation = stereo.ipd.unwrap_or(runtime_offset.length() * 2.0) * stereo.ipd_scale;
+    // Coincident eyes give no direction to spread them along; use the left eye's right
+    let direction = runtime_offset.try_normalize().unwrap_or_else(|| {
+        let o = left.pose.orientation;
+        Quat::from_xyzw(o.x, o.y, o.z, o.w) * Vec3::X
+    });
+    let offset = match stereo.mode {
+        StereoMode::Stereo => direction * separation * 0.5,
+        StereoMode::Swapped => -direction * separation * 0.5,
+        StereoMode::Mono => {
+            right.pose.orientation = left.pose.orientation;
+            Vec3::ZERO
+        }
+    };
+
+    let to_xr = |v: Vec3| xr::Vector3f { x: v.x, y: v.y, z: v.z };
+    left.pose.position = to_xr(center - offset);
+    right.pose.position = to_xr(center + offset);
 }
 
 pub fn create_view_matrix(pose: &xr::Posef) -> Mat4 {
@@ -79,6 +130,19 @@ pub fn create_view_matrix(pose: &xr::Posef) -> Mat4 {
     Mat4::from_rotation_translation(orientation, position).inverse()
 }
 
+/// Tracking-to-world adjustment for comfort options: raises the user by
+/// `height_offset` and rotates them by `yaw` (radians) about the origin.
+/// Multiply a view matrix by this on the right.
+pub fn comfort_transform(height_offset: f32, yaw: f32) -> Mat4 {
+    Mat4::from_translation(Vec3::new(0.0, -height_offset, 0.0)) * Mat4::from_rotation_y(-yaw)
+}
+
+/// World to tracking space: the play space sits at `stage_position` in the space of `parent`
+/// (a vehicle or elevator's world matrix, identity for none), with the comfort options on top
+pub fn world_to_tracking(height_offset: f32, yaw: f32, stage_position: Vec3, parent: Mat4) -> Mat4 {
+    comfort_transform(height_offset, yaw) * Mat4::from_translation(-stage_position) * parent.inverse()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,8 +155,9 @@ mod tests {
         // Test that the matrix preserves symmetry
         assert!((mat.col(0)[0] - mat.col(1)[1]).abs() < 1e-6);
         
-        // Test that near plane is correctly set
-        assert!((mat.col(2)[3] - 0.1).abs() < 1e-6);
+        // Test that near plane is correctly set: clip depth is the near distance, and w is -z
+        assert!((mat.col(3)[2] - 0.1).abs() < 1e-6);
+        assert_eq!(mat.col(2)[3], -1.0);
     }
 
     #[test]
@@ -118,4 +183,154 @@ mod tests {
         assert!((view_mat.col(3)[1] + 2.0).abs() < 1e-6);
         assert!((view_mat.col(3)[2] + 3.0).abs() < 1e-6);
     }
-} 
\ No newline at end of file
+
+    /// Clip space to normalized device coordinates
+    fn project(matrix: Mat4, point: Vec3) -> Vec3 {
+        let clip = matrix * point.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    #[test]
+    fn test_projection_fixtures() {
+        // Symmetric FoVs match glam's reverse-Z projection, which takes a vertical FoV and aspect
+        let (half_width, half_height) = (0.6f32, 0.45f32);
+        let symmetric = perspective_infinite_reverse_rh(-half_width, half_width, half_height, -half_height, NEAR_PLANE);
+        let aspect = half_width.tan() / half_height.tan();
+        let expected = Mat4::perspective_infinite_reverse_rh(half_height * 2.0, aspect, NEAR_PLANE);
+        assert!(symmetric.abs_diff_eq(expected, 1e-5), "{} != {}", symmetric, expected);
+
+        // A hand-computed asymmetric fixture: tan(-0.8) = -1.0296, tan(0.7) = 0.8423
+        let (left, right, up, down) = (-0.8f32, 0.7f32, 0.75f32, -0.9f32);
+        let matrix = perspective_infinite_reverse_rh(left, right, up, down, 0.05);
+        let expected = Mat4::from_cols_array(&[
+            1.068418, 0.0, 0.0, 0.0,
+            0.0, 0.912511, 0.0, 0.0,
+            -0.100084, -0.149908, 0.0, -1.0,
+            0.0, 0.0, 0.05, 0.0,
+        ]);
+        assert!(matrix.abs_diff_eq(expected, 1e-5), "{} != {}", matrix, expected);
+
+        // Each edge of the field of view lands on the matching edge of the image
+        let edge = |angle_x: f32, angle_y: f32| project(matrix, Vec3::new(angle_x.tan(), angle_y.tan(), -1.0));
+        assert!((edge(left, 0.0).x + 1.0).abs() < 1e-5);
+        assert!((edge(right, 0.0).x - 1.0).abs() < 1e-5);
+        assert!((edge(0.0, up).y - 1.0).abs() < 1e-5);
+        assert!((edge(0.0, down).y + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_reverse_depth() {
+        let matrix = perspective_infinite_reverse_rh(-0.8, 0.8, 0.8, -0.8, NEAR_PLANE);
+        let depth = |distance: f32| project(matrix, Vec3::new(0.0, 0.0, -distance)).z;
+        // 1 at the near plane, halving with each doubling of distance and never reaching 0
+        assert!((depth(NEAR_PLANE) - 1.0).abs() < 1e-6);
+        assert!((depth(NEAR_PLANE * 2.0) - 0.5).abs() < 1e-6);
+        assert!((depth(1000.0) - NEAR_PLANE / 1000.0).abs() < 1e-9);
+        assert!(depth(1e30) > 0.0);
+        // Behind the near plane is clipped
+        assert!(depth(NEAR_PLANE * 0.5) > 1.0);
+    }
+
+    #[test]
+    fn test_stereo_disparity() {
+        // Headsets mirror each eye's FoV, canted outwards
+        let left = eye(-0.032);
+        let mut right = eye(0.032);
+        right.fov = xr::Fovf { angle_left: -0.7, angle_right: 0.8, ..left.fov };
+        let (left, right) = (ViewProjection::from_xr_view(&left, NEAR_PLANE), ViewProjection::from_xr_view(&right, NEAR_PLANE));
+        let ndc = |eye: &ViewProjection, point: Vec3| project(eye.projection * eye.view, point);
+
+        // Far away, straight ahead, the eyes see mirror images of the FoV offset
+        let horizon = Vec3::new(0.0, 1.6, -1e6);
+        let offset = (0.8f32.tan() - 0.7f32.tan()) / (0.8f32.tan() + 0.7f32.tan());
+        assert!((ndc(&left, horizon).x - offset).abs() < 1e-5);
+        assert!((ndc(&right, horizon).x + offset).abs() < 1e-5);
+        assert!((ndc(&left, horizon).y - ndc(&right, horizon).y).abs() < 1e-6);
+
+        // Closer points shift apart by the eye separation over the distance, scaled to the image
+        let scale = 2.0 / (0.8f32.tan() + 0.7f32.tan());
+        for distance in [0.5, 2.0, 10.0] {
+            let point = Vec3::new(0.0, 1.6, -distance);
+            let disparity = ndc(&left, point).x - ndc(&right, point).x;
+            let expected = 2.0 * offset + 0.064 * scale / distance;
+            assert!((disparity - expected).abs() < 1e-4, "{} at {}m, expected {}", disparity, distance, expected);
+            // Both eyes agree on depth for a point ahead of them
+            assert!((ndc(&left, point).z - ndc(&right, point).z).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_aim_ray() {
+        // Turned 90 degrees left, pointing down -X
+        let half = std::f32::consts::FRAC_PI_4;
+        let pose = xr::Posef {
+            orientation: xr::Quaternionf { x: 0.0, y: half.sin(), z: 0.0, w: half.cos() },
+            position: xr::Vector3f { x: 0.0, y: 1.0, z: 0.0 },
+        };
+        let ray = aim_ray(&pose, &Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0)));
+        assert!((ray.origin - Vec3::new(5.0, 1.0, 0.0)).length() < 1e-5);
+        assert!((ray.direction - Vec3::NEG_X).length() < 1e-5);
+    }
+
+    fn eye(x: f32) -> xr::View {
+        xr::View {
+            pose: xr::Posef {
+                orientation: xr::Quaternionf { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+                position: xr::Vector3f { x, y: 1.6, z: 0.0 },
+            },
+            fov: xr::Fovf { angle_left: -0.8, angle_right: 0.7, angle_up: 0.8, angle_down: -0.8 },
+        }
+    }
+
+    #[test]
+    fn test_adjust_stereo() {
+        let adjusted = |stereo: VrStereoSettings| {
+            let mut views = [eye(0.97), eye(1.03)];
+            adjust_stereo(&mut views, &stereo);
+            [views[0].pose.position.x, views[1].pose.position.x]
+        };
+        let close = |[a, b]: [f32; 2], [c, d]: [f32; 2]| (a - c).abs() < 1e-5 && (b - d).abs() < 1e-5;
+
+        assert!(close(adjusted(VrStereoSettings::default()), [0.97, 1.03]));
+        assert!(close(adjusted(VrStereoSettings { ipd_scale: 2.0, ..VrStereoSettings::default() }), [0.94, 1.06]));
+        assert!(close(adjusted(VrStereoSettings { ipd: Some(0.1), ..VrStereoSettings::default() }), [0.95, 1.05]));
+        assert!(close(adjusted(VrStereoSettings { mode: StereoMode::Swapped, ..VrStereoSettings::default() }), [1.03, 0.97]));
+        assert!(close(adjusted(VrStereoSettings { mode: StereoMode::Mono, ..VrStereoSettings::default() }), [1.0, 1.0]));
+
+        // Eyes the runtime reports in one place can still be given a separation
+        let mut views = [eye(1.0), eye(1.0)];
+        adjust_stereo(&mut views, &VrStereoSettings { ipd: Some(0.06), ..VrStereoSettings::default() });
+        assert!((views[1].pose.position.x - views[0].pose.position.x - 0.06).abs() < 1e-5);
+        assert_eq!(views[0].fov.angle_left, -0.8);
+    }
+
+    #[test]
+    fn test_comfort_transform() {
+        // Raising the user moves a point at eye level below the eye
+        let view = comfort_transform(0.5, 0.0);
+        let p = view.transform_point3(Vec3::new(0.0, 1.0, 0.0));
+        assert!((p.y - 0.5).abs() < 1e-6);
+
+        // Positive yaw turns the user left, so a point that was ahead ends up on their right
+        let view = comfort_transform(0.0, std::f32::consts::FRAC_PI_2);
+        let p = view.transform_point3(Vec3::new(0.0, 0.0, -1.0));
+        assert!((p.x - 1.0).abs() < 1e-6 && p.z.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_world_to_tracking() {
+        // The stage offset applies within the parent: a cabin at x=10 turned to face +X, with the
+        // play space 1m along the cabin's forward axis
+        let parent = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)) * Mat4::from_rotation_y(-std::f32::consts::FRAC_PI_2);
+        let cabin = world_to_tracking(0.0, 0.0, Vec3::new(0.0, 0.0, -1.0), parent);
+        let origin = cabin.inverse().transform_point3(Vec3::ZERO);
+        assert!((origin - Vec3::new(11.0, 0.0, 0.0)).length() < 1e-5);
+        // Ahead of the user is ahead of the cabin
+        let ahead = cabin.transform_point3(Vec3::new(12.0, 0.0, 0.0));
+        assert!((ahead - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-5);
+
+        // Without a parent it's the comfort transform after the stage offset
+        let plain = world_to_tracking(0.5, 0.0, Vec3::new(1.0, 0.0, 0.0), Mat4::IDENTITY);
+        assert_eq!(plain, comfort_transform(0.5, 0.0) * Mat4::from_translation(Vec3::new(-1.0, 0.0, 0.0)));
+    }
+}
@@ -4,12 +4,29 @@ pub mod vulkan;
 pub mod system;
 pub mod frame;
 pub mod timing;
-
-pub use pipeline::VRPipeline;
+pub mod hud;
+pub mod actions;
+pub mod submit;
+pub mod adapter;
+pub mod diagnose;
+pub mod debug;
+pub mod swapchain;
+
+pub use pipeline::{StereoMode, StereoPass, VRPipeline, VRUniform, VR_VIEW_COUNT};
 pub use math::ViewProjection;
 pub use system::VRSystem;
 pub use frame::FrameManager;
-pub use timing::FrameTiming;
+pub use timing::{FrameTiming, FrameTimingManager};
+pub use hud::{HudAnchor, HudStats, VrHud};
+pub use actions::VrActions;
+pub use adapter::select_vr_adapter;
+pub use diagnose::{VrDiagnostics, VrSetupError};
+pub use debug::VrDebugOptions;
+pub use swapchain::SwapchainTextures;
+pub use submit::{FillSwapchain, FrameLoopControl, FramePrediction, SubmitThread, VrFrame, XrSubmitter};
+
+#[cfg(all(test, feature = "vr-integration"))]
+mod integration;
 
 #[cfg(test)]
 mod tests {
@@ -210,8 +227,8 @@ mod tests {
                 }
             }
 
-            // Check projection matrix properties
-            if proj.projection.w_axis.z >= 0.0 {
+            // Check projection matrix properties; reverse Z keeps the near distance in W.z
+            if proj.projection.w_axis.z <= 0.0 {
                 println!("Invalid projection W.z: {}", proj.projection.w_axis.z);
                 return Err("Invalid projection matrix W.z".to_string());
             }
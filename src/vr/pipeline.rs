@@ -1,30 +1,163 @@
 use wgpu;
 use std::mem;
+use anyhow::Result;
 use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
 use crate::model::ModelVertex;
+use crate::shaders;
+use super::math::ViewProjection;
 
+/// Views the pipeline renders in one pass, one per swapchain array layer
+pub const VR_VIEW_COUNT: u32 = 2;
+
+const VR_SHADER: &str = include_str!("shaders/vr.wgsl");
+const VR_EYE_SHADER: &str = include_str!("shaders/vr_eye.wgsl");
+
+/// How `VRPipeline` reaches both eyes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoMode {
+    /// One pass into both layers of the target, each draw reaching both eyes through
+    /// `@builtin(view_index)`
+    Multiview,
+    /// One pass per layer, for drivers without multiview; twice the draw calls
+    PerEye,
+}
+
+impl StereoMode {
+    /// Multiview when `device` was created with `VRPipeline::MULTIVIEW_FEATURES`
+    pub fn for_device(device: &wgpu::Device) -> Self {
+        if device.features().contains(VRPipeline::MULTIVIEW_FEATURES) {
+            Self::Multiview
+        } else {
+            Self::PerEye
+        }
+    }
+}
+
+/// One render pass of a stereo frame, from `VRPipeline::passes`
+pub struct StereoPass {
+    /// The eye's layer, or with multiview every layer
+    pub color: wgpu::TextureView,
+    pub depth: wgpu::TextureView,
+    /// The eye drawn, or with multiview 0
+    pub eye: u32,
+}
+
+/// Both eyes' matrices, indexed in the shader by `@builtin(view_index)`
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct VRUniform {
-    pub view_proj: [[f32; 4]; 4],
-    pub view: [[f32; 4]; 4],
-    pub proj: [[f32; 4]; 4],
-    pub eye_position: [f32; 3],
-    pub _padding: u32,
+    pub view_proj: [[[f32; 4]; 4]; VR_VIEW_COUNT as usize],
+    pub view: [[[f32; 4]; 4]; VR_VIEW_COUNT as usize],
+    pub proj: [[[f32; 4]; 4]; VR_VIEW_COUNT as usize],
+    /// `w` is unused; vec4 so the array stride matches WGSL's
+    pub eye_position: [[f32; 4]; VR_VIEW_COUNT as usize],
 }
 
+impl VRUniform {
+    /// Matrices of `views` in eye order; eyes past the end of `views` repeat the last one
+    pub fn from_views(views: &[ViewProjection]) -> Self {
+        let mut uniform = Self::zeroed();
+        for eye in 0..VR_VIEW_COUNT as usize {
+            let Some(view) = views.get(eye).or(views.last()) else {
+                break;
+            };
+            uniform.view_proj[eye] = (view.projection * view.view).to_cols_array_2d();
+            uniform.view[eye] = view.view.to_cols_array_2d();
+            uniform.proj[eye] = view.projection.to_cols_array_2d();
+            // Taken from the view matrix so comfort offsets are included
+            uniform.eye_position[eye] = view.view.inverse().w_axis.truncate().extend(1.0).to_array();
+        }
+        uniform
+    }
+}
+
+/// Check `VRUniform` against the struct in `shaders/vr.wgsl`
+#[cfg(any(test, debug_assertions))]
+fn check_uniform_layout() -> Result<()> {
+    shaders::check_uniform_layout(&shaders::resolve(VR_SHADER)?.source, "VRUniform", &crate::uniform_layout!(VRUniform {
+        view_proj, view, proj, eye_position,
+    }))
+}
+
+/// Draws models into both eyes of a swapchain image, in one multiview pass where the device
+/// supports it and else one pass per eye (`StereoMode`), from `shaders/vr.wgsl` or, with the
+/// `spirv-shaders` feature, precompiled SPIR-V
 pub struct VRPipeline {
     pub render_pipeline: wgpu::RenderPipeline,
     pub uniform_bind_group_layout: wgpu::BindGroupLayout,
     pub uniform_buffer: wgpu::Buffer,
+    /// With multiview, the bind group for every pass; else the first eye's
     pub uniform_bind_group: wgpu::BindGroup,
+    stereo: StereoMode,
+    /// The other eyes' bind groups, per eye
+    eye_bind_groups: Vec<wgpu::BindGroup>,
 }
 
 impl VRPipeline {
+    /// Device features for the single-pass multiview path; request them where the adapter has
+    /// them (`adapter.features() & MULTIVIEW_FEATURES`), and `new` falls back to a pass per
+    /// eye where it doesn't
+    pub const MULTIVIEW_FEATURES: wgpu::Features = wgpu::Features::MULTIVIEW;
+
+    /// What to clear the depth target to. Eye projections are reverse-Z (`ViewProjection`),
+    /// with depth 1 at the near plane falling toward 0 far away, so the pipeline keeps the
+    /// greater depth and a clear to 0 is the farthest there is.
+    pub const DEPTH_CLEAR: f32 = 0.0;
+
+    /// Picks the stereo mode from the device's features
     pub fn new(
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
         depth_format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        #[cfg(debug_assertions)]
+        check_uniform_layout()?;
+        let stereo = StereoMode::for_device(device);
+        if stereo == StereoMode::PerEye {
+            log::info!("The device has no multiview; VR eyes are drawn in separate passes");
+        }
+        let shader = match stereo {
+            StereoMode::Multiview => shaders::create_module(device, "VR Shader", VR_SHADER),
+            StereoMode::PerEye => shaders::create_module(device, "VR Eye Shader", VR_EYE_SHADER),
+        };
+        Ok(Self::with_module(device, &shader, format, depth_format, stereo))
+    }
+
+    /// A pipeline from custom SPIR-V, passed to the driver untranslated. `spirv` must have
+    /// `vs_main` and `fs_main` entry points using the same bindings and vertex layout as
+    /// `shaders/vr.wgsl`; the device needs `MULTIVIEW` and `SPIRV_SHADER_PASSTHROUGH`, as
+    /// there's no per-eye fallback for SPIR-V.
+    #[cfg(feature = "spirv-shaders")]
+    pub fn with_spirv(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        spirv: &[u32],
+    ) -> Result<Self> {
+        let required = Self::MULTIVIEW_FEATURES | wgpu::Features::SPIRV_SHADER_PASSTHROUGH;
+        anyhow::ensure!(
+            device.features().contains(required),
+            "SPIR-V VR shaders need {:?}, which the device wasn't created with",
+            required - device.features(),
+        );
+        anyhow::ensure!(spirv.first() == Some(&0x0723_0203), "Not a SPIR-V module");
+        // SAFETY: the module isn't validated; it is the caller's to match the pipeline layout
+        let shader = unsafe {
+            device.create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
+                label: Some("VR SPIR-V Shader"),
+                source: spirv.into(),
+            })
+        };
+        Ok(Self::with_module(device, &shader, format, depth_format, StereoMode::Multiview))
+    }
+
+    fn with_module(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        stereo: StereoMode,
     ) -> Self {
         // Create uniform buffer
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -34,36 +167,53 @@ impl VRPipeline {
             mapped_at_creation: false,
         });
 
-        // Create bind group layout
+        // Create bind group layout; drawing an eye per pass adds the eye's index
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout_entries = [uniform_entry(0), uniform_entry(1)];
         let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("VR Uniform Bind Group Layout"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-        });
-
-        // Create bind group
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("VR Uniform Bind Group"),
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
+            entries: match stereo {
+                StereoMode::Multiview => &layout_entries[..1],
+                StereoMode::PerEye => &layout_entries,
+            },
         });
 
-        // Create shader module
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("VR Shader"),
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!("shaders/vr.wgsl"))),
-        });
+        // Create bind groups, one per eye without multiview
+        let eyes = match stereo {
+            StereoMode::Multiview => 1,
+            StereoMode::PerEye => VR_VIEW_COUNT,
+        };
+        let mut bind_groups: Vec<_> = (0..eyes)
+            .map(|eye| {
+                let eye_buffer = (stereo == StereoMode::PerEye).then(|| {
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("VR Eye Buffer"),
+                        contents: bytemuck::cast_slice(&[eye, 0, 0, 0]),
+                        usage: wgpu::BufferUsages::UNIFORM,
+                    })
+                });
+                let entries: Vec<_> = std::iter::once(uniform_buffer.as_entire_binding())
+                    .chain(eye_buffer.as_ref().map(wgpu::Buffer::as_entire_binding))
+                    .enumerate()
+                    .map(|(binding, resource)| wgpu::BindGroupEntry { binding: binding as u32, resource })
+                    .collect();
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("VR Uniform Bind Group"),
+                    layout: &uniform_bind_group_layout,
+                    entries: &entries,
+                })
+            })
+            .collect();
+        let uniform_bind_group = bind_groups.remove(0);
 
         // Create pipeline layout
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -77,13 +227,13 @@ impl VRPipeline {
             label: Some("VR Render Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("vs_main"),
                 buffers: &[ModelVertex::desc()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format,
@@ -104,7 +254,7 @@ impl VRPipeline {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: depth_format,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_compare: wgpu::CompareFunction::GreaterEqual,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
@@ -113,7 +263,11 @@ impl VRPipeline {
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
-            multiview: None,
+            // Both eyes in one draw, into the layers of a `SwapchainTextures::array_view`
+            multiview: match stereo {
+                StereoMode::Multiview => std::num::NonZeroU32::new(VR_VIEW_COUNT),
+                StereoMode::PerEye => None,
+            },
             cache: None,
         });
 
@@ -122,10 +276,237 @@ impl VRPipeline {
             uniform_bind_group_layout,
             uniform_buffer,
             uniform_bind_group,
+            stereo,
+            eye_bind_groups: bind_groups,
+        }
+    }
+
+    pub fn stereo(&self) -> StereoMode {
+        self.stereo
+    }
+
+    /// The passes that draw a stereo frame into `color`, e.g. a swapchain image, with
+    /// `depth`; both have a layer per eye. Begin each pass on its views, clearing depth to
+    /// `DEPTH_CLEAR`, `bind` it, then draw.
+    pub fn passes(&self, color: &wgpu::Texture, depth: &wgpu::Texture) -> Vec<StereoPass> {
+        let layers = |texture: &wgpu::Texture, base_array_layer, array_layer_count, dimension| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("VR Stereo Pass View"),
+                dimension: Some(dimension),
+                base_array_layer,
+                array_layer_count: Some(array_layer_count),
+                ..Default::default()
+            })
+        };
+        match self.stereo {
+            StereoMode::Multiview => vec![StereoPass {
+                color: layers(color, 0, VR_VIEW_COUNT, wgpu::TextureViewDimension::D2Array),
+                depth: layers(depth, 0, VR_VIEW_COUNT, wgpu::TextureViewDimension::D2Array),
+                eye: 0,
+            }],
+            StereoMode::PerEye => (0..VR_VIEW_COUNT)
+                .map(|eye| StereoPass {
+                    color: layers(color, eye, 1, wgpu::TextureViewDimension::D2),
+                    depth: layers(depth, eye, 1, wgpu::TextureViewDimension::D2),
+                    eye,
+                })
+                .collect(),
         }
     }
 
+    /// Set the pipeline and the bind group of `eye`'s pass
+    pub fn bind(&self, render_pass: &mut wgpu::RenderPass<'_>, eye: u32) {
+        let bind_group = match eye {
+            0 => &self.uniform_bind_group,
+            eye => &self.eye_bind_groups[eye as usize - 1],
+        };
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+    }
+
     pub fn update_uniform(&self, queue: &wgpu::Queue, uniform: &VRUniform) {
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[*uniform]));
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Mat4, Vec3};
+    use openxr as xr;
+
+    fn view(x: f32) -> ViewProjection {
+        ViewProjection {
+            view: Mat4::from_translation(Vec3::new(-x, -1.6, 0.0)),
+            projection: Mat4::perspective_infinite_reverse_rh(1.5, 1.0, 0.01),
+            fov: xr::Fovf { angle_left: -0.75, angle_right: 0.75, angle_up: 0.75, angle_down: -0.75 },
+            pose: xr::Posef::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn test_uniform_from_views() {
+        // Three matrix arrays and a vec4 array, with no padding between them
+        assert_eq!(mem::size_of::<VRUniform>(), 3 * 2 * 64 + 2 * 16);
+
+        let uniform = VRUniform::from_views(&[view(-0.03), view(0.03)]);
+        assert_eq!(uniform.eye_position[0], [-0.03, 1.6, 0.0, 1.0]);
+        assert_eq!(uniform.eye_position[1], [0.03, 1.6, 0.0, 1.0]);
+        let right = view(0.03);
+        assert_eq!(uniform.view_proj[1], (right.projection * right.view).to_cols_array_2d());
+
+        // A single view is shared by both eyes
+        let mono = VRUniform::from_views(&[view(0.0)]);
+        assert_eq!(mono.view_proj[0], mono.view_proj[1]);
+        assert_eq!(VRUniform::from_views(&[]).view_proj, [[[0.0; 4]; 4]; 2]);
+    }
+
+    #[test]
+    fn test_uniform_matches_wgsl() {
+        check_uniform_layout().unwrap();
+    }
+
+    /// A device without multiview, as on drivers that lack it
+    fn per_eye_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        use pollster::FutureExt;
+        let adapter = wgpu::Instance::default()
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                force_fallback_adapter: true,
+                compatible_surface: None,
+            })
+            .block_on()?;
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                    memory_hints: Default::default(),
+                },
+                None,
+            )
+            .block_on()
+            .ok()
+    }
+
+    /// A vertex at clip-space `x`, `y` and depth `z` under identity eye matrices
+    fn vertex(x: f32, y: f32, z: f32, color: [f32; 4]) -> ModelVertex {
+        ModelVertex {
+            position: [x, y, z],
+            tex_coords: [1.0, 1.0],
+            normal: [0.0, 0.0, 1.0],
+            tangent: [1.0, 0.0, 0.0, 1.0],
+            color,
+        }
+    }
+
+    /// A 4x4 target with a layer per eye
+    fn layered(device: &wgpu::Device, format: wgpu::TextureFormat, usage: wgpu::TextureUsages) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d { width: 4, height: 4, depth_or_array_layers: VR_VIEW_COUNT },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        })
+    }
+
+    /// Draw `vertices` into both eyes of a 4x4 target, returning each eye's center pixel
+    fn draw_eyes(device: &wgpu::Device, queue: &wgpu::Queue, pipeline: &VRPipeline, vertices: &[ModelVertex]) -> Vec<Vec<u8>> {
+        use wgpu::util::DeviceExt;
+        use crate::readback::{self, TextureRegion};
+
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let color = layered(device, format, wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC);
+        let depth = layered(device, wgpu::TextureFormat::Depth32Float, wgpu::TextureUsages::RENDER_ATTACHMENT);
+
+        let passes = pipeline.passes(&color, &depth);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        for pass in &passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &pass.color,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: wgpu::StoreOp::Store },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &pass.depth,
+                    depth_ops: Some(wgpu::Operations { load: wgpu::LoadOp::Clear(VRPipeline::DEPTH_CLEAR), store: wgpu::StoreOp::Store }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pipeline.bind(&mut render_pass, pass.eye);
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(0..vertices.len() as u32, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        (0..VR_VIEW_COUNT)
+            .map(|layer| {
+                let region = TextureRegion { x: 2, y: 2, width: 1, height: 1, mip_level: 0, layer };
+                readback::read_texture_region(device, queue, &color, region).unwrap().data
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_per_eye_fallback() {
+        let Some((device, queue)) = per_eye_device() else {
+            println!("Skipping test 'test_per_eye_fallback' - no suitable GPU adapter available");
+            return;
+        };
+        let pipeline = VRPipeline::new(&device, wgpu::TextureFormat::Rgba8Unorm, wgpu::TextureFormat::Depth32Float).unwrap();
+        assert_eq!(pipeline.stereo(), StereoMode::PerEye);
+        let passes = pipeline.passes(
+            &layered(&device, wgpu::TextureFormat::Rgba8Unorm, wgpu::TextureUsages::RENDER_ATTACHMENT),
+            &layered(&device, wgpu::TextureFormat::Depth32Float, wgpu::TextureUsages::RENDER_ATTACHMENT),
+        );
+        assert_eq!(passes.iter().map(|pass| pass.eye).collect::<Vec<_>>(), [0, 1]);
+
+        // The left eye sees the triangle; the right eye's matrix moves it out of view
+        let mut uniform = VRUniform::zeroed();
+        uniform.view_proj[0] = Mat4::IDENTITY.to_cols_array_2d();
+        uniform.view_proj[1] = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)).to_cols_array_2d();
+        pipeline.update_uniform(&queue, &uniform);
+
+        let white = [1.0; 4];
+        let centers = draw_eyes(&device, &queue, &pipeline, &[
+            vertex(-1.0, -1.0, 0.5, white), vertex(3.0, -1.0, 0.5, white), vertex(-1.0, 3.0, 0.5, white),
+        ]);
+        assert!(centers[0][2] > 0, "the left eye wasn't drawn");
+        assert_eq!(centers[1], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_nearest_surface_wins() {
+        let Some((device, queue)) = per_eye_device() else {
+            println!("Skipping test 'test_nearest_surface_wins' - no suitable GPU adapter available");
+            return;
+        };
+        let pipeline = VRPipeline::new(&device, wgpu::TextureFormat::Rgba8Unorm, wgpu::TextureFormat::Depth32Float).unwrap();
+        let mut uniform = VRUniform::zeroed();
+        uniform.view_proj = [Mat4::IDENTITY.to_cols_array_2d(); VR_VIEW_COUNT as usize];
+        pipeline.update_uniform(&queue, &uniform);
+
+        // Under reverse-Z the red triangle at depth 0.8 is nearer than the blue one at 0.2;
+        // it's drawn first, so a far-wins depth test would leave the blue one on top
+        let triangle = |z, color| [vertex(-1.0, -1.0, z, color), vertex(3.0, -1.0, z, color), vertex(-1.0, 3.0, z, color)];
+        let mut vertices = triangle(0.8, [1.0, 0.0, 0.0, 1.0]).to_vec();
+        vertices.extend(triangle(0.2, [0.0, 0.0, 1.0, 1.0]));
+        for center in draw_eyes(&device, &queue, &pipeline, &vertices) {
+            assert!(center[0] > center[2], "the far triangle was drawn over the near one: {:?}", center);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Vertex layout, eye matrices and shading shared by the multiview and per-eye VR shaders
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(4) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) @interpolate(flat) view_index: i32,
+};
+
+// Per-eye matrices, indexed by the eye being drawn (0 = left, 1 = right)
+struct VRUniform {
+    view_proj: array<mat4x4<f32>, 2>,
+    view: array<mat4x4<f32>, 2>,
+    proj: array<mat4x4<f32>, 2>,
+    // w unused
+    eye_position: array<vec4<f32>, 2>,
+};
+
+@group(0) @binding(0)
+var<uniform> vr: VRUniform;
+
+#include "lighting"
+
+fn eye_vertex(model: VertexInput, view_index: i32) -> VertexOutput {
+    var out: VertexOutput;
+    
+    let world_pos = vec4<f32>(model.position, 1.0);
+    out.world_position = world_pos.xyz;
+    
+    // Transform position to clip space with this eye's view-projection matrix
+    out.clip_position = vr.view_proj[view_index] * world_pos;
+    
+    // Model vertices are already in world space
+    out.world_normal = model.normal;
+    
+    out.uv = model.uv;
+    out.color = model.color;
+    out.view_index = view_index;
+    
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Normalize vectors
+    let normal = normalize(in.world_normal);
+    let view_dir = normalize(vr.eye_position[in.view_index].xyz - in.world_position);
+    
+    // Basic lighting setup
+    let light_dir = normalize(vec3<f32>(1.0, 1.0, 1.0));
+    let ambient = 0.1;
+    
+    // Base color from UV coordinates for testing
+    let base_color = vec3<f32>(in.uv.x, in.uv.y, 1.0) * in.color.rgb;
+    
+    // Same lighting terms as the scene shader
+    let color = base_color * ambient + blinn_phong(normal, light_dir, view_dir, base_color, vec3<f32>(0.7));
+    
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,16 @@
+// One eye per pass, for devices without multiview: each eye's bind group names it
+
+struct EyeUniform {
+    // x = eye index
+    index: vec4<u32>,
+};
+
+@group(0) @binding(1)
+var<uniform> eye: EyeUniform;
+
+#include "vr"
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    return eye_vertex(model, i32(eye.index.x));
+}
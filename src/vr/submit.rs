@@ -0,0 +1,499 @@
+//! The VR frame loop split across two threads. A submit thread owns the OpenXR frame loop and
+//! swapchain: it blocks in xrWaitFrame, publishes each frame's predicted display time, and
+//! composites the newest frame the render thread has finished. The render thread updates the
+//! scene and records eye images at its own pace, so the wait never stalls simulation and every
+//! frame is rendered from the freshest pose prediction available. Frames are submitted with the
+//! poses they were rendered from, leaving the compositor's time warp to correct the difference.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use openxr as xr;
+use super::frame::FrameManager;
+
+/// Per-frame data buffers shared between the render and submit threads
+pub const FRAME_SLOTS: usize = 3;
+
+// How long the submit thread sleeps while the runtime isn't running a frame loop
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+struct Shared<T> {
+    ready: T,
+    fresh: bool,
+}
+
+/// Writing end of a triple buffer: one buffer to fill while the reader holds another and the
+/// third waits between them, so neither side ever waits for the other
+pub struct TripleBufferWriter<T> {
+    back: T,
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// Reading end of a triple buffer
+pub struct TripleBufferReader<T> {
+    front: T,
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// Connected ends of a triple buffer over `buffers`: the first starts with the reader, the
+/// second as published and the third with the writer
+pub fn triple_buffer<T>(buffers: [T; 3]) -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    let [front, ready, back] = buffers;
+    let shared = Arc::new(Mutex::new(Shared { ready, fresh: false }));
+    (
+        TripleBufferWriter { back, shared: shared.clone() },
+        TripleBufferReader { front, shared },
+    )
+}
+
+impl<T> TripleBufferWriter<T> {
+    /// The buffer to fill next. It holds whatever was last swapped out of the middle, so
+    /// overwrite every field.
+    pub fn back_mut(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    /// Hand the back buffer to the reader, replacing anything it hasn't taken yet
+    pub fn publish(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        std::mem::swap(&mut shared.ready, &mut self.back);
+        shared.fresh = true;
+    }
+
+    pub fn write(&mut self, value: T) {
+        self.back = value;
+        self.publish();
+    }
+}
+
+impl<T> TripleBufferReader<T> {
+    /// Take the newest published buffer, if there is one the reader hasn't seen
+    pub fn update(&mut self) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.fresh {
+            return false;
+        }
+        std::mem::swap(&mut shared.ready, &mut self.front);
+        shared.fresh = false;
+        true
+    }
+
+    pub fn front(&self) -> &T {
+        &self.front
+    }
+}
+
+/// When the runtime will show the frame it is waiting for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FramePrediction {
+    /// Frames waited for so far, starting at 1; 0 before the first
+    pub frame: u64,
+    pub display_time_nanos: i64,
+    pub display_period_nanos: i64,
+    pub should_render: bool,
+}
+
+/// Whether a session's frame loop may run, and whether a frame is in flight, shared by the
+/// thread handling session state and a submit thread. The frame loop `enter_frame`s before
+/// xrWaitFrame and `leave_frame`s after xrEndFrame, so `stop` can wait for the frame in
+/// flight before the session is ended under it.
+#[derive(Default)]
+pub struct FrameLoopControl {
+    state: Mutex<FrameLoopState>,
+    left: Condvar,
+}
+
+#[derive(Default)]
+struct FrameLoopState {
+    running: bool,
+    in_frame: bool,
+}
+
+impl FrameLoopControl {
+    /// Let frames start, once the session has begun
+    pub fn start(&self) {
+        self.state.lock().unwrap().running = true;
+    }
+
+    /// Keep new frames from starting and wait up to `timeout` for the one in flight to end;
+    /// false if it's still going
+    pub fn stop(&self, timeout: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.running = false;
+        let (state, _) = self.left.wait_timeout_while(state, timeout, |state| state.in_frame).unwrap();
+        !state.in_frame
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.lock().unwrap().running
+    }
+
+    /// Start a frame; false, starting nothing, while the loop is stopped
+    pub fn enter_frame(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.in_frame = state.running;
+        state.running
+    }
+
+    /// End the frame `enter_frame` started
+    pub fn leave_frame(&self) {
+        self.state.lock().unwrap().in_frame = false;
+        self.left.notify_all();
+    }
+}
+
+/// The half of a frame loop that talks to the runtime, run on the submit thread
+pub trait FrameSubmitter: Send + 'static {
+    type Frame: Send + 'static;
+
+    /// Block until the runtime is ready for another frame; `None` while it isn't running a frame loop
+    fn wait_frame(&mut self) -> Result<Option<FramePrediction>>;
+
+    /// Composite `frame` for `prediction`. `fresh` is false when the render thread hasn't
+    /// finished a frame since the last submit, in which case `frame` is that same one again.
+    fn submit(&mut self, prediction: &FramePrediction, frame: &Self::Frame, fresh: bool) -> Result<()>;
+}
+
+/// A submit thread running a `FrameSubmitter`, and the render thread's ends of the buffers
+/// connecting them. Dropping it stops the thread.
+pub struct SubmitThread<S: FrameSubmitter> {
+    frames: TripleBufferWriter<S::Frame>,
+    predictions: TripleBufferReader<FramePrediction>,
+    prediction: FramePrediction,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<S>>,
+}
+
+impl<S: FrameSubmitter> SubmitThread<S> {
+    /// Start submitting with `submitter`; `frames` are the per-frame buffers, each naming
+    /// resources only it uses
+    pub fn spawn(submitter: S, frames: [S::Frame; FRAME_SLOTS]) -> Result<Self> {
+        let (frames, mut frame_reader) = triple_buffer(frames);
+        let (mut prediction_writer, predictions) = triple_buffer([FramePrediction::default(); 3]);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stopping = stop.clone();
+        let handle = thread::Builder::new()
+            .name("vr-submit".to_string())
+            .spawn(move || {
+                let mut submitter = submitter;
+                while !stopping.load(Ordering::Acquire) {
+                    let prediction = match submitter.wait_frame() {
+                        Ok(Some(prediction)) => prediction,
+                        Ok(None) => {
+                            thread::sleep(IDLE_POLL);
+                            continue;
+                        }
+                        Err(e) => {
+                            log::error!("VR frame wait failed: {:#}", e);
+                            thread::sleep(IDLE_POLL);
+                            continue;
+                        }
+                    };
+                    prediction_writer.write(prediction);
+                    let fresh = frame_reader.update();
+                    if let Err(e) = submitter.submit(&prediction, frame_reader.front(), fresh) {
+                        log::error!("VR frame submit failed: {:#}", e);
+                    }
+                }
+                submitter
+            })
+            .context("Failed to start the VR submit thread")?;
+
+        Ok(Self {
+            frames,
+            predictions,
+            prediction: FramePrediction::default(),
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The newest frame the runtime has waited for; render the next frame for its display time
+    pub fn prediction(&mut self) -> FramePrediction {
+        if self.predictions.update() {
+            self.prediction = *self.predictions.front();
+        }
+        self.prediction
+    }
+
+    /// The frame buffer the render thread owns until `publish`
+    pub fn frame_mut(&mut self) -> &mut S::Frame {
+        self.frames.back_mut()
+    }
+
+    /// Hand the frame from `frame_mut` to the submit thread; its GPU work must already be submitted
+    pub fn publish(&mut self) {
+        self.frames.publish();
+    }
+
+    /// Whether the thread is still running; it only ends early by panicking
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Stop after the frame in progress, returning the submitter unless the thread panicked
+    pub fn stop(mut self) -> Option<S> {
+        self.stop.store(true, Ordering::Release);
+        self.handle.take().and_then(|handle| handle.join().ok())
+    }
+}
+
+impl<S: FrameSubmitter> Drop for SubmitThread<S> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A frame the render thread finished for OpenXR: the eye images in `slot` and the views
+/// they were rendered from
+#[derive(Debug, Clone, Default)]
+pub struct VrFrame {
+    /// Which of the render thread's `FRAME_SLOTS` sets of eye images holds this frame
+    pub slot: usize,
+    pub prediction: FramePrediction,
+    /// Eye poses and FoVs as rendered, one per swapchain array layer; empty until the first frame
+    pub views: Vec<xr::View>,
+}
+
+impl VrFrame {
+    /// One empty frame per slot, for `SubmitThread::spawn`
+    pub fn slots() -> [Self; FRAME_SLOTS] {
+        std::array::from_fn(|slot| Self { slot, ..Self::default() })
+    }
+}
+
+/// Copies a finished frame's eye images into the acquired swapchain image with the given index
+/// (see `SwapchainTextures`), submitting the work before it returns: the image is released
+/// right after
+pub type FillSwapchain = Box<dyn FnMut(u32, &VrFrame) -> Result<()> + Send>;
+
+/// The OpenXR side of the frame loop, owning the session's frame waiter, stream and swapchain
+pub struct XrSubmitter {
+    // Declared before the frame loop so the swapchain textures it holds are dropped before
+    // the swapchain is destroyed
+    fill: FillSwapchain,
+    frames: FrameManager,
+    control: Arc<FrameLoopControl>,
+    frame_state: Option<xr::FrameState>,
+    waited: u64,
+}
+
+impl XrSubmitter {
+    /// `control` tells the submitter when the session's frame loop may run
+    pub fn new(frames: FrameManager, control: Arc<FrameLoopControl>, fill: FillSwapchain) -> Self {
+        Self { fill, frames, control, frame_state: None, waited: 0 }
+    }
+
+    fn end_submitted_frame(&mut self, frame: &VrFrame, frame_state: xr::FrameState) -> Result<()> {
+        self.frames.begin_stream()?;
+        if !frame_state.should_render || frame.views.is_empty() {
+            return self.frames.end_frame(frame_state, &[]);
+        }
+
+        let image_index = self.frames.acquire_swapchain_image()?;
+        let filled = (self.fill)(image_index, frame);
+        self.frames.release_swapchain_image()?;
+        filled?;
+
+        self.frames.end_frame_with_views(frame_state, &frame.views)
+    }
+
+    /// Give the frame loop back, e.g. to `VRSystem::join_submit_thread`
+    pub fn into_frame_manager(self) -> FrameManager {
+        self.frames
+    }
+}
+
+impl FrameSubmitter for XrSubmitter {
+    type Frame = VrFrame;
+
+    fn wait_frame(&mut self) -> Result<Option<FramePrediction>> {
+        if !self.control.enter_frame() {
+            return Ok(None);
+        }
+        let frame_state = match self.frames.wait_frame() {
+            Ok(frame_state) => frame_state,
+            Err(e) => {
+                self.control.leave_frame();
+                return Err(e);
+            }
+        };
+        self.frame_state = Some(frame_state);
+        self.waited += 1;
+        Ok(Some(FramePrediction {
+            frame: self.waited,
+            display_time_nanos: frame_state.predicted_display_time.as_nanos(),
+            display_period_nanos: frame_state.predicted_display_period.as_nanos(),
+            should_render: frame_state.should_render,
+        }))
+    }
+
+    fn submit(&mut self, _prediction: &FramePrediction, frame: &VrFrame, _fresh: bool) -> Result<()> {
+        let frame_state = self.frame_state.take().context("Submitting a frame that wasn't waited for");
+        let ended = frame_state.and_then(|frame_state| self.end_submitted_frame(frame, frame_state));
+        // Ended or failed, the frame is no longer in flight
+        self.control.leave_frame();
+        ended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_triple_buffer() {
+        let (mut writer, mut reader) = triple_buffer([0, 0, 0]);
+        assert!(!reader.update());
+
+        // Only the newest of several writes reaches the reader
+        writer.write(1);
+        writer.write(2);
+        assert!(reader.update());
+        assert_eq!(*reader.front(), 2);
+        assert!(!reader.update());
+        assert_eq!(*reader.front(), 2);
+
+        // The writer never gets the buffer the reader holds
+        let (mut writer, mut reader) = triple_buffer([0, 1, 2]);
+        for _ in 0..10 {
+            assert_ne!(*writer.back_mut(), *reader.front());
+            writer.publish();
+            reader.update();
+        }
+    }
+
+    /// Waits until the test opens its gate, recording what it submits
+    struct Gated {
+        gate: mpsc::Receiver<()>,
+        submitted: Arc<Mutex<Vec<(u32, bool)>>>,
+        waited: u64,
+    }
+
+    impl FrameSubmitter for Gated {
+        type Frame = u32;
+
+        fn wait_frame(&mut self) -> Result<Option<FramePrediction>> {
+            self.gate.recv()?;
+            self.waited += 1;
+            Ok(Some(FramePrediction { frame: self.waited, should_render: true, ..FramePrediction::default() }))
+        }
+
+        fn submit(&mut self, _prediction: &FramePrediction, frame: &u32, fresh: bool) -> Result<()> {
+            self.submitted.lock().unwrap().push((*frame, fresh));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_submit_thread() {
+        let (gate, gate_receiver) = mpsc::channel();
+        let submitted = Arc::new(Mutex::new(Vec::new()));
+        let submitter = Gated { gate: gate_receiver, submitted: submitted.clone(), waited: 0 };
+        let mut thread = SubmitThread::spawn(submitter, [0, 0, 0]).unwrap();
+        let wait_for = |count: usize| {
+            let start = Instant::now();
+            while submitted.lock().unwrap().len() < count {
+                assert!(start.elapsed() < Duration::from_secs(5), "submit thread stalled");
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        // Publishing doesn't wait for the blocked submit thread
+        for frame in 1..=2 {
+            *thread.frame_mut() = frame;
+            thread.publish();
+        }
+        assert_eq!(thread.prediction().frame, 0);
+
+        gate.send(()).unwrap();
+        wait_for(1);
+        // No new frame, so the last one is shown again
+        gate.send(()).unwrap();
+        wait_for(2);
+        *thread.frame_mut() = 3;
+        thread.publish();
+        gate.send(()).unwrap();
+        wait_for(3);
+
+        assert_eq!(thread.prediction().frame, 3);
+        assert!(thread.is_running());
+        drop(gate);
+        let submitter = thread.stop().unwrap();
+        assert_eq!(submitter.waited, 3);
+        assert_eq!(*submitted.lock().unwrap(), vec![(2, true), (2, false), (3, true)]);
+    }
+
+    /// Holds each frame open in `submit` until the test releases it, as a slow xrEndFrame would
+    struct MidFrame {
+        control: Arc<FrameLoopControl>,
+        entered: mpsc::Sender<()>,
+        release: mpsc::Receiver<()>,
+    }
+
+    impl FrameSubmitter for MidFrame {
+        type Frame = u32;
+
+        fn wait_frame(&mut self) -> Result<Option<FramePrediction>> {
+            if !self.control.enter_frame() {
+                return Ok(None);
+            }
+            Ok(Some(FramePrediction { frame: 1, should_render: true, ..FramePrediction::default() }))
+        }
+
+        fn submit(&mut self, _prediction: &FramePrediction, _frame: &u32, _fresh: bool) -> Result<()> {
+            self.entered.send(())?;
+            let released = self.release.recv();
+            self.control.leave_frame();
+            released.context("The test hung up")
+        }
+    }
+
+    #[test]
+    fn test_stop_mid_frame() {
+        let control = Arc::new(FrameLoopControl::default());
+        control.start();
+        let (entered, entered_receiver) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let submitter = MidFrame { control: control.clone(), entered, release: release_receiver };
+        let thread = SubmitThread::spawn(submitter, [0, 0, 0]).unwrap();
+        entered_receiver.recv_timeout(Duration::from_secs(5)).expect("no frame started");
+
+        // Stopping the session waits for the frame in flight instead of ending under it
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let (control, stopped) = (control.clone(), stopped.clone());
+            thread::spawn(move || {
+                let left = control.stop(Duration::from_secs(5));
+                stopped.store(true, Ordering::Release);
+                left
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!stopped.load(Ordering::Acquire), "stop returned with a frame in flight");
+        assert!(!control.is_running());
+        release.send(()).unwrap();
+        assert!(stopper.join().unwrap());
+
+        // And no frame starts after it
+        assert!(entered_receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(release);
+        assert!(thread.stop().is_some());
+
+        // A frame that never ends only holds the stop up to its timeout
+        let control = FrameLoopControl::default();
+        control.start();
+        assert!(control.enter_frame());
+        assert!(!control.stop(Duration::from_millis(10)));
+        control.leave_frame();
+        assert!(!control.enter_frame());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use ash::vk::{self, Handle};
+use wgpu::hal::api::Vulkan;
+
+/// What the engine does with swapchain images: copies eye images in, renders overlays on top
+/// and samples them for the mirror window
+pub const SWAPCHAIN_USAGE: wgpu::TextureUsages = wgpu::TextureUsages::RENDER_ATTACHMENT
+    .union(wgpu::TextureUsages::COPY_DST)
+    .union(wgpu::TextureUsages::TEXTURE_BINDING);
+
+/// An OpenXR swapchain's images wrapped as wgpu textures, one array layer per eye.
+///
+/// Writing them through wgpu keeps the barriers between the engine's own passes in wgpu's
+/// tracker. The hand-off with the runtime follows XR_KHR_vulkan_enable: the session shares
+/// wgpu's queue, so work submitted before `xrReleaseSwapchainImage` is ordered before the
+/// compositor's reads without a fence or an idle wait, as long as the image is back in the
+/// color attachment layout the runtime expects (`prepare_release`).
+///
+/// The images stay the runtime's, so drop these textures, and let wgpu free them with a
+/// `Device::poll`, before their swapchain is destroyed.
+pub struct SwapchainTextures {
+    textures: Vec<wgpu::Texture>,
+}
+
+impl SwapchainTextures {
+    /// Wrap the swapchain's `images` (from `xr::Swapchain::enumerate_images`)
+    pub fn new(device: &wgpu::Device, images: &[u64], format: wgpu::TextureFormat, (width, height): (u32, u32), eyes: u32) -> Result<Self> {
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: eyes };
+        let textures = images.iter()
+            .map(|&image| {
+                let hal_texture = unsafe {
+                    wgpu::hal::vulkan::Device::texture_from_raw(
+                        vk::Image::from_raw(image),
+                        &wgpu::hal::TextureDescriptor {
+                            label: Some("VR Swapchain Image"),
+                            size,
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: wgpu::TextureDimension::D2,
+                            format,
+                            usage: wgpu::hal::TextureUses::COLOR_TARGET
+                                | wgpu::hal::TextureUses::COPY_DST
+                                | wgpu::hal::TextureUses::RESOURCE,
+                            memory_flags: wgpu::hal::MemoryFlags::empty(),
+                            view_formats: Vec::new(),
+                        },
+                        // The runtime owns the image and destroys it with the swapchain; without
+                        // a drop guard wgpu would destroy it too
+                        Some(Box::new(|| {})),
+                    )
+                };
+                unsafe {
+                    device.create_texture_from_hal::<Vulkan>(hal_texture, &wgpu::TextureDescriptor {
+                        label: Some("VR Swapchain Image"),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format,
+                        usage: SWAPCHAIN_USAGE,
+                        view_formats: &[],
+                    })
+                }
+            })
+            .collect();
+        Ok(Self { textures })
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    /// The image `xrAcquireSwapchainImage` returned `index` for
+    pub fn texture(&self, index: u32) -> &wgpu::Texture {
+        &self.textures[index as usize]
+    }
+
+    /// A 2D view of one eye's layer of image `index`
+    pub fn eye_view(&self, index: u32, eye: u32) -> wgpu::TextureView {
+        self.texture(index).create_view(&wgpu::TextureViewDescriptor {
+            label: Some("VR Swapchain Eye View"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: eye,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    /// A 2D array view of every eye's layer of image `index`, the target of a multiview pass
+    pub fn array_view(&self, index: u32) -> wgpu::TextureView {
+        self.texture(index).create_view(&wgpu::TextureViewDescriptor {
+            label: Some("VR Swapchain Array View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    }
+
+    /// Record, last in the frame's work on image `index`, the transition back to the color
+    /// attachment layout the runtime expects at release. A copy leaves the image in the
+    /// transfer layout, which the compositor would read as garbage (and validation flags);
+    /// an empty pass that loads and stores each layer makes wgpu move it back. Submit the
+    /// encoder before releasing the image.
+    pub fn prepare_release(&self, encoder: &mut wgpu::CommandEncoder, index: u32) {
+        for eye in 0..self.texture(index).depth_or_array_layers() {
+            let view = self.eye_view(index, eye);
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("VR Swapchain Release"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+        }
+    }
+}
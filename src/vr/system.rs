@@ -1,17 +1,39 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use openxr as xr;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use glam::{Mat4, Vec3};
 use wgpu;
 
-use super::math::ViewProjection;
+use crate::input::InputEvent;
+use crate::scene::{Profiler, Ray};
+use crate::settings::{RendererSettings, VrComfortSettings, VrStereoSettings};
+use super::math::{adjust_stereo, aim_ray, create_view_matrix, world_to_tracking, ViewProjection, NEAR_PLANE};
 use super::pipeline::{VRPipeline, VRUniform};
 use super::vulkan::{
     get_vulkan_instance_from_wgpu,
+    get_vulkan_instance_from_wgpu_instance,
     get_vulkan_physical_device_from_wgpu,
     get_vulkan_device_from_wgpu,
     get_vulkan_queue_info_from_wgpu,
+    is_vulkan_device,
     wgpu_format_to_vulkan,
 };
-use super::frame::{FrameManager, FrameResources};
+use super::actions::VrActions;
+use super::debug::{DebugMessenger, VrDebugOptions, VALIDATION_LAYER};
+use super::diagnose::{VrDiagnostics, VrSetupError};
+use super::frame::{recommended_image_size, FrameManager, FrameResources};
+use super::hud::{head_transform, HudStats, VrHud};
+use super::swapchain::SwapchainTextures;
+use super::submit::{FillSwapchain, FrameLoopControl, FramePrediction, SubmitThread, XrSubmitter};
+use super::timing::FrameTimingManager;
+
+/// How often the runtime's recommended eye resolution is checked, since OpenXR has no event
+/// for it changing (e.g. the render scale being changed in SteamVR)
+const RESOLUTION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long ending a session waits for a submit thread's frame in flight; a frame takes
+/// milliseconds, so only a hung runtime runs into it
+const FRAME_LOOP_STOP_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Debug)]
 pub enum SessionState {
@@ -25,17 +47,45 @@ pub enum SessionState {
 }
 
 pub struct VRSystem {
+    // Declared before the instance so it's destroyed first
+    _debug_messenger: Option<DebugMessenger>,
     instance: xr::Instance,
     system: xr::SystemId,
     frame_manager: Option<FrameManager>,
+    // Kept here too so views can be located and events handled while a submit thread owns the frame loop
+    session: Option<xr::Session<xr::Vulkan>>,
+    stage: Option<xr::Space>,
+    swapchain_size: Option<(u32, u32)>,
+    // A recommended size the swapchain doesn't have yet, and when it was last checked
+    pending_swapchain_size: Option<(u32, u32)>,
+    resolution_polled: Option<Instant>,
+    // Whether the session's frame loop may run, shared with a submit thread
+    frame_loop: Arc<FrameLoopControl>,
     view_configuration: Option<xr::ViewConfigurationProperties>,
     swapchain_format: wgpu::TextureFormat,
     pipeline: Option<VRPipeline>,
     session_state: SessionState,
+    comfort: VrComfortSettings,
+    stereo: VrStereoSettings,
+    timing: FrameTimingManager,
+    hud: Option<VrHud>,
+    actions: Option<VrActions>,
+    // Accumulated snap-turn rotation in radians
+    world_yaw: f32,
+    // Where the tracking space origin sits in the scene's local coordinates, or its parent's
+    stage_position: Vec3,
+    // World matrix of the object the play space rides on
+    stage_parent: Option<Mat4>,
 }
 
 impl VRSystem {
+    /// Failures carry a `VrSetupError` with the runtime's diagnostics. OpenXR debugging is
+    /// turned on by `XR_DEBUG` (see `VrDebugOptions`)
     pub fn new() -> Result<Self> {
+        Self::with_debug(VrDebugOptions::from_env())
+    }
+
+    pub fn with_debug(debug: VrDebugOptions) -> Result<Self> {
         // Create OpenXR instance with Vulkan graphics API
         let entry = xr::Entry::linked();
         let app_info = xr::ApplicationInfo {
@@ -46,32 +96,79 @@ impl VRSystem {
         };
 
         // Available extensions
-        let available_extensions = entry.enumerate_extensions()?;
+        let available_extensions = entry.enumerate_extensions()
+            .map_err(|e| VrSetupError::new("enumerate OpenXR extensions", e))?;
         #[cfg(debug_assertions)]
         log::debug!("Available OpenXR extensions: {:?}", available_extensions);
 
         // Required extensions for our application
         let mut required_extensions = xr::ExtensionSet::default();
         required_extensions.khr_vulkan_enable2 = true;  // Enable Vulkan 2 support
+        if debug.debug_messages {
+            if available_extensions.ext_debug_utils {
+                required_extensions.ext_debug_utils = true;
+            } else {
+                log::warn!("The OpenXR runtime doesn't support XR_EXT_debug_utils; its messages won't be logged");
+            }
+        }
+
+        let mut layers = Vec::new();
+        if debug.validation_layer {
+            let installed = entry.enumerate_layers()
+                .map(|available| available.iter().any(|layer| layer.layer_name == VALIDATION_LAYER))
+                .unwrap_or(false);
+            if installed {
+                layers.push(VALIDATION_LAYER);
+            } else {
+                log::warn!("{} isn't installed; OpenXR calls won't be validated", VALIDATION_LAYER);
+            }
+        }
 
         // Create instance
-        let instance = entry.create_instance(&app_info, &required_extensions, &[])?;
+        let instance = entry.create_instance(&app_info, &required_extensions, &layers)
+            .map_err(|e| VrSetupError::new("create the OpenXR instance", e))?;
+        let debug_messenger = if required_extensions.ext_debug_utils {
+            DebugMessenger::new(&instance)
+                .map_err(|e| log::warn!("{:#}", e))
+                .ok()
+        } else {
+            None
+        };
 
         // Get the system (HMD) with Vulkan graphics API
-        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+            .map_err(|e| VrSetupError::new("find a headset", e))?;
 
         Ok(Self {
+            _debug_messenger: debug_messenger,
             instance,
             system,
             frame_manager: None,
+            session: None,
+            stage: None,
+            swapchain_size: None,
+            pending_swapchain_size: None,
+            resolution_polled: None,
+            frame_loop: Arc::new(FrameLoopControl::default()),
             view_configuration: None,
             swapchain_format: wgpu::TextureFormat::Bgra8UnormSrgb,
             pipeline: None,
             session_state: SessionState::Idle,
+            comfort: VrComfortSettings::default(),
+            stereo: VrStereoSettings::default(),
+            timing: FrameTimingManager::new(90),
+            hud: None,
+            actions: None,
+            world_yaw: 0.0,
+            stage_position: Vec3::ZERO,
+            stage_parent: None,
         })
     }
 
     pub fn initialize_session(&mut self, device: &wgpu::Device) -> Result<()> {
+        if !is_vulkan_device(device) {
+            anyhow::bail!("VR needs a device created on the Vulkan backend (see RenderMode::Vr)");
+        }
         let _requirements = self.instance.graphics_requirements::<xr::Vulkan>(self.system)?;
         
         // Get Vulkan handles from wgpu
@@ -79,6 +176,15 @@ impl VRSystem {
         let vk_physical_device = get_vulkan_physical_device_from_wgpu(device)?;
         let vk_device = get_vulkan_device_from_wgpu(device)?;
         let (queue_family_index, queue_index) = get_vulkan_queue_info_from_wgpu(device)?;
+        log::debug!("Creating the VR session on queue {} of family {}", queue_index, queue_family_index);
+        match unsafe { self.instance.vulkan_graphics_device(self.system, vk_instance) } {
+            Ok(required) if required != vk_physical_device => log::warn!(
+                "The VR device isn't on the GPU the runtime requires and session creation may fail; \
+                 create it on the adapter from vr::select_vr_adapter"
+            ),
+            Ok(_) => {}
+            Err(e) => log::debug!("The VR runtime didn't name a GPU: {}", e),
+        }
 
         // Create session with proper Vulkan device info
         let vk_session_create_info = xr::vulkan::SessionCreateInfo {
@@ -112,27 +218,22 @@ impl VRSystem {
         )?;
 
         // Create swapchain
-        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
-            create_flags: xr::SwapchainCreateFlags::EMPTY,
-            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
-                | xr::SwapchainUsageFlags::SAMPLED,
-            format: wgpu_format_to_vulkan(self.swapchain_format),
-            sample_count: 1,
-            width: views[0].recommended_image_rect_width,
-            height: views[0].recommended_image_rect_height,
-            face_count: 1,
-            array_size: 2,  // One for each eye
-            mip_count: 1,
-        })?;
+        let size = recommended_image_size(&views).context("The runtime reported no views")?;
+        let swapchain = create_swapchain(&session, self.swapchain_format, size)?;
 
         // Create pipeline
         self.pipeline = Some(VRPipeline::new(
             device,
             self.swapchain_format,
             wgpu::TextureFormat::Depth32Float,
-        ));
+        )?);
+        self.hud = Some(VrHud::new(device, self.swapchain_format));
+        self.actions = Some(VrActions::new(&self.instance, &session)?);
 
         // Initialize frame manager
+        self.session = Some(session.clone());
+        self.stage = Some(session.create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)?);
+        self.swapchain_size = Some(size);
         let mut frame_manager = FrameManager::new();
         frame_manager.initialize(session, frame_waiter, frame_stream, swapchain, stage, views);
         self.frame_manager = Some(frame_manager);
@@ -142,7 +243,9 @@ impl VRSystem {
 
     pub fn begin_frame(&mut self) -> Result<xr::FrameState> {
         if let Some(frame_manager) = &mut self.frame_manager {
-            frame_manager.begin_frame()
+            let frame_state = frame_manager.begin_frame()?;
+            self.timing.begin_frame(frame_state.predicted_display_time);
+            Ok(frame_state)
         } else {
             Err(anyhow::anyhow!("Frame manager not initialized"))
         }
@@ -166,12 +269,25 @@ impl VRSystem {
 
     pub fn end_frame(&mut self, frame_state: xr::FrameState, views: &[xr::CompositionLayerProjectionView<xr::Vulkan>]) -> Result<()> {
         if let Some(frame_manager) = &mut self.frame_manager {
+            self.timing.end_frame();
             frame_manager.end_frame(frame_state, views)
         } else {
             Err(anyhow::anyhow!("Frame manager not initialized"))
         }
     }
 
+    /// Probe the OpenXR runtime, layers and headset without starting VR, e.g. for a
+    /// `--vr-diagnose` report or a setup screen
+    pub fn diagnose() -> VrDiagnostics {
+        VrDiagnostics::gather()
+    }
+
+    /// The physical device among `instance`'s that the runtime needs sessions created on
+    pub fn required_physical_device(&self, instance: &wgpu::Instance) -> Result<*const std::ffi::c_void> {
+        let vk_instance = get_vulkan_instance_from_wgpu_instance(instance)?;
+        Ok(unsafe { self.instance.vulkan_graphics_device(self.system, vk_instance)? })
+    }
+
     pub fn is_hmd_available(&self) -> bool {
         // Check if we can get view configuration views (means HMD is connected and available)
         self.instance
@@ -194,24 +310,237 @@ impl VRSystem {
         )?)
     }
 
+    /// Eye views for `frame_state`, with the stereo overrides applied
     pub fn get_views(&mut self, frame_state: &xr::FrameState) -> Result<Vec<xr::View>> {
         if let Some(frame_manager) = &self.frame_manager {
-            frame_manager.get_views(frame_state)
+            let mut views = frame_manager.get_views(frame_state)?;
+            adjust_stereo(&mut views, &self.stereo);
+            Ok(views)
         } else {
             Err(anyhow::anyhow!("Frame manager not initialized"))
         }
     }
 
     pub fn get_view_projections(&mut self, frame_state: &xr::FrameState) -> Result<Vec<ViewProjection>> {
-        if let Some(frame_manager) = &self.frame_manager {
-            frame_manager.get_view_projections(frame_state)
-        } else {
-            Err(anyhow::anyhow!("Frame manager not initialized"))
+        let views = self.get_views(frame_state)?;
+        Ok(self.project_views(&views))
+    }
+
+    /// Eye views at a submit thread's predicted display time, with the stereo overrides applied.
+    /// Submit them with the frame rendered from them.
+    pub fn locate_views(&self, prediction: &FramePrediction) -> Result<Vec<xr::View>> {
+        let (session, stage) = self.session.as_ref().zip(self.stage.as_ref()).context("Session not initialized")?;
+        let (_, mut views) = session.locate_views(
+            xr::ViewConfigurationType::PRIMARY_STEREO,
+            xr::Time::from_nanos(prediction.display_time_nanos),
+            stage,
+        )?;
+        adjust_stereo(&mut views, &self.stereo);
+        Ok(views)
+    }
+
+    /// World view and projection matrices of tracking-space eye views
+    pub fn project_views(&self, views: &[xr::View]) -> Vec<ViewProjection> {
+        let comfort = self.world_to_tracking();
+        views.iter()
+            .map(|view| {
+                let mut view_projection = ViewProjection::from_xr_view(view, NEAR_PLANE);
+                view_projection.view *= comfort;
+                view_projection
+            })
+            .collect()
+    }
+
+    /// The swapchain's images as wgpu textures, for the `fill` of `spawn_submit_thread` (or a
+    /// frame loop on this thread) to write
+    pub fn swapchain_textures(&self, device: &wgpu::Device) -> Result<SwapchainTextures> {
+        let frame_manager = self.frame_manager.as_ref().context("Frame manager not initialized")?;
+        let size = self.swapchain_size.context("Swapchain not initialized")?;
+        SwapchainTextures::new(device, &frame_manager.swapchain_images()?, self.swapchain_format, size, 2)
+    }
+
+    /// The size the runtime now recommends for eye images, when the swapchain was created at a
+    /// different one; `update_session_state` checks for this. Call `recreate_swapchain` then.
+    pub fn pending_swapchain_size(&self) -> Option<(u32, u32)> {
+        self.pending_swapchain_size
+    }
+
+    /// Recreate the swapchain at the runtime's recommended size, returning its new images.
+    /// Call it between frames, with no image acquired and no submit thread running
+    /// (`recreate_submit_thread` does this for a frame loop on one). `old` are the textures of
+    /// the current images, released before their swapchain is destroyed. Resize the renderer's
+    /// targets and eye images to the new size (`get_swapchain_image_layout`) afterwards; the
+    /// eye pipelines don't depend on the size and are kept.
+    pub fn recreate_swapchain(&mut self, device: &wgpu::Device, old: Option<SwapchainTextures>) -> Result<SwapchainTextures> {
+        let session = self.session.as_ref().context("Session not initialized")?;
+        let frame_manager = self.frame_manager.as_mut()
+            .context("The frame loop is on a submit thread; join it before recreating the swapchain")?;
+        let views = self.instance.enumerate_view_configuration_views(
+            self.system,
+            xr::ViewConfigurationType::PRIMARY_STEREO,
+        )?;
+        let size = recommended_image_size(&views).context("The runtime reported no views")?;
+
+        // The old images are destroyed with their swapchain, so their textures go first and
+        // nothing may still be using them
+        drop(old);
+        device.poll(wgpu::Maintain::Wait);
+        let swapchain = create_swapchain(session, self.swapchain_format, size)?;
+        frame_manager.replace_swapchain(swapchain, views);
+        log::info!("Recreated the VR swapchain at {}x{} per eye", size.0, size.1);
+        self.swapchain_size = Some(size);
+        self.pending_swapchain_size = None;
+        self.swapchain_textures(device)
+    }
+
+    /// Recreate the swapchain under a submit thread: stops `thread`, which drops its `fill` and
+    /// the swapchain textures in it, recreates the swapchain and spawns a new thread filling
+    /// the new images with `fill`. Call it when `pending_swapchain_size` is set, then resize
+    /// the eye targets as with `recreate_swapchain`.
+    pub fn recreate_submit_thread(
+        &mut self,
+        device: &wgpu::Device,
+        thread: SubmitThread<XrSubmitter>,
+        fill: impl FnOnce(SwapchainTextures) -> FillSwapchain,
+    ) -> Result<SubmitThread<XrSubmitter>> {
+        self.join_submit_thread(thread)?;
+        let swapchain = self.recreate_swapchain(device, None)?;
+        self.spawn_submit_thread(fill(swapchain))
+    }
+
+    /// Move the frame loop and swapchain to a submit thread, so waiting for the runtime no longer
+    /// blocks the caller. Render each frame for `SubmitThread::prediction` with `locate_views`,
+    /// into the eye images of its `VrFrame::slot`; `fill` copies those into the swapchain.
+    pub fn spawn_submit_thread(&mut self, fill: FillSwapchain) -> Result<SubmitThread<XrSubmitter>> {
+        let frame_manager = self.frame_manager.take().context("Frame manager not initialized")?;
+        let submitter = XrSubmitter::new(frame_manager, self.frame_loop.clone(), fill);
+        SubmitThread::spawn(submitter, super::submit::VrFrame::slots())
+    }
+
+    /// Stop a submit thread and take its frame loop back
+    pub fn join_submit_thread(&mut self, thread: SubmitThread<XrSubmitter>) -> Result<()> {
+        let submitter = thread.stop().context("The VR submit thread panicked")?;
+        self.frame_manager = Some(submitter.into_frame_manager());
+        Ok(())
+    }
+
+    /// Tracking space to world: undoes the comfort options and stage placement applied to views
+    pub fn tracking_to_world(&self) -> Mat4 {
+        self.world_to_tracking().inverse()
+    }
+
+    fn world_to_tracking(&self) -> Mat4 {
+        world_to_tracking(self.comfort.height_offset, self.world_yaw, self.stage_position, self.stage_parent.unwrap_or(Mat4::IDENTITY))
+    }
+
+    /// World transform of the point between `eyes` (from `project_views`), for head-locked
+    /// `AnchorPoses`
+    pub fn head_pose(&self, eyes: &[ViewProjection]) -> Mat4 {
+        head_transform(eyes.iter())
+    }
+
+    /// World transform of a pose located in the tracking space, e.g. a controller's grip
+    pub fn pose_to_world(&self, pose: &xr::Posef) -> Mat4 {
+        self.tracking_to_world() * create_view_matrix(pose).inverse()
+    }
+
+    /// The world-space ray of a controller aim pose located in the tracking space
+    pub fn aim_ray(&self, pose: &xr::Posef) -> Ray {
+        aim_ray(pose, &self.tracking_to_world())
+    }
+
+    pub fn set_comfort_settings(&mut self, comfort: &VrComfortSettings) {
+        self.comfort = comfort.clone();
+    }
+
+    /// CPU timings of recent frames, from `begin_frame` to `end_frame`
+    pub fn timing(&self) -> &FrameTimingManager {
+        &self.timing
+    }
+
+    /// For a render thread to time its frames while a submit thread runs the frame loop
+    pub fn timing_mut(&mut self) -> &mut FrameTimingManager {
+        &mut self.timing
+    }
+
+    /// The in-headset performance panel, once a session has been initialized
+    pub fn hud_mut(&mut self) -> Option<&mut VrHud> {
+        self.hud.as_mut()
+    }
+
+    /// Draw the HUD, when shown, over each eye's finished image, with the frame times from
+    /// `timing` and GPU time from `profiler`
+    pub fn render_hud(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        eyes: &[(&wgpu::TextureView, &ViewProjection)],
+        profiler: Option<&Profiler>,
+        resolution_scale: f32,
+    ) {
+        if let Some(hud) = &mut self.hud {
+            let stats = HudStats::gather(&self.timing, profiler, resolution_scale);
+            hud.render(device, queue, encoder, &stats, eyes);
+        }
+    }
+
+    /// Controller button states for this frame, e.g. the HUD's `TOGGLE_ACTION`; empty while
+    /// the session isn't running
+    pub fn poll_actions(&self) -> Result<Vec<InputEvent>> {
+        match (&self.actions, &self.session) {
+            (Some(actions), Some(session)) if self.is_session_running() => actions.poll(session),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Debug overrides for the eye positions of later frames' views
+    pub fn set_stereo_settings(&mut self, stereo: &VrStereoSettings) {
+        self.stereo = stereo.clone();
+    }
+
+    /// Take up the VR sections of `settings`; call again whenever `State::apply_settings`
+    /// reports them changed
+    pub fn apply_settings(&mut self, settings: &RendererSettings) {
+        let settings = settings.clone().sanitized();
+        self.set_comfort_settings(&settings.vr_comfort);
+        self.set_stereo_settings(&settings.vr_stereo);
+    }
+
+    /// Rotate the world by one snap-turn step; positive `direction` turns left
+    pub fn snap_turn(&mut self, direction: f32) {
+        if self.comfort.snap_turn_degrees > 0.0 && direction != 0.0 {
+            self.world_yaw += self.comfort.snap_turn_degrees.to_radians() * direction.signum();
+            self.world_yaw %= std::f32::consts::TAU;
+        }
+    }
+
+    pub fn stage_position(&self) -> Vec3 {
+        self.stage_position
+    }
+
+    /// Place the tracking space origin in the scene, e.g. to teleport
+    pub fn set_stage_position(&mut self, position: Vec3) {
+        self.stage_position = position;
+    }
+
+    /// Carry the play space along with a scene object, given its world matrix each frame
+    /// (`Scene::world_matrix`); the stage position is then relative to the object. `None`
+    /// puts the play space back in the scene.
+    pub fn set_stage_parent(&mut self, parent: Option<Mat4>) {
+        self.stage_parent = parent;
+    }
+
+    /// Follow a floating-origin rebase (the shift returned by `Scene::update`) so the user stays put
+    pub fn rebase(&mut self, shift: Vec3) {
+        // A parented stage is relative to its parent, whose next matrix already includes the shift
+        if self.stage_parent.is_none() {
+            self.stage_position -= shift;
         }
     }
 
     pub fn get_swapchain_image_layout(&self) -> Option<(u32, u32)> {
-        self.frame_manager.as_ref().and_then(|fm| fm.get_swapchain_image_layout())
+        self.swapchain_size
     }
 
     pub fn get_swapchain_format(&self) -> wgpu::TextureFormat {
@@ -226,20 +555,10 @@ impl VRSystem {
         self.pipeline.as_ref()
     }
 
-    pub fn update_view_uniforms(&self, queue: &wgpu::Queue, view_proj: &ViewProjection) -> Result<()> {
+    /// Upload the eyes' matrices, in view order, for the next stereo passes
+    pub fn update_view_uniforms(&self, queue: &wgpu::Queue, views: &[ViewProjection]) -> Result<()> {
         if let Some(pipeline) = &self.pipeline {
-            let uniform = VRUniform {
-                view_proj: view_proj.projection.mul_mat4(&view_proj.view).to_cols_array_2d(),
-                view: view_proj.view.to_cols_array_2d(),
-                proj: view_proj.projection.to_cols_array_2d(),
-                eye_position: [
-                    view_proj.pose.position.x,
-                    view_proj.pose.position.y,
-                    view_proj.pose.position.z,
-                ],
-                _padding: 0,
-            };
-            pipeline.update_uniform(queue, &uniform);
+            pipeline.update_uniform(queue, &VRUniform::from_views(views));
             Ok(())
         } else {
             Err(anyhow::anyhow!("Pipeline not initialized"))
@@ -247,7 +566,7 @@ impl VRSystem {
     }
 
     pub fn update_session_state(&mut self) -> Result<()> {
-        if let Some(frame_manager) = &self.frame_manager {
+        if let Some(session) = &self.session {
             let mut event_storage = xr::EventDataBuffer::new();
             while let Some(event) = self.instance.poll_event(&mut event_storage)? {
                 match event {
@@ -255,17 +574,18 @@ impl VRSystem {
                         match state_event.state() {
                             xr::SessionState::READY => {
                                 // Begin session
-                                if let Some(session) = frame_manager.get_session() {
-                                    session.begin(xr::ViewConfigurationType::PRIMARY_STEREO)?;
-                                    self.session_state = SessionState::Ready;
-                                }
+                                session.begin(xr::ViewConfigurationType::PRIMARY_STEREO)?;
+                                self.frame_loop.start();
+                                self.session_state = SessionState::Ready;
                             }
                             xr::SessionState::STOPPING => {
-                                // End session
-                                if let Some(session) = frame_manager.get_session() {
-                                    session.end()?;
-                                    self.session_state = SessionState::Stopping;
+                                // End the session once a submit thread has finished the frame
+                                // in flight, as xrEndSession mustn't race its xrEndFrame
+                                if !self.frame_loop.stop(FRAME_LOOP_STOP_TIMEOUT) {
+                                    log::warn!("The VR submit thread is still in a frame after {:?}; ending the session anyway", FRAME_LOOP_STOP_TIMEOUT);
                                 }
+                                session.end()?;
+                                self.session_state = SessionState::Stopping;
                             }
                             xr::SessionState::SYNCHRONIZED => {
                                 let frame_state = xr::FrameState {
@@ -286,14 +606,59 @@ impl VRSystem {
                             _ => {}
                         }
                     }
+                    xr::Event::ReferenceSpaceChangePending(change) => {
+                        // Recentering or a new play area often comes with new display settings;
+                        // check the resolution straight away
+                        log::info!("The {:?} reference space is changing", change.reference_space_type());
+                        self.resolution_polled = None;
+                    }
                     _ => {}
                 }
             }
         }
+        self.poll_resolution()
+    }
+
+    /// Note a change in the runtime's recommended eye resolution, at most every
+    /// `RESOLUTION_POLL_INTERVAL`
+    fn poll_resolution(&mut self) -> Result<()> {
+        let Some(current) = self.swapchain_size else {
+            return Ok(());
+        };
+        if self.resolution_polled.is_some_and(|polled| polled.elapsed() < RESOLUTION_POLL_INTERVAL) {
+            return Ok(());
+        }
+        self.resolution_polled = Some(Instant::now());
+        let recommended = recommended_image_size(&self.get_view_configuration_views()?)
+            .filter(|size| *size != current);
+        if let Some((width, height)) = recommended.filter(|_| recommended != self.pending_swapchain_size) {
+            log::info!(
+                "The VR runtime now recommends {}x{} per eye instead of {}x{}; the swapchain needs recreating",
+                width, height, current.0, current.1,
+            );
+        }
+        self.pending_swapchain_size = recommended;
         Ok(())
     }
 
     pub fn is_session_running(&self) -> bool {
         matches!(self.session_state, SessionState::Running { .. })
     }
-} 
\ No newline at end of file
+}
+
+/// A swapchain of `size` eye images, one array layer per eye
+fn create_swapchain(session: &xr::Session<xr::Vulkan>, format: wgpu::TextureFormat, (width, height): (u32, u32)) -> Result<xr::Swapchain<xr::Vulkan>> {
+    Ok(session.create_swapchain(&xr::SwapchainCreateInfo {
+        create_flags: xr::SwapchainCreateFlags::EMPTY,
+        usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+            | xr::SwapchainUsageFlags::TRANSFER_DST
+            | xr::SwapchainUsageFlags::SAMPLED,
+        format: wgpu_format_to_vulkan(format),
+        sample_count: 1,
+        width,
+        height,
+        face_count: 1,
+        array_size: 2,  // One for each eye
+        mip_count: 1,
+    })?)
+}
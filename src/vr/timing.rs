@@ -78,6 +78,11 @@ impl FrameTimingManager {
         }
     }
 
+    /// Frame time the display refresh allows; slower frames count as dropped
+    pub fn target_frame_time(&self) -> Duration {
+        self.target_frame_time
+    }
+
     pub fn get_stats(&self) -> &TimingStats {
         &self.last_stats
     }
@@ -2,6 +2,11 @@ use std::ffi::c_void;
 use wgpu::hal::api::Vulkan;
 use anyhow::Result;
 
+/// Whether `device` runs on wgpu's Vulkan backend, which VR interop needs
+pub fn is_vulkan_device(device: &wgpu::Device) -> bool {
+    unsafe { device.as_hal::<Vulkan, _, bool>(|vulkan_device| vulkan_device.is_some()) }.unwrap_or(false)
+}
+
 /// Extract Vulkan instance handle from wgpu device
 pub fn get_vulkan_instance_from_wgpu(device: &wgpu::Device) -> Result<*const c_void> {
     unsafe {
@@ -21,6 +26,26 @@ pub fn get_vulkan_instance_from_wgpu(device: &wgpu::Device) -> Result<*const c_v
     }
 }
 
+/// Extract Vulkan instance handle from a wgpu instance created with the Vulkan backend
+pub fn get_vulkan_instance_from_wgpu_instance(instance: &wgpu::Instance) -> Result<*const c_void> {
+    unsafe {
+        let vulkan_instance = instance.as_hal::<Vulkan>()
+            .ok_or_else(|| anyhow::anyhow!("The wgpu instance has no Vulkan backend"))?;
+        let raw_instance = vulkan_instance.shared_instance().raw_instance().handle();
+        let raw_instance: *const c_void = std::mem::transmute(raw_instance);
+        Ok(raw_instance)
+    }
+}
+
+/// Extract Vulkan physical device handle from a wgpu adapter, or `None` for other backends
+pub fn get_vulkan_physical_device_from_adapter(adapter: &wgpu::Adapter) -> Option<*const c_void> {
+    unsafe {
+        adapter.as_hal::<Vulkan, _, Option<*const c_void>>(|vulkan_adapter| {
+            vulkan_adapter.map(|vulkan_adapter| std::mem::transmute(vulkan_adapter.raw_physical_device()))
+        })
+    }
+}
+
 /// Extract Vulkan physical device handle from wgpu device
 pub fn get_vulkan_physical_device_from_wgpu(device: &wgpu::Device) -> Result<*const c_void> {
     unsafe {
@@ -56,17 +81,44 @@ pub fn get_vulkan_device_from_wgpu(device: &wgpu::Device) -> Result<*const c_voi
     }
 }
 
-/// Get Vulkan queue family and queue index from wgpu device
+/// `VK_QUEUE_GRAPHICS_BIT`
+const QUEUE_GRAPHICS_BIT: u32 = 0x1;
+
+/// Check that queue `index` of `family` exists and can do graphics, given each queue family's
+/// `(flags, queue count)`; OpenXR submits the compositor's work to this queue
+fn check_graphics_queue(families: &[(u32, u32)], family: u32, index: u32) -> Result<()> {
+    let (flags, count) = *families.get(family as usize)
+        .ok_or_else(|| anyhow::anyhow!("Queue family {} doesn't exist ({} families)", family, families.len()))?;
+    if flags & QUEUE_GRAPHICS_BIT == 0 {
+        anyhow::bail!("Queue family {} doesn't support graphics", family);
+    }
+    if index >= count {
+        anyhow::bail!("Queue family {} has {} queues, no queue {}", family, count, index);
+    }
+    Ok(())
+}
+
+/// Get the Vulkan queue family and queue index wgpu's device submits to, which the OpenXR
+/// session must be created with
 pub fn get_vulkan_queue_info_from_wgpu(device: &wgpu::Device) -> Result<(u32, u32)> {
     unsafe {
         device.as_hal::<Vulkan, _, Result<(u32, u32)>>(|vulkan_device| {
             let vulkan_device = vulkan_device
                 .ok_or_else(|| anyhow::anyhow!("Failed to get Vulkan device"))?;
-            
-            // Get the queue family index from the device
+
+            // wgpu picks its queue at adapter open; ask rather than assume family 0, which isn't
+            // the graphics family on every driver
             let family_index = vulkan_device.queue_family_index();
-            
-            Ok((family_index, 0)) // Using first queue in family
+            let queue_index = vulkan_device.queue_index();
+
+            let families: Vec<_> = vulkan_device.shared_instance().raw_instance()
+                .get_physical_device_queue_family_properties(vulkan_device.raw_physical_device())
+                .iter()
+                .map(|properties| (properties.queue_flags.as_raw(), properties.queue_count))
+                .collect();
+            check_graphics_queue(&families, family_index, queue_index)?;
+
+            Ok((family_index, queue_index))
         })
         .ok_or_else(|| anyhow::anyhow!("Failed to get Vulkan queue info"))?
     }
@@ -145,6 +197,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_check_graphics_queue() {
+        // A compute/transfer-only family 0 ahead of the graphics family, as on some drivers
+        let families = [(0x2 | 0x4, 2), (0x1 | 0x2 | 0x4, 1)];
+        assert!(check_graphics_queue(&families, 1, 0).is_ok());
+        assert!(check_graphics_queue(&families, 0, 0).is_err());
+        assert!(check_graphics_queue(&families, 1, 1).is_err());
+        assert!(check_graphics_queue(&families, 2, 0).is_err());
+    }
+
     #[test]
     fn test_vulkan_format_conversion() {
         assert_eq!(wgpu_format_to_vulkan(wgpu::TextureFormat::Bgra8UnormSrgb), 50);